@@ -0,0 +1,165 @@
+//! Per-request authentication overhead
+//!
+//! Compares full key packet verification on every request against the
+//! verified-packet cache. With the cache, the remaining per-request cost is
+//! the request signature check and nonce bookkeeping, which should sit well
+//! under 1ms. The pieces of that cost are benchmarked separately too:
+//! canonical string construction, Ed25519 signing and verification, key
+//! packet decoding, and the nonce cache with several threads checking at
+//! once, since every request takes its lock.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ed25519_dalek::{Signer, Verifier};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wfldb_auth::*;
+
+fn setup(cache_capacity: usize) -> (Authenticator, RequestSigner) {
+    let root = generate_signing_key();
+    let holder = generate_signing_key();
+    let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, u32::MAX as u64).unwrap();
+    let authenticator = Authenticator::new(Arc::new(KeyAuthority::new(root.verifying_key())))
+        .with_cache(VerifiedPacketCache::new(cache_capacity, DEFAULT_CACHE_TTL))
+        .with_nonce_cache(NonceCache::new(Duration::from_secs(u32::MAX as u64)));
+    (authenticator, RequestSigner::new(holder, packet))
+}
+
+fn bench_authenticate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("authenticate");
+    let now = now_ms();
+
+    for (name, capacity) in [("uncached", 0), ("cached", DEFAULT_CACHE_CAPACITY)] {
+        let (authenticator, signer) = setup(capacity);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || signer.sign("GET", "/v1/bench-bucket/key", b"", now),
+                |headers| {
+                    let request = RequestParts {
+                        method: "GET",
+                        path: "/v1/bench-bucket/key",
+                        query: "",
+                        headers: headers.iter().map(|(n, v)| (*n, v.as_str())).collect(),
+                    };
+                    black_box(authenticator.authenticate(&request, now).unwrap());
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_packet_verification(c: &mut Criterion) {
+    let root = generate_signing_key();
+    let holder = generate_signing_key();
+    let authority = KeyAuthority::new(root.verifying_key());
+    let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, u32::MAX as u64).unwrap();
+    let cache = VerifiedPacketCache::default();
+    let now = now_ms();
+
+    let mut group = c.benchmark_group("key_packet");
+    group.bench_function("verify", |b| {
+        b.iter(|| black_box(authority.verify_packet(&packet, now / 1000).unwrap()))
+    });
+    group.bench_function("cache_hit", |b| {
+        b.iter(|| black_box(cache.get_or_verify(&packet, &authority, now).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_canonical_string(c: &mut Criterion) {
+    let content_hash = blake3::hash(b"").to_hex().to_string();
+    let mut group = c.benchmark_group("canonical_string");
+
+    let plain = RequestParts::new("GET", "/v1/bench-bucket/key");
+    group.bench_function("no_headers", |b| {
+        b.iter(|| black_box(canonical_string(&plain, &[], 1_700_000_000_000, "0123456789abcdef", &content_hash).unwrap()))
+    });
+
+    let with_headers = RequestParts {
+        method: "GET",
+        path: "/v1/bench-bucket/key",
+        query: "prefix=logs%2F&limit=100&start_after=logs%2F2024",
+        headers: vec![
+            ("content-type", "application/json"),
+            ("x-wfldb-priority", "interactive"),
+            ("if-none-match", "\"01HZXB3V8K\""),
+        ],
+    };
+    let signed: Vec<String> = ["content-type", "if-none-match", "x-wfldb-priority"].map(String::from).to_vec();
+    group.bench_function("query_and_headers", |b| {
+        b.iter(|| black_box(canonical_string(&with_headers, &signed, 1_700_000_000_000, "0123456789abcdef", &content_hash).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_ed25519(c: &mut Criterion) {
+    let key = generate_signing_key();
+    let verifying_key = key.verifying_key();
+    let content_hash = blake3::hash(b"").to_hex().to_string();
+    let request = RequestParts::new("GET", "/v1/bench-bucket/key");
+    let canonical = canonical_string(&request, &[], 1_700_000_000_000, "0123456789abcdef", &content_hash).unwrap();
+    let signature = key.sign(canonical.as_bytes());
+
+    let mut group = c.benchmark_group("ed25519");
+    group.bench_function("sign", |b| b.iter(|| black_box(key.sign(black_box(canonical.as_bytes())))));
+    group.bench_function("verify", |b| {
+        b.iter(|| verifying_key.verify(black_box(canonical.as_bytes()), &signature).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_packet_decode(c: &mut Criterion) {
+    let root = generate_signing_key();
+    let holder = generate_signing_key();
+    let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, u32::MAX as u64).unwrap();
+
+    let mut group = c.benchmark_group("key_packet");
+    // Base64 and JSON decoding alone, without the signature check
+    group.bench_function("decode", |b| b.iter(|| black_box(decode_packet(black_box(&packet)).unwrap())));
+    group.finish();
+}
+
+fn bench_nonce_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nonce_check");
+    for threads in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            // Nonces are unique across iterations, so every check is an insert
+            // into the same long-lived cache, as under real traffic
+            let cache = NonceCache::new(DEFAULT_REPLAY_WINDOW);
+            let next = AtomicU64::new(0);
+            b.iter_custom(|iters| {
+                let per_thread = iters.div_ceil(threads);
+                let start = Instant::now();
+                std::thread::scope(|scope| {
+                    for _ in 0..threads {
+                        scope.spawn(|| {
+                            for _ in 0..per_thread {
+                                let nonce = format!("{:032x}", next.fetch_add(1, Ordering::Relaxed));
+                                let now = now_ms();
+                                cache.check(&nonce, now, now).unwrap();
+                            }
+                        });
+                    }
+                });
+                // Wall time across all threads, so a lock that serializes
+                // them shows up as checks that don't get cheaper with more threads
+                start.elapsed()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_authenticate,
+    bench_packet_verification,
+    bench_canonical_string,
+    bench_ed25519,
+    bench_packet_decode,
+    bench_nonce_contention
+);
+criterion_main!(benches);
@@ -0,0 +1,120 @@
+//! Per-bucket default object ACLs
+//!
+//! Bucket permissions in a key packet say who may touch a bucket at all; the
+//! bucket ACL decides how objects inside it are shared between those holders.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use wfldb_core::BucketId;
+use crate::store::BUCKET_ACL_PREFIX;
+use crate::{AuthError, AuthorityStore, Result};
+
+/// Default sharing semantics for objects in a bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketAcl {
+    /// Only the object's owner (or an admin) may read, overwrite, or delete it
+    OwnerOnly,
+    /// Anyone with the bucket permission may act on any object
+    #[default]
+    BucketWriters,
+    /// Like `BucketWriters`, but objects can also be read without authentication
+    PublicRead,
+}
+
+impl BucketAcl {
+    /// Whether unauthenticated requests with `method` are allowed
+    pub fn allows_anonymous(&self, method: &str) -> bool {
+        *self == BucketAcl::PublicRead && matches!(method, "GET" | "HEAD")
+    }
+}
+
+/// Bucket ACLs, persisted in the authority store
+#[derive(Default)]
+pub struct BucketAclRegistry {
+    acls: RwLock<BTreeMap<String, BucketAcl>>,
+    store: Option<Arc<dyn AuthorityStore>>,
+}
+
+impl BucketAclRegistry {
+    /// Create an empty in-memory registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the registry from a store, writing later changes through to it
+    pub fn open(store: Arc<dyn AuthorityStore>) -> Result<Self> {
+        let mut acls = BTreeMap::new();
+        for (key, value) in store.scan_prefix(BUCKET_ACL_PREFIX)? {
+            let acl: BucketAcl = serde_json::from_slice(&value)
+                .map_err(|e| AuthError::Storage(format!("corrupt bucket ACL: {}", e)))?;
+            if let Some(bucket) = key.strip_prefix(BUCKET_ACL_PREFIX) {
+                acls.insert(bucket.to_string(), acl);
+            }
+        }
+        Ok(BucketAclRegistry {
+            acls: RwLock::new(acls),
+            store: Some(store),
+        })
+    }
+
+    /// ACL of a bucket, `BucketWriters` unless set
+    pub fn get(&self, bucket: &BucketId) -> BucketAcl {
+        self.acls.read().unwrap().get(bucket.as_str()).copied().unwrap_or_default()
+    }
+
+    pub fn set(&self, bucket: &BucketId, acl: BucketAcl) -> Result<()> {
+        if let Some(store) = &self.store {
+            let key = format!("{}{}", BUCKET_ACL_PREFIX, bucket.as_str());
+            if acl == BucketAcl::default() {
+                store.delete(&key)?;
+            } else {
+                let encoded = serde_json::to_vec(&acl).map_err(|e| AuthError::Storage(e.to_string()))?;
+                store.put(&key, &encoded)?;
+            }
+        }
+        let mut acls = self.acls.write().unwrap();
+        if acl == BucketAcl::default() {
+            acls.remove(bucket.as_str());
+        } else {
+            acls.insert(bucket.as_str().to_string(), acl);
+        }
+        Ok(())
+    }
+
+    /// Buckets with a non-default ACL
+    pub fn list(&self) -> Vec<(String, BucketAcl)> {
+        self.acls.read().unwrap().iter().map(|(b, a)| (b.clone(), *a)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryAuthorityStore;
+
+    #[test]
+    fn test_acl_persists_and_defaults() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let photos = BucketId::new("photos").unwrap();
+        let logs = BucketId::new("logs").unwrap();
+
+        let registry = BucketAclRegistry::open(store.clone()).unwrap();
+        registry.set(&photos, BucketAcl::OwnerOnly).unwrap();
+        registry.set(&logs, BucketAcl::PublicRead).unwrap();
+        registry.set(&logs, BucketAcl::BucketWriters).unwrap();
+
+        let reopened = BucketAclRegistry::open(store).unwrap();
+        assert_eq!(reopened.get(&photos), BucketAcl::OwnerOnly);
+        assert_eq!(reopened.get(&logs), BucketAcl::BucketWriters);
+        assert_eq!(reopened.list().len(), 1);
+    }
+
+    #[test]
+    fn test_anonymous_access() {
+        assert!(BucketAcl::PublicRead.allows_anonymous("GET"));
+        assert!(!BucketAcl::PublicRead.allows_anonymous("PUT"));
+        assert!(!BucketAcl::BucketWriters.allows_anonymous("GET"));
+    }
+}
@@ -0,0 +1,409 @@
+//! Trusted issuers and revoked keys
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use crate::store::{ISSUER_PREFIX, REVOCATION_VERSION_KEY, REVOKED_PREFIX, ROOT_KEY, ROOT_POLICY_KEY};
+use crate::{
+    decode_packet, decode_public_key, encode_public_key, sign_packet, AuthError, AuthorityStore,
+    AdminAction, KeyId, KeyPacketClaims, Permissions, Result, RevocationList, RootPolicy,
+};
+
+/// Set of issuer keys trusted to sign key packets, plus the revocation list
+///
+/// The root key is always trusted. Additional issuers can be added so that
+/// gateways can mint packets without holding the root key.
+///
+/// An authority opened from an [`AuthorityStore`] writes every issuer and
+/// revocation through to the store, so they survive restarts.
+///
+/// Until a [`RootPolicy`] is configured, the root key alone forms the quorum
+/// for [`AdminAction`]s.
+pub struct KeyAuthority {
+    root: RwLock<VerifyingKey>,
+    policy: RwLock<Option<RootPolicy>>,
+    issuers: RwLock<HashMap<KeyId, VerifyingKey>>,
+    revoked: RwLock<HashSet<KeyId>>,
+    revocation_version: AtomicU64,
+    store: Option<Arc<dyn AuthorityStore>>,
+}
+
+impl KeyAuthority {
+    /// Create an in-memory authority trusting the given root key
+    pub fn new(root: VerifyingKey) -> Self {
+        let mut issuers = HashMap::new();
+        issuers.insert(KeyId::from_public_key(&root), root);
+        KeyAuthority {
+            root: RwLock::new(root),
+            policy: RwLock::new(None),
+            issuers: RwLock::new(issuers),
+            revoked: RwLock::new(HashSet::new()),
+            revocation_version: AtomicU64::new(0),
+            store: None,
+        }
+    }
+
+    /// Load a persisted authority, or `None` if the store has not been bootstrapped
+    pub fn open(store: Arc<dyn AuthorityStore>) -> Result<Option<Self>> {
+        let root = match store.get(ROOT_KEY)? {
+            Some(root) => decode_public_key(&stored_string(root)?)?,
+            None => return Ok(None),
+        };
+
+        let authority = KeyAuthority::new(root);
+        {
+            let mut issuers = authority.issuers.write().unwrap();
+            for (_, issuer) in store.scan_prefix(ISSUER_PREFIX)? {
+                let issuer = decode_public_key(&stored_string(issuer)?)?;
+                issuers.insert(KeyId::from_public_key(&issuer), issuer);
+            }
+
+            let mut revoked = authority.revoked.write().unwrap();
+            for (key, _) in store.scan_prefix(REVOKED_PREFIX)? {
+                revoked.insert(KeyId::from_hex(&key[REVOKED_PREFIX.len()..])?);
+            }
+
+            let version = match store.get(REVOCATION_VERSION_KEY)? {
+                Some(version) => stored_string(version)?
+                    .parse()
+                    .map_err(|_| AuthError::Storage("corrupt revocation version".to_string()))?,
+                None => revoked.len() as u64,
+            };
+            authority.revocation_version.store(version, Ordering::Release);
+        }
+
+        if let Some(policy) = store.get(ROOT_POLICY_KEY)? {
+            let policy: RootPolicy = serde_json::from_slice(&policy)
+                .map_err(|e| AuthError::Storage(format!("corrupt root policy: {}", e)))?;
+            *authority.policy.write().unwrap() = Some(policy);
+        }
+
+        Ok(Some(KeyAuthority {
+            store: Some(store),
+            ..authority
+        }))
+    }
+
+    /// Initialise an empty store with a root key
+    ///
+    /// Fails if the store already has a different root; bootstrapping again
+    /// with the same root simply reopens it.
+    pub fn bootstrap(store: Arc<dyn AuthorityStore>, root: VerifyingKey) -> Result<Self> {
+        if let Some(existing) = Self::open(store.clone())? {
+            if existing.root() != root {
+                return Err(AuthError::Storage(format!(
+                    "store already bootstrapped with root {}",
+                    existing.root_id()
+                )));
+            }
+            return Ok(existing);
+        }
+
+        store.put(ROOT_KEY, encode_public_key(&root).as_bytes())?;
+        Ok(KeyAuthority {
+            store: Some(store),
+            ..KeyAuthority::new(root)
+        })
+    }
+
+    /// Key ID of the root key
+    pub fn root_id(&self) -> KeyId {
+        KeyId::from_public_key(&self.root())
+    }
+
+    /// Root public key
+    pub fn root(&self) -> VerifyingKey {
+        *self.root.read().unwrap()
+    }
+
+    /// Quorum required for admin actions
+    ///
+    /// Defaults to 1-of-1 over the root key until a policy is set.
+    pub fn root_policy(&self) -> RootPolicy {
+        match &*self.policy.read().unwrap() {
+            Some(policy) => policy.clone(),
+            None => RootPolicy::single(&self.root()),
+        }
+    }
+
+    /// True when admin actions need more than one signature
+    pub fn requires_quorum(&self) -> bool {
+        self.root_policy().threshold > 1
+    }
+
+    /// Replace the admin quorum
+    pub fn set_root_policy(&self, policy: RootPolicy) -> Result<()> {
+        policy.validate()?;
+        if let Some(store) = &self.store {
+            let encoded = serde_json::to_vec(&policy)
+                .map_err(|e| AuthError::Storage(e.to_string()))?;
+            store.put(ROOT_POLICY_KEY, &encoded)?;
+        }
+        *self.policy.write().unwrap() = Some(policy);
+        Ok(())
+    }
+
+    /// Replace the root key
+    ///
+    /// Packets signed by the old root stop verifying; cached ones are
+    /// re-checked because the trust change bumps the revocation version.
+    pub fn rotate_root(&self, new_root: VerifyingKey) -> Result<()> {
+        if let Some(store) = &self.store {
+            store.put(ROOT_KEY, encode_public_key(&new_root).as_bytes())?;
+        }
+        let mut root = self.root.write().unwrap();
+        let mut issuers = self.issuers.write().unwrap();
+        issuers.remove(&KeyId::from_public_key(&root));
+        issuers.insert(KeyId::from_public_key(&new_root), new_root);
+        *root = new_root;
+        drop(issuers);
+        self.bump_revocation_version()
+    }
+
+    /// Carry out an admin action that has reached its quorum
+    pub fn apply_admin_action(&self, action: &AdminAction) -> Result<()> {
+        match action {
+            AdminAction::Revoke { key_id } => self.revoke(*key_id).map(|_| ()),
+            AdminAction::AddIssuer { key } => self.add_issuer(decode_public_key(key)?).map(|_| ()),
+            AdminAction::RotateRoot { key } => self.rotate_root(decode_public_key(key)?),
+            AdminAction::SetPolicy { policy } => self.set_root_policy(policy.clone()),
+        }
+    }
+
+    /// Trust an additional issuer key
+    pub fn add_issuer(&self, issuer: VerifyingKey) -> Result<KeyId> {
+        let id = KeyId::from_public_key(&issuer);
+        if let Some(store) = &self.store {
+            store.put(&format!("{}{}", ISSUER_PREFIX, id), encode_public_key(&issuer).as_bytes())?;
+        }
+        self.issuers.write().unwrap().insert(id, issuer);
+        Ok(id)
+    }
+
+    /// Look up a trusted issuer
+    pub fn issuer(&self, id: &KeyId) -> Option<VerifyingKey> {
+        self.issuers.read().unwrap().get(id).copied()
+    }
+
+    /// Revoke a key, returning false if it was already revoked
+    ///
+    /// Revoking an issuer invalidates every packet it has signed. The key is
+    /// blocked in memory even if persisting the revocation fails.
+    pub fn revoke(&self, id: KeyId) -> Result<bool> {
+        // Bump the version under the lock so snapshots see a matching version
+        let mut revoked = self.revoked.write().unwrap();
+        if !revoked.insert(id) {
+            return Ok(false);
+        }
+        let version = self.revocation_version.fetch_add(1, Ordering::Release) + 1;
+
+        if let Some(store) = &self.store {
+            store.put(&format!("{}{}", REVOKED_PREFIX, id), b"")?;
+            store.put(REVOCATION_VERSION_KEY, version.to_string().as_bytes())?;
+        }
+        Ok(true)
+    }
+
+    fn bump_revocation_version(&self) -> Result<()> {
+        let _revoked = self.revoked.write().unwrap();
+        let version = self.revocation_version.fetch_add(1, Ordering::Release) + 1;
+        if let Some(store) = &self.store {
+            store.put(REVOCATION_VERSION_KEY, version.to_string().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Check whether a key has been revoked
+    pub fn is_revoked(&self, id: &KeyId) -> bool {
+        self.revoked.read().unwrap().contains(id)
+    }
+
+    /// Version counter bumped on every revocation and root rotation
+    pub fn revocation_version(&self) -> u64 {
+        self.revocation_version.load(Ordering::Acquire)
+    }
+
+    /// Snapshot of the revocation list for distribution to other nodes
+    pub fn revocation_list(&self) -> RevocationList {
+        let revoked = self.revoked.read().unwrap();
+        RevocationList::new(self.revocation_version(), revoked.iter().copied().collect())
+    }
+
+    /// Merge a revocation list received from another node
+    ///
+    /// Returns the keys that were not already revoked locally.
+    pub fn apply_revocations(&self, list: &RevocationList) -> Result<Vec<KeyId>> {
+        let mut added = Vec::new();
+        for id in &list.revoked {
+            if self.revoke(*id)? {
+                added.push(*id);
+            }
+        }
+        Ok(added)
+    }
+
+    /// Check the holder and issuer of a packet against the revocation list
+    /// and the current set of trusted issuers
+    pub fn check_revoked(&self, claims: &KeyPacketClaims) -> Result<()> {
+        if self.issuer(&claims.iss).is_none() {
+            return Err(AuthError::UnknownIssuer(claims.iss));
+        }
+        let revoked = self.revoked.read().unwrap();
+        for id in [&claims.sub, &claims.iss] {
+            if revoked.contains(id) {
+                return Err(AuthError::Revoked(*id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fully verify a key packet: issuer trust, signature, expiry, and revocation
+    pub fn verify_packet(&self, token: &str, now_secs: u64) -> Result<KeyPacketClaims> {
+        let (claims, _, _) = decode_packet(token)?;
+        let issuer = self
+            .issuer(&claims.iss)
+            .ok_or(AuthError::UnknownIssuer(claims.iss))?;
+        let claims = crate::packet::verify_packet(token, &issuer, now_secs)?;
+        claims.public_key()?;
+        self.check_revoked(&claims)?;
+        Ok(claims)
+    }
+
+    /// Issue a packet for a holder key, signed by `issuer`
+    pub fn issue(
+        issuer: &SigningKey,
+        holder: &VerifyingKey,
+        perms: Permissions,
+        now_secs: u64,
+        ttl_secs: u64,
+    ) -> Result<String> {
+        let claims = KeyPacketClaims::new(holder, &issuer.verifying_key(), perms, now_secs, ttl_secs);
+        sign_packet(&claims, issuer)
+    }
+}
+
+fn stored_string(value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).map_err(|_| AuthError::Storage("corrupt authority entry".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_signing_key, MemoryAuthorityStore};
+
+    #[test]
+    fn test_verify_and_revoke() {
+        let root = generate_signing_key();
+        let holder = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let token = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_only(), 0, 60).unwrap();
+
+        let claims = authority.verify_packet(&token, 10).unwrap();
+        assert_eq!(claims.sub, KeyId::from_public_key(&holder.verifying_key()));
+
+        assert!(authority.revoke(claims.sub).unwrap());
+        assert!(!authority.revoke(claims.sub).unwrap());
+        assert_eq!(authority.revocation_version(), 1);
+        assert_eq!(authority.verify_packet(&token, 10), Err(AuthError::Revoked(claims.sub)));
+    }
+
+    #[test]
+    fn test_revocation_list_merge() {
+        let leader = KeyAuthority::new(generate_signing_key().verifying_key());
+        let follower = KeyAuthority::new(generate_signing_key().verifying_key());
+        let a = KeyId::from_public_key(&generate_signing_key().verifying_key());
+        let b = KeyId::from_public_key(&generate_signing_key().verifying_key());
+
+        leader.revoke(a).unwrap();
+        leader.revoke(b).unwrap();
+        follower.revoke(a).unwrap();
+
+        let list = leader.revocation_list();
+        assert_eq!(list.version, 2);
+        assert!(list.contains(&a) && list.contains(&b));
+
+        assert_eq!(follower.apply_revocations(&list).unwrap(), vec![b]);
+        assert!(follower.is_revoked(&b));
+        assert!(follower.apply_revocations(&list).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_issuer_rejected() {
+        let root = generate_signing_key();
+        let gateway = generate_signing_key();
+        let holder = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let token = KeyAuthority::issue(&gateway, &holder.verifying_key(), Permissions::read_only(), 0, 60).unwrap();
+
+        let gateway_id = KeyId::from_public_key(&gateway.verifying_key());
+        assert_eq!(authority.verify_packet(&token, 10), Err(AuthError::UnknownIssuer(gateway_id)));
+
+        authority.add_issuer(gateway.verifying_key()).unwrap();
+        assert!(authority.verify_packet(&token, 10).is_ok());
+    }
+
+    #[test]
+    fn test_persisted_authority_survives_reopen() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let root = generate_signing_key().verifying_key();
+        let gateway = generate_signing_key().verifying_key();
+        let revoked = KeyId::from_public_key(&generate_signing_key().verifying_key());
+
+        assert!(KeyAuthority::open(store.clone()).unwrap().is_none());
+        let authority = KeyAuthority::bootstrap(store.clone(), root).unwrap();
+        let gateway_id = authority.add_issuer(gateway).unwrap();
+        authority.revoke(revoked).unwrap();
+
+        let reopened = KeyAuthority::open(store.clone()).unwrap().unwrap();
+        assert_eq!(reopened.root(), root);
+        assert_eq!(reopened.issuer(&gateway_id), Some(gateway));
+        assert!(reopened.is_revoked(&revoked));
+        assert_eq!(reopened.revocation_version(), 1);
+    }
+
+    #[test]
+    fn test_bootstrap_rejects_different_root() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let root = generate_signing_key().verifying_key();
+
+        KeyAuthority::bootstrap(store.clone(), root).unwrap();
+        assert!(KeyAuthority::bootstrap(store.clone(), root).is_ok());
+        assert!(KeyAuthority::bootstrap(store, generate_signing_key().verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_rotate_root_untrusts_old_root() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let old_root = generate_signing_key();
+        let new_root = generate_signing_key();
+        let holder = generate_signing_key().verifying_key();
+        let authority = KeyAuthority::bootstrap(store.clone(), old_root.verifying_key()).unwrap();
+        let old_packet = KeyAuthority::issue(&old_root, &holder, Permissions::read_only(), 0, 60).unwrap();
+        let new_packet = KeyAuthority::issue(&new_root, &holder, Permissions::read_only(), 0, 60).unwrap();
+
+        authority.rotate_root(new_root.verifying_key()).unwrap();
+        assert!(authority.verify_packet(&old_packet, 10).is_err());
+        assert!(authority.verify_packet(&new_packet, 10).is_ok());
+        assert_eq!(authority.revocation_version(), 1);
+
+        let reopened = KeyAuthority::open(store).unwrap().unwrap();
+        assert_eq!(reopened.root(), new_root.verifying_key());
+    }
+
+    #[test]
+    fn test_root_policy_defaults_to_root_and_persists() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let root = generate_signing_key().verifying_key();
+        let authority = KeyAuthority::bootstrap(store.clone(), root).unwrap();
+        assert_eq!(authority.root_policy(), RootPolicy::single(&root));
+        assert!(!authority.requires_quorum());
+
+        let signers: Vec<_> = (0..3).map(|_| generate_signing_key().verifying_key()).collect();
+        authority.set_root_policy(RootPolicy::new(2, &signers).unwrap()).unwrap();
+        assert!(authority.requires_quorum());
+
+        let reopened = KeyAuthority::open(store).unwrap().unwrap();
+        assert_eq!(reopened.root_policy().threshold, 2);
+    }
+}
@@ -0,0 +1,266 @@
+//! Cache of verified key packets
+//!
+//! Verifying a packet means base64 and JSON decoding, an Ed25519 signature
+//! check, and decompressing the holder's public key. Clients reuse the same
+//! packet for every request, so the result is cached by token hash and the
+//! hot path is reduced to a hash, a map lookup, and the request signature.
+
+use ed25519_dalek::VerifyingKey;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::{KeyAuthority, KeyId, KeyPacketClaims, Result};
+
+/// Default number of packets kept in the cache
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Default upper bound on how long a verified packet is trusted without re-verification
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// A key packet whose signature, issuer, and expiry have been checked
+#[derive(Debug, Clone)]
+pub struct VerifiedPacket {
+    pub claims: KeyPacketClaims,
+    pub public_key: VerifyingKey,
+}
+
+struct CacheEntry {
+    packet: Arc<VerifiedPacket>,
+    expires_at_ms: u64,
+    revocation_version: AtomicU64,
+}
+
+/// Verified packets keyed by the BLAKE3 hash of the token
+pub struct VerifiedPacketCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: RwLock<HashMap<[u8; 32], CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl VerifiedPacketCache {
+    /// Create a cache holding at most `capacity` packets for at most `ttl` each
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        VerifiedPacketCache {
+            capacity,
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Return the verified packet for a token, verifying and caching it on a miss
+    ///
+    /// Entries expire at the packet's own `exp` or after the cache TTL,
+    /// whichever comes first. When the authority's revocation list has
+    /// changed since an entry was cached, the holder and issuer are
+    /// re-checked before the entry is used again.
+    pub fn get_or_verify(
+        &self,
+        token: &str,
+        authority: &KeyAuthority,
+        now_ms: u64,
+    ) -> Result<Arc<VerifiedPacket>> {
+        let hash = *blake3::hash(token.as_bytes()).as_bytes();
+
+        if let Some(packet) = self.lookup(&hash, authority, now_ms)? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(packet);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let revocation_version = authority.revocation_version();
+        let claims = authority.verify_packet(token, now_ms / 1000)?;
+        let public_key = claims.public_key()?;
+        let expires_at_ms = claims
+            .expires_at_ms()
+            .min(now_ms.saturating_add(self.ttl.as_millis() as u64));
+        let packet = Arc::new(VerifiedPacket { claims, public_key });
+
+        if self.capacity > 0 {
+            let mut entries = self.entries.write().unwrap();
+            if entries.len() >= self.capacity {
+                entries.retain(|_, entry| entry.expires_at_ms > now_ms);
+            }
+            if entries.len() >= self.capacity {
+                if let Some(victim) = entries.keys().next().copied() {
+                    entries.remove(&victim);
+                }
+            }
+            entries.insert(
+                hash,
+                CacheEntry {
+                    packet: packet.clone(),
+                    expires_at_ms,
+                    revocation_version: AtomicU64::new(revocation_version),
+                },
+            );
+        }
+
+        Ok(packet)
+    }
+
+    fn lookup(
+        &self,
+        hash: &[u8; 32],
+        authority: &KeyAuthority,
+        now_ms: u64,
+    ) -> Result<Option<Arc<VerifiedPacket>>> {
+        let entries = self.entries.read().unwrap();
+        let entry = match entries.get(hash) {
+            Some(entry) if entry.expires_at_ms > now_ms => entry,
+            Some(_) => {
+                drop(entries);
+                self.entries.write().unwrap().remove(hash);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+
+        let current = authority.revocation_version();
+        if entry.revocation_version.load(Ordering::Acquire) != current {
+            if let Err(e) = authority.check_revoked(&entry.packet.claims) {
+                drop(entries);
+                self.entries.write().unwrap().remove(hash);
+                return Err(e);
+            }
+            entry.revocation_version.store(current, Ordering::Release);
+        }
+
+        Ok(Some(entry.packet.clone()))
+    }
+
+    /// Drop every cached packet held by or issued by a key
+    pub fn invalidate(&self, id: &KeyId) {
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|_, entry| entry.packet.claims.sub != *id && entry.packet.claims.iss != *id);
+    }
+
+    /// Drop every cached packet
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+
+    /// Number of cached packets
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lookups served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that required full verification
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for VerifiedPacketCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_CAPACITY, DEFAULT_CACHE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_signing_key, AuthError, Permissions};
+    use ed25519_dalek::SigningKey;
+
+    fn setup(ttl_secs: u64) -> (SigningKey, KeyAuthority, String, KeyId) {
+        let root = generate_signing_key();
+        let holder = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let token = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_only(), 0, ttl_secs).unwrap();
+        let holder_id = KeyId::from_public_key(&holder.verifying_key());
+        (root, authority, token, holder_id)
+    }
+
+    #[test]
+    fn test_second_lookup_is_a_hit() {
+        let (_, authority, token, holder_id) = setup(3_600);
+        let cache = VerifiedPacketCache::default();
+
+        let first = cache.get_or_verify(&token, &authority, 1_000).unwrap();
+        let second = cache.get_or_verify(&token, &authority, 2_000).unwrap();
+
+        assert_eq!(first.claims.sub, holder_id);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[test]
+    fn test_entry_expires_with_packet() {
+        let (_, authority, token, _) = setup(10);
+        let cache = VerifiedPacketCache::new(16, Duration::from_secs(3_600));
+
+        cache.get_or_verify(&token, &authority, 1_000).unwrap();
+        assert_eq!(
+            cache.get_or_verify(&token, &authority, 10_000).unwrap_err(),
+            AuthError::Expired
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_entry_expires_after_ttl() {
+        let (_, authority, token, _) = setup(3_600);
+        let cache = VerifiedPacketCache::new(16, Duration::from_secs(5));
+
+        cache.get_or_verify(&token, &authority, 0).unwrap();
+        cache.get_or_verify(&token, &authority, 4_999).unwrap();
+        cache.get_or_verify(&token, &authority, 5_000).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[test]
+    fn test_revocation_invalidates_cached_packet() {
+        let (_, authority, token, holder_id) = setup(3_600);
+        let cache = VerifiedPacketCache::default();
+
+        cache.get_or_verify(&token, &authority, 0).unwrap();
+        authority.revoke(holder_id).unwrap();
+
+        assert_eq!(
+            cache.get_or_verify(&token, &authority, 0).unwrap_err(),
+            AuthError::Revoked(holder_id)
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_issuer_revocation_invalidates_cached_packet() {
+        let (root, authority, token, _) = setup(3_600);
+        let cache = VerifiedPacketCache::default();
+        let root_id = KeyId::from_public_key(&root.verifying_key());
+
+        cache.get_or_verify(&token, &authority, 0).unwrap();
+        authority.revoke(root_id).unwrap();
+        assert!(cache.get_or_verify(&token, &authority, 0).is_err());
+    }
+
+    #[test]
+    fn test_capacity_is_bounded() {
+        let root = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let cache = VerifiedPacketCache::new(2, DEFAULT_CACHE_TTL);
+
+        for _ in 0..5 {
+            let holder = generate_signing_key();
+            let token = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_only(), 0, 3_600).unwrap();
+            cache.get_or_verify(&token, &authority, 0).unwrap();
+        }
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,141 @@
+//! Per-request caveats
+//!
+//! A client can narrow what one signed request may do by sending
+//! `x-wfldb-caveats`, a JSON object, among its signed headers. The server
+//! grants the request only what both its key packet and its caveats allow,
+//! so a signature that leaks is worth no more than the caveats say, however
+//! broad the key behind it.
+//!
+//! Caveats are only ever restrictions. Unknown ones are refused rather than
+//! ignored, since a client relying on a caveat this server doesn't know
+//! would otherwise get less protection than it asked for.
+
+use serde::{Deserialize, Serialize};
+use crate::{headers, AuthError, Permissions, Result};
+
+/// Restrictions a client attaches to a single request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Caveats {
+    /// Buckets the request may act on, within those the key packet grants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<String>>,
+    /// The request may only address keys starting with this prefix, and
+    /// only list under it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_prefix: Option<String>,
+    /// Largest request body the request may carry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<u64>,
+}
+
+impl Caveats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the request to the given buckets
+    pub fn with_buckets(mut self, buckets: &[&str]) -> Self {
+        self.buckets = Some(buckets.iter().map(|b| b.to_string()).collect());
+        self
+    }
+
+    /// Limit the request to keys under `prefix`
+    pub fn with_key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Limit the request body to `max` bytes
+    pub fn with_max_body_bytes(mut self, max: u64) -> Self {
+        self.max_body_bytes = Some(max);
+        self
+    }
+
+    /// Parse the `x-wfldb-caveats` header
+    pub fn parse(value: &str) -> Result<Self> {
+        serde_json::from_str(value).map_err(|e| AuthError::MalformedHeader(headers::CAVEATS, e.to_string()))
+    }
+
+    /// Value of the `x-wfldb-caveats` header
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("caveats serialize")
+    }
+
+    /// What `granted` allows within these caveats
+    ///
+    /// Admin rights are server-wide and can't be narrowed to a bucket or a
+    /// prefix, so a request with caveats never holds them.
+    pub fn attenuate(&self, granted: &Permissions) -> Permissions {
+        let mut perms = granted.clone();
+        perms.admin = false;
+        if let Some(buckets) = &self.buckets {
+            perms.buckets = Some(match &granted.buckets {
+                Some(granted) => buckets.iter().filter(|b| granted.contains(b)).cloned().collect(),
+                None => buckets.clone(),
+            });
+        }
+        if let Some(prefix) = &self.key_prefix {
+            // The narrower of each granted prefix and the caveat's, where they overlap
+            perms.list_prefixes = Some(match &granted.list_prefixes {
+                Some(granted) => granted
+                    .iter()
+                    .filter_map(|p| match (p.starts_with(prefix.as_str()), prefix.starts_with(p.as_str())) {
+                        (true, _) => Some(p.clone()),
+                        (false, true) => Some(prefix.clone()),
+                        (false, false) => None,
+                    })
+                    .collect(),
+                None => vec![prefix.clone()],
+            });
+        }
+        perms
+    }
+
+    /// Whether the request may address `key`; `None` for a request that
+    /// addresses several keys or none, which a key prefix can't be checked on
+    pub fn allows_key(&self, key: Option<&str>) -> bool {
+        match (&self.key_prefix, key) {
+            (None, _) => true,
+            (Some(prefix), Some(key)) => key.starts_with(prefix.as_str()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_core::BucketId;
+
+    #[test]
+    fn test_attenuation_intersects_with_the_packet() {
+        let photos = BucketId::new("photos").unwrap();
+        let logs = BucketId::new("logs").unwrap();
+        let caveats = Caveats::new().with_buckets(&["photos", "logs"]).with_key_prefix("users/alice/");
+
+        let perms = caveats.attenuate(&Permissions::admin().with_buckets(&["photos"]));
+        assert!(perms.can_write(&photos));
+        assert!(!perms.can_read(&logs));
+        assert!(!perms.can_admin());
+        assert!(perms.can_list(&photos, "users/alice/2024/"));
+        assert!(!perms.can_list(&photos, "users/"));
+
+        let listing = Permissions::read_only().with_list_prefixes(&["users/", "users/alice/inbox/", "teams/"]);
+        let prefixes = caveats.attenuate(&listing).list_prefixes.unwrap();
+        assert_eq!(prefixes, vec!["users/alice/".to_string(), "users/alice/inbox/".to_string()]);
+
+        assert!(caveats.allows_key(Some("users/alice/cat.jpg")));
+        assert!(!caveats.allows_key(Some("users/bob/cat.jpg")));
+        assert!(!caveats.allows_key(None));
+        assert!(Caveats::new().allows_key(None));
+    }
+
+    #[test]
+    fn test_parse_refuses_unknown_caveats() {
+        let caveats = Caveats::new().with_key_prefix("tmp/").with_max_body_bytes(1 << 20);
+        assert_eq!(Caveats::parse(&caveats.encode()).unwrap(), caveats);
+        assert!(Caveats::parse(r#"{"max_body_bytes": 10, "methods": ["GET"]}"#).is_err());
+        assert!(Caveats::parse("not json").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Request authentication
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Verifier};
+use std::sync::Arc;
+use wfldb_core::{BucketId, TenantId};
+use wfldb_net::CanonicalRequest;
+use crate::{
+    headers, AuthError, BucketAcl, Caveats, BucketAclRegistry, ChunkVerifier, KeyAuthority, KeyId, NonceCache, Permissions, PrincipalRegistry,
+    Result, VerifiedPacket, VerifiedPacketCache, STREAMING_PAYLOAD,
+};
+
+/// The parts of an HTTP request covered by its signature
+#[derive(Debug, Clone, Default)]
+pub struct RequestParts<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// Raw query string without the leading `?`
+    pub query: &'a str,
+    pub headers: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> RequestParts<'a> {
+    pub fn new(method: &'a str, path: &'a str) -> Self {
+        RequestParts {
+            method,
+            path,
+            query: "",
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn with_query(mut self, query: &'a str) -> Self {
+        self.query = query;
+        self
+    }
+
+    pub fn with_header(mut self, name: &'a str, value: &'a str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Case-insensitive header lookup
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+
+    /// All values of a header, comma-joined as HTTP allows for repeated fields
+    pub fn header_values(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+            .collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(","))
+        }
+    }
+
+    fn require(&self, name: &'static str) -> Result<&'a str> {
+        self.header(name).ok_or(AuthError::MissingHeader(name))
+    }
+}
+
+/// Parse the signed-headers list into lowercase header names
+pub fn parse_signed_headers(list: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for name in list.split(';').map(str::trim).filter(|name| !name.is_empty()) {
+        let name = name.to_ascii_lowercase();
+        if name == headers::SIGNATURE || name == headers::SIGNED_HEADERS {
+            return Err(AuthError::MalformedHeader(
+                headers::SIGNED_HEADERS,
+                format!("'{}' cannot be signed", name),
+            ));
+        }
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Build the canonical string a client signs for a request
+///
+/// Covers the method, path, normalized query string, and every header named
+/// in `signed_headers`; a listed header missing from the request is an error.
+pub fn canonical_string(
+    request: &RequestParts<'_>,
+    signed_headers: &[String],
+    timestamp_ms: u64,
+    nonce: &str,
+    content_hash: &str,
+) -> Result<String> {
+    let mut canonical = CanonicalRequest::new(request.method, request.path)
+        .with_query(request.query)
+        .with_timestamp(timestamp_ms)
+        .with_nonce(nonce.to_string())
+        .with_payload_hash(content_hash.to_string());
+    for name in signed_headers {
+        let value = request.header_values(name).ok_or_else(|| {
+            AuthError::MalformedHeader(headers::SIGNED_HEADERS, format!("signed header '{}' is missing", name))
+        })?;
+        canonical = canonical.add_header(name, &value);
+    }
+    Ok(canonical.build())
+}
+
+/// Identity and permissions of an authenticated request
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    packet: Arc<VerifiedPacket>,
+    principal: Option<String>,
+    tenant: Option<TenantId>,
+    /// Content hash the client signed; callers must check it against the body
+    pub content_hash: String,
+    signature: String,
+    caveats: Option<Caveats>,
+    /// The packet's permissions within `caveats`, when there are any
+    attenuated: Option<Permissions>,
+}
+
+impl AuthContext {
+    /// Key ID of the caller
+    pub fn key_id(&self) -> KeyId {
+        self.packet.claims.sub
+    }
+
+    /// Name of the registered principal owning the caller's key
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Principal name if registered, otherwise the key ID, for logs
+    pub fn display_name(&self) -> String {
+        match &self.principal {
+            Some(name) => name.clone(),
+            None => self.key_id().to_string(),
+        }
+    }
+
+    /// Key ID of the issuer that vouched for the caller
+    pub fn issuer(&self) -> KeyId {
+        self.packet.claims.iss
+    }
+
+    /// What the request may do: the key packet's permissions, narrowed by
+    /// the request's caveats
+    pub fn permissions(&self) -> &Permissions {
+        self.attenuated.as_ref().unwrap_or(&self.packet.claims.perms)
+    }
+
+    /// Caveats the client attached to this request
+    pub fn caveats(&self) -> Option<&Caveats> {
+        self.caveats.as_ref()
+    }
+
+    /// Check that the request's caveats let it address `key`; `None` for a
+    /// request that doesn't address a single key
+    pub fn authorize_key(&self, key: Option<&str>) -> Result<()> {
+        match &self.caveats {
+            Some(caveats) if !caveats.allows_key(key) => Err(AuthError::PermissionDenied(match key {
+                Some(key) => format!("key '{}' is outside the request's caveats", key),
+                None => "request is not limited to keys within its caveats".to_string(),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tenant namespace the caller's buckets live in, from the key packet
+    pub fn tenant(&self) -> Option<&TenantId> {
+        self.tenant.as_ref()
+    }
+
+    /// Check the bucket ACL against an existing object's owner
+    ///
+    /// Under `OwnerOnly`, only the owner or an admin may act on an object;
+    /// objects without a recorded owner predate ownership and stay shared.
+    pub fn authorize_owner(&self, acl: BucketAcl, owner: Option<&str>) -> Result<()> {
+        if acl != BucketAcl::OwnerOnly || self.permissions().can_admin() {
+            return Ok(());
+        }
+        match owner {
+            Some(owner) if owner != self.key_id().to_string() => {
+                Err(AuthError::PermissionDenied("object is owned by another key".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the caller may list keys under `prefix` in `bucket`
+    pub fn authorize_list(&self, bucket: &BucketId, prefix: &str) -> Result<()> {
+        if self.permissions().can_list(bucket, prefix) {
+            Ok(())
+        } else {
+            Err(AuthError::PermissionDenied(format!(
+                "list '{}' in bucket '{}'",
+                prefix,
+                bucket.as_str()
+            )))
+        }
+    }
+
+    /// Whether the body is chunk-signed rather than covered by `content_hash`
+    pub fn is_streaming(&self) -> bool {
+        self.content_hash == STREAMING_PAYLOAD
+    }
+
+    /// Verifier for a chunk-signed body, chained to this request's signature
+    pub fn chunk_verifier(&self) -> Option<ChunkVerifier> {
+        if self.is_streaming() {
+            Some(ChunkVerifier::new(self.packet.public_key, self.signature.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Check that the caller may perform `method` on `bucket`
+    pub fn authorize(&self, method: &str, bucket: &BucketId) -> Result<()> {
+        let perms = self.permissions();
+        let allowed = match method {
+            "GET" | "HEAD" => perms.can_read(bucket),
+            "PUT" | "POST" | "PATCH" => perms.can_write(bucket),
+            "DELETE" => perms.can_delete(bucket),
+            _ => perms.can_admin(),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(AuthError::PermissionDenied(format!("{} on bucket '{}'", method, bucket.as_str())))
+        }
+    }
+}
+
+/// Verifies signed requests against a key authority
+pub struct Authenticator {
+    authority: Arc<KeyAuthority>,
+    cache: VerifiedPacketCache,
+    nonces: NonceCache,
+    principals: Arc<PrincipalRegistry>,
+    bucket_acls: Arc<BucketAclRegistry>,
+}
+
+impl Authenticator {
+    pub fn new(authority: Arc<KeyAuthority>) -> Self {
+        Authenticator {
+            authority,
+            cache: VerifiedPacketCache::default(),
+            nonces: NonceCache::default(),
+            principals: Arc::new(PrincipalRegistry::new()),
+            bucket_acls: Arc::new(BucketAclRegistry::new()),
+        }
+    }
+
+    pub fn with_cache(mut self, cache: VerifiedPacketCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_nonce_cache(mut self, nonces: NonceCache) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    pub fn with_principals(mut self, principals: Arc<PrincipalRegistry>) -> Self {
+        self.principals = principals;
+        self
+    }
+
+    pub fn with_bucket_acls(mut self, bucket_acls: Arc<BucketAclRegistry>) -> Self {
+        self.bucket_acls = bucket_acls;
+        self
+    }
+
+    pub fn bucket_acls(&self) -> &Arc<BucketAclRegistry> {
+        &self.bucket_acls
+    }
+
+    pub fn principals(&self) -> &Arc<PrincipalRegistry> {
+        &self.principals
+    }
+
+    pub fn authority(&self) -> &Arc<KeyAuthority> {
+        &self.authority
+    }
+
+    pub fn cache(&self) -> &VerifiedPacketCache {
+        &self.cache
+    }
+
+    pub fn nonces(&self) -> &NonceCache {
+        &self.nonces
+    }
+
+    /// Authenticate a request: key packet, request signature, and replay checks
+    pub fn authenticate(&self, request: &RequestParts<'_>, now_ms: u64) -> Result<AuthContext> {
+        let authorization = request.require(headers::AUTHORIZATION)?;
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| AuthError::MalformedHeader(headers::AUTHORIZATION, "expected Bearer token".to_string()))?;
+
+        let timestamp = request.require(headers::TIMESTAMP)?;
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| AuthError::MalformedHeader(headers::TIMESTAMP, timestamp.to_string()))?;
+        let nonce = request.require(headers::NONCE)?;
+        let content_hash = request.require(headers::CONTENT_HASH)?;
+        let signature_header = request.require(headers::SIGNATURE)?;
+        let signed_headers = match request.header(headers::SIGNED_HEADERS) {
+            Some(list) => parse_signed_headers(list)?,
+            None => Vec::new(),
+        };
+        // Caveats only protect a signature if they can't be stripped from it
+        let caveats = match request.header(headers::CAVEATS) {
+            Some(_) if !signed_headers.iter().any(|name| name == headers::CAVEATS) => {
+                return Err(AuthError::MalformedHeader(headers::CAVEATS, "must be a signed header".to_string()));
+            }
+            Some(value) => Some(Caveats::parse(value)?),
+            None => None,
+        };
+
+        let packet = self.cache.get_or_verify(token, &self.authority, now_ms)?;
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature_header)
+            .map_err(|_| AuthError::InvalidSignature)?;
+        let signature = Signature::from_slice(&signature).map_err(|_| AuthError::InvalidSignature)?;
+        let canonical = canonical_string(request, &signed_headers, timestamp, nonce, content_hash)?;
+        packet
+            .public_key
+            .verify(canonical.as_bytes(), &signature)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        // Only record the nonce once the signature is known to be genuine
+        if matches!(request.method, "GET" | "HEAD") {
+            self.nonces.check_read(nonce, timestamp, now_ms)?;
+        } else {
+            self.nonces.check(nonce, timestamp, now_ms)?;
+        }
+
+        let tenant = packet.claims.perms.tenant
+            .as_deref()
+            .map(TenantId::new)
+            .transpose()
+            .map_err(|e| AuthError::InvalidPacket(e.to_string()))?;
+        let principal = self.principals.name_for(&packet.claims.sub);
+        let attenuated = caveats.as_ref().map(|caveats| caveats.attenuate(&packet.claims.perms));
+        Ok(AuthContext {
+            packet,
+            principal,
+            tenant,
+            content_hash: content_hash.to_string(),
+            signature: signature_header.to_string(),
+            caveats,
+            attenuated,
+        })
+    }
+}
@@ -0,0 +1,75 @@
+//! Authentication error types
+
+use thiserror::Error;
+use crate::KeyId;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AuthError {
+    #[error("Missing header: {0}")]
+    MissingHeader(&'static str),
+
+    #[error("Malformed header {0}: {1}")]
+    MalformedHeader(&'static str, String),
+
+    #[error("Invalid key packet: {0}")]
+    InvalidPacket(String),
+
+    #[error("Unknown issuer: {0}")]
+    UnknownIssuer(KeyId),
+
+    #[error("Key revoked: {0}")]
+    Revoked(KeyId),
+
+    #[error("Key packet expired")]
+    Expired,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Request timestamp outside the allowed window")]
+    TimestampOutOfWindow,
+
+    #[error("Replayed request nonce")]
+    Replay,
+
+    #[error("Malformed body chunk: {0}")]
+    MalformedChunk(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Unknown admin proposal: {0}")]
+    UnknownProposal(String),
+
+    #[error("Invalid root policy: {0}")]
+    InvalidPolicy(String),
+
+    #[error("Invalid principal: {0}")]
+    InvalidPrincipal(String),
+
+    #[error("Authority store error: {0}")]
+    Storage(String),
+}
+
+impl AuthError {
+    /// Stable machine-readable code for error responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingHeader(_) => "missing_header",
+            AuthError::MalformedHeader(..) => "malformed_header",
+            AuthError::InvalidPacket(_) => "invalid_packet",
+            AuthError::UnknownIssuer(_) => "unknown_issuer",
+            AuthError::Revoked(_) => "revoked",
+            AuthError::Expired => "expired",
+            AuthError::InvalidSignature => "invalid_signature",
+            AuthError::TimestampOutOfWindow => "timestamp_out_of_window",
+            AuthError::Replay => "replay",
+            AuthError::MalformedChunk(_) => "malformed_chunk",
+            AuthError::PermissionDenied(_) => "permission_denied",
+            AuthError::UnknownProposal(_) => "unknown_proposal",
+            AuthError::InvalidPolicy(_) => "invalid_policy",
+            AuthError::InvalidPrincipal(_) => "invalid_principal",
+            AuthError::Storage(_) => "storage",
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Server identity key
+//!
+//! Each server holds its own Ed25519 key pair, generated on first start and
+//! kept in the authority store. Unlike root and issuer keys it authorizes
+//! nothing: it signs what the server vouches for, such as audit log
+//! exports, so a reader holding the public key can tell the content hasn't
+//! changed since the server produced it.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use wfldb_core::ContentHash;
+use crate::store::IDENTITY_KEY;
+use crate::{decode_signing_key, encode_signing_key, generate_signing_key, AuthError, AuthorityStore, KeyId, Result};
+
+/// Domain separator for audit export signatures
+const AUDIT_EXPORT_CONTEXT: &str = "wfldb-audit-export-v1";
+
+/// The key pair a server signs with
+#[derive(Clone)]
+pub struct ServerIdentity {
+    key: SigningKey,
+}
+
+impl ServerIdentity {
+    pub fn new(key: SigningKey) -> Self {
+        ServerIdentity { key }
+    }
+
+    /// This server's identity, generating and storing one on first use
+    pub fn load_or_generate(store: &dyn AuthorityStore) -> Result<Self> {
+        if let Some(stored) = store.get(IDENTITY_KEY)? {
+            let encoded = String::from_utf8(stored)
+                .map_err(|_| AuthError::Storage("identity key is not UTF-8".to_string()))?;
+            return Ok(ServerIdentity::new(decode_signing_key(&encoded)?));
+        }
+        let key = generate_signing_key();
+        store.put(IDENTITY_KEY, encode_signing_key(&key).as_bytes())?;
+        Ok(ServerIdentity::new(key))
+    }
+
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    pub fn key_id(&self) -> KeyId {
+        KeyId::from_public_key(&self.public_key())
+    }
+
+    /// Sign an audit export whose content hashes to `digest`, returning the
+    /// base64url signature
+    pub fn sign_audit_export(&self, digest: &ContentHash) -> String {
+        URL_SAFE_NO_PAD.encode(self.key.sign(audit_export_message(digest).as_bytes()).to_bytes())
+    }
+}
+
+/// The message signed for an audit export whose content hashes to `digest`
+pub fn audit_export_message(digest: &ContentHash) -> String {
+    format!("{}:{}", AUDIT_EXPORT_CONTEXT, digest.to_hex())
+}
+
+/// Check that `key` signed an audit export whose content hashes to `digest`
+pub fn verify_audit_export(key: &VerifyingKey, digest: &ContentHash, signature: &str) -> Result<()> {
+    let decoded = URL_SAFE_NO_PAD.decode(signature).map_err(|_| AuthError::InvalidSignature)?;
+    let decoded = Signature::from_slice(&decoded).map_err(|_| AuthError::InvalidSignature)?;
+    key.verify(audit_export_message(digest).as_bytes(), &decoded)
+        .map_err(|_| AuthError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryAuthorityStore;
+
+    #[test]
+    fn test_identity_persists_and_signs() {
+        let store = MemoryAuthorityStore::new();
+        let identity = ServerIdentity::load_or_generate(&store).unwrap();
+        let reloaded = ServerIdentity::load_or_generate(&store).unwrap();
+        assert_eq!(reloaded.key_id(), identity.key_id());
+
+        let digest = ContentHash::new(b"events");
+        let signature = identity.sign_audit_export(&digest);
+        verify_audit_export(&reloaded.public_key(), &digest, &signature).unwrap();
+        let altered = ContentHash::new(b"edited events");
+        assert!(verify_audit_export(&identity.public_key(), &altered, &signature).is_err());
+        let other = generate_signing_key().verifying_key();
+        assert!(verify_audit_export(&other, &digest, &signature).is_err());
+    }
+}
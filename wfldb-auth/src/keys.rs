@@ -0,0 +1,129 @@
+//! Key identifiers and Ed25519 key helpers
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::{AuthError, Result};
+
+/// Short identifier for a public key (first 16 bytes of its BLAKE3 hash)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyId([u8; 16]);
+
+impl KeyId {
+    /// Derive the key ID of an Ed25519 public key
+    pub fn from_public_key(key: &VerifyingKey) -> Self {
+        let hash = blake3::hash(key.as_bytes());
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&hash.as_bytes()[..16]);
+        KeyId(id)
+    }
+
+    /// Parse a key ID from its hex representation
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        if hex.len() != 32 {
+            return Err(AuthError::InvalidPacket(format!("invalid key id '{}'", hex)));
+        }
+        let mut id = [0u8; 16];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| AuthError::InvalidPacket(format!("invalid key id '{}'", hex)))?;
+        }
+        Ok(KeyId(id))
+    }
+
+    /// Get the key ID as bytes
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for KeyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in &self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for KeyId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        KeyId::from_hex(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Generate a new random signing key
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut rand::rngs::OsRng)
+}
+
+/// Encode a signing key's secret seed as unpadded base64url
+pub fn encode_signing_key(key: &SigningKey) -> String {
+    URL_SAFE_NO_PAD.encode(key.to_bytes())
+}
+
+/// Decode a signing key from its unpadded base64url secret seed
+pub fn decode_signing_key(encoded: &str) -> Result<SigningKey> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded.trim())
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid signing key encoding: {}", e)))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| AuthError::InvalidPacket("signing key must be 32 bytes".to_string()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Encode a public key as unpadded base64url
+pub fn encode_public_key(key: &VerifyingKey) -> String {
+    URL_SAFE_NO_PAD.encode(key.as_bytes())
+}
+
+/// Decode a public key from unpadded base64url
+pub fn decode_public_key(encoded: &str) -> Result<VerifyingKey> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid public key encoding: {}", e)))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| AuthError::InvalidPacket("public key must be 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid public key: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_id_hex_roundtrip() {
+        let key = generate_signing_key();
+        let id = KeyId::from_public_key(&key.verifying_key());
+
+        let hex = id.to_string();
+        assert_eq!(hex.len(), 32);
+        assert_eq!(KeyId::from_hex(&hex).unwrap(), id);
+        assert!(KeyId::from_hex("not-a-key-id").is_err());
+    }
+
+    #[test]
+    fn test_public_key_encoding_roundtrip() {
+        let key = generate_signing_key().verifying_key();
+        let encoded = encode_public_key(&key);
+        assert_eq!(decode_public_key(&encoded).unwrap(), key);
+        assert!(decode_public_key("AAAA").is_err());
+    }
+
+    #[test]
+    fn test_signing_key_encoding_roundtrip() {
+        let key = generate_signing_key();
+        let decoded = decode_signing_key(&encode_signing_key(&key)).unwrap();
+        assert_eq!(decoded.verifying_key(), key.verifying_key());
+    }
+}
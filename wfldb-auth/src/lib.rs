@@ -0,0 +1,99 @@
+//! Security plane for wflDB: key packets, request signing, and verification
+//!
+//! Clients hold an Ed25519 key pair and a key packet (a JWT signed with EdDSA
+//! by an issuer the server trusts) describing their permissions. Each request
+//! carries the packet plus a signature over the canonical request string.
+
+pub mod acl;
+pub mod authority;
+pub mod cache;
+pub mod caveats;
+pub mod context;
+pub mod error;
+pub mod identity;
+pub mod keys;
+pub mod multisig;
+pub mod nonce;
+pub mod packet;
+pub mod permissions;
+pub mod principals;
+pub mod revocation;
+pub mod signer;
+pub mod store;
+pub mod streaming;
+
+pub use acl::*;
+pub use authority::*;
+pub use cache::*;
+pub use caveats::*;
+pub use context::*;
+pub use error::*;
+pub use identity::*;
+pub use keys::*;
+pub use multisig::*;
+pub use nonce::*;
+pub use packet::*;
+pub use permissions::*;
+pub use principals::*;
+pub use revocation::*;
+pub use signer::*;
+pub use store::{AuthorityStore, MemoryAuthorityStore};
+pub use streaming::*;
+
+/// Result type alias for authentication operations
+pub type Result<T> = std::result::Result<T, AuthError>;
+
+/// Request headers used by the authentication scheme
+pub mod headers {
+    pub const AUTHORIZATION: &str = "authorization";
+    pub const SIGNATURE: &str = "x-wfldb-signature";
+    pub const TIMESTAMP: &str = "x-wfldb-timestamp";
+    pub const NONCE: &str = "x-wfldb-nonce";
+    pub const CONTENT_HASH: &str = "x-wfldb-content-hash";
+    /// `;`-separated, lowercase names of extra headers covered by the signature
+    pub const SIGNED_HEADERS: &str = "x-wfldb-signed-headers";
+    /// Server clock (ms) returned on 401 responses so clients can correct skew
+    pub const SERVER_TIME: &str = "x-wfldb-server-time";
+    /// Requested scheduling class: `interactive`, `normal`, or `bulk`
+    pub const PRIORITY: &str = "x-wfldb-priority";
+    /// Version of the object a GET returned, for optimistic transactions
+    pub const VERSION: &str = "x-wfldb-version";
+    /// Generation of the object a write made or a GET returned
+    pub const GENERATION: &str = "x-wfldb-generation";
+    /// Fencing token: a PUT only applies if the key is at this generation
+    pub const IF_GENERATION: &str = "x-wfldb-if-generation";
+    /// Lease id proving the caller holds a key's lease
+    pub const LEASE: &str = "x-wfldb-lease";
+    /// Seconds a PUT's object lives before it expires
+    pub const TTL: &str = "x-wfldb-ttl-secs";
+    /// When the object a GET returned expires (ms since the Unix epoch)
+    pub const EXPIRES_AT: &str = "x-wfldb-expires-at";
+    /// Asks for a `Server-Timing` breakdown; honored for admin keys only
+    pub const DEBUG_TIMING: &str = "x-wfldb-debug-timing";
+    /// Journal sequence the answering node has applied, on `/v1` responses
+    pub const APPLIED_SEQ: &str = "x-wfldb-applied-seq";
+    /// Read floor: the node must have applied at least this journal sequence
+    pub const MIN_SEQ: &str = "x-wfldb-min-seq";
+    /// Longest a replica may lag the leader (ms) and still serve the read
+    pub const MAX_STALENESS: &str = "x-wfldb-max-staleness-ms";
+    /// Session token: sent by clients, returned on every `/v1` response
+    pub const SESSION: &str = "x-wfldb-session";
+    /// `AES256`: the object is encrypted with the key in [`SSE_CUSTOMER_KEY`]
+    pub const SSE_CUSTOMER_ALGORITHM: &str = "x-wfldb-sse-customer-algorithm";
+    /// Customer-supplied encryption key, base64; never stored by the server
+    pub const SSE_CUSTOMER_KEY: &str = "x-wfldb-sse-customer-key";
+    /// BLAKE3 hash (hex) of the customer key an object is encrypted with
+    pub const SSE_CUSTOMER_KEY_HASH: &str = "x-wfldb-sse-customer-key-hash";
+    /// JSON restrictions on the one request; must be among the signed headers
+    pub const CAVEATS: &str = "x-wfldb-caveats";
+    /// Request id: sent by clients (reused across retries), echoed on every response
+    pub const REQUEST_ID: &str = "x-request-id";
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
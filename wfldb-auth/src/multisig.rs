@@ -0,0 +1,340 @@
+//! Threshold (m-of-n) approval of sensitive authority operations
+//!
+//! An admin action is first proposed, which fixes its content and assigns it
+//! an ID. Root keyholders then sign the proposal message out of band and
+//! submit their signatures; once `threshold` distinct signers from the root
+//! policy have signed, the action is applied.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::{decode_public_key, encode_public_key, AuthError, KeyAuthority, KeyId, Result};
+
+/// Domain separator for proposal signatures
+const PROPOSAL_CONTEXT: &str = "wfldb-admin-v1";
+
+/// Default lifetime of an unsigned proposal
+pub const DEFAULT_PROPOSAL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The set of root keyholders and how many of them must approve an action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootPolicy {
+    pub threshold: u32,
+    /// Signer public keys (base64url)
+    pub signers: Vec<String>,
+}
+
+impl RootPolicy {
+    /// Create an m-of-n policy
+    pub fn new(threshold: u32, signers: &[VerifyingKey]) -> Result<Self> {
+        let policy = RootPolicy {
+            threshold,
+            signers: signers.iter().map(encode_public_key).collect(),
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// The implicit 1-of-1 policy over a single root key
+    pub fn single(root: &VerifyingKey) -> Self {
+        RootPolicy {
+            threshold: 1,
+            signers: vec![encode_public_key(root)],
+        }
+    }
+
+    /// Check the threshold is satisfiable and every signer key decodes
+    pub fn validate(&self) -> Result<()> {
+        let keys = self.signer_keys()?;
+        let mut ids: Vec<KeyId> = keys.iter().map(KeyId::from_public_key).collect();
+        ids.sort();
+        ids.dedup();
+        if ids.len() != keys.len() {
+            return Err(AuthError::InvalidPolicy("duplicate signer".to_string()));
+        }
+        if self.threshold == 0 || self.threshold as usize > keys.len() {
+            return Err(AuthError::InvalidPolicy(format!(
+                "threshold {} with {} signers",
+                self.threshold,
+                keys.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decode the signer keys
+    pub fn signer_keys(&self) -> Result<Vec<VerifyingKey>> {
+        self.signers.iter().map(|s| decode_public_key(s)).collect()
+    }
+
+    /// Find a signer by key ID
+    pub fn signer(&self, id: &KeyId) -> Option<VerifyingKey> {
+        self.signer_keys()
+            .ok()?
+            .into_iter()
+            .find(|key| KeyId::from_public_key(key) == *id)
+    }
+}
+
+/// Operations that require approval from the root policy quorum
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminAction {
+    /// Revoke a key
+    Revoke { key_id: KeyId },
+    /// Trust an additional packet issuer (base64url public key)
+    AddIssuer { key: String },
+    /// Replace the root key (base64url public key)
+    RotateRoot { key: String },
+    /// Replace the root policy itself
+    SetPolicy { policy: RootPolicy },
+}
+
+impl AdminAction {
+    /// Check the action's arguments before it is proposed
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            AdminAction::Revoke { .. } => Ok(()),
+            AdminAction::AddIssuer { key } | AdminAction::RotateRoot { key } => {
+                decode_public_key(key).map(|_| ())
+            }
+            AdminAction::SetPolicy { policy } => policy.validate(),
+        }
+    }
+}
+
+/// A proposed admin action and the approvals collected so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminProposal {
+    pub id: String,
+    pub action: AdminAction,
+    pub created_at_ms: u64,
+    pub expires_at_ms: u64,
+    /// Signatures (base64url) keyed by signer key ID
+    pub signatures: BTreeMap<KeyId, String>,
+}
+
+impl AdminProposal {
+    fn new(action: AdminAction, now_ms: u64, ttl: Duration) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(PROPOSAL_CONTEXT.as_bytes());
+        hasher.update(&serde_json::to_vec(&action).unwrap_or_default());
+        hasher.update(&now_ms.to_le_bytes());
+        AdminProposal {
+            id: hasher.finalize().to_hex().to_string(),
+            action,
+            created_at_ms: now_ms,
+            expires_at_ms: now_ms.saturating_add(ttl.as_millis() as u64),
+            signatures: BTreeMap::new(),
+        }
+    }
+
+    /// The message each signer signs
+    pub fn message(&self) -> String {
+        proposal_message(&self.id)
+    }
+}
+
+/// The message signed to approve a proposal
+pub fn proposal_message(id: &str) -> String {
+    format!("{}:{}", PROPOSAL_CONTEXT, id)
+}
+
+/// Sign a proposal with a root keyholder's key, returning the base64url signature
+pub fn sign_proposal(key: &SigningKey, id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(key.sign(proposal_message(id).as_bytes()).to_bytes())
+}
+
+/// Outcome of submitting a signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProposalStatus {
+    pub id: String,
+    pub signatures: usize,
+    pub threshold: u32,
+    pub executed: bool,
+}
+
+/// Pending proposals awaiting quorum
+pub struct ProposalBook {
+    ttl: Duration,
+    proposals: Mutex<HashMap<String, AdminProposal>>,
+}
+
+impl ProposalBook {
+    pub fn new(ttl: Duration) -> Self {
+        ProposalBook {
+            ttl,
+            proposals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a new proposal
+    pub fn propose(&self, action: AdminAction, now_ms: u64) -> Result<AdminProposal> {
+        action.validate()?;
+        let proposal = AdminProposal::new(action, now_ms, self.ttl);
+        let mut proposals = self.proposals.lock().unwrap();
+        proposals.retain(|_, p| p.expires_at_ms > now_ms);
+        proposals.insert(proposal.id.clone(), proposal.clone());
+        Ok(proposal)
+    }
+
+    /// Look up a pending proposal
+    pub fn get(&self, id: &str, now_ms: u64) -> Option<AdminProposal> {
+        self.proposals
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|p| p.expires_at_ms > now_ms)
+            .cloned()
+    }
+
+    /// Add a signer's approval, applying the action once the quorum is reached
+    ///
+    /// Signatures are counted against the policy current at submission time,
+    /// so signers removed by a policy change no longer count.
+    pub fn sign(
+        &self,
+        id: &str,
+        signer: &KeyId,
+        signature: &str,
+        authority: &KeyAuthority,
+        now_ms: u64,
+    ) -> Result<ProposalStatus> {
+        let policy = authority.root_policy();
+        let key = policy.signer(signer).ok_or_else(|| {
+            AuthError::PermissionDenied(format!("{} is not in the root policy", signer))
+        })?;
+
+        let decoded = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::InvalidSignature)?;
+        let decoded = Signature::from_slice(&decoded).map_err(|_| AuthError::InvalidSignature)?;
+        key.verify(proposal_message(id).as_bytes(), &decoded)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        let mut proposals = self.proposals.lock().unwrap();
+        let proposal = match proposals.get_mut(id) {
+            Some(p) if p.expires_at_ms > now_ms => p,
+            _ => return Err(AuthError::UnknownProposal(id.to_string())),
+        };
+        proposal.signatures.insert(*signer, signature.to_string());
+
+        let approvals = proposal
+            .signatures
+            .keys()
+            .filter(|id| policy.signer(id).is_some())
+            .count();
+        let executed = approvals >= policy.threshold as usize;
+        if executed {
+            let proposal = proposals.remove(id).unwrap();
+            drop(proposals);
+            authority.apply_admin_action(&proposal.action)?;
+        }
+
+        Ok(ProposalStatus {
+            id: id.to_string(),
+            signatures: approvals,
+            threshold: policy.threshold,
+            executed,
+        })
+    }
+}
+
+impl Default for ProposalBook {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROPOSAL_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_signing_key;
+
+    fn quorum(threshold: u32, n: usize) -> (KeyAuthority, Vec<SigningKey>) {
+        let root = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let signers: Vec<SigningKey> = (0..n).map(|_| generate_signing_key()).collect();
+        let keys: Vec<VerifyingKey> = signers.iter().map(|k| k.verifying_key()).collect();
+        authority.set_root_policy(RootPolicy::new(threshold, &keys).unwrap()).unwrap();
+        (authority, signers)
+    }
+
+    #[test]
+    fn test_revoke_needs_threshold_signatures() {
+        let (authority, signers) = quorum(2, 3);
+        let book = ProposalBook::default();
+        let target = KeyId::from_public_key(&generate_signing_key().verifying_key());
+
+        let proposal = book.propose(AdminAction::Revoke { key_id: target }, 0).unwrap();
+        let first = KeyId::from_public_key(&signers[0].verifying_key());
+        let status = book
+            .sign(&proposal.id, &first, &sign_proposal(&signers[0], &proposal.id), &authority, 1)
+            .unwrap();
+        assert!(!status.executed);
+        assert!(!authority.is_revoked(&target));
+
+        // The same signer twice does not reach the quorum
+        let status = book
+            .sign(&proposal.id, &first, &sign_proposal(&signers[0], &proposal.id), &authority, 1)
+            .unwrap();
+        assert_eq!(status.signatures, 1);
+
+        let second = KeyId::from_public_key(&signers[2].verifying_key());
+        let status = book
+            .sign(&proposal.id, &second, &sign_proposal(&signers[2], &proposal.id), &authority, 2)
+            .unwrap();
+        assert!(status.executed);
+        assert!(authority.is_revoked(&target));
+        assert!(book.get(&proposal.id, 3).is_none());
+    }
+
+    #[test]
+    fn test_outsider_and_bad_signatures_rejected() {
+        let (authority, signers) = quorum(2, 3);
+        let book = ProposalBook::default();
+        let proposal = book
+            .propose(AdminAction::Revoke { key_id: authority.root_id() }, 0)
+            .unwrap();
+
+        let outsider = generate_signing_key();
+        let outsider_id = KeyId::from_public_key(&outsider.verifying_key());
+        assert!(matches!(
+            book.sign(&proposal.id, &outsider_id, &sign_proposal(&outsider, &proposal.id), &authority, 1),
+            Err(AuthError::PermissionDenied(_))
+        ));
+
+        let signer_id = KeyId::from_public_key(&signers[0].verifying_key());
+        assert_eq!(
+            book.sign(&proposal.id, &signer_id, &sign_proposal(&signers[1], &proposal.id), &authority, 1),
+            Err(AuthError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_expired_proposal_rejected() {
+        let (authority, signers) = quorum(1, 1);
+        let book = ProposalBook::new(Duration::from_secs(1));
+        let proposal = book
+            .propose(AdminAction::Revoke { key_id: authority.root_id() }, 0)
+            .unwrap();
+        let id = KeyId::from_public_key(&signers[0].verifying_key());
+
+        assert!(matches!(
+            book.sign(&proposal.id, &id, &sign_proposal(&signers[0], &proposal.id), &authority, 1_000),
+            Err(AuthError::UnknownProposal(_))
+        ));
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let keys: Vec<_> = (0..2).map(|_| generate_signing_key().verifying_key()).collect();
+        assert!(RootPolicy::new(2, &keys).is_ok());
+        assert!(RootPolicy::new(3, &keys).is_err());
+        assert!(RootPolicy::new(0, &keys).is_err());
+        assert!(RootPolicy::new(1, &[keys[0], keys[0]]).is_err());
+    }
+}
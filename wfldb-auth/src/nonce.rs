@@ -0,0 +1,170 @@
+//! Replay protection for signed requests
+//!
+//! Every request's nonce is remembered for as long as its timestamp is
+//! valid, which costs a lock and an entry per request. Optionally, reads
+//! (`GET` and `HEAD`) can skip that: with a read window set, a read is
+//! accepted on its timestamp alone, within the (much tighter) read window.
+//!
+//! The tradeoff: a captured read can be replayed until its read window
+//! runs out. Replaying a read changes nothing, but it serves the response
+//! again, to whoever holds the capture. That makes the mode only sound
+//! behind TLS, where requests can't be captured in transit, and with a
+//! window of seconds, not minutes. Writes, deletes, and anything else that
+//! changes state always get full nonce replay protection.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::{AuthError, Result};
+
+/// Default window within which request timestamps are accepted
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(300);
+
+/// Default allowance for disagreement between client and server clocks
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+/// Remembers nonces seen within the replay window
+///
+/// Requests with timestamps outside the window are rejected outright, so a
+/// nonce only needs to be remembered for as long as its timestamp is valid.
+/// The clock-skew tolerance widens the window on both sides.
+pub struct NonceCache {
+    window_ms: u64,
+    skew_ms: u64,
+    /// Reads are checked against this window instead of the nonce cache
+    read_window_ms: Option<u64>,
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl NonceCache {
+    pub fn new(window: Duration) -> Self {
+        NonceCache {
+            window_ms: window.as_millis() as u64,
+            skew_ms: 0,
+            read_window_ms: None,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_clock_skew(mut self, skew: Duration) -> Self {
+        self.skew_ms = skew.as_millis() as u64;
+        self
+    }
+
+    /// Accept reads on their timestamp alone when it is within `window` of
+    /// the server clock, without remembering their nonces
+    pub fn with_nonceless_reads(mut self, window: Duration) -> Self {
+        self.read_window_ms = Some(window.as_millis() as u64);
+        self
+    }
+
+    /// The read window, if reads skip the nonce cache
+    pub fn read_window(&self) -> Option<Duration> {
+        self.read_window_ms.map(Duration::from_millis)
+    }
+
+    /// Largest accepted distance between a request timestamp and the server clock
+    pub fn tolerance_ms(&self) -> u64 {
+        self.window_ms + self.skew_ms
+    }
+
+    /// Check a request timestamp and record its nonce
+    pub fn check(&self, nonce: &str, timestamp_ms: u64, now_ms: u64) -> Result<()> {
+        let tolerance_ms = self.tolerance_ms();
+        if timestamp_ms.abs_diff(now_ms) > tolerance_ms {
+            return Err(AuthError::TimestampOutOfWindow);
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains_key(nonce) {
+            return Err(AuthError::Replay);
+        }
+
+        // Prune lazily once the map grows, keeping it proportional to the request rate
+        if seen.len() >= 1024 && seen.len().is_power_of_two() {
+            let cutoff = now_ms.saturating_sub(tolerance_ms);
+            seen.retain(|_, ts| *ts >= cutoff);
+        }
+        seen.insert(nonce.to_string(), timestamp_ms);
+        Ok(())
+    }
+
+    /// Check a request that only reads: against the read window if reads
+    /// are nonce-less, like any other request otherwise
+    pub fn check_read(&self, nonce: &str, timestamp_ms: u64, now_ms: u64) -> Result<()> {
+        match self.read_window_ms {
+            Some(window_ms) if timestamp_ms.abs_diff(now_ms) > window_ms => Err(AuthError::TimestampOutOfWindow),
+            Some(_) => Ok(()),
+            None => self.check(nonce, timestamp_ms, now_ms),
+        }
+    }
+
+    /// Number of nonces currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for NonceCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_WINDOW).with_clock_skew(DEFAULT_CLOCK_SKEW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_rejected() {
+        let nonces = NonceCache::new(Duration::from_secs(60));
+        assert!(nonces.check("abc", 1_000, 1_000).is_ok());
+        assert_eq!(nonces.check("abc", 1_000, 1_500), Err(AuthError::Replay));
+        assert!(nonces.check("def", 1_000, 1_500).is_ok());
+    }
+
+    #[test]
+    fn test_timestamp_window() {
+        let nonces = NonceCache::new(Duration::from_secs(60));
+        assert_eq!(
+            nonces.check("abc", 0, 60_001),
+            Err(AuthError::TimestampOutOfWindow)
+        );
+        assert_eq!(
+            nonces.check("abc", 120_001, 60_000),
+            Err(AuthError::TimestampOutOfWindow)
+        );
+        assert!(nonces.is_empty());
+    }
+
+    #[test]
+    fn test_nonceless_reads_use_the_read_window() {
+        let nonces = NonceCache::new(Duration::from_secs(60)).with_nonceless_reads(Duration::from_secs(5));
+        assert!(nonces.check_read("abc", 1_000, 1_000).is_ok());
+        assert!(nonces.check_read("abc", 1_000, 6_000).is_ok());
+        assert_eq!(nonces.check_read("abc", 1_000, 6_001), Err(AuthError::TimestampOutOfWindow));
+        assert!(nonces.is_empty());
+        // Writes still can't be replayed
+        assert!(nonces.check("abc", 1_000, 1_000).is_ok());
+        assert_eq!(nonces.check("abc", 1_000, 1_500), Err(AuthError::Replay));
+
+        let nonces = NonceCache::new(Duration::from_secs(60));
+        assert!(nonces.check_read("abc", 1_000, 1_000).is_ok());
+        assert_eq!(nonces.check_read("abc", 1_000, 1_500), Err(AuthError::Replay));
+    }
+
+    #[test]
+    fn test_clock_skew_widens_window() {
+        let nonces = NonceCache::new(Duration::from_secs(60)).with_clock_skew(Duration::from_secs(10));
+        assert!(nonces.check("ahead", 130_000, 60_000).is_ok());
+        assert!(nonces.check("behind", 0, 70_000).is_ok());
+        assert_eq!(
+            nonces.check("late", 0, 70_001),
+            Err(AuthError::TimestampOutOfWindow)
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Key packets: EdDSA-signed JWTs binding a public key to permissions
+//!
+//! A packet is a compact JWS (`header.claims.signature`, base64url without
+//! padding) signed by an issuer key the server trusts.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use crate::{decode_public_key, encode_public_key, AuthError, KeyId, Permissions, Result};
+
+const JWS_HEADER: &str = r#"{"alg":"EdDSA","typ":"JWT"}"#;
+
+/// Claims carried by a key packet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyPacketClaims {
+    /// Key ID of the holder's public key
+    pub sub: KeyId,
+    /// Holder's public key (base64url)
+    pub key: String,
+    /// Key ID of the issuer that signed the packet
+    pub iss: KeyId,
+    /// Issued-at time (seconds since the Unix epoch)
+    pub iat: u64,
+    /// Expiry time (seconds since the Unix epoch)
+    pub exp: u64,
+    /// Granted permissions
+    pub perms: Permissions,
+}
+
+impl KeyPacketClaims {
+    /// Create claims for a holder key, valid for `ttl_secs` from `now_secs`
+    pub fn new(
+        holder: &VerifyingKey,
+        issuer: &VerifyingKey,
+        perms: Permissions,
+        now_secs: u64,
+        ttl_secs: u64,
+    ) -> Self {
+        KeyPacketClaims {
+            sub: KeyId::from_public_key(holder),
+            key: encode_public_key(holder),
+            iss: KeyId::from_public_key(issuer),
+            iat: now_secs,
+            exp: now_secs.saturating_add(ttl_secs),
+            perms,
+        }
+    }
+
+    /// Decode the holder's public key
+    pub fn public_key(&self) -> Result<VerifyingKey> {
+        let key = decode_public_key(&self.key)?;
+        if KeyId::from_public_key(&key) != self.sub {
+            return Err(AuthError::InvalidPacket("subject does not match key".to_string()));
+        }
+        Ok(key)
+    }
+
+    /// Expiry in milliseconds since the Unix epoch
+    pub fn expires_at_ms(&self) -> u64 {
+        self.exp.saturating_mul(1000)
+    }
+}
+
+/// Sign claims with an issuer key, producing a compact JWS
+pub fn sign_packet(claims: &KeyPacketClaims, issuer: &SigningKey) -> Result<String> {
+    let payload = serde_json::to_vec(claims)
+        .map_err(|e| AuthError::InvalidPacket(e.to_string()))?;
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(JWS_HEADER),
+        URL_SAFE_NO_PAD.encode(payload)
+    );
+    let signature = issuer.sign(signing_input.as_bytes());
+    Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes())))
+}
+
+/// Split a token and decode its claims without verifying the signature
+pub fn decode_packet(token: &str) -> Result<(KeyPacketClaims, &str, Signature)> {
+    let mut parts = token.rsplitn(2, '.');
+    let signature = parts.next().unwrap_or_default();
+    let signing_input = parts
+        .next()
+        .ok_or_else(|| AuthError::InvalidPacket("malformed token".to_string()))?;
+    let (header, payload) = signing_input
+        .split_once('.')
+        .ok_or_else(|| AuthError::InvalidPacket("malformed token".to_string()))?;
+
+    let header = URL_SAFE_NO_PAD
+        .decode(header)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid header encoding: {}", e)))?;
+    let header: serde_json::Value = serde_json::from_slice(&header)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid header: {}", e)))?;
+    if header["alg"] != "EdDSA" {
+        return Err(AuthError::InvalidPacket("unsupported algorithm".to_string()));
+    }
+
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid claims encoding: {}", e)))?;
+    let claims: KeyPacketClaims = serde_json::from_slice(&payload)
+        .map_err(|e| AuthError::InvalidPacket(format!("invalid claims: {}", e)))?;
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| AuthError::InvalidSignature)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| AuthError::InvalidSignature)?;
+
+    Ok((claims, signing_input, signature))
+}
+
+/// Verify a packet's signature against an issuer key and check expiry
+pub fn verify_packet(token: &str, issuer: &VerifyingKey, now_secs: u64) -> Result<KeyPacketClaims> {
+    let (claims, signing_input, signature) = decode_packet(token)?;
+    issuer
+        .verify(signing_input.as_bytes(), &signature)
+        .map_err(|_| AuthError::InvalidSignature)?;
+    if claims.exp <= now_secs {
+        return Err(AuthError::Expired);
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_signing_key;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let issuer = generate_signing_key();
+        let holder = generate_signing_key();
+        let claims = KeyPacketClaims::new(
+            &holder.verifying_key(),
+            &issuer.verifying_key(),
+            Permissions::read_only(),
+            1_000,
+            3_600,
+        );
+
+        let token = sign_packet(&claims, &issuer).unwrap();
+        let verified = verify_packet(&token, &issuer.verifying_key(), 2_000).unwrap();
+
+        assert_eq!(verified, claims);
+        assert_eq!(verified.public_key().unwrap(), holder.verifying_key());
+    }
+
+    #[test]
+    fn test_packet_rejects_wrong_issuer_and_expiry() {
+        let issuer = generate_signing_key();
+        let other = generate_signing_key();
+        let holder = generate_signing_key();
+        let claims = KeyPacketClaims::new(
+            &holder.verifying_key(),
+            &issuer.verifying_key(),
+            Permissions::read_only(),
+            1_000,
+            60,
+        );
+        let token = sign_packet(&claims, &issuer).unwrap();
+
+        assert_eq!(
+            verify_packet(&token, &other.verifying_key(), 1_000),
+            Err(AuthError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_packet(&token, &issuer.verifying_key(), 1_060),
+            Err(AuthError::Expired)
+        );
+        assert!(verify_packet("garbage", &issuer.verifying_key(), 1_000).is_err());
+    }
+}
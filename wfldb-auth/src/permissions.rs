@@ -0,0 +1,257 @@
+//! Permissions granted by a key packet
+
+use serde::{Deserialize, Serialize};
+use wfldb_core::{BucketId, Priority};
+
+/// Operations a key packet holder may perform
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    #[serde(default)]
+    pub read: bool,
+    #[serde(default)]
+    pub write: bool,
+    #[serde(default)]
+    pub delete: bool,
+    #[serde(default)]
+    pub admin: bool,
+    /// Enumerate keys; not implied by `read`
+    #[serde(default)]
+    pub list: bool,
+    /// Key prefixes listing is limited to; `None` means any prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_prefixes: Option<Vec<String>>,
+    /// Buckets the permissions apply to; `None` means every bucket
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<String>>,
+    /// Tenant whose bucket namespace the holder works in; `None` is the
+    /// shared namespace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Highest request priority the holder may ask for; `None` means any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_priority: Option<Priority>,
+    /// Read transforms the holder may request; `None` means any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<Vec<String>>,
+    /// Requests the holder's principal (or key, if unregistered) may have
+    /// in progress at once; `None` means no cap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<u32>,
+}
+
+impl Permissions {
+    /// Read-only access to every bucket, without listing
+    pub fn read_only() -> Self {
+        Permissions {
+            read: true,
+            ..Default::default()
+        }
+    }
+
+    /// Read, write, delete, and list access to every bucket
+    pub fn read_write() -> Self {
+        Permissions {
+            read: true,
+            write: true,
+            delete: true,
+            list: true,
+            ..Default::default()
+        }
+    }
+
+    /// Full access including administrative operations
+    pub fn admin() -> Self {
+        Permissions {
+            admin: true,
+            ..Self::read_write()
+        }
+    }
+
+    /// Restrict the permissions to the given buckets
+    pub fn with_buckets(mut self, buckets: &[&str]) -> Self {
+        self.buckets = Some(buckets.iter().map(|b| b.to_string()).collect());
+        self
+    }
+
+    /// Confine the holder to a tenant's buckets
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Cap the request priority the holder may ask for
+    pub fn with_max_priority(mut self, priority: Priority) -> Self {
+        self.max_priority = Some(priority);
+        self
+    }
+
+    /// The requested priority, lowered to the cap if it exceeds it
+    pub fn cap_priority(&self, requested: Priority) -> Priority {
+        match self.max_priority {
+            Some(max) => requested.min(max),
+            None => requested,
+        }
+    }
+
+    /// Cap how many requests the holder may have in progress at once
+    pub fn with_max_concurrent_requests(mut self, max: u32) -> Self {
+        self.max_concurrent_requests = Some(max);
+        self
+    }
+
+    /// Limit the read transforms the holder may request
+    pub fn with_transforms(mut self, transforms: &[&str]) -> Self {
+        self.transforms = Some(transforms.iter().map(|t| t.to_string()).collect());
+        self
+    }
+
+    /// Whether the holder may request the named read transform
+    pub fn can_transform(&self, name: &str) -> bool {
+        match &self.transforms {
+            Some(transforms) => transforms.iter().any(|t| t == name),
+            None => true,
+        }
+    }
+
+    /// Allow listing keys
+    pub fn with_list(mut self) -> Self {
+        self.list = true;
+        self
+    }
+
+    /// Allow listing, but only under the given key prefixes
+    pub fn with_list_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.list = true;
+        self.list_prefixes = Some(prefixes.iter().map(|p| p.to_string()).collect());
+        self
+    }
+
+    /// Check whether the permissions cover the bucket
+    pub fn covers_bucket(&self, bucket: &BucketId) -> bool {
+        match &self.buckets {
+            Some(buckets) => buckets.iter().any(|b| b == bucket.as_str()),
+            None => true,
+        }
+    }
+
+    pub fn can_read(&self, bucket: &BucketId) -> bool {
+        self.read && self.covers_bucket(bucket)
+    }
+
+    pub fn can_write(&self, bucket: &BucketId) -> bool {
+        self.write && self.covers_bucket(bucket)
+    }
+
+    pub fn can_delete(&self, bucket: &BucketId) -> bool {
+        self.delete && self.covers_bucket(bucket)
+    }
+
+    /// Whether the holder can do anything at all with the bucket
+    pub fn can_access(&self, bucket: &BucketId) -> bool {
+        (self.read || self.write || self.delete || self.list || self.can_admin()) && self.covers_bucket(bucket)
+    }
+
+    /// Listing `prefix` is allowed if it falls inside one of the granted prefixes
+    pub fn can_list(&self, bucket: &BucketId, prefix: &str) -> bool {
+        if !self.list || !self.covers_bucket(bucket) {
+            return false;
+        }
+        match &self.list_prefixes {
+            Some(prefixes) => prefixes.iter().any(|p| prefix.starts_with(p.as_str())),
+            None => true,
+        }
+    }
+
+    /// Admin rights are server-wide, so tenant-scoped keys never hold them
+    pub fn can_admin(&self) -> bool {
+        self.admin && self.tenant.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_scoping() {
+        let photos = BucketId::new("photos").unwrap();
+        let logs = BucketId::new("logs").unwrap();
+        let perms = Permissions::read_write().with_buckets(&["photos"]);
+
+        assert!(perms.can_read(&photos));
+        assert!(perms.can_write(&photos));
+        assert!(!perms.can_read(&logs));
+        assert!(!perms.can_admin());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let bucket = BucketId::new("photos").unwrap();
+        let perms = Permissions::read_only();
+
+        assert!(perms.can_read(&bucket));
+        assert!(!perms.can_write(&bucket));
+        assert!(!perms.can_delete(&bucket));
+        assert!(!perms.can_list(&bucket, ""));
+    }
+
+    #[test]
+    fn test_list_prefix_scoping() {
+        let bucket = BucketId::new("photos").unwrap();
+        let perms = Permissions::read_only().with_list_prefixes(&["users/alice/"]);
+
+        assert!(perms.can_list(&bucket, "users/alice/"));
+        assert!(perms.can_list(&bucket, "users/alice/2024/"));
+        assert!(!perms.can_list(&bucket, "users/"));
+        assert!(!perms.can_list(&bucket, ""));
+        assert!(Permissions::read_write().can_list(&bucket, ""));
+    }
+
+    #[test]
+    fn test_priority_cap() {
+        let capped = Permissions::read_write().with_max_priority(Priority::Normal);
+
+        assert_eq!(capped.cap_priority(Priority::Interactive), Priority::Normal);
+        assert_eq!(capped.cap_priority(Priority::Bulk), Priority::Bulk);
+        assert_eq!(Permissions::read_write().cap_priority(Priority::Interactive), Priority::Interactive);
+    }
+
+    #[test]
+    fn test_bucket_visibility() {
+        let photos = BucketId::new("photos").unwrap();
+        let logs = BucketId::new("logs").unwrap();
+
+        assert!(Permissions::read_only().with_buckets(&["photos"]).can_access(&photos));
+        assert!(!Permissions::read_only().with_buckets(&["photos"]).can_access(&logs));
+        assert!(!Permissions::default().can_access(&photos));
+    }
+
+    #[test]
+    fn test_concurrency_claim() {
+        let json = serde_json::to_value(Permissions::read_only()).unwrap();
+        assert!(json.get("max_concurrent_requests").is_none());
+
+        let capped = Permissions::read_only().with_max_concurrent_requests(8);
+        let json = serde_json::to_string(&capped).unwrap();
+        let parsed: Permissions = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.max_concurrent_requests, Some(8));
+    }
+
+    #[test]
+    fn test_transform_grants() {
+        let limited = Permissions::read_only().with_transforms(&["redact"]);
+
+        assert!(limited.can_transform("redact"));
+        assert!(!limited.can_transform("gunzip"));
+        assert!(Permissions::read_only().can_transform("gunzip"));
+    }
+
+    #[test]
+    fn test_tenant_keys_are_not_admins() {
+        let perms = Permissions::admin().with_tenant("acme");
+
+        assert!(perms.can_write(&BucketId::new("photos").unwrap()));
+        assert!(!perms.can_admin());
+        assert!(Permissions::admin().can_admin());
+    }
+}
@@ -0,0 +1,245 @@
+//! Named principals (people and service accounts)
+//!
+//! A principal gives a set of public keys a stable, human-readable name and
+//! the permissions packets for it are issued with, so logs and ACLs can refer
+//! to "ci-builder" rather than a raw key ID.
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use crate::store::PRINCIPAL_PREFIX;
+use crate::{decode_public_key, AuthError, AuthorityStore, KeyAuthority, KeyId, Permissions, Result};
+
+/// A named holder of one or more keys
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    pub name: String,
+    /// Public keys (base64url) belonging to the principal
+    pub keys: Vec<String>,
+    /// Permissions granted to packets issued for the principal
+    #[serde(default)]
+    pub permissions: Permissions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl Principal {
+    pub fn new(name: &str, keys: &[VerifyingKey], permissions: Permissions) -> Self {
+        Principal {
+            name: name.to_string(),
+            keys: keys.iter().map(crate::encode_public_key).collect(),
+            permissions,
+            description: None,
+        }
+    }
+
+    /// Key IDs of the principal's keys
+    pub fn key_ids(&self) -> Result<Vec<KeyId>> {
+        self.keys
+            .iter()
+            .map(|k| decode_public_key(k).map(|k| KeyId::from_public_key(&k)))
+            .collect()
+    }
+}
+
+/// Check a principal name: 1-64 characters of `[a-z0-9._-]`
+pub fn validate_principal_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidPrincipal(format!("invalid name '{}'", name)))
+    }
+}
+
+/// Registry of principals, indexed by name and by key
+#[derive(Default)]
+pub struct PrincipalRegistry {
+    by_name: RwLock<BTreeMap<String, Principal>>,
+    by_key: RwLock<HashMap<KeyId, String>>,
+    store: Option<Arc<dyn AuthorityStore>>,
+}
+
+impl PrincipalRegistry {
+    /// Create an empty in-memory registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the registry from a store, writing later changes through to it
+    pub fn open(store: Arc<dyn AuthorityStore>) -> Result<Self> {
+        let registry = PrincipalRegistry::new();
+        for (_, value) in store.scan_prefix(PRINCIPAL_PREFIX)? {
+            let principal: Principal = serde_json::from_slice(&value)
+                .map_err(|e| AuthError::Storage(format!("corrupt principal: {}", e)))?;
+            registry.index(principal)?;
+        }
+        Ok(PrincipalRegistry {
+            store: Some(store),
+            ..registry
+        })
+    }
+
+    /// Create or replace a principal
+    ///
+    /// Fails if one of its keys already belongs to a different principal.
+    pub fn upsert(&self, principal: Principal) -> Result<()> {
+        validate_principal_name(&principal.name)?;
+        let ids = principal.key_ids()?;
+        {
+            let by_key = self.by_key.read().unwrap();
+            for id in &ids {
+                if let Some(owner) = by_key.get(id) {
+                    if *owner != principal.name {
+                        return Err(AuthError::InvalidPrincipal(format!(
+                            "key {} already belongs to '{}'",
+                            id, owner
+                        )));
+                    }
+                }
+            }
+        }
+
+        if let Some(store) = &self.store {
+            let encoded = serde_json::to_vec(&principal)
+                .map_err(|e| AuthError::Storage(e.to_string()))?;
+            store.put(&format!("{}{}", PRINCIPAL_PREFIX, principal.name), &encoded)?;
+        }
+        self.unindex(&principal.name);
+        self.index(principal)
+    }
+
+    /// Remove a principal, returning it if it existed
+    pub fn remove(&self, name: &str) -> Result<Option<Principal>> {
+        if self.get(name).is_none() {
+            return Ok(None);
+        }
+        if let Some(store) = &self.store {
+            store.delete(&format!("{}{}", PRINCIPAL_PREFIX, name))?;
+        }
+        Ok(self.unindex(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Principal> {
+        self.by_name.read().unwrap().get(name).cloned()
+    }
+
+    /// Name of the principal owning a key
+    pub fn name_for(&self, id: &KeyId) -> Option<String> {
+        self.by_key.read().unwrap().get(id).cloned()
+    }
+
+    /// All principals, sorted by name
+    pub fn list(&self) -> Vec<Principal> {
+        self.by_name.read().unwrap().values().cloned().collect()
+    }
+
+    /// Issue a packet for one of a principal's keys with its permissions
+    pub fn issue_packet(
+        &self,
+        issuer: &SigningKey,
+        name: &str,
+        key: &VerifyingKey,
+        now_secs: u64,
+        ttl_secs: u64,
+    ) -> Result<String> {
+        let principal = self
+            .get(name)
+            .ok_or_else(|| AuthError::InvalidPrincipal(format!("unknown principal '{}'", name)))?;
+        if self.name_for(&KeyId::from_public_key(key)).as_deref() != Some(name) {
+            return Err(AuthError::InvalidPrincipal(format!("key does not belong to '{}'", name)));
+        }
+        KeyAuthority::issue(issuer, key, principal.permissions, now_secs, ttl_secs)
+    }
+
+    fn index(&self, principal: Principal) -> Result<()> {
+        let ids = principal.key_ids()?;
+        let mut by_key = self.by_key.write().unwrap();
+        for id in ids {
+            by_key.insert(id, principal.name.clone());
+        }
+        self.by_name.write().unwrap().insert(principal.name.clone(), principal);
+        Ok(())
+    }
+
+    fn unindex(&self, name: &str) -> Option<Principal> {
+        let removed = self.by_name.write().unwrap().remove(name);
+        self.by_key.write().unwrap().retain(|_, owner| owner != name);
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_signing_key, MemoryAuthorityStore};
+
+    #[test]
+    fn test_lookup_by_key_and_name() {
+        let registry = PrincipalRegistry::new();
+        let key = generate_signing_key().verifying_key();
+        registry
+            .upsert(Principal::new("ci-builder", &[key], Permissions::read_write()))
+            .unwrap();
+
+        assert_eq!(registry.name_for(&KeyId::from_public_key(&key)).as_deref(), Some("ci-builder"));
+        assert_eq!(registry.get("ci-builder").unwrap().permissions, Permissions::read_write());
+        assert_eq!(registry.list().len(), 1);
+    }
+
+    #[test]
+    fn test_key_cannot_belong_to_two_principals() {
+        let registry = PrincipalRegistry::new();
+        let key = generate_signing_key().verifying_key();
+        registry.upsert(Principal::new("alice", &[key], Permissions::read_only())).unwrap();
+
+        assert!(registry.upsert(Principal::new("bob", &[key], Permissions::read_only())).is_err());
+        assert!(registry.upsert(Principal::new("Not Valid", &[], Permissions::read_only())).is_err());
+    }
+
+    #[test]
+    fn test_replacing_keys_updates_index() {
+        let registry = PrincipalRegistry::new();
+        let old = generate_signing_key().verifying_key();
+        let new = generate_signing_key().verifying_key();
+        registry.upsert(Principal::new("alice", &[old], Permissions::read_only())).unwrap();
+        registry.upsert(Principal::new("alice", &[new], Permissions::read_only())).unwrap();
+
+        assert_eq!(registry.name_for(&KeyId::from_public_key(&old)), None);
+        assert_eq!(registry.name_for(&KeyId::from_public_key(&new)).as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_registry_persists() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let key = generate_signing_key().verifying_key();
+        let registry = PrincipalRegistry::open(store.clone()).unwrap();
+        registry.upsert(Principal::new("alice", &[key], Permissions::read_only())).unwrap();
+        registry.upsert(Principal::new("bob", &[], Permissions::read_only())).unwrap();
+        registry.remove("bob").unwrap();
+
+        let reopened = PrincipalRegistry::open(store).unwrap();
+        assert_eq!(reopened.list().len(), 1);
+        assert_eq!(reopened.name_for(&KeyId::from_public_key(&key)).as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_issue_packet_uses_principal_permissions() {
+        let root = generate_signing_key();
+        let authority = KeyAuthority::new(root.verifying_key());
+        let registry = PrincipalRegistry::new();
+        let key = generate_signing_key().verifying_key();
+        registry.upsert(Principal::new("reader", &[key], Permissions::read_only())).unwrap();
+
+        let packet = registry.issue_packet(&root, "reader", &key, 0, 60).unwrap();
+        assert_eq!(authority.verify_packet(&packet, 1).unwrap().perms, Permissions::read_only());
+
+        let stranger = generate_signing_key().verifying_key();
+        assert!(registry.issue_packet(&root, "reader", &stranger, 0, 60).is_err());
+    }
+}
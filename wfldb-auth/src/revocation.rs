@@ -0,0 +1,28 @@
+//! Revocation list exchanged between nodes
+//!
+//! Each node serves its revocation list with a version counter. Followers and
+//! gateways poll an upstream node and merge any new entries, so a key revoked
+//! anywhere is blocked everywhere within one poll interval.
+
+use serde::{Deserialize, Serialize};
+use crate::KeyId;
+
+/// Snapshot of a node's revoked keys
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationList {
+    /// Bumped by the serving node on every revocation
+    pub version: u64,
+    /// Revoked key IDs, sorted
+    pub revoked: Vec<KeyId>,
+}
+
+impl RevocationList {
+    pub fn new(version: u64, mut revoked: Vec<KeyId>) -> Self {
+        revoked.sort();
+        RevocationList { version, revoked }
+    }
+
+    pub fn contains(&self, id: &KeyId) -> bool {
+        self.revoked.binary_search(id).is_ok()
+    }
+}
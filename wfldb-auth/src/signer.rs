@@ -0,0 +1,268 @@
+//! Client-side request signing
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+use rand::RngCore;
+use wfldb_core::ContentHash;
+use crate::{canonical_string, headers, ChunkSigner, RequestParts, STREAMING_PAYLOAD};
+
+/// Signs requests with a holder key and attaches its key packet
+#[derive(Clone)]
+pub struct RequestSigner {
+    key: SigningKey,
+    packet: String,
+}
+
+impl RequestSigner {
+    pub fn new(key: SigningKey, packet: String) -> Self {
+        RequestSigner { key, packet }
+    }
+
+    /// Produce the authentication headers for a request; `path` may carry a query string
+    pub fn sign(&self, method: &str, path: &str, body: &[u8], now_ms: u64) -> Vec<(&'static str, String)> {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        self.sign_request(&RequestParts::new(method, path).with_query(query), body, now_ms)
+    }
+
+    /// Produce the authentication headers for a request, signing its query
+    /// string and every header in `request.headers`
+    pub fn sign_request(&self, request: &RequestParts<'_>, body: &[u8], now_ms: u64) -> Vec<(&'static str, String)> {
+        self.sign_with_hash(request, ContentHash::new(body).to_hex(), now_ms)
+    }
+
+    /// Sign a request whose body will be streamed as signed chunks
+    ///
+    /// The returned [`ChunkSigner`] must frame the entire body, ending with
+    /// [`ChunkSigner::finish`].
+    pub fn sign_streaming(&self, request: &RequestParts<'_>, now_ms: u64) -> (Vec<(&'static str, String)>, ChunkSigner) {
+        let auth_headers = self.sign_with_hash(request, STREAMING_PAYLOAD.to_string(), now_ms);
+        let signature = auth_headers
+            .iter()
+            .find(|(name, _)| *name == headers::SIGNATURE)
+            .map(|(_, value)| value.clone())
+            .expect("signed headers include the signature");
+        (auth_headers, ChunkSigner::new(self.key.clone(), signature))
+    }
+
+    fn sign_with_hash(&self, request: &RequestParts<'_>, content_hash: String, now_ms: u64) -> Vec<(&'static str, String)> {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce: String = nonce.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut signed_headers: Vec<String> = Vec::new();
+        for (name, _) in &request.headers {
+            let name = name.to_ascii_lowercase();
+            if name != headers::SIGNATURE && name != headers::SIGNED_HEADERS && !signed_headers.contains(&name) {
+                signed_headers.push(name);
+            }
+        }
+        signed_headers.sort();
+
+        let canonical = canonical_string(request, &signed_headers, now_ms, &nonce, &content_hash)
+            .expect("signed headers are taken from the request");
+        let signature = self.key.sign(canonical.as_bytes());
+
+        let mut auth_headers = vec![
+            (headers::AUTHORIZATION, format!("Bearer {}", self.packet)),
+            (headers::TIMESTAMP, now_ms.to_string()),
+            (headers::NONCE, nonce),
+            (headers::CONTENT_HASH, content_hash),
+            (headers::SIGNATURE, URL_SAFE_NO_PAD.encode(signature.to_bytes())),
+        ];
+        if !signed_headers.is_empty() {
+            auth_headers.push((headers::SIGNED_HEADERS, signed_headers.join(";")));
+        }
+        auth_headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_signing_key, AuthError, Authenticator, BucketAcl, Caveats, KeyAuthority, Permissions, RequestParts};
+    use std::sync::Arc;
+    use wfldb_core::BucketId;
+
+    fn setup(perms: Permissions) -> (Authenticator, RequestSigner) {
+        let root = generate_signing_key();
+        let holder = generate_signing_key();
+        let packet = KeyAuthority::issue(&root, &holder.verifying_key(), perms, 0, 3_600).unwrap();
+        let authenticator = Authenticator::new(Arc::new(KeyAuthority::new(root.verifying_key())));
+        (authenticator, RequestSigner::new(holder, packet))
+    }
+
+    fn parts<'a>(method: &'a str, path: &'a str, headers: &'a [(&'static str, String)]) -> RequestParts<'a> {
+        RequestParts {
+            method,
+            path,
+            query: "",
+            headers: headers.iter().map(|(n, v)| (*n, v.as_str())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_signed_request_authenticates() {
+        let (authenticator, signer) = setup(Permissions::read_only());
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+
+        let ctx = authenticator
+            .authenticate(&parts("GET", "/v1/photos/cat.jpg", &headers), 1_000)
+            .unwrap();
+        let bucket = BucketId::new("photos").unwrap();
+        assert!(ctx.authorize("GET", &bucket).is_ok());
+        assert!(matches!(ctx.authorize("PUT", &bucket), Err(AuthError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn test_tenant_comes_from_packet() {
+        let (authenticator, signer) = setup(Permissions::read_write().with_tenant("acme"));
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+        let ctx = authenticator
+            .authenticate(&parts("GET", "/v1/photos/cat.jpg", &headers), 1_000)
+            .unwrap();
+        assert_eq!(ctx.tenant().map(|t| t.as_str()), Some("acme"));
+
+        // Tenant names end up in partition names, so malformed ones are refused
+        let (authenticator, signer) = setup(Permissions::read_write().with_tenant("acme#photos"));
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+        let result = authenticator.authenticate(&parts("GET", "/v1/photos/cat.jpg", &headers), 1_000);
+        assert!(matches!(result, Err(AuthError::InvalidPacket(_))));
+    }
+
+    #[test]
+    fn test_tampered_request_rejected() {
+        let (authenticator, signer) = setup(Permissions::read_only());
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+
+        let result = authenticator.authenticate(&parts("GET", "/v1/photos/dog.jpg", &headers), 1_000);
+        assert_eq!(result.unwrap_err(), AuthError::InvalidSignature);
+    }
+
+    #[test]
+    fn test_replayed_request_rejected() {
+        let (authenticator, signer) = setup(Permissions::read_only());
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+        let request = parts("GET", "/v1/photos/cat.jpg", &headers);
+
+        assert!(authenticator.authenticate(&request, 1_000).is_ok());
+        assert_eq!(authenticator.authenticate(&request, 1_000).unwrap_err(), AuthError::Replay);
+        assert_eq!(authenticator.cache().hits(), 1);
+    }
+
+    #[test]
+    fn test_query_and_custom_headers_signed() {
+        let (authenticator, signer) = setup(Permissions::read_only());
+        let request = RequestParts::new("GET", "/v1/photos")
+            .with_query("prefix=cat&limit=10")
+            .with_header("x-wfldb-consistency", "strong");
+        let auth_headers = signer.sign_request(&request, b"", 1_000);
+
+        let mut received = RequestParts::new("GET", "/v1/photos")
+            .with_query("limit=10&prefix=cat")
+            .with_header("x-wfldb-consistency", "strong");
+        for (name, value) in &auth_headers {
+            received = received.with_header(name, value);
+        }
+        assert!(authenticator.authenticate(&received, 1_000).is_ok());
+
+        // Altering a signed query parameter breaks the signature
+        let mut tampered = RequestParts::new("GET", "/v1/photos")
+            .with_query("limit=1000&prefix=cat")
+            .with_header("x-wfldb-consistency", "strong");
+        for (name, value) in &auth_headers {
+            tampered = tampered.with_header(name, value);
+        }
+        assert_eq!(authenticator.authenticate(&tampered, 1_000).unwrap_err(), AuthError::InvalidSignature);
+
+        // Dropping a signed header is rejected before verification
+        let mut stripped = RequestParts::new("GET", "/v1/photos").with_query("limit=10&prefix=cat");
+        for (name, value) in &auth_headers {
+            stripped = stripped.with_header(name, value);
+        }
+        assert!(matches!(
+            authenticator.authenticate(&stripped, 1_000),
+            Err(AuthError::MalformedHeader(headers::SIGNED_HEADERS, _))
+        ));
+    }
+
+    #[test]
+    fn test_caveats_narrow_the_request() {
+        let (authenticator, signer) = setup(Permissions::admin());
+        let caveats = Caveats::new().with_buckets(&["photos"]).with_key_prefix("cats/").encode();
+        let request = RequestParts::new("PUT", "/v1/photos/cats/tom.jpg").with_header(headers::CAVEATS, &caveats);
+        let auth_headers = signer.sign_request(&request, b"", 1_000);
+        let mut received = request.clone();
+        for (name, value) in &auth_headers {
+            received = received.with_header(name, value);
+        }
+        let ctx = authenticator.authenticate(&received, 1_000).unwrap();
+        assert!(ctx.authorize("PUT", &BucketId::new("photos").unwrap()).is_ok());
+        assert!(ctx.authorize("PUT", &BucketId::new("logs").unwrap()).is_err());
+        assert!(!ctx.permissions().can_admin());
+        assert!(ctx.authorize_key(Some("cats/tom.jpg")).is_ok());
+        assert!(matches!(ctx.authorize_key(Some("dogs/rex.jpg")), Err(AuthError::PermissionDenied(_))));
+
+        // Caveats outside the signature could be stripped, so they are refused
+        let auth_headers = signer.sign_request(&RequestParts::new("PUT", "/v1/photos/cats/tom.jpg"), b"", 2_000);
+        let mut unsigned = RequestParts::new("PUT", "/v1/photos/cats/tom.jpg");
+        for (name, value) in &auth_headers {
+            unsigned = unsigned.with_header(name, value);
+        }
+        let unsigned = unsigned.with_header(headers::CAVEATS, &caveats);
+        assert!(matches!(
+            authenticator.authenticate(&unsigned, 2_000),
+            Err(AuthError::MalformedHeader(headers::CAVEATS, _))
+        ));
+    }
+
+    #[test]
+    fn test_sign_splits_query_from_path() {
+        let (authenticator, signer) = setup(Permissions::read_only());
+        let auth_headers = signer.sign("GET", "/v1/photos?prefix=cat", b"", 1_000);
+        let mut request = parts("GET", "/v1/photos", &auth_headers);
+        request.query = "prefix=cat";
+        assert!(authenticator.authenticate(&request, 1_000).is_ok());
+    }
+
+    #[test]
+    fn test_streaming_request_verifies_chunks() {
+        let (authenticator, signer) = setup(Permissions::read_write());
+        let (auth_headers, mut chunks) = signer.sign_streaming(&RequestParts::new("PUT", "/v1/photos/big.bin"), 1_000);
+        let mut body = chunks.sign_chunk(b"first");
+        body.extend(chunks.sign_chunk(b"second"));
+        body.extend(chunks.finish());
+
+        let ctx = authenticator
+            .authenticate(&parts("PUT", "/v1/photos/big.bin", &auth_headers), 1_000)
+            .unwrap();
+        assert!(ctx.is_streaming());
+        let mut verifier = ctx.chunk_verifier().unwrap();
+        assert_eq!(verifier.push(&body).unwrap(), b"firstsecond");
+        verifier.finish().unwrap();
+    }
+
+    #[test]
+    fn test_owner_only_acl() {
+        let (authenticator, signer) = setup(Permissions::read_write());
+        let headers = signer.sign("GET", "/v1/photos/cat.jpg", b"", 1_000);
+        let ctx = authenticator
+            .authenticate(&parts("GET", "/v1/photos/cat.jpg", &headers), 1_000)
+            .unwrap();
+        let me = ctx.key_id().to_string();
+
+        assert!(ctx.authorize_owner(BucketAcl::OwnerOnly, Some(&me)).is_ok());
+        assert!(ctx.authorize_owner(BucketAcl::OwnerOnly, None).is_ok());
+        assert!(matches!(
+            ctx.authorize_owner(BucketAcl::OwnerOnly, Some("someone-else")),
+            Err(AuthError::PermissionDenied(_))
+        ));
+        assert!(ctx.authorize_owner(BucketAcl::BucketWriters, Some("someone-else")).is_ok());
+    }
+
+    #[test]
+    fn test_missing_headers_rejected() {
+        let (authenticator, _) = setup(Permissions::read_only());
+        let result = authenticator.authenticate(&RequestParts::new("GET", "/v1/photos/cat.jpg"), 1_000);
+        assert_eq!(result.unwrap_err(), AuthError::MissingHeader(headers::AUTHORIZATION));
+    }
+}
@@ -0,0 +1,70 @@
+//! Durable storage for the key authority
+//!
+//! The authority's state is kept as plain key-value entries so any ordered
+//! store can back it. The server uses the engine's system partition.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use crate::Result;
+
+pub(crate) const ROOT_KEY: &str = "auth/root";
+pub(crate) const ISSUER_PREFIX: &str = "auth/issuer/";
+pub(crate) const REVOKED_PREFIX: &str = "auth/revoked/";
+pub(crate) const REVOCATION_VERSION_KEY: &str = "auth/revocation_version";
+pub(crate) const ROOT_POLICY_KEY: &str = "auth/root_policy";
+pub(crate) const PRINCIPAL_PREFIX: &str = "auth/principal/";
+pub(crate) const BUCKET_ACL_PREFIX: &str = "auth/bucket_acl/";
+pub(crate) const IDENTITY_KEY: &str = "auth/identity";
+
+/// Key-value backend for a persistent [`KeyAuthority`](crate::KeyAuthority)
+pub trait AuthorityStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Durably store a value; must not return before the write is persisted
+    fn put(&self, key: &str, value: &[u8]) -> Result<()>;
+
+    /// Durably remove a value
+    fn delete(&self, key: &str) -> Result<()>;
+
+    /// All entries whose key starts with `prefix`
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>>;
+}
+
+/// In-memory store, for tests and ephemeral deployments
+#[derive(Debug, Default)]
+pub struct MemoryAuthorityStore {
+    entries: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryAuthorityStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AuthorityStore for MemoryAuthorityStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.entries.lock().unwrap().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+}
@@ -0,0 +1,223 @@
+//! Chunk-signed request bodies for streaming uploads
+//!
+//! A streaming request declares [`STREAMING_PAYLOAD`] as its content hash, so
+//! the request signature covers everything but the body. The body is then
+//! framed as `{len:x};chunk-signature={sig}\r\n{data}\r\n` where each chunk
+//! signature covers the previous signature and the chunk's BLAKE3 hash,
+//! chaining back to the request signature. A zero-length chunk ends the body,
+//! so truncation, reordering, and splicing are all detected.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use crate::{AuthError, Result};
+
+/// Content hash value announcing a chunk-signed body
+pub const STREAMING_PAYLOAD: &str = "STREAMING-ED25519-CHUNKED";
+
+/// Largest chunk a verifier will buffer before checking its signature
+pub const MAX_SIGNED_CHUNK: usize = 16 * 1024 * 1024;
+
+/// Longest chunk header line accepted (hex length plus signature)
+const MAX_CHUNK_HEADER: usize = 256;
+
+fn chunk_message(previous_signature: &str, data: &[u8]) -> String {
+    format!("wfldb-chunk-v1\n{}\n{}", previous_signature, blake3::hash(data).to_hex())
+}
+
+/// Frames and signs body chunks on the client
+pub struct ChunkSigner {
+    key: SigningKey,
+    previous: String,
+}
+
+impl ChunkSigner {
+    /// Start a chain from the request signature
+    pub fn new(key: SigningKey, request_signature: String) -> Self {
+        ChunkSigner {
+            key,
+            previous: request_signature,
+        }
+    }
+
+    /// Frame and sign one chunk of the body
+    pub fn sign_chunk(&mut self, data: &[u8]) -> Vec<u8> {
+        let signature = self.key.sign(chunk_message(&self.previous, data).as_bytes());
+        self.previous = URL_SAFE_NO_PAD.encode(signature.to_bytes());
+
+        let mut frame = format!("{:x};chunk-signature={}\r\n", data.len(), self.previous).into_bytes();
+        frame.extend_from_slice(data);
+        frame.extend_from_slice(b"\r\n");
+        frame
+    }
+
+    /// Signed zero-length chunk that terminates the body
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.sign_chunk(&[])
+    }
+}
+
+/// Incrementally decodes and verifies a chunk-signed body on the server
+pub struct ChunkVerifier {
+    key: VerifyingKey,
+    previous: String,
+    buffer: Vec<u8>,
+    max_chunk: usize,
+    finished: bool,
+}
+
+impl ChunkVerifier {
+    pub fn new(key: VerifyingKey, request_signature: String) -> Self {
+        ChunkVerifier {
+            key,
+            previous: request_signature,
+            buffer: Vec::new(),
+            max_chunk: MAX_SIGNED_CHUNK,
+            finished: false,
+        }
+    }
+
+    pub fn with_max_chunk(mut self, max_chunk: usize) -> Self {
+        self.max_chunk = max_chunk;
+        self
+    }
+
+    /// Feed received bytes; returns payload from every chunk completed and verified so far
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut payload = Vec::new();
+        while let Some(chunk) = self.next_chunk()? {
+            payload.extend_from_slice(&chunk);
+        }
+        Ok(payload)
+    }
+
+    /// Check that the terminating chunk arrived and nothing followed it
+    pub fn finish(self) -> Result<()> {
+        if !self.finished {
+            return Err(AuthError::MalformedChunk("body ended before the final chunk".to_string()));
+        }
+        if !self.buffer.is_empty() {
+            return Err(AuthError::MalformedChunk("data after the final chunk".to_string()));
+        }
+        Ok(())
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.finished {
+            if self.buffer.is_empty() {
+                return Ok(None);
+            }
+            return Err(AuthError::MalformedChunk("data after the final chunk".to_string()));
+        }
+
+        let header_end = match self.buffer.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None if self.buffer.len() > MAX_CHUNK_HEADER => {
+                return Err(AuthError::MalformedChunk("chunk header too long".to_string()));
+            }
+            None => return Ok(None),
+        };
+        let header = std::str::from_utf8(&self.buffer[..header_end])
+            .map_err(|_| AuthError::MalformedChunk("chunk header is not UTF-8".to_string()))?;
+        let (len, signature) = header
+            .split_once(";chunk-signature=")
+            .ok_or_else(|| AuthError::MalformedChunk(format!("bad chunk header '{}'", header)))?;
+        let len = usize::from_str_radix(len, 16)
+            .map_err(|_| AuthError::MalformedChunk(format!("bad chunk length '{}'", len)))?;
+        if len > self.max_chunk {
+            return Err(AuthError::MalformedChunk(format!("chunk of {} bytes exceeds {}", len, self.max_chunk)));
+        }
+
+        let data_start = header_end + 2;
+        let frame_end = data_start + len + 2;
+        if self.buffer.len() < frame_end {
+            return Ok(None);
+        }
+        if &self.buffer[data_start + len..frame_end] != b"\r\n" {
+            return Err(AuthError::MalformedChunk("chunk not terminated by CRLF".to_string()));
+        }
+
+        let signature = signature.to_string();
+        let data = self.buffer[data_start..data_start + len].to_vec();
+        let decoded = URL_SAFE_NO_PAD
+            .decode(&signature)
+            .map_err(|_| AuthError::InvalidSignature)?;
+        let decoded = Signature::from_slice(&decoded).map_err(|_| AuthError::InvalidSignature)?;
+        self.key
+            .verify(chunk_message(&self.previous, &data).as_bytes(), &decoded)
+            .map_err(|_| AuthError::InvalidSignature)?;
+
+        self.previous = signature;
+        self.buffer.drain(..frame_end);
+        if len == 0 {
+            self.finished = true;
+        }
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_signing_key;
+
+    fn signed_body(key: &SigningKey, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut signer = ChunkSigner::new(key.clone(), "seed".to_string());
+        let mut body = Vec::new();
+        for chunk in chunks {
+            body.extend(signer.sign_chunk(chunk));
+        }
+        body.extend(signer.finish());
+        body
+    }
+
+    #[test]
+    fn test_round_trip_across_arbitrary_splits() {
+        let key = generate_signing_key();
+        let body = signed_body(&key, &[b"hello ", b"streaming ", b"world"]);
+
+        for split in [1, 7, 64, body.len()] {
+            let mut verifier = ChunkVerifier::new(key.verifying_key(), "seed".to_string());
+            let mut payload = Vec::new();
+            for piece in body.chunks(split) {
+                payload.extend(verifier.push(piece).unwrap());
+            }
+            verifier.finish().unwrap();
+            assert_eq!(payload, b"hello streaming world");
+        }
+    }
+
+    #[test]
+    fn test_truncated_body_rejected() {
+        let key = generate_signing_key();
+        let mut signer = ChunkSigner::new(key.clone(), "seed".to_string());
+        let body = signer.sign_chunk(b"partial");
+
+        let mut verifier = ChunkVerifier::new(key.verifying_key(), "seed".to_string());
+        verifier.push(&body).unwrap();
+        assert!(matches!(verifier.finish(), Err(AuthError::MalformedChunk(_))));
+    }
+
+    #[test]
+    fn test_tampered_or_reordered_chunks_rejected() {
+        let key = generate_signing_key();
+        let mut body = signed_body(&key, &[b"aaaa", b"bbbb"]);
+        let pos = body.windows(4).position(|w| w == b"bbbb").unwrap();
+        body[pos] = b'c';
+        let mut verifier = ChunkVerifier::new(key.verifying_key(), "seed".to_string());
+        assert_eq!(verifier.push(&body).unwrap_err(), AuthError::InvalidSignature);
+
+        // A chain seeded by a different request signature does not verify
+        let body = signed_body(&key, &[b"aaaa"]);
+        let mut verifier = ChunkVerifier::new(key.verifying_key(), "other".to_string());
+        assert_eq!(verifier.push(&body).unwrap_err(), AuthError::InvalidSignature);
+    }
+
+    #[test]
+    fn test_oversized_chunk_rejected() {
+        let key = generate_signing_key();
+        let body = signed_body(&key, &[&[0u8; 64]]);
+        let mut verifier = ChunkVerifier::new(key.verifying_key(), "seed".to_string()).with_max_chunk(16);
+        assert!(matches!(verifier.push(&body), Err(AuthError::MalformedChunk(_))));
+    }
+}
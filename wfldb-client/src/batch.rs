@@ -0,0 +1,179 @@
+//! Batch GETs
+//!
+//! [`Client::get_many`] reads many keys of a bucket in one request to
+//! `POST /v1/{bucket}/_mget`, whose answer is a multiplexed body with one
+//! stream per key (see [`wfldb_net::multiplex`]). Frames are decoded as
+//! they arrive, so the response is never held twice. Each key's outcome
+//! comes back in the position the key was asked for, failing on its own as
+//! a GET of it alone would; the call as a whole fails only when the
+//! request or the body does. Longer lists are sent as several batches.
+//!
+//! ```ignore
+//! let keys: Vec<Key> = ids.iter().map(|id| Key::new(&format!("users/{}", id))).collect::<Result<_, _>>()?;
+//! for (key, found) in keys.iter().zip(client.get_many(&bucket, &keys).await?) {
+//!     if let Some(data) = found? {
+//!         println!("{}: {} bytes", key.as_str(), data.len());
+//!     }
+//! }
+//! ```
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Method, StatusCode};
+use serde_json::json;
+use wfldb_core::*;
+use wfldb_net::{EntryHeader, EntryStatus, MuxDecoder, MuxFrame, MuxFrameKind, MAX_MGET_KEYS, MUX_CONTENT_TYPE};
+use crate::client::{status_error, RawResponse};
+use crate::{Client, ClientError, Result};
+
+impl Client {
+    /// Retrieve many objects of a bucket in as few requests as fit them;
+    /// each key's outcome is at its position in `keys`
+    pub async fn get_many(&self, bucket: &BucketId, keys: &[Key]) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        let path = format!("/v1/{}/_mget", bucket.as_str());
+        let mut found = Vec::with_capacity(keys.len());
+        for batch in keys.chunks(MAX_MGET_KEYS) {
+            let body = json!({"keys": batch.iter().map(Key::as_str).collect::<Vec<_>>()});
+            let response = match self.open(Method::POST, &path, Bytes::from(body.to_string()), &[]).await? {
+                Ok(response) => response,
+                Err(response) => return Err(status_error(&response)),
+            };
+            let is_mux = response.headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v == MUX_CONTENT_TYPE);
+            if !is_mux {
+                return Err(ClientError::InvalidResponse("batch GET answered without a multiplexed body".to_string()));
+            }
+            let mut entries = Entries::new(batch.len(), response.headers().clone());
+            let mut decoder = MuxDecoder::new();
+            let mut body = response.into_body();
+            while let Some(frame) = body.frame().await {
+                let frame = frame.map_err(|e| ClientError::Stream(e.to_string()))?;
+                if let Some(data) = frame.data_ref() {
+                    decoder.push(data);
+                    while let Some(frame) = decoder.next_frame()? {
+                        entries.apply(frame)?;
+                    }
+                }
+            }
+            decoder.finish()?;
+            found.extend(entries.finish()?);
+        }
+        Ok(found)
+    }
+}
+
+/// Where one key of a batch stands while its stream is read
+enum Entry {
+    Waiting,
+    Receiving { size: Option<u64>, data: Vec<u8> },
+    Done(Result<Option<Vec<u8>>>),
+}
+
+/// The outcomes of a batch, assembled from its frames
+struct Entries {
+    entries: Vec<Entry>,
+    /// Headers of the response, naming its request in errors
+    headers: HeaderMap,
+}
+
+impl Entries {
+    fn new(len: usize, headers: HeaderMap) -> Self {
+        Entries { entries: (0..len).map(|_| Entry::Waiting).collect(), headers }
+    }
+
+    fn apply(&mut self, frame: MuxFrame) -> Result<()> {
+        let invalid = |what: &str| ClientError::InvalidResponse(format!("{} for stream {} of a batch GET", what, frame.stream));
+        let error = |header: &EntryHeader| entry_error(header, &self.headers);
+        let entry = self.entries.get_mut(frame.stream as usize).ok_or_else(|| invalid("frame"))?;
+        *entry = match (std::mem::replace(entry, Entry::Waiting), frame.kind) {
+            (Entry::Waiting, MuxFrameKind::Header) => {
+                let header = frame.entry_header()?;
+                match header.status {
+                    EntryStatus::Ok => Entry::Receiving {
+                        size: header.size,
+                        data: Vec::with_capacity(header.size.unwrap_or_default().min(1 << 20) as usize),
+                    },
+                    EntryStatus::NotFound => Entry::Done(Ok(None)),
+                    EntryStatus::Error => Entry::Done(Err(error(&header))),
+                }
+            }
+            (Entry::Receiving { size, mut data }, MuxFrameKind::Data) => {
+                data.extend_from_slice(&frame.payload);
+                Entry::Receiving { size, data }
+            }
+            (Entry::Receiving { size, data }, MuxFrameKind::End) => {
+                if size.is_some_and(|size| size != data.len() as u64) {
+                    return Err(invalid("object of the wrong size"));
+                }
+                Entry::Done(Ok(Some(data)))
+            }
+            (Entry::Receiving { .. }, MuxFrameKind::Abort) => Entry::Done(Err(error(&frame.entry_header()?))),
+            (done @ Entry::Done(_), MuxFrameKind::End) => done,
+            (_, kind) => return Err(invalid(&format!("unexpected {:?} frame", kind))),
+        };
+        Ok(())
+    }
+
+    /// Each key's outcome, once every stream has closed
+    fn finish(self) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        self.entries
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::Done(found) => Ok(found),
+                _ => Err(ClientError::Stream("batch GET ended before all of its objects were sent".to_string())),
+            })
+            .collect()
+    }
+}
+
+/// The error a GET of the key alone would have failed with
+fn entry_error(header: &EntryHeader, headers: &HeaderMap) -> ClientError {
+    let status = header.http_status.and_then(|status| StatusCode::from_u16(status).ok());
+    let message = format!("key '{}': {}", header.key, header.error.as_deref().unwrap_or("failed"));
+    let body = json!({"error": message, "code": header.code, "retryable": header.retryable});
+    status_error(&RawResponse {
+        status: status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        headers: headers.clone(),
+        body: Bytes::from(body.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_assemble_interleaved_streams() {
+        let mut entries = Entries::new(3, HeaderMap::new());
+        let frames = [
+            MuxFrame::header(0, &EntryHeader::found("a", "v".to_string(), 1, 11)),
+            MuxFrame::header(1, &EntryHeader::not_found("b")),
+            MuxFrame::header(2, &EntryHeader::error("c", 403, "permission_denied", "denied".to_string(), false)),
+            MuxFrame::data(0, b"hello ".to_vec()),
+            MuxFrame::end(1),
+            MuxFrame::data(0, b"world".to_vec()),
+            MuxFrame::end(2),
+            MuxFrame::end(0),
+        ];
+        for frame in frames {
+            entries.apply(frame).unwrap();
+        }
+        let found = entries.finish().unwrap();
+        assert_eq!(found[0].as_ref().unwrap().as_deref(), Some(&b"hello world"[..]));
+        assert!(found[1].as_ref().unwrap().is_none());
+        assert!(matches!(found[2], Err(ClientError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn test_entries_reject_truncated_batch() {
+        let mut entries = Entries::new(2, HeaderMap::new());
+        entries.apply(MuxFrame::header(0, &EntryHeader::found("a", "v".to_string(), 1, 3))).unwrap();
+        assert!(entries.apply(MuxFrame::data(1, b"abc".to_vec())).is_err());
+        entries.apply(MuxFrame::data(0, b"abc".to_vec())).unwrap();
+        entries.apply(MuxFrame::end(0)).unwrap();
+        assert!(entries.finish().is_err());
+    }
+}
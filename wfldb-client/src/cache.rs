@@ -0,0 +1,248 @@
+//! Local disk cache of fetched objects
+//!
+//! A client built with [`Client::with_disk_cache`] keeps every object a
+//! GET returns on disk, under the ETag it came with. The next GET of the
+//! key sends that ETag as `If-None-Match`, and a 304 is answered from disk
+//! without the body crossing the network, which is most of a build or CI
+//! job's downloads. Data is stored by BLAKE3 hash, so objects with the same
+//! content under many keys or versions are kept once, and is checked
+//! against its hash on every read; a damaged file is dropped and the object
+//! fetched whole. Cache failures never fail a read, they only make it a
+//! plain GET.
+//!
+//! ```text
+//! {dir}/objects/{hash}          object data
+//! {dir}/index/{hash of key}     {"bucket", "key", "etag", "hash"} of the key's cached version
+//! ```
+//!
+//! With a size limit, the least recently read objects are removed once the
+//! data outgrows it; an index entry whose data is gone is a miss.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use hyper::header::{ETAG, IF_NONE_MATCH};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use wfldb_core::*;
+use crate::client::{object_path, status_error, RawResponse};
+use crate::{Client, ReadConsistency, Result};
+
+/// Objects cached on local disk, shared by any clients given the same directory
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    /// Most object data kept, in bytes
+    max_bytes: Option<u64>,
+}
+
+/// Cached version of a key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    bucket: String,
+    key: String,
+    etag: String,
+    hash: String,
+}
+
+impl DiskCache {
+    /// Cache in `dir`, creating it if needed
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(dir.join("objects"))?;
+        std::fs::create_dir_all(dir.join("index"))?;
+        Ok(DiskCache { dir, max_bytes: None })
+    }
+
+    /// Remove the least recently read objects once their data outgrows `max_bytes`
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn index_path(&self, bucket: &BucketId, key: &Key) -> PathBuf {
+        let name = ContentHash::new(format!("{}/{}", bucket.as_str(), key.as_str()).as_bytes());
+        self.dir.join("index").join(name.to_hex())
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("objects").join(hash)
+    }
+
+    async fn lookup(&self, bucket: &BucketId, key: &Key) -> Option<IndexEntry> {
+        let entry = tokio::fs::read(self.index_path(bucket, key)).await.ok()?;
+        let entry: IndexEntry = serde_json::from_slice(&entry).ok()?;
+        // Keys whose names hash alike can't be told apart by file name
+        (entry.bucket == bucket.as_str() && entry.key == key.as_str()).then_some(entry)
+    }
+
+    /// Cached data of `entry`, if it is still there and undamaged
+    async fn read(&self, entry: &IndexEntry) -> Option<Vec<u8>> {
+        let path = self.object_path(&entry.hash);
+        let data = tokio::fs::read(&path).await.ok()?;
+        if ContentHash::new(&data).to_hex() != entry.hash {
+            tracing::warn!(path = %path.display(), "dropping damaged cache entry");
+            let _ = tokio::fs::remove_file(&path).await;
+            return None;
+        }
+        // Reads keep an object from eviction
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(data)
+    }
+
+    async fn store(&self, bucket: &BucketId, key: &Key, etag: &str, data: &[u8]) -> Result<()> {
+        let hash = ContentHash::new(data).to_hex();
+        let path = self.object_path(&hash);
+        if tokio::fs::metadata(&path).await.is_err() {
+            write_atomic(&path, data).await?;
+        }
+        let entry = IndexEntry {
+            bucket: bucket.as_str().to_string(),
+            key: key.as_str().to_string(),
+            etag: etag.to_string(),
+            hash,
+        };
+        write_atomic(&self.index_path(bucket, key), &serde_json::to_vec(&entry)?).await?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(max_bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn forget(&self, bucket: &BucketId, key: &Key) {
+        let _ = tokio::fs::remove_file(self.index_path(bucket, key)).await;
+    }
+
+    /// Remove the least recently read objects until the rest fit `max_bytes`
+    async fn evict(&self, max_bytes: u64) -> Result<()> {
+        let mut objects = Vec::new();
+        let mut total = 0;
+        let mut entries = tokio::fs::read_dir(self.dir.join("objects")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                total += metadata.len();
+                objects.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        objects.sort();
+        for (_, len, path) in objects {
+            if total <= max_bytes {
+                break;
+            }
+            tokio::fs::remove_file(&path).await?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Write a file whole or not at all, so a crash never leaves part of one
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let temp = path.with_extension(format!("tmp-{}", ulid::Ulid::new()));
+    tokio::fs::write(&temp, data).await?;
+    if let Err(e) = tokio::fs::rename(&temp, path).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+impl Client {
+    /// Keep fetched objects in `cache` and revalidate them instead of
+    /// downloading them again
+    pub fn with_disk_cache(mut self, cache: DiskCache) -> Self {
+        self.disk_cache = Some(cache);
+        self
+    }
+
+    /// [`get_with`](Self::get_with) through the disk cache
+    pub(crate) async fn get_cached(
+        &self,
+        cache: &DiskCache,
+        bucket: &BucketId,
+        key: &Key,
+        consistency: ReadConsistency,
+    ) -> Result<Option<Vec<u8>>> {
+        let path = object_path(bucket, key);
+        let cached = cache.lookup(bucket, key).await;
+        let extra: Vec<(&str, String)> = cached.iter().map(|entry| (IF_NONE_MATCH.as_str(), entry.etag.clone())).collect();
+        let mut response = self.send_read(&path, consistency, &extra).await?;
+        if response.status == StatusCode::NOT_MODIFIED {
+            if let Some(data) = match &cached {
+                Some(entry) => cache.read(entry).await,
+                None => None,
+            } {
+                return Ok(Some(data));
+            }
+            // Evicted or damaged since the lookup
+            response = self.send_read(&path, consistency, &[]).await?;
+        }
+        match response.status {
+            StatusCode::NOT_FOUND => {
+                cache.forget(bucket, key).await;
+                Ok(None)
+            }
+            status if status.is_success() => {
+                self.fill(cache, bucket, key, &response).await;
+                Ok(Some(response.body.to_vec()))
+            }
+            _ => Err(status_error(&response)),
+        }
+    }
+
+    async fn fill(&self, cache: &DiskCache, bucket: &BucketId, key: &Key, response: &RawResponse) {
+        let Some(etag) = response.headers.get(ETAG).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+        if let Err(e) = cache.store(bucket, key, etag, &response.body).await {
+            tracing::warn!(error = %e, "failed to cache object");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path()).unwrap();
+        let bucket = BucketId::new("builds").unwrap();
+        let (a, b) = (Key::new("a.tar").unwrap(), Key::new("b.tar").unwrap());
+
+        cache.store(&bucket, &a, "\"v1\"", b"artifact").await.unwrap();
+        cache.store(&bucket, &b, "\"v2\"", b"artifact").await.unwrap();
+        let entry = cache.lookup(&bucket, &a).await.unwrap();
+        assert_eq!(entry.etag, "\"v1\"");
+        assert_eq!(cache.read(&entry).await.unwrap(), b"artifact");
+        // Same content, stored once
+        assert_eq!(std::fs::read_dir(dir.path().join("objects")).unwrap().count(), 1);
+
+        // Damaged data is dropped, not returned
+        std::fs::write(cache.object_path(&entry.hash), b"artefact").unwrap();
+        assert!(cache.read(&entry).await.is_none());
+
+        cache.forget(&bucket, &a).await;
+        assert!(cache.lookup(&bucket, &a).await.is_none());
+        assert!(cache.lookup(&bucket, &b).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_evicts_to_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::open(dir.path()).unwrap().with_max_bytes(10);
+        let bucket = BucketId::new("builds").unwrap();
+        for (i, data) in [b"123456", b"abcdef"].iter().enumerate() {
+            let key = Key::new(&format!("{}.bin", i)).unwrap();
+            cache.store(&bucket, &key, "\"v\"", *data).await.unwrap();
+        }
+        let remaining: u64 = std::fs::read_dir(dir.path().join("objects"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum();
+        assert!(remaining <= 10);
+    }
+}
@@ -1,14 +1,84 @@
 //! Main client implementation
 
-use std::sync::Arc;
-use hyper::Uri;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::HeaderMap;
+use hyper::body::Incoming;
+use hyper::{Method, Request, StatusCode, Uri};
+use hyper_util::client::legacy::{connect::HttpConnector, Client as HttpClient};
+use hyper_util::rt::TokioExecutor;
+use wfldb_auth::{headers, now_ms, Caveats, RequestParts, RequestSigner};
 use wfldb_core::*;
-use crate::{Result, ClientError, MultipartUpload};
+use wfldb_net::encode_query_component;
+use tracing::{Instrument, Span};
+use crate::cache::DiskCache;
+use crate::credentials::{CredentialCache, CredentialsProvider};
+use crate::metrics::{MetricsRecorder, RequestMetrics};
+use crate::{Result, ClientError, ReadConsistency, Transaction};
 
 /// wflDB client
 pub struct Client {
     base_url: String,
-    // Future: connection pool, auth, etc.
+    http: HttpClient<HttpConnector, Full<Bytes>>,
+    signer: Option<RequestSigner>,
+    /// Refreshed key packets, used instead of `signer`
+    credentials: Option<CredentialCache>,
+    priority: Option<Priority>,
+    /// Encoded caveats signed into every request
+    caveats: Option<String>,
+    /// Server clock minus local clock, learned from skew rejections
+    clock_offset_ms: AtomicI64,
+    /// Base URLs of read replicas
+    pub(crate) replicas: Vec<String>,
+    pub(crate) next_replica: AtomicUsize,
+    pub(crate) read_consistency: ReadConsistency,
+    /// Newest position and clock reading any response reported
+    session: Mutex<Option<SessionToken>>,
+    recorder: Option<Arc<dyn MetricsRecorder>>,
+    pub(crate) disk_cache: Option<DiskCache>,
+}
+
+/// One page of a bucket listing
+#[derive(Debug, Clone)]
+pub struct ListPage {
+    pub keys: Vec<Key>,
+    /// Pass as `start_after` to fetch the next page; `None` on the last page
+    pub next_start_after: Option<String>,
+}
+
+/// A bucket visible to the client's key
+#[derive(Debug, Clone)]
+pub struct BucketInfo {
+    pub id: BucketId,
+    /// `None` for buckets the server has no creation record of
+    pub created_at_ms: Option<u64>,
+    /// Stored entries, estimated; more than the object count
+    pub approximate_entries: u64,
+    pub disk_bytes: u64,
+}
+
+/// Change records read from a server's journal
+#[derive(Debug, Clone)]
+pub struct JournalPage {
+    pub records: Vec<ChangeRecord>,
+    /// Sequence number to pass as `after` on the next read
+    pub next: u64,
+}
+
+/// Response header carrying the journal cursor
+const JOURNAL_NEXT_HEADER: &str = "x-wfldb-journal-next";
+
+/// Asks the server to keep checksums beside BLAKE3 on a PUT
+const CHECKSUM_ALGORITHM_HEADER: &str = "x-wfldb-checksum-algorithm";
+
+/// A fully buffered response
+pub(crate) struct RawResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Bytes,
 }
 
 impl Client {
@@ -18,37 +88,956 @@ impl Client {
         // Validate URL
         let _uri: Uri = base_url.parse()
             .map_err(|e| ClientError::Connection(format!("Invalid URL: {}", e)))?;
-        
-        Ok(Client { base_url })
+
+        Ok(Client {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: HttpClient::builder(TokioExecutor::new()).build_http(),
+            signer: None,
+            credentials: None,
+            priority: None,
+            caveats: None,
+            clock_offset_ms: AtomicI64::new(0),
+            replicas: Vec::new(),
+            next_replica: AtomicUsize::new(0),
+            read_consistency: ReadConsistency::default(),
+            session: Mutex::new(None),
+            recorder: None,
+            disk_cache: None,
+        })
+    }
+
+    /// Sign every request with the given holder key and key packet
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Sign every request with credentials from `provider`, fetching new
+    /// ones once the packet is within a minute of expiring
+    pub fn with_credentials_provider(self, provider: Arc<dyn CredentialsProvider>) -> Self {
+        self.with_credentials_refresh(provider, Duration::from_secs(60))
+    }
+
+    /// [`with_credentials_provider`](Self::with_credentials_provider),
+    /// fetching new credentials `refresh_before` the packet expires
+    pub fn with_credentials_refresh(mut self, provider: Arc<dyn CredentialsProvider>, refresh_before: Duration) -> Self {
+        self.credentials = Some(CredentialCache::new(provider, refresh_before));
+        self
+    }
+
+    /// Sign `caveats` into every request, so the server allows each only
+    /// what both the key packet and the caveats do
+    pub fn with_caveats(mut self, caveats: Caveats) -> Self {
+        self.caveats = Some(caveats.encode());
+        self
     }
-    
+
+    /// Ask the server to queue every request in this class; the key packet
+    /// may cap it lower
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Spread reads over these replicas, as the read consistency allows
+    pub fn with_read_replicas<I, S>(mut self, replicas: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for replica in replicas {
+            let replica = replica.into();
+            let _uri: Uri = replica.parse()
+                .map_err(|e| ClientError::Connection(format!("Invalid replica URL: {}", e)))?;
+            self.replicas.push(replica.trim_end_matches('/').to_string());
+        }
+        Ok(self)
+    }
+
+    /// Consistency of reads that don't ask for their own; the default reads
+    /// from the leader
+    pub fn with_read_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.read_consistency = consistency;
+        self
+    }
+
+    /// Continue a session from another client, e.g. one that served an
+    /// earlier request of the same user
+    pub fn with_session(self, token: SessionToken) -> Self {
+        self.merge_session(token);
+        self
+    }
+
+    /// Hand the metrics of every request to `recorder`
+    pub fn with_metrics_recorder(mut self, recorder: Arc<dyn MetricsRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// What this client has seen so far; every request carries it, so reads
+    /// wait for the client's own writes and never go back in time
+    pub fn session(&self) -> Option<SessionToken> {
+        *self.session.lock().unwrap()
+    }
+
+    fn merge_session(&self, token: SessionToken) {
+        let mut session = self.session.lock().unwrap();
+        *session = Some(session.map_or(token, |session| session.merge(token)));
+    }
+
+    /// Correction applied to request timestamps, learned from the server clock
+    pub fn clock_offset_ms(&self) -> i64 {
+        self.clock_offset_ms.load(Ordering::Relaxed)
+    }
+
     /// Store an object
     pub async fn put(&self, bucket: &BucketId, key: &Key, data: &[u8]) -> Result<ObjectMetadata> {
-        // Placeholder implementation
-        todo!("Implement PUT request")
+        self.put_with_checksums(bucket, key, data, &[]).await
+    }
+
+    /// Store an object, asking the server to also keep its `algorithms`
+    /// checksums, which come back in the metadata and on later GETs
+    pub async fn put_with_checksums(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        data: &[u8],
+        algorithms: &[ChecksumAlgorithm],
+    ) -> Result<ObjectMetadata> {
+        let names: Vec<&str> = algorithms.iter().map(|algorithm| algorithm.as_str()).collect();
+        let extra: Vec<(&str, String)> = match names.is_empty() {
+            true => Vec::new(),
+            false => vec![(CHECKSUM_ALGORITHM_HEADER, names.join(","))],
+        };
+        self.put_with_headers(bucket, key, data, &extra).await
     }
-    
+
+    /// Store an object only if the key is still at `generation`, as a
+    /// fencing token: a writer that lost its lead to one that has written
+    /// since fails with a 412 instead of overwriting newer state
+    ///
+    /// Every write returns the key's new generation in its metadata.
+    pub async fn put_fenced(&self, bucket: &BucketId, key: &Key, data: &[u8], generation: u64) -> Result<ObjectMetadata> {
+        self.put_with_headers(bucket, key, data, &[(headers::IF_GENERATION, generation.to_string())]).await
+    }
+
+    pub(crate) async fn put_with_headers(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        data: &[u8],
+        extra: &[(&str, String)],
+    ) -> Result<ObjectMetadata> {
+        let response = self
+            .send_with_headers(Method::PUT, &object_path(bucket, key), Bytes::copy_from_slice(data), extra)
+            .await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let version = body["version"]
+            .as_str()
+            .and_then(|v| ulid::Ulid::from_string(v).ok())
+            .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+        Ok(ObjectMetadata {
+            size: body["size"].as_u64().unwrap_or(data.len() as u64),
+            version: Version::from_ulid(version),
+            content_hash: Some(ContentHash::new(data)),
+            created_at: SystemTime::now(),
+            chunk_manifest: None,
+            owner: None,
+            checksums: ChecksumAlgorithm::ALL
+                .into_iter()
+                .filter_map(|algorithm| {
+                    let value = response.headers.get(format!("x-wfldb-checksum-{}", algorithm.as_str()))?;
+                    Some((algorithm, value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            generation: body["generation"].as_u64().unwrap_or_default(),
+            customer_key_hash: None,
+        })
+    }
+
     /// Retrieve an object
     pub async fn get(&self, bucket: &BucketId, key: &Key) -> Result<Option<Vec<u8>>> {
-        // Placeholder implementation
-        todo!("Implement GET request")
+        self.get_with(bucket, key, self.read_consistency).await
+    }
+
+    /// Apply a JSON Merge Patch to a document, creating it if missing
+    pub async fn patch_document(&self, bucket: &BucketId, key: &Key, patch: &serde_json::Value) -> Result<ObjectMetadata> {
+        self.send_patch(&object_path(bucket, key), patch).await
+    }
+
+    /// Set the value at a JSON Pointer inside an existing document
+    pub async fn set_document_field(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        pointer: &str,
+        value: &serde_json::Value,
+    ) -> Result<ObjectMetadata> {
+        let path = format!("{}?pointer={}", object_path(bucket, key), encode_query_component(pointer.as_bytes()));
+        self.send_patch(&path, value).await
     }
-    
+
+    async fn send_patch(&self, path: &str, body: &serde_json::Value) -> Result<ObjectMetadata> {
+        let response = self.send(Method::PATCH, path, Bytes::from(body.to_string())).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let version = body["version"]
+            .as_str()
+            .and_then(|v| ulid::Ulid::from_string(v).ok())
+            .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+        Ok(ObjectMetadata {
+            size: body["size"].as_u64().unwrap_or_default(),
+            version: Version::from_ulid(version),
+            content_hash: None,
+            created_at: SystemTime::now(),
+            chunk_manifest: None,
+            owner: None,
+            checksums: Default::default(),
+            generation: body["generation"].as_u64().unwrap_or_default(),
+            customer_key_hash: None,
+        })
+    }
+
+    /// Selected fields of a JSON document: top-level names or JSON Pointers
+    pub async fn get_fields(&self, bucket: &BucketId, key: &Key, fields: &[&str]) -> Result<Option<serde_json::Value>> {
+        let path = format!("{}?fields={}", object_path(bucket, key), encode_query_component(fields.join(",").as_bytes()));
+        let response = self.send(Method::GET, &path, Bytes::new()).await?;
+        match response.status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => serde_json::from_slice(&response.body)
+                .map(Some)
+                .map_err(|e| ClientError::InvalidResponse(e.to_string())),
+            _ => Err(status_error(&response)),
+        }
+    }
+
     /// Delete an object
     pub async fn delete(&self, bucket: &BucketId, key: &Key) -> Result<()> {
-        // Placeholder implementation
-        todo!("Implement DELETE request")
+        let response = self.send(Method::DELETE, &object_path(bucket, key), Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        Ok(())
     }
-    
-    /// List objects with prefix
+
+    /// List objects with prefix, following pages until `limit` keys or the end
     pub async fn list(&self, bucket: &BucketId, prefix: &str, limit: Option<usize>) -> Result<Vec<Key>> {
-        // Placeholder implementation
-        todo!("Implement LIST request")
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut keys = Vec::new();
+        let mut start_after = None;
+        while keys.len() < limit {
+            let page = self
+                .list_page(bucket, prefix, start_after.as_deref(), Some(limit - keys.len()))
+                .await?;
+            keys.extend(page.keys);
+            match page.next_start_after {
+                Some(next) => start_after = Some(next),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    /// List one page of objects with prefix, after the last key of the previous page
+    pub async fn list_page(
+        &self,
+        bucket: &BucketId,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<ListPage> {
+        let mut path = format!("/v1/{}?prefix={}", bucket.as_str(), encode_query_component(prefix.as_bytes()));
+        if let Some(start_after) = start_after {
+            path.push_str(&format!("&start_after={}", encode_query_component(start_after.as_bytes())));
+        }
+        if let Some(limit) = limit {
+            path.push_str(&format!("&limit={}", limit));
+        }
+        let response = self.send(Method::GET, &path, Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let keys = body["keys"]
+            .as_array()
+            .ok_or_else(|| ClientError::InvalidResponse("missing keys".to_string()))?
+            .iter()
+            .map(|key| {
+                key.as_str()
+                    .ok_or_else(|| ClientError::InvalidResponse("non-string key".to_string()))
+                    .and_then(|key| Key::new(key).map_err(ClientError::from))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ListPage {
+            keys,
+            next_start_after: body["next_start_after"].as_str().map(str::to_string),
+        })
     }
-    
-    /// Start multipart upload
-    pub async fn start_multipart_upload(&self, bucket: &BucketId, key: &Key) -> Result<MultipartUpload> {
-        // Placeholder implementation
-        todo!("Implement multipart upload start")
+
+    /// Buckets the client's key has any permission on, by name
+    pub async fn list_buckets(&self) -> Result<Vec<BucketInfo>> {
+        let response = self.send(Method::GET, "/v1", Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        body["buckets"]
+            .as_array()
+            .ok_or_else(|| ClientError::InvalidResponse("missing buckets".to_string()))?
+            .iter()
+            .map(|bucket| {
+                let name = bucket["name"]
+                    .as_str()
+                    .ok_or_else(|| ClientError::InvalidResponse("non-string bucket name".to_string()))?;
+                Ok(BucketInfo {
+                    id: BucketId::new(name)?,
+                    created_at_ms: bucket["created_at_ms"].as_u64(),
+                    approximate_entries: bucket["approximate_entries"].as_u64().unwrap_or(0),
+                    disk_bytes: bucket["disk_bytes"].as_u64().unwrap_or(0),
+                })
+            })
+            .collect()
+    }
+
+    /// Last sequence number written to the server's change journal (admin only)
+    pub async fn journal_head(&self) -> Result<u64> {
+        let response = self.send(Method::GET, "/admin/journal/head", Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        body["head"]
+            .as_u64()
+            .ok_or_else(|| ClientError::InvalidResponse("missing journal head".to_string()))
+    }
+
+    /// Change records after sequence `after`, optionally of one bucket (admin only)
+    pub async fn read_journal(&self, after: u64, bucket: Option<&BucketId>, limit: usize) -> Result<JournalPage> {
+        let mut path = format!("/admin/journal?after={}&limit={}", after, limit);
+        if let Some(bucket) = bucket {
+            path.push_str(&format!("&bucket={}", bucket.as_str()));
+        }
+        let response = self.send(Method::GET, &path, Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+
+        let next = response.headers
+            .get(JOURNAL_NEXT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| ClientError::InvalidResponse("missing journal cursor".to_string()))?;
+        let (records, valid_len) = decode_change_frames(&response.body)?;
+        if valid_len != response.body.len() {
+            return Err(ClientError::InvalidResponse("truncated change record".to_string()));
+        }
+        Ok(JournalPage { records, next })
+    }
+
+    /// Refuse or resume writes to a bucket on the server (admin only)
+    pub async fn set_bucket_frozen(&self, bucket: &BucketId, frozen: bool) -> Result<()> {
+        let method = if frozen { Method::PUT } else { Method::DELETE };
+        let path = format!("/admin/buckets/{}/freeze", bucket.as_str());
+        let response = self.send(method, &path, Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        Ok(())
+    }
+
+    /// Take the lease on a key for `ttl`; fails with
+    /// [`ClientError::LeaseHeld`] while another client holds it
+    pub async fn acquire_lease(&self, bucket: &BucketId, key: &Key, ttl: Duration) -> Result<Lease> {
+        let body = serde_json::json!({"ttl_secs": ttl.as_secs()}).to_string();
+        let response = self.send(Method::POST, &lease_path(bucket, key), Bytes::from(body)).await?;
+        let body = lease_response(&response)?;
+        Ok(Lease {
+            id: body["lease_id"]
+                .as_str()
+                .ok_or_else(|| ClientError::InvalidResponse("missing lease id".to_string()))?
+                .to_string(),
+            holder: body["holder"].as_str().map(str::to_string),
+            acquired_at_ms: body["acquired_at_ms"].as_u64().unwrap_or_default(),
+            expires_at_ms: body["expires_at_ms"].as_u64().unwrap_or_default(),
+        })
+    }
+
+    /// Extend a held lease to `ttl` from now, updating its expiry
+    pub async fn renew_lease(&self, bucket: &BucketId, key: &Key, lease: &mut Lease, ttl: Duration) -> Result<()> {
+        let body = serde_json::json!({"ttl_secs": ttl.as_secs()}).to_string();
+        let extra = [(headers::LEASE, lease.id.clone())];
+        let response = self
+            .send_with_headers(Method::PUT, &lease_path(bucket, key), Bytes::from(body), &extra)
+            .await?;
+        let body = lease_response(&response)?;
+        if let Some(expires_at_ms) = body["expires_at_ms"].as_u64() {
+            lease.expires_at_ms = expires_at_ms;
+        }
+        Ok(())
+    }
+
+    /// Give up a held lease
+    pub async fn release_lease(&self, bucket: &BucketId, key: &Key, lease: Lease) -> Result<()> {
+        let extra = [(headers::LEASE, lease.id)];
+        let response = self
+            .send_with_headers(Method::DELETE, &lease_path(bucket, key), Bytes::new(), &extra)
+            .await?;
+        lease_response(&response).map(|_| ())
+    }
+
+    /// Make an object expire `ttl` from now without rewriting it, returning
+    /// the new expiry (ms since the Unix epoch); a missing or already
+    /// expired object fails with a 404
+    pub async fn touch(&self, bucket: &BucketId, key: &Key, ttl: Duration) -> Result<u64> {
+        let body = serde_json::json!({"ttl_secs": ttl.as_secs()}).to_string();
+        let path = format!("{}/_touch", object_path(bucket, key));
+        let response = self.send(Method::POST, &path, Bytes::from(body)).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        serde_json::from_slice::<serde_json::Value>(&response.body)
+            .ok()
+            .and_then(|body| body["expires_at_ms"].as_u64())
+            .ok_or_else(|| ClientError::InvalidResponse("missing expiry".to_string()))
+    }
+
+    /// Upload one chunk of a large object ahead of its manifest, returning
+    /// the hash to list in [`commit_manifest`](Self::commit_manifest)
+    pub async fn put_chunk(&self, bucket: &BucketId, data: &[u8]) -> Result<ContentHash> {
+        let hash = ContentHash::new(data);
+        let response = self
+            .send(Method::PUT, &chunk_path(bucket, &hash), Bytes::copy_from_slice(data))
+            .await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        Ok(hash)
     }
-}
\ No newline at end of file
+
+    /// Whether the bucket already stores a chunk, so it needn't be uploaded
+    pub async fn has_chunk(&self, bucket: &BucketId, hash: &ContentHash) -> Result<bool> {
+        let response = self.send(Method::HEAD, &chunk_path(bucket, hash), Bytes::new()).await?;
+        match response.status {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            _ => Err(status_error(&response)),
+        }
+    }
+
+    /// Which of `chunks` the bucket doesn't store yet, checked in one request
+    pub async fn missing_chunks(&self, bucket: &BucketId, chunks: &[ContentHash]) -> Result<Vec<ContentHash>> {
+        let hashes: Vec<String> = chunks.iter().map(ContentHash::to_hex).collect();
+        let body = serde_json::json!({"chunks": hashes}).to_string();
+        let path = format!("/v1/{}/_chunks/exists", bucket.as_str());
+        let response = self.send(Method::POST, &path, Bytes::from(body)).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        body["missing"]
+            .as_array()
+            .ok_or_else(|| ClientError::InvalidResponse("missing chunk list".to_string()))?
+            .iter()
+            .map(|hash| {
+                hash.as_str()
+                    .and_then(|hash| ContentHash::from_hex(hash).ok())
+                    .ok_or_else(|| ClientError::InvalidResponse(format!("invalid chunk hash: {}", hash)))
+            })
+            .collect()
+    }
+
+    /// Store an object made of uploaded chunks, in order
+    pub async fn commit_manifest(&self, bucket: &BucketId, key: &Key, chunks: &[ContentHash]) -> Result<ObjectMetadata> {
+        let hashes: Vec<String> = chunks.iter().map(ContentHash::to_hex).collect();
+        let body = serde_json::json!({"chunks": hashes}).to_string();
+        let path = format!("/v1/{}/_manifest/{}", bucket.as_str(), key.to_url());
+        let response = self.send(Method::PUT, &path, Bytes::from(body)).await?;
+        written_metadata(&response)
+    }
+
+    /// Store `key` as the concatenation of `sources` from the same bucket,
+    /// in order; the server references the sources' chunks instead of
+    /// copying their data
+    pub async fn compose(&self, bucket: &BucketId, key: &Key, sources: &[Key]) -> Result<ObjectMetadata> {
+        let sources: Vec<&str> = sources.iter().map(Key::as_str).collect();
+        let body = serde_json::json!({"sources": sources}).to_string();
+        let path = format!("/v1/{}/_compose/{}", bucket.as_str(), key.to_url());
+        let response = self.send(Method::POST, &path, Bytes::from(body)).await?;
+        written_metadata(&response)
+    }
+
+    /// Overwrite the bytes of a large object from `offset` on with `data`,
+    /// extending it if `data` runs past the end; the server rewrites only
+    /// the chunks the range touches
+    pub async fn write_range(&self, bucket: &BucketId, key: &Key, offset: u64, data: &[u8]) -> Result<ObjectMetadata> {
+        let path = format!("/v1/{}/_range/{}?offset={}", bucket.as_str(), key.to_url(), offset);
+        let response = self.send(Method::PUT, &path, Bytes::copy_from_slice(data)).await?;
+        written_metadata(&response)
+    }
+
+    /// Start an optimistic transaction on one bucket
+    pub fn transaction(&self, bucket: &BucketId) -> Transaction<'_> {
+        Transaction::new(self, bucket.clone())
+    }
+
+    /// Send a request, re-signing and retrying once if the server rejects our clock
+    pub(crate) async fn send(&self, method: Method, path: &str, body: Bytes) -> Result<RawResponse> {
+        self.send_with_headers(method, path, body, &[]).await
+    }
+
+    /// [`send`](Self::send) with extra, unsigned request headers
+    pub(crate) async fn send_with_headers(
+        &self,
+        method: Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+    ) -> Result<RawResponse> {
+        self.send_to(&self.base_url, method, path, body, extra).await
+    }
+
+    /// [`send_with_headers`](Self::send_with_headers) to the server at `base_url`
+    ///
+    /// Both attempts carry the same request id, so the server logs them as
+    /// one operation.
+    pub(crate) async fn send_to(
+        &self,
+        base_url: &str,
+        method: Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+    ) -> Result<RawResponse> {
+        let request_id = ulid::Ulid::new().to_string();
+        let span = request_span(&method, path, &request_id);
+        let started = Instant::now();
+        let mut retries = 0;
+        let bytes = body.len() as u64;
+        let result = self
+            .send_attempts(base_url, &method, path, body, extra, &request_id, &mut retries)
+            .instrument(span.clone())
+            .await;
+        let status = result.as_ref().ok().map(|response| response.status);
+        let received = result.as_ref().map_or(0, |response| response.body.len() as u64);
+        self.observe(&span, RequestMetrics {
+            method,
+            path: path.to_string(),
+            request_id,
+            status,
+            latency: started.elapsed(),
+            retries,
+            bytes_sent: bytes * (1 + retries as u64),
+            bytes_received: received,
+        });
+        result
+    }
+
+    /// Send a request with `extra` headers, returning a successful response
+    /// before its body is read, for bodies too large to buffer; any other
+    /// response is read in full and returned as `Err`
+    pub(crate) async fn open(
+        &self,
+        method: Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+    ) -> Result<std::result::Result<hyper::Response<Incoming>, RawResponse>> {
+        let request_id = ulid::Ulid::new().to_string();
+        let span = request_span(&method, path, &request_id);
+        let started = Instant::now();
+        let mut retries = 0;
+        let bytes = body.len() as u64;
+        let result = self
+            .open_attempts(&method, path, body, extra, &request_id, &mut retries)
+            .instrument(span.clone())
+            .await;
+        let (status, received) = match &result {
+            Ok(Ok(response)) => (Some(response.status()), 0),
+            Ok(Err(rejected)) => (Some(rejected.status), rejected.body.len() as u64),
+            Err(_) => (None, 0),
+        };
+        self.observe(&span, RequestMetrics {
+            method,
+            path: path.to_string(),
+            request_id,
+            status,
+            latency: started.elapsed(),
+            retries,
+            bytes_sent: bytes * (1 + retries as u64),
+            bytes_received: received,
+        });
+        result
+    }
+
+    /// The request, and its retry if the server rejected our clock or an
+    /// expired packet; a 401 that isn't retried comes back read in full
+    #[allow(clippy::too_many_arguments)]
+    async fn attempts(
+        &self,
+        base_url: &str,
+        method: &Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+        request_id: &str,
+        retries: &mut u32,
+    ) -> Result<std::result::Result<hyper::Response<Incoming>, RawResponse>> {
+        let response = self.request_once(base_url, method, path, body.clone(), extra, request_id).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(Ok(response));
+        }
+        let rejected = buffer(response).await?;
+        if !self.recover_unauthorized(&rejected).await {
+            return Ok(Err(rejected));
+        }
+        *retries += 1;
+        Ok(Ok(self.request_once(base_url, method, path, body, extra, request_id).await?))
+    }
+
+    /// [`attempts`](Self::attempts) with the response read in full
+    #[allow(clippy::too_many_arguments)]
+    async fn send_attempts(
+        &self,
+        base_url: &str,
+        method: &Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+        request_id: &str,
+        retries: &mut u32,
+    ) -> Result<RawResponse> {
+        match self.attempts(base_url, method, path, body, extra, request_id, retries).await? {
+            Ok(response) => buffer(response).await,
+            Err(rejected) => Ok(rejected),
+        }
+    }
+
+    /// [`attempts`](Self::attempts) for [`open`](Self::open), reading only
+    /// unsuccessful responses in full
+    async fn open_attempts(
+        &self,
+        method: &Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+        request_id: &str,
+        retries: &mut u32,
+    ) -> Result<std::result::Result<hyper::Response<Incoming>, RawResponse>> {
+        match self.attempts(&self.base_url, method, path, body, extra, request_id, retries).await? {
+            Ok(response) if response.status().is_success() => Ok(Ok(response)),
+            Ok(response) => Ok(Err(buffer(response).await?)),
+            Err(rejected) => Ok(Err(rejected)),
+        }
+    }
+
+    fn signs(&self) -> bool {
+        self.signer.is_some() || self.credentials.is_some()
+    }
+
+    /// Whether a 401 is worth retrying: it rejected our clock, now
+    /// corrected, or an expired packet, now dropped to be refreshed
+    async fn recover_unauthorized(&self, rejected: &RawResponse) -> bool {
+        if !self.signs() {
+            return false;
+        }
+        if let Some(server_time) = skew_hint(rejected) {
+            let offset = server_time as i64 - now_ms() as i64;
+            self.clock_offset_ms.store(offset, Ordering::Relaxed);
+            return true;
+        }
+        let Some(credentials) = &self.credentials else {
+            return false;
+        };
+        let body = serde_json::from_slice::<serde_json::Value>(&rejected.body).unwrap_or_default();
+        if body["code"] != "expired" {
+            return false;
+        }
+        credentials.invalidate().await;
+        true
+    }
+
+    /// Close out a request's span and hand its metrics to the recorder
+    fn observe(&self, span: &Span, metrics: RequestMetrics) {
+        span.record("retries", metrics.retries);
+        match metrics.status {
+            Some(status) => {
+                span.record("status", status.as_u16());
+            }
+            None => {
+                span.in_scope(|| tracing::debug!("request failed without a response"));
+            }
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&metrics);
+        }
+    }
+
+    async fn request_once(
+        &self,
+        base_url: &str,
+        method: &Method,
+        path: &str,
+        body: Bytes,
+        extra: &[(&str, String)],
+        request_id: &str,
+    ) -> Result<hyper::Response<Incoming>> {
+        let uri: Uri = format!("{}{}", base_url, path)
+            .parse()
+            .map_err(|e| ClientError::Request(format!("Invalid request URI: {}", e)))?;
+        let mut builder = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .header(headers::REQUEST_ID, request_id);
+        let now = (now_ms() as i64).saturating_add(self.clock_offset_ms()).max(0) as u64;
+        let refreshed = match &self.credentials {
+            Some(credentials) => Some(credentials.signer(now).await?),
+            None => None,
+        };
+        if let Some(signer) = refreshed.as_deref().or(self.signer.as_ref()) {
+            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+            let mut request = RequestParts::new(method.as_str(), path).with_query(query);
+            if let Some(caveats) = &self.caveats {
+                request = request.with_header(headers::CAVEATS, caveats);
+                builder = builder.header(headers::CAVEATS, caveats);
+            }
+            for (name, value) in signer.sign_request(&request, &body, now) {
+                builder = builder.header(name, value);
+            }
+        }
+        if let Some(priority) = self.priority {
+            builder = builder.header(headers::PRIORITY, priority.as_str());
+        }
+        if let Some(session) = self.session() {
+            builder = builder.header(headers::SESSION, session.to_string());
+        }
+        for (name, value) in extra {
+            builder = builder.header(*name, value);
+        }
+        let request = builder
+            .body(Full::new(body))
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+
+        let response = self.http
+            .request(request)
+            .await
+            .map_err(|e| ClientError::Connection(e.to_string()))?;
+        if let Some(token) = response
+            .headers()
+            .get(headers::SESSION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| SessionToken::parse(v).ok())
+        {
+            self.merge_session(token);
+        }
+        Ok(response)
+    }
+}
+
+/// Span a request runs in, named by the id the server logs it under
+fn request_span(method: &Method, path: &str, request_id: &str) -> Span {
+    tracing::debug_span!(
+        "wfldb.request",
+        method = %method,
+        path,
+        request_id,
+        status = tracing::field::Empty,
+        retries = tracing::field::Empty,
+    )
+}
+
+/// Read a whole response
+async fn buffer(response: hyper::Response<Incoming>) -> Result<RawResponse> {
+    let (parts, body) = response.into_parts();
+    let body = body.collect().await.map_err(|e| ClientError::Http(e.to_string()))?.to_bytes();
+    Ok(RawResponse { status: parts.status, headers: parts.headers, body })
+}
+
+pub(crate) fn object_path(bucket: &BucketId, key: &Key) -> String {
+    format!("/v1/{}/{}", bucket.as_str(), key.to_url())
+}
+
+fn chunk_path(bucket: &BucketId, hash: &ContentHash) -> String {
+    format!("/v1/{}/_chunks/{}", bucket.as_str(), hash.to_hex())
+}
+
+fn lease_path(bucket: &BucketId, key: &Key) -> String {
+    format!("/v1/{}/_lease/{}", bucket.as_str(), key.to_url())
+}
+
+/// Body of a successful lease response, or the lease-specific error
+fn lease_response(response: &RawResponse) -> Result<serde_json::Value> {
+    let body = serde_json::from_slice::<serde_json::Value>(&response.body).unwrap_or_default();
+    match response.status {
+        status if status.is_success() => Ok(body),
+        StatusCode::CONFLICT => Err(ClientError::LeaseHeld(body["expires_at_ms"].as_u64().unwrap_or_default())),
+        StatusCode::GONE => Err(ClientError::LeaseLost(body["error"].as_str().unwrap_or_default().to_string())),
+        _ => Err(status_error(response)),
+    }
+}
+
+/// Metadata of an object a manifest commit, compose or multipart upload wrote
+pub(crate) fn written_metadata(response: &RawResponse) -> Result<ObjectMetadata> {
+    if !response.status.is_success() {
+        return Err(status_error(response));
+    }
+    let body: serde_json::Value = serde_json::from_slice(&response.body)
+        .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+    let version = body["version"]
+        .as_str()
+        .and_then(|v| ulid::Ulid::from_string(v).ok())
+        .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+    Ok(ObjectMetadata {
+        size: body["size"].as_u64().unwrap_or_default(),
+        version: Version::from_ulid(version),
+        content_hash: None,
+        created_at: SystemTime::now(),
+        chunk_manifest: None,
+        owner: None,
+        checksums: Default::default(),
+        generation: body["generation"].as_u64().unwrap_or_default(),
+        customer_key_hash: None,
+    })
+}
+
+/// Server clock from a 401 that rejected the request timestamp
+fn skew_hint(response: &RawResponse) -> Option<u64> {
+    let body: serde_json::Value = serde_json::from_slice(&response.body).ok()?;
+    if body["code"] != "timestamp_out_of_window" {
+        return None;
+    }
+    response.headers
+        .get(headers::SERVER_TIME)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+pub(crate) fn status_error(response: &RawResponse) -> ClientError {
+    let body = serde_json::from_slice::<serde_json::Value>(&response.body).unwrap_or_default();
+    let message = body["error"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| String::from_utf8_lossy(&response.body).into_owned());
+    let request_id = response.headers
+        .get(headers::REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    if body["retryable"] == true {
+        let retry_after = response.headers
+            .get(hyper::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs);
+        return ClientError::Retryable {
+            status: response.status.as_u16(),
+            code: body["code"].as_str().unwrap_or_default().to_string(),
+            message,
+            retry_after,
+            request_id,
+        };
+    }
+    if body["code"] == "schema_violation" {
+        if let Ok(violations) = serde_json::from_value(body["violations"].clone()) {
+            return ClientError::SchemaViolation(violations);
+        }
+    }
+    // Name the request so the failure can be found in the server's logs
+    let message = match request_id {
+        Some(id) => format!("{}: {} (request {})", response.status, message, id),
+        None => format!("{}: {}", response.status, message),
+    };
+    match response.status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ClientError::Unauthorized(message),
+        _ => ClientError::Request(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: StatusCode, server_time: Option<&str>, body: &str) -> RawResponse {
+        let mut headers = HeaderMap::new();
+        if let Some(server_time) = server_time {
+            headers.insert(headers::SERVER_TIME, server_time.parse().unwrap());
+        }
+        RawResponse { status, headers, body: Bytes::from(body.to_string()) }
+    }
+
+    #[test]
+    fn test_skew_hint() {
+        let skewed = response(
+            StatusCode::UNAUTHORIZED,
+            Some("1700000000000"),
+            r#"{"error":"Request timestamp outside the allowed window","code":"timestamp_out_of_window"}"#,
+        );
+        assert_eq!(skew_hint(&skewed), Some(1_700_000_000_000));
+
+        // Other auth failures are not retried
+        let bad_signature = response(
+            StatusCode::UNAUTHORIZED,
+            Some("1700000000000"),
+            r#"{"error":"Invalid signature","code":"invalid_signature"}"#,
+        );
+        assert_eq!(skew_hint(&bad_signature), None);
+        assert!(matches!(status_error(&bad_signature), ClientError::Unauthorized(_)));
+    }
+
+    #[test]
+    fn test_retryable_status_error() {
+        let mut full = response(
+            StatusCode::INSUFFICIENT_STORAGE,
+            None,
+            r#"{"error":"IO error: storage full","code":"storage_full","retryable":true}"#,
+        );
+        full.headers.insert(hyper::header::RETRY_AFTER, "30".parse().unwrap());
+        let error = status_error(&full);
+        assert!(error.is_retryable());
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(30)));
+        assert!(matches!(error, ClientError::Retryable { status: 507, ref code, .. } if code == "storage_full"));
+
+        let missing = response(StatusCode::NOT_FOUND, None, r#"{"error":"Not found","code":"not_found","retryable":false}"#);
+        assert!(!status_error(&missing).is_retryable());
+    }
+
+    #[test]
+    fn test_errors_carry_request_id() {
+        let mut full = response(StatusCode::INSUFFICIENT_STORAGE, None, r#"{"error":"full","retryable":true}"#);
+        full.headers.insert(headers::REQUEST_ID, "trace-42".parse().unwrap());
+        assert_eq!(status_error(&full).request_id(), Some("trace-42"));
+
+        let mut missing = response(StatusCode::NOT_FOUND, None, r#"{"error":"Not found","retryable":false}"#);
+        missing.headers.insert(headers::REQUEST_ID, "trace-43".parse().unwrap());
+        assert!(status_error(&missing).to_string().contains("(request trace-43)"));
+    }
+
+    #[test]
+    fn test_schema_violation() {
+        let rejected = response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            None,
+            r#"{"error":"Document does not match the bucket's schema","code":"schema_violation","retryable":false,
+                "violations":[{"path":"/age","schema_path":"/properties/age/minimum","message":"-1 is less than the minimum of 0"}]}"#,
+        );
+        let ClientError::SchemaViolation(violations) = status_error(&rejected) else {
+            panic!("expected a schema violation");
+        };
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "/age");
+    }
+}
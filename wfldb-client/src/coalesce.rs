@@ -0,0 +1,302 @@
+//! Batching of small writes
+//!
+//! Every PUT costs a round trip and a commit on the server, which for small
+//! objects is most of its cost. A [`WriteCoalescer`] holds each put for up
+//! to its linger time, a few milliseconds, and sends the puts that arrived
+//! meanwhile as one transaction (`POST /v1/{bucket}/_txn`), so a burst of
+//! small writes costs one request instead of one each. Each caller still
+//! gets its own object's metadata back.
+//!
+//! A batch is sent early once it holds `max_batch` puts or `max_batch_bytes`
+//! of data, or when a put names a key already in it, which starts the next
+//! batch. Batches are sent one at a time, the next gathering while one is
+//! in flight, so puts of a key land in the order they were made. Values
+//! over `max_value_size` don't fit a transaction and are sent as plain PUTs
+//! straight away, unordered with the puts still queued. A transaction
+//! applies all of its writes or none, so if a batch fails its puts are sent
+//! again one by one before the next batch, and only a put that fails on its
+//! own reports an error.
+//!
+//! ```ignore
+//! let coalescer = WriteCoalescer::new(Arc::new(client), bucket, CoalesceOptions::default());
+//! let writes = events.iter().map(|event| coalescer.put(&event.key(), &event.encode()));
+//! futures::future::try_join_all(writes).await?;
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use futures::future::join_all;
+use hyper::Method;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Instant};
+use wfldb_core::*;
+use crate::client::status_error;
+use crate::{Client, ClientError, Result};
+
+/// When a [`WriteCoalescer`] sends a batch
+#[derive(Debug, Clone)]
+pub struct CoalesceOptions {
+    /// Longest a put waits for others to join its batch
+    pub linger: Duration,
+    /// Most puts in one batch
+    pub max_batch: usize,
+    /// Most data in one batch
+    pub max_batch_bytes: usize,
+    /// Larger values are sent on their own
+    pub max_value_size: usize,
+}
+
+impl Default for CoalesceOptions {
+    fn default() -> Self {
+        CoalesceOptions {
+            linger: Duration::from_millis(5),
+            max_batch: 100,
+            max_batch_bytes: 1024 * 1024,
+            max_value_size: 64 * 1024,
+        }
+    }
+}
+
+/// A put waiting for its batch to be sent
+struct Pending {
+    key: Key,
+    data: Vec<u8>,
+    reply: oneshot::Sender<Result<ObjectMetadata>>,
+}
+
+/// Sends the small puts to one bucket in batches
+///
+/// Batches are sent by a background task, which stops once the coalescer
+/// is dropped and the puts already handed to it are sent.
+pub struct WriteCoalescer {
+    client: Arc<Client>,
+    bucket: BucketId,
+    max_value_size: usize,
+    queue: mpsc::UnboundedSender<Pending>,
+}
+
+impl WriteCoalescer {
+    /// Start batching puts to `bucket`; must be called within a Tokio runtime
+    pub fn new(client: Arc<Client>, bucket: BucketId, options: CoalesceOptions) -> Self {
+        let (queue, pending) = mpsc::unbounded_channel();
+        let max_value_size = options.max_value_size;
+        tokio::spawn(run(client.clone(), bucket.clone(), options, pending));
+        WriteCoalescer { client, bucket, max_value_size, queue }
+    }
+
+    /// Store an object as part of the next batch
+    pub async fn put(&self, key: &Key, data: &[u8]) -> Result<ObjectMetadata> {
+        if data.len() > self.max_value_size {
+            return self.client.put(&self.bucket, key, data).await;
+        }
+        let (reply, result) = oneshot::channel();
+        let stopped = || ClientError::Request("write coalescer stopped".to_string());
+        self.queue
+            .send(Pending { key: key.clone(), data: data.to_vec(), reply })
+            .map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())?
+    }
+}
+
+/// Gather puts into batches and send each, waiting for one batch before
+/// sending the next
+async fn run(client: Arc<Client>, bucket: BucketId, options: CoalesceOptions, mut pending: mpsc::UnboundedReceiver<Pending>) {
+    // A put that didn't fit the previous batch starts the next
+    let mut carried = None;
+    loop {
+        let first = match carried.take() {
+            Some(first) => first,
+            None => match pending.recv().await {
+                Some(first) => first,
+                None => return,
+            },
+        };
+        let deadline = Instant::now() + options.linger;
+        let mut bytes = first.data.len();
+        let mut batch = vec![first];
+        while batch.len() < options.max_batch {
+            let Ok(Some(next)) = timeout_at(deadline, pending.recv()).await else {
+                break;
+            };
+            if bytes + next.data.len() > options.max_batch_bytes || batch.iter().any(|put| put.key == next.key) {
+                carried = Some(next);
+                break;
+            }
+            bytes += next.data.len();
+            batch.push(next);
+        }
+        send_batch(&client, &bucket, batch).await;
+    }
+}
+
+async fn send_batch(client: &Client, bucket: &BucketId, batch: Vec<Pending>) {
+    if batch.len() > 1 {
+        match commit_batch(client, bucket, &batch).await {
+            Ok(written) => {
+                for (put, metadata) in batch.into_iter().zip(written) {
+                    let _ = put.reply.send(Ok(metadata));
+                }
+                return;
+            }
+            Err(e) => tracing::debug!(error = %e, puts = batch.len(), "batch failed; sending its puts one by one"),
+        }
+    }
+    // A batch holds each key once, so these can't overtake each other
+    join_all(batch.into_iter().map(|put| async move {
+        let _ = put.reply.send(client.put(bucket, &put.key, &put.data).await);
+    }))
+    .await;
+}
+
+/// Send a batch as one transaction, returning each put's metadata in order
+async fn commit_batch(client: &Client, bucket: &BucketId, batch: &[Pending]) -> Result<Vec<ObjectMetadata>> {
+    let operations: Vec<Value> = batch
+        .iter()
+        .map(|put| json!({"op": "put", "key": put.key.as_str(), "data": STANDARD.encode(&put.data)}))
+        .collect();
+    let body = json!({"operations": operations});
+    let path = format!("/v1/{}/_txn", bucket.as_str());
+    let response = client.send(Method::POST, &path, Bytes::from(body.to_string())).await?;
+    if !response.status.is_success() {
+        return Err(status_error(&response));
+    }
+    let body: Value = serde_json::from_slice(&response.body)?;
+    let applied = body["operations"].as_array().filter(|applied| applied.len() == batch.len());
+    let applied = applied.ok_or_else(|| ClientError::InvalidResponse("missing applied operations".to_string()))?;
+    applied.iter().zip(batch).map(|(op, put)| applied_metadata(op, &put.data)).collect()
+}
+
+/// Metadata of a put a transaction applied
+fn applied_metadata(op: &Value, data: &[u8]) -> Result<ObjectMetadata> {
+    let version = op["version"]
+        .as_str()
+        .and_then(|v| ulid::Ulid::from_string(v).ok())
+        .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+    Ok(ObjectMetadata {
+        size: op["size"].as_u64().unwrap_or(data.len() as u64),
+        version: Version::from_ulid(version),
+        content_hash: Some(ContentHash::new(data)),
+        created_at: SystemTime::now(),
+        chunk_manifest: None,
+        owner: None,
+        checksums: Default::default(),
+        generation: op["generation"].as_u64().unwrap_or_default(),
+        customer_key_hash: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use crate::test_server;
+
+    /// Requests a stub server saw, as (method, keys written), and what
+    /// each key holds once the writes applied
+    #[derive(Default)]
+    struct Stub {
+        requests: Mutex<Vec<(String, Vec<String>)>>,
+        stored: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    /// A server taking PUTs and transactions, refusing writes of `reject`
+    /// and applying writes of `slow` only after a pause
+    async fn stub_server(stub: Arc<Stub>) -> String {
+        test_server::serve(move |req| {
+            let stub = stub.clone();
+            async move {
+                let writes: Vec<(String, Vec<u8>)> = match req.uri().path().strip_prefix("/v1/logs/") {
+                    Some("_txn") => {
+                        let body: Value = serde_json::from_slice(req.body()).unwrap();
+                        body["operations"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|op| (op["key"].as_str().unwrap().to_string(), STANDARD.decode(op["data"].as_str().unwrap()).unwrap()))
+                            .collect()
+                    }
+                    Some(key) => vec![(key.to_string(), req.body().to_vec())],
+                    None => return test_server::json(404, json!({"error": "Not found"})),
+                };
+                let keys = writes.iter().map(|(key, _)| key.clone()).collect();
+                stub.requests.lock().unwrap().push((req.method().to_string(), keys));
+                if writes.iter().any(|(_, data)| data == b"reject") {
+                    return test_server::json(400, json!({"error": "rejected", "retryable": false}));
+                }
+                if writes.iter().any(|(_, data)| data == b"slow") {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let applied: Vec<Value> = writes
+                    .into_iter()
+                    .map(|(key, data)| {
+                        let op = json!({"key": key, "size": data.len(), "version": Version::new().to_string(), "generation": 1});
+                        stub.stored.lock().unwrap().insert(key, data);
+                        op
+                    })
+                    .collect();
+                match req.method() == Method::POST {
+                    true => test_server::json(200, json!({"operations": applied})),
+                    false => test_server::json(201, applied[0].clone()),
+                }
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_puts_are_batched_in_order() {
+        let stub = Arc::new(Stub::default());
+        let client = Arc::new(Client::new(stub_server(stub.clone()).await).unwrap());
+        let options = CoalesceOptions { linger: Duration::from_millis(20), ..CoalesceOptions::default() };
+        let coalescer = WriteCoalescer::new(client, BucketId::new("logs").unwrap(), options);
+        let key = |name: &str| Key::new(name).unwrap();
+        let requests = || std::mem::take(&mut *stub.requests.lock().unwrap());
+        let keys = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        // Puts arriving together share one transaction
+        let (a, b, c) = (key("a"), key("b"), key("c"));
+        let written = futures::future::join3(coalescer.put(&a, b"1"), coalescer.put(&b, b"2"), coalescer.put(&c, b"3")).await;
+        assert!(written.0.is_ok() && written.1.is_ok() && written.2.is_ok());
+        assert_eq!(requests(), [("POST".to_string(), keys(&["a", "b", "c"]))]);
+
+        // A key already in the batch starts the next, sent after the first
+        // lands even when the first is slower
+        let (slow, fast) = futures::future::join(coalescer.put(&a, b"slow"), coalescer.put(&a, b"fast")).await;
+        assert!(slow.is_ok() && fast.is_ok());
+        assert_eq!(requests(), [("PUT".to_string(), keys(&["a"])), ("PUT".to_string(), keys(&["a"]))]);
+        assert_eq!(stub.stored.lock().unwrap()["a"], b"fast");
+
+        // A failed batch is retried put by put before the next batch
+        let (rejected, slow, fast) = futures::future::join3(
+            coalescer.put(&b, b"reject"),
+            coalescer.put(&c, b"slow"),
+            coalescer.put(&c, b"fast"),
+        )
+        .await;
+        assert!(rejected.is_err());
+        assert!(slow.is_ok() && fast.is_ok());
+        let mut seen = requests();
+        assert_eq!(seen.remove(0), ("POST".to_string(), keys(&["b", "c"])));
+        seen[..2].sort();
+        assert_eq!(seen, [
+            ("PUT".to_string(), keys(&["b"])),
+            ("PUT".to_string(), keys(&["c"])),
+            ("PUT".to_string(), keys(&["c"])),
+        ]);
+        assert_eq!(stub.stored.lock().unwrap()["c"], b"fast");
+    }
+
+    #[test]
+    fn test_applied_metadata() {
+        let version = Version::new().to_string();
+        let op = json!({"key": "a", "size": 5, "version": version, "generation": 3});
+        let metadata = applied_metadata(&op, b"hello").unwrap();
+        assert_eq!((metadata.size, metadata.generation), (5, 3));
+        assert_eq!(metadata.version.to_string(), version);
+        assert!(applied_metadata(&json!({"key": "a", "deleted": true}), b"").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Read consistency across replicas
+//!
+//! Writes always go to the leader. Reads can be spread over read replicas,
+//! each of which trails the leader by however much of its change journal it
+//! has yet to apply. Every response carries a session token with the
+//! answering node's journal position, and the client keeps the newest it
+//! has seen and sends it on every request. A replica holds the read until
+//! it has applied that position, which covers the client's own writes and
+//! everything it has already read; a replica that can't catch up in time
+//! answers `replica_behind` and the read goes to the leader instead. The
+//! session's reads therefore never go back in time, whichever node serves
+//! them.
+
+use bytes::Bytes;
+use hyper::{Method, StatusCode};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use wfldb_auth::headers;
+use wfldb_core::*;
+use crate::client::{object_path, status_error, RawResponse};
+use crate::{Client, ClientError, Result};
+
+/// Where a read may be served from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Always the leader: the latest committed data
+    #[default]
+    Leader,
+    /// A replica that matched the leader within the bound, or the leader
+    BoundedStaleness(Duration),
+    /// Any replica that has this session's writes
+    AnyReplica,
+}
+
+impl ReadConsistency {
+    /// Headers asking a replica to meet this consistency, on top of the
+    /// session token every request carries
+    pub(crate) fn replica_headers(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ReadConsistency::BoundedStaleness(bound) => vec![(headers::MAX_STALENESS, bound.as_millis().to_string())],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Client {
+    /// Retrieve an object with the given consistency instead of the
+    /// client's default
+    pub async fn get_with(&self, bucket: &BucketId, key: &Key, consistency: ReadConsistency) -> Result<Option<Vec<u8>>> {
+        if let Some(cache) = &self.disk_cache {
+            return self.get_cached(cache, bucket, key, consistency).await;
+        }
+        let response = self.send_read(&object_path(bucket, key), consistency, &[]).await?;
+        match response.status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.body.to_vec())),
+            _ => Err(status_error(&response)),
+        }
+    }
+
+    /// Send a GET with `extra` headers to a replica allowed by
+    /// `consistency`, falling back to the leader when the replica is
+    /// unreachable or too far behind
+    pub(crate) async fn send_read(
+        &self,
+        path: &str,
+        consistency: ReadConsistency,
+        extra: &[(&str, String)],
+    ) -> Result<RawResponse> {
+        let replica = match consistency {
+            ReadConsistency::Leader => None,
+            _ => self.next_replica(),
+        };
+        if let Some(replica) = replica {
+            let mut replica_extra: Vec<(&str, String)> = consistency.replica_headers();
+            replica_extra.extend(extra.iter().cloned());
+            match self.send_to(replica, Method::GET, path, Bytes::new(), &replica_extra).await {
+                Ok(response) if !is_replica_refusal(&response) => return Ok(response),
+                Ok(_) | Err(ClientError::Connection(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.send_with_headers(Method::GET, path, Bytes::new(), extra).await
+    }
+
+    /// Replicas in turn, or `None` without any
+    fn next_replica(&self) -> Option<&str> {
+        if self.replicas.is_empty() {
+            return None;
+        }
+        let turn = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        Some(&self.replicas[turn % self.replicas.len()])
+    }
+}
+
+/// Whether a replica declined the read rather than answering it
+fn is_replica_refusal(response: &RawResponse) -> bool {
+    if response.status != StatusCode::SERVICE_UNAVAILABLE {
+        return false;
+    }
+    serde_json::from_slice::<serde_json::Value>(&response.body)
+        .map(|body| body["code"] == "replica_behind")
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replica_headers() {
+        assert!(ReadConsistency::AnyReplica.replica_headers().is_empty());
+        assert_eq!(
+            ReadConsistency::BoundedStaleness(Duration::from_secs(2)).replica_headers(),
+            [(headers::MAX_STALENESS, "2000".to_string())]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Refreshing key packets
+//!
+//! A key packet is valid until its `exp` claim, so a service that runs for
+//! longer than its packet needs a new one before then. A client built with
+//! [`Client::with_credentials_provider`] asks its [`CredentialsProvider`]
+//! for credentials on first use and again once the packet is within the
+//! refresh window of expiring, while requests already signed go on with
+//! the old packet. One request fetches while the others wait, so a
+//! provider isn't called once per request in flight. Should the server
+//! still reject a packet as expired, say because the local clock is
+//! behind, the client fetches fresh credentials and retries the request
+//! once. A failed refresh falls back to the current packet for as long as
+//! it is valid.
+//!
+//! [`LocalIssuer`] mints packets from an issuer key the server trusts;
+//! services that get their packets from elsewhere, such as a token exchange
+//! in front of an identity provider, implement the trait themselves:
+//!
+//! ```ignore
+//! struct Exchange { http: reqwest::Client, holder: SigningKey }
+//!
+//! impl CredentialsProvider for Exchange {
+//!     fn fetch(&self) -> BoxFuture<'_, Result<Credentials>> {
+//!         Box::pin(async move {
+//!             let packet = self.http.post(EXCHANGE_URL).send().await?.text().await?;
+//!             Credentials::new(self.holder.clone(), packet)
+//!         })
+//!     }
+//! }
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+use ed25519_dalek::SigningKey;
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+use wfldb_auth::{decode_packet, now_ms, sign_packet, KeyPacketClaims, Permissions, RequestSigner};
+use crate::{ClientError, Result};
+
+/// A holder key and the key packet granting it permissions
+#[derive(Clone)]
+pub struct Credentials {
+    pub signing_key: SigningKey,
+    pub packet: String,
+    /// When the packet expires, in ms since the Unix epoch
+    pub expires_at_ms: u64,
+}
+
+impl Credentials {
+    /// Credentials for `packet`, reading its expiry from its claims
+    pub fn new(signing_key: SigningKey, packet: String) -> Result<Self> {
+        let (claims, _, _) = decode_packet(&packet).map_err(|e| ClientError::Unauthorized(e.to_string()))?;
+        Ok(Credentials { signing_key, packet, expires_at_ms: claims.expires_at_ms() })
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials").field("expires_at_ms", &self.expires_at_ms).finish_non_exhaustive()
+    }
+}
+
+/// Source of fresh credentials
+pub trait CredentialsProvider: Send + Sync {
+    fn fetch(&self) -> BoxFuture<'_, Result<Credentials>>;
+}
+
+/// Mints packets for one holder key, signed by an issuer the server trusts
+pub struct LocalIssuer {
+    issuer: SigningKey,
+    holder: SigningKey,
+    permissions: Permissions,
+    ttl: Duration,
+}
+
+impl LocalIssuer {
+    pub fn new(issuer: SigningKey, holder: SigningKey, permissions: Permissions, ttl: Duration) -> Self {
+        LocalIssuer { issuer, holder, permissions, ttl }
+    }
+}
+
+impl CredentialsProvider for LocalIssuer {
+    fn fetch(&self) -> BoxFuture<'_, Result<Credentials>> {
+        Box::pin(async move {
+            let claims = KeyPacketClaims::new(
+                &self.holder.verifying_key(),
+                &self.issuer.verifying_key(),
+                self.permissions.clone(),
+                now_ms() / 1000,
+                self.ttl.as_secs(),
+            );
+            let packet = sign_packet(&claims, &self.issuer).map_err(|e| ClientError::Unauthorized(e.to_string()))?;
+            Ok(Credentials { signing_key: self.holder.clone(), packet, expires_at_ms: claims.expires_at_ms() })
+        })
+    }
+}
+
+/// Current credentials of a client, refreshed from its provider
+pub(crate) struct CredentialCache {
+    provider: Arc<dyn CredentialsProvider>,
+    /// Fetch this long before the packet expires
+    refresh_before: Duration,
+    current: Mutex<Option<(u64, Arc<RequestSigner>)>>,
+}
+
+impl CredentialCache {
+    pub(crate) fn new(provider: Arc<dyn CredentialsProvider>, refresh_before: Duration) -> Self {
+        CredentialCache { provider, refresh_before, current: Mutex::new(None) }
+    }
+
+    /// Signer for a request sent at `now_ms`, refreshing the packet first
+    /// if it expires within the refresh window
+    pub(crate) async fn signer(&self, now_ms: u64) -> Result<Arc<RequestSigner>> {
+        let mut current = self.current.lock().await;
+        let fresh_until = now_ms.saturating_add(self.refresh_before.as_millis() as u64);
+        if let Some((expires_at_ms, signer)) = &*current {
+            if *expires_at_ms > fresh_until {
+                return Ok(signer.clone());
+            }
+        }
+        match self.provider.fetch().await {
+            Ok(credentials) => {
+                let signer = Arc::new(RequestSigner::new(credentials.signing_key, credentials.packet));
+                *current = Some((credentials.expires_at_ms, signer.clone()));
+                Ok(signer)
+            }
+            Err(e) => match &*current {
+                Some((expires_at_ms, signer)) if *expires_at_ms > now_ms => {
+                    tracing::warn!(error = %e, "key packet refresh failed; using the current packet until it expires");
+                    Ok(signer.clone())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Drop the current packet after the server rejected it as expired
+    pub(crate) async fn invalidate(&self) {
+        self.current.lock().await.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wfldb_auth::generate_signing_key;
+
+    /// Counts fetches, handing out packets valid for `ttl`
+    struct Counting {
+        issuer: LocalIssuer,
+        fetches: AtomicUsize,
+    }
+
+    impl CredentialsProvider for Counting {
+        fn fetch(&self) -> BoxFuture<'_, Result<Credentials>> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            self.issuer.fetch()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_near_expiry() {
+        let provider = Arc::new(Counting {
+            issuer: LocalIssuer::new(generate_signing_key(), generate_signing_key(), Permissions::default(), Duration::from_secs(600)),
+            fetches: AtomicUsize::new(0),
+        });
+        let cache = CredentialCache::new(provider.clone(), Duration::from_secs(60));
+        let now = now_ms();
+        cache.signer(now).await.unwrap();
+        cache.signer(now + 1_000).await.unwrap();
+        assert_eq!(provider.fetches.load(Ordering::Relaxed), 1);
+
+        // Within a minute of expiring
+        cache.signer(now + 550_000).await.unwrap();
+        assert_eq!(provider.fetches.load(Ordering::Relaxed), 2);
+
+        cache.invalidate().await;
+        cache.signer(now).await.unwrap();
+        assert_eq!(provider.fetches.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_read_expiry() {
+        let credentials = LocalIssuer::new(generate_signing_key(), generate_signing_key(), Permissions::default(), Duration::from_secs(300))
+            .fetch()
+            .await
+            .unwrap();
+        let parsed = Credentials::new(credentials.signing_key.clone(), credentials.packet.clone()).unwrap();
+        assert_eq!(parsed.expires_at_ms, credentials.expires_at_ms);
+    }
+}
@@ -1,5 +1,6 @@
 //! Client error types
 
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,6 +11,9 @@ pub enum ClientError {
     #[error("Request failed: {0}")]
     Request(String),
     
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
     
@@ -19,6 +23,39 @@ pub enum ClientError {
     #[error("Multipart upload error: {0}")]
     MultipartUpload(String),
     
+    /// A key the transaction read changed before it committed; retry
+    #[error("Transaction conflict on key: {0}")]
+    Conflict(String),
+    
+    /// Someone else holds the lease until the given time (ms since the epoch)
+    #[error("Lease held until {0}")]
+    LeaseHeld(u64),
+    
+    /// The lease expired or was taken over before it was renewed or released
+    #[error("Lease lost: {0}")]
+    LeaseLost(String),
+    
+    /// The server marked the failure retryable (overload, full disk, conflict)
+    #[error("{status}: {message}")]
+    Retryable {
+        status: u16,
+        /// Machine-readable error code from the server
+        code: String,
+        message: String,
+        /// How long the server asked the client to wait
+        retry_after: Option<Duration>,
+        /// Id the server logged the request under
+        request_id: Option<String>,
+    },
+    
+    /// The document doesn't match the bucket's JSON Schema
+    #[error("Document does not match the bucket's schema ({} violations)", .0.len())]
+    SchemaViolation(Vec<crate::json::SchemaViolation>),
+    
+    /// A value couldn't be serialized, or a document deserialized
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    
     #[error("Core error: {0}")]
     Core(#[from] wfldb_core::WflDBError),
     
@@ -27,4 +64,27 @@ pub enum ClientError {
     
     #[error("HTTP error: {0}")]
     Http(String),
-}
\ No newline at end of file
+}
+
+impl ClientError {
+    /// Whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ClientError::Retryable { .. } | ClientError::Conflict(_) | ClientError::LeaseHeld(_))
+    }
+
+    /// Wait the server asked for before retrying, if any
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::Retryable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Id the server logged the failed request under, if it said
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            ClientError::Retryable { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+}
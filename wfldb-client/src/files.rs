@@ -0,0 +1,332 @@
+//! File and directory transfers
+//!
+//! [`Client::upload_file`] sends a small file as one PUT and a large one as
+//! a multipart upload whose parts go up several at a time, each read from
+//! the file as it is sent, so memory use stays at the parts in flight.
+//! [`Client::download_file`] writes the response body to disk as it
+//! arrives, through a temporary file beside the target that is renamed into
+//! place once complete. [`Client::upload_dir`] uploads a directory tree,
+//! keyed by each file's path under the directory:
+//!
+//! ```ignore
+//! let options = TransferOptions::default().with_concurrency(8).include("**/*.tar.zst").exclude("tmp/**");
+//! let report = client.upload_dir("backups", &bucket, "nightly/", &options).await?;
+//! println!("{} files, {} bytes", report.files, report.bytes);
+//! ```
+
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use http_body_util::BodyExt;
+use hyper::{Method, StatusCode};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use wfldb_core::*;
+use crate::client::{object_path, status_error};
+use crate::{Client, ClientError, Result};
+
+/// Largest part the server accepts
+pub const MAX_PART_SIZE: usize = 64 * 1024 * 1024;
+
+/// How files are transferred
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    /// Files larger than this go up as multipart uploads
+    pub multipart_threshold: u64,
+    /// Bytes per multipart part, at most [`MAX_PART_SIZE`]
+    pub part_size: usize,
+    /// Parts of one file, or files of a directory, in flight at once
+    pub concurrency: usize,
+    /// Globs a directory's files must match one of to be uploaded; all
+    /// files when empty
+    pub include: Vec<String>,
+    /// Globs of files a directory upload skips, even if included
+    pub exclude: Vec<String>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            multipart_threshold: 16 * 1024 * 1024,
+            part_size: 16 * 1024 * 1024,
+            concurrency: 4,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl TransferOptions {
+    pub fn with_multipart_threshold(mut self, threshold: u64) -> Self {
+        self.multipart_threshold = threshold;
+        self
+    }
+
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.clamp(1, MAX_PART_SIZE);
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Upload only files matching `glob`, or one of the other includes;
+    /// `*` and `?` stay within a path segment and `**` spans segments
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Skip files matching `glob`
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Whether a file at `path`, relative to the uploaded directory with
+    /// `/` separators, is uploaded
+    fn selects(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob_match(glob, path)))
+            && !self.exclude.iter().any(|glob| glob_match(glob, path))
+    }
+}
+
+/// Outcome of a directory upload
+#[derive(Debug, Clone, Default)]
+pub struct DirUploadReport {
+    /// Files uploaded
+    pub files: usize,
+    pub bytes: u64,
+    /// Files the include and exclude globs left out
+    pub skipped: usize,
+}
+
+impl Client {
+    /// Store the file at `path` as `key`
+    pub async fn upload_file(&self, path: impl AsRef<Path>, bucket: &BucketId, key: &Key) -> Result<ObjectMetadata> {
+        self.upload_file_with(path, bucket, key, &TransferOptions::default()).await
+    }
+
+    /// [`upload_file`](Self::upload_file) with custom part sizes and concurrency
+    pub async fn upload_file_with(
+        &self,
+        path: impl AsRef<Path>,
+        bucket: &BucketId,
+        key: &Key,
+        options: &TransferOptions,
+    ) -> Result<ObjectMetadata> {
+        let path = path.as_ref();
+        let size = tokio::fs::metadata(path).await?.len();
+        if size <= options.multipart_threshold {
+            let data = tokio::fs::read(path).await?;
+            if data.len() as u64 != size {
+                return Err(changed(path));
+            }
+            return self.put(bucket, key, &data).await;
+        }
+
+        let part_size = options.part_size.clamp(1, MAX_PART_SIZE) as u64;
+        let mut upload = self.start_multipart_upload(bucket, key).await?;
+        let sent = {
+            let upload = &upload;
+            stream::iter((0..size.div_ceil(part_size)).map(|index| async move {
+                let offset = index * part_size;
+                let data = read_part(path, offset, part_size.min(size - offset) as usize).await?;
+                upload.send_part(index as u32 + 1, data).await
+            }))
+            .buffer_unordered(options.concurrency.max(1))
+            .try_collect::<Vec<_>>()
+            .await
+        };
+        match sent {
+            Ok(parts) => {
+                for part in parts {
+                    upload.record_part(part);
+                }
+                upload.complete().await
+            }
+            Err(e) => {
+                // Don't leave the sent parts on the server until the upload expires
+                let _ = upload.abort().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Write object `key` to the file at `path`, returning its size, or
+    /// `None` without touching `path` if the object doesn't exist
+    pub async fn download_file(&self, bucket: &BucketId, key: &Key, path: impl AsRef<Path>) -> Result<Option<u64>> {
+        let path = path.as_ref();
+        let response = match self.open(Method::GET, &object_path(bucket, key), Bytes::new(), &[]).await? {
+            Ok(response) => response,
+            Err(response) if response.status == StatusCode::NOT_FOUND => return Ok(None),
+            Err(response) => return Err(status_error(&response)),
+        };
+        let partial = partial_path(path);
+        let mut file = File::create(&partial).await?;
+        let mut body = response.into_body();
+        let mut written = 0u64;
+        let copied: Result<()> = async {
+            while let Some(frame) = body.frame().await {
+                let frame = frame.map_err(|e| ClientError::Stream(e.to_string()))?;
+                if let Some(data) = frame.data_ref() {
+                    file.write_all(data).await?;
+                    written += data.len() as u64;
+                }
+            }
+            file.sync_all().await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = copied {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&partial, path).await?;
+        Ok(Some(written))
+    }
+
+    /// Upload every file under `dir` the options select, as `prefix`
+    /// followed by the file's path under `dir` with `/` separators
+    pub async fn upload_dir(
+        &self,
+        dir: impl AsRef<Path>,
+        bucket: &BucketId,
+        prefix: &str,
+        options: &TransferOptions,
+    ) -> Result<DirUploadReport> {
+        let files = walk_dir(dir.as_ref()).await?;
+        let mut report = DirUploadReport::default();
+        let mut selected = Vec::new();
+        for (path, relative) in files {
+            if options.selects(&relative) {
+                selected.push((path, Key::new(&format!("{}{}", prefix, relative))?));
+            } else {
+                report.skipped += 1;
+            }
+        }
+        // Files already run concurrently; their parts go one at a time
+        let per_file = TransferOptions { concurrency: 1, ..options.clone() };
+        let per_file = &per_file;
+        let sizes: Vec<u64> = stream::iter(selected)
+            .map(|(path, key)| async move {
+                let metadata = self.upload_file_with(&path, bucket, &key, per_file).await?;
+                Ok::<_, ClientError>(metadata.size)
+            })
+            .buffer_unordered(options.concurrency.max(1))
+            .try_collect()
+            .await?;
+        report.files = sizes.len();
+        report.bytes = sizes.iter().sum();
+        Ok(report)
+    }
+}
+
+/// Read `len` bytes of the file at `path` from `offset`
+async fn read_part(path: &Path, offset: u64, len: usize) -> Result<Bytes> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = vec![0; len];
+    file.read_exact(&mut buf).await.map_err(|_| changed(path))?;
+    Ok(Bytes::from(buf))
+}
+
+fn changed(path: &Path) -> ClientError {
+    ClientError::Request(format!("{} changed while uploading", path.display()))
+}
+
+/// Where a download is written until it is complete
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".wfldb-partial");
+    path.with_file_name(name)
+}
+
+/// Regular files under `dir`, with their paths relative to it, in order
+async fn walk_dir(dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, relative)) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().into_string().map_err(|name| {
+                ClientError::Request(format!("non-UTF-8 file name: {}", name.to_string_lossy()))
+            })?;
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((entry.path(), format!("{}{}/", relative, name)));
+            } else if file_type.is_file() {
+                files.push((entry.path(), format!("{}{}", relative, name)));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+/// Match `path` against `glob`: `?` is one character and `*` any run of
+/// characters within a segment, `**` any run of whole segments
+fn glob_match(glob: &str, path: &str) -> bool {
+    fn matches(glob: &[u8], path: &[u8]) -> bool {
+        match glob {
+            [] => path.is_empty(),
+            [b'*', b'*', b'/', rest @ ..] => {
+                // Zero segments, or one more and try again
+                matches(rest, path)
+                    || path.iter().position(|&c| c == b'/').is_some_and(|slash| matches(glob, &path[slash + 1..]))
+            }
+            [b'*', b'*'] => true,
+            [b'*', rest @ ..] => {
+                let segment = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+                (0..=segment).any(|skip| matches(rest, &path[skip..]))
+            }
+            [b'?', rest @ ..] => matches!(path, [c, tail @ ..] if *c != b'/' && matches(rest, tail)),
+            [c, rest @ ..] => matches!(path, [p, tail @ ..] if p == c && matches(rest, tail)),
+        }
+    }
+    matches(glob.as_bytes(), path.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "a.txt"));
+        assert!(!glob_match("*.txt", "dir/a.txt"));
+        assert!(glob_match("**/*.txt", "a.txt"));
+        assert!(glob_match("**/*.txt", "dir/sub/a.txt"));
+        assert!(glob_match("dir/**", "dir/sub/a.txt"));
+        assert!(!glob_match("dir/**", "other/a.txt"));
+        assert!(glob_match("log-?.gz", "log-1.gz"));
+        assert!(!glob_match("log-?.gz", "log-10.gz"));
+        assert!(glob_match("a/**/z", "a/z"));
+        assert!(glob_match("a/**/z", "a/b/c/z"));
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let options = TransferOptions::default().include("**/*.rs").exclude("target/**");
+        assert!(options.selects("src/lib.rs"));
+        assert!(!options.selects("target/debug/build.rs"));
+        assert!(!options.selects("README.md"));
+        assert!(TransferOptions::default().selects("anything/at/all"));
+    }
+
+    #[tokio::test]
+    async fn test_walk_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("b/c")).unwrap();
+        for file in ["a.txt", "b/c/d.txt", "b/e.txt"] {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        let files = walk_dir(dir.path()).await.unwrap();
+        let relative: Vec<&str> = files.iter().map(|(_, relative)| relative.as_str()).collect();
+        assert_eq!(relative, ["a.txt", "b/c/d.txt", "b/e.txt"]);
+        assert_eq!(std::fs::read_to_string(&files[1].0).unwrap(), "b/c/d.txt");
+    }
+}
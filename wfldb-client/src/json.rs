@@ -0,0 +1,113 @@
+//! Typed JSON documents
+//!
+//! [`Client::put_json`] stores any `Serialize` value as an
+//! `application/json` object, so a bucket with a JSON Schema attached
+//! checks it; a document the schema rejects fails with
+//! [`ClientError::SchemaViolation`] listing where and why.
+//! [`Client::get_json`] reads one back into any `DeserializeOwned` type.
+//! A reader that keeps a document around revalidates it with
+//! [`Client::get_json_if_changed`], which sends the ETag it holds and only
+//! downloads the document again if it changed:
+//!
+//! ```ignore
+//! let mut settings = client.get_json_document::<Settings>(&bucket, &key).await?.unwrap();
+//! loop {
+//!     sleep(Duration::from_secs(30)).await;
+//!     match client.get_json_if_changed(&bucket, &key, Some(&settings.etag)).await? {
+//!         JsonFetch::Unchanged => {}
+//!         JsonFetch::Changed(newer) => settings = newer,
+//!         JsonFetch::Missing => break,
+//!     }
+//! }
+//! ```
+
+use hyper::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use wfldb_core::*;
+use crate::client::{object_path, status_error, RawResponse};
+use crate::{Client, ClientError, Result};
+
+/// A document read with the ETag of the version it came from
+#[derive(Debug, Clone)]
+pub struct JsonDocument<T> {
+    pub value: T,
+    /// Pass to [`Client::get_json_if_changed`] to revalidate
+    pub etag: String,
+}
+
+/// Outcome of a conditional document read
+#[derive(Debug, Clone)]
+pub enum JsonFetch<T> {
+    /// The document is still at the given ETag
+    Unchanged,
+    Changed(JsonDocument<T>),
+    /// The document was deleted, or never existed
+    Missing,
+}
+
+/// One way a document fails the bucket's schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    /// JSON Pointer into the document
+    pub path: String,
+    /// JSON Pointer into the schema
+    pub schema_path: String,
+    pub message: String,
+}
+
+impl Client {
+    /// Store `value` as a JSON document
+    pub async fn put_json<T: Serialize + ?Sized>(&self, bucket: &BucketId, key: &Key, value: &T) -> Result<ObjectMetadata> {
+        let data = serde_json::to_vec(value)?;
+        let extra = [(CONTENT_TYPE.as_str(), "application/json".to_string())];
+        self.put_with_headers(bucket, key, &data, &extra).await
+    }
+
+    /// Read a JSON document, or `None` if it doesn't exist
+    pub async fn get_json<T: DeserializeOwned>(&self, bucket: &BucketId, key: &Key) -> Result<Option<T>> {
+        Ok(self.get_json_document(bucket, key).await?.map(|document| document.value))
+    }
+
+    /// Read a JSON document with its ETag
+    pub async fn get_json_document<T: DeserializeOwned>(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+    ) -> Result<Option<JsonDocument<T>>> {
+        match self.get_json_if_changed(bucket, key, None).await? {
+            JsonFetch::Changed(document) => Ok(Some(document)),
+            JsonFetch::Missing => Ok(None),
+            JsonFetch::Unchanged => Err(ClientError::InvalidResponse("not modified without an ETag".to_string())),
+        }
+    }
+
+    /// Read a JSON document unless it is still at `etag`
+    pub async fn get_json_if_changed<T: DeserializeOwned>(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        etag: Option<&str>,
+    ) -> Result<JsonFetch<T>> {
+        let extra: Vec<(&str, String)> = etag.map(|etag| (IF_NONE_MATCH.as_str(), etag.to_string())).into_iter().collect();
+        let response = self.send_read(&object_path(bucket, key), self.read_consistency, &extra).await?;
+        match response.status {
+            StatusCode::NOT_MODIFIED => Ok(JsonFetch::Unchanged),
+            StatusCode::NOT_FOUND => Ok(JsonFetch::Missing),
+            status if status.is_success() => Ok(JsonFetch::Changed(JsonDocument {
+                value: serde_json::from_slice(&response.body)?,
+                etag: response_etag(&response)?,
+            })),
+            _ => Err(status_error(&response)),
+        }
+    }
+}
+
+fn response_etag(response: &RawResponse) -> Result<String> {
+    response.headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| ClientError::InvalidResponse("missing ETag".to_string()))
+}
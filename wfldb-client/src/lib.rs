@@ -3,14 +3,44 @@
 use std::sync::Arc;
 use wfldb_core::*;
 
+pub mod batch;
+pub mod cache;
 pub mod client;
+pub mod coalesce;
+pub mod consistency;
+pub mod credentials;
 pub mod error;
+pub mod files;
+pub mod json;
+pub mod listing;
+pub mod metrics;
+pub mod migrate;
 pub mod multipart;
+pub mod ranged;
 pub mod streaming;
+pub mod sync;
+pub mod txn;
+pub mod watch;
 
-pub use client::Client;
+#[cfg(test)]
+mod test_server;
+
+pub use cache::DiskCache;
+pub use client::{BucketInfo, Client, JournalPage, ListPage};
+pub use coalesce::{CoalesceOptions, WriteCoalescer};
+pub use consistency::ReadConsistency;
+pub use credentials::{Credentials, CredentialsProvider, LocalIssuer};
 pub use error::ClientError;
+pub use files::{DirUploadReport, TransferOptions};
+pub use json::{JsonDocument, JsonFetch, SchemaViolation};
+pub use listing::{ListEntry, ListOptions};
+pub use metrics::{MetricsRecorder, RequestMetrics};
+pub use migrate::{BucketMigration, MigrationReport};
 pub use multipart::MultipartUpload;
+pub use ranged::{RangedDownload, RangedReport};
 pub use streaming::{StreamingGet, StreamingPut};
+pub use sync::{Chunker, SyncReport};
+pub use txn::Transaction;
+pub use watch::{ChangeEvent, ChangeKind};
 
 pub type Result<T> = std::result::Result<T, ClientError>;
\ No newline at end of file
@@ -0,0 +1,215 @@
+//! Streamed bucket listings
+//!
+//! [`Client::list_objects`] pages through a bucket as the stream is polled,
+//! following each page's `next_start_after` cursor until the listing ends:
+//!
+//! ```ignore
+//! let options = ListOptions::prefix("logs/").with_delimiter("/").with_metadata();
+//! let mut entries = pin!(client.list_objects(&bucket, options));
+//! while let Some(entry) = entries.try_next().await? {
+//!     match entry {
+//!         ListEntry::Object { key, metadata } => println!("{} {:?}", key.as_str(), metadata.map(|m| m.size)),
+//!         ListEntry::CommonPrefix(prefix) => println!("{}", prefix),
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+use bytes::Bytes;
+use futures::stream::{self, Stream, TryStreamExt};
+use hyper::Method;
+use serde_json::Value;
+use wfldb_core::*;
+use wfldb_net::encode_query_component;
+use crate::client::status_error;
+use crate::{Client, ClientError, Result};
+
+/// What [`Client::list_objects`] lists
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub prefix: String,
+    /// Roll keys holding this after the prefix up into their common prefix
+    pub delimiter: Option<String>,
+    /// Entries asked for per request; the server's maximum when unset
+    pub page_size: Option<usize>,
+    /// Fetch each key's metadata with it
+    pub metadata: bool,
+}
+
+impl ListOptions {
+    /// List the keys starting with `prefix`
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        ListOptions { prefix: prefix.into(), ..Default::default() }
+    }
+
+    pub fn with_delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.delimiter = Some(delimiter.into());
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    pub fn with_metadata(mut self) -> Self {
+        self.metadata = true;
+        self
+    }
+}
+
+/// An entry of a listing, in key order
+#[derive(Debug, Clone)]
+pub enum ListEntry {
+    /// A key, with its metadata when the listing asked for it
+    Object { key: Key, metadata: Option<Box<ObjectMetadata>> },
+    /// Keys rolled up under a prefix ending in the delimiter
+    CommonPrefix(String),
+}
+
+impl ListEntry {
+    fn sort_key(&self) -> &str {
+        match self {
+            ListEntry::Object { key, .. } => key.as_str(),
+            ListEntry::CommonPrefix(prefix) => prefix,
+        }
+    }
+}
+
+impl Client {
+    /// Stream a bucket's keys, fetching pages as the stream is polled
+    pub fn list_objects<'a>(
+        &'a self,
+        bucket: &'a BucketId,
+        options: ListOptions,
+    ) -> impl Stream<Item = Result<ListEntry>> + 'a {
+        // `None` once the last page has been fetched
+        stream::try_unfold(Some(None), move |cursor: Option<Option<String>>| {
+            let options = options.clone();
+            async move {
+                let Some(start_after) = cursor else {
+                    return Ok::<_, ClientError>(None);
+                };
+                let (entries, next) = self.list_entries(bucket, &options, start_after.as_deref()).await?;
+                Ok(Some((stream::iter(entries.into_iter().map(Ok::<_, ClientError>)), next.map(Some))))
+            }
+        })
+        .try_flatten()
+    }
+
+    async fn list_entries(
+        &self,
+        bucket: &BucketId,
+        options: &ListOptions,
+        start_after: Option<&str>,
+    ) -> Result<(Vec<ListEntry>, Option<String>)> {
+        let mut path = format!("/v1/{}?prefix={}", bucket.as_str(), encode_query_component(options.prefix.as_bytes()));
+        if let Some(start_after) = start_after {
+            path.push_str(&format!("&start_after={}", encode_query_component(start_after.as_bytes())));
+        }
+        if let Some(delimiter) = &options.delimiter {
+            path.push_str(&format!("&delimiter={}", encode_query_component(delimiter.as_bytes())));
+        }
+        if let Some(page_size) = options.page_size {
+            path.push_str(&format!("&limit={}", page_size));
+        }
+        if options.metadata {
+            path.push_str("&metadata=true");
+        }
+        let response = self.send(Method::GET, &path, Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        let body: Value = serde_json::from_slice(&response.body).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        parse_listing(&body, options.metadata)
+    }
+}
+
+/// Entries of a listing page, keys and common prefixes merged in order,
+/// and the cursor of the next page
+fn parse_listing(body: &Value, with_metadata: bool) -> Result<(Vec<ListEntry>, Option<String>)> {
+    let strings = |field: &str| -> Result<Vec<String>> {
+        match &body[field] {
+            Value::Null => Ok(Vec::new()),
+            Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| ClientError::InvalidResponse(format!("non-string entry in {}", field))),
+            _ => Err(ClientError::InvalidResponse(format!("malformed {}", field))),
+        }
+    };
+    let mut metadata = HashMap::new();
+    if with_metadata {
+        for object in body["objects"].as_array().into_iter().flatten() {
+            if let Some(key) = object["key"].as_str() {
+                metadata.insert(key.to_string(), Box::new(listed_metadata(object)?));
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    for key in strings("keys")? {
+        let object_metadata = metadata.remove(&key);
+        // Deleted between being listed and its metadata being read
+        if with_metadata && object_metadata.is_none() {
+            continue;
+        }
+        entries.push(ListEntry::Object { key: Key::new(&key)?, metadata: object_metadata });
+    }
+    entries.extend(strings("common_prefixes")?.into_iter().map(ListEntry::CommonPrefix));
+    entries.sort_by(|a, b| a.sort_key().cmp(b.sort_key()));
+    Ok((entries, body["next_start_after"].as_str().map(str::to_string)))
+}
+
+/// Metadata of a listed key; only what a listing reports is filled in
+fn listed_metadata(object: &Value) -> Result<ObjectMetadata> {
+    let version = object["version"]
+        .as_str()
+        .and_then(|v| ulid::Ulid::from_string(v).ok())
+        .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+    let content_hash = object["content_hash"].as_str().map(ContentHash::from_hex).transpose()?;
+    Ok(ObjectMetadata {
+        size: object["size"].as_u64().unwrap_or_default(),
+        version: Version::from_ulid(version),
+        content_hash,
+        created_at: UNIX_EPOCH + Duration::from_millis(object["created_at_ms"].as_u64().unwrap_or_default()),
+        chunk_manifest: None,
+        owner: None,
+        checksums: Default::default(),
+        generation: object["generation"].as_u64().unwrap_or_default(),
+        customer_key_hash: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_listing() {
+        let version = Version::new().to_string();
+        let body = json!({
+            "keys": ["a.txt", "c.txt", "gone.txt"],
+            "common_prefixes": ["b/"],
+            "objects": [
+                {"key": "a.txt", "size": 3, "version": version, "generation": 2, "created_at_ms": 1_000},
+                {"key": "c.txt", "size": 5, "version": version, "generation": 1, "created_at_ms": 2_000},
+            ],
+            "next_start_after": "c.txt",
+        });
+        let (entries, next) = parse_listing(&body, true).unwrap();
+        let order: Vec<_> = entries.iter().map(ListEntry::sort_key).collect();
+        assert_eq!(order, ["a.txt", "b/", "c.txt"]);
+        let ListEntry::Object { metadata: Some(metadata), .. } = &entries[0] else {
+            panic!("expected metadata for a.txt");
+        };
+        assert_eq!((metadata.size, metadata.generation), (3, 2));
+        assert_eq!(next.as_deref(), Some("c.txt"));
+
+        let (entries, next) = parse_listing(&json!({"keys": ["a.txt"], "next_start_after": null}), false).unwrap();
+        assert!(matches!(&entries[..], [ListEntry::Object { metadata: None, .. }]));
+        assert_eq!(next, None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Request metrics and tracing
+//!
+//! Every request the client sends runs in a `wfldb.request` tracing span
+//! carrying its method, path and request id, the same id the server logs
+//! it under, and the status and retry count once it finishes. An
+//! application that wants numbers rather than spans registers a
+//! [`MetricsRecorder`] with [`Client::with_metrics_recorder`]; it is handed
+//! a [`RequestMetrics`] per request, after any retry:
+//!
+//! ```ignore
+//! struct Prometheus { latency: HistogramVec }
+//!
+//! impl MetricsRecorder for Prometheus {
+//!     fn record(&self, metrics: &RequestMetrics) {
+//!         self.latency
+//!             .with_label_values(&[&metrics.operation(), metrics.status_class()])
+//!             .observe(metrics.latency.as_secs_f64());
+//!     }
+//! }
+//!
+//! let client = Client::new(url)?.with_metrics_recorder(Arc::new(Prometheus::new()));
+//! ```
+
+use std::time::Duration;
+use hyper::{Method, StatusCode};
+
+/// What one request did
+#[derive(Debug, Clone)]
+pub struct RequestMetrics {
+    pub method: Method,
+    /// Path and query, with the bucket and key in it
+    pub path: String,
+    /// Id sent with every attempt of the request
+    pub request_id: String,
+    /// Status of the last attempt; `None` if no response arrived
+    pub status: Option<StatusCode>,
+    /// From the first attempt being sent to the last response being read
+    pub latency: Duration,
+    /// Attempts after the first
+    pub retries: u32,
+    /// Request body bytes, counting every attempt
+    pub bytes_sent: u64,
+    /// Response body bytes read; a streamed body isn't counted
+    pub bytes_received: u64,
+}
+
+impl RequestMetrics {
+    /// Low-cardinality name for the request, e.g. `GET object` or
+    /// `PUT _uploads`: the method and the route, without bucket or key
+    pub fn operation(&self) -> String {
+        let path = self.path.split('?').next().unwrap_or_default();
+        let mut segments = path.trim_start_matches('/').split('/');
+        let route = match (segments.next(), segments.next(), segments.next()) {
+            (Some("v1"), None | Some(""), _) => "buckets",
+            (Some("v1"), Some(_), None) => "list",
+            (Some("v1"), Some(_), Some(route)) if route.starts_with('_') => route,
+            (Some("v1"), Some(_), Some(_)) => "object",
+            (Some(root), _, _) => root,
+            (None, _, _) => "",
+        };
+        format!("{} {}", self.method, route)
+    }
+
+    /// `2xx` to `5xx`, or `error` when no response arrived
+    pub fn status_class(&self) -> &'static str {
+        match self.status.map(|status| status.as_u16() / 100) {
+            Some(1) => "1xx",
+            Some(2) => "2xx",
+            Some(3) => "3xx",
+            Some(4) => "4xx",
+            Some(5) => "5xx",
+            _ => "error",
+        }
+    }
+}
+
+/// Receives the metrics of every request a client sends
+///
+/// Called on the task that sent the request, so it should only record,
+/// not block.
+pub trait MetricsRecorder: Send + Sync {
+    fn record(&self, metrics: &RequestMetrics);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(method: Method, path: &str) -> RequestMetrics {
+        RequestMetrics {
+            method,
+            path: path.to_string(),
+            request_id: String::new(),
+            status: None,
+            latency: Duration::ZERO,
+            retries: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    #[test]
+    fn test_operation() {
+        assert_eq!(metrics(Method::GET, "/v1").operation(), "GET buckets");
+        assert_eq!(metrics(Method::GET, "/v1/photos?prefix=2024/").operation(), "GET list");
+        assert_eq!(metrics(Method::PUT, "/v1/photos/2024/cat.jpg").operation(), "PUT object");
+        assert_eq!(metrics(Method::PUT, "/v1/photos/_uploads/big.bin?upload_id=u&part=3").operation(), "PUT _uploads");
+        assert_eq!(metrics(Method::GET, "/admin/journal?after=0").operation(), "GET admin");
+        assert_eq!(metrics(Method::GET, "/v1").status_class(), "error");
+    }
+}
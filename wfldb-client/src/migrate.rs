@@ -0,0 +1,169 @@
+//! Bucket migration between nodes
+//!
+//! A migration records the source node's journal position, copies every
+//! object in the bucket to the target, then replays the journal from that
+//! position so writes made during the copy reach the target too. Replaying
+//! repeats until the backlog is drained. With cutover enabled, the source
+//! bucket is then frozen and one last catch-up runs, after which clients can
+//! be pointed at the target with nothing lost.
+//!
+//! The source client must hold an admin key: the journal and freeze routes
+//! live under `/admin/`. The source node must run with its change journal
+//! enabled.
+
+use std::time::Duration;
+use wfldb_core::*;
+use crate::{Client, Result};
+
+/// Objects listed, and change records read, per request
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+
+/// Wait after freezing before the final catch-up, so writes that passed the
+/// freeze check just before it took effect have reached the journal
+const CUTOVER_SETTLE: Duration = Duration::from_secs(1);
+
+/// Outcome of a migration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub objects_copied: u64,
+    pub changes_replayed: u64,
+    /// Source journal sequence the target is caught up to
+    pub journal_seq: u64,
+    /// Whether the source bucket was left frozen for cutover
+    pub cut_over: bool,
+}
+
+/// Streams one bucket from a source node to a target node
+pub struct BucketMigration<'a> {
+    source: &'a Client,
+    target: &'a Client,
+    bucket: BucketId,
+    page_size: usize,
+    cutover: bool,
+}
+
+impl<'a> BucketMigration<'a> {
+    pub fn new(source: &'a Client, target: &'a Client, bucket: BucketId) -> Self {
+        BucketMigration {
+            source,
+            target,
+            bucket,
+            page_size: DEFAULT_PAGE_SIZE,
+            cutover: false,
+        }
+    }
+
+    /// Objects listed, and change records read, per request
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Freeze the source bucket once caught up and drain the last changes
+    pub fn with_cutover(mut self, cutover: bool) -> Self {
+        self.cutover = cutover;
+        self
+    }
+
+    /// Copy the bucket, catch up on changes made meanwhile, and optionally cut over
+    pub async fn run(&self) -> Result<MigrationReport> {
+        let mut report = MigrationReport {
+            journal_seq: self.source.journal_head().await?,
+            ..Default::default()
+        };
+        self.copy_objects(&mut report).await?;
+        self.catch_up(&mut report).await?;
+
+        if self.cutover {
+            self.source.set_bucket_frozen(&self.bucket, true).await?;
+            tokio::time::sleep(CUTOVER_SETTLE).await;
+            if let Err(e) = self.catch_up(&mut report).await {
+                // Give writes back to the source rather than leave the bucket unwritable
+                let _ = self.source.set_bucket_frozen(&self.bucket, false).await;
+                return Err(e);
+            }
+            report.cut_over = true;
+        }
+        Ok(report)
+    }
+
+    async fn copy_objects(&self, report: &mut MigrationReport) -> Result<()> {
+        let mut start_after = None;
+        loop {
+            let page = self
+                .source
+                .list_page(&self.bucket, "", start_after.as_deref(), Some(self.page_size))
+                .await?;
+            for key in &page.keys {
+                // Deleted since listing; the journal replay covers it
+                if let Some(data) = self.source.get(&self.bucket, key).await? {
+                    self.target.put(&self.bucket, key, &data).await?;
+                    report.objects_copied += 1;
+                }
+            }
+            match page.next_start_after {
+                Some(next) => start_after = Some(next),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Apply journal records until the source has nothing newer
+    async fn catch_up(&self, report: &mut MigrationReport) -> Result<()> {
+        loop {
+            let page = self
+                .source
+                .read_journal(report.journal_seq, Some(&self.bucket), self.page_size)
+                .await?;
+            if page.records.is_empty() && page.next == report.journal_seq {
+                return Ok(());
+            }
+            for record in &page.records {
+                self.apply(record).await?;
+                report.changes_replayed += 1;
+            }
+            report.journal_seq = page.next;
+        }
+    }
+
+    async fn apply(&self, record: &ChangeRecord) -> Result<()> {
+        let key = Key::new(&record.key)?;
+        match &record.op {
+            ChangeOp::Put { data, .. } => {
+                self.target.put(&self.bucket, &key, data).await?;
+            }
+            ChangeOp::Delete => self.target.delete(&self.bucket, &key).await?,
+            // Clones share chunks the target doesn't have, so fetch the bytes
+            ChangeOp::Copy { .. } => self.copy_from_source(&key).await?,
+            ChangeOp::WriteRange { offset, data, .. } => {
+                self.target.write_range(&self.bucket, &key, *offset, data).await?;
+            }
+            // The record's key is the chunk's hash, not an object
+            ChangeOp::Chunk { data } => {
+                self.target.put_chunk(&self.bucket, data).await?;
+            }
+            // Chunks of objects copied before the replay may be cut
+            // differently on the target; without all of them, fetch the bytes
+            ChangeOp::Manifest { chunks, .. } => {
+                if self.target.missing_chunks(&self.bucket, chunks).await?.is_empty() {
+                    self.target.commit_manifest(&self.bucket, &key, chunks).await?;
+                } else {
+                    self.copy_from_source(&key).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy what `key` holds on the source now; a newer value is fine, as
+    /// later records replay over it
+    async fn copy_from_source(&self, key: &Key) -> Result<()> {
+        match self.source.get(&self.bucket, key).await? {
+            Some(data) => {
+                self.target.put(&self.bucket, key, &data).await?;
+            }
+            None => self.target.delete(&self.bucket, key).await?,
+        }
+        Ok(())
+    }
+}
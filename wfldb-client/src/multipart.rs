@@ -1,55 +1,266 @@
 //! Multipart upload support
+//!
+//! Parts are numbered from 1 and may be sent in any order, and again:
+//! re-sending part N replaces it, so a failed part is simply retried. A
+//! client that crashed mid-upload resumes with
+//! [`Client::resume_multipart_upload`], which fetches the parts the server
+//! already holds, and sends only the rest.
+//!
+//! ```ignore
+//! let mut upload = client.resume_multipart_upload(&bucket, &key, &saved_id).await?;
+//! for (number, part) in file_parts.enumerate().map(|(i, p)| (i as u32 + 1, p)) {
+//!     if !upload.has_part(number, &part) {
+//!         upload.upload_part(number, &part).await?;
+//!     }
+//! }
+//! upload.complete().await?;
+//! ```
 
-use std::sync::Arc;
+use bytes::Bytes;
+use hyper::{Method, StatusCode};
 use wfldb_core::*;
-use crate::{Result, ClientError};
+use crate::client::{status_error, written_metadata, RawResponse};
+use crate::{Client, ClientError, Result};
 
 /// Multipart upload session
-pub struct MultipartUpload {
+pub struct MultipartUpload<'a> {
+    client: &'a Client,
     upload_id: String,
     bucket: BucketId,
     key: Key,
     parts: Vec<PartInfo>,
 }
 
-#[derive(Clone, Debug)]
-struct PartInfo {
-    part_number: u32,
-    size: u64,
-    hash: ContentHash,
-}
-
-impl MultipartUpload {
-    /// Create new multipart upload
-    pub fn new(upload_id: String, bucket: BucketId, key: Key) -> Self {
+impl<'a> MultipartUpload<'a> {
+    pub(crate) fn new(client: &'a Client, upload_id: String, bucket: BucketId, key: Key) -> Self {
         MultipartUpload {
+            client,
             upload_id,
             bucket,
             key,
             parts: Vec::new(),
         }
     }
-    
-    /// Upload a part
+
+    fn path(&self) -> String {
+        format!(
+            "/v1/{}/_uploads/{}?upload_id={}",
+            self.bucket.as_str(),
+            self.key.to_url(),
+            self.upload_id
+        )
+    }
+
+    /// Upload a part, replacing any earlier upload of the same number
     pub async fn upload_part(&mut self, part_number: u32, data: &[u8]) -> Result<()> {
-        // Placeholder implementation
-        todo!("Implement part upload")
+        let part = self.send_part(part_number, Bytes::copy_from_slice(data)).await?;
+        self.record_part(part);
+        Ok(())
+    }
+
+    /// Send a part without recording it, so several can be in flight at once
+    pub(crate) async fn send_part(&self, part_number: u32, data: Bytes) -> Result<PartInfo> {
+        let path = format!("{}&part={}", self.path(), part_number);
+        let part = PartInfo {
+            part_number,
+            size: data.len() as u64,
+            content_hash: ContentHash::new(&data),
+            chunks: Vec::new(),
+        };
+        let response = self.client.send(Method::PUT, &path, data).await?;
+        upload_response(&response)?;
+        Ok(part)
+    }
+
+    pub(crate) fn record_part(&mut self, part: PartInfo) {
+        self.parts.retain(|known| known.part_number != part.part_number);
+        self.parts.push(part);
+        self.parts.sort_by_key(|part| part.part_number);
+    }
+
+    /// Parts the server holds, refreshing the session's view of them
+    pub async fn list_parts(&mut self) -> Result<&[PartInfo]> {
+        let response = self.client.send(Method::GET, &self.path(), Bytes::new()).await?;
+        let body = upload_response(&response)?;
+        let parts = body["parts"]
+            .as_array()
+            .ok_or_else(|| ClientError::InvalidResponse("missing part list".to_string()))?;
+        self.parts = parts
+            .iter()
+            .map(|part| {
+                let hash = part["hash"].as_str().and_then(|hash| ContentHash::from_hex(hash).ok());
+                match (part["part"].as_u64(), part["size"].as_u64(), hash) {
+                    (Some(part_number), Some(size), Some(content_hash)) => Ok(PartInfo {
+                        part_number: part_number as u32,
+                        size,
+                        content_hash,
+                        chunks: Vec::new(),
+                    }),
+                    _ => Err(ClientError::InvalidResponse(format!("invalid part: {}", part))),
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(&self.parts)
+    }
+
+    /// Parts known to be stored, as of the last upload or listing
+    pub fn parts(&self) -> &[PartInfo] {
+        &self.parts
+    }
+
+    /// Whether part `part_number` is stored with exactly `data`
+    pub fn has_part(&self, part_number: u32, data: &[u8]) -> bool {
+        self.parts
+            .iter()
+            .any(|part| part.part_number == part_number && part.content_hash == ContentHash::new(data))
     }
-    
+
     /// Complete the multipart upload
     pub async fn complete(self) -> Result<ObjectMetadata> {
-        // Placeholder implementation
-        todo!("Implement multipart completion")
+        let response = self.client.send(Method::POST, &self.path(), Bytes::new()).await?;
+        upload_response(&response)?;
+        written_metadata(&response)
     }
-    
+
     /// Abort the multipart upload
     pub async fn abort(self) -> Result<()> {
-        // Placeholder implementation
-        todo!("Implement multipart abort")
+        let response = self.client.send(Method::DELETE, &self.path(), Bytes::new()).await?;
+        upload_response(&response).map(|_| ())
     }
-    
+
     /// Get upload ID
     pub fn upload_id(&self) -> &str {
         &self.upload_id
     }
-}
\ No newline at end of file
+}
+
+impl Client {
+    /// Start multipart upload
+    pub async fn start_multipart_upload(&self, bucket: &BucketId, key: &Key) -> Result<MultipartUpload<'_>> {
+        let path = format!("/v1/{}/_uploads/{}", bucket.as_str(), key.to_url());
+        let response = self.send(Method::POST, &path, Bytes::new()).await?;
+        let body = upload_response(&response)?;
+        let upload_id = body["upload_id"]
+            .as_str()
+            .ok_or_else(|| ClientError::InvalidResponse("missing upload id".to_string()))?;
+        Ok(MultipartUpload::new(self, upload_id.to_string(), bucket.clone(), key.clone()))
+    }
+
+    /// Pick up an upload started earlier, with the parts the server already holds
+    pub async fn resume_multipart_upload(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        upload_id: &str,
+    ) -> Result<MultipartUpload<'_>> {
+        let mut upload = MultipartUpload::new(self, upload_id.to_string(), bucket.clone(), key.clone());
+        upload.list_parts().await?;
+        Ok(upload)
+    }
+}
+
+/// Body of a successful upload response; an upload the server doesn't
+/// know (completed, aborted or expired) is a [`ClientError::MultipartUpload`]
+fn upload_response(response: &RawResponse) -> Result<serde_json::Value> {
+    match response.status {
+        status if status.is_success() => serde_json::from_slice(&response.body)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string())),
+        StatusCode::NOT_FOUND => {
+            let body = serde_json::from_slice::<serde_json::Value>(&response.body).unwrap_or_default();
+            Err(ClientError::MultipartUpload(body["error"].as_str().unwrap_or("upload not found").to_string()))
+        }
+        _ => Err(status_error(response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use wfldb_net::query_param;
+    use crate::test_server;
+
+    /// Parts a stub server holds for its one upload, `u1`, and the object
+    /// completing it assembled
+    #[derive(Default)]
+    struct Stub {
+        parts: Mutex<BTreeMap<u32, Vec<u8>>>,
+        puts: Mutex<Vec<u32>>,
+        completed: Mutex<Option<Vec<u8>>>,
+    }
+
+    async fn stub_server(stub: Arc<Stub>) -> String {
+        test_server::serve(move |req| {
+            let stub = stub.clone();
+            async move {
+                let query = req.uri().query().unwrap_or_default().to_string();
+                if req.uri().path() != "/v1/videos/_uploads/clip.mp4" {
+                    return test_server::json(404, json!({"error": "Not found"}));
+                }
+                if query_param(&query, "upload_id").as_deref().is_some_and(|id| id != "u1") {
+                    return test_server::json(404, json!({"error": "upload_not_found"}));
+                }
+                let mut parts = stub.parts.lock().unwrap();
+                match (req.method().as_str(), query_param(&query, "part")) {
+                    ("POST", _) if query.is_empty() => test_server::json(201, json!({"upload_id": "u1"})),
+                    ("PUT", Some(part)) => {
+                        let part: u32 = part.parse().unwrap();
+                        stub.puts.lock().unwrap().push(part);
+                        parts.insert(part, req.body().to_vec());
+                        test_server::json(200, json!({"upload_id": "u1", "part": part}))
+                    }
+                    ("GET", None) => {
+                        let listed: Vec<_> = parts
+                            .iter()
+                            .map(|(part, data)| json!({"part": part, "size": data.len(), "hash": ContentHash::new(data).to_hex()}))
+                            .collect();
+                        test_server::json(200, json!({"upload_id": "u1", "parts": listed}))
+                    }
+                    ("POST", None) => {
+                        let object: Vec<u8> = parts.values().flatten().copied().collect();
+                        let size = object.len();
+                        *stub.completed.lock().unwrap() = Some(object);
+                        test_server::json(201, json!({"size": size, "version": Version::new().to_string(), "generation": 1}))
+                    }
+                    _ => test_server::json(400, json!({"error": "unexpected request"})),
+                }
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_resume_sends_only_missing_parts() {
+        let stub = Arc::new(Stub::default());
+        let client = Client::new(stub_server(stub.clone()).await).unwrap();
+        let (bucket, key) = (BucketId::new("videos").unwrap(), Key::new("clip.mp4").unwrap());
+        let file = [&b"one"[..], b"two", b"three"];
+
+        // A first run stores parts 3 and 1, then crashes
+        let mut upload = client.start_multipart_upload(&bucket, &key).await.unwrap();
+        upload.upload_part(3, file[2]).await.unwrap();
+        upload.upload_part(1, b"stale").await.unwrap();
+        upload.upload_part(1, file[0]).await.unwrap();
+        let upload_id = upload.upload_id().to_string();
+        drop(upload);
+        stub.puts.lock().unwrap().clear();
+
+        let mut upload = client.resume_multipart_upload(&bucket, &key, &upload_id).await.unwrap();
+        let listed: Vec<u32> = upload.parts().iter().map(|part| part.part_number).collect();
+        assert_eq!(listed, [1, 3]);
+        for (number, data) in (1..).zip(file) {
+            if !upload.has_part(number, data) {
+                upload.upload_part(number, data).await.unwrap();
+            }
+        }
+        assert_eq!(*stub.puts.lock().unwrap(), [2]);
+        let metadata = upload.complete().await.unwrap();
+        assert_eq!(metadata.size, 11);
+        assert_eq!(stub.completed.lock().unwrap().as_deref(), Some(&b"onetwothree"[..]));
+
+        let gone = client.resume_multipart_upload(&bucket, &key, "u2").await;
+        assert!(matches!(gone, Err(ClientError::MultipartUpload(_))));
+    }
+}
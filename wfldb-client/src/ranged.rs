@@ -0,0 +1,361 @@
+//! Parallel ranged downloads
+//!
+//! [`Client::download_ranged`] fetches a large object as several `Range`
+//! requests in flight at once and writes them to an `AsyncWrite` in order,
+//! holding at most the ranges in flight in memory. The first range is
+//! asked for alone; a server that answers it with a plain 200 doesn't
+//! serve ranges, and its body is streamed to the writer instead. Later
+//! ranges carry the first response's ETag as `If-Range`, so an object
+//! rewritten mid-download fails the download rather than splicing two
+//! versions together.
+//!
+//! Ranges are only checked by their `Content-Range` and length: each must
+//! come back as the bytes asked for, but no hash of a single range is
+//! verified. The whole object is hashed with BLAKE3 as it is written and
+//! compared with the expected hash if one is given, and with the SHA-256
+//! checksum the server keeps for objects stored with one, so a corrupt
+//! range fails the download once the last range is written.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use http_body_util::BodyExt;
+use hyper::header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use hyper::{HeaderMap, Method, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use wfldb_core::*;
+use crate::client::{object_path, status_error};
+use crate::{Client, ClientError, Result};
+
+/// Header with the server's SHA-256 checksum of the object
+const SHA256_HEADER: &str = "x-wfldb-checksum-sha256";
+
+/// How a ranged download is split
+#[derive(Debug, Clone)]
+pub struct RangedDownload {
+    /// Bytes per range request
+    pub range_size: u64,
+    /// Range requests in flight at once
+    pub concurrency: usize,
+    /// BLAKE3 hash the object must have, e.g. from a listing's metadata
+    pub expected_hash: Option<ContentHash>,
+}
+
+impl Default for RangedDownload {
+    fn default() -> Self {
+        RangedDownload { range_size: 8 * 1024 * 1024, concurrency: 4, expected_hash: None }
+    }
+}
+
+impl RangedDownload {
+    pub fn with_range_size(mut self, range_size: u64) -> Self {
+        self.range_size = range_size.max(1);
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_expected_hash(mut self, hash: ContentHash) -> Self {
+        self.expected_hash = Some(hash);
+        self
+    }
+}
+
+/// Outcome of a ranged download
+#[derive(Debug, Clone)]
+pub struct RangedReport {
+    pub size: u64,
+    /// Range requests made; 0 when the server sent the object whole
+    pub ranges: usize,
+    /// BLAKE3 hash of the bytes written
+    pub content_hash: ContentHash,
+}
+
+/// Hashes of everything written so far
+struct Digests {
+    blake3: blake3::Hasher,
+    sha256: Sha256,
+    size: u64,
+}
+
+impl Digests {
+    fn new() -> Self {
+        Digests { blake3: blake3::Hasher::new(), sha256: Sha256::new(), size: 0 }
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, data: &[u8]) -> Result<()> {
+        self.blake3.update(data);
+        self.sha256.update(data);
+        self.size += data.len() as u64;
+        writer.write_all(data).await?;
+        Ok(())
+    }
+
+    /// Check the object against what it was expected to hash to
+    fn finish(self, expected: Option<&ContentHash>, sha256: Option<&str>, ranges: usize) -> Result<RangedReport> {
+        let content_hash = ContentHash::from_bytes(self.blake3.finalize().into());
+        if expected.is_some_and(|expected| *expected != content_hash) {
+            return Err(ClientError::InvalidResponse("downloaded object does not match its expected hash".to_string()));
+        }
+        if sha256.is_some_and(|sha256| STANDARD.encode(self.sha256.finalize()) != sha256) {
+            return Err(ClientError::InvalidResponse("downloaded object does not match its SHA-256 checksum".to_string()));
+        }
+        Ok(RangedReport { size: self.size, ranges, content_hash })
+    }
+}
+
+impl Client {
+    /// Write object `key` to `writer`, fetching ranges in parallel when
+    /// the server serves them; `None` if the object doesn't exist
+    pub async fn download_ranged<W: AsyncWrite + Unpin>(
+        &self,
+        bucket: &BucketId,
+        key: &Key,
+        writer: &mut W,
+        options: &RangedDownload,
+    ) -> Result<Option<RangedReport>> {
+        let path = object_path(bucket, key);
+        let range_size = options.range_size.max(1);
+        let first = [(RANGE.as_str(), range_header(0, range_size))];
+        let response = match self.open(Method::GET, &path, Bytes::new(), &first).await? {
+            Ok(response) => response,
+            Err(response) if response.status == StatusCode::NOT_FOUND => return Ok(None),
+            // An empty object has no first byte to ask for
+            Err(response) if response.status == StatusCode::RANGE_NOT_SATISFIABLE => {
+                let mut digests = Digests::new();
+                digests.write(writer, &[]).await?;
+                return digests.finish(options.expected_hash.as_ref(), None, 0).map(Some);
+            }
+            Err(response) => return Err(status_error(&response)),
+        };
+        let sha256 = header(response.headers(), SHA256_HEADER);
+        let mut digests = Digests::new();
+
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            let mut body = response.into_body();
+            while let Some(frame) = body.frame().await {
+                let frame = frame.map_err(|e| ClientError::Stream(e.to_string()))?;
+                if let Some(data) = frame.data_ref() {
+                    digests.write(writer, data).await?;
+                }
+            }
+            writer.flush().await?;
+            return digests.finish(options.expected_hash.as_ref(), sha256.as_deref(), 0).map(Some);
+        }
+
+        let etag = header(response.headers(), ETAG.as_str())
+            .ok_or_else(|| ClientError::InvalidResponse("ranged response without an ETag".to_string()))?;
+        let (start, end, total) = header(response.headers(), CONTENT_RANGE.as_str())
+            .as_deref()
+            .and_then(parse_content_range)
+            .ok_or_else(|| ClientError::InvalidResponse("missing or malformed Content-Range".to_string()))?;
+        let body = response.into_body().collect().await.map_err(|e| ClientError::Stream(e.to_string()))?.to_bytes();
+        check_range(&body, (start, end), (0, range_size.min(total).saturating_sub(1)))?;
+        digests.write(writer, &body).await?;
+
+        let rest: Vec<(u64, u64)> = (range_size..total)
+            .step_by(range_size as usize)
+            .map(|offset| (offset, range_size.min(total - offset)))
+            .collect();
+        let ranges = 1 + rest.len();
+        let path = &path;
+        let etag = &etag;
+        let mut fetched = stream::iter(rest)
+            .map(|(offset, len)| async move {
+                let extra = [(RANGE.as_str(), range_header(offset, len)), (IF_RANGE.as_str(), etag.clone())];
+                let response = self.send_with_headers(Method::GET, path, Bytes::new(), &extra).await?;
+                match response.status {
+                    StatusCode::PARTIAL_CONTENT => {}
+                    // If-Range fell back to the whole, newer object
+                    StatusCode::OK => return Err(ClientError::Stream("object changed during download".to_string())),
+                    _ => return Err(status_error(&response)),
+                }
+                let got = header(&response.headers, CONTENT_RANGE.as_str())
+                    .as_deref()
+                    .and_then(parse_content_range)
+                    .ok_or_else(|| ClientError::InvalidResponse("missing or malformed Content-Range".to_string()))?;
+                if got.2 != total {
+                    return Err(ClientError::Stream("object changed during download".to_string()));
+                }
+                check_range(&response.body, (got.0, got.1), (offset, offset + len - 1))?;
+                Ok(response.body)
+            })
+            .buffered(options.concurrency.max(1));
+        while let Some(data) = fetched.try_next().await? {
+            digests.write(writer, &data).await?;
+        }
+        writer.flush().await?;
+        digests.finish(options.expected_hash.as_ref(), sha256.as_deref(), ranges).map(Some)
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+/// `Range` value for `len` bytes from `offset`
+fn range_header(offset: u64, len: u64) -> String {
+    format!("bytes={}-{}", offset, offset + len - 1)
+}
+
+/// First byte, last byte and total size from `bytes first-last/total`
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last, total) = (first.parse().ok()?, last.parse().ok()?, total.parse().ok()?);
+    (first <= last && last < total).then_some((first, last, total))
+}
+
+/// Check a range came back as the bytes asked for
+fn check_range(body: &[u8], got: (u64, u64), asked: (u64, u64)) -> Result<()> {
+    if got != asked || body.len() as u64 != got.1 - got.0 + 1 {
+        return Err(ClientError::InvalidResponse(format!(
+            "asked for bytes {}-{}, got {}-{} ({} bytes)",
+            asked.0,
+            asked.1,
+            got.0,
+            got.1,
+            body.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use serde_json::json;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use crate::test_server;
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-99/1000"), Some((0, 99, 1000)));
+        assert_eq!(parse_content_range("bytes 900-999/1000"), Some((900, 999, 1000)));
+        assert_eq!(parse_content_range("bytes 0-1000/1000"), None);
+        assert_eq!(parse_content_range("bytes */1000"), None);
+        assert_eq!(range_header(100, 50), "bytes=100-149");
+    }
+
+    #[test]
+    fn test_check_range() {
+        assert!(check_range(&[0; 100], (0, 99), (0, 99)).is_ok());
+        assert!(check_range(&[0; 99], (0, 99), (0, 99)).is_err());
+        assert!(check_range(&[0; 100], (100, 199), (0, 99)).is_err());
+    }
+
+    /// A server holding `data` at `/v1/images/disk.img` with ETag `"v1"`,
+    /// or `"v2"` once `changed` is set, answering `Range` with 206s unless
+    /// `ranges` is off
+    async fn stub_server(data: Bytes, ranges: bool, changed: Arc<AtomicBool>) -> String {
+        test_server::serve(move |req| {
+            let (data, changed) = (data.clone(), changed.clone());
+            async move {
+                if req.uri().path() != "/v1/images/disk.img" {
+                    return test_server::json(404, json!({"error": "Not found"}));
+                }
+                let etag = if changed.load(Ordering::SeqCst) { "\"v2\"" } else { "\"v1\"" };
+                let sha256 = STANDARD.encode(Sha256::digest(&data));
+                let response = hyper::Response::builder().header(ETAG, etag).header(SHA256_HEADER, sha256);
+                let if_range_holds = req.headers().get(IF_RANGE).is_none_or(|if_range| if_range == etag);
+                let range = req.headers().get(RANGE).filter(|_| ranges && if_range_holds).map(|range| {
+                    let (first, last) = range.to_str().unwrap().strip_prefix("bytes=").unwrap().split_once('-').unwrap();
+                    let (first, last): (usize, usize) = (first.parse().unwrap(), last.parse().unwrap());
+                    (first, last.min(data.len() - 1))
+                });
+                match range {
+                    Some((first, last)) => response
+                        .status(206)
+                        .header(CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, data.len()))
+                        .body(Full::new(data.slice(first..=last)))
+                        .unwrap(),
+                    None => response.status(200).body(Full::new(data)).unwrap(),
+                }
+            }
+        })
+        .await
+    }
+
+    fn object() -> (BucketId, Key, Bytes) {
+        let data: Vec<u8> = (0..100u8).collect();
+        (BucketId::new("images").unwrap(), Key::new("disk.img").unwrap(), data.into())
+    }
+
+    #[tokio::test]
+    async fn test_download_in_ranges() {
+        let (bucket, key, data) = object();
+        let client = Client::new(stub_server(data.clone(), true, Arc::default()).await).unwrap();
+        let options = RangedDownload::default()
+            .with_range_size(30)
+            .with_concurrency(3)
+            .with_expected_hash(ContentHash::new(&data));
+        let mut out = Vec::new();
+        let report = client.download_ranged(&bucket, &key, &mut out, &options).await.unwrap().unwrap();
+        assert_eq!((report.size, report.ranges), (100, 4));
+        assert_eq!(out, data);
+
+        let missing = Key::new("missing").unwrap();
+        assert!(client.download_ranged(&bucket, &missing, &mut Vec::new(), &options).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_download_whole_without_ranges() {
+        let (bucket, key, data) = object();
+        let client = Client::new(stub_server(data.clone(), false, Arc::default()).await).unwrap();
+        let mut out = Vec::new();
+        let options = RangedDownload::default().with_range_size(30);
+        let report = client.download_ranged(&bucket, &key, &mut out, &options).await.unwrap().unwrap();
+        assert_eq!((report.size, report.ranges), (100, 0));
+        assert_eq!(out, data);
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_when_object_changes() {
+        let (bucket, key, data) = object();
+        let changed = Arc::new(AtomicBool::new(false));
+        let client = Client::new(stub_server(data, true, changed.clone()).await).unwrap();
+
+        // Written after the first range, so the next ones' If-Range misses
+        struct ChangeAfterFirst(Vec<u8>, Arc<AtomicBool>);
+        impl AsyncWrite for ChangeAfterFirst {
+            fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+                self.0.extend_from_slice(buf);
+                self.1.store(true, Ordering::SeqCst);
+                Poll::Ready(Ok(buf.len()))
+            }
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+        let mut out = ChangeAfterFirst(Vec::new(), changed);
+        let options = RangedDownload::default().with_range_size(30).with_concurrency(1);
+        let err = client.download_ranged(&bucket, &key, &mut out, &options).await.unwrap_err();
+        assert!(err.to_string().contains("object changed"), "{}", err);
+        assert_eq!(out.0.len(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_digests_check_expected_hash() {
+        let mut out = Vec::new();
+        let mut digests = Digests::new();
+        digests.write(&mut out, b"hello ").await.unwrap();
+        digests.write(&mut out, b"world").await.unwrap();
+        let report = digests.finish(Some(&ContentHash::new(b"hello world")), None, 2).unwrap();
+        assert_eq!((report.size, out.as_slice()), (11, &b"hello world"[..]));
+
+        let mut digests = Digests::new();
+        digests.write(&mut Vec::new(), b"hello").await.unwrap();
+        assert!(digests.finish(Some(&ContentHash::new(b"hello world")), None, 1).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Delta sync of large files
+//!
+//! [`Client::sync_file`] splits a file into content-defined chunks, asks
+//! the server which of them the bucket already stores, uploads only the
+//! rest, and commits the object as a manifest of the chunk hashes. Chunk
+//! boundaries come from a rolling gear hash over the content rather than
+//! fixed offsets, so inserting or deleting bytes only changes the chunks
+//! around the edit; every other chunk hashes the same as before and is not
+//! sent again. The file is read twice, once to hash and once to upload, so
+//! memory use stays at one chunk however large the file is.
+
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use wfldb_core::*;
+use crate::{Client, ClientError, Result};
+
+/// Largest chunk the server accepts
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Most chunks one manifest may name on the server, and so the most hashes
+/// checked per existence request
+pub const MAX_MANIFEST_CHUNKS: usize = 10_000;
+
+/// Random value per byte for the rolling hash, fixed so every client cuts
+/// the same content at the same places
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5746_6c44_4253_796e;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Content-defined chunk boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min_size: usize,
+    max_size: usize,
+    /// High bits of the rolling hash that must be zero at a boundary
+    mask: u64,
+}
+
+impl Default for Chunker {
+    /// Chunks of 256 KiB to 4 MiB, about 1.25 MiB on average
+    fn default() -> Self {
+        Chunker::new(256 * 1024, 1024 * 1024, MAX_CHUNK_SIZE)
+    }
+}
+
+impl Chunker {
+    /// Cut chunks of `min_size` to `max_size` bytes; past `min_size` a
+    /// boundary falls about every `spacing` bytes, rounded to a power of two
+    pub fn new(min_size: usize, spacing: usize, max_size: usize) -> Self {
+        let max_size = max_size.clamp(1, MAX_CHUNK_SIZE);
+        let bits = spacing.max(2).next_power_of_two().trailing_zeros();
+        Chunker {
+            min_size: min_size.min(max_size),
+            max_size,
+            mask: !(u64::MAX >> bits),
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`, which must hold `max_size`
+    /// bytes unless it is the end of the input
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let end = data.len().min(self.max_size);
+        if end <= self.min_size {
+            return end;
+        }
+        let mut hash = 0u64;
+        for (i, &byte) in data[..end].iter().enumerate() {
+            // Each byte's contribution shifts out after 64 more, so the
+            // hash only depends on the last 64 bytes
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            if i >= self.min_size && hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split in-memory data into chunks
+    pub fn split<'a>(&self, mut data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let (chunk, rest) = data.split_at(self.cut(data));
+            chunks.push(chunk);
+            data = rest;
+        }
+        chunks
+    }
+}
+
+/// Outcome of a file sync
+#[derive(Debug, Clone)]
+pub struct SyncReport {
+    pub chunks: usize,
+    /// Chunks the server didn't have yet
+    pub chunks_uploaded: usize,
+    pub bytes_uploaded: u64,
+    pub metadata: ObjectMetadata,
+}
+
+/// Where a chunk sits in the file
+struct ChunkSpan {
+    hash: ContentHash,
+    offset: u64,
+    len: usize,
+}
+
+impl Client {
+    /// Store the file at `path` as `key`, uploading only the chunks the
+    /// bucket doesn't already store
+    pub async fn sync_file(&self, path: impl AsRef<Path>, bucket: &BucketId, key: &Key) -> Result<SyncReport> {
+        self.sync_file_with(path, bucket, key, &Chunker::default()).await
+    }
+
+    /// [`sync_file`](Self::sync_file) with custom chunk boundaries; every
+    /// client syncing the same data should use the same chunker
+    pub async fn sync_file_with(
+        &self,
+        path: impl AsRef<Path>,
+        bucket: &BucketId,
+        key: &Key,
+        chunker: &Chunker,
+    ) -> Result<SyncReport> {
+        let mut file = File::open(path.as_ref()).await?;
+        let spans = chunk_file(&mut file, chunker).await?;
+        if spans.is_empty() {
+            // A manifest needs at least one chunk
+            let metadata = self.put(bucket, key, &[]).await?;
+            return Ok(SyncReport { chunks: 0, chunks_uploaded: 0, bytes_uploaded: 0, metadata });
+        }
+        if spans.len() > MAX_MANIFEST_CHUNKS {
+            return Err(ClientError::Request(format!(
+                "file splits into {} chunks; the server accepts at most {}",
+                spans.len(),
+                MAX_MANIFEST_CHUNKS
+            )));
+        }
+
+        // A file may repeat a chunk; ask about and upload each once
+        let mut seen = HashSet::new();
+        let unique: Vec<&ChunkSpan> = spans.iter().filter(|span| seen.insert(&span.hash)).collect();
+        let hashes: Vec<ContentHash> = unique.iter().map(|span| span.hash.clone()).collect();
+        let missing: HashSet<ContentHash> = self.missing_chunks(bucket, &hashes).await?.into_iter().collect();
+
+        let mut bytes_uploaded = 0;
+        let mut buf = Vec::new();
+        for span in unique.iter().filter(|span| missing.contains(&span.hash)) {
+            buf.resize(span.len, 0);
+            file.seek(SeekFrom::Start(span.offset)).await?;
+            file.read_exact(&mut buf).await?;
+            if ContentHash::new(&buf) != span.hash {
+                return Err(ClientError::Request(format!(
+                    "{} changed while syncing",
+                    path.as_ref().display()
+                )));
+            }
+            self.put_chunk(bucket, &buf).await?;
+            bytes_uploaded += span.len as u64;
+        }
+
+        let manifest: Vec<ContentHash> = spans.iter().map(|span| span.hash.clone()).collect();
+        let metadata = self.commit_manifest(bucket, key, &manifest).await?;
+        Ok(SyncReport {
+            chunks: spans.len(),
+            chunks_uploaded: missing.len(),
+            bytes_uploaded,
+            metadata,
+        })
+    }
+}
+
+/// Hash a file chunk by chunk, holding at most one chunk in memory
+async fn chunk_file(file: &mut File, chunker: &Chunker) -> Result<Vec<ChunkSpan>> {
+    let mut spans = Vec::new();
+    let mut buf = Vec::with_capacity(chunker.max_size());
+    let mut offset = 0u64;
+    let mut eof = false;
+    loop {
+        while !eof && buf.len() < chunker.max_size() {
+            let start = buf.len();
+            buf.resize(chunker.max_size(), 0);
+            let read = file.read(&mut buf[start..]).await?;
+            buf.truncate(start + read);
+            eof = read == 0;
+        }
+        if buf.is_empty() {
+            return Ok(spans);
+        }
+        let len = chunker.cut(&buf);
+        spans.push(ChunkSpan { hash: ContentHash::new(&buf[..len]), offset, len });
+        buf.drain(..len);
+        offset += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Incompressible bytes, like the media and archives sync is used for
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunker() -> Chunker {
+        Chunker::new(2 * 1024, 8 * 1024, 32 * 1024)
+    }
+
+    /// Chunks of `edited` that `original` doesn't have
+    fn changed_chunks(original: &[u8], edited: &[u8]) -> usize {
+        let before: Vec<ContentHash> = chunker().split(original).iter().map(|c| ContentHash::new(c)).collect();
+        chunker()
+            .split(edited)
+            .iter()
+            .filter(|chunk| !before.contains(&ContentHash::new(chunk)))
+            .count()
+    }
+
+    #[test]
+    fn test_chunks_cover_data_within_bounds() {
+        let data = random_bytes(1024 * 1024, 1);
+        let chunks = chunker().split(&data);
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|c| (2 * 1024..=32 * 1024).contains(&c.len())));
+        assert!(last.len() <= 32 * 1024);
+        // Boundaries fall about every 8 KiB past the 2 KiB minimum
+        assert!((60..=160).contains(&chunks.len()), "{} chunks", chunks.len());
+
+        // Content with no boundaries is cut at the maximum
+        let zeros = vec![0u8; 100 * 1024];
+        let sizes: Vec<usize> = chunker().split(&zeros).iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, [32 * 1024, 32 * 1024, 32 * 1024, 4 * 1024]);
+    }
+
+    #[test]
+    fn test_edits_change_only_nearby_chunks() {
+        let original = random_bytes(2 * 1024 * 1024, 7);
+        let middle = original.len() / 2;
+
+        let mut inserted = original.clone();
+        inserted.splice(middle..middle, random_bytes(100, 8));
+        assert!(changed_chunks(&original, &inserted) <= 2);
+
+        let mut deleted = original.clone();
+        deleted.drain(middle..middle + 5000);
+        assert!(changed_chunks(&original, &deleted) <= 2);
+
+        let mut appended = original.clone();
+        appended.extend(random_bytes(50 * 1024, 9));
+        assert!(changed_chunks(&original, &appended) <= 1 + 50 / 2);
+
+        let mut prepended = random_bytes(10, 10);
+        prepended.extend(&original);
+        assert!(changed_chunks(&original, &prepended) <= 1);
+
+        // Scattered in-place edits each touch the one chunk they land in
+        let mut patched = original.clone();
+        for offset in [10_000, 600_000, 1_200_000, 1_900_000] {
+            patched[offset] ^= 0xff;
+        }
+        assert!(changed_chunks(&original, &patched) <= 4 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_file_matches_split() {
+        let data = random_bytes(300 * 1024, 3);
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(temp.path(), &data).unwrap();
+
+        let mut file = File::open(temp.path()).await.unwrap();
+        let spans = chunk_file(&mut file, &chunker()).await.unwrap();
+        let chunks = chunker().split(&data);
+        assert_eq!(spans.len(), chunks.len());
+        for (span, chunk) in spans.iter().zip(chunks) {
+            assert_eq!(span.hash, ContentHash::new(chunk));
+            assert_eq!(&data[span.offset as usize..span.offset as usize + span.len], chunk);
+        }
+    }
+}
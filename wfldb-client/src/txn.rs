@@ -0,0 +1,140 @@
+//! Optimistic transactions
+//!
+//! A transaction reads through the server as usual but remembers the
+//! version of every key it saw, and buffers its writes locally. `commit`
+//! sends the writes together with those versions; the server applies them
+//! all at once only if none of the read keys changed meanwhile, and
+//! otherwise fails with [`ClientError::Conflict`], after which the caller
+//! starts over with fresh reads. Nothing is locked on the server while the
+//! transaction is open.
+//!
+//! ```ignore
+//! loop {
+//!     let mut txn = client.transaction(&bucket);
+//!     let balance = txn.get(&key).await?.unwrap_or_default();
+//!     txn.put(&key, &debit(balance));
+//!     match txn.commit().await {
+//!         Err(ClientError::Conflict(_)) => continue,
+//!         result => break result,
+//!     }
+//! }
+//! ```
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use hyper::{Method, StatusCode};
+use serde_json::json;
+use std::time::Duration;
+use wfldb_auth::headers;
+use wfldb_core::*;
+use crate::client::{object_path, status_error};
+use crate::{Client, ClientError, Result};
+
+/// Reads and buffered writes of one optimistic transaction
+pub struct Transaction<'a> {
+    client: &'a Client,
+    bucket: BucketId,
+    token: TxnToken,
+    writes: Vec<TxnOp>,
+    /// Keys to keep for a TTL from the commit, which the server works out
+    touches: Vec<(Key, Duration)>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(client: &'a Client, bucket: BucketId) -> Self {
+        Transaction {
+            client,
+            bucket,
+            token: TxnToken::new(),
+            writes: Vec::new(),
+            touches: Vec::new(),
+        }
+    }
+
+    /// Read an object, noting its version; keys already written in this
+    /// transaction return the buffered value instead
+    pub async fn get(&mut self, key: &Key) -> Result<Option<Vec<u8>>> {
+        if let Some(write) = self.writes.iter().find(|op| op.key() == key) {
+            return Ok(match write {
+                TxnOp::Put { data, .. } => Some(data.clone()),
+                _ => None,
+            });
+        }
+
+        let response = self.client
+            .send(Method::GET, &object_path(&self.bucket, key), Bytes::new())
+            .await?;
+        match response.status {
+            StatusCode::NOT_FOUND => {
+                self.token.observe(key, None);
+                Ok(None)
+            }
+            status if status.is_success() => {
+                let version = response.headers
+                    .get(headers::VERSION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| ulid::Ulid::from_string(v).ok())
+                    .ok_or_else(|| ClientError::InvalidResponse("missing object version".to_string()))?;
+                self.token.observe(key, Some(&Version::from_ulid(version)));
+                Ok(Some(response.body.to_vec()))
+            }
+            _ => Err(status_error(&response)),
+        }
+    }
+
+    /// Store an object when the transaction commits
+    pub fn put(&mut self, key: &Key, data: &[u8]) {
+        self.write(TxnOp::Put { key: key.clone(), data: data.to_vec(), precondition: None });
+    }
+
+    /// Delete an object when the transaction commits
+    pub fn delete(&mut self, key: &Key) {
+        self.write(TxnOp::Delete { key: key.clone(), precondition: None });
+    }
+
+    /// Make a key expire `ttl` after the transaction commits without
+    /// rewriting it; the key must exist then
+    pub fn touch(&mut self, key: &Key, ttl: Duration) {
+        self.writes.retain(|existing| existing.key() != key);
+        self.touches.retain(|(existing, _)| existing != key);
+        self.touches.push((key.clone(), ttl));
+    }
+
+    fn write(&mut self, op: TxnOp) {
+        self.writes.retain(|existing| existing.key() != op.key());
+        self.touches.retain(|(existing, _)| existing != op.key());
+        self.writes.push(op);
+    }
+
+    /// Apply the buffered writes if nothing read has changed since
+    pub async fn commit(self) -> Result<()> {
+        if self.writes.is_empty() && self.touches.is_empty() && self.token.reads.is_empty() {
+            return Ok(());
+        }
+        let touches = self.touches.iter().map(|(key, ttl)| {
+            json!({"op": "touch", "key": key.as_str(), "ttl_secs": ttl.as_secs().max(1)})
+        });
+        let body = json!({
+            "operations": self.writes.iter().map(|op| match op {
+                TxnOp::Put { key, data, .. } => json!({"op": "put", "key": key.as_str(), "data": STANDARD.encode(data)}),
+                _ => json!({"op": "delete", "key": op.key().as_str()}),
+            }).chain(touches).collect::<Vec<_>>(),
+            "reads": self.token.reads,
+        });
+        let path = format!("/v1/{}/_txn", self.bucket.as_str());
+        let response = self.client
+            .send(Method::POST, &path, Bytes::from(body.to_string()))
+            .await?;
+        match response.status {
+            status if status.is_success() => Ok(()),
+            StatusCode::CONFLICT => {
+                let key = serde_json::from_slice::<serde_json::Value>(&response.body)
+                    .ok()
+                    .and_then(|body| body["key"].as_str().map(str::to_string))
+                    .unwrap_or_default();
+                Err(ClientError::Conflict(key))
+            }
+            _ => Err(status_error(&response)),
+        }
+    }
+}
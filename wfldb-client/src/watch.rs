@@ -0,0 +1,175 @@
+//! Following a bucket's changes
+//!
+//! [`Client::watch`] streams the changes to a bucket's keys under a prefix
+//! from `GET /v1/{bucket}/_watch`, keeping one long-poll outstanding while
+//! the stream is polled. Each answer's cursor is sent with the next
+//! request, so no change is skipped or repeated. A dropped connection or a
+//! retryable failure reconnects after a growing pause and resumes from the
+//! last cursor; any other failure is yielded and ends the stream. Resume a
+//! stream across restarts with [`Client::watch_from`], passing the `seq` of
+//! the last event handled:
+//!
+//! ```ignore
+//! let mut changes = pin!(client.watch_from(&bucket, "users/", saved_seq));
+//! while let Some(change) = changes.try_next().await? {
+//!     match change.kind {
+//!         ChangeKind::Delete => cache.remove(&change.key),
+//!         ChangeKind::Put | ChangeKind::Copy => cache.invalidate(&change.key),
+//!     }
+//!     saved_seq = Some(change.seq);
+//! }
+//! ```
+//!
+//! A cursor the server no longer holds records for fails with a 410
+//! [`ClientError::Request`]; changes were missed and whatever was derived
+//! from the bucket must be rebuilt.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use bytes::Bytes;
+use futures::stream::{self, Stream};
+use hyper::Method;
+use serde::Deserialize;
+use wfldb_core::*;
+use wfldb_net::encode_query_component;
+use crate::client::status_error;
+use crate::{Client, ClientError, Result};
+
+/// How long each request asks the server to wait for a change
+const WATCH_WAIT: Duration = Duration::from_secs(20);
+
+/// Pause before the first reconnect, doubled on each failure in a row
+const RECONNECT_MIN: Duration = Duration::from_millis(100);
+
+/// Longest pause between reconnects
+const RECONNECT_MAX: Duration = Duration::from_secs(5);
+
+/// What a change did to its key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Put,
+    Delete,
+    Copy,
+}
+
+/// One change to a watched key
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChangeEvent {
+    /// Journal sequence of the change; pass it to [`Client::watch_from`]
+    /// to resume after it
+    pub seq: u64,
+    pub key: String,
+    #[serde(rename = "op")]
+    pub kind: ChangeKind,
+    /// Version the change wrote, if it wrote one
+    pub version: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+/// One answer of the watch endpoint
+#[derive(Deserialize)]
+struct WatchPage {
+    events: Vec<ChangeEvent>,
+    next: u64,
+}
+
+/// Where a watch stream stands between polls
+struct WatchState {
+    /// Cursor of the next request; `None` until the server names the head
+    after: Option<u64>,
+    pending: VecDeque<ChangeEvent>,
+    backoff: Duration,
+    done: bool,
+}
+
+impl Client {
+    /// Stream changes to the bucket's keys under `prefix` from now on,
+    /// reconnecting as needed
+    pub fn watch<'a>(&'a self, bucket: &'a BucketId, prefix: &'a str) -> impl Stream<Item = Result<ChangeEvent>> + 'a {
+        self.watch_from(bucket, prefix, None)
+    }
+
+    /// [`watch`](Self::watch) from the change after sequence `after`, or
+    /// from now on without it
+    pub fn watch_from<'a>(
+        &'a self,
+        bucket: &'a BucketId,
+        prefix: &'a str,
+        after: Option<u64>,
+    ) -> impl Stream<Item = Result<ChangeEvent>> + 'a {
+        let state = WatchState { after, pending: VecDeque::new(), backoff: RECONNECT_MIN, done: false };
+        stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(event) = state.pending.pop_front() {
+                    return Some((Ok(event), state));
+                }
+                if state.done {
+                    return None;
+                }
+                match self.watch_page(bucket, prefix, state.after).await {
+                    Ok(page) => {
+                        state.after = Some(page.next);
+                        state.pending.extend(page.events);
+                        state.backoff = RECONNECT_MIN;
+                    }
+                    Err(e) if reconnects(&e) => {
+                        tracing::debug!(bucket = bucket.as_str(), error = %e, "watch reconnecting");
+                        tokio::time::sleep(e.retry_after().unwrap_or(state.backoff)).await;
+                        state.backoff = (state.backoff * 2).min(RECONNECT_MAX);
+                    }
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        })
+    }
+
+    async fn watch_page(&self, bucket: &BucketId, prefix: &str, after: Option<u64>) -> Result<WatchPage> {
+        let mut path = format!(
+            "/v1/{}/_watch?prefix={}&wait_ms={}",
+            bucket.as_str(),
+            encode_query_component(prefix.as_bytes()),
+            WATCH_WAIT.as_millis()
+        );
+        if let Some(after) = after {
+            path.push_str(&format!("&after={}", after));
+        }
+        let response = self.send(Method::GET, &path, Bytes::new()).await?;
+        if !response.status.is_success() {
+            return Err(status_error(&response));
+        }
+        serde_json::from_slice(&response.body).map_err(|e| ClientError::InvalidResponse(format!("watch: {}", e)))
+    }
+}
+
+/// Whether a failed poll is worth sending again from the same cursor
+fn reconnects(error: &ClientError) -> bool {
+    error.is_retryable() || matches!(error, ClientError::Connection(_) | ClientError::Http(_) | ClientError::Stream(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watch_page() {
+        let body = r#"{"bucket":"photos","events":[
+            {"seq":7,"key":"cats/tom.jpg","op":"put","version":"01J0","timestamp_ms":1700000000000},
+            {"seq":9,"key":"cats/old.jpg","op":"delete","version":null,"timestamp_ms":1700000000001}
+        ],"next":12}"#;
+        let page: WatchPage = serde_json::from_str(body).unwrap();
+        assert_eq!(page.next, 12);
+        assert_eq!((page.events[0].kind, page.events[0].version.as_deref()), (ChangeKind::Put, Some("01J0")));
+        assert_eq!((page.events[1].seq, page.events[1].kind), (9, ChangeKind::Delete));
+    }
+
+    #[test]
+    fn test_reconnects() {
+        assert!(reconnects(&ClientError::Connection("reset".to_string())));
+        assert!(!reconnects(&ClientError::Request("410 Gone: archived".to_string())));
+        assert!(!reconnects(&ClientError::Unauthorized("403".to_string())));
+    }
+}
@@ -0,0 +1,56 @@
+//! Helpers shared by the binary record formats
+
+use crate::{Result, WflDBError};
+
+/// Append `bytes` with a u32 LE length prefix
+pub(crate) fn put_bytes(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    body.extend_from_slice(bytes);
+}
+
+/// Cursor over an encoded record; errors name the kind of record
+pub(crate) struct Reader<'a> {
+    pub(crate) buf: &'a [u8],
+    record: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8], record: &'static str) -> Self {
+        Reader { buf, record }
+    }
+
+    pub(crate) fn corrupt(&self, message: impl std::fmt::Display) -> WflDBError {
+        WflDBError::Corrupt(format!("{}: {}", self.record, message))
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(self.corrupt("truncated"));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let bytes = self.bytes()?.to_vec();
+        String::from_utf8(bytes).map_err(|e| self.corrupt(e))
+    }
+}
@@ -1,27 +1,236 @@
 //! Error types for wflDB
+//!
+//! Every error has a stable machine-readable [`code`](WflDBError::code), the
+//! HTTP status it maps to, and whether repeating the operation can succeed.
+//! Failures of the storage backend keep the backend's error as their
+//! [`source`](std::error::Error::source), so callers can inspect it instead
+//! of matching on messages.
 
 use thiserror::Error;
 
+/// Boxed error from a lower layer, kept as a [`WflDBError`]'s source
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 #[derive(Error, Debug)]
 pub enum WflDBError {
+    /// The storage backend failed
     #[error("Storage error: {0}")]
-    Storage(String),
-    
+    Storage(#[source] BoxError),
+
+    /// Stored data doesn't decode, or is missing pieces it refers to
+    #[error("Corrupt data: {0}")]
+    Corrupt(String),
+
     #[error("Invalid bucket name: {0}")]
     InvalidBucketName(String),
-    
+
+    #[error("Invalid tenant: {0}")]
+    InvalidTenant(String),
+
     #[error("Invalid key: {0}")]
     InvalidKey(String),
-    
+
+    /// A request that can't be carried out as given
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Object not found: {key}")]
     ObjectNotFound { key: String },
-    
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
-    
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
-    
-    #[error("Internal error: {0}")]
-    Internal(String),
-}
\ No newline at end of file
+
+    #[error("Precondition failed for key: {key}")]
+    PreconditionFailed { key: String },
+
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+
+    #[error("Transaction conflict on key: {key}")]
+    TxnConflict { key: String },
+
+    #[error("Lease on {key} is held until {expires_at_ms}")]
+    LeaseHeld { key: String, expires_at_ms: u64 },
+
+    #[error("No current lease on {key} with that id")]
+    LeaseNotHeld { key: String },
+
+    #[error("No multipart upload {upload_id}")]
+    UploadNotFound { upload_id: String },
+
+    /// A manifest names chunks the bucket doesn't store (hex hashes)
+    #[error("Missing chunks: {}", .0.join(", "))]
+    MissingChunks(Vec<String>),
+
+    #[error("Invalid document: {0}")]
+    InvalidDocument(String),
+
+    /// The data directory is in a format newer than this build reads
+    #[error("Data directory format {found} is newer than this build supports ({supported})")]
+    UnsupportedFormat { found: u32, supported: u32 },
+
+    /// A violated assumption, optionally with the error that revealed it
+    #[error("Internal error: {message}")]
+    Internal {
+        message: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+}
+
+impl WflDBError {
+    /// A storage backend failure caused by `source`
+    pub fn storage(source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        WflDBError::Storage(Box::new(source))
+    }
+
+    /// A violated assumption
+    pub fn internal(message: impl Into<String>) -> Self {
+        WflDBError::Internal { message: message.into(), source: None }
+    }
+
+    /// A violated assumption revealed by `source`
+    pub fn internal_from(source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        WflDBError::Internal { message: source.to_string(), source: Some(Box::new(source)) }
+    }
+
+    /// Stable machine-readable code, as sent in API error bodies
+    pub fn code(&self) -> &'static str {
+        match self {
+            _ if self.is_storage_full() => "storage_full",
+            WflDBError::Storage(_) | WflDBError::Io(_) => "storage_error",
+            WflDBError::Corrupt(_) => "corrupt_data",
+            WflDBError::UnsupportedFormat { .. } => "unsupported_format",
+            WflDBError::InvalidBucketName(_)
+            | WflDBError::InvalidTenant(_)
+            | WflDBError::InvalidKey(_)
+            | WflDBError::InvalidRequest(_)
+            | WflDBError::InvalidTransaction(_)
+            | WflDBError::InvalidDocument(_) => "bad_request",
+            WflDBError::ObjectNotFound { .. } => "not_found",
+            WflDBError::UploadNotFound { .. } => "upload_not_found",
+            WflDBError::Serialization(_) => "serialization_error",
+            WflDBError::PreconditionFailed { .. } => "precondition_failed",
+            WflDBError::TxnConflict { .. } => "conflict",
+            WflDBError::LeaseHeld { .. } => "lease_held",
+            WflDBError::LeaseNotHeld { .. } => "lease_lost",
+            WflDBError::MissingChunks(_) => "missing_chunks",
+            WflDBError::Internal { .. } => "internal",
+        }
+    }
+
+    /// HTTP status the error answers with
+    pub fn http_status(&self) -> u16 {
+        match self {
+            _ if self.is_storage_full() => 507,
+            WflDBError::InvalidBucketName(_)
+            | WflDBError::InvalidTenant(_)
+            | WflDBError::InvalidKey(_)
+            | WflDBError::InvalidRequest(_)
+            | WflDBError::InvalidTransaction(_)
+            | WflDBError::InvalidDocument(_)
+            | WflDBError::MissingChunks(_) => 400,
+            WflDBError::ObjectNotFound { .. } | WflDBError::UploadNotFound { .. } => 404,
+            WflDBError::TxnConflict { .. } | WflDBError::LeaseHeld { .. } => 409,
+            WflDBError::LeaseNotHeld { .. } => 410,
+            WflDBError::PreconditionFailed { .. } => 412,
+            WflDBError::Storage(_)
+            | WflDBError::Io(_)
+            | WflDBError::Corrupt(_)
+            | WflDBError::UnsupportedFormat { .. }
+            | WflDBError::Serialization(_)
+            | WflDBError::Internal { .. } => 500,
+        }
+    }
+
+    /// Whether repeating the operation unchanged can succeed: after a
+    /// conflict, once a lease expires, once disk space frees up, or after a
+    /// transient I/O failure
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            _ if self.is_storage_full() => true,
+            WflDBError::TxnConflict { .. } | WflDBError::LeaseHeld { .. } => true,
+            WflDBError::Io(e) => is_transient(e),
+            WflDBError::Storage(source) => io_source(source.as_ref()).is_some_and(is_transient),
+            _ => false,
+        }
+    }
+
+    /// Whether the object or upload asked for doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, WflDBError::ObjectNotFound { .. } | WflDBError::UploadNotFound { .. })
+    }
+
+    /// Whether the error comes from the disk being full
+    pub fn is_storage_full(&self) -> bool {
+        let io = match self {
+            WflDBError::Io(e) => Some(e),
+            WflDBError::Storage(source) => io_source(source.as_ref()),
+            _ => None,
+        };
+        match io {
+            // ENOSPC, for errors raised before `StorageFull` was stable
+            Some(e) => e.kind() == std::io::ErrorKind::StorageFull || e.raw_os_error() == Some(28),
+            None => matches!(self, WflDBError::Storage(source) if source.to_string().contains("No space left on device")),
+        }
+    }
+}
+
+/// The I/O error at the root of `source`'s chain, if there is one
+fn io_source<'a>(source: &'a (dyn std::error::Error + 'static)) -> Option<&'a std::io::Error> {
+    let mut current = Some(source);
+    while let Some(error) = current {
+        if let Some(io) = error.downcast_ref::<std::io::Error>() {
+            return Some(io);
+        }
+        current = error.source();
+    }
+    None
+}
+
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::Interrupted | std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_sources_are_kept() {
+        let full = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        let err = WflDBError::storage(full);
+        assert!(err.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+        assert!(err.is_storage_full() && err.is_retryable());
+        assert_eq!((err.code(), err.http_status()), ("storage_full", 507));
+
+        let interrupted = WflDBError::Io(std::io::ErrorKind::Interrupted.into());
+        assert!(interrupted.is_retryable());
+        assert_eq!(interrupted.code(), "storage_error");
+
+        let bug = WflDBError::internal_from(std::io::Error::other("boom"));
+        assert!(bug.source().is_some());
+        assert_eq!((bug.code(), bug.http_status(), bug.is_retryable()), ("internal", 500, false));
+    }
+
+    #[test]
+    fn test_classification() {
+        let missing = WflDBError::ObjectNotFound { key: "k".to_string() };
+        assert!(missing.is_not_found() && !missing.is_retryable());
+        assert_eq!((missing.code(), missing.http_status()), ("not_found", 404));
+
+        let conflict = WflDBError::TxnConflict { key: "k".to_string() };
+        assert_eq!((conflict.code(), conflict.http_status(), conflict.is_retryable()), ("conflict", 409, true));
+
+        let corrupt = WflDBError::Corrupt("torn record".to_string());
+        assert_eq!((corrupt.code(), corrupt.http_status()), ("corrupt_data", 500));
+        assert_eq!(WflDBError::InvalidRequest("no".to_string()).http_status(), 400);
+    }
+}
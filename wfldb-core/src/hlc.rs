@@ -0,0 +1,227 @@
+//! Hybrid logical clock for object versions
+//!
+//! Wall clocks on different nodes disagree and can step backwards, so a
+//! version taken from the local clock alone may sort before one written
+//! earlier on another node. A hybrid logical clock keeps the wall-clock
+//! milliseconds but never goes backwards: each tick is at least one past
+//! the previous one, and observing a timestamp from another node pulls the
+//! clock up to it. Ticks within the same millisecond count up a 16-bit
+//! logical counter.
+//!
+//! Versions stay ULIDs, laid out so that comparing them compares
+//! (milliseconds, counter, node, random):
+//!
+//! ```text
+//! ms: 48 | logical: 16 | node: 16 | random: 48
+//! ```
+//!
+//! A [`SessionToken`] carries a client's causal history between requests:
+//! the newest clock reading and journal position it has seen.
+
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bits of a packed timestamp holding the logical counter
+const LOGICAL_BITS: u32 = 16;
+
+/// A point in hybrid logical time: wall-clock ms and a logical counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HlcTimestamp(u64);
+
+impl HlcTimestamp {
+    pub fn new(physical_ms: u64, logical: u16) -> Self {
+        HlcTimestamp((physical_ms << LOGICAL_BITS) | logical as u64)
+    }
+
+    pub fn physical_ms(&self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    pub fn logical(&self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Both parts in one ordered integer
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_u64(packed: u64) -> Self {
+        HlcTimestamp(packed)
+    }
+}
+
+/// Hybrid logical clock of one node
+#[derive(Debug)]
+pub struct HybridClock {
+    /// Last timestamp handed out or observed, packed
+    last: AtomicU64,
+    node_id: AtomicU16,
+}
+
+static GLOBAL: HybridClock = HybridClock::new(0);
+
+impl HybridClock {
+    pub const fn new(node_id: u16) -> Self {
+        HybridClock {
+            last: AtomicU64::new(0),
+            node_id: AtomicU16::new(node_id),
+        }
+    }
+
+    /// The process-wide clock that [`Version::new`](crate::Version::new) ticks
+    pub fn global() -> &'static HybridClock {
+        &GLOBAL
+    }
+
+    /// Identify this node in the versions it creates
+    pub fn set_node_id(&self, node_id: u16) {
+        self.node_id.store(node_id, Ordering::Relaxed);
+    }
+
+    pub fn node_id(&self) -> u16 {
+        self.node_id.load(Ordering::Relaxed)
+    }
+
+    /// Next timestamp, later than every one handed out or observed before
+    pub fn tick(&self) -> HlcTimestamp {
+        self.tick_at(wall_clock_ms())
+    }
+
+    fn tick_at(&self, now_ms: u64) -> HlcTimestamp {
+        let wall = HlcTimestamp::new(now_ms, 0).0;
+        let previous = self
+            .last
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| Some(wall.max(last + 1)))
+            .unwrap();
+        // A full counter carries into the millisecond, running slightly ahead
+        // of the wall clock until it catches up
+        HlcTimestamp(wall.max(previous + 1))
+    }
+
+    /// Merge a timestamp seen from another node, so the next tick sorts
+    /// after it
+    pub fn observe(&self, remote: HlcTimestamp) {
+        self.last.fetch_max(remote.0, Ordering::AcqRel);
+    }
+
+    /// Last timestamp handed out or observed
+    pub fn last(&self) -> HlcTimestamp {
+        HlcTimestamp(self.last.load(Ordering::Acquire))
+    }
+}
+
+/// What a client session has seen: the newest journal position and clock
+/// reading from any node it talked to
+///
+/// Written as `{seq}.{hlc}.{node}` in hex. A node given the token serves
+/// reads only once it has applied `seq`, and advances its clock past `hlc`
+/// so the session's next write versions after everything it has read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionToken {
+    pub seq: u64,
+    pub hlc: HlcTimestamp,
+    /// Node whose clock reading `hlc` is
+    pub node_id: u16,
+}
+
+impl SessionToken {
+    /// The later of two tokens in each part
+    pub fn merge(self, other: SessionToken) -> SessionToken {
+        let newer = if other.hlc > self.hlc { other } else { self };
+        SessionToken {
+            seq: self.seq.max(other.seq),
+            ..newer
+        }
+    }
+
+    pub fn parse(text: &str) -> crate::Result<Self> {
+        let invalid = || crate::WflDBError::InvalidKey(format!("invalid session token: {}", text));
+        let mut parts = text.trim().split('.');
+        let mut next = || parts.next().and_then(|part| u64::from_str_radix(part, 16).ok()).ok_or_else(invalid);
+        let token = SessionToken {
+            seq: next()?,
+            hlc: HlcTimestamp(next()?),
+            node_id: u16::try_from(next()?).map_err(|_| invalid())?,
+        };
+        match parts.next() {
+            None => Ok(token),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}.{:x}.{:x}", self.seq, self.hlc.0, self.node_id)
+    }
+}
+
+fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_never_goes_backwards() {
+        let clock = HybridClock::new(1);
+        let first = clock.tick_at(1_000);
+        assert_eq!((first.physical_ms(), first.logical()), (1_000, 0));
+
+        // Same millisecond, then a wall clock that stepped back
+        let second = clock.tick_at(1_000);
+        let third = clock.tick_at(900);
+        assert_eq!((second.physical_ms(), second.logical()), (1_000, 1));
+        assert_eq!((third.physical_ms(), third.logical()), (1_000, 2));
+
+        // The wall clock moving ahead resets the counter
+        let fourth = clock.tick_at(1_001);
+        assert_eq!((fourth.physical_ms(), fourth.logical()), (1_001, 0));
+    }
+
+    #[test]
+    fn test_observe_orders_after_remote() {
+        let clock = HybridClock::new(1);
+        clock.tick_at(1_000);
+
+        // Another node's clock runs ahead of ours
+        let remote = HlcTimestamp::new(5_000, 7);
+        clock.observe(remote);
+        let next = clock.tick_at(1_001);
+        assert!(next > remote);
+        assert_eq!((next.physical_ms(), next.logical()), (5_000, 8));
+
+        // Observing an older timestamp changes nothing
+        clock.observe(HlcTimestamp::new(10, 0));
+        assert_eq!(clock.last(), next);
+    }
+
+    #[test]
+    fn test_session_token() {
+        let token = SessionToken { seq: 42, hlc: HlcTimestamp::new(1_000, 3), node_id: 7 };
+        assert_eq!(SessionToken::parse(&token.to_string()).unwrap(), token);
+        assert!(SessionToken::parse("2a.3e80003").is_err());
+        assert!(SessionToken::parse("2a.3e80003.7.1").is_err());
+        assert!(SessionToken::parse("2a.3e80003.10000").is_err());
+
+        // A replica further along but with an older clock reading
+        let replica = SessionToken { seq: 50, hlc: HlcTimestamp::new(900, 0), node_id: 2 };
+        let merged = token.merge(replica);
+        assert_eq!((merged.seq, merged.hlc, merged.node_id), (50, token.hlc, 7));
+        assert_eq!(replica.merge(token), merged);
+    }
+
+    #[test]
+    fn test_counter_overflow_carries() {
+        let clock = HybridClock::new(1);
+        clock.observe(HlcTimestamp::new(1_000, u16::MAX));
+        let next = clock.tick_at(1_000);
+        assert_eq!((next.physical_ms(), next.logical()), (1_001, 0));
+    }
+}
@@ -1,13 +1,21 @@
 //! Core data models and types for wflDB
 
 
+pub mod change;
+mod codec;
 pub mod error;
+pub mod hlc;
+mod metadata;
+pub mod naming;
 pub mod types;
 
 #[cfg(test)]
 pub mod test_utils;
 
+pub use change::*;
 pub use error::*;
+pub use hlc::*;
+pub use naming::*;
 pub use types::*;
 
 /// Result type alias for wflDB operations
@@ -34,6 +42,26 @@ mod tests {
         assert!(BucketId::new("").is_err());
         assert!(BucketId::new("bucket with spaces").is_err());
         assert!(BucketId::new("bucket/with/slashes").is_err());
+        assert!(BucketId::new("assets.example.com").is_ok());
+    }
+
+    #[test]
+    fn test_key_url_form() {
+        let key = Key::new("photos/été 2024.jpg").unwrap();
+        assert_eq!(key.to_url(), "photos/%C3%A9t%C3%A9%202024.jpg");
+        assert_eq!(Key::from_url(&key.to_url()).unwrap(), key);
+        assert!(Key::from_url("bad%2").is_err());
+        
+        // Binary keys are a superset, so enabling them leaves text keys alone
+        NamePolicy::set_global(NamePolicy { key_charset: KeyCharset::Binary, ..NamePolicy::DEFAULT });
+        let binary = Key::from_url("id/%FF%00").unwrap();
+        assert!(binary.is_binary());
+        assert_eq!(binary.to_bytes().as_ref(), b"id/\xff\x00");
+        assert_eq!(binary.to_url(), "id/%FF%00");
+        assert!(binary.has_prefix("id/"));
+        assert_eq!(Key::new(binary.as_str()).unwrap(), binary);
+        assert_eq!(serde_json::from_str::<Key>(&serde_json::to_string(&binary).unwrap()).unwrap(), binary);
+        assert!(!Key::from_bytes(b"id/text").unwrap().is_binary());
     }
 
     #[test]
@@ -44,9 +72,52 @@ mod tests {
             content_hash: Some(ContentHash::new(b"test data")),
             created_at: std::time::SystemTime::now(),
             chunk_manifest: None,
+            owner: None,
+            checksums: Default::default(),
+            generation: 0,
+            customer_key_hash: None,
         };
         
         assert_eq!(metadata.size, 1024);
         assert!(metadata.content_hash.is_some());
     }
+
+    #[test]
+    fn test_versions_order_across_nodes() {
+        let (leader, follower) = (HybridClock::new(1), HybridClock::new(2));
+        let written = Version::from_clock(&leader);
+        assert_eq!(written.node_id(), 1);
+        assert_eq!(written.timestamp(), written.hlc().physical_ms());
+        // Survives the string form clients see
+        assert_eq!(written.to_string().parse::<ulid::Ulid>().map(Version::from_ulid).unwrap(), written);
+
+        // A node that has seen the write versions after it, even if its
+        // wall clock is behind
+        follower.observe(HlcTimestamp::new(written.timestamp() + 60_000, 0));
+        follower.observe(written.hlc());
+        let overwritten = Version::from_clock(&follower);
+        assert!(overwritten > written);
+        assert_eq!(overwritten.node_id(), 2);
+        assert!(Version::from_clock(&follower) > overwritten);
+    }
+
+    #[test]
+    fn test_txn_token_into_ops() {
+        let (a, b) = (Key::new("a").unwrap(), Key::new("b").unwrap());
+        let version = Version::new();
+        let mut token = TxnToken::new();
+        token.observe(&a, Some(&version));
+        token.observe(&b, None);
+        // Re-reading keeps the first observation
+        token.observe(&a, None);
+
+        let writes = vec![TxnOp::Delete { key: a.clone(), precondition: None }];
+        let ops = token.clone().into_ops(writes).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[0].precondition(), Some(&Precondition::Version(version)));
+        assert!(matches!(&ops[1], TxnOp::Check { key, precondition: Precondition::Absent } if key == &b));
+
+        let contradicting = vec![TxnOp::Delete { key: a, precondition: Some(Precondition::Absent) }];
+        assert!(token.into_ops(contradicting).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,265 @@
+//! Stored form of object metadata
+//!
+//! Metadata is written in a compact binary encoding whose first byte is the
+//! format version. Records written before it are JSON, which always starts
+//! with `{`, so [`ObjectMetadata::decode`] tells the two apart by that byte
+//! and reads either.
+//!
+//! Format 1, integers little endian unless noted:
+//! `format: u8 | flags: u8 | size: u64 | version: u128 BE |
+//! created_at: u64 secs, u32 nanos | [hash: 32] | [manifest] | [owner] |
+//! checksum count: u8, then tag: u8 and value per checksum | [generation: u64] |
+//! [customer key hash: 32] | [data]`, where
+//! the flags say which bracketed fields are present (a flag this build
+//! doesn't know fails the decode, since it can't tell where the fields
+//! after it start) and a manifest is
+//! `chunk_size: u32 | total_size: u64 | count: u32, hashes | count: u32, sizes`.
+//! Strings and variable-length values carry a u32 length prefix. A small
+//! object's data rides at the end of its metadata record, so reading the
+//! object is one lookup.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+use crate::codec::{put_bytes, Reader};
+use crate::{ChecksumAlgorithm, ChunkManifest, ContentHash, ObjectMetadata, Result, Version};
+
+const METADATA_FORMAT: u8 = 1;
+
+const HAS_HASH: u8 = 1;
+const HAS_MANIFEST: u8 = 1 << 1;
+const HAS_OWNER: u8 = 1 << 2;
+const HAS_DATA: u8 = 1 << 3;
+const HAS_GENERATION: u8 = 1 << 4;
+const HAS_CUSTOMER_KEY: u8 = 1 << 5;
+/// Every flag this build reads; a record with any other was written by a
+/// newer one, and its fields can't be found
+const KNOWN_FLAGS: u8 = HAS_HASH | HAS_MANIFEST | HAS_OWNER | HAS_DATA | HAS_GENERATION | HAS_CUSTOMER_KEY;
+
+fn checksum_tag(algorithm: ChecksumAlgorithm) -> u8 {
+    match algorithm {
+        ChecksumAlgorithm::Crc32c => 1,
+        ChecksumAlgorithm::Sha256 => 2,
+    }
+}
+
+impl ObjectMetadata {
+    /// Encode the metadata in the current binary format
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_record(None)
+    }
+
+    /// Encode the metadata followed by the object's inline data
+    pub fn encode_with_data(&self, data: &[u8]) -> Vec<u8> {
+        self.encode_record(Some(data))
+    }
+
+    fn encode_record(&self, data: Option<&[u8]>) -> Vec<u8> {
+        let mut flags = 0;
+        if self.content_hash.is_some() {
+            flags |= HAS_HASH;
+        }
+        if self.chunk_manifest.is_some() {
+            flags |= HAS_MANIFEST;
+        }
+        if self.owner.is_some() {
+            flags |= HAS_OWNER;
+        }
+        if data.is_some() {
+            flags |= HAS_DATA;
+        }
+        if self.generation != 0 {
+            flags |= HAS_GENERATION;
+        }
+        if self.customer_key_hash.is_some() {
+            flags |= HAS_CUSTOMER_KEY;
+        }
+        let mut out = Vec::with_capacity(96 + data.map_or(0, <[u8]>::len));
+        out.push(METADATA_FORMAT);
+        out.push(flags);
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.version.as_ulid().0.to_be_bytes());
+        let created = self.created_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        out.extend_from_slice(&created.as_secs().to_le_bytes());
+        out.extend_from_slice(&created.subsec_nanos().to_le_bytes());
+        if let Some(hash) = &self.content_hash {
+            out.extend_from_slice(hash.as_bytes());
+        }
+        if let Some(manifest) = &self.chunk_manifest {
+            out.extend_from_slice(&manifest.chunk_size.to_le_bytes());
+            out.extend_from_slice(&manifest.total_size.to_le_bytes());
+            out.extend_from_slice(&(manifest.chunks.len() as u32).to_le_bytes());
+            for chunk in &manifest.chunks {
+                out.extend_from_slice(chunk.as_bytes());
+            }
+            out.extend_from_slice(&(manifest.chunk_sizes.len() as u32).to_le_bytes());
+            for size in &manifest.chunk_sizes {
+                out.extend_from_slice(&size.to_le_bytes());
+            }
+        }
+        if let Some(owner) = &self.owner {
+            put_bytes(&mut out, owner.as_bytes());
+        }
+        out.push(self.checksums.len() as u8);
+        for (algorithm, value) in &self.checksums {
+            out.push(checksum_tag(*algorithm));
+            put_bytes(&mut out, value.as_bytes());
+        }
+        if self.generation != 0 {
+            out.extend_from_slice(&self.generation.to_le_bytes());
+        }
+        if let Some(hash) = &self.customer_key_hash {
+            out.extend_from_slice(hash.as_bytes());
+        }
+        if let Some(data) = data {
+            put_bytes(&mut out, data);
+        }
+        out
+    }
+
+    /// Decode metadata in the binary format or the legacy JSON one
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        Ok(Self::decode_with_data(bytes)?.0)
+    }
+
+    /// Decode metadata and the inline data stored with it, if any
+    pub fn decode_with_data(bytes: &[u8]) -> Result<(Self, Option<&[u8]>)> {
+        let mut reader = Reader::new(bytes, "object metadata");
+        match reader.u8()? {
+            b'{' => return Ok((serde_json::from_slice(bytes)?, None)),
+            METADATA_FORMAT => {}
+            other => return Err(reader.corrupt(format!("unknown format {}", other))),
+        }
+        let flags = reader.u8()?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(reader.corrupt(format!("unknown flags {:#04x}", flags & !KNOWN_FLAGS)));
+        }
+        let size = reader.u64()?;
+        let version = Version::from_ulid(ulid::Ulid(u128::from_be_bytes(reader.take(16)?.try_into().unwrap())));
+        let secs = reader.u64()?;
+        let nanos = reader.u32()?;
+        let created_at = UNIX_EPOCH + Duration::new(secs, nanos);
+        let content_hash = match flags & HAS_HASH {
+            0 => None,
+            _ => Some(read_hash(&mut reader)?),
+        };
+        let chunk_manifest = match flags & HAS_MANIFEST {
+            0 => None,
+            _ => {
+                let chunk_size = reader.u32()?;
+                let total_size = reader.u64()?;
+                let chunks = (0..reader.u32()?).map(|_| read_hash(&mut reader)).collect::<Result<_>>()?;
+                let chunk_sizes = (0..reader.u32()?).map(|_| reader.u32()).collect::<Result<_>>()?;
+                Some(ChunkManifest { chunks, chunk_size, total_size, chunk_sizes })
+            }
+        };
+        let owner = match flags & HAS_OWNER {
+            0 => None,
+            _ => Some(reader.string()?),
+        };
+        let mut checksums = BTreeMap::new();
+        for _ in 0..reader.u8()? {
+            let tag = reader.u8()?;
+            let algorithm = ChecksumAlgorithm::ALL
+                .into_iter()
+                .find(|algorithm| checksum_tag(*algorithm) == tag)
+                .ok_or_else(|| reader.corrupt(format!("unknown checksum {}", tag)))?;
+            checksums.insert(algorithm, reader.string()?);
+        }
+        let generation = match flags & HAS_GENERATION {
+            0 => 0,
+            _ => reader.u64()?,
+        };
+        let customer_key_hash = match flags & HAS_CUSTOMER_KEY {
+            0 => None,
+            _ => Some(read_hash(&mut reader)?),
+        };
+        let data = match flags & HAS_DATA {
+            0 => None,
+            _ => Some(reader.bytes()?),
+        };
+        let metadata = ObjectMetadata {
+            size,
+            version,
+            content_hash,
+            created_at,
+            chunk_manifest,
+            owner,
+            checksums,
+            generation,
+            customer_key_hash,
+        };
+        Ok((metadata, data))
+    }
+
+    /// Whether `bytes` hold metadata in the legacy JSON format
+    pub fn is_legacy_encoding(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&b'{')
+    }
+}
+
+fn read_hash(reader: &mut Reader<'_>) -> Result<ContentHash> {
+    Ok(ContentHash::from_bytes(reader.take(32)?.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same(a: &ObjectMetadata, b: &ObjectMetadata) {
+        assert_eq!(serde_json::to_value(a).unwrap(), serde_json::to_value(b).unwrap());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let inline = ObjectMetadata::new_inline(3, ContentHash::new(b"cat"));
+        let encoded = inline.encode();
+        assert_eq!(encoded[0], METADATA_FORMAT);
+        assert!(encoded.len() < serde_json::to_vec(&inline).unwrap().len());
+        assert_same(&ObjectMetadata::decode(&encoded).unwrap(), &inline);
+
+        let manifest = ChunkManifest::new(vec![ContentHash::new(b"a"), ContentHash::new(b"b")], 4, 7)
+            .with_chunk_sizes(vec![4, 3]);
+        let chunked = ObjectMetadata::new_chunked(manifest)
+            .with_owner(Some("abc".to_string()))
+            .with_checksums(BTreeMap::from([
+                (ChecksumAlgorithm::Crc32c, "AAAAAA==".to_string()),
+                (ChecksumAlgorithm::Sha256, "c2hh".to_string()),
+            ]))
+            .with_generation(7)
+            .with_customer_key_hash(Some(ContentHash::new(b"key")));
+        assert_same(&ObjectMetadata::decode(&chunked.encode()).unwrap(), &chunked);
+    }
+
+    #[test]
+    fn test_decodes_legacy_json() {
+        let metadata = ObjectMetadata::new_inline(3, ContentHash::new(b"cat")).with_owner(Some("abc".to_string()));
+        let json = serde_json::to_vec(&metadata).unwrap();
+        assert!(ObjectMetadata::is_legacy_encoding(&json));
+        assert_same(&ObjectMetadata::decode(&json).unwrap(), &metadata);
+
+        let encoded = metadata.encode();
+        assert!(ObjectMetadata::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(matches!(ObjectMetadata::decode(&[9]), Err(crate::WflDBError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_rejects_unknown_flags() {
+        let mut record = ObjectMetadata::new_inline(3, ContentHash::new(b"cat")).encode_with_data(b"cat");
+        record[1] |= 1 << 6;
+        assert!(matches!(ObjectMetadata::decode(&record), Err(crate::WflDBError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_inline_data() {
+        let metadata = ObjectMetadata::new_inline(3, ContentHash::new(b"cat"));
+        let record = metadata.encode_with_data(b"cat");
+        let (decoded, data) = ObjectMetadata::decode_with_data(&record).unwrap();
+        assert_same(&decoded, &metadata);
+        assert_eq!(data, Some(&b"cat"[..]));
+        assert_same(&ObjectMetadata::decode(&record).unwrap(), &metadata);
+
+        assert_eq!(ObjectMetadata::decode_with_data(&metadata.encode()).unwrap().1, None);
+        assert_eq!(ObjectMetadata::decode_with_data(&metadata.encode_with_data(b"")).unwrap().1, Some(&b""[..]));
+        assert!(ObjectMetadata::decode_with_data(&record[..record.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Validation policy for bucket names and object keys
+//!
+//! [`BucketId::new`](crate::BucketId::new) and [`Key::new`](crate::Key::new)
+//! check names against one process-wide [`NamePolicy`], so the server, the
+//! engine and anything else building them agree on what a valid name is.
+//! The server sets it once at startup, before any name is built.
+//!
+//! Bucket names are ASCII letters, digits, `-`, `_` and `.`, where dots
+//! only separate labels, as in domain-style names like `assets.example.com`.
+//! Keys take any character but controls by default; the policy can narrow
+//! that to printable ASCII or to the URL-safe set, and can normalize keys
+//! to Unicode NFC (with the `unicode-normalization` feature) so composed and
+//! decomposed spellings of a name are one key.
+//!
+//! With the `binary` charset, keys may also be arbitrary bytes. Wherever
+//! keys travel as strings, a key that isn't valid text is written as
+//! [`BINARY_KEY_MARKER`] followed by its bytes in hex. Text keys can't
+//! contain the marker, so the two kinds never share a name.
+
+use std::sync::RwLock;
+use crate::types::hex;
+use crate::{Result, WflDBError};
+
+/// First character of a binary key's text form; a control character, so
+/// never part of a text key
+pub const BINARY_KEY_MARKER: char = '\0';
+
+/// Longest bucket name any policy allows: a bucket's partition is named
+/// `{tenant}#{bucket}_main`, and with the longest (64-character) tenant
+/// that must stay within the storage engine's 255-character limit
+pub const MAX_BUCKET_NAME_LEN: usize = 255 - 64 - 1 - "_main".len();
+
+/// Characters a key may contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCharset {
+    /// Anything but control characters
+    Unicode,
+    /// Printable ASCII, space included
+    Ascii,
+    /// ASCII letters, digits and `-_.~/!*'()`, which need no escaping in URLs
+    UrlSafe,
+    /// Text as with `Unicode`, or any bytes that aren't such text
+    Binary,
+}
+
+impl KeyCharset {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "unicode" => Some(KeyCharset::Unicode),
+            "ascii" => Some(KeyCharset::Ascii),
+            "url-safe" => Some(KeyCharset::UrlSafe),
+            "binary" => Some(KeyCharset::Binary),
+            _ => None,
+        }
+    }
+
+    fn allows(self, c: char) -> bool {
+        match self {
+            KeyCharset::Unicode | KeyCharset::Binary => !c.is_control(),
+            KeyCharset::Ascii => c.is_ascii() && !c.is_ascii_control(),
+            KeyCharset::UrlSafe => c.is_ascii_alphanumeric() || "-_.~/!*'()".contains(c),
+        }
+    }
+}
+
+/// How keys are normalized before they're checked and stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyNormalization {
+    /// Stored as given
+    None,
+    /// Unicode Normalization Form C
+    #[cfg(feature = "unicode-normalization")]
+    Nfc,
+}
+
+impl KeyNormalization {
+    /// Parse a form name; `nfc` needs the `unicode-normalization` feature
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "none" => Some(KeyNormalization::None),
+            #[cfg(feature = "unicode-normalization")]
+            "nfc" => Some(KeyNormalization::Nfc),
+            _ => None,
+        }
+    }
+
+    fn apply(self, key: &str) -> String {
+        match self {
+            KeyNormalization::None => key.to_string(),
+            #[cfg(feature = "unicode-normalization")]
+            KeyNormalization::Nfc => unicode_normalization::UnicodeNormalization::nfc(key).collect(),
+        }
+    }
+}
+
+/// Limits and character sets for names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamePolicy {
+    /// Longest key in UTF-8 bytes, after normalization
+    pub max_key_bytes: usize,
+    pub key_charset: KeyCharset,
+    pub key_normalization: KeyNormalization,
+    /// Longest bucket name, at most [`MAX_BUCKET_NAME_LEN`]
+    pub max_bucket_len: usize,
+}
+
+static GLOBAL: RwLock<NamePolicy> = RwLock::new(NamePolicy::DEFAULT);
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        NamePolicy::DEFAULT
+    }
+}
+
+impl NamePolicy {
+    /// Keys of up to 1 KiB without control characters, stored as given, and
+    /// bucket names of up to 63 characters, as in S3
+    pub const DEFAULT: NamePolicy = NamePolicy {
+        max_key_bytes: 1024,
+        key_charset: KeyCharset::Unicode,
+        key_normalization: KeyNormalization::None,
+        max_bucket_len: 63,
+    };
+
+    /// The policy [`BucketId::new`](crate::BucketId::new) and
+    /// [`Key::new`](crate::Key::new) enforce
+    pub fn global() -> NamePolicy {
+        *GLOBAL.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replace the process-wide policy
+    pub fn set_global(policy: NamePolicy) {
+        *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = policy;
+    }
+
+    /// The key to store for `key`, normalized, if the policy allows it
+    pub fn check_key(&self, key: &str) -> Result<String> {
+        if key.is_empty() {
+            return Err(WflDBError::InvalidKey("empty key".to_string()));
+        }
+        let key = self.key_normalization.apply(key);
+        if key.len() > self.max_key_bytes {
+            return Err(WflDBError::InvalidKey(format!(
+                "key is {} bytes, more than the {} allowed",
+                key.len(),
+                self.max_key_bytes
+            )));
+        }
+        if let Some(c) = key.chars().find(|c| !self.key_charset.allows(*c)) {
+            return Err(WflDBError::InvalidKey(match c.is_control() {
+                true => "control characters not allowed".to_string(),
+                false => format!("character {:?} not allowed", c),
+            }));
+        }
+        Ok(key)
+    }
+
+    /// The key to store for raw `bytes`: the text key if they are one,
+    /// otherwise, if the charset allows it, the binary key's text form
+    pub fn check_key_bytes(&self, bytes: &[u8]) -> Result<String> {
+        let text = std::str::from_utf8(bytes).map_err(|_| WflDBError::InvalidKey("key is not UTF-8".to_string()));
+        let checked = text.and_then(|text| self.check_key(text));
+        if checked.is_ok() || self.key_charset != KeyCharset::Binary {
+            return checked;
+        }
+        if bytes.is_empty() {
+            return Err(WflDBError::InvalidKey("empty key".to_string()));
+        }
+        if bytes.len() > self.max_key_bytes {
+            return Err(WflDBError::InvalidKey(format!(
+                "key is {} bytes, more than the {} allowed",
+                bytes.len(),
+                self.max_key_bytes
+            )));
+        }
+        Ok(format!("{}{}", BINARY_KEY_MARKER, hex::encode(bytes)))
+    }
+
+    /// Check a bucket name against the policy
+    pub fn check_bucket(&self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(WflDBError::InvalidBucketName("empty name".to_string()));
+        }
+        // Capped even if the policy was set higher, since a longer name
+        // can't be a partition name
+        let max_len = self.max_bucket_len.min(MAX_BUCKET_NAME_LEN);
+        if name.len() > max_len {
+            return Err(WflDBError::InvalidBucketName(format!(
+                "'{}' is longer than {} characters",
+                name, max_len
+            )));
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+            return Err(WflDBError::InvalidBucketName(format!("invalid characters in '{}'", name)));
+        }
+        if name.split('.').any(str::is_empty) {
+            return Err(WflDBError::InvalidBucketName(format!(
+                "dots in '{}' must separate non-empty labels",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = NamePolicy::default();
+        assert_eq!(policy.check_key("photos/été 2024.jpg").unwrap(), "photos/été 2024.jpg");
+        assert!(policy.check_key("a\nb").is_err());
+        assert!(policy.check_key(&"k".repeat(1024)).is_ok());
+        assert!(policy.check_key(&"k".repeat(1025)).is_err());
+        // Deserialized names are checked too
+        assert!(serde_json::from_str::<crate::Key>("\"a\\u0000b\"").is_err());
+        assert!(serde_json::from_str::<crate::BucketId>("\"two..dots\"").is_err());
+
+        assert!(policy.check_bucket("assets.example.com").is_ok());
+        assert!(policy.check_bucket("my_bucket-2").is_ok());
+        for invalid in ["", ".hidden", "trailing.", "two..dots", "slash/ed", "ünïcode", "b".repeat(64).as_str()] {
+            assert!(policy.check_bucket(invalid).is_err(), "{:?}", invalid);
+        }
+
+        // No policy admits a name too long for a partition
+        let lax = NamePolicy { max_bucket_len: 1000, ..NamePolicy::DEFAULT };
+        assert_eq!(MAX_BUCKET_NAME_LEN, 185);
+        assert!(lax.check_bucket(&"b".repeat(MAX_BUCKET_NAME_LEN)).is_ok());
+        assert!(lax.check_bucket(&"b".repeat(MAX_BUCKET_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_narrower_charsets() {
+        let ascii = NamePolicy { key_charset: KeyCharset::Ascii, ..NamePolicy::DEFAULT };
+        assert!(ascii.check_key("reports/Q1 2024.csv").is_ok());
+        assert!(ascii.check_key("été").is_err());
+
+        let url_safe = NamePolicy { key_charset: KeyCharset::UrlSafe, max_key_bytes: 8, ..NamePolicy::DEFAULT };
+        assert!(url_safe.check_key("a/b~c.d").is_ok());
+        assert!(url_safe.check_key("a b").is_err());
+        assert!(url_safe.check_key("a?b").is_err());
+        assert!(url_safe.check_key("123456789").is_err());
+
+        assert_eq!(KeyCharset::parse("URL-safe"), Some(KeyCharset::UrlSafe));
+        assert_eq!(KeyNormalization::parse("none"), Some(KeyNormalization::None));
+        assert_eq!(KeyCharset::parse("latin1"), None);
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        let binary = NamePolicy { key_charset: KeyCharset::Binary, ..NamePolicy::DEFAULT };
+        assert_eq!(binary.check_key_bytes(b"photos/cat.jpg").unwrap(), "photos/cat.jpg");
+        assert_eq!(binary.check_key_bytes(&[0xff, 0x00]).unwrap(), "\0ff00");
+        // Valid UTF-8 that isn't a valid text key is binary too
+        assert_eq!(binary.check_key_bytes(b"\x00A").unwrap(), "\u{0}0041");
+        assert!(binary.check_key_bytes(&[0xff; 1025]).is_err());
+        assert!(binary.check_key_bytes(b"").is_err());
+
+        assert!(NamePolicy::DEFAULT.check_key_bytes(&[0xff]).is_err());
+        assert_eq!(KeyCharset::parse("binary"), Some(KeyCharset::Binary));
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_nfc_normalization() {
+        let policy = NamePolicy { key_normalization: KeyNormalization::Nfc, ..NamePolicy::DEFAULT };
+        assert_eq!(policy.check_key("cafe\u{301}").unwrap(), "caf\u{e9}");
+    }
+}
@@ -1,70 +1,203 @@
 //! Core data types for wflDB
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::SystemTime;
+use crate::{HlcTimestamp, HybridClock, NamePolicy, BINARY_KEY_MARKER};
 
 /// Unique bucket identifier
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
 pub struct BucketId(String);
 
 impl BucketId {
-    /// Create a new bucket ID with validation
+    /// Create a new bucket ID, validated by the global [`NamePolicy`]
     pub fn new(name: &str) -> crate::Result<Self> {
-        if name.is_empty() {
-            return Err(crate::WflDBError::InvalidBucketName("empty name".to_string()));
+        NamePolicy::global().check_bucket(name)?;
+        Ok(BucketId(name.to_string()))
+    }
+    
+    /// Get the bucket name as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for BucketId {
+    type Error = crate::WflDBError;
+    
+    fn try_from(name: String) -> crate::Result<Self> {
+        BucketId::new(&name)
+    }
+}
+
+impl std::fmt::Display for BucketId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Tenant whose buckets live in their own namespace
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// Create a new tenant ID with validation
+    pub fn new(name: &str) -> crate::Result<Self> {
+        if name.is_empty() || name.len() > 64 {
+            return Err(crate::WflDBError::InvalidTenant(
+                format!("'{}' must be 1-64 characters", name)
+            ));
         }
         
-        // Validate bucket name (alphanumeric, hyphens, underscores only)
-        if !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
-            return Err(crate::WflDBError::InvalidBucketName(
+        // Tenant IDs become part of partition names, so stick to ASCII
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(crate::WflDBError::InvalidTenant(
                 format!("invalid characters in '{}'", name)
             ));
         }
         
-        Ok(BucketId(name.to_string()))
+        Ok(TenantId(name.to_string()))
     }
     
-    /// Get the bucket name as a string slice
+    /// Get the tenant name as a string slice
     pub fn as_str(&self) -> &str {
         &self.0
     }
 }
 
-impl std::fmt::Display for BucketId {
+impl std::fmt::Display for TenantId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+/// Scheduling class of a request, lowest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Backfills and other bulk transfers
+    Bulk,
+    #[default]
+    Normal,
+    /// Latency-sensitive requests, served first under contention
+    Interactive,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Normal, Priority::Bulk];
+    
+    /// Parse a priority name as used in the `x-wfldb-priority` header
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "bulk" => Some(Priority::Bulk),
+            "normal" => Some(Priority::Normal),
+            "interactive" => Some(Priority::Interactive),
+            _ => None,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Bulk => "bulk",
+            Priority::Normal => "normal",
+            Priority::Interactive => "interactive",
+        }
+    }
+}
+
 /// Object key within a bucket
+///
+/// Keys are text, or arbitrary bytes when the [`NamePolicy`] allows binary
+/// keys. A key is held in its text form (see [`crate::naming`]), which is
+/// what JSON bodies, listings and the journal carry; storage keys objects
+/// by the raw bytes, and URLs carry them percent-encoded.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Key(String);
 
 impl Key {
-    /// Create a new key with validation
+    /// Create a new key from its text form, validated and normalized by the
+    /// global [`NamePolicy`]
     pub fn new(key: &str) -> crate::Result<Self> {
-        if key.is_empty() {
-            return Err(crate::WflDBError::InvalidKey("empty key".to_string()));
+        match key.strip_prefix(BINARY_KEY_MARKER) {
+            Some(digits) => {
+                let bytes = hex::decode(digits).map_err(|e| crate::WflDBError::InvalidKey(format!("invalid binary key: {}", e)))?;
+                Key::from_bytes(&bytes)
+            }
+            None => NamePolicy::global().check_key(key).map(Key),
         }
-        
-        // Basic validation - no control characters
-        if key.chars().any(|c| c.is_control()) {
-            return Err(crate::WflDBError::InvalidKey(
-                "control characters not allowed".to_string()
-            ));
+    }
+    
+    /// Create a key from raw bytes, binary unless they are a valid text key
+    pub fn from_bytes(bytes: &[u8]) -> crate::Result<Self> {
+        NamePolicy::global().check_key_bytes(bytes).map(Key)
+    }
+    
+    /// Create a key from its percent-encoded URL path form
+    pub fn from_url(encoded: &str) -> crate::Result<Self> {
+        let mut bytes = Vec::with_capacity(encoded.len());
+        let mut rest = encoded.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte != b'%' {
+                bytes.push(byte);
+                rest = tail;
+                continue;
+            }
+            let escaped = tail
+                .get(..2)
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| crate::WflDBError::InvalidKey("invalid percent-encoding".to_string()))?;
+            bytes.push(escaped);
+            rest = &tail[2..];
         }
-        
-        Ok(Key(key.to_string()))
+        Key::from_bytes(&bytes)
     }
     
-    /// Get the key as a string slice
+    /// Get the key's text form
     pub fn as_str(&self) -> &str {
         &self.0
     }
     
-    /// Check if this key has the given prefix
+    /// Whether the key is bytes rather than text
+    pub fn is_binary(&self) -> bool {
+        self.0.starts_with(BINARY_KEY_MARKER)
+    }
+    
+    /// The key's raw bytes, as stored
+    pub fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        match self.0.strip_prefix(BINARY_KEY_MARKER) {
+            // Checked on construction
+            Some(digits) => hex::decode(digits).unwrap_or_default().into(),
+            None => self.0.as_bytes().into(),
+        }
+    }
+    
+    /// The key as a URL path: its bytes percent-encoded, except unreserved
+    /// characters and `/`
+    pub fn to_url(&self) -> String {
+        let mut encoded = String::with_capacity(self.0.len());
+        for &byte in self.to_bytes().iter() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+                _ => encoded.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        encoded
+    }
+    
+    /// Check if this key's bytes start with the given prefix
     pub fn has_prefix(&self, prefix: &str) -> bool {
-        self.0.starts_with(prefix)
+        self.to_bytes().starts_with(prefix.as_bytes())
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = crate::WflDBError;
+    
+    fn try_from(key: String) -> crate::Result<Self> {
+        Key::new(&key)
     }
 }
 
@@ -75,13 +208,39 @@ impl std::fmt::Display for Key {
 }
 
 /// Version identifier using ULID for time-ordering
+///
+/// New versions carry a hybrid logical clock timestamp and the node id in
+/// the ULID's random part (see [`crate::hlc`]), so they order by causality
+/// across nodes. Versions written before that are plain ULIDs and still
+/// order by their millisecond.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Version(ulid::Ulid);
 
+/// Low bits of a version filled at random
+const VERSION_RANDOM_BITS: u32 = 48;
+
 impl Version {
-    /// Generate a new version with current timestamp
+    /// Generate a new version from the process-wide clock
     pub fn new() -> Self {
-        Version(ulid::Ulid::new())
+        Self::from_clock(HybridClock::global())
+    }
+
+    /// Generate a new version from `clock`
+    pub fn from_clock(clock: &HybridClock) -> Self {
+        let timestamp = clock.tick();
+        let random = ulid::Ulid::new().random() & ((1 << VERSION_RANDOM_BITS) - 1);
+        let bits = (timestamp.as_u64() as u128) << 64 | (clock.node_id() as u128) << VERSION_RANDOM_BITS | random;
+        Version(ulid::Ulid(bits))
+    }
+    
+    /// Hybrid logical clock timestamp of the write
+    pub fn hlc(&self) -> HlcTimestamp {
+        HlcTimestamp::from_u64((self.0 .0 >> 64) as u64)
+    }
+    
+    /// Node that created the version
+    pub fn node_id(&self) -> u16 {
+        (self.0 .0 >> VERSION_RANDOM_BITS) as u16
     }
     
     /// Create version from ULID
@@ -106,6 +265,16 @@ impl Default for Version {
     }
 }
 
+impl std::str::FromStr for Version {
+    type Err = crate::WflDBError;
+
+    fn from_str(text: &str) -> crate::Result<Self> {
+        ulid::Ulid::from_string(text)
+            .map(Version)
+            .map_err(|e| crate::WflDBError::InvalidKey(format!("invalid version '{}': {}", text, e)))
+    }
+}
+
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -137,6 +306,15 @@ impl ContentHash {
     pub fn to_hex(&self) -> String {
         hex::encode(self.0)
     }
+    
+    /// Parse a hash from its hex form
+    pub fn from_hex(text: &str) -> crate::Result<Self> {
+        let bytes = hex::decode(text).map_err(|e| crate::WflDBError::InvalidKey(format!("invalid chunk hash: {}", e)))?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| crate::WflDBError::InvalidKey("chunk hash must be 32 bytes".to_string()))?;
+        Ok(ContentHash(bytes))
+    }
 }
 
 /// Chunk manifest for large objects
@@ -145,6 +323,10 @@ pub struct ChunkManifest {
     pub chunks: Vec<ContentHash>,
     pub chunk_size: u32,
     pub total_size: u64,
+    /// Length of each chunk, in order; empty in manifests written before
+    /// lengths were recorded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunk_sizes: Vec<u32>,
 }
 
 impl ChunkManifest {
@@ -154,15 +336,48 @@ impl ChunkManifest {
             chunks,
             chunk_size,
             total_size,
+            chunk_sizes: Vec::new(),
         }
     }
     
+    /// Record the length of each chunk
+    pub fn with_chunk_sizes(mut self, chunk_sizes: Vec<u32>) -> Self {
+        self.chunk_sizes = chunk_sizes;
+        self
+    }
+    
     /// Get number of chunks
     pub fn chunk_count(&self) -> usize {
         self.chunks.len()
     }
 }
 
+/// Checksum kept beside the BLAKE3 hash for clients that validate objects
+/// with another algorithm, such as those migrating from S3 or GCS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    /// CRC-32C (Castagnoli)
+    Crc32c,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Crc32c, ChecksumAlgorithm::Sha256];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Algorithm by name, ignoring case
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
 /// Object metadata stored in the primary LSM-tree
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectMetadata {
@@ -171,6 +386,21 @@ pub struct ObjectMetadata {
     pub content_hash: Option<ContentHash>,
     pub created_at: SystemTime,
     pub chunk_manifest: Option<ChunkManifest>,
+    /// Key ID (hex) of the principal that created the object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Checksums of the whole object computed when it was written, base64
+    /// of the big-endian digest
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<ChecksumAlgorithm, String>,
+    /// Count of writes to the key, across deletes, as of this one; 0 for
+    /// objects written before generations were counted
+    #[serde(default)]
+    pub generation: u64,
+    /// Hash of the customer-supplied key the stored data is encrypted with;
+    /// the key itself is never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_key_hash: Option<ContentHash>,
 }
 
 impl ObjectMetadata {
@@ -182,6 +412,10 @@ impl ObjectMetadata {
             content_hash: Some(content_hash),
             created_at: SystemTime::now(),
             chunk_manifest: None,
+            owner: None,
+            checksums: BTreeMap::new(),
+            generation: 0,
+            customer_key_hash: None,
         }
     }
     
@@ -193,9 +427,43 @@ impl ObjectMetadata {
             content_hash: None, // Overall hash computed from manifest
             created_at: SystemTime::now(),
             chunk_manifest: Some(chunk_manifest),
+            owner: None,
+            checksums: BTreeMap::new(),
+            generation: 0,
+            customer_key_hash: None,
         }
     }
     
+    /// Record the creating principal
+    pub fn with_owner(mut self, owner: Option<String>) -> Self {
+        self.owner = owner;
+        self
+    }
+    
+    /// Record checksums of the object's data
+    pub fn with_checksums(mut self, checksums: BTreeMap<ChecksumAlgorithm, String>) -> Self {
+        self.checksums = checksums;
+        self
+    }
+    
+    /// Keep the version a write was given on another node
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+    
+    /// Record the write's place in the key's generations
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+    
+    /// Record the hash of the customer key the data is encrypted with
+    pub fn with_customer_key_hash(mut self, customer_key_hash: Option<ContentHash>) -> Self {
+        self.customer_key_hash = customer_key_hash;
+        self
+    }
+    
     /// Check if this is a large object with chunks
     pub fn is_chunked(&self) -> bool {
         self.chunk_manifest.is_some()
@@ -230,6 +498,148 @@ pub enum BatchResult {
     Error(String),
 }
 
+/// Required current state of a key for a transaction operation to apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Precondition {
+    /// The key must not exist
+    Absent,
+    /// The key must exist at exactly this version
+    Version(Version),
+    /// The key must exist at exactly this generation; a fencing token
+    /// that a zombie writer holding an older one can't satisfy
+    Generation(u64),
+}
+
+impl Precondition {
+    /// Whether an object with `current` metadata (or none) satisfies this
+    pub fn holds(&self, current: Option<&ObjectMetadata>) -> bool {
+        match self {
+            Precondition::Absent => current.is_none(),
+            Precondition::Version(version) => current.map(|m| &m.version) == Some(version),
+            Precondition::Generation(generation) => current.map(|m| m.generation) == Some(*generation),
+        }
+    }
+}
+
+/// One operation of a multi-key transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TxnOp {
+    Put { key: Key, data: Vec<u8>, precondition: Option<Precondition> },
+    Delete { key: Key, precondition: Option<Precondition> },
+    /// Write nothing, only require the precondition
+    Check { key: Key, precondition: Precondition },
+    /// Keep the key's current content until `expires_at_ms` without
+    /// rewriting it; the key must exist and not have expired
+    Touch { key: Key, expires_at_ms: u64, precondition: Option<Precondition> },
+}
+
+impl TxnOp {
+    pub fn key(&self) -> &Key {
+        match self {
+            TxnOp::Put { key, .. } | TxnOp::Delete { key, .. } | TxnOp::Check { key, .. } | TxnOp::Touch { key, .. } => key,
+        }
+    }
+
+    pub fn precondition(&self) -> Option<&Precondition> {
+        match self {
+            TxnOp::Put { precondition, .. } | TxnOp::Delete { precondition, .. } | TxnOp::Touch { precondition, .. } => {
+                precondition.as_ref()
+            }
+            TxnOp::Check { precondition, .. } => Some(precondition),
+        }
+    }
+
+    fn precondition_mut(&mut self) -> Option<&mut Option<Precondition>> {
+        match self {
+            TxnOp::Put { precondition, .. } | TxnOp::Delete { precondition, .. } | TxnOp::Touch { precondition, .. } => {
+                Some(precondition)
+            }
+            TxnOp::Check { .. } => None,
+        }
+    }
+}
+
+/// A key's version as an optimistic transaction read it; `None` if absent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxnRead {
+    pub key: Key,
+    pub version: Option<Version>,
+}
+
+/// Read set of an optimistic transaction
+///
+/// Clients note the version of every key they read, then commit their
+/// writes together with the token. Each read becomes a precondition, so the
+/// commit fails with a conflict if any of those keys changed in between,
+/// written or not. Nothing is held on the server until the commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxnToken {
+    pub reads: Vec<TxnRead>,
+}
+
+impl TxnToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note that `key` was read at `version`; the first read of a key wins,
+    /// since that is what the transaction's decisions were based on
+    pub fn observe(&mut self, key: &Key, version: Option<&Version>) {
+        if !self.reads.iter().any(|read| &read.key == key) {
+            self.reads.push(TxnRead { key: key.clone(), version: version.cloned() });
+        }
+    }
+
+    /// Combine the read set with `writes` into one transaction: writes to a
+    /// read key take its observed version as precondition, and keys only
+    /// read become checks
+    pub fn into_ops(self, mut writes: Vec<TxnOp>) -> crate::Result<Vec<TxnOp>> {
+        for read in self.reads {
+            let observed = match read.version {
+                Some(version) => Precondition::Version(version),
+                None => Precondition::Absent,
+            };
+            match writes.iter_mut().find(|op| op.key() == &read.key) {
+                Some(op) => match op.precondition_mut() {
+                    Some(slot @ None) => *slot = Some(observed),
+                    Some(Some(existing)) if *existing == observed => {}
+                    _ => {
+                        return Err(crate::WflDBError::InvalidTransaction(format!(
+                            "key '{}' has a precondition that contradicts its read",
+                            read.key
+                        )))
+                    }
+                },
+                None => writes.push(TxnOp::Check { key: read.key, precondition: observed }),
+            }
+        }
+        Ok(writes)
+    }
+}
+
+/// Exclusive, expiring claim on a key, for clients coordinating who may
+/// write it
+///
+/// Leases are advisory: they don't block writes by themselves. The `id` is
+/// the holder's proof of ownership for renewing and releasing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub id: String,
+    /// Key ID of the holder, when the server authenticates requests
+    pub holder: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub acquired_at_ms: u64,
+    /// Milliseconds since the Unix epoch
+    pub expires_at_ms: u64,
+}
+
+impl Lease {
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_at_ms
+    }
+}
+
 /// Multipart upload state
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MultipartUploadState {
@@ -246,6 +656,9 @@ pub struct PartInfo {
     pub part_number: u32,
     pub size: u64,
     pub content_hash: ContentHash,
+    /// Chunks the part was stored as, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ContentHash>,
 }
 
 impl MultipartUploadState {
@@ -262,10 +675,18 @@ impl MultipartUploadState {
     
     /// Add a part
     pub fn add_part(&mut self, part_number: u32, size: u64, hash: ContentHash) {
+        self.add_chunked_part(part_number, size, hash, Vec::new());
+    }
+    
+    /// Add a part stored as `chunks`, replacing any earlier part with the
+    /// same number
+    pub fn add_chunked_part(&mut self, part_number: u32, size: u64, hash: ContentHash, chunks: Vec<ContentHash>) {
+        self.parts.retain(|part| part.part_number != part_number);
         self.parts.push(PartInfo {
             part_number,
             size,
             content_hash: hash,
+            chunks,
         });
         // Keep parts sorted by part number
         self.parts.sort_by_key(|p| p.part_number);
@@ -293,7 +714,7 @@ impl MultipartUploadState {
     }
 }
 
-mod hex {
+pub(crate) mod hex {
     use std::fmt::Write;
     
     pub fn encode(bytes: impl AsRef<[u8]>) -> String {
@@ -302,4 +723,18 @@ mod hex {
             output
         })
     }
+    
+    pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+        if !text.len().is_multiple_of(2) {
+            return Err("odd number of digits".to_string());
+        }
+        (0..text.len())
+            .step_by(2)
+            .map(|i| {
+                text.get(i..i + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| format!("invalid hex at offset {}", i))
+            })
+            .collect()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+//! Audit log of access and admin events
+//!
+//! Events are buffered in memory on the request path, like usage, and
+//! flushed to the system partition under `audit/{at_ms}/{ulid}`, with the
+//! time zero-padded so entries sort by it and time ranges are key ranges.
+//! Entries are only ever appended, then dropped once older than the
+//! retention period.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use wfldb_core::*;
+use crate::SystemPartition;
+
+pub(crate) const AUDIT_PREFIX: &str = "audit/";
+
+/// What an audit event records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A request to the object API
+    Access,
+    /// A request to the admin API
+    Admin,
+}
+
+/// One request, as kept in the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Milliseconds since the Unix epoch
+    pub at_ms: u64,
+    pub kind: AuditKind,
+    pub request_id: Option<String>,
+    /// Key the request was signed with; `None` if anonymous or unauthenticated
+    pub key_id: Option<String>,
+    pub principal: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// Start of the entries recorded at `at_ms` and after
+fn time_key(at_ms: u64) -> String {
+    format!("{}{:020}/", AUDIT_PREFIX, at_ms)
+}
+
+/// Records audit events and persists them to the system partition
+pub struct AuditLog {
+    system: SystemPartition,
+    pending: Mutex<Vec<AuditEvent>>,
+}
+
+impl AuditLog {
+    pub fn new(system: SystemPartition) -> Self {
+        AuditLog {
+            system,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, event: AuditEvent) {
+        self.pending.lock().unwrap().push(event);
+    }
+
+    /// Write pending events to the system partition, returning how many were written
+    pub fn flush(&self) -> Result<usize> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for event in &pending {
+            let storage_key = format!("{}{}", time_key(event.at_ms), ulid::Ulid::new());
+            let encoded = serde_json::to_vec(event).map_err(WflDBError::Serialization)?;
+            self.system.put(&storage_key, &encoded)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Up to `limit` flushed events from `from_ms` up to `to_ms`, oldest first
+    pub fn events(&self, from_ms: u64, to_ms: u64, limit: usize) -> Result<Vec<AuditEvent>> {
+        self.system
+            .scan_range(&time_key(from_ms), &time_key(to_ms), limit)?
+            .into_iter()
+            .map(|(_, value)| serde_json::from_slice(&value).map_err(WflDBError::Serialization))
+            .collect()
+    }
+
+    /// Delete up to `limit` events recorded before `before_ms`, returning how many were deleted
+    pub fn prune(&self, before_ms: u64, limit: usize) -> Result<usize> {
+        let expired = self.system.scan_range(AUDIT_PREFIX, &time_key(before_ms), limit)?;
+        for (storage_key, _) in &expired {
+            self.system.delete(storage_key)?;
+        }
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageEngine;
+
+    fn event(at_ms: u64, path: &str) -> AuditEvent {
+        AuditEvent {
+            at_ms,
+            kind: AuditKind::Access,
+            request_id: None,
+            key_id: Some("abc".to_string()),
+            principal: None,
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status: 200,
+        }
+    }
+
+    #[test]
+    fn test_time_ranges_and_pruning() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let log = AuditLog::new(engine.system().unwrap());
+        log.record(event(2_000, "/v1/b/second"));
+        log.record(event(1_000, "/v1/b/first"));
+        log.record(event(2_000, "/v1/b/third"));
+        log.record(event(30_000, "/v1/b/late"));
+        assert!(log.events(0, u64::MAX, 10).unwrap().is_empty());
+        assert_eq!(log.flush().unwrap(), 4);
+
+        let range: Vec<u64> = log.events(1_000, 30_000, 10).unwrap().iter().map(|e| e.at_ms).collect();
+        assert_eq!(range, vec![1_000, 2_000, 2_000]);
+        assert_eq!(log.events(0, u64::MAX, 2).unwrap()[0].path, "/v1/b/first");
+
+        assert_eq!(log.prune(2_001, 100).unwrap(), 3);
+        let left = log.events(0, u64::MAX, 10).unwrap();
+        assert_eq!(left, vec![event(30_000, "/v1/b/late")]);
+    }
+}
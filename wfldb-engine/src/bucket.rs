@@ -1,9 +1,11 @@
 //! Bucket abstraction over fjall partitions
 
-use fjall::{Partition, PartitionCreateOptions};
-use serde_json;
+use fjall::{Batch, Partition, PartitionCreateOptions, Snapshot};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use wfldb_core::*;
+use crate::layout::{self, CHUNK, CHUNK_HOME, CHUNK_REF, GENERATION, META, TOMBSTONE};
+use crate::expiry::{self, ObjectExpiry};
 use crate::StorageEngine;
 
 /// Bucket represents a multi-tenant boundary
@@ -16,14 +18,23 @@ pub struct Bucket {
 impl Bucket {
     /// Create or open bucket
     pub(crate) fn new(engine: StorageEngine, id: BucketId) -> Result<Self> {
-        let partition_name = format!("{}_main", id.as_str());
+        let partition_name = engine.partition_name(&id);
+        if let Some(main_partition) = engine.cached_partition(&partition_name) {
+            return Ok(Bucket { id, main_partition, engine });
+        }
+        let created = !engine.keyspace().partition_exists(&partition_name);
         
         let main_partition = Arc::new(
             engine
                 .keyspace()
                 .open_partition(&partition_name, PartitionCreateOptions::default())
-                .map_err(|e| WflDBError::Storage(e.to_string()))?
+                .map_err(WflDBError::storage)?
         );
+        if created {
+            layout::mark_current(&main_partition)?;
+            crate::catalog::record_created(&engine, &engine.namespaced(&id))?;
+        }
+        engine.cache_partition(partition_name, main_partition.clone());
         
         Ok(Bucket {
             id,
@@ -37,31 +48,52 @@ impl Bucket {
         &self.id
     }
     
-    /// Put small object (stored inline in LSM-tree)
+    /// Put small object (stored inline in LSM-tree, in its metadata record)
     pub fn put_small(&self, key: &Key, data: &[u8]) -> Result<ObjectMetadata> {
+        self.put_small_as(key, data, None)
+    }
+    
+    /// Put small object recording its owner
+    pub fn put_small_as(&self, key: &Key, data: &[u8], owner: Option<String>) -> Result<ObjectMetadata> {
+        self.put_small_at(key, data, owner, None, BTreeMap::new(), None, None)
+    }
+    
+    /// Put small object at a given version, or a new one, with checksums of
+    /// `data` and the hash of the customer key it's encrypted with
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn put_small_at(
+        &self,
+        key: &Key,
+        data: &[u8],
+        owner: Option<String>,
+        version: Option<Version>,
+        checksums: BTreeMap<ChecksumAlgorithm, String>,
+        customer_key_hash: Option<ContentHash>,
+        expires_at_ms: Option<u64>,
+    ) -> Result<ObjectMetadata> {
         if data.len() > self.engine.value_threshold() {
-            return Err(WflDBError::Internal(
-                "Data too large for small object storage".to_string()
-            ));
+            return Err(WflDBError::internal("Data too large for small object storage"));
         }
         
         let content_hash = ContentHash::new(data);
-        let metadata = ObjectMetadata::new_inline(data.len() as u64, content_hash);
-        
-        let metadata_key = self.metadata_key(key);
-        let data_key = self.data_key(key);
-        
-        // Store metadata and data
-        let metadata_json = serde_json::to_vec(&metadata)
-            .map_err(WflDBError::Serialization)?;
-        
-        self.main_partition
-            .insert(&metadata_key, metadata_json)
-            .map_err(|e| WflDBError::Storage(e.to_string()))?;
+        let mut metadata = ObjectMetadata::new_inline(data.len() as u64, content_hash)
+            .with_owner(owner)
+            .with_checksums(checksums)
+            .with_customer_key_hash(customer_key_hash)
+            .with_generation(self.generation(key)? + 1);
+        if let Some(version) = version {
+            metadata = metadata.with_version(version);
+        }
         
-        self.main_partition
-            .insert(&data_key, data)
-            .map_err(|e| WflDBError::Storage(e.to_string()))?;
+        // Store metadata and data as one record, through a batch like every
+        // other object write so a crash can't leave part of it
+        let mut batch = self.engine.keyspace().batch();
+        self.stage_generation(&mut batch, key, metadata.generation);
+        batch.insert(&self.main_partition, self.metadata_key(key), metadata.encode_with_data(data));
+        self.stage_expiry(&mut batch, key, &metadata, expires_at_ms)?;
+        crash_point("put_small")?;
+        batch.commit()
+            .map_err(WflDBError::storage)?;
         
         self.engine.persist()?;
         
@@ -70,63 +102,96 @@ impl Bucket {
     
     /// Get small object
     pub fn get_small(&self, key: &Key) -> Result<Option<Vec<u8>>> {
-        let data_key = self.data_key(key);
-        
-        match self.main_partition.get(&data_key) {
-            Ok(Some(data)) => Ok(Some(data.to_vec())),
+        Ok(self.get_record(key)?.and_then(|(_, data)| data))
+    }
+    
+    /// Get object metadata and, for a small object, its data, in one lookup
+    pub fn get_record(&self, key: &Key) -> Result<Option<(ObjectMetadata, Option<Vec<u8>>)>> {
+        match self.main_partition.get(self.metadata_key(key)) {
+            Ok(Some(record)) => {
+                let (metadata, data) = ObjectMetadata::decode_with_data(&record)?;
+                Ok(Some((metadata, data.map(<[u8]>::to_vec))))
+            }
             Ok(None) => Ok(None),
-            Err(e) => Err(WflDBError::Storage(e.to_string())),
+            Err(e) => Err(WflDBError::storage(e)),
         }
     }
     
     /// Put large object (using value log for data, metadata in LSM-tree)
     pub fn put_large(&self, key: &Key, chunks: Vec<Vec<u8>>) -> Result<ObjectMetadata> {
+        self.put_large_as(key, chunks, None)
+    }
+    
+    /// Put large object recording its owner
+    pub fn put_large_as(&self, key: &Key, chunks: Vec<Vec<u8>>, owner: Option<String>) -> Result<ObjectMetadata> {
+        self.put_large_at(key, chunks, owner, None, BTreeMap::new(), None, None)
+    }
+    
+    /// Put large object at a given version, or a new one, with checksums of
+    /// the whole object and the hash of the customer key it's encrypted with
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn put_large_at(
+        &self,
+        key: &Key,
+        chunks: Vec<Vec<u8>>,
+        owner: Option<String>,
+        version: Option<Version>,
+        checksums: BTreeMap<ChecksumAlgorithm, String>,
+        customer_key_hash: Option<ContentHash>,
+        expires_at_ms: Option<u64>,
+    ) -> Result<ObjectMetadata> {
         let mut chunk_hashes = Vec::new();
         let mut total_size = 0u64;
         let chunk_size = chunks.first().map(|c| c.len() as u32).unwrap_or(0);
+        let chunk_sizes = chunks.iter().map(|c| c.len() as u32).collect();
         
-        // Store each chunk in the value log using content-addressing with deduplication
+        // Store each chunk using content-addressing with deduplication. Chunk
+        // data goes in first; a crash before the batch below commits leaves
+        // only unreferenced chunks, which the refcount pass reports as orphans.
+        let mut increments: HashMap<ContentHash, u32> = HashMap::new();
         for chunk in chunks {
             let chunk_hash = ContentHash::new(&chunk);
-            let chunk_key = self.chunk_key(&chunk_hash);
-            let ref_key = self.chunk_ref_key(&chunk_hash);
-            
-            // Check if chunk already exists
-            let existing_ref = self.main_partition.get(&ref_key)
-                .map_err(|e| WflDBError::Storage(e.to_string()))?;
-            
-            if let Some(ref_data) = existing_ref {
-                // Chunk exists, increment reference count
-                let ref_count = u32::from_le_bytes(ref_data[0..4].try_into().unwrap());
-                let new_ref_count = ref_count + 1;
+            let stored = !self.has_local_chunk(&chunk_hash)?;
+            if stored {
                 self.main_partition
-                    .insert(&ref_key, &new_ref_count.to_le_bytes())
-                    .map_err(|e| WflDBError::Storage(e.to_string()))?;
-            } else {
-                // New chunk, store it with reference count of 1
-                self.main_partition
-                    .insert(&chunk_key, &chunk)
-                    .map_err(|e| WflDBError::Storage(e.to_string()))?;
-                self.main_partition
-                    .insert(&ref_key, &1u32.to_le_bytes())
-                    .map_err(|e| WflDBError::Storage(e.to_string()))?;
+                    .insert(self.chunk_key(&chunk_hash), &chunk)
+                    .map_err(WflDBError::storage)?;
             }
-            
+            self.engine.stats().record_chunk_write(stored);
+            *increments.entry(chunk_hash.clone()).or_default() += 1;
             chunk_hashes.push(chunk_hash);
             total_size += chunk.len() as u64;
         }
         
-        let chunk_manifest = ChunkManifest::new(chunk_hashes, chunk_size, total_size);
-        let metadata = ObjectMetadata::new_chunked(chunk_manifest);
+        // Reference counts and the manifest commit together
+        let mut batch = self.engine.keyspace().batch();
+        for (hash, increment) in increments {
+            let ref_key = self.chunk_ref_key(&hash);
+            let ref_count = self.main_partition.get(&ref_key)
+                .map_err(WflDBError::storage)?
+                .map(|data| u32::from_le_bytes(data[0..4].try_into().unwrap()))
+                .unwrap_or(0);
+            batch.insert(&self.main_partition, ref_key, (ref_count + increment).to_le_bytes());
+        }
         
-        // Store metadata
-        let metadata_key = self.metadata_key(key);
-        let metadata_json = serde_json::to_vec(&metadata)
-            .map_err(WflDBError::Serialization)?;
+        let chunk_count = chunk_hashes.len() as u64;
+        let chunk_manifest = ChunkManifest::new(chunk_hashes, chunk_size, total_size).with_chunk_sizes(chunk_sizes);
+        let mut metadata = ObjectMetadata::new_chunked(chunk_manifest)
+            .with_owner(owner)
+            .with_checksums(checksums)
+            .with_customer_key_hash(customer_key_hash)
+            .with_generation(self.generation(key)? + 1);
+        if let Some(version) = version {
+            metadata = metadata.with_version(version);
+        }
         
-        self.main_partition
-            .insert(&metadata_key, metadata_json)
-            .map_err(|e| WflDBError::Storage(e.to_string()))?;
+        self.stage_generation(&mut batch, key, metadata.generation);
+        batch.insert(&self.main_partition, self.metadata_key(key), metadata.encode());
+        self.stage_expiry(&mut batch, key, &metadata, expires_at_ms)?;
+        crash_point("put_large")?;
+        batch.commit()
+            .map_err(WflDBError::storage)?;
+        self.engine.stats().record_refcount_increments(chunk_count);
         
         self.engine.persist()?;
         
@@ -139,58 +204,272 @@ impl Bucket {
         
         match self.main_partition.get(&metadata_key) {
             Ok(Some(data)) => {
-                let metadata: ObjectMetadata = serde_json::from_slice(&data)
-                    .map_err(WflDBError::Serialization)?;
+                let metadata = ObjectMetadata::decode(&data)?;
                 Ok(Some(metadata))
             }
             Ok(None) => Ok(None),
-            Err(e) => Err(WflDBError::Storage(e.to_string())),
+            Err(e) => Err(WflDBError::storage(e)),
         }
     }
     
-    /// Get large object chunk by hash
+    /// Generation of the latest write of `key`, even if the object has been
+    /// deleted since; 0 if it was never written
+    pub fn generation(&self, key: &Key) -> Result<u64> {
+        match self.main_partition.get(self.generation_key(key)) {
+            Ok(Some(bytes)) => bytes[..]
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| WflDBError::Corrupt("generation".to_string())),
+            Ok(None) => Ok(0),
+            Err(e) => Err(WflDBError::storage(e)),
+        }
+    }
+    
+    /// Record `generation` as the latest of `key` in `batch`, which writes
+    /// the object carrying it
+    pub(crate) fn stage_generation(&self, batch: &mut Batch, key: &Key, generation: u64) {
+        batch.insert(&self.main_partition, self.generation_key(key), generation.to_le_bytes());
+    }
+    
+    /// Add the expiry of the version `metadata` describes, if it's given
+    /// one, to the batch writing it
+    fn stage_expiry(&self, batch: &mut Batch, key: &Key, metadata: &ObjectMetadata, expires_at_ms: Option<u64>) -> Result<()> {
+        let Some(expires_at_ms) = expires_at_ms else {
+            return Ok(());
+        };
+        let system = self.engine.system()?;
+        let namespaced = self.engine.namespaced(&self.id);
+        let previous = expiry::load_expiry(&system, &namespaced, key)?;
+        let expiry = ObjectExpiry { version: metadata.version.clone(), expires_at_ms };
+        expiry::stage_expiry(batch, &system, &namespaced, key, previous.as_ref(), &expiry)
+    }
+    
+    /// Get large object chunk by hash, following a clone's link to the bucket holding it
     pub fn get_chunk(&self, hash: &ContentHash) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.get_local_chunk(hash)? {
+            return Ok(Some(data));
+        }
+        match self.chunk_home(hash)? {
+            Some(home) => self.engine.bucket(&home)?.get_local_chunk(hash),
+            None => Ok(None),
+        }
+    }
+    
+    /// Whether a chunk is readable from this bucket
+    pub fn has_chunk(&self, hash: &ContentHash) -> Result<bool> {
+        let present = self.has_local_chunk(hash)?;
+        match self.chunk_home(hash)? {
+            Some(home) if !present => self.engine.bucket(&home)?.main_partition
+                .contains_key(self.chunk_key(hash))
+                .map_err(WflDBError::storage),
+            _ => Ok(present),
+        }
+    }
+    
+    /// Store a chunk ahead of the manifest that will use it, returning its
+    /// hash and whether it was new
+    ///
+    /// The chunk holds no reference until [`put_manifest_as`](Self::put_manifest_as)
+    /// commits an object using it; until then the refcount pass counts it
+    /// as an orphan.
+    pub fn put_chunk(&self, data: &[u8]) -> Result<(ContentHash, bool)> {
+        let hash = ContentHash::new(data);
+        if self.has_local_chunk(&hash)? {
+            self.engine.stats().record_chunk_write(false);
+            return Ok((hash, false));
+        }
+        self.main_partition
+            .insert(self.chunk_key(&hash), data)
+            .map_err(WflDBError::storage)?;
+        self.engine.stats().record_chunk_write(true);
+        self.engine.persist()?;
+        Ok((hash, true))
+    }
+    
+    /// Whether a chunk is stored in this bucket itself
+    pub fn has_local_chunk(&self, hash: &ContentHash) -> Result<bool> {
+        self.main_partition
+            .contains_key(self.chunk_key(hash))
+            .map_err(WflDBError::storage)
+    }
+    
+    /// Commit a large object made of chunks already stored in this bucket,
+    /// taking a reference on each; fails with
+    /// [`MissingChunks`](WflDBError::MissingChunks) naming any that aren't
+    pub fn put_manifest_as(&self, key: &Key, chunks: Vec<ContentHash>, owner: Option<String>) -> Result<ObjectMetadata> {
+        self.put_manifest_at(key, chunks, owner, None)
+    }
+    
+    /// [`put_manifest_as`](Self::put_manifest_as) at `version`, or a new
+    /// version if `None`
+    pub(crate) fn put_manifest_at(
+        &self,
+        key: &Key,
+        chunks: Vec<ContentHash>,
+        owner: Option<String>,
+        version: Option<Version>,
+    ) -> Result<ObjectMetadata> {
+        // A manifest may repeat a chunk, so count increments per chunk first
+        let mut increments: HashMap<ContentHash, u32> = HashMap::new();
+        for hash in &chunks {
+            *increments.entry(hash.clone()).or_default() += 1;
+        }
+        let mut sizes: HashMap<ContentHash, u64> = HashMap::new();
+        let mut missing = Vec::new();
+        for hash in increments.keys() {
+            match self.get_local_chunk(hash)? {
+                Some(data) => {
+                    sizes.insert(hash.clone(), data.len() as u64);
+                }
+                None => missing.push(hash.to_hex()),
+            }
+        }
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(WflDBError::MissingChunks(missing));
+        }
+        
+        let mut batch = self.engine.keyspace().batch();
+        for (hash, increment) in increments {
+            let ref_key = self.chunk_ref_key(&hash);
+            let ref_count = self.main_partition.get(&ref_key)
+                .map_err(WflDBError::storage)?
+                .map(|data| u32::from_le_bytes(data[0..4].try_into().unwrap()))
+                .unwrap_or(0);
+            batch.insert(&self.main_partition, ref_key, (ref_count + increment).to_le_bytes());
+        }
+        
+        let chunk_size = chunks.first().map(|hash| sizes[hash] as u32).unwrap_or(0);
+        let total_size = chunks.iter().map(|hash| sizes[hash]).sum();
+        let chunk_sizes = chunks.iter().map(|hash| sizes[hash] as u32).collect();
+        let chunk_count = chunks.len() as u64;
+        let manifest = ChunkManifest::new(chunks, chunk_size, total_size).with_chunk_sizes(chunk_sizes);
+        let mut metadata = ObjectMetadata::new_chunked(manifest)
+            .with_owner(owner)
+            .with_generation(self.generation(key)? + 1);
+        if let Some(version) = version {
+            metadata = metadata.with_version(version);
+        }
+        let metadata_bytes = metadata.encode();
+        self.stage_generation(&mut batch, key, metadata.generation);
+        batch.insert(&self.main_partition, self.metadata_key(key), metadata_bytes);
+        batch.commit()
+            .map_err(WflDBError::storage)?;
+        self.engine.stats().record_refcount_increments(chunk_count);
+        self.engine.persist()?;
+        
+        Ok(metadata)
+    }
+    
+    /// Bucket holding a chunk this bucket references but does not store,
+    /// recorded when objects are copied in by a clone
+    pub fn chunk_home(&self, hash: &ContentHash) -> Result<Option<BucketId>> {
+        match self.main_partition.get(self.chunk_home_key(hash)) {
+            Ok(Some(home)) => {
+                let home = std::str::from_utf8(&home).map_err(WflDBError::storage)?;
+                Ok(Some(BucketId::new(home)?))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(WflDBError::storage(e)),
+        }
+    }
+    
+    /// Copy an object from another bucket, sharing its chunks by reference
+    /// instead of copying them; returns `None` if the source has no such object
+    ///
+    /// The copy keeps the source's generation unless this bucket's key has
+    /// already counted past it.
+    pub fn copy_object_from(&self, source: &Bucket, key: &Key) -> Result<Option<ObjectMetadata>> {
+        let metadata = match source.get_metadata(key)? {
+            Some(metadata) => {
+                let generation = metadata.generation.max(self.generation(key)? + 1);
+                metadata.with_generation(generation)
+            }
+            None => return Ok(None),
+        };
+        if self.get_metadata(key)?.is_some() {
+            self.delete(key)?;
+        }
+        
+        let mut batch = self.engine.keyspace().batch();
+        let metadata_bytes = match &metadata.chunk_manifest {
+            Some(manifest) => {
+                // A manifest may repeat a chunk, so count increments per holder first
+                let mut increments: HashMap<(BucketId, ContentHash), u32> = HashMap::new();
+                for hash in &manifest.chunks {
+                    let home = source.chunk_holder(hash)?.ok_or_else(|| {
+                        WflDBError::Corrupt(format!("Missing chunk: {}", hash.to_hex()))
+                    })?;
+                    if home != self.id {
+                        batch.insert(&self.main_partition, self.chunk_home_key(hash), home.as_str());
+                    }
+                    *increments.entry((home, hash.clone())).or_default() += 1;
+                }
+                for ((home, hash), increment) in increments {
+                    let holder = if home == self.id { None } else { Some(self.engine.bucket(&home)?) };
+                    let holder = holder.as_ref().unwrap_or(self);
+                    let ref_key = holder.chunk_ref_key(&hash);
+                    let ref_count = holder.main_partition.get(&ref_key)
+                        .map_err(WflDBError::storage)?
+                        .map(|data| u32::from_le_bytes(data[0..4].try_into().unwrap()))
+                        .unwrap_or(0);
+                    batch.insert(&holder.main_partition, ref_key, (ref_count + increment).to_le_bytes());
+                }
+                metadata.encode()
+            }
+            None => {
+                let data = source.get_small(key)?.ok_or_else(|| WflDBError::ObjectNotFound {
+                    key: key.as_str().to_string(),
+                })?;
+                metadata.encode_with_data(&data)
+            }
+        };
+        
+        self.stage_generation(&mut batch, key, metadata.generation);
+        batch.insert(&self.main_partition, self.metadata_key(key), metadata_bytes);
+        batch.commit()
+            .map_err(WflDBError::storage)?;
+        let references = metadata.chunk_manifest.as_ref().map_or(0, |m| m.chunks.len() as u64);
+        self.engine.stats().record_refcount_increments(references);
+        self.engine.persist()?;
+        
+        Ok(Some(metadata))
+    }
+    
+    /// Bucket whose reference count tracks a chunk this bucket uses
+    pub(crate) fn chunk_holder(&self, hash: &ContentHash) -> Result<Option<BucketId>> {
+        if self.has_local_chunk_ref(hash)? {
+            return Ok(Some(self.id.clone()));
+        }
+        self.chunk_home(hash)
+    }
+    
+    fn get_local_chunk(&self, hash: &ContentHash) -> Result<Option<Vec<u8>>> {
         let chunk_key = self.chunk_key(hash);
         
         match self.main_partition.get(&chunk_key) {
             Ok(Some(data)) => Ok(Some(data.to_vec())),
             Ok(None) => Ok(None),
-            Err(e) => Err(WflDBError::Storage(e.to_string())),
+            Err(e) => Err(WflDBError::storage(e)),
         }
     }
     
+    fn has_local_chunk_ref(&self, hash: &ContentHash) -> Result<bool> {
+        self.main_partition
+            .contains_key(self.chunk_ref_key(hash))
+            .map_err(WflDBError::storage)
+    }
+    
     /// Delete object
     pub fn delete(&self, key: &Key) -> Result<()> {
         // Get metadata to check if we need to clean up chunks
         if let Some(metadata) = self.get_metadata(key)? {
-            // Remove metadata and data
+            // Remove metadata, and the data stored with it
             let _ = self.main_partition.remove(&self.metadata_key(key));
-            let _ = self.main_partition.remove(&self.data_key(key));
             
             // If chunked, decrement reference counts and remove unreferenced chunks
             if let Some(manifest) = metadata.chunk_manifest {
-                for chunk_hash in manifest.chunks {
-                    let ref_key = self.chunk_ref_key(&chunk_hash);
-                    
-                    // Get current reference count
-                    if let Some(ref_data) = self.main_partition.get(&ref_key)
-                        .map_err(|e| WflDBError::Storage(e.to_string()))? {
-                        
-                        let ref_count = u32::from_le_bytes(ref_data[0..4].try_into().unwrap());
-                        
-                        if ref_count > 1 {
-                            // Decrement reference count
-                            let new_ref_count = ref_count - 1;
-                            self.main_partition
-                                .insert(&ref_key, &new_ref_count.to_le_bytes())
-                                .map_err(|e| WflDBError::Storage(e.to_string()))?;
-                        } else {
-                            // Last reference, remove chunk and reference count
-                            let _ = self.main_partition.remove(&self.chunk_key(&chunk_hash));
-                            let _ = self.main_partition.remove(&ref_key);
-                        }
-                    }
-                }
+                self.release_chunks(&manifest)?;
             }
         }
         
@@ -198,14 +477,148 @@ impl Bucket {
         Ok(())
     }
     
+    /// Delete object as of `version`, leaving a tombstone so that replicated
+    /// writes older than the delete aren't applied after it
+    ///
+    /// Returns false, changing nothing, when the key already holds that
+    /// version or a later write or delete.
+    pub fn delete_version(&self, key: &Key, version: &Version) -> Result<bool> {
+        if self.is_superseded(key, version)? {
+            return Ok(false);
+        }
+        self.main_partition
+            .insert(self.tombstone_key(key), encode_tombstone(version))
+            .map_err(WflDBError::storage)?;
+        self.delete(key)?;
+        Ok(true)
+    }
+    
+    /// Version of the delete that removed `key`, until its tombstone is purged
+    pub fn get_tombstone(&self, key: &Key) -> Result<Option<Version>> {
+        self.main_partition
+            .get(self.tombstone_key(key))
+            .map_err(WflDBError::storage)?
+            .map(|bytes| decode_tombstone(&bytes))
+            .transpose()
+    }
+    
+    /// Whether `key` already holds `version` or a later write or delete, so
+    /// a replicated change at `version` is stale
+    pub fn is_superseded(&self, key: &Key, version: &Version) -> Result<bool> {
+        if self.get_metadata(key)?.is_some_and(|metadata| metadata.version >= *version) {
+            return Ok(true);
+        }
+        Ok(self.get_tombstone(key)?.is_some_and(|deleted| deleted >= *version))
+    }
+    
+    /// Remove tombstones of deletes made before `cutoff_ms`, returning how many
+    pub fn purge_tombstones(&self, cutoff_ms: u64) -> Result<usize> {
+        let mut expired = Vec::new();
+        for item in self.main_partition.prefix([TOMBSTONE]) {
+            let (tomb_key, value) = item.map_err(WflDBError::storage)?;
+            if decode_tombstone(&value).is_ok_and(|deleted| deleted.timestamp() < cutoff_ms) {
+                expired.push(layout::object_key(&tomb_key)?);
+            }
+        }
+        
+        let namespace = self.engine.namespaced(&self.id);
+        let mut purged = 0;
+        for key in expired {
+            // Re-check under the lock: the key may have been deleted again since the scan
+            let _lock = self.engine.key_locks().lock(&namespace, [&key]);
+            if self.get_tombstone(&key)?.is_some_and(|deleted| deleted.timestamp() < cutoff_ms) {
+                self.main_partition
+                    .remove(self.tombstone_key(&key))
+                    .map_err(WflDBError::storage)?;
+                purged += 1;
+            }
+        }
+        if purged > 0 {
+            self.engine.persist()?;
+        }
+        Ok(purged)
+    }
+    
+    /// Drop the references a removed object's manifest held, wherever its chunks live
+    pub(crate) fn release_chunks(&self, manifest: &ChunkManifest) -> Result<()> {
+        for chunk_hash in &manifest.chunks {
+            if self.has_local_chunk_ref(chunk_hash)? {
+                self.release_chunk(chunk_hash)?;
+            } else if let Some(home) = self.chunk_home(chunk_hash)? {
+                self.engine.bucket(&home)?.release_chunk(chunk_hash)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Drop one reference to a locally stored chunk, removing it with the last
+    fn release_chunk(&self, chunk_hash: &ContentHash) -> Result<()> {
+        let ref_key = self.chunk_ref_key(chunk_hash);
+        
+        // Get current reference count
+        if let Some(ref_data) = self.main_partition.get(&ref_key)
+            .map_err(WflDBError::storage)? {
+            
+            let ref_count = u32::from_le_bytes(ref_data[0..4].try_into().unwrap());
+            
+            if ref_count > 1 {
+                // Decrement reference count
+                let new_ref_count = ref_count - 1;
+                self.main_partition
+                    .insert(&ref_key, &new_ref_count.to_le_bytes())
+                    .map_err(WflDBError::storage)?;
+                self.engine.stats().record_refcount_decrement(false);
+            } else {
+                // Last reference, remove chunk and reference count
+                let _ = self.main_partition.remove(&self.chunk_key(chunk_hash));
+                let _ = self.main_partition.remove(&ref_key);
+                self.engine.stats().record_refcount_decrement(true);
+            }
+        }
+        Ok(())
+    }
+    
     /// Scan keys with prefix
     pub fn scan_prefix(&self, prefix: &str, limit: Option<usize>) -> Result<Vec<Key>> {
-        let prefix_bytes = format!("meta:{}", prefix).into_bytes();
+        self.scan_prefix_after(prefix, None, limit)
+    }
+
+    /// Scan keys with prefix that sort strictly after `start_after`, for
+    /// paging through a bucket
+    ///
+    /// The scan reads a point-in-time snapshot of the bucket, so writes
+    /// landing while it runs don't show up halfway through a page.
+    pub fn scan_prefix_after(&self, prefix: &str, start_after: Option<&str>, limit: Option<usize>) -> Result<Vec<Key>> {
+        self.scan_snapshot(&self.main_partition.snapshot(), prefix, start_after, limit)
+    }
+    
+    /// [`scan_prefix_after`](Self::scan_prefix_after) over a snapshot the
+    /// caller took
+    pub(crate) fn scan_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Key>> {
+        let prefix_bytes = layout::object_entry(META, prefix.as_bytes());
         let mut keys = Vec::new();
         let max_results = limit.unwrap_or(usize::MAX);
+        // `start_after` is a key's text form; binary keys are stored by their bytes
+        let after = start_after.map(|after| {
+            Key::new(after).map_or_else(|_| after.as_bytes().to_vec(), |key| key.to_bytes().into_owned())
+        });
+        let start = match after {
+            // A trailing NUL is the smallest key sorting after `after`
+            Some(mut after) if after.as_slice() >= prefix.as_bytes() => {
+                after.push(0);
+                layout::object_entry(META, &after)
+            }
+            _ => prefix_bytes.clone(),
+        };
         
         // Use fjall's range iterator for efficient prefix scanning
-        let iter = self.main_partition.range(prefix_bytes.clone()..);
+        let iter = snapshot.range(start..);
         
         for item in iter {
             match item {
@@ -216,22 +629,18 @@ impl Bucket {
                     }
                     
                     // Extract the actual key from the metadata key
-                    if let Ok(key_str) = std::str::from_utf8(&key_bytes) {
-                        if let Some(actual_key) = key_str.strip_prefix("meta:") {
-                            if let Ok(key) = Key::new(actual_key) {
-                                // Check if the actual key has the requested prefix
-                                if key.has_prefix(prefix) {
-                                    keys.push(key);
-                                    if keys.len() >= max_results {
-                                        break;
-                                    }
-                                }
+                    if let Ok(key) = layout::object_key(&key_bytes) {
+                        // Check if the actual key has the requested prefix
+                        if key.has_prefix(prefix) {
+                            keys.push(key);
+                            if keys.len() >= max_results {
+                                break;
                             }
                         }
                     }
                 }
                 Err(e) => {
-                    return Err(WflDBError::Storage(format!("Scan error: {}", e)));
+                    return Err(WflDBError::storage(e));
                 }
             }
         }
@@ -240,21 +649,85 @@ impl Bucket {
     }
     
     // Helper methods for key formatting
-    fn metadata_key(&self, key: &Key) -> Vec<u8> {
-        format!("meta:{}", key.as_str()).into_bytes()
+    pub(crate) fn metadata_key(&self, key: &Key) -> Vec<u8> {
+        layout::object_entry(META, &key.to_bytes())
     }
     
-    fn data_key(&self, key: &Key) -> Vec<u8> {
-        format!("data:{}", key.as_str()).into_bytes()
+    pub(crate) fn chunk_key(&self, hash: &ContentHash) -> Vec<u8> {
+        layout::chunk_entry(CHUNK, hash)
     }
     
-    fn chunk_key(&self, hash: &ContentHash) -> Vec<u8> {
-        format!("chunk:{}", hash.to_hex()).into_bytes()
+    pub(crate) fn chunk_ref_key(&self, hash: &ContentHash) -> Vec<u8> {
+        layout::chunk_entry(CHUNK_REF, hash)
     }
     
-    fn chunk_ref_key(&self, hash: &ContentHash) -> Vec<u8> {
-        format!("chunkref:{}", hash.to_hex()).into_bytes()
+    fn chunk_home_key(&self, hash: &ContentHash) -> Vec<u8> {
+        layout::chunk_entry(CHUNK_HOME, hash)
     }
+    
+    pub(crate) fn tombstone_key(&self, key: &Key) -> Vec<u8> {
+        layout::object_entry(TOMBSTONE, &key.to_bytes())
+    }
+    
+    fn generation_key(&self, key: &Key) -> Vec<u8> {
+        layout::object_entry(GENERATION, &key.to_bytes())
+    }
+}
+
+/// Remove tombstones of deletes made before `cutoff_ms` in every bucket of
+/// every tenant
+///
+/// A change journaled before the cutoff can no longer be checked against
+/// the deletes they recorded, so replicas further behind than the
+/// retention period must be re-seeded rather than catch up.
+pub fn purge_tombstones(engine: &StorageEngine, cutoff_ms: u64) -> Result<usize> {
+    let mut purged = 0;
+    for namespace in &engine.namespaces()? {
+        for bucket_id in namespace.bucket_ids()? {
+            purged += namespace.bucket(&bucket_id)?.purge_tombstones(cutoff_ms)?;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+thread_local! {
+    static CRASH_AT: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+/// Make the next write on this thread reaching `point` stop just before
+/// its batch commits, leaving storage as a crash there would
+#[cfg(test)]
+pub(crate) fn arm_crash(point: &'static str) {
+    CRASH_AT.with(|armed| armed.set(Some(point)));
+}
+
+#[cfg(test)]
+fn crash_point(point: &'static str) -> Result<()> {
+    match CRASH_AT.with(|armed| armed.take()) {
+        Some(armed) if armed == point => Err(WflDBError::internal(format!("simulated crash at {}", point))),
+        other => {
+            CRASH_AT.with(|armed| armed.set(other));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn crash_point(_point: &'static str) -> Result<()> {
+    Ok(())
+}
+
+pub(crate) fn encode_tombstone(version: &Version) -> [u8; 16] {
+    version.as_ulid().0.to_be_bytes()
+}
+
+pub(crate) fn decode_tombstone(bytes: &[u8]) -> Result<Version> {
+    let bits = bytes
+        .try_into()
+        .map(u128::from_be_bytes)
+        .map_err(|_| WflDBError::Corrupt("tombstone".to_string()))?;
+    Ok(Version::from_ulid(ulid::Ulid(bits)))
 }
 
 #[cfg(test)]
@@ -311,6 +784,66 @@ mod tests {
         assert_eq!(retrieved_chunk2, chunk2);
     }
     
+    #[tokio::test]
+    async fn test_copy_shares_chunks_by_reference() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let source = engine.bucket(&BucketId::new("prod").unwrap()).unwrap();
+        let clone = engine.bucket(&BucketId::new("staging").unwrap()).unwrap();
+        let branch = engine.bucket(&BucketId::new("branch").unwrap()).unwrap();
+        let key = Key::new("large-object").unwrap();
+        let chunk = vec![7u8; 1024];
+        
+        let metadata = source.put_large(&key, vec![chunk.clone(), chunk.clone()]).unwrap();
+        let hash = metadata.chunk_manifest.unwrap().chunks[0].clone();
+        clone.copy_object_from(&source, &key).unwrap().unwrap();
+        // A clone of a clone points at the bucket actually holding the chunk
+        branch.copy_object_from(&clone, &key).unwrap().unwrap();
+        assert_eq!(branch.chunk_home(&hash).unwrap(), Some(source.id().clone()));
+        
+        // The chunk was not copied, but survives deletes until the last reference goes
+        assert!(clone.get_local_chunk(&hash).unwrap().is_none());
+        source.delete(&key).unwrap();
+        clone.delete(&key).unwrap();
+        assert_eq!(branch.get_chunk(&hash).unwrap(), Some(chunk));
+        branch.delete(&key).unwrap();
+        assert!(source.get_chunk(&hash).unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_generation_counts_across_deletes() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket = engine.bucket(&BucketId::new("locks").unwrap()).unwrap();
+        let clone = engine.bucket(&BucketId::new("locks-copy").unwrap()).unwrap();
+        let key = Key::new("leader").unwrap();
+        assert_eq!(bucket.generation(&key).unwrap(), 0);
+        
+        assert_eq!(bucket.put_small(&key, b"a").unwrap().generation, 1);
+        assert_eq!(bucket.put_large(&key, vec![vec![1; 8]]).unwrap().generation, 2);
+        bucket.delete(&key).unwrap();
+        // A recreated key never repeats a generation an old writer saw
+        assert_eq!(bucket.generation(&key).unwrap(), 2);
+        assert_eq!(bucket.put_small(&key, b"b").unwrap().generation, 3);
+        assert_eq!(bucket.get_metadata(&key).unwrap().unwrap().generation, 3);
+        
+        assert_eq!(clone.copy_object_from(&bucket, &key).unwrap().unwrap().generation, 3);
+        assert_eq!(clone.put_small(&key, b"c").unwrap().generation, 4);
+    }
+    
+    #[tokio::test]
+    async fn test_scan_pages_after_key() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket = engine.bucket(&BucketId::new("test-bucket").unwrap()).unwrap();
+        for name in ["a/1", "a/2", "a/3", "b/1"] {
+            bucket.put_small(&Key::new(name).unwrap(), b"x").unwrap();
+        }
+        
+        let page = bucket.scan_prefix_after("a/", Some("a/1"), Some(10)).unwrap();
+        assert_eq!(page.iter().map(Key::as_str).collect::<Vec<_>>(), vec!["a/2", "a/3"]);
+        assert!(bucket.scan_prefix_after("a/", Some("a/3"), None).unwrap().is_empty());
+        // A cursor before the prefix starts at the prefix
+        assert_eq!(bucket.scan_prefix_after("b/", Some("a/9"), None).unwrap().len(), 1);
+    }
+    
     #[tokio::test]
     async fn test_delete() {
         let (engine, _temp) = StorageEngine::temp().unwrap();
@@ -329,4 +862,70 @@ mod tests {
         assert!(bucket.get_small(&key).unwrap().is_none());
         assert!(bucket.get_metadata(&key).unwrap().is_none());
     }
+    
+    #[test]
+    fn test_put_small_is_atomic_across_crashes() {
+        let temp = tempfile::tempdir().unwrap();
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let key = Key::new("test-key").unwrap();
+        {
+            let bucket = StorageEngine::new(temp.path()).unwrap().bucket(&bucket_id).unwrap();
+            bucket.put_small(&key, b"old").unwrap();
+            arm_crash("put_small");
+            assert!(bucket.put_small(&key, b"new but longer").is_err());
+        }
+        
+        // Reopened, the key holds the old object whole
+        let bucket = StorageEngine::new(temp.path()).unwrap().bucket(&bucket_id).unwrap();
+        let (metadata, data) = bucket.get_record(&key).unwrap().unwrap();
+        assert_eq!((metadata.size, data.unwrap()), (3, b"old".to_vec()));
+        bucket.put_small(&key, b"new but longer").unwrap();
+        assert_eq!(bucket.get_small(&key).unwrap().unwrap(), b"new but longer");
+    }
+    
+    #[test]
+    fn test_put_large_is_atomic_across_crashes() {
+        let temp = tempfile::tempdir().unwrap();
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let key = Key::new("large-object").unwrap();
+        let chunk = vec![3u8; 1024];
+        let hash = ContentHash::new(&chunk);
+        {
+            let bucket = StorageEngine::new(temp.path()).unwrap().bucket(&bucket_id).unwrap();
+            arm_crash("put_large");
+            assert!(bucket.put_large(&key, vec![chunk.clone(), chunk.clone()]).is_err());
+        }
+        
+        // No manifest and no reference survive; the chunk is an orphan
+        let bucket = StorageEngine::new(temp.path()).unwrap().bucket(&bucket_id).unwrap();
+        assert!(bucket.get_metadata(&key).unwrap().is_none());
+        assert!(!bucket.has_local_chunk_ref(&hash).unwrap());
+        
+        // The retry takes one reference per use of the chunk
+        bucket.put_large(&key, vec![chunk.clone(), chunk]).unwrap();
+        let ref_count = bucket.main_partition.get(bucket.chunk_ref_key(&hash)).unwrap().unwrap();
+        assert_eq!(ref_count.as_ref(), 2u32.to_le_bytes());
+    }
+    
+    #[tokio::test]
+    async fn test_tombstone_rejects_older_writes() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket = engine.bucket(&BucketId::new("test-bucket").unwrap()).unwrap();
+        let key = Key::new("test-key").unwrap();
+        let (put, delete, late) = (Version::new(), Version::new(), Version::new());
+        
+        bucket.put_small_at(&key, b"v1", None, Some(put.clone()), BTreeMap::new(), None, None).unwrap();
+        assert!(bucket.delete_version(&key, &delete).unwrap());
+        assert_eq!(bucket.get_tombstone(&key).unwrap(), Some(delete.clone()));
+        
+        // The same delete again, and the put it followed, change nothing
+        assert!(!bucket.delete_version(&key, &delete).unwrap());
+        assert!(bucket.is_superseded(&key, &put).unwrap());
+        assert!(!bucket.is_superseded(&key, &late).unwrap());
+        
+        // Purging only removes tombstones older than the cutoff
+        assert_eq!(bucket.purge_tombstones(delete.timestamp()).unwrap(), 0);
+        assert_eq!(bucket.purge_tombstones(delete.timestamp() + 1).unwrap(), 1);
+        assert!(bucket.get_tombstone(&key).unwrap().is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,127 @@
+//! Bucket catalog
+//!
+//! Buckets come into existence when their partition is first opened, so the
+//! catalog records the creation time then, in the system partition under
+//! `bucket/{bucket}` with the namespaced bucket name. Buckets created before
+//! the catalog existed have no record. Size figures come from the partition
+//! and are estimates: they count every stored entry, not just objects.
+//!
+//! A bucket's JSON Schema, when one is attached, is kept next to its record
+//! under `schema/{bucket}`; the engine stores it as given and leaves
+//! validation to the server.
+
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wfldb_core::*;
+use crate::StorageEngine;
+
+pub(crate) const BUCKET_PREFIX: &str = "bucket/";
+pub(crate) const SCHEMA_PREFIX: &str = "schema/";
+
+/// Catalog record of one bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BucketRecord {
+    pub created_at_ms: u64,
+}
+
+/// A bucket with its creation time and size
+#[derive(Debug, Clone)]
+pub struct BucketSummary {
+    pub id: BucketId,
+    /// `None` for buckets older than the catalog
+    pub created_at_ms: Option<u64>,
+    /// Stored entries (metadata, inline data, chunks), estimated
+    pub approximate_entries: usize,
+    pub disk_bytes: u64,
+}
+
+/// Record that the bucket with namespaced name `bucket` was just created
+pub(crate) fn record_created(engine: &StorageEngine, bucket: &str) -> Result<()> {
+    let created_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let encoded = serde_json::to_vec(&BucketRecord { created_at_ms }).map_err(WflDBError::Serialization)?;
+    engine.system()?.put(&format!("{}{}", BUCKET_PREFIX, bucket), &encoded)
+}
+
+impl StorageEngine {
+    /// Every bucket in this view's namespace, by name
+    pub fn bucket_summaries(&self) -> Result<Vec<BucketSummary>> {
+        let system = self.system()?;
+        let mut summaries = Vec::new();
+        for id in self.bucket_ids()? {
+            let record = match system.get(&format!("{}{}", BUCKET_PREFIX, self.namespaced(&id)))? {
+                Some(data) => Some(serde_json::from_slice::<BucketRecord>(&data).map_err(WflDBError::Serialization)?),
+                None => None,
+            };
+            let bucket = self.bucket(&id)?;
+            summaries.push(BucketSummary {
+                created_at_ms: record.map(|r| r.created_at_ms),
+                approximate_entries: bucket.main_partition.approximate_len(),
+                disk_bytes: bucket.main_partition.disk_space(),
+                id,
+            });
+        }
+        Ok(summaries)
+    }
+
+    /// JSON Schema documents put in the bucket must match, if any
+    pub fn bucket_schema(&self, bucket_id: &BucketId) -> Result<Option<serde_json::Value>> {
+        match self.system()?.get(&format!("{}{}", SCHEMA_PREFIX, self.namespaced(bucket_id)))? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data).map_err(WflDBError::Serialization)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Attach a JSON Schema to a bucket, or detach it with `None`
+    pub fn set_bucket_schema(&self, bucket_id: &BucketId, schema: Option<&serde_json::Value>) -> Result<()> {
+        let system = self.system()?;
+        let name = format!("{}{}", SCHEMA_PREFIX, self.namespaced(bucket_id));
+        match schema {
+            Some(schema) => system.put(&name, &serde_json::to_vec(schema).map_err(WflDBError::Serialization)?),
+            None => system.delete(&name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_summaries() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let photos = BucketId::new("photos").unwrap();
+        engine.bucket(&photos).unwrap().put_small(&Key::new("cat").unwrap(), b"meow").unwrap();
+        engine.bucket(&BucketId::new("logs").unwrap()).unwrap();
+
+        let summaries = engine.bucket_summaries().unwrap();
+        let names: Vec<&str> = summaries.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(names, ["logs", "photos"]);
+        assert!(summaries.iter().all(|s| s.created_at_ms.is_some()));
+        assert!(summaries[1].approximate_entries > 0);
+
+        // Reopening an existing bucket keeps its creation time
+        let created = summaries[1].created_at_ms;
+        engine.bucket(&photos).unwrap();
+        assert_eq!(engine.bucket_summaries().unwrap()[1].created_at_ms, created);
+    }
+
+    #[test]
+    fn test_bucket_schema() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let people = BucketId::new("people").unwrap();
+        let schema = serde_json::json!({"type": "object", "required": ["name"]});
+        assert_eq!(engine.bucket_schema(&people).unwrap(), None);
+
+        engine.set_bucket_schema(&people, Some(&schema)).unwrap();
+        assert_eq!(engine.bucket_schema(&people).unwrap(), Some(schema));
+        // Tenants have their own bucket of the same name
+        let tenant = engine.for_tenant(&TenantId::new("acme").unwrap());
+        assert_eq!(tenant.bucket_schema(&people).unwrap(), None);
+
+        engine.set_bucket_schema(&people, None).unwrap();
+        assert_eq!(engine.bucket_schema(&people).unwrap(), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! JSON documents stored as small objects
+//!
+//! Any inline object whose bytes are a JSON value can be treated as a
+//! document: patched in place without the client reading and rewriting it,
+//! and read back with only some of its fields. A patch is read, applied,
+//! and written back with the version it read as a precondition, retrying if
+//! another write got in between, so concurrent patches never lose updates.
+
+use serde_json::{Map, Value};
+use wfldb_core::*;
+use crate::Storage;
+
+/// Attempts at a patch before giving up on a heavily contended document
+const MAX_PATCH_ATTEMPTS: usize = 8;
+
+/// Partial update of a JSON document
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocumentPatch {
+    /// RFC 7396 JSON Merge Patch; a missing document is patched from `null`
+    Merge(Value),
+    /// Set the value at an RFC 6901 JSON Pointer. The parent must exist;
+    /// `-` as the last token appends to an array
+    Pointer { pointer: String, value: Value },
+}
+
+/// Apply an RFC 7396 merge patch to `target`
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(fields) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let object = target.as_object_mut().unwrap();
+    for (name, value) in fields {
+        if value.is_null() {
+            object.remove(name);
+        } else {
+            merge_patch(object.entry(name.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Unescape one JSON Pointer reference token
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Set the value at `pointer`, adding the last token to its parent if needed
+pub fn set_pointer(target: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    if pointer.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let invalid = |reason: &str| WflDBError::InvalidDocument(format!("pointer '{}' {}", pointer, reason));
+    let (parent, last) = pointer.rsplit_once('/').ok_or_else(|| invalid("must start with '/'"))?;
+    let last = unescape(last);
+    match target.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(last, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) if last == "-" => {
+            array.push(value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => match last.parse::<usize>() {
+            Ok(index) if index < array.len() => {
+                array[index] = value;
+                Ok(())
+            }
+            Ok(index) if index == array.len() => {
+                array.push(value);
+                Ok(())
+            }
+            _ => Err(invalid("indexes past the end of an array")),
+        },
+        Some(_) => Err(invalid("descends into a scalar")),
+        None => Err(invalid("has no parent in the document")),
+    }
+}
+
+/// Select fields of a document
+///
+/// Each field is a top-level member name, or a JSON Pointer if it starts
+/// with `/`. The result keeps the selected values at their original paths
+/// and leaves out fields the document doesn't have.
+pub fn project(document: &Value, fields: &[&str]) -> Value {
+    let mut projected = Value::Object(Map::new());
+    for field in fields {
+        let pointer = if field.starts_with('/') {
+            field.to_string()
+        } else {
+            format!("/{}", field.replace('~', "~0").replace('/', "~1"))
+        };
+        let Some(value) = document.pointer(&pointer) else {
+            continue;
+        };
+        let mut slot = &mut projected;
+        let tokens: Vec<String> = pointer[1..].split('/').map(unescape).collect();
+        for (i, token) in tokens.iter().enumerate() {
+            let object = match slot {
+                Value::Object(object) => object,
+                // An earlier field already selected this whole subtree
+                _ => break,
+            };
+            if i + 1 == tokens.len() {
+                object.insert(token.clone(), value.clone());
+                break;
+            }
+            slot = object.entry(token.clone()).or_insert_with(|| Value::Object(Map::new()));
+        }
+    }
+    projected
+}
+
+/// Parse stored bytes as a document
+pub fn parse_document(data: &[u8]) -> Result<Value> {
+    serde_json::from_slice(data).map_err(|_| WflDBError::InvalidDocument("object is not a JSON document".to_string()))
+}
+
+impl Storage {
+    /// Apply a partial update to a JSON document, returning the new
+    /// metadata; the document is created by a merge patch if missing
+    pub fn patch_document(&self, bucket_id: &BucketId, key: &Key, patch: &DocumentPatch) -> Result<ObjectMetadata> {
+        self.patch_document_as(bucket_id, key, patch, None)
+    }
+
+    /// [`patch_document`](Self::patch_document) on behalf of `owner`
+    pub fn patch_document_as(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        patch: &DocumentPatch,
+        owner: Option<&str>,
+    ) -> Result<ObjectMetadata> {
+        self.patch_document_checked(bucket_id, key, patch, owner, |_| Ok(()))
+    }
+
+    /// [`patch_document_as`](Self::patch_document_as), with `check` seeing
+    /// each patched document before it's written; an error from `check`
+    /// leaves the document as it was
+    pub fn patch_document_checked(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        patch: &DocumentPatch,
+        owner: Option<&str>,
+        check: impl Fn(&Value) -> Result<()>,
+    ) -> Result<ObjectMetadata> {
+        for _ in 0..MAX_PATCH_ATTEMPTS {
+            let (mut document, precondition) = match self.get_versioned(bucket_id, key)? {
+                Some((data, metadata)) => {
+                    if metadata.is_chunked() {
+                        return Err(WflDBError::InvalidDocument("documents must be stored inline".to_string()));
+                    }
+                    if metadata.customer_key_hash.is_some() {
+                        return Err(WflDBError::InvalidDocument("document is encrypted with a customer key".to_string()));
+                    }
+                    (parse_document(&data)?, Precondition::Version(metadata.version))
+                }
+                None if matches!(patch, DocumentPatch::Merge(_)) => (Value::Null, Precondition::Absent),
+                None => return Err(WflDBError::ObjectNotFound { key: key.as_str().to_string() }),
+            };
+            match patch {
+                DocumentPatch::Merge(merge) => merge_patch(&mut document, merge),
+                DocumentPatch::Pointer { pointer, value } => set_pointer(&mut document, pointer, value.clone())?,
+            }
+            check(&document)?;
+            let data = serde_json::to_vec(&document).map_err(WflDBError::Serialization)?;
+            if data.len() > self.engine().value_threshold() {
+                return Err(WflDBError::InvalidDocument(format!(
+                    "patched document exceeds the {} byte inline limit",
+                    self.engine().value_threshold()
+                )));
+            }
+            let op = TxnOp::Put { key: key.clone(), data, precondition: Some(precondition) };
+            match self.transact_as(bucket_id, vec![op], owner) {
+                Ok(mut results) => return Ok(results.remove(0).expect("put returns metadata")),
+                Err(WflDBError::PreconditionFailed { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(WflDBError::TxnConflict { key: key.as_str().to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageEngine;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_patch_follows_rfc_7396() {
+        let mut doc = json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["a"]});
+        merge_patch(&mut doc, &json!({"title": "Hello!", "author": {"familyName": null}, "tags": ["b"], "n": 1}));
+        assert_eq!(doc, json!({"title": "Hello!", "author": {"givenName": "John"}, "tags": ["b"], "n": 1}));
+    }
+
+    #[test]
+    fn test_set_pointer_and_project() {
+        let mut doc = json!({"a": {"b": 1}, "list": [1, 2], "x~y": true});
+        set_pointer(&mut doc, "/a/c", json!(2)).unwrap();
+        set_pointer(&mut doc, "/list/-", json!(3)).unwrap();
+        set_pointer(&mut doc, "/list/0", json!(0)).unwrap();
+        assert!(set_pointer(&mut doc, "/missing/child", json!(1)).is_err());
+        assert_eq!(doc["a"], json!({"b": 1, "c": 2}));
+        assert_eq!(doc["list"], json!([0, 2, 3]));
+
+        let projected = project(&doc, &["/a/c", "x~y", "absent"]);
+        assert_eq!(projected, json!({"a": {"c": 2}, "x~y": true}));
+    }
+
+    #[test]
+    fn test_patch_document_round_trip() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let bucket = BucketId::new("docs").unwrap();
+        let key = Key::new("user/1").unwrap();
+
+        storage.patch_document(&bucket, &key, &DocumentPatch::Merge(json!({"name": "Ada"}))).unwrap();
+        let pointer = DocumentPatch::Pointer { pointer: "/langs".to_string(), value: json!(["en"]) };
+        storage.patch_document(&bucket, &key, &pointer).unwrap();
+        let stored = parse_document(&storage.get_object(&bucket, &key).unwrap().unwrap()).unwrap();
+        assert_eq!(stored, json!({"name": "Ada", "langs": ["en"]}));
+
+        storage.put_object(&bucket, &Key::new("raw").unwrap(), b"\xff not json").unwrap();
+        let raw = storage.patch_document(&bucket, &Key::new("raw").unwrap(), &DocumentPatch::Merge(json!({})));
+        assert!(matches!(raw, Err(WflDBError::InvalidDocument(_))));
+
+        // A refused patch leaves the document alone
+        let refuse = |doc: &Value| match doc.get("age") {
+            Some(_) => Err(WflDBError::InvalidDocument("no ages".to_string())),
+            None => Ok(()),
+        };
+        let aged = storage.patch_document_checked(&bucket, &key, &DocumentPatch::Merge(json!({"age": 36})), None, refuse);
+        assert!(aged.is_err());
+        assert_eq!(parse_document(&storage.get_object(&bucket, &key).unwrap().unwrap()).unwrap(), stored);
+    }
+}
@@ -0,0 +1,242 @@
+//! Object expiry
+//!
+//! An object written with a TTL, or touched since, expires at a time kept
+//! under `expiry/{bucket}/{key}` in the system partition, with the
+//! namespaced bucket name, together with the version it applies to. A later
+//! write of the key makes a version the record doesn't describe, so new
+//! content only expires if it gets a TTL of its own. Touching moves the
+//! expiry of the current version without rewriting the object.
+//!
+//! Expired objects read as missing and are deleted by
+//! [`purge_expired_objects`], which finds them through a second entry per
+//! record, `expiry-due/{expires_at_ms}/{bucket}/{key}`, ordered by time.
+
+use fjall::Batch;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wfldb_core::*;
+use crate::{Storage, StorageEngine, SystemPartition};
+
+pub(crate) const EXPIRY_PREFIX: &str = "expiry/";
+const DUE_PREFIX: &str = "expiry-due/";
+
+/// When one version of a key expires
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectExpiry {
+    pub version: Version,
+    pub expires_at_ms: u64,
+}
+
+impl ObjectExpiry {
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_at_ms
+    }
+}
+
+fn expiry_key(bucket: &str, key: &str) -> String {
+    format!("{}{}/{}", EXPIRY_PREFIX, bucket, key)
+}
+
+/// Zero-padded so entries sort by time
+fn due_key(bucket: &str, key: &str, expires_at_ms: u64) -> String {
+    format!("{}{:020}/{}/{}", DUE_PREFIX, expires_at_ms, bucket, key)
+}
+
+fn load(system: &SystemPartition, bucket: &str, key: &str) -> Result<Option<ObjectExpiry>> {
+    match system.get(&expiry_key(bucket, key))? {
+        Some(data) => serde_json::from_slice(&data).map(Some).map_err(WflDBError::Serialization),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Expiry record of a key whatever version it describes, for
+/// [`stage_expiry`] to replace
+pub(crate) fn load_expiry(system: &SystemPartition, bucket: &str, key: &Key) -> Result<Option<ObjectExpiry>> {
+    load(system, bucket, key.as_str())
+}
+
+/// Add replacing a key's expiry record, `previous`, with `expiry` to a batch
+pub(crate) fn stage_expiry(
+    batch: &mut Batch,
+    system: &SystemPartition,
+    bucket: &str,
+    key: &Key,
+    previous: Option<&ObjectExpiry>,
+    expiry: &ObjectExpiry,
+) -> Result<()> {
+    if let Some(previous) = previous {
+        batch.remove(system.partition(), due_key(bucket, key.as_str(), previous.expires_at_ms));
+    }
+    let encoded = serde_json::to_vec(expiry).map_err(WflDBError::Serialization)?;
+    batch.insert(system.partition(), expiry_key(bucket, key.as_str()), encoded);
+    batch.insert(system.partition(), due_key(bucket, key.as_str(), expiry.expires_at_ms), []);
+    Ok(())
+}
+
+/// Durably remove a key's expiry record
+fn remove(engine: &StorageEngine, system: &SystemPartition, bucket: &str, key: &str, expiry: &ObjectExpiry) -> Result<()> {
+    let mut batch = engine.keyspace().batch();
+    batch.remove(system.partition(), expiry_key(bucket, key));
+    batch.remove(system.partition(), due_key(bucket, key, expiry.expires_at_ms));
+    batch.commit().map_err(WflDBError::storage)?;
+    engine.persist()
+}
+
+impl Storage {
+    /// When a key's content at `version` expires, if it was given a TTL
+    pub fn object_expiry(&self, bucket_id: &BucketId, key: &Key, version: &Version) -> Result<Option<u64>> {
+        let engine = self.engine();
+        let expiry = load(&engine.system()?, &engine.namespaced(bucket_id), key.as_str())?;
+        Ok(expiry.filter(|expiry| &expiry.version == version).map(|expiry| expiry.expires_at_ms))
+    }
+
+    /// Make a key's current content expire at `expires_at_ms` without
+    /// rewriting it; a transaction of one [`TxnOp::Touch`]
+    pub fn touch(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        expires_at_ms: u64,
+        precondition: Option<Precondition>,
+    ) -> Result<ObjectMetadata> {
+        let op = TxnOp::Touch { key: key.clone(), expires_at_ms, precondition };
+        self.transact(bucket_id, vec![op])?
+            .pop()
+            .flatten()
+            .ok_or_else(|| WflDBError::internal("touch returned no metadata"))
+    }
+}
+
+/// Delete up to `limit` objects of every namespace that expired by
+/// `now_ms`, returning how many were deleted
+///
+/// Deletes go through [`Storage::transact`] on the expired version, so a
+/// write racing the purge keeps its content and the journal tells replicas.
+pub fn purge_expired_objects(engine: &StorageEngine, now_ms: u64, limit: usize) -> Result<usize> {
+    let system = engine.system()?;
+    let mut purged = 0;
+    for (storage_key, _) in system.scan_range(DUE_PREFIX, &due_key("", "", now_ms.saturating_add(1)), limit)? {
+        let entry = &storage_key[DUE_PREFIX.len()..];
+        let parsed = entry.split_once('/').and_then(|(at, rest)| Some((at.parse::<u64>().ok()?, rest.split_once('/')?)));
+        let Some((due_at, (bucket, key))) = parsed else {
+            system.delete(&storage_key)?;
+            continue;
+        };
+        let (scoped, bucket_id) = engine.resolve_namespaced(bucket)?;
+        let key = Key::new(key)?;
+
+        // A touch moves the record before the purge reads it, or is refused
+        // because the record has expired; either way this one is current
+        let expired = {
+            let _lock = engine.key_locks().lock(bucket, [&key]);
+            match load(&system, bucket, key.as_str())? {
+                Some(expiry) if expiry.expires_at_ms == due_at => {
+                    let current = scoped.bucket(&bucket_id)?.get_metadata(&key)?;
+                    if current.is_some_and(|metadata| metadata.version == expiry.version) {
+                        Some(expiry)
+                    } else {
+                        // Rewritten or deleted since; the record is stale
+                        remove(engine, &system, bucket, key.as_str(), &expiry)?;
+                        None
+                    }
+                }
+                _ => {
+                    system.delete(&storage_key)?;
+                    None
+                }
+            }
+        };
+        let Some(expiry) = expired else {
+            continue;
+        };
+
+        let delete = TxnOp::Delete { key: key.clone(), precondition: Some(Precondition::Version(expiry.version.clone())) };
+        match Storage::new(scoped).transact(&bucket_id, vec![delete]) {
+            Ok(_) => purged += 1,
+            Err(WflDBError::PreconditionFailed { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        let _lock = engine.key_locks().lock(bucket, [&key]);
+        if load(&system, bucket, key.as_str())?.as_ref() == Some(&expiry) {
+            remove(engine, &system, bucket, key.as_str(), &expiry)?;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch_and_purge() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("sessions").unwrap();
+        let key = Key::new("abc").unwrap();
+        let missing = Key::new("missing").unwrap();
+
+        let written = storage.put_object(&bucket, &key, b"state").unwrap();
+        assert_eq!(storage.object_expiry(&bucket, &key, &written.version).unwrap(), None);
+        assert!(matches!(storage.touch(&bucket, &missing, 1_000, None), Err(WflDBError::ObjectNotFound { .. })));
+        let stale = Some(Precondition::Version(Version::new()));
+        assert!(matches!(storage.touch(&bucket, &key, 1_000, stale), Err(WflDBError::PreconditionFailed { .. })));
+
+        let now = now_ms();
+        let touched = storage.touch(&bucket, &key, now + 1_000, None).unwrap();
+        assert_eq!(touched.version, written.version);
+        storage.touch(&bucket, &key, now + 60_000, Some(Precondition::Version(written.version.clone()))).unwrap();
+        assert_eq!(storage.object_expiry(&bucket, &key, &written.version).unwrap(), Some(now + 60_000));
+
+        // Only the latest expiry counts
+        assert_eq!(purge_expired_objects(&engine, now + 1_000, 100).unwrap(), 0);
+        assert!(storage.get_object(&bucket, &key).unwrap().is_some());
+        assert_eq!(purge_expired_objects(&engine, now + 60_000, 100).unwrap(), 1);
+        assert!(storage.get_object(&bucket, &key).unwrap().is_none());
+        assert!(engine.system().unwrap().scan_prefix("expiry").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_drops_expiry() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("cache").unwrap();
+        let key = Key::new("page").unwrap();
+
+        storage.put_object(&bucket, &key, b"old").unwrap();
+        storage.touch(&bucket, &key, 5_000, None).unwrap();
+        // Expired content can't be kept alive, only rewritten
+        assert!(matches!(storage.touch(&bucket, &key, u64::MAX, None), Err(WflDBError::ObjectNotFound { .. })));
+
+        let rewritten = storage.put_object(&bucket, &key, b"new").unwrap();
+        assert_eq!(storage.object_expiry(&bucket, &key, &rewritten.version).unwrap(), None);
+        assert_eq!(purge_expired_objects(&engine, 10_000, 100).unwrap(), 0);
+        assert_eq!(storage.get_object(&bucket, &key).unwrap(), Some(b"new".to_vec()));
+        assert!(engine.system().unwrap().scan_prefix("expiry").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_put_with_ttl_commits_expiry_with_object() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("sessions").unwrap();
+        let key = Key::new("abc").unwrap();
+        let now = now_ms();
+
+        crate::bucket::arm_crash("put_small");
+        assert!(storage.put_object_if(&bucket, &key, b"state", None, Default::default(), None, None, Some(now)).is_err());
+        assert!(storage.get_object(&bucket, &key).unwrap().is_none());
+        assert!(engine.system().unwrap().scan_prefix("expiry").unwrap().is_empty());
+
+        let written = storage
+            .put_object_if(&bucket, &key, b"state", None, Default::default(), None, None, Some(now + 1_000))
+            .unwrap();
+        assert_eq!(storage.object_expiry(&bucket, &key, &written.version).unwrap(), Some(now + 1_000));
+        assert_eq!(purge_expired_objects(&engine, now + 1_000, 100).unwrap(), 1);
+        assert!(storage.get_object(&bucket, &key).unwrap().is_none());
+    }
+}
@@ -0,0 +1,343 @@
+//! Portable bucket archives
+//!
+//! [`export_bucket`] writes a bucket's objects to a directory whose format
+//! doesn't depend on the storage layout, so [`import_bucket`] on another
+//! wflDB version can load it and other tools can read it:
+//!
+//! ```text
+//! {dir}/manifest.json         format, format_version, bucket, exported_at_ms, and totals
+//! {dir}/objects.jsonl         one object per line, in key order
+//! {dir}/chunks/{hh}/{hash}    data, named by its BLAKE3 hex hash under its first byte
+//! ```
+//!
+//! An object line holds `key`, `size`, `version`, `created_at_ms`, and when
+//! set `owner`, `checksums`, `expires_at_ms` and `customer_key_hash` (for
+//! data encrypted with a customer key), plus `chunks`: the hashes
+//! of the files whose concatenation is the object's data. A small object is
+//! a single chunk (none when empty), so every object reads the same way,
+//! and chunks shared between objects are stored once. The manifest is
+//! written last; a directory without one holds an interrupted export.
+//!
+//! Readers refuse a newer `format_version` than they know; additions that
+//! older readers can ignore, such as new object fields, keep the version.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use wfldb_core::*;
+use crate::Storage;
+
+/// Name of the format in an archive's manifest
+pub const ARCHIVE_FORMAT: &str = "wfldb-bucket-archive";
+/// Archive format version this build writes, and the newest it reads
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const OBJECTS_FILE: &str = "objects.jsonl";
+const CHUNKS_DIR: &str = "chunks";
+/// Keys listed per page while exporting
+const EXPORT_PAGE: usize = 1000;
+
+/// Contents of an archive's `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format: String,
+    pub format_version: u32,
+    /// Bucket the archive was exported from
+    pub bucket: String,
+    pub exported_at_ms: u64,
+    pub objects: u64,
+    /// Distinct chunk files
+    pub chunks: u64,
+    /// Total size of the objects
+    pub bytes: u64,
+}
+
+/// One line of an archive's `objects.jsonl`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedObject {
+    pub key: Key,
+    pub size: u64,
+    pub version: Version,
+    pub created_at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<ChecksumAlgorithm, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_key_hash: Option<ContentHash>,
+    /// Hex hashes of the chunk files holding the data, in order
+    pub chunks: Vec<String>,
+}
+
+/// Outcome of importing an archive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: u64,
+    /// Objects the bucket already held at the archived version or later
+    pub skipped: u64,
+    /// Objects whose expiry had passed by the time they were read
+    pub expired: u64,
+}
+
+fn chunk_path(dir: &Path, hex: &str) -> PathBuf {
+    dir.join(CHUNKS_DIR).join(&hex[..2]).join(hex)
+}
+
+/// Write `bucket_id` as an archive into `dir`, which must be empty or not
+/// exist yet
+///
+/// Objects are read one at a time, each at a single version; writes made
+/// while the export runs may or may not be included. Expired objects are
+/// left out.
+pub fn export_bucket(storage: &Storage, bucket_id: &BucketId, dir: impl AsRef<Path>) -> Result<ArchiveManifest> {
+    let dir = dir.as_ref();
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(WflDBError::InvalidRequest(format!("export directory {} is not empty", dir.display())));
+    }
+    fs::create_dir_all(dir.join(CHUNKS_DIR))?;
+    let bucket = storage.engine().bucket(bucket_id)?;
+    let now_ms = crate::expiry::now_ms();
+
+    let mut manifest = ArchiveManifest {
+        format: ARCHIVE_FORMAT.to_string(),
+        format_version: ARCHIVE_FORMAT_VERSION,
+        bucket: bucket_id.as_str().to_string(),
+        exported_at_ms: now_ms,
+        objects: 0,
+        chunks: 0,
+        bytes: 0,
+    };
+    let mut written = HashSet::new();
+    let mut write_chunk = |hash: &ContentHash, data: &[u8]| -> Result<String> {
+        let hex = hash.to_hex();
+        if written.insert(hex.clone()) {
+            let path = chunk_path(dir, &hex);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, data)?;
+        }
+        Ok(hex)
+    };
+
+    let mut objects = BufWriter::new(File::create(dir.join(OBJECTS_FILE))?);
+    let mut start_after = None;
+    loop {
+        let keys = storage.list_objects_after(bucket_id, "", start_after.as_deref(), Some(EXPORT_PAGE))?;
+        for key in &keys {
+            // A chunked object rewritten mid-read releases the chunks being
+            // copied; start it over at its new version
+            let archived = 'read: loop {
+                let Some((metadata, data)) = bucket.get_record(key)? else {
+                    break None;
+                };
+                let expires_at_ms = storage.object_expiry(bucket_id, key, &metadata.version)?;
+                if expires_at_ms.is_some_and(|expires_at_ms| now_ms >= expires_at_ms) {
+                    break None;
+                }
+                let chunks = match (&metadata.chunk_manifest, data) {
+                    (Some(chunk_manifest), _) => {
+                        let mut chunks = Vec::with_capacity(chunk_manifest.chunks.len());
+                        for hash in &chunk_manifest.chunks {
+                            match bucket.get_chunk(hash)? {
+                                Some(data) => chunks.push(write_chunk(hash, &data)?),
+                                None if bucket.get_metadata(key)?.is_some_and(|m| m.version != metadata.version) => {
+                                    continue 'read;
+                                }
+                                None => return Err(WflDBError::MissingChunks(vec![hash.to_hex()])),
+                            }
+                        }
+                        chunks
+                    }
+                    (None, Some(data)) if data.is_empty() => Vec::new(),
+                    (None, Some(data)) => vec![write_chunk(&ContentHash::new(&data), &data)?],
+                    (None, None) => return Err(WflDBError::Corrupt(format!("object {} has no data", key))),
+                };
+                let created_at = metadata.created_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                break Some(ArchivedObject {
+                    key: key.clone(),
+                    size: metadata.size,
+                    version: metadata.version,
+                    created_at_ms: created_at.as_millis() as u64,
+                    owner: metadata.owner,
+                    checksums: metadata.checksums,
+                    expires_at_ms,
+                    customer_key_hash: metadata.customer_key_hash,
+                    chunks,
+                });
+            };
+            if let Some(archived) = archived {
+                serde_json::to_writer(&mut objects, &archived)?;
+                objects.write_all(b"\n")?;
+                manifest.objects += 1;
+                manifest.bytes += archived.size;
+            }
+        }
+        if keys.len() < EXPORT_PAGE {
+            break;
+        }
+        start_after = keys.last().map(|key| key.as_str().to_string());
+    }
+    objects.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    manifest.chunks = written.len() as u64;
+
+    let mut file = File::create(dir.join(MANIFEST_FILE))?;
+    serde_json::to_writer_pretty(&mut file, &manifest)?;
+    file.sync_all()?;
+    Ok(manifest)
+}
+
+/// Read and check an archive's manifest
+pub fn read_archive_manifest(dir: impl AsRef<Path>) -> Result<ArchiveManifest> {
+    let path = dir.as_ref().join(MANIFEST_FILE);
+    let file = File::open(&path).map_err(|e| {
+        WflDBError::InvalidRequest(format!("{} is not a complete archive: {}", path.display(), e))
+    })?;
+    let manifest: ArchiveManifest = serde_json::from_reader(BufReader::new(file))?;
+    if manifest.format != ARCHIVE_FORMAT {
+        return Err(WflDBError::InvalidRequest(format!("unknown archive format '{}'", manifest.format)));
+    }
+    if manifest.format_version > ARCHIVE_FORMAT_VERSION {
+        return Err(WflDBError::InvalidRequest(format!(
+            "archive format version {} is newer than this build reads ({})",
+            manifest.format_version, ARCHIVE_FORMAT_VERSION
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Load the archive in `dir` into `bucket_id`, which needn't be the bucket
+/// it was exported from
+///
+/// Objects keep their versions, owners, checksums and expiry, and go in
+/// like replicated writes: a key already holding that version or a later
+/// write or delete is skipped, so an interrupted import can be run again.
+/// Chunk files are checked against their hashes before anything is written.
+pub fn import_bucket(storage: &Storage, bucket_id: &BucketId, dir: impl AsRef<Path>) -> Result<ImportSummary> {
+    let dir = dir.as_ref();
+    read_archive_manifest(dir)?;
+    let mut summary = ImportSummary::default();
+    for line in BufReader::new(File::open(dir.join(OBJECTS_FILE))?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object: ArchivedObject = serde_json::from_str(&line)?;
+        if object.expires_at_ms.is_some_and(|expires_at_ms| crate::expiry::now_ms() >= expires_at_ms) {
+            summary.expired += 1;
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(object.size as usize);
+        for hex in &object.chunks {
+            // Parsed first so a malformed name can't point outside the archive
+            let hash = ContentHash::from_hex(hex)?;
+            let chunk = fs::read(chunk_path(dir, &hash.to_hex()))?;
+            if ContentHash::new(&chunk) != hash {
+                return Err(WflDBError::Corrupt(format!("archive chunk {} does not match its hash", hex)));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        if data.len() as u64 != object.size {
+            return Err(WflDBError::Corrupt(format!("archived object {} is not {} bytes", object.key, object.size)));
+        }
+
+        let version = object.version.clone();
+        let owner = object.owner.as_deref();
+        match storage.put_object_version_with_checksums(
+            bucket_id,
+            &object.key,
+            &data,
+            owner,
+            version,
+            object.checksums,
+            object.customer_key_hash,
+        )? {
+            Some(metadata) => {
+                if let Some(expires_at_ms) = object.expires_at_ms {
+                    let written = Some(Precondition::Version(metadata.version));
+                    match storage.touch(bucket_id, &object.key, expires_at_ms, written) {
+                        Ok(_) | Err(WflDBError::PreconditionFailed { .. } | WflDBError::ObjectNotFound { .. }) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                summary.imported += 1;
+            }
+            None => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageEngine;
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let source = BucketId::new("photos").unwrap();
+        let target = BucketId::new("restored").unwrap();
+        let small = Key::new("notes/a.txt").unwrap();
+        let large = Key::new("raw/b.bin").unwrap();
+        let empty = Key::new("empty").unwrap();
+        let big: Vec<u8> = (0..(crate::MAX_CHUNK_SIZE + 100)).map(|i| (i % 251) as u8).collect();
+
+        let checksums = BTreeMap::from([(ChecksumAlgorithm::Crc32c, "AAAAAA==".to_string())]);
+        storage.put_object_with_checksums_as(&source, &small, b"hello", Some("abc"), checksums.clone()).unwrap();
+        let large_version = storage.put_object(&source, &large, &big).unwrap().version;
+        storage.put_object(&source, &empty, b"").unwrap();
+        storage.touch(&source, &small, u64::MAX, None).unwrap();
+
+        let archive = tempfile::tempdir().unwrap();
+        let dir = archive.path().join("photos");
+        let manifest = export_bucket(&storage, &source, &dir).unwrap();
+        assert_eq!((manifest.objects, manifest.chunks), (3, 3));
+        assert_eq!(manifest.bytes, big.len() as u64 + 5);
+        assert_eq!(read_archive_manifest(&dir).unwrap(), manifest);
+        assert!(matches!(export_bucket(&storage, &source, &dir), Err(WflDBError::InvalidRequest(_))));
+
+        let summary = import_bucket(&storage, &target, &dir).unwrap();
+        assert_eq!(summary, ImportSummary { imported: 3, skipped: 0, expired: 0 });
+        assert_eq!(storage.get_object(&target, &small).unwrap().unwrap(), b"hello");
+        assert_eq!(storage.get_object(&target, &large).unwrap().unwrap(), big);
+        assert_eq!(storage.get_object(&target, &empty).unwrap().unwrap(), b"");
+        let metadata = storage.get_metadata(&target, &small).unwrap().unwrap();
+        assert_eq!((metadata.owner.as_deref(), metadata.checksums), (Some("abc"), checksums));
+        assert_eq!(storage.object_expiry(&target, &small, &metadata.version).unwrap(), Some(u64::MAX));
+        assert_eq!(storage.get_metadata(&target, &large).unwrap().unwrap().version, large_version);
+
+        // Importing again changes nothing
+        assert_eq!(import_bucket(&storage, &target, &dir).unwrap().skipped, 3);
+    }
+
+    #[test]
+    fn test_import_checks_archive() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let bucket = BucketId::new("docs").unwrap();
+        storage.put_object(&bucket, &Key::new("a").unwrap(), b"original").unwrap();
+
+        let archive = tempfile::tempdir().unwrap();
+        let dir = archive.path().join("docs");
+        export_bucket(&storage, &bucket, &dir).unwrap();
+        let target = BucketId::new("copy").unwrap();
+
+        let hex = ContentHash::new(b"original").to_hex();
+        fs::write(chunk_path(&dir, &hex), b"tampered").unwrap();
+        assert!(matches!(import_bucket(&storage, &target, &dir), Err(WflDBError::Corrupt(_))));
+
+        let manifest = fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), manifest.replace("\"format_version\": 1", "\"format_version\": 99")).unwrap();
+        assert!(matches!(import_bucket(&storage, &target, &dir), Err(WflDBError::InvalidRequest(_))));
+        fs::remove_file(dir.join(MANIFEST_FILE)).unwrap();
+        assert!(matches!(read_archive_manifest(&dir), Err(WflDBError::InvalidRequest(_))));
+        assert!(storage.get_object(&target, &Key::new("a").unwrap()).unwrap().is_none());
+    }
+}
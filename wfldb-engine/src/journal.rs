@@ -0,0 +1,471 @@
+//! Change journal for archival and point-in-time recovery
+//!
+//! Every object mutation made through [`Storage`](crate::Storage) is appended
+//! to a segment file as a self-contained record (puts carry the full object
+//! body), so replaying records in sequence order onto an older copy of the
+//! data directory converges on the state at the end of the log. Replay is
+//! idempotent, which lets a restore start from the last checkpoint written
+//! before a snapshot was taken rather than the exact sequence it contains.
+//!
+//! Segments are named `{first_seq:020}.wal` and hold records in the framing
+//! of [`ChangeRecord::encode_frame`]; a torn record at the tail of the active
+//! segment is truncated away on open.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wfldb_core::*;
+use crate::{Storage, SystemPartition};
+
+/// Segment size at which the active segment is sealed
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// System partition key holding the last sequence number sealed into a segment
+pub const JOURNAL_CHECKPOINT_KEY: &str = "journal/checkpoint";
+
+const SEGMENT_EXTENSION: &str = "wal";
+
+/// A sealed segment ready to be archived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedSegment {
+    pub path: PathBuf,
+    pub first_seq: u64,
+}
+
+impl SealedSegment {
+    /// File name, also used as the archive object name
+    pub fn name(&self) -> String {
+        segment_name(self.first_seq)
+    }
+}
+
+fn segment_name(first_seq: u64) -> String {
+    format!("{:020}.{}", first_seq, SEGMENT_EXTENSION)
+}
+
+struct ActiveSegment {
+    file: File,
+    first_seq: u64,
+    bytes: u64,
+    next_seq: u64,
+}
+
+/// Append-only, segmented log of object mutations
+pub struct ChangeJournal {
+    dir: PathBuf,
+    segment_bytes: u64,
+    active: Mutex<ActiveSegment>,
+    checkpoint: Option<SystemPartition>,
+}
+
+impl ChangeJournal {
+    /// Open the journal in `dir`, resuming after the last intact record
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_after(dir.as_ref(), 0)
+    }
+
+    /// Open the journal, recording checkpoints in the system partition so a
+    /// snapshot of the data directory knows where its replay should start.
+    /// Numbering continues after the stored checkpoint, so a journal
+    /// recreated after a restore never reuses archived segment names.
+    pub fn open_with_checkpoint(dir: impl AsRef<Path>, system: SystemPartition) -> Result<Self> {
+        let mut journal = Self::open_after(dir.as_ref(), journal_checkpoint(&system)?)?;
+        journal.checkpoint = Some(system);
+        Ok(journal)
+    }
+
+    fn open_after(dir: &Path, last_seq: u64) -> Result<Self> {
+        let dir = dir.to_path_buf();
+        fs::create_dir_all(&dir).map_err(io_error)?;
+
+        let segments = list_segments(&dir)?;
+        let active = match segments.last() {
+            Some(&(first_seq, ref path)) => {
+                let (records, valid_len) = read_segment(path)?;
+                let file = OpenOptions::new().append(true).open(path).map_err(io_error)?;
+                // Drop a record torn by a crash mid-append
+                file.set_len(valid_len).map_err(io_error)?;
+                ActiveSegment {
+                    file,
+                    first_seq,
+                    bytes: valid_len,
+                    next_seq: records.last().map(|r| r.seq + 1).unwrap_or(first_seq),
+                }
+            }
+            None => create_segment(&dir, last_seq + 1)?,
+        };
+        let active = if active.bytes == 0 && active.next_seq <= last_seq {
+            fs::remove_file(dir.join(segment_name(active.first_seq))).map_err(io_error)?;
+            create_segment(&dir, last_seq + 1)?
+        } else {
+            active
+        };
+
+        Ok(ChangeJournal {
+            dir,
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            active: Mutex::new(active),
+            checkpoint: None,
+        })
+    }
+
+    /// Seal the active segment once it reaches `bytes`
+    pub fn with_segment_bytes(mut self, bytes: u64) -> Self {
+        self.segment_bytes = bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sequence number the next record will get
+    pub fn next_seq(&self) -> u64 {
+        self.active.lock().unwrap().next_seq
+    }
+
+    /// Durably append a mutation, returning its sequence number. `bucket` is
+    /// the namespaced name from [`StorageEngine::namespaced`](crate::StorageEngine::namespaced).
+    pub fn append(&self, bucket: &str, key: &Key, op: ChangeOp) -> Result<u64> {
+        self.append_versioned(bucket, key, op, None)
+    }
+
+    /// Append a change together with the version it wrote, so a replica can
+    /// tell it apart from later and earlier changes to the key
+    pub fn append_versioned(&self, bucket: &str, key: &Key, op: ChangeOp, version: Option<Version>) -> Result<u64> {
+        let mut active = self.active.lock().unwrap();
+        let record = ChangeRecord {
+            seq: active.next_seq,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            bucket: bucket.to_string(),
+            key: key.as_str().to_string(),
+            op,
+            version,
+        };
+        let frame = record.encode_frame();
+
+        active.file.write_all(&frame).map_err(io_error)?;
+        active.file.sync_data().map_err(io_error)?;
+        active.bytes += frame.len() as u64;
+        active.next_seq += 1;
+
+        if active.bytes >= self.segment_bytes {
+            self.seal(&mut active)?;
+        }
+        Ok(record.seq)
+    }
+
+    /// Seal the active segment if it holds any records
+    pub fn rotate(&self) -> Result<()> {
+        let mut active = self.active.lock().unwrap();
+        if active.bytes > 0 {
+            self.seal(&mut active)?;
+        }
+        Ok(())
+    }
+
+    fn seal(&self, active: &mut ActiveSegment) -> Result<()> {
+        let last_seq = active.next_seq - 1;
+        *active = create_segment(&self.dir, active.next_seq)?;
+        if let Some(system) = &self.checkpoint {
+            system.put(JOURNAL_CHECKPOINT_KEY, &last_seq.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Segments no longer being written to, oldest first
+    pub fn sealed_segments(&self) -> Result<Vec<SealedSegment>> {
+        let active_first = self.active.lock().unwrap().first_seq;
+        Ok(list_segments(&self.dir)?
+            .into_iter()
+            .filter(|(first_seq, _)| *first_seq != active_first)
+            .map(|(first_seq, path)| SealedSegment { path, first_seq })
+            .collect())
+    }
+
+    /// Sequence number of the oldest record still held locally; records
+    /// before it have been archived and removed
+    pub fn oldest_seq(&self) -> Result<u64> {
+        Ok(list_segments(&self.dir)?
+            .first()
+            .map(|(first_seq, _)| *first_seq)
+            .unwrap_or_else(|| self.next_seq()))
+    }
+
+    /// Up to `max_records` records after `after_seq`, optionally only those of
+    /// one bucket, with the sequence number to resume from. The cursor also
+    /// advances past records of other buckets, so pollers make progress.
+    pub fn read_after(
+        &self,
+        after_seq: u64,
+        bucket: Option<&str>,
+        max_records: usize,
+    ) -> Result<(Vec<ChangeRecord>, u64)> {
+        let segments = list_segments(&self.dir)?;
+        let mut records = Vec::new();
+        let mut cursor = after_seq;
+        for (i, (_, path)) in segments.iter().enumerate() {
+            // Every record in this segment precedes the next segment's first
+            if segments.get(i + 1).is_some_and(|(next_first, _)| *next_first <= after_seq + 1) {
+                continue;
+            }
+            let (segment_records, _) = read_segment(path)?;
+            for record in segment_records.into_iter().filter(|r| r.seq > after_seq) {
+                if records.len() >= max_records {
+                    return Ok((records, cursor));
+                }
+                cursor = record.seq;
+                if bucket.is_none_or(|bucket| record.bucket == bucket) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok((records, cursor))
+    }
+
+    /// Delete a sealed segment once it has been archived
+    pub fn remove_segment(&self, segment: &SealedSegment) -> Result<()> {
+        fs::remove_file(&segment.path).map_err(io_error)
+    }
+}
+
+/// Last sequence number sealed before the data directory was copied
+pub fn journal_checkpoint(system: &SystemPartition) -> Result<u64> {
+    Ok(system
+        .get(JOURNAL_CHECKPOINT_KEY)?
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .unwrap_or(0))
+}
+
+/// Outcome of replaying archived segments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub segments: usize,
+    pub applied: u64,
+    /// Highest sequence number seen, or the starting point if none were newer
+    pub last_seq: u64,
+}
+
+/// Apply every record in the segments under `dir` with a sequence number
+/// above the checkpoint stored in the system partition, then advance the
+/// checkpoint past them
+pub fn replay_segments(dir: impl AsRef<Path>, storage: &Storage) -> Result<ReplaySummary> {
+    let system = storage.engine().system()?;
+    let after_seq = journal_checkpoint(&system)?;
+    let mut summary = ReplaySummary {
+        last_seq: after_seq,
+        ..Default::default()
+    };
+    for (_, path) in list_segments(dir.as_ref())? {
+        summary.segments += 1;
+        let (records, _) = read_segment(&path)?;
+        for record in records.into_iter().filter(|r| r.seq > after_seq) {
+            let seq = record.seq;
+            apply_change(storage, record)?;
+            summary.applied += 1;
+            summary.last_seq = summary.last_seq.max(seq);
+        }
+    }
+    system.put(JOURNAL_CHECKPOINT_KEY, &summary.last_seq.to_le_bytes())?;
+    Ok(summary)
+}
+
+/// Apply one change record, from a replayed segment or a followed journal
+pub fn apply_change(storage: &Storage, record: ChangeRecord) -> Result<()> {
+    let (engine, bucket) = storage.engine().resolve_namespaced(&record.bucket)?;
+    let storage = Storage::new(engine);
+    let key = Key::new(&record.key)?;
+    // Versioned changes are dropped when the key already holds a later write
+    // or delete, so they apply in any order and at most once
+    match (record.op, record.version) {
+        (ChangeOp::Put { data, owner, customer_key_hash }, Some(version)) => {
+            let owner = owner.as_deref();
+            storage.put_object_version_with_checksums(&bucket, &key, &data, owner, version, BTreeMap::new(), customer_key_hash)?;
+        }
+        (ChangeOp::Put { data, owner, .. }, None) => {
+            storage.put_object_as(&bucket, &key, &data, owner.as_deref())?;
+        }
+        (ChangeOp::Delete, Some(version)) => {
+            storage.delete_object_version(&bucket, &key, version)?;
+        }
+        (ChangeOp::Delete, None) => storage.delete_object(&bucket, &key)?,
+        // Only spliced into a chunked copy; an inline or missing one fails
+        // the apply, as it would on the leader
+        (ChangeOp::WriteRange { offset, data, owner }, Some(version)) => {
+            storage.write_range_version(&bucket, &key, offset, &data, owner.as_deref(), version)?;
+        }
+        (ChangeOp::WriteRange { offset, data, owner }, None) => {
+            storage.write_range_as(&bucket, &key, offset, &data, owner.as_deref())?;
+        }
+        (ChangeOp::Chunk { data }, _) => {
+            storage.put_chunk(&bucket, &data)?;
+        }
+        (ChangeOp::Manifest { chunks, owner }, Some(version)) => {
+            storage.put_manifest_version(&bucket, &key, chunks, owner.as_deref(), version)?;
+        }
+        (ChangeOp::Manifest { chunks, owner }, None) => {
+            storage.put_manifest_as(&bucket, &key, chunks, owner.as_deref())?;
+        }
+        (ChangeOp::Copy { source }, _) => {
+            let source = storage.engine().bucket(&BucketId::new(&source)?)?;
+            storage.engine().bucket(&bucket)?.copy_object_from(&source, &key)?;
+        }
+    }
+    Ok(())
+}
+
+/// Decode the records of one segment file, with the length of its intact prefix
+pub fn read_segment(path: &Path) -> Result<(Vec<ChangeRecord>, u64)> {
+    let mut buf = Vec::new();
+    File::open(path).map_err(io_error)?.read_to_end(&mut buf).map_err(io_error)?;
+
+    let (records, valid_len) = decode_change_frames(&buf)?;
+    Ok((records, valid_len as u64))
+}
+
+fn list_segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(first_seq) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()) {
+            segments.push((first_seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn create_segment(dir: &Path, first_seq: u64) -> Result<ActiveSegment> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(segment_name(first_seq)))
+        .map_err(io_error)?;
+    Ok(ActiveSegment {
+        file,
+        first_seq,
+        bytes: 0,
+        next_seq: first_seq,
+    })
+}
+
+fn io_error(e: std::io::Error) -> WflDBError {
+    WflDBError::storage(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::StorageEngine;
+
+    fn ids() -> (BucketId, Key) {
+        (BucketId::new("photos").unwrap(), Key::new("cat.jpg").unwrap())
+    }
+
+    #[test]
+    fn test_append_rotate_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bucket, key) = ids();
+
+        let journal = ChangeJournal::open(dir.path()).unwrap();
+        let put = ChangeOp::Put { data: b"meow".to_vec(), owner: Some("abc".to_string()), customer_key_hash: None };
+        assert_eq!(journal.append(bucket.as_str(), &key, put.clone()).unwrap(), 1);
+        journal.rotate().unwrap();
+        assert_eq!(journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap(), 2);
+
+        let sealed = journal.sealed_segments().unwrap();
+        assert_eq!(sealed.len(), 1);
+        assert_eq!(sealed[0].name(), "00000000000000000001.wal");
+        let (records, _) = read_segment(&sealed[0].path).unwrap();
+        assert_eq!(records[0].op, put);
+        drop(journal);
+
+        let reopened = ChangeJournal::open(dir.path()).unwrap();
+        assert_eq!(reopened.next_seq(), 3);
+    }
+
+    #[test]
+    fn test_read_after_filters_and_advances_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bucket, key) = ids();
+        let other = BucketId::new("logs").unwrap();
+        let journal = ChangeJournal::open(dir.path()).unwrap();
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+        journal.rotate().unwrap();
+        journal.append(other.as_str(), &key, ChangeOp::Delete).unwrap();
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+
+        let (records, cursor) = journal.read_after(1, Some("photos"), 10).unwrap();
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(cursor, 3);
+
+        let (records, cursor) = journal.read_after(0, None, 2).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(cursor, 2);
+        assert_eq!(journal.read_after(3, None, 10).unwrap(), (vec![], 3));
+    }
+
+    #[test]
+    fn test_torn_tail_truncated_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bucket, key) = ids();
+        let journal = ChangeJournal::open(dir.path()).unwrap();
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+        drop(journal);
+
+        let path = dir.path().join(segment_name(1));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[9, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let journal = ChangeJournal::open(dir.path()).unwrap();
+        assert_eq!(journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap(), 2);
+        let (records, _) = read_segment(&path).unwrap();
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn test_replay_onto_empty_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bucket, key) = ids();
+        let (source, _source_dir) = StorageEngine::temp().unwrap();
+        let journal = Arc::new(ChangeJournal::open_with_checkpoint(dir.path(), source.system().unwrap()).unwrap());
+        let source = Storage::new(source.with_journal(journal.clone()));
+        source.put_object(&bucket, &key, b"meow").unwrap();
+        source.put_object(&bucket, &Key::new("dog.jpg").unwrap(), b"woof").unwrap();
+        source.delete_object(&bucket, &Key::new("dog.jpg").unwrap()).unwrap();
+        journal.rotate().unwrap();
+
+        let (restored, _restored_dir) = StorageEngine::temp().unwrap();
+        let restored = Storage::new(restored);
+        let summary = replay_segments(dir.path(), &restored).unwrap();
+        assert_eq!(summary.applied, 3);
+        assert_eq!(summary.last_seq, 3);
+        assert_eq!(restored.get_object(&bucket, &key).unwrap(), Some(b"meow".to_vec()));
+        assert_eq!(restored.get_object(&bucket, &Key::new("dog.jpg").unwrap()).unwrap(), None);
+
+        // The checkpoint advanced, so a second replay is a no-op
+        assert_eq!(replay_segments(dir.path(), &restored).unwrap().applied, 0);
+    }
+
+    #[test]
+    fn test_segment_sealed_at_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bucket, key) = ids();
+        let journal = ChangeJournal::open(dir.path()).unwrap().with_segment_bytes(1);
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+        assert_eq!(journal.sealed_segments().unwrap().len(), 2);
+    }
+}
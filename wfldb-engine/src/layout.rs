@@ -0,0 +1,225 @@
+//! Key layout of bucket partitions
+//!
+//! Every entry of a `{bucket}_main` partition is keyed by a one-byte tag
+//! saying what it holds, followed by its subject: the object key's raw bytes for
+//! metadata, inline data, tombstones and generations, and the raw 32-byte
+//! hash for chunks, their reference counts, and the bucket a cloned chunk
+//! lives in.
+//! The tag has a fixed length, so no object key can reach entries of
+//! another kind, and a prefix scan of one kind is a range scan of its tag
+//! followed by the prefix.
+//!
+//! Partitions written before this layout keyed entries with string
+//! prefixes (`meta:{key}`, `chunk:{hex}`, ...). The engine rewrites them
+//! with [`migrate_partition`] when it opens, and [`LAYOUT_KEY`] records the
+//! layout a partition is in. Layout 2 also stores metadata in
+//! [`ObjectMetadata::encode`]'s binary form instead of JSON, and layout 3
+//! keeps a small object's data in its metadata record rather than a
+//! separate [`DATA`] entry; migrating re-encodes the older records.
+
+use fjall::{Keyspace, PartitionHandle};
+use wfldb_core::*;
+
+/// Object metadata, by object key
+pub(crate) const META: u8 = 1;
+/// Inline object data, by object key, before layout 3 moved it into the
+/// metadata record
+pub(crate) const DATA: u8 = 2;
+/// Chunk data, by hash
+pub(crate) const CHUNK: u8 = 3;
+/// Chunk reference counts (u32 LE), by hash
+pub(crate) const CHUNK_REF: u8 = 4;
+/// Bucket holding a cloned chunk's data, by hash
+pub(crate) const CHUNK_HOME: u8 = 5;
+/// Version of the delete that removed an object, by object key
+pub(crate) const TOMBSTONE: u8 = 6;
+/// Generation of the latest write of an object (u64 LE), by object key;
+/// kept when the object is deleted so a recreated key counts on
+pub(crate) const GENERATION: u8 = 7;
+
+/// Entry holding the partition's layout version; tag 0 keys nothing else
+pub(crate) const LAYOUT_KEY: &[u8] = b"\x00layout";
+const LAYOUT_VERSION: u32 = 3;
+
+/// Legacy prefixes and the tags replacing them; `chunk:` comes after the
+/// longer prefixes it would otherwise shadow
+const LEGACY_PREFIXES: [(&str, u8); 6] = [
+    ("meta:", META),
+    ("data:", DATA),
+    ("chunkref:", CHUNK_REF),
+    ("chunkhome:", CHUNK_HOME),
+    ("chunk:", CHUNK),
+    ("tomb:", TOMBSTONE),
+];
+
+/// Entries moved per write batch while migrating
+const MIGRATION_BATCH: usize = 1000;
+
+/// Key of the `tag` entry for an object key's bytes
+pub(crate) fn object_entry(tag: u8, key: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(1 + key.len());
+    entry.push(tag);
+    entry.extend_from_slice(key);
+    entry
+}
+
+/// Key of the `tag` entry for chunk `hash`
+pub(crate) fn chunk_entry(tag: u8, hash: &ContentHash) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(33);
+    entry.push(tag);
+    entry.extend_from_slice(hash.as_bytes());
+    entry
+}
+
+/// Object key an object entry is for
+pub(crate) fn object_key(entry: &[u8]) -> Result<Key> {
+    Key::from_bytes(&entry[1..])
+}
+
+/// Chunk a chunk entry is for; `None` if the entry is malformed
+pub(crate) fn chunk_hash(entry: &[u8]) -> Option<ContentHash> {
+    entry[1..].try_into().ok().map(ContentHash::from_bytes)
+}
+
+/// The tagged key replacing a legacy string-prefixed one
+fn translate_legacy(entry: &[u8]) -> Option<Vec<u8>> {
+    let entry = std::str::from_utf8(entry).ok()?;
+    let (subject, tag) = LEGACY_PREFIXES
+        .iter()
+        .find_map(|(prefix, tag)| entry.strip_prefix(prefix).map(|subject| (subject, *tag)))?;
+    match tag {
+        CHUNK | CHUNK_REF | CHUNK_HOME => ContentHash::from_hex(subject).ok().map(|hash| chunk_entry(tag, &hash)),
+        _ => Some(object_entry(tag, subject.as_bytes())),
+    }
+}
+
+/// Layout a partition is in; 0 for the string-prefixed one, which predates
+/// the layout entry
+fn stored_version(partition: &PartitionHandle) -> Result<u32> {
+    match partition.get(LAYOUT_KEY).map_err(WflDBError::storage)? {
+        Some(value) => value
+            .as_ref()
+            .try_into()
+            .map(u32::from_le_bytes)
+            .map_err(|_| WflDBError::Corrupt("layout version is not 4 bytes".to_string())),
+        None => Ok(0),
+    }
+}
+
+/// Mark a new, empty partition as using the current layout
+pub(crate) fn mark_current(partition: &PartitionHandle) -> Result<()> {
+    partition
+        .insert(LAYOUT_KEY, LAYOUT_VERSION.to_le_bytes())
+        .map_err(WflDBError::storage)
+}
+
+/// Bring a partition's entries to the current layout, returning how many
+/// were rewritten
+///
+/// Each batch rewrites its entries atomically, so a migration cut short by
+/// a crash resumes where it stopped. Metadata that doesn't decode is left
+/// for the startup check to report.
+pub(crate) fn migrate_partition(keyspace: &Keyspace, partition: &PartitionHandle) -> Result<usize> {
+    let version = stored_version(partition)?;
+    if version >= LAYOUT_VERSION {
+        return Ok(0);
+    }
+    let mut moved = 0;
+    let mut batch = keyspace.batch();
+    let mut pending = 0;
+    for item in partition.iter() {
+        let (entry, value) = item.map_err(WflDBError::storage)?;
+        let tagged = match version {
+            0 => translate_legacy(&entry),
+            _ => None,
+        };
+        let target = tagged.as_deref().unwrap_or(&entry);
+        let reencoded = match target.first() == Some(&META) && ObjectMetadata::is_legacy_encoding(&value) {
+            true => ObjectMetadata::decode(&value).ok().map(|metadata| metadata.encode()),
+            false => None,
+        };
+        if tagged.is_none() && reencoded.is_none() {
+            continue;
+        }
+        batch.insert(partition, target, reencoded.unwrap_or_else(|| value.to_vec()));
+        if tagged.is_some() {
+            batch.remove(partition, entry);
+        }
+        pending += 1;
+        if pending == MIGRATION_BATCH {
+            batch.commit().map_err(WflDBError::storage)?;
+            batch = keyspace.batch();
+            moved += pending;
+            pending = 0;
+        }
+    }
+    batch.commit().map_err(WflDBError::storage)?;
+    moved += pending;
+    moved += merge_inline_data(keyspace, partition)?;
+    mark_current(partition)?;
+    Ok(moved)
+}
+
+/// Move each `DATA` entry into its object's metadata record
+///
+/// Runs once metadata is tagged and binary, so every record it reads is
+/// committed; a batch that has moved an entry has also removed it, so
+/// rerunning after a crash skips it.
+fn merge_inline_data(keyspace: &Keyspace, partition: &PartitionHandle) -> Result<usize> {
+    let mut moved = 0;
+    let mut batch = keyspace.batch();
+    let mut pending = 0;
+    for item in partition.prefix([DATA]) {
+        let (entry, data) = item.map_err(WflDBError::storage)?;
+        let meta_key = object_entry(META, &entry[1..]);
+        // Data without metadata is left for the startup check to find
+        let Some(record) = partition.get(&meta_key).map_err(WflDBError::storage)? else {
+            continue;
+        };
+        let Ok(metadata) = ObjectMetadata::decode(&record) else {
+            continue;
+        };
+        if metadata.chunk_manifest.is_none() {
+            batch.insert(partition, meta_key, metadata.encode_with_data(&data));
+        }
+        batch.remove(partition, entry);
+        pending += 1;
+        if pending == MIGRATION_BATCH {
+            batch.commit().map_err(WflDBError::storage)?;
+            batch = keyspace.batch();
+            moved += pending;
+            pending = 0;
+        }
+    }
+    batch.commit().map_err(WflDBError::storage)?;
+    Ok(moved + pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_keep_kinds_apart() {
+        let hash = ContentHash::new(b"chunk");
+        assert_eq!(object_entry(META, b"a/b"), b"\x01a/b");
+        assert_eq!(chunk_entry(CHUNK_REF, &hash).len(), 33);
+        assert_eq!(chunk_hash(&chunk_entry(CHUNK, &hash)), Some(hash));
+        assert_eq!(object_key(&object_entry(TOMBSTONE, "été".as_bytes())).unwrap().as_str(), "été");
+        // A key spelled like another kind's entry stays in its own kind
+        assert_ne!(object_entry(META, b"data:x")[0], object_entry(DATA, b"x")[0]);
+    }
+
+    #[test]
+    fn test_translate_legacy() {
+        let hash = ContentHash::new(b"chunk");
+        assert_eq!(translate_legacy(b"meta:photos/cat.jpg"), Some(object_entry(META, b"photos/cat.jpg")));
+        assert_eq!(translate_legacy(b"tomb:gone"), Some(object_entry(TOMBSTONE, b"gone")));
+        for (prefix, tag) in [("chunk:", CHUNK), ("chunkref:", CHUNK_REF), ("chunkhome:", CHUNK_HOME)] {
+            let legacy = format!("{}{}", prefix, hash.to_hex());
+            assert_eq!(translate_legacy(legacy.as_bytes()), Some(chunk_entry(tag, &hash)));
+        }
+        assert_eq!(translate_legacy(&object_entry(META, b"meta:x")), None);
+        assert_eq!(translate_legacy(LAYOUT_KEY), None);
+    }
+}
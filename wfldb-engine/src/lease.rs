@@ -0,0 +1,163 @@
+//! Leases on object keys
+//!
+//! A lease is an exclusive claim on one key that lapses unless renewed, so
+//! a crashed holder can't keep it forever. Leases live in the system
+//! partition under `lease/{bucket}/{key}`, with the namespaced bucket name,
+//! and every operation on one holds the key's write lock, so two clients
+//! can't both acquire it. Expired leases are only ever replaced or purged;
+//! nothing reads them as held.
+
+use std::time::Duration;
+use wfldb_core::*;
+use crate::{Storage, StorageEngine, SystemPartition};
+
+pub(crate) const LEASE_PREFIX: &str = "lease/";
+
+/// Longest TTL a lease may be acquired or renewed for
+pub const MAX_LEASE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn lease_key(bucket: &str, key: &Key) -> String {
+    format!("{}{}/{}", LEASE_PREFIX, bucket, key.as_str())
+}
+
+fn load(system: &SystemPartition, storage_key: &str) -> Result<Option<Lease>> {
+    match system.get(storage_key)? {
+        Some(data) => serde_json::from_slice(&data).map(Some).map_err(WflDBError::Serialization),
+        None => Ok(None),
+    }
+}
+
+fn save(system: &SystemPartition, storage_key: &str, lease: &Lease) -> Result<()> {
+    let encoded = serde_json::to_vec(lease).map_err(WflDBError::Serialization)?;
+    system.put(storage_key, &encoded)
+}
+
+fn ttl_ms(ttl: Duration) -> u64 {
+    ttl.min(MAX_LEASE_TTL).as_millis() as u64
+}
+
+impl Storage {
+    /// Current lease on a key, if one is held
+    pub fn lease(&self, bucket_id: &BucketId, key: &Key, now_ms: u64) -> Result<Option<Lease>> {
+        let engine = self.engine();
+        let lease = load(&engine.system()?, &lease_key(&engine.namespaced(bucket_id), key))?;
+        Ok(lease.filter(|lease| !lease.is_expired(now_ms)))
+    }
+
+    /// Take the lease on a key for `ttl` (at most [`MAX_LEASE_TTL`]), failing
+    /// with [`WflDBError::LeaseHeld`] while someone else holds it
+    pub fn acquire_lease(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        ttl: Duration,
+        holder: Option<&str>,
+        now_ms: u64,
+    ) -> Result<Lease> {
+        let engine = self.engine();
+        let bucket = engine.namespaced(bucket_id);
+        let _lock = engine.key_locks().lock(&bucket, [key]);
+        let system = engine.system()?;
+        let storage_key = lease_key(&bucket, key);
+        if let Some(current) = load(&system, &storage_key)?.filter(|lease| !lease.is_expired(now_ms)) {
+            return Err(WflDBError::LeaseHeld {
+                key: key.as_str().to_string(),
+                expires_at_ms: current.expires_at_ms,
+            });
+        }
+        let lease = Lease {
+            id: ulid::Ulid::new().to_string(),
+            holder: holder.map(str::to_string),
+            acquired_at_ms: now_ms,
+            expires_at_ms: now_ms + ttl_ms(ttl),
+        };
+        save(&system, &storage_key, &lease)?;
+        Ok(lease)
+    }
+
+    /// Extend a held lease to `ttl` from now; fails with
+    /// [`WflDBError::LeaseNotHeld`] once it has expired or been released
+    pub fn renew_lease(&self, bucket_id: &BucketId, key: &Key, lease_id: &str, ttl: Duration, now_ms: u64) -> Result<Lease> {
+        let engine = self.engine();
+        let bucket = engine.namespaced(bucket_id);
+        let _lock = engine.key_locks().lock(&bucket, [key]);
+        let system = engine.system()?;
+        let storage_key = lease_key(&bucket, key);
+        match load(&system, &storage_key)? {
+            Some(mut lease) if lease.id == lease_id && !lease.is_expired(now_ms) => {
+                lease.expires_at_ms = now_ms + ttl_ms(ttl);
+                save(&system, &storage_key, &lease)?;
+                Ok(lease)
+            }
+            _ => Err(WflDBError::LeaseNotHeld { key: key.as_str().to_string() }),
+        }
+    }
+
+    /// Give up a lease before it expires
+    pub fn release_lease(&self, bucket_id: &BucketId, key: &Key, lease_id: &str, now_ms: u64) -> Result<()> {
+        let engine = self.engine();
+        let bucket = engine.namespaced(bucket_id);
+        let _lock = engine.key_locks().lock(&bucket, [key]);
+        let system = engine.system()?;
+        let storage_key = lease_key(&bucket, key);
+        match load(&system, &storage_key)? {
+            Some(lease) if lease.id == lease_id && !lease.is_expired(now_ms) => system.delete(&storage_key),
+            _ => Err(WflDBError::LeaseNotHeld { key: key.as_str().to_string() }),
+        }
+    }
+}
+
+/// Remove expired leases of every namespace, returning how many were removed
+pub fn purge_expired_leases(engine: &StorageEngine, now_ms: u64) -> Result<usize> {
+    let system = engine.system()?;
+    let mut purged = 0;
+    for (storage_key, value) in system.scan_prefix(LEASE_PREFIX)? {
+        let expired = serde_json::from_slice::<Lease>(&value).is_ok_and(|lease| lease.is_expired(now_ms));
+        let Some((bucket, key)) = storage_key[LEASE_PREFIX.len()..].split_once('/').filter(|_| expired) else {
+            continue;
+        };
+        // Re-check under the lock: the key may have been leased again since the scan
+        let key = Key::new(key)?;
+        let _lock = engine.key_locks().lock(bucket, [&key]);
+        if load(&system, &storage_key)?.is_some_and(|lease| lease.is_expired(now_ms)) {
+            system.delete(&storage_key)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_lifecycle() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("jobs").unwrap();
+        let key = Key::new("pipeline").unwrap();
+        let ttl = Duration::from_secs(10);
+
+        let lease = storage.acquire_lease(&bucket, &key, ttl, Some("writer-a"), 1_000).unwrap();
+        assert_eq!(lease.expires_at_ms, 11_000);
+        assert!(matches!(
+            storage.acquire_lease(&bucket, &key, ttl, Some("writer-b"), 5_000),
+            Err(WflDBError::LeaseHeld { expires_at_ms: 11_000, .. })
+        ));
+
+        let renewed = storage.renew_lease(&bucket, &key, &lease.id, ttl, 9_000).unwrap();
+        assert_eq!(renewed.expires_at_ms, 19_000);
+        assert!(storage.renew_lease(&bucket, &key, "someone-else", ttl, 9_000).is_err());
+
+        // Once lapsed the lease can be taken over, and the old id is dead
+        let taken = storage.acquire_lease(&bucket, &key, ttl, Some("writer-b"), 20_000).unwrap();
+        assert!(storage.release_lease(&bucket, &key, &lease.id, 20_000).is_err());
+        storage.release_lease(&bucket, &key, &taken.id, 21_000).unwrap();
+        assert!(storage.lease(&bucket, &key, 21_000).unwrap().is_none());
+
+        storage.acquire_lease(&bucket, &key, ttl, None, 30_000).unwrap();
+        assert_eq!(purge_expired_leases(&engine, 35_000).unwrap(), 0);
+        assert_eq!(purge_expired_leases(&engine, 40_000).unwrap(), 1);
+    }
+}
@@ -1,44 +1,160 @@
 //! Storage engine implementation using fjall
 
-use fjall::{Config, Keyspace, PersistMode};
+use fjall::{Keyspace, PartitionCreateOptions, PartitionHandle, PersistMode};
+use std::collections::HashMap;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use wfldb_core::*;
+use crate::locks::KeyLocks;
 
+pub mod audit;
 pub mod bucket;
+pub mod catalog;
+pub mod document;
+pub mod expiry;
+pub mod export;
+pub mod journal;
+mod layout;
+pub mod lease;
+mod locks;
+pub mod merkle;
+pub mod migrations;
+pub mod multipart;
+pub mod quarantine;
+pub mod recovery;
+pub mod refcount;
+pub mod stats;
 pub mod storage;
+pub mod system;
+pub mod usage;
+pub mod warmup;
 
+pub use audit::*;
 pub use bucket::*;
+pub use catalog::*;
+pub use document::*;
+pub use expiry::*;
+pub use export::*;
+pub use journal::*;
+pub use lease::*;
+pub use merkle::*;
+pub use migrations::{data_format_version, MigrationOptions, DATA_FORMAT_VERSION};
+pub use multipart::*;
+pub use quarantine::*;
+pub use recovery::*;
+pub use refcount::*;
+pub use stats::*;
 pub use storage::*;
+pub use system::*;
+pub use usage::*;
+pub use warmup::*;
 
 /// Storage engine wrapping fjall keyspace
 #[derive(Clone)]
 pub struct StorageEngine {
     keyspace: Arc<Keyspace>,
+    path: Arc<Path>,
     value_threshold: usize,
+    journal: Option<Arc<ChangeJournal>>,
+    tenant: Option<TenantId>,
+    key_locks: Arc<KeyLocks>,
+    /// Bucket partitions already opened, by partition name, so a request
+    /// touching a bucket doesn't open it again
+    partitions: Arc<RwLock<HashMap<String, Arc<PartitionHandle>>>>,
+    stats: Arc<EngineStats>,
 }
 
+/// Partition used by health probes; like `_system`, it can't collide with `{bucket}_main`
+pub const HEALTH_PARTITION: &str = "_health";
+
+/// Separates the tenant from the bucket in namespaced names; bucket IDs can't contain it
+pub const TENANT_SEPARATOR: char = '#';
+
+/// Stands in for the dots of domain-style bucket names in partition names,
+/// which can't contain dots; bucket IDs can't contain it either
+const PARTITION_DOT: char = '$';
+
 impl StorageEngine {
     /// Create new storage engine at the given path
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let config = Config::new(path);
-        let keyspace = Arc::new(
-            config
-                .open()
-                .map_err(|e| WflDBError::Storage(e.to_string()))?
-        );
+        Self::open(path, MigrationOptions::default())
+    }
+    
+    /// Open the storage engine at the given path, first migrating a data
+    /// directory in an older format as `options` say (see [`migrations`])
+    pub fn open(path: impl AsRef<Path>, options: MigrationOptions) -> Result<Self> {
+        let path: Arc<Path> = path.as_ref().into();
+        let keyspace = Arc::new(migrations::open_keyspace(&path, options)?);
         
         Ok(StorageEngine {
             keyspace,
+            path,
             value_threshold: 64 * 1024, // 64KB threshold for key-value separation
+            journal: None,
+            tenant: None,
+            key_locks: Arc::new(KeyLocks::new()),
+            partitions: Arc::new(RwLock::new(HashMap::new())),
+            stats: Arc::new(EngineStats::default()),
         })
     }
     
+    /// View of the engine whose buckets live in `tenant`'s namespace
+    ///
+    /// Tenant buckets are separate partitions (`{tenant}#{bucket}_main`), so
+    /// a bucket name used by two tenants never shares data.
+    pub fn for_tenant(&self, tenant: &TenantId) -> Self {
+        StorageEngine {
+            tenant: Some(tenant.clone()),
+            ..self.clone()
+        }
+    }
+    
+    /// Tenant this view is scoped to; `None` is the shared namespace
+    pub fn tenant(&self) -> Option<&TenantId> {
+        self.tenant.as_ref()
+    }
+    
+    /// Bucket name qualified by this view's tenant, as used in partition
+    /// names and journal records
+    pub fn namespaced(&self, bucket_id: &BucketId) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{}{}{}", tenant.as_str(), TENANT_SEPARATOR, bucket_id.as_str()),
+            None => bucket_id.as_str().to_string(),
+        }
+    }
+    
+    /// Name of the partition holding a bucket's objects
+    pub(crate) fn partition_name(&self, bucket_id: &BucketId) -> String {
+        format!("{}_main", self.namespaced(bucket_id).replace('.', &PARTITION_DOT.to_string()))
+    }
+    
+    /// Inverse of [`namespaced`](Self::namespaced): the tenant view and bucket a name refers to
+    pub fn resolve_namespaced(&self, name: &str) -> Result<(StorageEngine, BucketId)> {
+        match name.split_once(TENANT_SEPARATOR) {
+            Some((tenant, bucket)) => Ok((self.for_tenant(&TenantId::new(tenant)?), BucketId::new(bucket)?)),
+            None => {
+                let shared = StorageEngine { tenant: None, ..self.clone() };
+                Ok((shared, BucketId::new(name)?))
+            }
+        }
+    }
+    
+    /// Record every object mutation made through `Storage` in `journal`
+    pub fn with_journal(mut self, journal: Arc<ChangeJournal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+    
+    /// Change journal, if mutations are being journaled
+    pub fn journal(&self) -> Option<&Arc<ChangeJournal>> {
+        self.journal.as_ref()
+    }
+    
     /// Create temporary storage engine for testing
     #[cfg(any(test, feature = "test-utils"))]
     pub fn temp() -> Result<(Self, tempfile::TempDir)> {
-        let temp_dir = tempfile::tempdir()
-            .map_err(|e| WflDBError::Internal(e.to_string()))?;
+        let temp_dir = tempfile::tempdir()?;
         let engine = Self::new(temp_dir.path())?;
         Ok((engine, temp_dir))
     }
@@ -48,11 +164,121 @@ impl StorageEngine {
         Bucket::new(self.clone(), bucket_id.clone())
     }
     
+    /// IDs of every bucket in this view's namespace with a partition on disk
+    pub fn bucket_ids(&self) -> Result<Vec<BucketId>> {
+        let tenant_prefix = self.tenant.as_ref().map(|t| format!("{}{}", t.as_str(), TENANT_SEPARATOR));
+        let mut ids: Vec<BucketId> = self.keyspace
+            .list_partitions()
+            .iter()
+            .filter_map(|name| name.strip_suffix("_main").map(|name| name.replace(PARTITION_DOT, ".")))
+            .filter_map(|name| match &tenant_prefix {
+                Some(prefix) => name.strip_prefix(prefix.as_str()).map(str::to_string),
+                // Tenant names contain the separator, which bucket IDs reject
+                None => Some(name),
+            })
+            .filter_map(|name| BucketId::new(&name).ok())
+            .collect();
+        ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        Ok(ids)
+    }
+    
+    /// Tenants with at least one bucket on disk
+    pub fn tenant_ids(&self) -> Result<Vec<TenantId>> {
+        let mut ids: Vec<TenantId> = self.keyspace
+            .list_partitions()
+            .iter()
+            .filter(|name| name.ends_with("_main"))
+            .filter_map(|name| name.split_once(TENANT_SEPARATOR))
+            .filter_map(|(tenant, _)| TenantId::new(tenant).ok())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+    
+    /// The shared namespace and a view of every tenant with buckets on disk
+    pub fn namespaces(&self) -> Result<Vec<StorageEngine>> {
+        let shared = StorageEngine { tenant: None, ..self.clone() };
+        let mut namespaces = vec![shared.clone()];
+        for tenant in self.tenant_ids()? {
+            namespaces.push(shared.for_tenant(&tenant));
+        }
+        Ok(namespaces)
+    }
+    
+    /// Open the system partition for server-internal metadata
+    pub fn system(&self) -> Result<SystemPartition> {
+        SystemPartition::open(self.clone())
+    }
+    
+    /// A bucket partition opened earlier through any view of this keyspace
+    pub(crate) fn cached_partition(&self, name: &str) -> Option<Arc<PartitionHandle>> {
+        let partition = self.partitions.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned();
+        self.stats.record_partition_lookup(partition.is_some());
+        partition
+    }
+    
+    /// Remember an opened bucket partition for later requests
+    pub(crate) fn cache_partition(&self, name: String, partition: Arc<PartitionHandle>) {
+        self.partitions.write().unwrap_or_else(|e| e.into_inner()).insert(name, partition);
+    }
+    
+    /// Counters shared by every view of this keyspace
+    pub fn stats(&self) -> &EngineStats {
+        &self.stats
+    }
+    
+    /// Sample the keyspace's LSM state across every bucket partition
+    pub fn keyspace_stats(&self) -> Result<KeyspaceStats> {
+        let mut stats = KeyspaceStats {
+            disk_bytes: self.keyspace.disk_space(),
+            write_buffer_bytes: self.keyspace.write_buffer_size(),
+            journals: self.keyspace.journal_count(),
+            partitions: self.keyspace.partition_count(),
+            ..KeyspaceStats::default()
+        };
+        for namespace in self.namespaces()? {
+            for id in namespace.bucket_ids()? {
+                let bucket = namespace.bucket(&id)?;
+                stats.segments += bucket.main_partition.segment_count();
+                stats.blob_files += bucket.main_partition.blob_file_count();
+            }
+        }
+        Ok(stats)
+    }
+    
+    /// Write locks shared by every view of this keyspace
+    pub(crate) fn key_locks(&self) -> &KeyLocks {
+        &self.key_locks
+    }
+    
     /// Get the underlying keyspace
     pub(crate) fn keyspace(&self) -> &Keyspace {
         &self.keyspace
     }
     
+    /// Data directory the keyspace lives in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    /// Write, persist, and read back a canary value to prove the keyspace is writable
+    pub fn canary_check(&self) -> Result<()> {
+        let partition = self.keyspace
+            .open_partition(HEALTH_PARTITION, PartitionCreateOptions::default())
+            .map_err(WflDBError::storage)?;
+        let value = Version::new().to_string();
+        partition
+            .insert("canary", value.as_bytes())
+            .map_err(WflDBError::storage)?;
+        self.persist()?;
+        match partition.get("canary") {
+            Ok(Some(read)) if read.as_ref() == value.as_bytes() => Ok(()),
+            Ok(_) => Err(WflDBError::Corrupt("canary read back a different value".to_string())),
+            Err(e) => Err(WflDBError::storage(e)),
+        }
+    }
+    
     /// Get value separation threshold
     pub fn value_threshold(&self) -> usize {
         self.value_threshold
@@ -60,10 +286,26 @@ impl StorageEngine {
     
     /// Persist all changes to disk
     pub fn persist(&self) -> Result<()> {
-        self.keyspace
+        let start = Instant::now();
+        let result = self.keyspace
             .persist(PersistMode::SyncAll)
-            .map_err(|e| WflDBError::Storage(e.to_string()))
+            .map_err(WflDBError::storage);
+        self.stats.record_persist(start.elapsed());
+        result
+    }
+}
+
+/// Rewrite every bucket partition still in the string-prefixed layout
+///
+/// Runs before the engine serves anything, so no write races the rewrite.
+pub(crate) fn migrate_layout(keyspace: &Keyspace) -> Result<()> {
+    for name in keyspace.list_partitions().iter().filter(|name| name.ends_with("_main")) {
+        let partition = keyspace
+            .open_partition(name, PartitionCreateOptions::default())
+            .map_err(WflDBError::storage)?;
+        layout::migrate_partition(keyspace, &partition)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -76,6 +318,14 @@ mod tests {
         assert_eq!(engine.value_threshold(), 64 * 1024);
     }
     
+    #[test]
+    fn test_canary_check() {
+        let (engine, temp) = StorageEngine::temp().unwrap();
+        assert_eq!(engine.path(), temp.path());
+        engine.canary_check().unwrap();
+        engine.canary_check().unwrap();
+    }
+    
     #[test]
     fn test_bucket_creation() {
         let (engine, _temp) = StorageEngine::temp().unwrap();
@@ -83,4 +333,98 @@ mod tests {
         let bucket = engine.bucket(&bucket_id).unwrap();
         assert_eq!(bucket.id(), &bucket_id);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_bucket_partitions_are_reused() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let photos = BucketId::new("photos").unwrap();
+        let first = engine.bucket(&photos).unwrap();
+        // Views of the engine share opened partitions, but not across tenants
+        let again = engine.clone().bucket(&photos).unwrap();
+        assert!(Arc::ptr_eq(&first.main_partition, &again.main_partition));
+        let tenant = engine.for_tenant(&TenantId::new("acme").unwrap()).bucket(&photos).unwrap();
+        assert!(!Arc::ptr_eq(&first.main_partition, &tenant.main_partition));
+    }
+    
+    #[test]
+    fn test_tenant_buckets_are_isolated() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let acme = engine.for_tenant(&TenantId::new("acme").unwrap());
+        let globex = engine.for_tenant(&TenantId::new("globex").unwrap());
+        let photos = BucketId::new("photos").unwrap();
+        let key = Key::new("cat.jpg").unwrap();
+        
+        acme.bucket(&photos).unwrap().put_small(&key, b"acme").unwrap();
+        assert_eq!(acme.bucket_ids().unwrap(), vec![photos.clone()]);
+        assert!(engine.bucket_ids().unwrap().is_empty());
+        assert_eq!(engine.tenant_ids().unwrap(), vec![TenantId::new("acme").unwrap()]);
+        
+        // Opening the same bucket name elsewhere gets a different partition
+        assert!(globex.bucket(&photos).unwrap().get_small(&key).unwrap().is_none());
+        assert!(engine.bucket(&photos).unwrap().get_small(&key).unwrap().is_none());
+        
+        let (resolved, bucket) = engine.resolve_namespaced(&acme.namespaced(&photos)).unwrap();
+        assert_eq!(resolved.tenant(), acme.tenant());
+        assert_eq!(bucket, photos);
+    }
+    
+    #[test]
+    fn test_domain_style_bucket_names() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let assets = BucketId::new("assets.example.com").unwrap();
+        engine.bucket(&assets).unwrap().put_small(&Key::new("logo.svg").unwrap(), b"<svg/>").unwrap();
+        assert_eq!(engine.partition_name(&assets), "assets$example$com_main");
+        assert_eq!(engine.bucket_ids().unwrap(), vec![assets]);
+    }
+    
+    #[test]
+    fn test_legacy_layout_migrated_on_open() {
+        let temp = tempfile::tempdir().unwrap();
+        let chunk = ContentHash::new(b"chunk");
+        {
+            let engine = StorageEngine::new(temp.path()).unwrap();
+            let partition = engine.keyspace()
+                .open_partition("photos_main", PartitionCreateOptions::default())
+                .unwrap();
+            let metadata = ObjectMetadata::new_inline(3, ContentHash::new(b"cat"));
+            partition.insert("meta:cat.jpg", serde_json::to_vec(&metadata).unwrap()).unwrap();
+            partition.insert("data:cat.jpg", b"cat").unwrap();
+            partition.insert(format!("chunk:{}", chunk.to_hex()), b"chunk").unwrap();
+            
+            // Tagged, but with JSON metadata
+            let partition = engine.keyspace()
+                .open_partition("docs_main", PartitionCreateOptions::default())
+                .unwrap();
+            partition.insert(layout::LAYOUT_KEY, 1u32.to_le_bytes()).unwrap();
+            partition.insert(layout::object_entry(layout::META, b"a.txt"), serde_json::to_vec(&metadata).unwrap()).unwrap();
+            partition.insert(layout::object_entry(layout::DATA, b"a.txt"), b"cat").unwrap();
+            engine.persist().unwrap();
+        }
+        // As written before the data directory recorded its format
+        std::fs::remove_file(temp.path().join(migrations::FORMAT_FILE)).unwrap();
+        
+        let engine = StorageEngine::new(temp.path()).unwrap();
+        let bucket = engine.bucket(&BucketId::new("photos").unwrap()).unwrap();
+        let key = Key::new("cat.jpg").unwrap();
+        assert_eq!(bucket.get_small(&key).unwrap().unwrap(), b"cat");
+        assert!(bucket.has_chunk(&chunk).unwrap());
+        assert!(bucket.main_partition.get("meta:cat.jpg").unwrap().is_none());
+        let stored = bucket.main_partition.get(bucket.metadata_key(&key)).unwrap().unwrap();
+        assert!(!ObjectMetadata::is_legacy_encoding(&stored));
+        // Inline data now lives in the metadata record
+        assert!(bucket.main_partition.get(layout::object_entry(layout::DATA, b"cat.jpg")).unwrap().is_none());
+        
+        let docs = engine.bucket(&BucketId::new("docs").unwrap()).unwrap();
+        let a = Key::new("a.txt").unwrap();
+        let stored = docs.main_partition.get(docs.metadata_key(&a)).unwrap().unwrap();
+        assert!(!ObjectMetadata::is_legacy_encoding(&stored));
+        assert_eq!(docs.get_metadata(&a).unwrap().unwrap().size, 3);
+        assert_eq!(docs.get_small(&a).unwrap().unwrap(), b"cat");
+        
+        // Keys spelled like internal entries are ordinary keys
+        let spoof = Key::new("data:cat.jpg").unwrap();
+        bucket.put_small(&spoof, b"spoof").unwrap();
+        assert_eq!(bucket.get_small(&key).unwrap().unwrap(), b"cat");
+        assert_eq!(bucket.scan_prefix("", None).unwrap(), vec![key, spoof]);
+    }
+}
@@ -0,0 +1,43 @@
+//! Striped per-key write locks
+//!
+//! Writes that check an object's current state before replacing it hold the
+//! lock for each key they touch, so a transaction's preconditions still hold
+//! when its batch commits. Keys hash onto a fixed set of stripes; multi-key
+//! callers take stripes in index order, so two transactions can't deadlock.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use wfldb_core::Key;
+
+const STRIPES: usize = 256;
+
+pub(crate) struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl KeyLocks {
+    pub(crate) fn new() -> Self {
+        KeyLocks {
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    /// Lock every key of the bucket with namespaced name `bucket`
+    pub(crate) fn lock<'a, 'k>(&'a self, bucket: &str, keys: impl IntoIterator<Item = &'k Key>) -> Vec<MutexGuard<'a, ()>> {
+        let mut stripes: Vec<usize> = keys
+            .into_iter()
+            .map(|key| {
+                let mut hasher = DefaultHasher::new();
+                (bucket, key.as_str()).hash(&mut hasher);
+                hasher.finish() as usize % STRIPES
+            })
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|stripe| self.stripes[stripe].lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+            .collect()
+    }
+}
@@ -0,0 +1,251 @@
+//! Merkle trees over a bucket's key versions, for anti-entropy
+//!
+//! Every key falls in one of [`LEAVES`] leaves by the hash of its name. A
+//! leaf's hash combines the hashes of the `(key, version)` pairs in it,
+//! deletes whose tombstone is still kept included, and every inner node
+//! hashes its [`FANOUT`] children. Two nodes holding the same data build
+//! the same tree, so comparing roots tells whether a bucket differs at all,
+//! and descending only into children whose hashes differ finds the few
+//! leaves that do without exchanging every key.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wfldb_core::*;
+use crate::bucket::decode_tombstone;
+use crate::layout::{self, META, TOMBSTONE};
+use crate::Bucket;
+
+/// Children of every inner node
+pub const FANOUT: usize = 16;
+
+/// Levels below the root
+pub const DEPTH: usize = 3;
+
+/// Leaves of every tree
+pub const LEAVES: usize = FANOUT.pow(DEPTH as u32);
+
+/// A key's version as far as anti-entropy is concerned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleEntry {
+    pub key: String,
+    pub version: Version,
+    /// The version is a delete's tombstone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// Owner of a live object; not part of the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl MerkleEntry {
+    fn hash(&self) -> ContentHash {
+        let mut buf = Vec::with_capacity(self.key.len() + 21);
+        buf.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(self.key.as_bytes());
+        buf.extend_from_slice(&self.version.as_ulid().0.to_be_bytes());
+        buf.push(self.deleted as u8);
+        ContentHash::new(&buf)
+    }
+}
+
+/// Leaf a key falls in
+pub fn leaf_of(key: &str) -> usize {
+    let hash = ContentHash::new(key.as_bytes());
+    u32::from_be_bytes(hash.as_bytes()[..4].try_into().unwrap()) as usize % LEAVES
+}
+
+/// Indices at the next level down of the children of node `index`
+pub fn children(index: usize) -> Range<usize> {
+    index * FANOUT..(index + 1) * FANOUT
+}
+
+/// Hash tree of one bucket at the moment it was built
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// Node hashes by level, the root's level first
+    levels: Vec<Vec<ContentHash>>,
+    /// Entries of each leaf, sorted by key
+    leaves: Vec<Vec<MerkleEntry>>,
+    built_at_ms: u64,
+}
+
+impl MerkleTree {
+    /// Build the tree of a bucket's objects and tombstones from a scan of
+    /// its metadata
+    ///
+    /// Both scans read one snapshot, so a delete racing the build can't
+    /// drop a key from both or neither.
+    pub fn build(bucket: &Bucket) -> Result<Self> {
+        let built_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let snapshot = bucket.main_partition.snapshot();
+        let mut entries = BTreeMap::new();
+        for item in snapshot.prefix([META]) {
+            let (meta_key, value) = item.map_err(WflDBError::storage)?;
+            let key = layout::object_key(&meta_key)?.as_str().to_string();
+            let metadata = ObjectMetadata::decode(&value)?;
+            let entry = MerkleEntry { key: key.clone(), version: metadata.version, deleted: false, owner: metadata.owner };
+            entries.insert(key, entry);
+        }
+        for item in snapshot.prefix([TOMBSTONE]) {
+            let (tomb_key, value) = item.map_err(WflDBError::storage)?;
+            let key = layout::object_key(&tomb_key)?.as_str().to_string();
+            let version = decode_tombstone(&value)?;
+            // A key written again after its delete is live
+            if entries.get(&key).is_none_or(|live: &MerkleEntry| live.version < version) {
+                entries.insert(key.clone(), MerkleEntry { key, version, deleted: true, owner: None });
+            }
+        }
+        Ok(Self::from_entries(entries.into_values(), built_at_ms))
+    }
+
+    /// Build a tree from entries sorted by key
+    pub fn from_entries(entries: impl IntoIterator<Item = MerkleEntry>, built_at_ms: u64) -> Self {
+        let mut leaves = vec![Vec::new(); LEAVES];
+        let mut leaf_hashes = vec![[0u8; 32]; LEAVES];
+        for entry in entries {
+            let leaf = leaf_of(&entry.key);
+            // XOR keeps a leaf's hash independent of the order it was filled in
+            for (byte, entry_byte) in leaf_hashes[leaf].iter_mut().zip(entry.hash().as_bytes()) {
+                *byte ^= entry_byte;
+            }
+            leaves[leaf].push(entry);
+        }
+
+        let mut levels = vec![leaf_hashes.into_iter().map(ContentHash::from_bytes).collect::<Vec<_>>()];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(FANOUT)
+                .map(|children| ContentHash::new(&children.iter().flat_map(|c| *c.as_bytes()).collect::<Vec<u8>>()))
+                .collect();
+            levels.insert(0, parents);
+        }
+        MerkleTree { levels, leaves, built_at_ms }
+    }
+
+    pub fn root(&self) -> &ContentHash {
+        &self.levels[0][0]
+    }
+
+    /// Hash of node `index` at `level`, where level 0 is the root and
+    /// [`DEPTH`] the leaves
+    pub fn node(&self, level: usize, index: usize) -> Option<&ContentHash> {
+        self.levels.get(level)?.get(index)
+    }
+
+    /// Entries of leaf `index`, sorted by key
+    pub fn leaf(&self, index: usize) -> Option<&[MerkleEntry]> {
+        self.leaves.get(index).map(Vec::as_slice)
+    }
+
+    /// When the bucket was scanned
+    pub fn built_at_ms(&self) -> u64 {
+        self.built_at_ms
+    }
+}
+
+/// Changes that bring one leaf of a replica in line with its leader's
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeafRepair {
+    /// Leader entries the replica is missing or holds older versions of
+    pub updates: Vec<MerkleEntry>,
+    /// Live keys the leader doesn't have
+    pub removals: Vec<String>,
+}
+
+impl LeafRepair {
+    /// Compare a replica's leaf with the leader's, scanned at
+    /// `leader_built_at_ms`
+    ///
+    /// A replica key the leader lacks is only removed if it was written
+    /// before the leader's scan; a newer one arrived through the journal
+    /// after it.
+    pub fn diff(local: &[MerkleEntry], leader: &[MerkleEntry], leader_built_at_ms: u64) -> Self {
+        let local_versions: BTreeMap<&str, &MerkleEntry> = local.iter().map(|e| (e.key.as_str(), e)).collect();
+        let leader_keys: BTreeMap<&str, &MerkleEntry> = leader.iter().map(|e| (e.key.as_str(), e)).collect();
+        let updates = leader
+            .iter()
+            .filter(|entry| match local_versions.get(entry.key.as_str()) {
+                Some(local) => local.version < entry.version,
+                None => !entry.deleted,
+            })
+            .cloned()
+            .collect();
+        let removals = local
+            .iter()
+            .filter(|entry| !entry.deleted && !leader_keys.contains_key(entry.key.as_str()))
+            .filter(|entry| entry.version.timestamp() < leader_built_at_ms)
+            .map(|entry| entry.key.clone())
+            .collect();
+        LeafRepair { updates, removals }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty() && self.removals.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, version: &Version, deleted: bool) -> MerkleEntry {
+        MerkleEntry { key: key.to_string(), version: version.clone(), deleted, owner: None }
+    }
+
+    #[test]
+    fn test_trees_differ_only_along_changed_leaves() {
+        let versions: Vec<Version> = (0..100).map(|_| Version::new()).collect();
+        let entries: Vec<MerkleEntry> = versions
+            .iter()
+            .enumerate()
+            .map(|(i, version)| entry(&format!("key-{:03}", i), version, false))
+            .collect();
+        let leader = MerkleTree::from_entries(entries.clone(), 0);
+        assert_eq!(leader.root(), MerkleTree::from_entries(entries.clone(), 0).root());
+
+        // A replica that missed one delete
+        let mut replica_entries = entries.clone();
+        replica_entries[42].version = Version::new();
+        replica_entries[42].deleted = true;
+        let replica = MerkleTree::from_entries(replica_entries.clone(), 0);
+        assert_ne!(leader.root(), replica.root());
+
+        let changed = leaf_of("key-042");
+        let mut index = 0;
+        for level in 1..=DEPTH {
+            let differing: Vec<usize> = children(index)
+                .filter(|&child| leader.node(level, child) != replica.node(level, child))
+                .collect();
+            assert_eq!(differing, [changed / FANOUT.pow((DEPTH - level) as u32)]);
+            index = differing[0];
+        }
+
+        let repair = LeafRepair::diff(replica.leaf(changed).unwrap(), leader.leaf(changed).unwrap(), 0);
+        assert!(repair.updates.is_empty() && repair.removals.is_empty());
+        let repair = LeafRepair::diff(leader.leaf(changed).unwrap(), replica.leaf(changed).unwrap(), u64::MAX);
+        assert_eq!(repair.updates, [replica_entries[42].clone()]);
+    }
+
+    /// A version written at `ms`
+    fn at(ms: u64) -> Version {
+        Version::from_ulid(ulid::Ulid::from_parts(ms, 0))
+    }
+
+    #[test]
+    fn test_leaf_repair() {
+        let (old, new, late) = (at(1_000), at(2_000), at(3_000));
+        let leader = [entry("a", &new, false), entry("b", &new, true), entry("c", &old, true)];
+        let local = [entry("a", &old, false), entry("b", &old, false), entry("d", &old, false), entry("e", &late, false)];
+
+        let repair = LeafRepair::diff(&local, &leader, late.timestamp());
+        // Deleted keys the replica never had need nothing
+        assert_eq!(repair.updates, [entry("a", &new, false), entry("b", &new, true)]);
+        // `e` was written after the leader's scan
+        assert_eq!(repair.removals, ["d".to_string()]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Data directory format versions and the migrations between them
+//!
+//! `FORMAT_VERSION` in the data directory holds the format the directory
+//! is in, as a decimal number. The engine reads it before opening the
+//! keyspace: a directory in a newer format than [`DATA_FORMAT_VERSION`] is
+//! refused with [`WflDBError::UnsupportedFormat`], so an older build never
+//! misreads data a newer one wrote, and an older format is brought up to
+//! date by running every migration above it, in order. Directories from
+//! before the file existed are format 0; new ones start at the current
+//! format.
+//!
+//! Before migrating, the whole directory is copied to a sibling
+//! `{dir}.pre-format-{N}`, unless [`MigrationOptions::backup`] is off. When
+//! a migration fails the copy is moved back into place, and the failed
+//! directory is kept as `{dir}.failed-format-{N}` for inspection, so the
+//! previous build still opens the data. The format is recorded after each
+//! step, and steps are safe to run again, so a crash part way through
+//! resumes at the interrupted step.
+//!
+//! A change to the on-disk format bumps [`DATA_FORMAT_VERSION`] and adds
+//! the step reaching it to the end of `MIGRATIONS`, even when older data
+//! needs no rewrite, so older builds refuse the directory.
+
+use fjall::{Config, Keyspace, PersistMode};
+use std::fs;
+use std::path::{Path, PathBuf};
+use wfldb_core::*;
+
+/// Format of data directories this build writes, and the newest it opens
+pub const DATA_FORMAT_VERSION: u32 = 3;
+
+pub(crate) const FORMAT_FILE: &str = "FORMAT_VERSION";
+
+/// One step from the previous data directory format to `to`
+pub(crate) struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    pub run: fn(&Keyspace) -> Result<()>,
+}
+
+/// Every step, oldest first; each reaches the format after the one before
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 1,
+        description: "tagged key layout and binary metadata in bucket partitions",
+        run: crate::migrate_layout,
+    },
+    Migration {
+        // Older records read as generation 0; nothing to rewrite
+        to: 2,
+        description: "object generations in metadata records",
+        run: |_| Ok(()),
+    },
+    Migration {
+        // Older records carry no key hash and the journal no range writes,
+        // chunks or manifests; nothing to rewrite
+        to: 3,
+        description: "customer key hashes in metadata records and journaled puts; range writes, chunks and manifests in the journal",
+        run: |_| Ok(()),
+    },
+];
+
+/// How the engine treats a data directory in an older format
+#[derive(Debug, Clone, Copy)]
+pub struct MigrationOptions {
+    /// Copy the directory aside before migrating and restore it if a step fails
+    pub backup: bool,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        MigrationOptions { backup: true }
+    }
+}
+
+/// Format the data directory at `path` is in, or `None` if it's empty or
+/// doesn't exist yet
+pub fn data_format_version(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path.join(FORMAT_FILE)) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| WflDBError::Corrupt(format!("{} holds '{}'", FORMAT_FILE, text.trim()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let empty = match fs::read_dir(path) {
+                Ok(mut entries) => entries.next().is_none(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+                Err(e) => return Err(e.into()),
+            };
+            Ok(if empty { None } else { Some(0) })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Open the keyspace at `path`, migrating it to [`DATA_FORMAT_VERSION`] first
+pub(crate) fn open_keyspace(path: &Path, options: MigrationOptions) -> Result<Keyspace> {
+    open_with(path, options, MIGRATIONS)
+}
+
+fn open_with(path: &Path, options: MigrationOptions, migrations: &[Migration]) -> Result<Keyspace> {
+    let supported = migrations.last().map_or(0, |migration| migration.to);
+    let open = || Config::new(path).open().map_err(WflDBError::storage);
+    let current = match data_format_version(path)? {
+        None => {
+            let keyspace = open()?;
+            write_format_version(path, supported)?;
+            return Ok(keyspace);
+        }
+        Some(found) if found > supported => return Err(WflDBError::UnsupportedFormat { found, supported }),
+        Some(found) if found == supported => return open(),
+        Some(found) => found,
+    };
+
+    let backup = match options.backup {
+        true => Some(back_up(path, supported)?),
+        false => None,
+    };
+    let keyspace = open()?;
+    for migration in migrations.iter().filter(|migration| migration.to > current) {
+        let result = (migration.run)(&keyspace)
+            .and_then(|()| keyspace.persist(PersistMode::SyncAll).map_err(WflDBError::storage))
+            .and_then(|()| write_format_version(path, migration.to));
+        if let Err(e) = result {
+            drop(keyspace);
+            let mut message = format!("migration to data format {} ({}) failed", migration.to, migration.description);
+            if let Some(backup) = backup {
+                let failed = sibling(path, &format!("failed-format-{}", migration.to));
+                fs::rename(path, &failed)?;
+                fs::rename(&backup, path)?;
+                message = format!("{}; restored the previous data, the failed directory is {}", message, failed.display());
+            }
+            return Err(WflDBError::Internal { message, source: Some(Box::new(e)) });
+        }
+    }
+    Ok(keyspace)
+}
+
+/// `{dir}.{suffix}`, next to the data directory
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    path.with_file_name(name)
+}
+
+/// Copy the data directory aside before migrating it to `target`
+///
+/// A copy left by an interrupted earlier attempt is from before any step
+/// ran, so it's kept rather than replaced by the half-migrated directory.
+fn back_up(path: &Path, target: u32) -> Result<PathBuf> {
+    let backup = sibling(path, &format!("pre-format-{}", target));
+    if !backup.exists() {
+        let partial = sibling(path, &format!("pre-format-{}.partial", target));
+        if partial.exists() {
+            fs::remove_dir_all(&partial)?;
+        }
+        copy_dir(path, &partial)?;
+        fs::rename(&partial, &backup)?;
+    }
+    Ok(backup)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Record the directory's format, replacing the file in one rename
+fn write_format_version(path: &Path, version: u32) -> Result<()> {
+    let temp = path.join(format!("{}.tmp", FORMAT_FILE));
+    fs::write(&temp, format!("{}\n", version))?;
+    fs::File::open(&temp)?.sync_all()?;
+    fs::rename(&temp, path.join(FORMAT_FILE))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageEngine;
+
+    #[test]
+    fn test_fresh_directory_stamped() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("data");
+        assert_eq!(data_format_version(&path).unwrap(), None);
+        StorageEngine::new(&path).unwrap();
+        assert_eq!(data_format_version(&path).unwrap(), Some(DATA_FORMAT_VERSION));
+        // Nothing to migrate, so nothing copied aside
+        assert!(!sibling(&path, &format!("pre-format-{}", DATA_FORMAT_VERSION)).exists());
+    }
+
+    #[test]
+    fn test_newer_format_refused() {
+        let temp = tempfile::tempdir().unwrap();
+        StorageEngine::new(temp.path()).unwrap();
+        write_format_version(temp.path(), DATA_FORMAT_VERSION + 1).unwrap();
+        let refused = StorageEngine::new(temp.path());
+        assert!(matches!(
+            refused,
+            Err(WflDBError::UnsupportedFormat { found, supported })
+                if found == DATA_FORMAT_VERSION + 1 && supported == DATA_FORMAT_VERSION
+        ));
+    }
+
+    #[test]
+    fn test_failed_migration_restores_backup() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("data");
+        let steps = [
+            Migration { to: 1, description: "first", run: |_| Ok(()) },
+            Migration { to: 2, description: "second", run: |_| Err(WflDBError::internal("boom")) },
+        ];
+        drop(open_with(&path, MigrationOptions::default(), &steps[..0]).unwrap());
+        assert_eq!(data_format_version(&path).unwrap(), Some(0));
+
+        let error = open_with(&path, MigrationOptions::default(), &steps).err().unwrap();
+        assert!(matches!(&error, WflDBError::Internal { message, .. } if message.contains("second")));
+        assert_eq!(data_format_version(&path).unwrap(), Some(0));
+        let failed = sibling(&path, "failed-format-2");
+        assert_eq!(data_format_version(&failed).unwrap(), Some(1));
+
+        // The restored directory migrates once the step is fixed
+        let steps = [steps[0].to, 2].map(|to| Migration { to, description: "fixed", run: |_| Ok(()) });
+        drop(open_with(&path, MigrationOptions { backup: false }, &steps).unwrap());
+        assert_eq!(data_format_version(&path).unwrap(), Some(2));
+    }
+}
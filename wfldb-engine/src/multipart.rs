@@ -0,0 +1,260 @@
+//! Multipart uploads
+//!
+//! An upload in progress lives in the system partition under
+//! `multipart/{bucket}/{upload id}`, with the namespaced bucket name, so it
+//! survives a restart. Each part is stored as chunks of at most
+//! [`MAX_CHUNK_SIZE`] as it arrives, and completing the upload commits an
+//! object from every part's chunks in part order. Until then the chunks
+//! hold no reference; the refcount pass leaves chunks named by an upload in
+//! progress alone, and those of an aborted or expired upload become orphans
+//! for its repair to remove.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use wfldb_core::*;
+use crate::recovery::MULTIPART_PREFIX;
+use crate::{Storage, StorageEngine, SystemPartition, MAX_CHUNK_SIZE};
+
+/// Highest part number an upload may use
+pub const MAX_PART_NUMBER: u32 = 10_000;
+
+fn upload_key(bucket: &str, upload_id: &str) -> String {
+    format!("{}{}/{}", MULTIPART_PREFIX, bucket, upload_id)
+}
+
+fn load(system: &SystemPartition, storage_key: &str) -> Result<Option<MultipartUploadState>> {
+    match system.get(storage_key)? {
+        Some(data) => serde_json::from_slice(&data).map(Some).map_err(WflDBError::Serialization),
+        None => Ok(None),
+    }
+}
+
+fn save(system: &SystemPartition, storage_key: &str, upload: &MultipartUploadState) -> Result<()> {
+    let encoded = serde_json::to_vec(upload).map_err(WflDBError::Serialization)?;
+    system.put(storage_key, &encoded)
+}
+
+fn not_found(upload_id: &str) -> WflDBError {
+    WflDBError::UploadNotFound { upload_id: upload_id.to_string() }
+}
+
+/// Upload ids are ULIDs; anything else can't name an upload
+fn upload_lock_key(upload_id: &str) -> Result<Key> {
+    match ulid::Ulid::from_string(upload_id) {
+        Ok(_) => Key::new(upload_id),
+        Err(_) => Err(not_found(upload_id)),
+    }
+}
+
+impl Storage {
+    /// Start an upload of `key`
+    pub fn create_multipart_upload(&self, bucket_id: &BucketId, key: &Key) -> Result<MultipartUploadState> {
+        let engine = self.engine();
+        // Make sure the bucket exists before parts are sent to it
+        engine.bucket(bucket_id)?;
+        let upload = MultipartUploadState::new(ulid::Ulid::new().to_string(), bucket_id.clone(), key.clone());
+        save(&engine.system()?, &upload_key(&engine.namespaced(bucket_id), &upload.upload_id), &upload)?;
+        Ok(upload)
+    }
+
+    /// Upload in progress, if it hasn't been completed, aborted or expired
+    pub fn multipart_upload(&self, bucket_id: &BucketId, upload_id: &str) -> Result<Option<MultipartUploadState>> {
+        let engine = self.engine();
+        load(&engine.system()?, &upload_key(&engine.namespaced(bucket_id), upload_id))
+    }
+
+    /// Store part `part_number` (1 to [`MAX_PART_NUMBER`]) of an upload,
+    /// replacing any earlier upload of the same part
+    pub fn upload_part(&self, bucket_id: &BucketId, upload_id: &str, part_number: u32, data: &[u8]) -> Result<PartInfo> {
+        if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+            return Err(WflDBError::InvalidKey(format!("part numbers run from 1 to {}", MAX_PART_NUMBER)));
+        }
+        let engine = self.engine();
+        let bucket = engine.namespaced(bucket_id);
+        let storage_key = upload_key(&bucket, upload_id);
+        let system = engine.system()?;
+        if load(&system, &storage_key)?.is_none() {
+            return Err(not_found(upload_id));
+        }
+
+        // Chunks are stored before the state is locked, so parts of one
+        // upload are written in parallel
+        let mut chunks = Vec::with_capacity(data.len().div_ceil(MAX_CHUNK_SIZE).max(1));
+        for chunk in data.chunks(MAX_CHUNK_SIZE) {
+            chunks.push(self.put_chunk(bucket_id, chunk)?.0);
+        }
+        if data.is_empty() {
+            chunks.push(self.put_chunk(bucket_id, data)?.0);
+        }
+
+        let lock_key = upload_lock_key(upload_id)?;
+        let _lock = engine.key_locks().lock(&storage_key, [&lock_key]);
+        let mut upload = load(&system, &storage_key)?.ok_or_else(|| not_found(upload_id))?;
+        upload.add_chunked_part(part_number, data.len() as u64, ContentHash::new(data), chunks);
+        save(&system, &storage_key, &upload)?;
+        Ok(upload.parts.iter().find(|part| part.part_number == part_number).cloned().unwrap())
+    }
+
+    /// Commit the upload's parts as its object on behalf of `owner`, with the
+    /// ownership rules of [`put_manifest_as`](Self::put_manifest_as)
+    ///
+    /// Parts must be numbered 1 to N without gaps.
+    pub fn complete_multipart_upload(
+        &self,
+        bucket_id: &BucketId,
+        upload_id: &str,
+        owner: Option<&str>,
+    ) -> Result<ObjectMetadata> {
+        let engine = self.engine();
+        let storage_key = upload_key(&engine.namespaced(bucket_id), upload_id);
+        let system = engine.system()?;
+
+        // The state is taken out before the object is committed, so parts
+        // arriving meanwhile are refused rather than lost
+        let upload = {
+            let lock_key = upload_lock_key(upload_id)?;
+            let _lock = engine.key_locks().lock(&storage_key, [&lock_key]);
+            let upload = load(&system, &storage_key)?.ok_or_else(|| not_found(upload_id))?;
+            if !upload.is_complete() {
+                return Err(WflDBError::InvalidKey(format!(
+                    "upload {} needs parts numbered 1 to N without gaps",
+                    upload_id
+                )));
+            }
+            system.delete(&storage_key)?;
+            upload
+        };
+        let chunks = upload.parts.iter().flat_map(|part| part.chunks.iter().cloned()).collect();
+        match self.put_manifest_as(bucket_id, &upload.key, chunks, owner) {
+            Ok(metadata) => Ok(metadata),
+            Err(e) => {
+                save(&system, &storage_key, &upload)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop an upload; its chunks are left for the refcount pass
+    pub fn abort_multipart_upload(&self, bucket_id: &BucketId, upload_id: &str) -> Result<()> {
+        let engine = self.engine();
+        let storage_key = upload_key(&engine.namespaced(bucket_id), upload_id);
+        let lock_key = upload_lock_key(upload_id)?;
+        let _lock = engine.key_locks().lock(&storage_key, [&lock_key]);
+        let system = engine.system()?;
+        match load(&system, &storage_key)? {
+            Some(_) => system.delete(&storage_key),
+            None => Err(not_found(upload_id)),
+        }
+    }
+}
+
+/// Uploads in progress in every namespace, with their namespaced bucket
+/// names, oldest first
+pub fn list_multipart_uploads(engine: &StorageEngine) -> Result<Vec<(String, MultipartUploadState)>> {
+    let mut uploads = Vec::new();
+    for (storage_key, value) in engine.system()?.scan_prefix(MULTIPART_PREFIX)? {
+        let Some((bucket, _)) = storage_key[MULTIPART_PREFIX.len()..].rsplit_once('/') else {
+            continue;
+        };
+        // Unparseable state is the startup check's to report
+        if let Ok(upload) = serde_json::from_slice::<MultipartUploadState>(&value) {
+            uploads.push((bucket.to_string(), upload));
+        }
+    }
+    uploads.sort_by_key(|(_, upload)| upload.created_at);
+    Ok(uploads)
+}
+
+/// Chunks named by uploads in progress to the bucket with namespaced name `bucket`
+pub(crate) fn pending_upload_chunks(engine: &StorageEngine, bucket: &str) -> Result<HashSet<ContentHash>> {
+    let prefix = format!("{}{}/", MULTIPART_PREFIX, bucket);
+    let mut chunks = HashSet::new();
+    for (_, value) in engine.system()?.scan_prefix(&prefix)? {
+        if let Ok(upload) = serde_json::from_slice::<MultipartUploadState>(&value) {
+            chunks.extend(upload.parts.into_iter().flat_map(|part| part.chunks));
+        }
+    }
+    Ok(chunks)
+}
+
+/// Abort uploads of every namespace started more than `max_age` before
+/// `now`, returning how many were removed
+pub fn purge_stale_uploads(engine: &StorageEngine, max_age: Duration, now: SystemTime) -> Result<usize> {
+    let system = engine.system()?;
+    let stale = |upload: &MultipartUploadState| now.duration_since(upload.created_at).is_ok_and(|age| age >= max_age);
+    let mut purged = 0;
+    for (storage_key, value) in system.scan_prefix(MULTIPART_PREFIX)? {
+        if !serde_json::from_slice::<MultipartUploadState>(&value).is_ok_and(|upload| stale(&upload)) {
+            continue;
+        }
+        let Some((_, upload_id)) = storage_key.rsplit_once('/') else {
+            continue;
+        };
+        let Ok(lock_key) = upload_lock_key(upload_id) else {
+            continue;
+        };
+        // Re-check under the lock: the upload may have completed since the scan
+        let _lock = engine.key_locks().lock(&storage_key, [&lock_key]);
+        if load(&system, &storage_key)?.is_some_and(|upload| stale(&upload)) {
+            system.delete(&storage_key)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_lifecycle() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("videos").unwrap();
+        let key = Key::new("clip.mp4").unwrap();
+
+        let upload = storage.create_multipart_upload(&bucket, &key).unwrap();
+        let second = vec![2u8; MAX_CHUNK_SIZE + 10];
+        storage.upload_part(&bucket, &upload.upload_id, 2, &second).unwrap();
+        assert!(storage.complete_multipart_upload(&bucket, &upload.upload_id, None).is_err());
+        storage.upload_part(&bucket, &upload.upload_id, 1, b"a first try").unwrap();
+        // Re-sending a part replaces it
+        let part = storage.upload_part(&bucket, &upload.upload_id, 1, b"first").unwrap();
+        assert_eq!((part.size, part.chunks.len()), (5, 1));
+        assert!(storage.upload_part(&bucket, &upload.upload_id, 0, b"x").is_err());
+
+        // The upload is still there after a failed completion
+        let listed = list_multipart_uploads(&engine).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].0.as_str(), listed[0].1.parts.len()), ("videos", 2));
+        assert_eq!(listed[0].1.total_size(), 5 + second.len() as u64);
+        assert_eq!(pending_upload_chunks(&engine, "videos").unwrap().len(), 3);
+
+        let metadata = storage.complete_multipart_upload(&bucket, &upload.upload_id, None).unwrap();
+        assert_eq!(metadata.size, 5 + second.len() as u64);
+        let data = storage.get_object(&bucket, &key).unwrap().unwrap();
+        assert_eq!((&data[..5], data.len()), (&b"first"[..], 5 + second.len()));
+        assert!(storage.multipart_upload(&bucket, &upload.upload_id).unwrap().is_none());
+        assert!(matches!(
+            storage.upload_part(&bucket, &upload.upload_id, 3, b"late"),
+            Err(WflDBError::UploadNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stale_uploads_purged() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("videos").unwrap();
+        let upload = storage.create_multipart_upload(&bucket, &Key::new("a").unwrap()).unwrap();
+        let aborted = storage.create_multipart_upload(&bucket, &Key::new("b").unwrap()).unwrap();
+        storage.abort_multipart_upload(&bucket, &aborted.upload_id).unwrap();
+        assert!(storage.abort_multipart_upload(&bucket, &aborted.upload_id).is_err());
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(purge_stale_uploads(&engine, day, SystemTime::now()).unwrap(), 0);
+        assert_eq!(purge_stale_uploads(&engine, day, upload.created_at + day).unwrap(), 1);
+        assert!(list_multipart_uploads(&engine).unwrap().is_empty());
+    }
+}
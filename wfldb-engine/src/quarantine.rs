@@ -0,0 +1,253 @@
+//! Quarantine of newly written objects
+//!
+//! With quarantine on, a write first holds its key under
+//! `quarantine/{bucket}/{key}` in the system partition, with the namespaced
+//! bucket name; the hold is taken before the object changes, so there is no
+//! moment where unscanned content is served. Once the write has finished,
+//! settling the hold records the version it produced, and a scanner
+//! approves or rejects that version. A later write of the key takes a new
+//! hold, so a verdict about older content never releases newer content.
+
+use serde::{Deserialize, Serialize};
+use wfldb_core::*;
+use crate::{Storage, StorageEngine, SystemPartition};
+
+pub(crate) const QUARANTINE_PREFIX: &str = "quarantine/";
+
+/// A key held until its content has been scanned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    /// Namespaced bucket name
+    #[serde(skip)]
+    pub bucket: String,
+    #[serde(skip)]
+    pub key: String,
+    /// Id of the write holding the key
+    pub id: String,
+    pub held_at_ms: u64,
+    /// Version to scan, set once the write has finished
+    #[serde(default)]
+    pub version: Option<Version>,
+    /// Scans that failed to reach a verdict
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+fn hold_key(bucket: &str, key: &str) -> String {
+    format!("{}{}/{}", QUARANTINE_PREFIX, bucket, key)
+}
+
+fn load(system: &SystemPartition, bucket: &str, key: &str) -> Result<Option<QuarantineEntry>> {
+    match system.get(&hold_key(bucket, key))? {
+        Some(data) => {
+            let mut entry: QuarantineEntry = serde_json::from_slice(&data).map_err(WflDBError::Serialization)?;
+            entry.bucket = bucket.to_string();
+            entry.key = key.to_string();
+            Ok(Some(entry))
+        }
+        None => Ok(None),
+    }
+}
+
+fn save(system: &SystemPartition, entry: &QuarantineEntry) -> Result<()> {
+    let encoded = serde_json::to_vec(entry).map_err(WflDBError::Serialization)?;
+    system.put(&hold_key(&entry.bucket, &entry.key), &encoded)
+}
+
+/// Record the version a finished write left behind, or drop the hold if
+/// the key is gone; does nothing once another write has taken the hold
+fn settle(engine: &StorageEngine, bucket: &str, key: &Key, id: &str) -> Result<()> {
+    let (scoped, bucket_id) = engine.resolve_namespaced(bucket)?;
+    let _lock = engine.key_locks().lock(bucket, [key]);
+    let system = engine.system()?;
+    let Some(mut entry) = load(&system, bucket, key.as_str())?.filter(|entry| entry.id == id) else {
+        return Ok(());
+    };
+    match scoped.bucket(&bucket_id)?.get_metadata(key)? {
+        Some(metadata) => {
+            entry.version = Some(metadata.version);
+            save(&system, &entry)
+        }
+        None => system.delete(&hold_key(bucket, key.as_str())),
+    }
+}
+
+impl Storage {
+    /// Hold a key ahead of a write, returning the hold's id for
+    /// [`Storage::settle_hold`]
+    pub fn hold_for_scan(&self, bucket_id: &BucketId, key: &Key, now_ms: u64) -> Result<String> {
+        let engine = self.engine();
+        let bucket = engine.namespaced(bucket_id);
+        let _lock = engine.key_locks().lock(&bucket, [key]);
+        let entry = QuarantineEntry {
+            bucket,
+            key: key.as_str().to_string(),
+            id: ulid::Ulid::new().to_string(),
+            held_at_ms: now_ms,
+            version: None,
+            attempts: 0,
+            last_error: None,
+        };
+        save(&engine.system()?, &entry)?;
+        Ok(entry.id)
+    }
+
+    /// Mark the write holding a key as finished, whether it succeeded or not
+    ///
+    /// A failed write leaves whatever the key held before held until it is
+    /// scanned again.
+    pub fn settle_hold(&self, bucket_id: &BucketId, key: &Key, id: &str) -> Result<()> {
+        settle(self.engine(), &self.engine().namespaced(bucket_id), key, id)
+    }
+
+    /// Hold on a key, if its content is waiting for a scan
+    pub fn quarantine_entry(&self, bucket_id: &BucketId, key: &Key) -> Result<Option<QuarantineEntry>> {
+        let engine = self.engine();
+        load(&engine.system()?, &engine.namespaced(bucket_id), key.as_str())
+    }
+
+    /// Held keys of a bucket that start with `prefix`
+    pub fn quarantined_keys(&self, bucket_id: &BucketId, prefix: &str) -> Result<Vec<String>> {
+        let engine = self.engine();
+        let scan_prefix = hold_key(&engine.namespaced(bucket_id), prefix);
+        let start = scan_prefix.len() - prefix.len();
+        Ok(engine.system()?.scan_prefix(&scan_prefix)?.into_iter().map(|(k, _)| k[start..].to_string()).collect())
+    }
+}
+
+/// Held keys of every namespace, oldest hold first
+pub fn list_quarantine(engine: &StorageEngine) -> Result<Vec<QuarantineEntry>> {
+    let mut entries = Vec::new();
+    for (storage_key, value) in engine.system()?.scan_prefix(QUARANTINE_PREFIX)? {
+        let Some((bucket, key)) = storage_key[QUARANTINE_PREFIX.len()..].split_once('/') else {
+            continue;
+        };
+        let mut entry: QuarantineEntry = serde_json::from_slice(&value).map_err(WflDBError::Serialization)?;
+        entry.bucket = bucket.to_string();
+        entry.key = key.to_string();
+        entries.push(entry);
+    }
+    entries.sort_by_key(|entry| entry.held_at_ms);
+    Ok(entries)
+}
+
+/// Record a held key's current version, or drop the hold if the key is
+/// gone, for a hold its write never settled (the server stopped midway) or
+/// whose content changed without taking a new hold (a replicated write)
+pub fn refresh_hold(engine: &StorageEngine, entry: &QuarantineEntry) -> Result<()> {
+    settle(engine, &entry.bucket, &Key::new(&entry.key)?, &entry.id)
+}
+
+/// Release a held key whose scan approved `entry.version`; false if the
+/// key has been written again since
+pub fn release_quarantined(engine: &StorageEngine, entry: &QuarantineEntry) -> Result<bool> {
+    let key = Key::new(&entry.key)?;
+    let _lock = engine.key_locks().lock(&entry.bucket, [&key]);
+    let system = engine.system()?;
+    match load(&system, &entry.bucket, &entry.key)? {
+        Some(current) if current.id == entry.id && current.version == entry.version => {
+            system.delete(&hold_key(&entry.bucket, &entry.key))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Delete the content a scan rejected and release its hold; false, leaving
+/// everything alone, if the key has been written again since
+pub fn reject_quarantined(engine: &StorageEngine, entry: &QuarantineEntry) -> Result<bool> {
+    let Some(version) = entry.version.clone() else {
+        return Ok(false);
+    };
+    let key = Key::new(&entry.key)?;
+    let (scoped, bucket_id) = engine.resolve_namespaced(&entry.bucket)?;
+    let delete = TxnOp::Delete { key: key.clone(), precondition: Some(Precondition::Version(version)) };
+    match Storage::new(scoped).transact(&bucket_id, vec![delete]) {
+        Ok(_) => {}
+        Err(WflDBError::PreconditionFailed { .. }) => return Ok(false),
+        Err(e) => return Err(e),
+    }
+    // The delete settles the key; only this hold's own record is removed
+    let _lock = engine.key_locks().lock(&entry.bucket, [&key]);
+    let system = engine.system()?;
+    if load(&system, &entry.bucket, &entry.key)?.is_some_and(|current| current.id == entry.id) {
+        system.delete(&hold_key(&entry.bucket, &entry.key))?;
+    }
+    Ok(true)
+}
+
+/// Note a scan that failed to reach a verdict, for a later retry
+pub fn record_scan_failure(engine: &StorageEngine, entry: &QuarantineEntry, error: &str) -> Result<()> {
+    let key = Key::new(&entry.key)?;
+    let _lock = engine.key_locks().lock(&entry.bucket, [&key]);
+    let system = engine.system()?;
+    match load(&system, &entry.bucket, &entry.key)? {
+        Some(mut current) if current.id == entry.id => {
+            current.attempts += 1;
+            current.last_error = Some(error.to_string());
+            save(&system, &current)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_lifecycle() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("uploads").unwrap();
+        let key = Key::new("report.pdf").unwrap();
+
+        let id = storage.hold_for_scan(&bucket, &key, 1_000).unwrap();
+        let written = storage.put_object(&bucket, &key, b"first").unwrap();
+        storage.settle_hold(&bucket, &key, &id).unwrap();
+        let entry = storage.quarantine_entry(&bucket, &key).unwrap().unwrap();
+        assert_eq!(entry.version, Some(written.version));
+        assert_eq!(storage.quarantined_keys(&bucket, "rep").unwrap(), vec!["report.pdf"]);
+        assert!(storage.quarantined_keys(&bucket, "other").unwrap().is_empty());
+
+        // A write after the scan started keeps its own hold
+        let newer = storage.hold_for_scan(&bucket, &key, 2_000).unwrap();
+        storage.put_object(&bucket, &key, b"second").unwrap();
+        assert!(!release_quarantined(&engine, &entry).unwrap());
+        assert!(!reject_quarantined(&engine, &entry).unwrap());
+        storage.settle_hold(&bucket, &key, &newer).unwrap();
+
+        let entries = list_quarantine(&engine).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, newer);
+        assert!(release_quarantined(&engine, &entries[0]).unwrap());
+        assert!(storage.quarantine_entry(&bucket, &key).unwrap().is_none());
+        assert_eq!(storage.get_object(&bucket, &key).unwrap(), Some(b"second".to_vec()));
+    }
+
+    #[test]
+    fn test_reject_deletes_scanned_version() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("uploads").unwrap();
+        let key = Key::new("payload.exe").unwrap();
+
+        let id = storage.hold_for_scan(&bucket, &key, 1_000).unwrap();
+        storage.put_object(&bucket, &key, b"bad").unwrap();
+        storage.settle_hold(&bucket, &key, &id).unwrap();
+        let entry = storage.quarantine_entry(&bucket, &key).unwrap().unwrap();
+        record_scan_failure(&engine, &entry, "scanner unreachable").unwrap();
+        assert_eq!(storage.quarantine_entry(&bucket, &key).unwrap().unwrap().attempts, 1);
+
+        assert!(reject_quarantined(&engine, &entry).unwrap());
+        assert!(storage.get_object(&bucket, &key).unwrap().is_none());
+        assert!(list_quarantine(&engine).unwrap().is_empty());
+
+        // A hold whose write failed is dropped when nothing was written
+        let id = storage.hold_for_scan(&bucket, &key, 3_000).unwrap();
+        storage.settle_hold(&bucket, &key, &id).unwrap();
+        assert!(storage.quarantine_entry(&bucket, &key).unwrap().is_none());
+    }
+}
@@ -0,0 +1,325 @@
+//! Startup consistency check
+//!
+//! `put_large` writes chunks before the manifest, so a crash mid-write leaves
+//! chunks with reference counts but no object pointing at them, and a torn
+//! write can leave metadata that no longer parses. The quick pass checks a
+//! bounded number of objects per bucket and that every manifest's chunks are
+//! present; the deep pass checks every object, re-hashes all data, and looks
+//! for orphaned chunks.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+use wfldb_core::*;
+use crate::layout;
+use crate::{StorageEngine, SystemPartition};
+
+/// System partition prefix for in-progress multipart upload state
+pub const MULTIPART_PREFIX: &str = "multipart/";
+
+/// Uploads untouched for this long are reported as dangling
+pub const DANGLING_UPLOAD_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How thorough the startup check is
+#[derive(Debug, Clone, Copy)]
+pub struct CheckOptions {
+    /// Verify every object, re-hash data, and find orphaned chunks
+    pub deep: bool,
+    /// Objects checked per bucket in the quick pass
+    pub max_objects_per_bucket: usize,
+}
+
+impl CheckOptions {
+    pub fn quick() -> Self {
+        CheckOptions {
+            deep: false,
+            max_objects_per_bucket: 10_000,
+        }
+    }
+
+    pub fn deep() -> Self {
+        CheckOptions {
+            deep: true,
+            max_objects_per_bucket: usize::MAX,
+        }
+    }
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self::quick()
+    }
+}
+
+/// Findings of a consistency check
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    pub deep: bool,
+    pub elapsed: Duration,
+    pub buckets: usize,
+    pub objects_checked: u64,
+    /// Buckets whose objects were not all checked
+    pub truncated_buckets: usize,
+    pub system_entries: usize,
+    /// System partition keys whose values do not parse
+    pub corrupt_system_entries: Vec<String>,
+    pub open_uploads: usize,
+    pub dangling_uploads: usize,
+    /// `bucket/key` of objects whose metadata does not parse
+    pub corrupt_metadata: Vec<String>,
+    /// `bucket/key` of objects with missing chunks or inline data
+    pub incomplete_objects: Vec<String>,
+    /// `bucket/key` of objects whose data doesn't match its hash (deep only)
+    pub hash_mismatches: Vec<String>,
+    /// Chunks no manifest references, left by interrupted writes (deep only)
+    pub orphaned_chunks: u64,
+}
+
+impl RecoveryReport {
+    /// Whether the check found nothing to act on
+    pub fn is_clean(&self) -> bool {
+        self.corrupt_system_entries.is_empty()
+            && self.dangling_uploads == 0
+            && self.corrupt_metadata.is_empty()
+            && self.incomplete_objects.is_empty()
+            && self.hash_mismatches.is_empty()
+            && self.orphaned_chunks == 0
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} check in {}ms: {} buckets, {} objects checked{}, {} system entries ({} corrupt), \
+             {} open uploads ({} dangling), {} corrupt metadata, {} incomplete objects",
+            if self.deep { "deep" } else { "quick" },
+            self.elapsed.as_millis(),
+            self.buckets,
+            self.objects_checked,
+            if self.truncated_buckets > 0 {
+                format!(" ({} buckets partially)", self.truncated_buckets)
+            } else {
+                String::new()
+            },
+            self.system_entries,
+            self.corrupt_system_entries.len(),
+            self.open_uploads,
+            self.dangling_uploads,
+            self.corrupt_metadata.len(),
+            self.incomplete_objects.len(),
+        )?;
+        if self.deep {
+            write!(
+                f,
+                ", {} hash mismatches, {} orphaned chunks",
+                self.hash_mismatches.len(),
+                self.orphaned_chunks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a consistency pass over the system partition and every bucket
+pub fn check_consistency(engine: &StorageEngine, options: CheckOptions) -> Result<RecoveryReport> {
+    let started = Instant::now();
+    let mut report = RecoveryReport {
+        deep: options.deep,
+        ..Default::default()
+    };
+
+    check_system(&engine.system()?, &mut report)?;
+    // The shared namespace and every tenant's are checked alike
+    let mut namespaces = vec![engine.clone()];
+    for tenant in engine.tenant_ids()? {
+        namespaces.push(engine.for_tenant(&tenant));
+    }
+    for namespace in &namespaces {
+        check_namespace(namespace, options, &mut report)?;
+    }
+
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+fn check_namespace(engine: &StorageEngine, options: CheckOptions, report: &mut RecoveryReport) -> Result<()> {
+    // Cloned buckets reference chunks held by other buckets, so orphans can
+    // only be identified once every manifest in every bucket has been seen
+    let mut referenced = HashSet::new();
+    let bucket_ids = engine.bucket_ids()?;
+    for bucket_id in &bucket_ids {
+        report.buckets += 1;
+        check_bucket(engine, bucket_id, options, &mut referenced, report)?;
+    }
+    if options.deep {
+        for bucket_id in &bucket_ids {
+            let bucket = engine.bucket(bucket_id)?;
+            for item in bucket.main_partition.prefix([layout::CHUNK_REF]) {
+                let (ref_key, _) = item.map_err(WflDBError::storage)?;
+                let hex = layout::chunk_hash(&ref_key).map(|hash| hash.to_hex()).unwrap_or_default();
+                if !referenced.contains(&(bucket_id.as_str().to_string(), hex)) {
+                    report.orphaned_chunks += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_system(system: &SystemPartition, report: &mut RecoveryReport) -> Result<()> {
+    let now = SystemTime::now();
+    for (key, value) in system.scan_prefix("")? {
+        report.system_entries += 1;
+        if key.starts_with(MULTIPART_PREFIX) {
+            match serde_json::from_slice::<MultipartUploadState>(&value) {
+                Ok(upload) => {
+                    report.open_uploads += 1;
+                    let age = now.duration_since(upload.created_at).unwrap_or_default();
+                    if age >= DANGLING_UPLOAD_AGE {
+                        report.dangling_uploads += 1;
+                    }
+                }
+                Err(_) => report.corrupt_system_entries.push(key),
+            }
+        } else {
+            let corrupt = if key.starts_with(crate::usage::USAGE_PREFIX) {
+                serde_json::from_slice::<crate::KeyUsage>(&value).is_err()
+            } else if key == crate::warmup::HOT_KEYS_KEY {
+                serde_json::from_slice::<Vec<crate::HotKey>>(&value).is_err()
+            } else if key.starts_with(crate::lease::LEASE_PREFIX) {
+                serde_json::from_slice::<Lease>(&value).is_err()
+            } else if key.starts_with(crate::catalog::BUCKET_PREFIX) {
+                serde_json::from_slice::<crate::catalog::BucketRecord>(&value).is_err()
+            } else {
+                false
+            };
+            if corrupt {
+                report.corrupt_system_entries.push(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_bucket(
+    engine: &StorageEngine,
+    bucket_id: &BucketId,
+    options: CheckOptions,
+    referenced: &mut HashSet<(String, String)>,
+    report: &mut RecoveryReport,
+) -> Result<()> {
+    let bucket = engine.bucket(bucket_id)?;
+    let partition = &bucket.main_partition;
+
+    for (checked, item) in partition.prefix([layout::META]).enumerate() {
+        if checked >= options.max_objects_per_bucket {
+            report.truncated_buckets += 1;
+            break;
+        }
+        report.objects_checked += 1;
+
+        let (meta_key, value) = item.map_err(WflDBError::storage)?;
+        // A key the name policy no longer accepts is still checked, under its raw bytes
+        let key = layout::object_key(&meta_key)
+            .map(|key| key.as_str().to_string())
+            .unwrap_or_else(|_| String::from_utf8_lossy(&meta_key[1..]).into_owned());
+        let name = format!("{}/{}", engine.namespaced(bucket_id), key);
+        let (metadata, data) = match ObjectMetadata::decode_with_data(&value) {
+            Ok(record) => record,
+            Err(_) => {
+                report.corrupt_metadata.push(name);
+                continue;
+            }
+        };
+
+        match &metadata.chunk_manifest {
+            Some(manifest) => {
+                let mut complete = true;
+                for hash in &manifest.chunks {
+                    if options.deep {
+                        let holder = bucket.chunk_holder(hash)?.unwrap_or_else(|| bucket_id.clone());
+                        referenced.insert((holder.as_str().to_string(), hash.to_hex()));
+                        match bucket.get_chunk(hash)? {
+                            Some(chunk) if ContentHash::new(&chunk) == *hash => {}
+                            Some(_) => {
+                                report.hash_mismatches.push(name.clone());
+                                break;
+                            }
+                            None => complete = false,
+                        }
+                    } else {
+                        complete &= bucket.has_chunk(hash)?;
+                    }
+                }
+                if !complete {
+                    report.incomplete_objects.push(name);
+                }
+            }
+            None => {
+                match (data, &metadata.content_hash) {
+                    (None, _) => report.incomplete_objects.push(name),
+                    (Some(data), Some(hash)) if options.deep && ContentHash::new(data) != *hash => {
+                        report.hash_mismatches.push(name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
+
+    #[test]
+    fn test_clean_store_reports_clean() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket = BucketId::new("photos").unwrap();
+        storage.put_object(&bucket, &Key::new("small").unwrap(), b"tiny").unwrap();
+        storage.put_object(&bucket, &Key::new("large").unwrap(), &vec![7u8; 128 * 1024]).unwrap();
+
+        let report = check_consistency(&engine, CheckOptions::deep()).unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.buckets, 1);
+        assert_eq!(report.objects_checked, 2);
+    }
+
+    #[test]
+    fn test_detects_interrupted_large_write() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket_id = BucketId::new("photos").unwrap();
+        let bucket = engine.bucket(&bucket_id).unwrap();
+        bucket.put_large(&Key::new("large").unwrap(), vec![vec![1u8; 1024], vec![2u8; 1024]]).unwrap();
+
+        // A crash after writing a chunk but before its manifest leaves an orphan
+        let orphan = ContentHash::new(b"orphan");
+        bucket.main_partition.insert(bucket.chunk_key(&orphan), b"orphan").unwrap();
+        bucket.main_partition.insert(bucket.chunk_ref_key(&orphan), 1u32.to_le_bytes()).unwrap();
+        // And a lost chunk makes an object incomplete
+        let lost = ContentHash::new(&[2u8; 1024]);
+        bucket.main_partition.remove(bucket.chunk_key(&lost)).unwrap();
+
+        let quick = check_consistency(&engine, CheckOptions::quick()).unwrap();
+        assert_eq!(quick.incomplete_objects, vec!["photos/large".to_string()]);
+        assert_eq!(quick.orphaned_chunks, 0);
+
+        let deep = check_consistency(&engine, CheckOptions::deep()).unwrap();
+        assert_eq!(deep.orphaned_chunks, 1);
+        assert!(!deep.is_clean());
+    }
+
+    #[test]
+    fn test_corrupt_system_entry_reported() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let system = engine.system().unwrap();
+        system.put("multipart/abc", b"not json").unwrap();
+
+        let report = check_consistency(&engine, CheckOptions::quick()).unwrap();
+        assert_eq!(report.corrupt_system_entries, vec!["multipart/abc".to_string()]);
+    }
+}
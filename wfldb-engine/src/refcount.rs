@@ -0,0 +1,323 @@
+//! Chunk reference count verification and rebuild
+//!
+//! A chunk's reference count is meant to equal the number of manifest
+//! entries that resolve to it. A crash between writing a chunk and its
+//! manifest, or between a delete and its release, leaves the count off:
+//! too high and the chunk leaks, too low and a later delete removes data
+//! another object still needs. This pass recounts every manifest and
+//! compares the result with what is stored, optionally writing the
+//! recomputed counts back.
+//!
+//! A manifest entry counts against the bucket that stores the chunk
+//! locally, otherwise against the bucket its clone link points at, which is
+//! the bucket a delete would release it from.
+//!
+//! Repair assumes nothing is writing large objects meanwhile: a chunk
+//! written just before its manifest looks exactly like an orphan, as does
+//! a chunk a client uploaded for a manifest it hasn't committed yet.
+//! Chunks named by a multipart upload in progress are the exception: they
+//! are never counted as orphans.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+use wfldb_core::*;
+use crate::layout;
+use crate::multipart::pending_upload_chunks;
+use crate::StorageEngine;
+
+/// Discrepancies listed individually in a report; the rest are only counted
+const MAX_LISTED_ISSUES: usize = 100;
+
+/// What is wrong with one chunk's reference count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefcountIssueKind {
+    /// Stored count differs from the manifests
+    Mismatched,
+    /// Manifests use the chunk but it has no count
+    MissingRef,
+    /// Count or data with no manifest using it
+    Orphaned,
+    /// Manifests use the chunk but its data is gone; not repairable
+    MissingChunk,
+}
+
+/// One chunk whose reference count is wrong
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefcountIssue {
+    /// Namespaced name of the bucket holding the chunk
+    pub bucket: String,
+    pub chunk: String,
+    pub kind: RefcountIssueKind,
+    pub expected: u32,
+    pub actual: Option<u32>,
+}
+
+/// Findings of a reference count pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RefcountReport {
+    pub repair: bool,
+    /// Whether every bucket was scanned; an interrupted pass repairs nothing
+    /// in the namespace it stopped in
+    pub complete: bool,
+    pub buckets: usize,
+    pub objects: u64,
+    /// Distinct chunks referenced by manifests
+    pub chunks: u64,
+    pub mismatched: u64,
+    pub missing_refs: u64,
+    pub orphaned: u64,
+    pub missing_chunks: u64,
+    /// Counts written or orphans removed
+    pub repaired: u64,
+    pub issues: Vec<RefcountIssue>,
+    pub elapsed_ms: u64,
+}
+
+impl RefcountReport {
+    /// Whether the pass found nothing to fix
+    pub fn is_clean(&self) -> bool {
+        self.mismatched == 0 && self.missing_refs == 0 && self.orphaned == 0 && self.missing_chunks == 0
+    }
+
+    fn record(&mut self, issue: RefcountIssue) {
+        match issue.kind {
+            RefcountIssueKind::Mismatched => self.mismatched += 1,
+            RefcountIssueKind::MissingRef => self.missing_refs += 1,
+            RefcountIssueKind::Orphaned => self.orphaned += 1,
+            RefcountIssueKind::MissingChunk => self.missing_chunks += 1,
+        }
+        if self.issues.len() < MAX_LISTED_ISSUES {
+            self.issues.push(issue);
+        }
+    }
+}
+
+impl std::fmt::Display for RefcountReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} in {}ms: {} buckets, {} objects, {} chunks; {} mismatched, {} missing refs, {} orphaned, \
+             {} missing chunks, {} repaired",
+            if self.repair { "refcount rebuild" } else { "refcount check" },
+            if self.complete { "" } else { " (interrupted)" },
+            self.elapsed_ms,
+            self.buckets,
+            self.objects,
+            self.chunks,
+            self.mismatched,
+            self.missing_refs,
+            self.orphaned,
+            self.missing_chunks,
+            self.repaired,
+        )
+    }
+}
+
+/// Recount chunk references in every namespace, writing corrected counts
+/// and removing orphans when `repair` is set
+///
+/// `progress` is called with the bytes read for each object and chunk
+/// count; returning `false` stops the pass early.
+pub fn rebuild_refcounts(
+    engine: &StorageEngine,
+    repair: bool,
+    progress: &mut dyn FnMut(u64) -> bool,
+) -> Result<RefcountReport> {
+    let started = Instant::now();
+    let mut report = RefcountReport { repair, complete: true, ..Default::default() };
+
+    let mut namespaces = vec![engine.clone()];
+    for tenant in engine.tenant_ids()? {
+        namespaces.push(engine.for_tenant(&tenant));
+    }
+    for namespace in &namespaces {
+        if !rebuild_namespace(namespace, repair, progress, &mut report)? {
+            report.complete = false;
+            break;
+        }
+    }
+    if repair && report.repaired > 0 {
+        engine.persist()?;
+    }
+
+    report.elapsed_ms = started.elapsed().as_millis() as u64;
+    Ok(report)
+}
+
+/// Returns `false` if `progress` asked to stop
+fn rebuild_namespace(
+    engine: &StorageEngine,
+    repair: bool,
+    progress: &mut dyn FnMut(u64) -> bool,
+    report: &mut RefcountReport,
+) -> Result<bool> {
+    // Clones point across buckets, so every manifest is counted before any
+    // bucket's stored counts can be judged
+    let bucket_ids = engine.bucket_ids()?;
+    let mut expected: BTreeMap<(String, String), u32> = BTreeMap::new();
+    for bucket_id in &bucket_ids {
+        let bucket = engine.bucket(bucket_id)?;
+        report.buckets += 1;
+        for item in bucket.main_partition.prefix([layout::META]) {
+            let (_, value) = item.map_err(WflDBError::storage)?;
+            if !progress(value.len() as u64) {
+                return Ok(false);
+            }
+            report.objects += 1;
+            // Unparseable metadata is the startup check's to report
+            let manifest = match ObjectMetadata::decode(&value) {
+                Ok(metadata) => metadata.chunk_manifest,
+                Err(_) => None,
+            };
+            for hash in manifest.iter().flat_map(|m| &m.chunks) {
+                let local = bucket.main_partition
+                    .contains_key(bucket.chunk_key(hash))
+                    .map_err(WflDBError::storage)?;
+                let holder = match bucket.chunk_home(hash)? {
+                    Some(home) if !local => home,
+                    _ => bucket_id.clone(),
+                };
+                *expected.entry((holder.as_str().to_string(), hash.to_hex())).or_default() += 1;
+            }
+        }
+    }
+    report.chunks += expected.len() as u64;
+
+    for bucket_id in &bucket_ids {
+        let bucket = engine.bucket(bucket_id)?;
+        let partition = &bucket.main_partition;
+        let name = engine.namespaced(bucket_id);
+        let pending: HashSet<String> = pending_upload_chunks(engine, &name)?.iter().map(ContentHash::to_hex).collect();
+
+        let mut stored: HashMap<String, u32> = HashMap::new();
+        for item in partition.prefix([layout::CHUNK_REF]) {
+            let (ref_key, value) = item.map_err(WflDBError::storage)?;
+            if !progress(value.len() as u64) {
+                return Ok(false);
+            }
+            let Some(hash) = layout::chunk_hash(&ref_key) else {
+                continue;
+            };
+            let hex = hash.to_hex();
+            let count = value.get(0..4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).unwrap_or(0);
+            stored.insert(hex, count);
+        }
+
+        // Chunk data with no count at all is an orphan too
+        let mut orphans: Vec<String> = Vec::new();
+        for item in partition.prefix([layout::CHUNK]) {
+            let (chunk_key, _) = item.map_err(WflDBError::storage)?;
+            let Some(hash) = layout::chunk_hash(&chunk_key) else {
+                continue;
+            };
+            let hex = hash.to_hex();
+            if !stored.contains_key(&hex)
+                && !pending.contains(&hex)
+                && !expected.contains_key(&(bucket_id.as_str().to_string(), hex.clone()))
+            {
+                orphans.push(hex);
+            }
+        }
+        for (hex, count) in &stored {
+            if !pending.contains(hex) && !expected.contains_key(&(bucket_id.as_str().to_string(), hex.clone())) {
+                report.record(RefcountIssue {
+                    bucket: name.clone(),
+                    chunk: hex.clone(),
+                    kind: RefcountIssueKind::Orphaned,
+                    expected: 0,
+                    actual: Some(*count),
+                });
+                orphans.push(hex.clone());
+            }
+        }
+        for hex in &orphans {
+            if !stored.contains_key(hex) {
+                report.record(RefcountIssue {
+                    bucket: name.clone(),
+                    chunk: hex.clone(),
+                    kind: RefcountIssueKind::Orphaned,
+                    expected: 0,
+                    actual: None,
+                });
+            }
+            if repair {
+                let hash = ContentHash::from_hex(hex)?;
+                partition.remove(bucket.chunk_key(&hash)).map_err(WflDBError::storage)?;
+                partition.remove(bucket.chunk_ref_key(&hash)).map_err(WflDBError::storage)?;
+                report.repaired += 1;
+            }
+        }
+
+        let held = expected.range((bucket_id.as_str().to_string(), String::new())..)
+            .take_while(|((holder, _), _)| holder == bucket_id.as_str());
+        for ((_, hex), &count) in held {
+            let hash = ContentHash::from_hex(hex)?;
+            let present = partition
+                .contains_key(bucket.chunk_key(&hash))
+                .map_err(WflDBError::storage)?;
+            let actual = stored.get(hex).copied();
+            let kind = if !present {
+                RefcountIssueKind::MissingChunk
+            } else if actual.is_none() {
+                RefcountIssueKind::MissingRef
+            } else if actual != Some(count) {
+                RefcountIssueKind::Mismatched
+            } else {
+                continue;
+            };
+            report.record(RefcountIssue { bucket: name.clone(), chunk: hex.clone(), kind, expected: count, actual });
+            if repair && kind != RefcountIssueKind::MissingChunk {
+                partition
+                    .insert(bucket.chunk_ref_key(&hash), count.to_le_bytes())
+                    .map_err(WflDBError::storage)?;
+                report.repaired += 1;
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebuild_repairs_drifted_counts() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket = engine.bucket(&BucketId::new("photos").unwrap()).unwrap();
+        let shared = vec![1u8; 1024];
+        bucket.put_large(&Key::new("a").unwrap(), vec![shared.clone(), vec![2u8; 1024]]).unwrap();
+        bucket.put_large(&Key::new("b").unwrap(), vec![shared.clone()]).unwrap();
+
+        // A lost increment and a crash before a manifest was written
+        let shared_ref = bucket.chunk_ref_key(&ContentHash::new(&shared));
+        bucket.main_partition.insert(&shared_ref, 1u32.to_le_bytes()).unwrap();
+        let orphan = ContentHash::new(b"orphan");
+        bucket.main_partition.insert(bucket.chunk_key(&orphan), b"orphan").unwrap();
+
+        let check = rebuild_refcounts(&engine, false, &mut |_| true).unwrap();
+        assert_eq!((check.mismatched, check.orphaned, check.repaired), (1, 1, 0));
+        assert_eq!(check.chunks, 2);
+
+        let rebuilt = rebuild_refcounts(&engine, true, &mut |_| true).unwrap();
+        assert_eq!(rebuilt.repaired, 2);
+        assert!(rebuild_refcounts(&engine, false, &mut |_| true).unwrap().is_clean());
+        let count = bucket.main_partition.get(&shared_ref).unwrap().unwrap();
+        assert_eq!(u32::from_le_bytes(count[0..4].try_into().unwrap()), 2);
+    }
+
+    #[test]
+    fn test_interrupted_pass_repairs_nothing() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let bucket = engine.bucket(&BucketId::new("photos").unwrap()).unwrap();
+        bucket.put_large(&Key::new("a").unwrap(), vec![vec![1u8; 1024]]).unwrap();
+        let orphan = ContentHash::new(b"orphan");
+        bucket.main_partition.insert(bucket.chunk_key(&orphan), b"orphan").unwrap();
+
+        let report = rebuild_refcounts(&engine, true, &mut |_| false).unwrap();
+        assert!(!report.complete);
+        assert_eq!(report.repaired, 0);
+    }
+}
@@ -0,0 +1,216 @@
+//! Counters for engine internals
+//!
+//! Every view of a keyspace shares one [`EngineStats`], so the metrics
+//! endpoint can tell storage regressions apart from HTTP latency: how long
+//! `persist()` takes, how chunk writes deduplicate, and how often chunk
+//! reference counts change. Flushes and compactions run inside fjall, which
+//! reports no events for them; [`KeyspaceStats`] samples the state they
+//! leave behind instead.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the `persist()` latency buckets
+pub const PERSIST_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Fixed-bucket latency histogram, safe to record into from any thread
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, plus one past the last bound
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+/// Cumulative view of a [`LatencyHistogram`], as Prometheus exposes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    /// Upper bound in seconds and observations at or below it
+    pub buckets: Vec<(f64, u64)>,
+    pub sum_seconds: f64,
+    pub count: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        LatencyHistogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = self.bounds.iter().position(|&bound| seconds <= bound).unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self.bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(&bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (bound, cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            sum_seconds: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+            count: cumulative + self.counts[self.bounds.len()].load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters shared by every view of one keyspace
+#[derive(Debug)]
+pub struct EngineStats {
+    persist: LatencyHistogram,
+    chunks_written: AtomicU64,
+    chunks_deduplicated: AtomicU64,
+    refcount_increments: AtomicU64,
+    refcount_decrements: AtomicU64,
+    chunks_freed: AtomicU64,
+    partition_cache_hits: AtomicU64,
+    partition_cache_misses: AtomicU64,
+}
+
+impl Default for EngineStats {
+    fn default() -> Self {
+        EngineStats {
+            persist: LatencyHistogram::new(PERSIST_BUCKETS),
+            chunks_written: AtomicU64::new(0),
+            chunks_deduplicated: AtomicU64::new(0),
+            refcount_increments: AtomicU64::new(0),
+            refcount_decrements: AtomicU64::new(0),
+            chunks_freed: AtomicU64::new(0),
+            partition_cache_hits: AtomicU64::new(0),
+            partition_cache_misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl EngineStats {
+    /// Latency of `persist()` calls
+    pub fn persist_latency(&self) -> HistogramSnapshot {
+        self.persist.snapshot()
+    }
+
+    /// Chunks stored because no copy was present
+    pub fn chunks_written(&self) -> u64 {
+        self.chunks_written.load(Ordering::Relaxed)
+    }
+
+    /// Chunk writes skipped because the bucket already held the chunk
+    pub fn chunks_deduplicated(&self) -> u64 {
+        self.chunks_deduplicated.load(Ordering::Relaxed)
+    }
+
+    /// References taken on chunks by committed objects
+    pub fn refcount_increments(&self) -> u64 {
+        self.refcount_increments.load(Ordering::Relaxed)
+    }
+
+    /// References dropped from chunks by deleted or replaced objects
+    pub fn refcount_decrements(&self) -> u64 {
+        self.refcount_decrements.load(Ordering::Relaxed)
+    }
+
+    /// Chunks removed when their last reference was dropped
+    pub fn chunks_freed(&self) -> u64 {
+        self.chunks_freed.load(Ordering::Relaxed)
+    }
+
+    /// Bucket opens served by an already open partition
+    pub fn partition_cache_hits(&self) -> u64 {
+        self.partition_cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Bucket opens that had to open the partition
+    pub fn partition_cache_misses(&self) -> u64 {
+        self.partition_cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_persist(&self, elapsed: Duration) {
+        self.persist.observe(elapsed);
+    }
+
+    pub(crate) fn record_chunk_write(&self, stored: bool) {
+        let counter = if stored { &self.chunks_written } else { &self.chunks_deduplicated };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_refcount_increments(&self, count: u64) {
+        self.refcount_increments.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_refcount_decrement(&self, freed: bool) {
+        self.refcount_decrements.fetch_add(1, Ordering::Relaxed);
+        if freed {
+            self.chunks_freed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_partition_lookup(&self, hit: bool) {
+        let counter = if hit { &self.partition_cache_hits } else { &self.partition_cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Point-in-time state of the keyspace's LSM trees
+///
+/// Memtable flushes add segments and compactions merge them, so a segment
+/// count that keeps climbing means compaction is falling behind writes; a
+/// write buffer or journal count that keeps climbing means flushes are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyspaceStats {
+    /// Bytes on disk across every partition
+    pub disk_bytes: u64,
+    /// Bytes held in memtables not yet flushed to segments
+    pub write_buffer_bytes: u64,
+    /// Journal files not yet released by a flush
+    pub journals: usize,
+    /// Partitions open in the keyspace, system partitions included
+    pub partitions: usize,
+    /// Disk segments across bucket partitions
+    pub segments: usize,
+    /// Value-log blob files across bucket partitions
+    pub blob_files: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_snapshot_is_cumulative() {
+        let histogram = LatencyHistogram::new(&[0.001, 0.01]);
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_secs(2));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(0.001, 1), (0.01, 3)]);
+        // The slow observation lands only in the implicit +Inf bucket
+        assert_eq!(snapshot.count, 4);
+        assert!((snapshot.sum_seconds - 2.0105).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_refcount_counters() {
+        let stats = EngineStats::default();
+        stats.record_refcount_increments(3);
+        stats.record_refcount_decrement(false);
+        stats.record_refcount_decrement(true);
+        stats.record_chunk_write(true);
+        stats.record_chunk_write(false);
+        assert_eq!(stats.refcount_increments(), 3);
+        assert_eq!(stats.refcount_decrements(), 2);
+        assert_eq!(stats.chunks_freed(), 1);
+        assert_eq!((stats.chunks_written(), stats.chunks_deduplicated()), (1, 1));
+    }
+}
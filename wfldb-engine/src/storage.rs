@@ -1,10 +1,28 @@
 //! High-level storage operations
 
 use wfldb_core::*;
-use crate::{StorageEngine, Bucket};
-use serde_json;
+use crate::{StorageEngine, Bucket, ChangeOp, ObjectExpiry};
+use crate::expiry;
+use crate::bucket::encode_tombstone;
+use std::collections::{BTreeMap, HashSet};
+
+/// Most operations one transaction may carry
+pub const MAX_TXN_OPS: usize = 100;
+
+/// Size of the chunks large objects are split into, and the largest chunk
+/// a client may upload
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Most chunks one uploaded manifest may name
+pub const MAX_MANIFEST_CHUNKS: usize = 10_000;
+
+/// Most objects one compose may concatenate
+pub const MAX_COMPOSE_SOURCES: usize = 32;
 
 /// High-level storage interface
+///
+/// Cheap to clone; clones share the engine and its open buckets.
+#[derive(Clone)]
 pub struct Storage {
     engine: StorageEngine,
 }
@@ -15,32 +33,440 @@ impl Storage {
         Storage { engine }
     }
     
+    /// Storage over `tenant`'s namespace of the same engine
+    pub fn for_tenant(&self, tenant: &TenantId) -> Self {
+        Storage::new(self.engine.for_tenant(tenant))
+    }
+    
     /// Put object with automatic size-based routing
     pub fn put_object(&self, bucket_id: &BucketId, key: &Key, data: &[u8]) -> Result<ObjectMetadata> {
+        self.put_object_as(bucket_id, key, data, None)
+    }
+    
+    /// Put object on behalf of `owner`; overwrites keep the original creator as owner
+    pub fn put_object_as(&self, bucket_id: &BucketId, key: &Key, data: &[u8], owner: Option<&str>) -> Result<ObjectMetadata> {
+        self.put_object_with_checksums_as(bucket_id, key, data, owner, BTreeMap::new())
+    }
+    
+    /// [`put_object_as`](Self::put_object_as), recording `checksums` the
+    /// caller computed over `data` in the object's metadata
+    ///
+    /// Checksums aren't journaled: replicas and migrated copies of the
+    /// object carry only its BLAKE3 hash.
+    pub fn put_object_with_checksums_as(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        data: &[u8],
+        owner: Option<&str>,
+        checksums: BTreeMap<ChecksumAlgorithm, String>,
+    ) -> Result<ObjectMetadata> {
+        self.put_object_if(bucket_id, key, data, owner, checksums, None, None, None)
+    }
+    
+    /// [`put_object_with_checksums_as`](Self::put_object_with_checksums_as)
+    /// only if the key satisfies `precondition`, failing with
+    /// [`PreconditionFailed`](WflDBError::PreconditionFailed) otherwise
+    ///
+    /// With [`Precondition::Generation`] this is a fenced write: a writer
+    /// holding an older generation than the key's can't overwrite it.
+    /// `customer_key_hash` marks `data` as encrypted with a customer key;
+    /// unlike checksums it is journaled, so replicas know it too. With
+    /// `expires_at_ms` the new version expires then, its expiry record
+    /// committed together with the object.
+    #[allow(clippy::too_many_arguments)]
+    pub fn put_object_if(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        data: &[u8],
+        owner: Option<&str>,
+        checksums: BTreeMap<ChecksumAlgorithm, String>,
+        precondition: Option<Precondition>,
+        customer_key_hash: Option<ContentHash>,
+        expires_at_ms: Option<u64>,
+    ) -> Result<ObjectMetadata> {
         let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        
+        let existing = match (owner, &precondition) {
+            (None, None) => None,
+            _ => bucket.get_metadata(key)?,
+        };
+        if precondition.is_some_and(|precondition| !precondition.holds(existing.as_ref())) {
+            return Err(WflDBError::PreconditionFailed { key: key.as_str().to_string() });
+        }
+        let owner = match owner {
+            Some(owner) => existing
+                .and_then(|existing| existing.owner)
+                .or_else(|| Some(owner.to_string())),
+            None => None,
+        };
         
-        if data.len() <= self.engine.value_threshold() {
-            bucket.put_small(key, data)
+        let metadata = if data.len() <= self.engine.value_threshold() {
+            bucket.put_small_at(key, data, owner.clone(), None, checksums, customer_key_hash.clone(), expires_at_ms)?
         } else {
             // Split large data into chunks
             let chunks = self.chunk_data(data);
-            bucket.put_large(key, chunks)
+            bucket.put_large_at(key, chunks, owner.clone(), None, checksums, customer_key_hash.clone(), expires_at_ms)?
+        };
+        
+        if let Some(journal) = self.engine.journal() {
+            let op = ChangeOp::Put { data: data.to_vec(), owner, customer_key_hash };
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
         }
+        Ok(metadata)
     }
     
-    /// Get object data (small or large)
-    pub fn get_object(&self, bucket_id: &BucketId, key: &Key) -> Result<Option<Vec<u8>>> {
+    /// Apply a put made on another node, keeping its version and owner
+    ///
+    /// Skipped, returning `None`, when the key already holds that version or
+    /// a later write or delete, so a put reaching this node after the
+    /// delete that followed it doesn't bring the object back.
+    pub fn put_object_version(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        data: &[u8],
+        owner: Option<&str>,
+        version: Version,
+    ) -> Result<Option<ObjectMetadata>> {
+        self.put_object_version_with_checksums(bucket_id, key, data, owner, version, BTreeMap::new(), None)
+    }
+    
+    /// [`put_object_version`](Self::put_object_version), recording
+    /// `checksums` of `data` and the customer key hash like
+    /// [`put_object_if`](Self::put_object_if)
+    #[allow(clippy::too_many_arguments)]
+    pub fn put_object_version_with_checksums(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        data: &[u8],
+        owner: Option<&str>,
+        version: Version,
+        checksums: BTreeMap<ChecksumAlgorithm, String>,
+        customer_key_hash: Option<ContentHash>,
+    ) -> Result<Option<ObjectMetadata>> {
         let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        if bucket.is_superseded(key, &version)? {
+            return Ok(None);
+        }
         
-        // First check metadata to determine if it's chunked
-        match bucket.get_metadata(key)? {
-            Some(metadata) => {
-                if metadata.is_chunked() {
-                    self.get_large_object(&bucket, &metadata)
-                } else {
-                    bucket.get_small(key)
+        let owner = owner.map(str::to_string);
+        let metadata = if data.len() <= self.engine.value_threshold() {
+            bucket.put_small_at(key, data, owner.clone(), Some(version), checksums, customer_key_hash.clone(), None)?
+        } else {
+            bucket.put_large_at(key, self.chunk_data(data), owner.clone(), Some(version), checksums, customer_key_hash.clone(), None)?
+        };
+        
+        if let Some(journal) = self.engine.journal() {
+            let op = ChangeOp::Put { data: data.to_vec(), owner, customer_key_hash };
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
+        }
+        Ok(Some(metadata))
+    }
+    
+    /// Commit an object from chunks uploaded with [`Bucket::put_chunk`], with
+    /// the same ownership rules as [`put_object_as`](Self::put_object_as)
+    pub fn put_manifest_as(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        chunks: Vec<ContentHash>,
+        owner: Option<&str>,
+    ) -> Result<ObjectMetadata> {
+        if chunks.is_empty() || chunks.len() > MAX_MANIFEST_CHUNKS {
+            return Err(WflDBError::InvalidKey(format!(
+                "a manifest needs between 1 and {} chunks",
+                MAX_MANIFEST_CHUNKS
+            )));
+        }
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        let existing = bucket.get_metadata(key)?;
+        let metadata = self.commit_manifest_locked(&bucket, key, chunks, owner, existing, None)?;
+        
+        // Journaled as the chunk hashes; the object may be far too large to
+        // hold, and replicas already have the chunks
+        if let Some(journal) = self.engine.journal() {
+            let op = manifest_op(&metadata);
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
+        }
+        Ok(metadata)
+    }
+
+    /// Apply a manifest commit made on another node, keeping its version
+    ///
+    /// Skipped, returning `None`, when the key already holds that version or
+    /// a later write or delete, like
+    /// [`put_object_version`](Self::put_object_version).
+    pub fn put_manifest_version(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        chunks: Vec<ContentHash>,
+        owner: Option<&str>,
+        version: Version,
+    ) -> Result<Option<ObjectMetadata>> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        if bucket.is_superseded(key, &version)? {
+            return Ok(None);
+        }
+        let existing = bucket.get_metadata(key)?;
+        let metadata = self.commit_manifest_locked(&bucket, key, chunks, owner, existing, Some(version))?;
+        
+        if let Some(journal) = self.engine.journal() {
+            let op = manifest_op(&metadata);
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
+        }
+        Ok(Some(metadata))
+    }
+
+    /// Store an uploaded chunk in the bucket, returning its hash and whether
+    /// it's new there
+    ///
+    /// A new chunk is journaled under its hash, so replicas hold it before a
+    /// manifest naming it arrives.
+    pub fn put_chunk(&self, bucket_id: &BucketId, data: &[u8]) -> Result<(ContentHash, bool)> {
+        let (hash, stored) = self.engine.bucket(bucket_id)?.put_chunk(data)?;
+        if let (true, Some(journal)) = (stored, self.engine.journal()) {
+            let op = ChangeOp::Chunk { data: data.to_vec() };
+            journal.append(&self.engine.namespaced(bucket_id), &Key::new(&hash.to_hex())?, op)?;
+        }
+        Ok((hash, stored))
+    }
+
+    /// Replace `data.len()` bytes of a chunked object starting at `offset`,
+    /// extending it if the range runs past the end, on behalf of `owner`
+    ///
+    /// Only the chunks the range touches are read and rewritten; the rest
+    /// of the manifest is kept as is, and the journal records just the
+    /// range. Small objects are cheaper to rewrite whole and are refused, as
+    /// are ranges starting past the end and objects encrypted with a
+    /// customer key, whose chunks hold ciphertext.
+    pub fn write_range_as(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        offset: u64,
+        data: &[u8],
+        owner: Option<&str>,
+    ) -> Result<ObjectMetadata> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        let metadata = self.write_range_locked(&bucket, key, offset, data, owner, None)?;
+        
+        if let Some(journal) = self.engine.journal() {
+            let op = ChangeOp::WriteRange { offset, data: data.to_vec(), owner: metadata.owner.clone() };
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
+        }
+        Ok(metadata)
+    }
+    
+    /// Apply a range write made on another node, keeping its version
+    ///
+    /// Skipped, returning `None`, when the key already holds that version or
+    /// a later write or delete, like
+    /// [`put_object_version`](Self::put_object_version).
+    pub fn write_range_version(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        offset: u64,
+        data: &[u8],
+        owner: Option<&str>,
+        version: Version,
+    ) -> Result<Option<ObjectMetadata>> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        if bucket.is_superseded(key, &version)? {
+            return Ok(None);
+        }
+        let metadata = self.write_range_locked(&bucket, key, offset, data, owner, Some(version))?;
+        
+        if let Some(journal) = self.engine.journal() {
+            let op = ChangeOp::WriteRange { offset, data: data.to_vec(), owner: metadata.owner.clone() };
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, op, Some(metadata.version.clone()))?;
+        }
+        Ok(Some(metadata))
+    }
+    
+    /// Splice a range into a chunked object with the key already locked
+    fn write_range_locked(
+        &self,
+        bucket: &Bucket,
+        key: &Key,
+        offset: u64,
+        data: &[u8],
+        owner: Option<&str>,
+        version: Option<Version>,
+    ) -> Result<ObjectMetadata> {
+        let existing = bucket
+            .get_metadata(key)?
+            .ok_or_else(|| WflDBError::ObjectNotFound { key: key.as_str().to_string() })?;
+        if existing.customer_key_hash.is_some() {
+            return Err(WflDBError::InvalidRequest(format!(
+                "{} is encrypted with a customer key and can only be rewritten whole",
+                key.as_str()
+            )));
+        }
+        let manifest = existing
+            .chunk_manifest
+            .as_ref()
+            .ok_or_else(|| WflDBError::InvalidKey(format!("{} is not a chunked object", key.as_str())))?;
+        if data.is_empty() || offset > manifest.total_size {
+            return Err(WflDBError::InvalidKey(format!(
+                "range must be non-empty and start within the object's {} bytes",
+                manifest.total_size
+            )));
+        }
+
+        let sizes: Vec<u64> = if manifest.chunk_sizes.len() == manifest.chunks.len() {
+            manifest.chunk_sizes.iter().map(|&size| size as u64).collect()
+        } else {
+            // Manifests from before chunk lengths were recorded
+            let mut sizes = Vec::with_capacity(manifest.chunks.len());
+            for hash in &manifest.chunks {
+                sizes.push(self.read_chunk(bucket, hash)?.len() as u64);
+            }
+            sizes
+        };
+        let end = offset + data.len() as u64;
+        let starts: Vec<u64> = sizes
+            .iter()
+            .scan(0, |start, size| {
+                let chunk_start = *start;
+                *start += size;
+                Some(chunk_start)
+            })
+            .collect();
+        // The chunk holding the first byte (the last chunk for an append)
+        // through the one holding the last byte, or the last chunk
+        let first = starts.partition_point(|&start| start <= offset).saturating_sub(1);
+        let last = starts.partition_point(|&start| start < end).saturating_sub(1).max(first);
+
+        let mut spliced = Vec::new();
+        let head = self.read_chunk(bucket, &manifest.chunks[first])?;
+        spliced.extend_from_slice(&head[..(offset - starts[first]) as usize]);
+        spliced.extend_from_slice(data);
+        let tail_start = end - starts[last];
+        if tail_start < sizes[last] {
+            let tail = match last == first {
+                true => head,
+                false => self.read_chunk(bucket, &manifest.chunks[last])?,
+            };
+            spliced.extend_from_slice(&tail[tail_start as usize..]);
+        }
+
+        let mut chunks = manifest.chunks[..first].to_vec();
+        for chunk in spliced.chunks(MAX_CHUNK_SIZE) {
+            chunks.push(bucket.put_chunk(chunk)?.0);
+        }
+        chunks.extend_from_slice(&manifest.chunks[last + 1..]);
+        if chunks.len() > MAX_MANIFEST_CHUNKS {
+            return Err(WflDBError::InvalidKey(format!("a manifest holds at most {} chunks", MAX_MANIFEST_CHUNKS)));
+        }
+        self.commit_manifest_locked(bucket, key, chunks, owner, Some(existing), version)
+    }
+
+    /// Commit a manifest over `existing` with the key already locked,
+    /// leaving the journal to the caller
+    fn commit_manifest_locked(
+        &self,
+        bucket: &Bucket,
+        key: &Key,
+        chunks: Vec<ContentHash>,
+        owner: Option<&str>,
+        existing: Option<ObjectMetadata>,
+        version: Option<Version>,
+    ) -> Result<ObjectMetadata> {
+        let owner = match owner {
+            Some(owner) => existing
+                .as_ref()
+                .and_then(|existing| existing.owner.clone())
+                .or_else(|| Some(owner.to_string())),
+            None => None,
+        };
+        let metadata = bucket.put_manifest_at(key, chunks, owner, version)?;
+        // References on the new chunks are taken first, so chunks shared with
+        // the replaced version survive its release
+        if let Some(manifest) = existing.and_then(|existing| existing.chunk_manifest) {
+            bucket.release_chunks(&manifest)?;
+        }
+        Ok(metadata)
+    }
+
+    /// Create `key` as the concatenation of `sources` from the same bucket,
+    /// in order, without copying their data: the new manifest references the
+    /// sources' chunks, and small sources are stored as a chunk each
+    ///
+    /// Sources are read without being locked; one deleted meanwhile fails
+    /// the compose with [`MissingChunks`](WflDBError::MissingChunks).
+    /// Sources encrypted with a customer key are refused, since their
+    /// chunks hold ciphertext.
+    pub fn compose_as(
+        &self,
+        bucket_id: &BucketId,
+        key: &Key,
+        sources: &[Key],
+        owner: Option<&str>,
+    ) -> Result<ObjectMetadata> {
+        if sources.is_empty() || sources.len() > MAX_COMPOSE_SOURCES {
+            return Err(WflDBError::InvalidKey(format!(
+                "a compose needs between 1 and {} sources",
+                MAX_COMPOSE_SOURCES
+            )));
+        }
+        let bucket = self.engine.bucket(bucket_id)?;
+        let mut chunks = Vec::new();
+        for source in sources {
+            let not_found = || WflDBError::ObjectNotFound { key: source.as_str().to_string() };
+            let metadata = bucket.get_metadata(source)?.ok_or_else(not_found)?;
+            if metadata.customer_key_hash.is_some() {
+                return Err(WflDBError::InvalidRequest(format!(
+                    "{} is encrypted with a customer key and can't be composed",
+                    source.as_str()
+                )));
+            }
+            match metadata.chunk_manifest {
+                Some(manifest) => {
+                    for hash in manifest.chunks {
+                        // Chunks a clone links to elsewhere are copied in,
+                        // since a manifest only references local chunks
+                        if !bucket.has_local_chunk(&hash)? {
+                            let data = bucket
+                                .get_chunk(&hash)?
+                                .ok_or_else(|| WflDBError::MissingChunks(vec![hash.to_hex()]))?;
+                            self.put_chunk(bucket_id, &data)?;
+                        }
+                        chunks.push(hash);
+                    }
+                }
+                None => {
+                    let data = bucket.get_small(source)?.ok_or_else(not_found)?;
+                    if !data.is_empty() {
+                        chunks.push(self.put_chunk(bucket_id, &data)?.0);
+                    }
                 }
             }
+        }
+        if chunks.is_empty() {
+            return self.put_object_as(bucket_id, key, &[], owner);
+        }
+        self.put_manifest_as(bucket_id, key, chunks, owner)
+    }
+
+    /// Get object data (small or large)
+    pub fn get_object(&self, bucket_id: &BucketId, key: &Key) -> Result<Option<Vec<u8>>> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        
+        // Small objects come back with their metadata; chunked ones are assembled
+        match bucket.get_record(key)? {
+            Some((metadata, _)) if metadata.is_chunked() => self.get_large_object(&bucket, &metadata),
+            Some((_, data)) => Ok(data),
             None => Ok(None),
         }
     }
@@ -52,9 +478,100 @@ impl Storage {
     }
     
     /// Delete object
+    ///
+    /// With a journal the delete leaves a tombstone, which replicas compare
+    /// older writes against; see [`Bucket::delete_version`].
     pub fn delete_object(&self, bucket_id: &BucketId, key: &Key) -> Result<()> {
+        self.delete_object_checked(bucket_id, key, None)
+    }
+    
+    /// [`delete_object`](Self::delete_object) only if the key satisfies
+    /// `precondition`, failing with
+    /// [`PreconditionFailed`](WflDBError::PreconditionFailed) otherwise
+    ///
+    /// With [`Precondition::Generation`] this is a fenced delete, like a
+    /// fenced [`put_object_if`](Self::put_object_if).
+    pub fn delete_object_if(&self, bucket_id: &BucketId, key: &Key, precondition: Precondition) -> Result<()> {
+        self.delete_object_checked(bucket_id, key, Some(precondition))
+    }
+    
+    fn delete_object_checked(&self, bucket_id: &BucketId, key: &Key, precondition: Option<Precondition>) -> Result<()> {
         let bucket = self.engine.bucket(bucket_id)?;
-        bucket.delete(key)
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        
+        if let Some(precondition) = precondition {
+            if !precondition.holds(bucket.get_metadata(key)?.as_ref()) {
+                return Err(WflDBError::PreconditionFailed { key: key.as_str().to_string() });
+            }
+        }
+        match self.engine.journal() {
+            Some(journal) => {
+                let version = Version::new();
+                bucket.delete_version(key, &version)?;
+                journal.append_versioned(&self.engine.namespaced(bucket_id), key, ChangeOp::Delete, Some(version))?;
+            }
+            None => bucket.delete(key)?,
+        }
+        Ok(())
+    }
+    
+    /// Apply a delete made on another node at `version`, leaving a tombstone
+    ///
+    /// Returns false, changing nothing, when the key already holds that
+    /// version or a later write or delete, so applying a delete twice or
+    /// after a newer put is harmless.
+    pub fn delete_object_version(&self, bucket_id: &BucketId, key: &Key, version: Version) -> Result<bool> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        if !bucket.delete_version(key, &version)? {
+            return Ok(false);
+        }
+        
+        if let Some(journal) = self.engine.journal() {
+            journal.append_versioned(&self.engine.namespaced(bucket_id), key, ChangeOp::Delete, Some(version))?;
+        }
+        Ok(true)
+    }
+    
+    /// Remove an object without leaving a tombstone, for a repair dropping a
+    /// key the leader never had; a tombstone versioned by this node's clock
+    /// could hide the leader's next write to the key
+    pub fn discard_object(&self, bucket_id: &BucketId, key: &Key) -> Result<()> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        bucket.delete(key)?;
+        
+        if let Some(journal) = self.engine.journal() {
+            journal.append(&self.engine.namespaced(bucket_id), key, ChangeOp::Delete)?;
+        }
+        Ok(())
+    }
+    
+    /// Clone every object of `source` into the empty bucket `target`; large
+    /// objects share chunks with the source by reference, so nothing but
+    /// metadata and small inline values is copied
+    pub fn clone_bucket(&self, source_id: &BucketId, target_id: &BucketId) -> Result<u64> {
+        if source_id == target_id {
+            return Err(WflDBError::InvalidRequest("cannot clone a bucket onto itself".to_string()));
+        }
+        let source = self.engine.bucket(source_id)?;
+        let target = self.engine.bucket(target_id)?;
+        if !target.scan_prefix("", Some(1))?.is_empty() {
+            return Err(WflDBError::InvalidRequest(format!("target bucket '{}' is not empty", target_id.as_str())));
+        }
+        
+        let mut cloned = 0;
+        for key in source.scan_prefix("", None)? {
+            if target.copy_object_from(&source, &key)?.is_none() {
+                continue;
+            }
+            if let Some(journal) = self.engine.journal() {
+                let op = ChangeOp::Copy { source: source_id.as_str().to_string() };
+                journal.append(&self.engine.namespaced(target_id), &key, op)?;
+            }
+            cloned += 1;
+        }
+        Ok(cloned)
     }
     
     /// List objects with prefix
@@ -62,11 +579,47 @@ impl Storage {
         let bucket = self.engine.bucket(bucket_id)?;
         bucket.scan_prefix(prefix, limit)
     }
+
+    /// List a page of objects with prefix, starting after the last key of the previous page
+    pub fn list_objects_after(
+        &self,
+        bucket_id: &BucketId,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Key>> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        bucket.scan_prefix_after(prefix, start_after, limit)
+    }
+    
+    /// List a page like [`list_objects_after`](Self::list_objects_after),
+    /// together with the journal position the listing covers, if mutations
+    /// are journaled
+    ///
+    /// Every change up to that position is reflected in the listing, so a
+    /// change feed bootstrapped from it follows the journal from there.
+    /// Changes after it may be reflected too, since writes commit before
+    /// they're journaled; applying those again is harmless.
+    pub fn list_objects_at(
+        &self,
+        bucket_id: &BucketId,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Key>, Option<u64>)> {
+        let bucket = self.engine.bucket(bucket_id)?;
+        // Read the position before taking the snapshot: anything journaled
+        // by then was already committed
+        let position = self.engine.journal().map(|journal| journal.next_seq() - 1);
+        let snapshot = bucket.main_partition.snapshot();
+        Ok((bucket.scan_snapshot(&snapshot, prefix, start_after, limit)?, position))
+    }
     
     /// Execute batch operations atomically
     pub fn batch(&self, bucket_id: &BucketId, operations: Vec<BatchOperation>) -> Result<BatchResponse> {
         let bucket = self.engine.bucket(bucket_id)?;
         let mut results = Vec::new();
+        let mut journaled = Vec::new();
         
         // Start atomic batch
         let mut batch = self.engine.keyspace().batch();
@@ -78,17 +631,14 @@ impl Storage {
                     if data.len() <= self.engine.value_threshold() {
                         // Small object - store inline
                         let content_hash = ContentHash::new(&data);
-                        let metadata = ObjectMetadata::new_inline(data.len() as u64, content_hash);
-                        
-                        let metadata_key = format!("meta:{}", key.as_str()).into_bytes();
-                        let data_key = format!("data:{}", key.as_str()).into_bytes();
+                        let metadata = ObjectMetadata::new_inline(data.len() as u64, content_hash)
+                            .with_generation(bucket.generation(&key)? + 1);
                         
-                        let metadata_json = serde_json::to_vec(&metadata)
-                            .map_err(WflDBError::Serialization)?;
-                        
-                        batch.insert(&bucket.main_partition, &metadata_key, metadata_json);
-                        batch.insert(&bucket.main_partition, &data_key, data);
+                        let metadata_key = bucket.metadata_key(&key);
+                        bucket.stage_generation(&mut batch, &key, metadata.generation);
+                        batch.insert(&bucket.main_partition, &metadata_key, metadata.encode_with_data(&data));
                         
+                        journaled.push((key, ChangeOp::Put { data, owner: None, customer_key_hash: None }, metadata.version));
                         results.push(BatchResult::Success);
                     } else {
                         // Large object - for now, fail in batch
@@ -98,12 +648,14 @@ impl Storage {
                     }
                 }
                 BatchOperation::Delete { key } => {
-                    let metadata_key = format!("meta:{}", key.as_str()).into_bytes();
-                    let data_key = format!("data:{}", key.as_str()).into_bytes();
-                    
+                    let metadata_key = bucket.metadata_key(&key);
                     batch.remove(&bucket.main_partition, &metadata_key);
-                    batch.remove(&bucket.main_partition, &data_key);
                     
+                    let version = Version::new();
+                    if self.engine.journal().is_some() {
+                        batch.insert(&bucket.main_partition, bucket.tombstone_key(&key), encode_tombstone(&version));
+                    }
+                    journaled.push((key, ChangeOp::Delete, version));
                     results.push(BatchResult::Success);
                 }
             }
@@ -111,13 +663,215 @@ impl Storage {
         
         // Commit the batch atomically
         batch.commit()
-            .map_err(|e| WflDBError::Storage(format!("Batch commit failed: {}", e)))?;
+            .map_err(WflDBError::storage)?;
         
         self.engine.persist()?;
         
+        if let Some(journal) = self.engine.journal() {
+            for (key, op, version) in journaled {
+                journal.append_versioned(&self.engine.namespaced(bucket_id), &key, op, Some(version))?;
+            }
+        }
+        
         Ok(BatchResponse { results })
     }
     
+    /// Apply puts and deletes to several keys all-or-nothing
+    ///
+    /// Every precondition is checked, under the keys' write locks, before
+    /// anything is written; the writes then commit as one batch. Returns, in
+    /// order, the new metadata for each put, `None` for each delete, and the
+    /// current metadata for each check. Values must be small enough to store
+    /// inline.
+    pub fn transact(&self, bucket_id: &BucketId, ops: Vec<TxnOp>) -> Result<Vec<Option<ObjectMetadata>>> {
+        self.transact_as(bucket_id, ops, None)
+    }
+    
+    /// [`transact`](Self::transact) on behalf of `owner`, with the same
+    /// ownership rules as [`put_object_as`](Self::put_object_as)
+    pub fn transact_as(
+        &self,
+        bucket_id: &BucketId,
+        ops: Vec<TxnOp>,
+        owner: Option<&str>,
+    ) -> Result<Vec<Option<ObjectMetadata>>> {
+        if ops.is_empty() || ops.len() > MAX_TXN_OPS {
+            return Err(WflDBError::InvalidTransaction(format!(
+                "a transaction needs between 1 and {} operations",
+                MAX_TXN_OPS
+            )));
+        }
+        let mut keys = HashSet::new();
+        for op in &ops {
+            if !keys.insert(op.key().as_str()) {
+                return Err(WflDBError::InvalidTransaction(format!("key '{}' appears more than once", op.key())));
+            }
+            if let TxnOp::Put { key, data, .. } = op {
+                if data.len() > self.engine.value_threshold() {
+                    return Err(WflDBError::InvalidTransaction(format!(
+                        "value for '{}' exceeds the {} byte inline limit",
+                        key,
+                        self.engine.value_threshold()
+                    )));
+                }
+            }
+        }
+        
+        let bucket = self.engine.bucket(bucket_id)?;
+        let namespaced = self.engine.namespaced(bucket_id);
+        let _locks = self.engine.key_locks().lock(&namespaced, ops.iter().map(TxnOp::key));
+        // Touches replace expiry records in the system partition
+        let system = match ops.iter().any(|op| matches!(op, TxnOp::Touch { .. })) {
+            true => Some(self.engine.system()?),
+            false => None,
+        };
+        let now_ms = expiry::now_ms();
+        let mut current = Vec::with_capacity(ops.len());
+        let mut expiries = Vec::with_capacity(ops.len());
+        for op in &ops {
+            let existing = bucket.get_metadata(op.key())?;
+            if let Some(precondition) = op.precondition() {
+                if !precondition.holds(existing.as_ref()) {
+                    return Err(WflDBError::PreconditionFailed { key: op.key().as_str().to_string() });
+                }
+            }
+            let previous = match (op, &system) {
+                (TxnOp::Touch { key, .. }, Some(system)) => {
+                    let previous = expiry::load_expiry(system, &namespaced, key)?;
+                    // Expired content is gone even before it's purged
+                    let live = existing.as_ref().is_some_and(|metadata| {
+                        !previous.as_ref().is_some_and(|e| e.version == metadata.version && e.is_expired(now_ms))
+                    });
+                    if !live {
+                        return Err(WflDBError::ObjectNotFound { key: key.as_str().to_string() });
+                    }
+                    previous
+                }
+                _ => None,
+            };
+            current.push(existing);
+            expiries.push(previous);
+        }
+        
+        let mut batch = self.engine.keyspace().batch();
+        let mut results = Vec::with_capacity(ops.len());
+        let mut deleted = Vec::new();
+        let mut replaced = Vec::new();
+        for ((op, existing), previous) in ops.iter().zip(current).zip(expiries) {
+            let metadata_key = bucket.metadata_key(op.key());
+            match op {
+                TxnOp::Put { data, .. } => {
+                    let owner = match owner {
+                        Some(owner) => existing
+                            .as_ref()
+                            .and_then(|metadata| metadata.owner.clone())
+                            .or_else(|| Some(owner.to_string())),
+                        None => None,
+                    };
+                    let metadata = ObjectMetadata::new_inline(data.len() as u64, ContentHash::new(data))
+                        .with_owner(owner)
+                        .with_generation(bucket.generation(op.key())? + 1);
+                    bucket.stage_generation(&mut batch, op.key(), metadata.generation);
+                    batch.insert(&bucket.main_partition, metadata_key, metadata.encode_with_data(data));
+                    results.push(Some(metadata));
+                }
+                TxnOp::Delete { key, .. } => {
+                    batch.remove(&bucket.main_partition, metadata_key);
+                    let version = Version::new();
+                    if self.engine.journal().is_some() {
+                        batch.insert(&bucket.main_partition, bucket.tombstone_key(key), encode_tombstone(&version));
+                    }
+                    deleted.push(version);
+                    results.push(None);
+                }
+                TxnOp::Check { .. } => {
+                    results.push(existing);
+                    continue;
+                }
+                TxnOp::Touch { key, expires_at_ms, .. } => {
+                    if let (Some(system), Some(metadata)) = (&system, &existing) {
+                        let expiry = ObjectExpiry { version: metadata.version.clone(), expires_at_ms: *expires_at_ms };
+                        expiry::stage_expiry(&mut batch, system, &namespaced, key, previous.as_ref(), &expiry)?;
+                    }
+                    results.push(existing);
+                    continue;
+                }
+            }
+            if let Some(manifest) = existing.and_then(|metadata| metadata.chunk_manifest) {
+                replaced.push(manifest);
+            }
+        }
+        batch.commit()
+            .map_err(WflDBError::storage)?;
+        
+        // Chunks of replaced large objects are released after the commit; a
+        // crash in between leaks them rather than losing live data
+        for manifest in &replaced {
+            bucket.release_chunks(manifest)?;
+        }
+        self.engine.persist()?;
+        
+        if let Some(journal) = self.engine.journal() {
+            let mut deleted = deleted.into_iter();
+            for (op, metadata) in ops.into_iter().zip(&results) {
+                let (key, change, version) = match op {
+                    TxnOp::Put { key, data, .. } => {
+                        let owner = metadata.as_ref().and_then(|m| m.owner.clone());
+                        (key, ChangeOp::Put { data, owner, customer_key_hash: None }, metadata.as_ref().map(|m| m.version.clone()))
+                    }
+                    TxnOp::Delete { key, .. } => (key, ChangeOp::Delete, deleted.next()),
+                    TxnOp::Check { .. } | TxnOp::Touch { .. } => continue,
+                };
+                journal.append_versioned(&self.engine.namespaced(bucket_id), &key, change, version)?;
+            }
+        }
+        Ok(results)
+    }
+    
+    /// Read an object together with the metadata of the version read
+    ///
+    /// The key's write lock is held across both lookups, so the version
+    /// always describes the returned data; pass it to
+    /// [`TxnToken::observe`] for an optimistic transaction.
+    pub fn get_versioned(&self, bucket_id: &BucketId, key: &Key) -> Result<Option<(Vec<u8>, ObjectMetadata)>> {
+        let _lock = self.engine.key_locks().lock(&self.engine.namespaced(bucket_id), [key]);
+        let bucket = self.engine.bucket(bucket_id)?;
+        match bucket.get_record(key)? {
+            Some((metadata, _)) if metadata.is_chunked() => {
+                Ok(self.get_large_object(&bucket, &metadata)?.map(|data| (data, metadata)))
+            }
+            Some((metadata, data)) => Ok(data.map(|data| (data, metadata))),
+            None => Ok(None),
+        }
+    }
+    
+    /// Commit an optimistic transaction: apply `writes` only if every key in
+    /// `token` is still at the version it was read at
+    ///
+    /// A changed read fails with [`WflDBError::TxnConflict`]; the client
+    /// re-reads and retries. Explicit preconditions on writes to keys that
+    /// weren't read still fail with [`WflDBError::PreconditionFailed`].
+    pub fn commit(&self, bucket_id: &BucketId, token: TxnToken, writes: Vec<TxnOp>) -> Result<Vec<Option<ObjectMetadata>>> {
+        self.commit_as(bucket_id, token, writes, None)
+    }
+    
+    /// [`commit`](Self::commit) on behalf of `owner`
+    pub fn commit_as(
+        &self,
+        bucket_id: &BucketId,
+        token: TxnToken,
+        writes: Vec<TxnOp>,
+        owner: Option<&str>,
+    ) -> Result<Vec<Option<ObjectMetadata>>> {
+        let read_keys: HashSet<String> = token.reads.iter().map(|read| read.key.as_str().to_string()).collect();
+        match self.transact_as(bucket_id, token.into_ops(writes)?, owner) {
+            Err(WflDBError::PreconditionFailed { key }) if read_keys.contains(&key) => {
+                Err(WflDBError::TxnConflict { key })
+            }
+            result => result,
+        }
+    }
+    
     /// Get storage engine reference
     pub fn engine(&self) -> &StorageEngine {
         &self.engine
@@ -126,25 +880,25 @@ impl Storage {
     // Private helper methods
     
     fn chunk_data(&self, data: &[u8]) -> Vec<Vec<u8>> {
-        const CHUNK_SIZE: usize = 4 * 1024 * 1024; // 4MB chunks
-        
-        data.chunks(CHUNK_SIZE)
+        data.chunks(MAX_CHUNK_SIZE)
             .map(|chunk| chunk.to_vec())
             .collect()
     }
     
+    fn read_chunk(&self, bucket: &Bucket, hash: &ContentHash) -> Result<Vec<u8>> {
+        bucket.get_chunk(hash)?.ok_or_else(|| WflDBError::MissingChunks(vec![hash.to_hex()]))
+    }
+    
     fn get_large_object(&self, bucket: &Bucket, metadata: &ObjectMetadata) -> Result<Option<Vec<u8>>> {
         let manifest = metadata.chunk_manifest.as_ref()
-            .ok_or_else(|| WflDBError::Internal("Missing chunk manifest".to_string()))?;
+            .ok_or_else(|| WflDBError::internal("Missing chunk manifest"))?;
         
         let mut data = Vec::with_capacity(metadata.size as usize);
         
         for chunk_hash in &manifest.chunks {
             match bucket.get_chunk(chunk_hash)? {
                 Some(chunk_data) => data.extend(chunk_data),
-                None => return Err(WflDBError::Internal(
-                    format!("Missing chunk: {}", chunk_hash.to_hex())
-                )),
+                None => return Err(WflDBError::Corrupt(format!("Missing chunk: {}", chunk_hash.to_hex()))),
             }
         }
         
@@ -152,6 +906,12 @@ impl Storage {
     }
 }
 
+/// Journal record of a committed manifest: its chunk hashes, not its bytes
+fn manifest_op(metadata: &ObjectMetadata) -> ChangeOp {
+    let chunks = metadata.chunk_manifest.as_ref().map(|manifest| manifest.chunks.clone()).unwrap_or_default();
+    ChangeOp::Manifest { chunks, owner: metadata.owner.clone() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,6 +943,104 @@ mod tests {
         assert_eq!(keys[0].as_str(), "test-key");
     }
     
+    #[tokio::test]
+    async fn test_transact_is_all_or_nothing() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let a = Key::new("a").unwrap();
+        let b = Key::new("b").unwrap();
+        let original = storage.put_object(&bucket_id, &a, b"one").unwrap();
+        
+        // A stale version on `a` rejects the whole batch, including `b`
+        let stale = storage.transact(&bucket_id, vec![
+            TxnOp::Put { key: b.clone(), data: b"new".to_vec(), precondition: Some(Precondition::Absent) },
+            TxnOp::Delete { key: a.clone(), precondition: Some(Precondition::Version(Version::new())) },
+        ]);
+        assert!(matches!(stale, Err(WflDBError::PreconditionFailed { ref key }) if key == "a"));
+        assert!(storage.get_object(&bucket_id, &b).unwrap().is_none());
+        
+        let results = storage.transact(&bucket_id, vec![
+            TxnOp::Put { key: b.clone(), data: b"new".to_vec(), precondition: Some(Precondition::Absent) },
+            TxnOp::Delete { key: a.clone(), precondition: Some(Precondition::Version(original.version)) },
+        ]).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_none());
+        assert_eq!(storage.get_object(&bucket_id, &b).unwrap().unwrap(), b"new");
+        assert!(storage.get_object(&bucket_id, &a).unwrap().is_none());
+        
+        let duplicate = storage.transact(&bucket_id, vec![
+            TxnOp::Delete { key: b.clone(), precondition: None },
+            TxnOp::Delete { key: b.clone(), precondition: None },
+        ]);
+        assert!(matches!(duplicate, Err(WflDBError::InvalidTransaction(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_generation_fences_writes() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let key = Key::new("leader").unwrap();
+        let first = storage.put_object(&bucket_id, &key, b"one").unwrap();
+        let fenced = |data: &[u8], generation| {
+            let fence = Some(Precondition::Generation(generation));
+            storage.put_object_if(&bucket_id, &key, data, None, BTreeMap::new(), fence, None, None)
+        };
+        let second = fenced(b"two", first.generation).unwrap();
+        assert_eq!(second.generation, first.generation + 1);
+        // The writer that held the first generation is fenced off
+        assert!(matches!(fenced(b"zombie", first.generation), Err(WflDBError::PreconditionFailed { .. })));
+        assert_eq!(storage.get_object(&bucket_id, &key).unwrap().unwrap(), b"two");
+        
+        let stale = vec![TxnOp::Delete { key: key.clone(), precondition: Some(Precondition::Generation(first.generation)) }];
+        assert!(matches!(storage.transact(&bucket_id, stale), Err(WflDBError::PreconditionFailed { .. })));
+        let put = vec![TxnOp::Put { key: key.clone(), data: b"three".to_vec(), precondition: Some(Precondition::Generation(second.generation)) }];
+        let results = storage.transact(&bucket_id, put).unwrap();
+        let third = results[0].as_ref().unwrap().generation;
+        assert_eq!(third, second.generation + 1);
+        
+        let stale = storage.delete_object_if(&bucket_id, &key, Precondition::Generation(second.generation));
+        assert!(matches!(stale, Err(WflDBError::PreconditionFailed { .. })));
+        assert!(storage.get_object(&bucket_id, &key).unwrap().is_some());
+        storage.delete_object_if(&bucket_id, &key, Precondition::Generation(third)).unwrap();
+        assert!(storage.get_object(&bucket_id, &key).unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_commit_detects_changed_reads() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let counter = Key::new("counter").unwrap();
+        let total = Key::new("total").unwrap();
+        storage.put_object(&bucket_id, &counter, b"1").unwrap();
+        
+        // Read both keys, then someone else bumps the counter
+        let mut token = TxnToken::new();
+        let (_, metadata) = storage.get_versioned(&bucket_id, &counter).unwrap().unwrap();
+        token.observe(&counter, Some(&metadata.version));
+        assert!(storage.get_versioned(&bucket_id, &total).unwrap().is_none());
+        token.observe(&total, None);
+        storage.put_object(&bucket_id, &counter, b"2").unwrap();
+        
+        // The write only touches `total`, but its read of `counter` is stale
+        let writes = vec![TxnOp::Put { key: total.clone(), data: b"1".to_vec(), precondition: None }];
+        let stale = storage.commit(&bucket_id, token, writes.clone());
+        assert!(matches!(stale, Err(WflDBError::TxnConflict { ref key }) if key == "counter"));
+        assert!(storage.get_object(&bucket_id, &total).unwrap().is_none());
+        
+        let mut token = TxnToken::new();
+        let (_, metadata) = storage.get_versioned(&bucket_id, &counter).unwrap().unwrap();
+        token.observe(&counter, Some(&metadata.version));
+        token.observe(&total, None);
+        storage.commit(&bucket_id, token, writes).unwrap();
+        assert_eq!(storage.get_object(&bucket_id, &total).unwrap().unwrap(), b"1");
+    }
+    
     #[tokio::test]
     async fn test_large_object_automatic_chunking() {
         let (engine, _temp) = StorageEngine::temp().unwrap();
@@ -205,7 +1063,254 @@ mod tests {
         assert_eq!(retrieved.len(), 128 * 1024);
     }
     
-    #[tokio::test] 
+    #[tokio::test]
+    async fn test_commit_uploaded_manifest() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket_id = BucketId::new("sync").unwrap();
+        let key = Key::new("disk.img").unwrap();
+        let bucket = engine.bucket(&bucket_id).unwrap();
+        
+        let (first, new) = bucket.put_chunk(&[1u8; 1000]).unwrap();
+        assert!(new);
+        assert!(!bucket.put_chunk(&[1u8; 1000]).unwrap().1);
+        let unknown = ContentHash::new(b"never uploaded");
+        match storage.put_manifest_as(&bucket_id, &key, vec![first.clone(), unknown.clone()], None) {
+            Err(WflDBError::MissingChunks(missing)) => assert_eq!(missing, [unknown.to_hex()]),
+            other => panic!("expected missing chunks, got {:?}", other),
+        }
+        
+        let (second, _) = bucket.put_chunk(&[2u8; 500]).unwrap();
+        let metadata = storage
+            .put_manifest_as(&bucket_id, &key, vec![first.clone(), second.clone(), first.clone()], None)
+            .unwrap();
+        assert_eq!(metadata.size, 2500);
+        let data = storage.get_object(&bucket_id, &key).unwrap().unwrap();
+        assert_eq!(data, [vec![1u8; 1000], vec![2u8; 500], vec![1u8; 1000]].concat());
+        
+        // Replacing the object releases chunks only the old version used
+        storage.put_manifest_as(&bucket_id, &key, vec![first.clone()], None).unwrap();
+        assert!(!bucket.has_local_chunk(&second).unwrap());
+        assert!(bucket.has_local_chunk(&first).unwrap());
+        let report = crate::rebuild_refcounts(&engine, false, &mut |_| true).unwrap();
+        assert!(report.is_clean(), "{}", report);
+    }
+
+    #[test]
+    fn test_manifest_journals_chunk_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let journal = std::sync::Arc::new(crate::ChangeJournal::open(dir.path()).unwrap());
+        let storage = Storage::new(engine.with_journal(journal.clone()));
+        let bucket_id = BucketId::new("sync").unwrap();
+        let key = Key::new("disk.img").unwrap();
+        let (first, _) = storage.put_chunk(&bucket_id, &[1u8; 1000]).unwrap();
+        let (second, _) = storage.put_chunk(&bucket_id, &[2u8; 500]).unwrap();
+        assert!(!storage.put_chunk(&bucket_id, &[1u8; 1000]).unwrap().1);
+        let chunks = vec![first.clone(), second, first.clone(), first];
+        let metadata = storage.put_manifest_as(&bucket_id, &key, chunks.clone(), None).unwrap();
+        
+        // Each new chunk once, then the manifest as hashes alone
+        let (records, _) = journal.read_after(0, None, 10).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].op, ChangeOp::Chunk { data: vec![1u8; 1000] });
+        assert_eq!(records[2].op, ChangeOp::Manifest { chunks, owner: None });
+        assert!(records[2].encode_frame().len() < 300);
+        
+        let (replica, _replica_dir) = StorageEngine::temp().unwrap();
+        let replica = Storage::new(replica);
+        for record in records.iter().cloned() {
+            crate::apply_change(&replica, record).unwrap();
+        }
+        assert_eq!(replica.get_object(&bucket_id, &key).unwrap(), storage.get_object(&bucket_id, &key).unwrap());
+        assert_eq!(replica.get_metadata(&bucket_id, &key).unwrap().unwrap().version, metadata.version);
+    }
+
+    #[tokio::test]
+    async fn test_compose() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket_id = BucketId::new("outputs").unwrap();
+        let part = |name: &str| Key::new(name).unwrap();
+        let large = vec![7u8; MAX_CHUNK_SIZE + 100];
+        storage.put_object(&bucket_id, &part("part-0"), &large).unwrap();
+        storage.put_object(&bucket_id, &part("part-1"), b"tail").unwrap();
+
+        let sources = [part("part-0"), part("part-1"), part("part-0")];
+        let metadata = storage.compose_as(&bucket_id, &part("all"), &sources, None).unwrap();
+        assert_eq!(metadata.size, 2 * large.len() as u64 + 4);
+        let data = storage.get_object(&bucket_id, &part("all")).unwrap().unwrap();
+        assert_eq!(data, [large.clone(), b"tail".to_vec(), large.clone()].concat());
+        assert!(matches!(
+            storage.compose_as(&bucket_id, &part("none"), &[part("missing")], None),
+            Err(WflDBError::ObjectNotFound { .. })
+        ));
+
+        // The composed object keeps its chunks once the sources are gone
+        storage.delete_object(&bucket_id, &part("part-0")).unwrap();
+        assert_eq!(storage.get_object(&bucket_id, &part("all")).unwrap().unwrap().len(), data.len());
+        let report = crate::rebuild_refcounts(&engine, false, &mut |_| true).unwrap();
+        assert!(report.is_clean(), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_objects_refuse_splicing() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let bucket_id = BucketId::new("vault").unwrap();
+        let key = Key::new("sealed.bin").unwrap();
+        let ciphertext = vec![5u8; MAX_CHUNK_SIZE + 100];
+        let key_hash = Some(ContentHash::new(b"customer key"));
+        storage.put_object_if(&bucket_id, &key, &ciphertext, None, BTreeMap::new(), None, key_hash.clone(), None).unwrap();
+
+        // Plaintext spliced into ciphertext would corrupt the object
+        assert!(matches!(
+            storage.write_range_as(&bucket_id, &key, 10, b"plain", None),
+            Err(WflDBError::InvalidRequest(_))
+        ));
+        // A composed object would serve ciphertext as its data
+        storage.put_object(&bucket_id, &Key::new("open.txt").unwrap(), b"open").unwrap();
+        let sources = [Key::new("open.txt").unwrap(), key.clone()];
+        assert!(matches!(
+            storage.compose_as(&bucket_id, &Key::new("joined").unwrap(), &sources, None),
+            Err(WflDBError::InvalidRequest(_))
+        ));
+        let metadata = storage.get_metadata(&bucket_id, &key).unwrap().unwrap();
+        assert_eq!(metadata.customer_key_hash, key_hash);
+        assert_eq!(storage.get_object(&bucket_id, &key).unwrap().unwrap(), ciphertext);
+        assert!(storage.get_object(&bucket_id, &Key::new("joined").unwrap()).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_checksums_recorded() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let bucket_id = BucketId::new("migrated").unwrap();
+        let key = Key::new("report.csv").unwrap();
+        let checksums = BTreeMap::from([(ChecksumAlgorithm::Crc32c, "4waSgw==".to_string())]);
+
+        storage.put_object_with_checksums_as(&bucket_id, &key, b"data", None, checksums.clone()).unwrap();
+        assert_eq!(storage.get_metadata(&bucket_id, &key).unwrap().unwrap().checksums, checksums);
+        // A later write without them doesn't keep stale ones
+        storage.put_object(&bucket_id, &key, b"other data").unwrap();
+        assert!(storage.get_metadata(&bucket_id, &key).unwrap().unwrap().checksums.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_range() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let bucket_id = BucketId::new("images").unwrap();
+        let key = Key::new("disk.img").unwrap();
+        let mut expected = vec![1u8; 2 * MAX_CHUNK_SIZE + 10];
+        let original = storage.put_object(&bucket_id, &key, &expected).unwrap().chunk_manifest.unwrap();
+
+        // An edit inside the second chunk leaves the others alone
+        let offset = MAX_CHUNK_SIZE + 5;
+        let metadata = storage.write_range_as(&bucket_id, &key, offset as u64, b"patched", None).unwrap();
+        expected[offset..offset + 7].copy_from_slice(b"patched");
+        let manifest = metadata.chunk_manifest.unwrap();
+        assert_eq!(manifest.chunks[0], original.chunks[0]);
+        assert_eq!(manifest.chunks[2], original.chunks[2]);
+        assert_eq!(storage.get_object(&bucket_id, &key).unwrap().unwrap(), expected);
+
+        // Across the last boundary and past the end
+        let offset = expected.len() - 12;
+        storage.write_range_as(&bucket_id, &key, offset as u64, &[9u8; 20], None).unwrap();
+        expected.truncate(offset);
+        expected.extend_from_slice(&[9u8; 20]);
+        assert_eq!(storage.get_object(&bucket_id, &key).unwrap().unwrap(), expected);
+
+        let end = expected.len() as u64;
+        assert!(storage.write_range_as(&bucket_id, &key, end + 1, b"gap", None).is_err());
+        storage.put_object(&bucket_id, &Key::new("small").unwrap(), b"tiny").unwrap();
+        assert!(storage.write_range_as(&bucket_id, &Key::new("small").unwrap(), 0, b"x", None).is_err());
+        let report = crate::rebuild_refcounts(&engine, false, &mut |_| true).unwrap();
+        assert!(report.is_clean(), "{}", report);
+    }
+    
+    #[test]
+    fn test_write_range_journals_the_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let journal = std::sync::Arc::new(crate::ChangeJournal::open(dir.path()).unwrap());
+        let storage = Storage::new(engine.with_journal(journal.clone()));
+        let bucket_id = BucketId::new("images").unwrap();
+        let key = Key::new("disk.img").unwrap();
+        storage.put_object(&bucket_id, &key, &vec![1u8; 2 * MAX_CHUNK_SIZE + 10]).unwrap();
+        let written = storage.write_range_as(&bucket_id, &key, MAX_CHUNK_SIZE as u64 + 5, b"patched", None).unwrap();
+        
+        let (records, _) = journal.read_after(0, None, 10).unwrap();
+        assert_eq!(records.len(), 2);
+        let range = ChangeOp::WriteRange { offset: MAX_CHUNK_SIZE as u64 + 5, data: b"patched".to_vec(), owner: None };
+        assert_eq!(records[1].op, range);
+        assert_eq!(records[1].version.as_ref(), Some(&written.version));
+        
+        // A replica splices the range into its own copy, at the same version
+        let (replica, _replica_dir) = StorageEngine::temp().unwrap();
+        let replica = Storage::new(replica);
+        for record in records.iter().cloned() {
+            crate::apply_change(&replica, record).unwrap();
+        }
+        assert_eq!(replica.get_object(&bucket_id, &key).unwrap(), storage.get_object(&bucket_id, &key).unwrap());
+        assert_eq!(replica.get_metadata(&bucket_id, &key).unwrap().unwrap().version, written.version);
+        // Applied again, the range is stale and changes nothing
+        crate::apply_change(&replica, records[1].clone()).unwrap();
+        assert_eq!(replica.get_metadata(&bucket_id, &key).unwrap().unwrap().generation, 2);
+
+        // A replica whose copy isn't chunked refuses the range
+        let (inline, _inline_dir) = StorageEngine::temp().unwrap();
+        let inline = Storage::new(inline);
+        inline.put_object(&bucket_id, &key, b"small").unwrap();
+        let later = ChangeRecord { version: Some(Version::new()), ..records[1].clone() };
+        assert!(crate::apply_change(&inline, later).is_err());
+        assert_eq!(inline.get_object(&bucket_id, &key).unwrap().unwrap(), b"small");
+    }
+
+    #[tokio::test]
+    async fn test_binary_keys() {
+        NamePolicy::set_global(NamePolicy { key_charset: KeyCharset::Binary, ..NamePolicy::DEFAULT });
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        let bucket_id = BucketId::new("ids").unwrap();
+        let binary = Key::from_bytes(&[b'u', 0xff, 0x00, 0x01]).unwrap();
+        let text = Key::new("u").unwrap();
+        
+        storage.put_object(&bucket_id, &binary, b"binary").unwrap();
+        storage.put_object(&bucket_id, &text, b"text").unwrap();
+        assert_eq!(storage.get_object(&bucket_id, &binary).unwrap().unwrap(), b"binary");
+        // Listed in byte order, and a listing can resume after a binary key
+        assert_eq!(storage.list_objects(&bucket_id, "u", None).unwrap(), vec![text.clone(), binary.clone()]);
+        let bucket = storage.engine().bucket(&bucket_id).unwrap();
+        assert!(bucket.scan_prefix_after("u", Some(binary.as_str()), None).unwrap().is_empty());
+        
+        storage.delete_object(&bucket_id, &binary).unwrap();
+        assert!(storage.get_object(&bucket_id, &binary).unwrap().is_none());
+        assert_eq!(storage.get_object(&bucket_id, &text).unwrap().unwrap(), b"text");
+    }
+
+    #[tokio::test]
+    async fn test_listing_reports_journal_position() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        assert_eq!(Storage::new(engine.clone()).list_objects_at(&bucket_id, "", None, None).unwrap().1, None);
+        
+        let journal = std::sync::Arc::new(crate::ChangeJournal::open(dir.path()).unwrap());
+        let storage = Storage::new(engine.with_journal(journal));
+        storage.put_object(&bucket_id, &Key::new("a").unwrap(), b"1").unwrap();
+        storage.put_object(&bucket_id, &Key::new("b").unwrap(), b"2").unwrap();
+        let bucket = storage.engine().bucket(&bucket_id).unwrap();
+        let snapshot = bucket.main_partition.snapshot();
+        storage.put_object(&bucket_id, &Key::new("c").unwrap(), b"3").unwrap();
+        
+        // A scan of an earlier snapshot doesn't see later writes
+        assert_eq!(bucket.scan_snapshot(&snapshot, "", None, None).unwrap().len(), 2);
+        let (keys, position) = storage.list_objects_at(&bucket_id, "", None, None).unwrap();
+        assert_eq!((keys.len(), position), (3, Some(3)));
+    }
+
+    #[tokio::test]
     async fn test_delete_object() {
         let (engine, _temp) = StorageEngine::temp().unwrap();
         let storage = Storage::new(engine);
@@ -223,4 +1328,21 @@ mod tests {
         assert!(storage.get_object(&bucket_id, &key).unwrap().is_none());
         assert!(storage.get_metadata(&bucket_id, &key).unwrap().is_none());
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_owner_survives_overwrite() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine);
+        
+        let bucket_id = BucketId::new("test-bucket").unwrap();
+        let key = Key::new("shared").unwrap();
+        
+        let metadata = storage.put_object_as(&bucket_id, &key, b"v1", Some("alice")).unwrap();
+        assert_eq!(metadata.owner.as_deref(), Some("alice"));
+        
+        // A later writer doesn't take over ownership
+        storage.put_object_as(&bucket_id, &key, b"v2", Some("bob")).unwrap();
+        let metadata = storage.get_metadata(&bucket_id, &key).unwrap().unwrap();
+        assert_eq!(metadata.owner.as_deref(), Some("alice"));
+    }
+}
@@ -0,0 +1,123 @@
+//! System partition for server-internal metadata
+//!
+//! Bucket partitions are named `{bucket}_main`, so the system partition name
+//! can never collide with a user bucket.
+
+use fjall::{Partition, PartitionCreateOptions};
+use std::sync::Arc;
+use wfldb_core::*;
+use crate::StorageEngine;
+
+/// Name of the fjall partition holding system metadata
+pub const SYSTEM_PARTITION: &str = "_system";
+
+/// Key-value access to the system partition
+#[derive(Clone)]
+pub struct SystemPartition {
+    partition: Arc<Partition>,
+    engine: StorageEngine,
+}
+
+impl SystemPartition {
+    pub(crate) fn open(engine: StorageEngine) -> Result<Self> {
+        let partition = Arc::new(
+            engine
+                .keyspace()
+                .open_partition(SYSTEM_PARTITION, PartitionCreateOptions::default())
+                .map_err(WflDBError::storage)?
+        );
+
+        Ok(SystemPartition { partition, engine })
+    }
+
+    /// Get a value
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.partition.get(key) {
+            Ok(Some(data)) => Ok(Some(data.to_vec())),
+            Ok(None) => Ok(None),
+            Err(e) => Err(WflDBError::storage(e)),
+        }
+    }
+
+    /// Durably store a value
+    pub fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.partition
+            .insert(key, value)
+            .map_err(WflDBError::storage)?;
+        self.engine.persist()
+    }
+
+    /// Durably remove a value
+    pub fn delete(&self, key: &str) -> Result<()> {
+        self.partition
+            .remove(key)
+            .map_err(WflDBError::storage)?;
+        self.engine.persist()
+    }
+
+    /// Up to `limit` entries with keys from `start` up to `end`, in key order
+    pub fn scan_range(&self, start: &str, end: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>> {
+        self.partition
+            .range(start..end)
+            .take(limit)
+            .map(decode_entry)
+            .collect()
+    }
+
+    /// Partition handle, for writes batched with other partitions
+    pub(crate) fn partition(&self) -> &Partition {
+        &self.partition
+    }
+
+    /// All entries whose key starts with `prefix`, in key order
+    pub fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        self.partition
+            .prefix(prefix)
+            .map(decode_entry)
+            .collect()
+    }
+}
+
+fn decode_entry<K, V, E>(item: std::result::Result<(K, V), E>) -> Result<(String, Vec<u8>)>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let (key, value) = item.map_err(WflDBError::storage)?;
+    let key = String::from_utf8(key.as_ref().to_vec())
+        .map_err(|e| WflDBError::Corrupt(format!("system key: {}", e)))?;
+    Ok((key, value.as_ref().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_partition_roundtrip() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let system = engine.system().unwrap();
+
+        system.put("auth/root", b"root-key").unwrap();
+        system.put("auth/issuer/a", b"a").unwrap();
+        system.put("auth/issuer/b", b"b").unwrap();
+
+        assert_eq!(system.get("auth/root").unwrap(), Some(b"root-key".to_vec()));
+        let issuers = system.scan_prefix("auth/issuer/").unwrap();
+        assert_eq!(issuers.len(), 2);
+        assert_eq!(issuers[0].0, "auth/issuer/a");
+
+        system.delete("auth/root").unwrap();
+        assert_eq!(system.get("auth/root").unwrap(), None);
+    }
+
+    #[test]
+    fn test_system_partition_is_not_a_bucket() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        engine.system().unwrap().put("k", b"system").unwrap();
+
+        let bucket = engine.bucket(&BucketId::new("_system").unwrap()).unwrap();
+        assert_eq!(bucket.get_small(&Key::new("k").unwrap()).unwrap(), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Per-key request usage statistics
+//!
+//! Usage is accumulated in memory on the request path and periodically
+//! merged into the system partition, so recording a request never waits on
+//! a disk write.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use wfldb_core::*;
+use crate::SystemPartition;
+
+pub(crate) const USAGE_PREFIX: &str = "usage/";
+
+/// Aggregate usage of a single key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyUsage {
+    pub requests: u64,
+    pub errors: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Milliseconds since the Unix epoch
+    pub first_used_ms: u64,
+    /// Milliseconds since the Unix epoch
+    pub last_used_ms: u64,
+}
+
+impl KeyUsage {
+    fn merge(&mut self, other: &KeyUsage) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.first_used_ms = match (self.first_used_ms, other.first_used_ms) {
+            (0, other) => other,
+            (mine, 0) => mine,
+            (mine, other) => mine.min(other),
+        };
+        self.last_used_ms = self.last_used_ms.max(other.last_used_ms);
+    }
+}
+
+/// Records usage per key ID and persists it to the system partition
+pub struct UsageTracker {
+    system: SystemPartition,
+    pending: Mutex<HashMap<String, KeyUsage>>,
+}
+
+impl UsageTracker {
+    pub fn new(system: SystemPartition) -> Self {
+        UsageTracker {
+            system,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record one request made with a key
+    pub fn record(&self, key_id: &str, bytes_in: u64, bytes_out: u64, error: bool, now_ms: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        let usage = pending.entry(key_id.to_string()).or_default();
+        usage.merge(&KeyUsage {
+            requests: 1,
+            errors: error as u64,
+            bytes_in,
+            bytes_out,
+            first_used_ms: now_ms,
+            last_used_ms: now_ms,
+        });
+    }
+
+    /// Merge pending usage into the system partition, returning the number of keys written
+    pub fn flush(&self) -> Result<usize> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let count = pending.len();
+        for (key_id, delta) in pending {
+            let storage_key = format!("{}{}", USAGE_PREFIX, key_id);
+            let mut usage = self.load(&storage_key)?.unwrap_or_default();
+            usage.merge(&delta);
+            let encoded = serde_json::to_vec(&usage).map_err(WflDBError::Serialization)?;
+            self.system.put(&storage_key, &encoded)?;
+        }
+        Ok(count)
+    }
+
+    /// Usage of a single key, including unflushed requests
+    pub fn get(&self, key_id: &str) -> Result<Option<KeyUsage>> {
+        let mut usage = self.load(&format!("{}{}", USAGE_PREFIX, key_id))?;
+        if let Some(delta) = self.pending.lock().unwrap().get(key_id) {
+            usage.get_or_insert_with(KeyUsage::default).merge(delta);
+        }
+        Ok(usage)
+    }
+
+    /// Usage of every key seen, including unflushed requests, sorted by key ID
+    pub fn list(&self) -> Result<Vec<(String, KeyUsage)>> {
+        let mut all = BTreeMap::new();
+        for (key, value) in self.system.scan_prefix(USAGE_PREFIX)? {
+            let usage: KeyUsage = serde_json::from_slice(&value).map_err(WflDBError::Serialization)?;
+            all.insert(key[USAGE_PREFIX.len()..].to_string(), usage);
+        }
+        for (key_id, delta) in self.pending.lock().unwrap().iter() {
+            all.entry(key_id.clone()).or_insert_with(KeyUsage::default).merge(delta);
+        }
+        Ok(all.into_iter().collect())
+    }
+
+    fn load(&self, storage_key: &str) -> Result<Option<KeyUsage>> {
+        match self.system.get(storage_key)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data).map_err(WflDBError::Serialization)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageEngine;
+
+    #[test]
+    fn test_usage_accumulates_across_flushes() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let tracker = UsageTracker::new(engine.system().unwrap());
+
+        tracker.record("abc", 100, 0, false, 1_000);
+        tracker.record("abc", 0, 50, true, 2_000);
+        assert_eq!(tracker.flush().unwrap(), 1);
+        tracker.record("abc", 10, 10, false, 3_000);
+
+        let usage = tracker.get("abc").unwrap().unwrap();
+        assert_eq!(usage.requests, 3);
+        assert_eq!(usage.errors, 1);
+        assert_eq!((usage.bytes_in, usage.bytes_out), (110, 60));
+        assert_eq!((usage.first_used_ms, usage.last_used_ms), (1_000, 3_000));
+    }
+
+    #[test]
+    fn test_usage_survives_new_tracker() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let tracker = UsageTracker::new(engine.system().unwrap());
+        tracker.record("abc", 1, 1, false, 1_000);
+        tracker.record("def", 1, 1, false, 1_000);
+        tracker.flush().unwrap();
+
+        let reopened = UsageTracker::new(engine.system().unwrap());
+        let keys: Vec<String> = reopened.list().unwrap().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["abc", "def"]);
+    }
+}
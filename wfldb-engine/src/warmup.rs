@@ -0,0 +1,172 @@
+//! Startup warm-up from recently read objects
+//!
+//! A freshly started node has cold partitions and an empty block cache, so
+//! its first reads all go to disk. `HotKeyTracker` remembers which objects
+//! were read most recently and periodically saves that list to the system
+//! partition; `warm_up` reads the saved objects back on the next start,
+//! before the node takes traffic.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wfldb_core::*;
+use crate::{Storage, StorageEngine, SystemPartition};
+
+pub(crate) const HOT_KEYS_KEY: &str = "warmup/hot_keys";
+
+/// An object by namespaced bucket name and key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HotKey {
+    pub bucket: String,
+    pub key: String,
+}
+
+/// Remembers the most recently read objects and persists them
+pub struct HotKeyTracker {
+    system: SystemPartition,
+    capacity: usize,
+    recent: Mutex<RecentKeys>,
+}
+
+#[derive(Default)]
+struct RecentKeys {
+    tick: u64,
+    last_read: HashMap<HotKey, u64>,
+}
+
+impl RecentKeys {
+    /// Keys from most to least recently read
+    fn by_recency(&self) -> Vec<HotKey> {
+        let mut keys: Vec<(&HotKey, u64)> = self.last_read.iter().map(|(k, t)| (k, *t)).collect();
+        keys.sort_by_key(|(_, tick)| std::cmp::Reverse(*tick));
+        keys.into_iter().map(|(k, _)| k.clone()).collect()
+    }
+}
+
+impl HotKeyTracker {
+    /// Track up to `capacity` keys
+    pub fn new(system: SystemPartition, capacity: usize) -> Self {
+        HotKeyTracker {
+            system,
+            capacity: capacity.max(1),
+            recent: Mutex::new(RecentKeys::default()),
+        }
+    }
+
+    /// Note a read of `key` in the bucket with namespaced name `bucket`
+    pub fn record(&self, bucket: &str, key: &str) {
+        let mut recent = self.recent.lock().unwrap();
+        recent.tick += 1;
+        let tick = recent.tick;
+        recent.last_read.insert(HotKey { bucket: bucket.to_string(), key: key.to_string() }, tick);
+        // Trim in batches so a read doesn't sort the table every time
+        if recent.last_read.len() >= self.capacity * 2 {
+            let keep: HashSet<HotKey> = recent.by_recency().into_iter().take(self.capacity).collect();
+            recent.last_read.retain(|k, _| keep.contains(k));
+        }
+    }
+
+    /// Save the hottest keys, topping up with the previously saved list so a
+    /// quiet period doesn't forget it. Returns the number of keys saved.
+    pub fn flush(&self) -> Result<usize> {
+        let mut keys = self.recent.lock().unwrap().by_recency();
+        keys.truncate(self.capacity);
+        let seen: HashSet<HotKey> = keys.iter().cloned().collect();
+        for key in load_hot_keys(&self.system)? {
+            if keys.len() >= self.capacity {
+                break;
+            }
+            if !seen.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let encoded = serde_json::to_vec(&keys).map_err(WflDBError::Serialization)?;
+        self.system.put(HOT_KEYS_KEY, &encoded)?;
+        Ok(keys.len())
+    }
+}
+
+/// Saved hot keys, hottest first
+pub fn load_hot_keys(system: &SystemPartition) -> Result<Vec<HotKey>> {
+    match system.get(HOT_KEYS_KEY)? {
+        Some(data) => serde_json::from_slice(&data).map_err(WflDBError::Serialization),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Outcome of a warm-up pass
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WarmupReport {
+    pub objects_loaded: usize,
+    pub bytes_loaded: u64,
+    /// Saved keys that no longer exist or couldn't be read
+    pub skipped: usize,
+    pub partitions: usize,
+    pub duration_ms: u64,
+}
+
+impl std::fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "loaded {} objects ({} bytes) from {} partitions in {}ms, {} skipped",
+            self.objects_loaded, self.bytes_loaded, self.partitions, self.duration_ms, self.skipped
+        )
+    }
+}
+
+/// Read up to `limit` saved hot objects, stopping early once `budget` is spent
+pub fn warm_up(engine: &StorageEngine, limit: usize, budget: Duration) -> Result<WarmupReport> {
+    let started = Instant::now();
+    let mut report = WarmupReport::default();
+    let mut partitions = HashSet::new();
+    for hot in load_hot_keys(&engine.system()?)?.into_iter().take(limit) {
+        if started.elapsed() >= budget {
+            break;
+        }
+        let loaded = engine
+            .resolve_namespaced(&hot.bucket)
+            .and_then(|(view, bucket_id)| {
+                let key = Key::new(&hot.key)?;
+                Storage::new(view).get_object(&bucket_id, &key)
+            });
+        match loaded {
+            Ok(Some(data)) => {
+                report.objects_loaded += 1;
+                report.bytes_loaded += data.len() as u64;
+                partitions.insert(hot.bucket);
+            }
+            Ok(None) | Err(_) => report.skipped += 1,
+        }
+    }
+    report.partitions = partitions.len();
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_keeps_hottest_and_warm_up_reads_them() {
+        let (engine, _temp) = StorageEngine::temp().unwrap();
+        let storage = Storage::new(engine.clone());
+        let photos = BucketId::new("photos").unwrap();
+        for name in ["a", "b", "c"] {
+            storage.put_object(&photos, &Key::new(name).unwrap(), name.as_bytes()).unwrap();
+        }
+
+        let tracker = HotKeyTracker::new(engine.system().unwrap(), 2);
+        for name in ["a", "b", "c", "b", "gone"] {
+            tracker.record("photos", name);
+        }
+        assert_eq!(tracker.flush().unwrap(), 2);
+        let saved: Vec<String> = load_hot_keys(&engine.system().unwrap()).unwrap().into_iter().map(|k| k.key).collect();
+        assert_eq!(saved, vec!["gone", "b"]);
+
+        let report = warm_up(&engine, 10, Duration::from_secs(10)).unwrap();
+        assert_eq!((report.objects_loaded, report.skipped, report.partitions), (1, 1, 1));
+    }
+}
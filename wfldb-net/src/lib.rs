@@ -4,9 +4,11 @@ use flatbuffers::{FlatBufferBuilder, WIPOffset};
 use std::io::{self, Read, Write};
 use wfldb_core::*;
 
+pub mod multiplex;
 pub mod protocol;
 pub mod wire;
 
+pub use multiplex::*;
 pub use protocol::*;
 pub use wire::*;
 
@@ -47,14 +49,14 @@ impl WireFrame {
     /// Parse frame from bytes
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         if bytes.len() < 4 {
-            return Err(WflDBError::Internal("Frame too short".to_string()));
+            return Err(WflDBError::InvalidRequest("Frame too short".to_string()));
         }
         
         // Read header length
         let header_len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
         
         if bytes.len() < 4 + header_len {
-            return Err(WflDBError::Internal("Incomplete frame".to_string()));
+            return Err(WflDBError::InvalidRequest("Incomplete frame".to_string()));
         }
         
         // Extract header and body
@@ -144,10 +146,9 @@ impl RequestMessage {
     /// Parse from bytes (simplified JSON for spike)
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         let json_str = std::str::from_utf8(bytes)
-            .map_err(|_| WflDBError::Internal("Invalid UTF-8".to_string()))?;
+            .map_err(|_| WflDBError::InvalidRequest("Invalid UTF-8".to_string()))?;
         
-        let json: serde_json::Value = serde_json::from_str(json_str)
-            .map_err(|e| WflDBError::Internal(format!("JSON parse error: {}", e)))?;
+        let json: serde_json::Value = serde_json::from_str(json_str)?;
         
         let request_type = match json["request_type"].as_str().unwrap_or("") {
             "Get" => RequestType::Get,
@@ -155,7 +156,7 @@ impl RequestMessage {
             "Delete" => RequestType::Delete,
             "Scan" => RequestType::Scan,
             "Batch" => RequestType::Batch,
-            _ => return Err(WflDBError::Internal("Invalid request type".to_string())),
+            _ => return Err(WflDBError::InvalidRequest("Invalid request type".to_string())),
         };
         
         Ok(RequestMessage {
@@ -179,6 +180,10 @@ pub struct ResponseMessage {
     pub request_id: String,
     pub status: ResponseStatus,
     pub error_message: Option<String>,
+    /// Stable code of the error, as from [`WflDBError::code`]
+    pub error_code: Option<String>,
+    /// Whether sending the request again can succeed
+    pub retryable: bool,
     pub content_length: u64,
     pub content_hash: Option<Vec<u8>>,
     pub version: Option<String>,
@@ -199,6 +204,8 @@ impl ResponseMessage {
             request_id,
             status: ResponseStatus::Ok,
             error_message: None,
+            error_code: None,
+            retryable: false,
             content_length: 0,
             content_hash: None,
             version: None,
@@ -211,6 +218,8 @@ impl ResponseMessage {
             request_id,
             status: ResponseStatus::Error,
             error_message: Some(message),
+            error_code: None,
+            retryable: false,
             content_length: 0,
             content_hash: None,
             version: None,
@@ -218,11 +227,23 @@ impl ResponseMessage {
         }
     }
     
+    /// Response reporting `err`, classified like the HTTP API's errors
+    pub fn from_error(request_id: String, err: &WflDBError) -> Self {
+        ResponseMessage {
+            status: if err.is_not_found() { ResponseStatus::NotFound } else { ResponseStatus::Error },
+            error_code: Some(err.code().to_string()),
+            retryable: err.is_retryable(),
+            ..ResponseMessage::error(request_id, err.to_string())
+        }
+    }
+    
     pub fn to_bytes(&self) -> Vec<u8> {
         let json = serde_json::json!({
             "request_id": self.request_id,
             "status": format!("{:?}", self.status),
             "error_message": self.error_message,
+            "error_code": self.error_code,
+            "retryable": self.retryable,
             "content_length": self.content_length,
             "content_hash": self.content_hash,
             "version": self.version,
@@ -314,6 +335,18 @@ mod tests {
         assert_eq!(parsed.request_type, msg.request_type);
     }
     
+    #[test]
+    fn test_error_response() {
+        let missing = ResponseMessage::from_error("r1".to_string(), &WflDBError::ObjectNotFound { key: "k".to_string() });
+        assert_eq!(missing.status, ResponseStatus::NotFound);
+        let conflict = ResponseMessage::from_error("r2".to_string(), &WflDBError::TxnConflict { key: "k".to_string() });
+        let json: serde_json::Value = serde_json::from_slice(&conflict.to_bytes()).unwrap();
+        assert_eq!((json["error_code"].as_str(), json["retryable"].as_bool()), (Some("conflict"), Some(true)));
+        
+        // Malformed frames are the sender's fault, not an internal error
+        assert_eq!(WireFrame::from_bytes(&[1]).unwrap_err().code(), "bad_request");
+    }
+    
     #[test]
     fn test_zero_copy_parsing() {
         // This test demonstrates the concept of zero-copy parsing
@@ -0,0 +1,274 @@
+//! Multiplexed bodies carrying many objects in one response
+//!
+//! A batch GET answers with one body holding every object it asked for,
+//! split into length-prefixed frames:
+//!
+//! ```text
+//! [1 byte: kind][4 bytes: stream id][4 bytes: payload length][payload]
+//! ```
+//!
+//! Integers are little-endian. Each object is one stream, numbered by its
+//! position in the request. A stream opens with a `Header` frame, a JSON
+//! [`EntryHeader`] with the key, its outcome and metadata, followed by any
+//! number of `Data` frames with the object's bytes, and closes with `End`,
+//! or with `Abort` carrying the error that cut it short. Frames of
+//! different streams may interleave, so a sender can start on the next
+//! object while a large one is still being read.
+
+use serde::{Deserialize, Serialize};
+use wfldb_core::*;
+
+/// Content type of a multiplexed body
+pub const MUX_CONTENT_TYPE: &str = "application/x-wfldb-mux";
+
+/// Bytes before each frame's payload
+pub const MUX_FRAME_HEADER_LEN: usize = 9;
+
+/// Largest payload in one frame; senders split larger data
+pub const MAX_MUX_PAYLOAD: usize = 4 * 1024 * 1024;
+
+/// Most keys one batch GET may ask for
+pub const MAX_MGET_KEYS: usize = 1000;
+
+/// What a frame holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxFrameKind {
+    /// JSON [`EntryHeader`] opening a stream
+    Header = 1,
+    /// Object bytes
+    Data = 2,
+    /// The stream is complete
+    End = 3,
+    /// JSON [`EntryHeader`] with the error that cut the stream short
+    Abort = 4,
+}
+
+impl MuxFrameKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(MuxFrameKind::Header),
+            2 => Some(MuxFrameKind::Data),
+            3 => Some(MuxFrameKind::End),
+            4 => Some(MuxFrameKind::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of one object in a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Ok,
+    NotFound,
+    Error,
+}
+
+/// What a stream's `Header` or `Abort` frame says about its object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryHeader {
+    pub key: String,
+    pub status: EntryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Status a GET of the key alone would have failed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    /// Stable code of the error, like the HTTP API's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retryable: bool,
+}
+
+impl EntryHeader {
+    fn new(key: &str, status: EntryStatus) -> Self {
+        EntryHeader {
+            key: key.to_string(),
+            status,
+            version: None,
+            generation: None,
+            size: None,
+            http_status: None,
+            code: None,
+            error: None,
+            retryable: false,
+        }
+    }
+
+    pub fn found(key: &str, version: String, generation: u64, size: u64) -> Self {
+        EntryHeader {
+            version: Some(version),
+            generation: Some(generation),
+            size: Some(size),
+            ..EntryHeader::new(key, EntryStatus::Ok)
+        }
+    }
+
+    pub fn not_found(key: &str) -> Self {
+        EntryHeader {
+            http_status: Some(404),
+            code: Some("not_found".to_string()),
+            error: Some("Object not found".to_string()),
+            ..EntryHeader::new(key, EntryStatus::NotFound)
+        }
+    }
+
+    pub fn error(key: &str, http_status: u16, code: &str, message: String, retryable: bool) -> Self {
+        EntryHeader {
+            http_status: Some(http_status),
+            code: Some(code.to_string()),
+            error: Some(message),
+            retryable,
+            ..EntryHeader::new(key, EntryStatus::Error)
+        }
+    }
+}
+
+/// One frame of a multiplexed body
+#[derive(Debug, Clone, PartialEq)]
+pub struct MuxFrame {
+    pub kind: MuxFrameKind,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl MuxFrame {
+    pub fn header(stream: u32, header: &EntryHeader) -> Self {
+        let payload = serde_json::to_vec(header).expect("entry headers serialize");
+        MuxFrame { kind: MuxFrameKind::Header, stream, payload }
+    }
+
+    pub fn data(stream: u32, data: Vec<u8>) -> Self {
+        MuxFrame { kind: MuxFrameKind::Data, stream, payload: data }
+    }
+
+    pub fn end(stream: u32) -> Self {
+        MuxFrame { kind: MuxFrameKind::End, stream, payload: Vec::new() }
+    }
+
+    pub fn abort(stream: u32, header: &EntryHeader) -> Self {
+        MuxFrame { kind: MuxFrameKind::Abort, ..MuxFrame::header(stream, header) }
+    }
+
+    /// Data frames carrying `data`, split to fit [`MAX_MUX_PAYLOAD`]
+    pub fn data_frames(stream: u32, data: &[u8]) -> impl Iterator<Item = MuxFrame> + '_ {
+        data.chunks(MAX_MUX_PAYLOAD).map(move |part| MuxFrame::data(stream, part.to_vec()))
+    }
+
+    /// Serialize frame to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MUX_FRAME_HEADER_LEN + self.payload.len());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.stream.to_le_bytes());
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// The [`EntryHeader`] of a `Header` or `Abort` frame
+    pub fn entry_header(&self) -> Result<EntryHeader> {
+        if !matches!(self.kind, MuxFrameKind::Header | MuxFrameKind::Abort) {
+            return Err(WflDBError::InvalidRequest(format!("{:?} frame has no entry header", self.kind)));
+        }
+        serde_json::from_slice(&self.payload)
+            .map_err(|e| WflDBError::InvalidRequest(format!("Malformed entry header: {}", e)))
+    }
+}
+
+/// Splits a multiplexed body into frames as its bytes arrive
+#[derive(Debug, Default)]
+pub struct MuxDecoder {
+    buffer: Vec<u8>,
+}
+
+impl MuxDecoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add bytes received from the body
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Next complete frame, or `None` until more bytes are pushed
+    pub fn next_frame(&mut self) -> Result<Option<MuxFrame>> {
+        if self.buffer.len() < MUX_FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let kind = MuxFrameKind::from_byte(self.buffer[0])
+            .ok_or_else(|| WflDBError::InvalidRequest(format!("Unknown frame kind {}", self.buffer[0])))?;
+        let stream = u32::from_le_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]);
+        let len = u32::from_le_bytes([self.buffer[5], self.buffer[6], self.buffer[7], self.buffer[8]]) as usize;
+        if len > MAX_MUX_PAYLOAD {
+            return Err(WflDBError::InvalidRequest(format!("Frame payload too large: {} bytes", len)));
+        }
+        if self.buffer.len() < MUX_FRAME_HEADER_LEN + len {
+            return Ok(None);
+        }
+        let payload = self.buffer[MUX_FRAME_HEADER_LEN..MUX_FRAME_HEADER_LEN + len].to_vec();
+        self.buffer.drain(..MUX_FRAME_HEADER_LEN + len);
+        Ok(Some(MuxFrame { kind, stream, payload }))
+    }
+
+    /// Check the body ended on a frame boundary
+    pub fn finish(&self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            return Err(WflDBError::InvalidRequest("Body ended mid-frame".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mux_roundtrip_in_pieces() {
+        let frames = vec![
+            MuxFrame::header(0, &EntryHeader::found("a", "v1".to_string(), 1, 5)),
+            MuxFrame::header(1, &EntryHeader::not_found("b")),
+            MuxFrame::data(0, b"hello".to_vec()),
+            MuxFrame::end(1),
+            MuxFrame::end(0),
+        ];
+        let body: Vec<u8> = frames.iter().flat_map(MuxFrame::to_bytes).collect();
+
+        // Bytes arrive in arbitrary pieces
+        let mut decoder = MuxDecoder::new();
+        let mut decoded = Vec::new();
+        for piece in body.chunks(3) {
+            decoder.push(piece);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                decoded.push(frame);
+            }
+        }
+        decoder.finish().unwrap();
+        assert_eq!(decoded, frames);
+        assert_eq!(decoded[1].entry_header().unwrap().status, EntryStatus::NotFound);
+        assert!(decoded[2].entry_header().is_err());
+    }
+
+    #[test]
+    fn test_mux_rejects_malformed() {
+        let mut decoder = MuxDecoder::new();
+        decoder.push(&[9, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(decoder.next_frame().unwrap_err().code(), "bad_request");
+
+        let mut decoder = MuxDecoder::new();
+        decoder.push(&MuxFrame::data(0, b"cut".to_vec()).to_bytes()[..10]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        assert!(decoder.finish().is_err());
+
+        let large = vec![0; MAX_MUX_PAYLOAD + 1];
+        assert_eq!(MuxFrame::data_frames(0, &large).count(), 2);
+    }
+}
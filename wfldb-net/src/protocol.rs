@@ -94,6 +94,12 @@ impl CanonicalRequest {
         self
     }
     
+    /// Set the query string; parameters are normalized so encoding and order don't matter
+    pub fn with_query(mut self, query: &str) -> Self {
+        self.query_string = canonical_query(query);
+        self
+    }
+    
     pub fn add_header(mut self, name: &str, value: &str) -> Self {
         self.headers.push((name.to_lowercase(), value.to_string()));
         self
@@ -131,6 +137,77 @@ impl CanonicalRequest {
     }
 }
 
+/// Normalize a query string: percent-encode every parameter the same way and sort them
+pub fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (normalize_component(name), normalize_component(value))
+        })
+        .collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Value of the first `name` parameter in a query string, percent-decoded
+pub fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .map(|param| param.split_once('=').unwrap_or((param, "")))
+        .find(|(key, _)| decode_component(key) == name.as_bytes())
+        .map(|(_, value)| String::from_utf8_lossy(&decode_component(value)).into_owned())
+}
+
+/// Percent-decode a query component, treating `+` as a space
+fn decode_component(component: &str) -> Vec<u8> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+/// Decode a query component and re-encode everything except RFC 3986 unreserved characters
+fn normalize_component(component: &str) -> String {
+    encode_query_component(&decode_component(component))
+}
+
+/// Percent-encode a query parameter value, leaving RFC 3986 unreserved characters as-is
+pub fn encode_query_component(value: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &byte in value {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,6 +230,21 @@ mod tests {
         assert!(canonical.contains("abc123"));
     }
     
+    #[test]
+    fn test_canonical_query() {
+        assert_eq!(canonical_query("b=2&a=1"), "a=1&b=2");
+        assert_eq!(canonical_query("prefix=a%2fb&x=y+z"), "prefix=a%2Fb&x=y%20z");
+        assert_eq!(canonical_query("prefix=a/b&flag"), "flag=&prefix=a%2Fb");
+        assert_eq!(canonical_query(""), "");
+        
+        assert_eq!(query_param("prefix=a%2Fb+c&limit=5", "prefix").as_deref(), Some("a/b c"));
+        assert_eq!(query_param("flag&limit=5", "flag").as_deref(), Some(""));
+        assert_eq!(query_param("limit=5", "prefix"), None);
+        
+        let mut request = CanonicalRequest::new("GET", "/v1/photos").with_query("limit=10&prefix=cat");
+        assert!(request.build().contains("limit=10&prefix=cat"));
+    }
+    
     #[test]
     fn test_frame_validation() {
         // Valid frame
@@ -0,0 +1,171 @@
+//! End-to-end request latency over HTTP/2 with authentication on
+//!
+//! The other benches call the engine or the auth crate directly; this one
+//! starts the real server binary with a root key, then times signed PUT
+//! and GET round-trips from a client speaking HTTP/2 over loopback, so the
+//! figures include framing, the auth middleware, routing, and storage.
+//! Percentiles are reported per payload size against the 10ms p95 target.
+//!
+//! Run with `cargo bench -p wfldb-server --bench e2e_http2`; set
+//! `WFLDB_E2E_ITERATIONS` to change the number of timed round-trips per size.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use wfldb_auth::*;
+
+const PAYLOAD_SIZES: &[usize] = &[1024, 16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024];
+const DEFAULT_ITERATIONS: usize = 500;
+const WARMUP_ITERATIONS: usize = 50;
+const P95_TARGET: Duration = Duration::from_millis(10);
+
+/// Server process, killed when dropped so a failed run doesn't leave it behind
+struct ServerProcess {
+    child: Child,
+    addr: SocketAddr,
+    _data_dir: tempfile::TempDir,
+}
+
+impl ServerProcess {
+    /// Start the server with request authentication rooted at `root_key`,
+    /// a base64url Ed25519 public key
+    fn start(root_key: &str) -> ServerProcess {
+        let data_dir = tempfile::tempdir().expect("create data directory");
+        // Take a free port from the OS; the server binds it right after
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_wfldb-server"))
+            .arg("--data-dir").arg(data_dir.path())
+            .arg("--bind").arg(addr.to_string())
+            .arg("--auth-root-key").arg(root_key)
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start wfldb-server");
+        ServerProcess { child, addr, _data_dir: data_dir }
+    }
+
+    async fn wait_until_live(&mut self, client: &Client<HttpConnector>) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let uri = format!("http://{}/livez", self.addr);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("server exited during startup: {}", status);
+            }
+            if let Ok(response) = client.get(uri.parse().unwrap()).await {
+                if response.status() == StatusCode::OK {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server at {} did not come up", self.addr);
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Send one signed request and read the whole response
+async fn round_trip(
+    client: &Client<HttpConnector>,
+    signer: &RequestSigner,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    body: &[u8],
+) -> (StatusCode, usize) {
+    let mut request = Request::builder()
+        .method(method.clone())
+        .uri(format!("http://{}{}", addr, path));
+    for (name, value) in signer.sign(method.as_str(), path, body, now_ms()) {
+        request = request.header(name, value);
+    }
+    let response = client
+        .request(request.body(Body::from(body.to_vec())).unwrap())
+        .await
+        .expect("request failed");
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.expect("read response body");
+    (status, body.len())
+}
+
+/// Sample at percentile `p` (0 to 100) of sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[index.clamp(1, sorted.len()) - 1]
+}
+
+fn report(name: &str, samples: &mut [Duration]) {
+    samples.sort();
+    let p95 = percentile(samples, 95.0);
+    let verdict = if p95 <= P95_TARGET { "ok" } else { "OVER TARGET" };
+    println!(
+        "{:<10} p50 {:>10.3?}  p95 {:>10.3?}  p99 {:>10.3?}  p99.9 {:>10.3?}  [{} vs {:?}]",
+        name,
+        percentile(samples, 50.0),
+        p95,
+        percentile(samples, 99.0),
+        percentile(samples, 99.9),
+        verdict,
+        P95_TARGET,
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let iterations = std::env::var("WFLDB_E2E_ITERATIONS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_ITERATIONS);
+
+    let root = generate_signing_key();
+    let holder = generate_signing_key();
+    let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, u32::MAX as u64)
+        .expect("issue key packet");
+    let signer = RequestSigner::new(holder, packet);
+
+    let mut server = ServerProcess::start(&encode_public_key(&root.verifying_key()));
+    // Prior-knowledge HTTP/2 over one connection, as the client library's
+    // long-lived connections see it
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    server.wait_until_live(&client).await;
+
+    println!("signed round-trips over HTTP/2, {} per size", iterations);
+    for &size in PAYLOAD_SIZES {
+        let data = vec![0x5au8; size];
+        let path = format!("/v1/bench-e2e/object-{}", size);
+        let mut puts = Vec::with_capacity(iterations);
+        let mut gets = Vec::with_capacity(iterations);
+        for i in 0..WARMUP_ITERATIONS + iterations {
+            let start = Instant::now();
+            let (status, _) = round_trip(&client, &signer, server.addr, Method::PUT, &path, &data).await;
+            let put_elapsed = start.elapsed();
+            assert_eq!(status, StatusCode::CREATED, "PUT {} failed", path);
+
+            let start = Instant::now();
+            let (status, read) = round_trip(&client, &signer, server.addr, Method::GET, &path, b"").await;
+            let get_elapsed = start.elapsed();
+            assert_eq!((status, read), (StatusCode::OK, size), "GET {} failed", path);
+
+            if i >= WARMUP_ITERATIONS {
+                puts.push(put_elapsed);
+                gets.push(get_elapsed);
+            }
+        }
+        report(&format!("PUT {}", human_size(size)), &mut puts);
+        report(&format!("GET {}", human_size(size)), &mut gets);
+    }
+}
+
+fn human_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{}MB", b / (1024 * 1024)),
+        b => format!("{}KB", b / 1024),
+    }
+}
@@ -0,0 +1,1097 @@
+//! Admin API for authority operations
+//!
+//! ```text
+//! POST   /admin/proposals                  {"action": {...}}   (admin key packet)
+//! GET    /admin/proposals/{id}
+//! POST   /admin/proposals/{id}/signatures  {"signer": "<key id>", "signature": "<base64url>"}
+//! GET    /admin/principals                                      (admin key packet)
+//! GET    /admin/principals/{name}                               (admin key packet)
+//! PUT    /admin/principals/{name}          {"keys": [...], "permissions": {...}}
+//! DELETE /admin/principals/{name}                               (admin key packet)
+//! GET    /admin/usage[?stale_days=N]                            (admin key packet)
+//! GET    /admin/usage/{key_id}                                  (admin key packet)
+//! GET    /admin/buckets                                         (admin key packet)
+//! GET    /admin/buckets/{bucket}/acl                            (admin key packet)
+//! PUT    /admin/buckets/{bucket}/acl       {"acl": "owner_only" | "bucket_writers" | "public_read"}
+//! GET    /admin/buckets/{bucket}/schema                         (admin key packet)
+//! PUT    /admin/buckets/{bucket}/schema    <JSON Schema>
+//! DELETE /admin/buckets/{bucket}/schema                         (admin key packet)
+//! POST   /admin/buckets/{bucket}/clone     {"target": "<empty bucket>"}
+//! PUT    /admin/buckets/{bucket}/freeze                         (admin key packet)
+//! DELETE /admin/buckets/{bucket}/freeze                         (admin key packet)
+//! GET    /admin/journal/head                                    (admin key packet)
+//! GET    /admin/journal?after=N[&bucket=B][&limit=L]            (admin key packet)
+//! GET    /admin/membership                                      (admin key packet)
+//! GET    /admin/v1/cluster                                      (admin key packet)
+//! GET    /admin/merkle[?bucket=B[&level=L&nodes=i,j|&leaves=i,j]] (admin key packet)
+//! GET    /admin/merkle/object?bucket=B&key=K                    (admin key packet)
+//! GET    /admin/uploads                                         (admin key packet)
+//! GET    /admin/identity                                        (admin key packet)
+//! GET    /admin/audit/export?from_ms=N&to_ms=M                  (admin key packet)
+//! GET    /admin/tasks                                           (admin key packet)
+//! PUT    /admin/tasks/{name}/pause                              (admin key packet)
+//! DELETE /admin/tasks/{name}/pause                              (admin key packet)
+//! PUT    /admin/tasks/{name}/throttle      {"io_bytes_per_sec": N | null, "cpu_percent": 1-100 | null}
+//! POST   /admin/tasks/{name}/run                                (admin key packet)
+//! GET    /admin/refcounts                                       (admin key packet)
+//! POST   /admin/refcounts[?repair=true]                         (admin key packet)
+//! GET    /admin/chaos                                           (admin key packet)
+//! PUT    /admin/chaos  {"storage_latency_ms": N, "write_failure_percent": 0-100, "drop_connection_percent": 0-100}
+//! DELETE /admin/chaos                                           (admin key packet)
+//! GET    /admin/config                                          (admin key packet)
+//! PATCH  /admin/config  {"log_level": "...", "bandwidth": [...], "max_background_tasks": N, ...}
+//! GET    /admin/quarantine                                      (admin key packet)
+//! POST   /admin/quarantine/release         {"bucket": "<bucket>", "key": "<key>"}
+//! POST   /admin/quarantine/reject          {"bucket": "<bucket>", "key": "<key>"}
+//! ```
+//!
+//! The schema routes attach a JSON Schema that JSON documents put in the
+//! bucket must match (see [`crate::schema`]); a schema that doesn't compile
+//! is refused with 400, and documents already stored aren't re-checked.
+//!
+//! The journal route streams change records after sequence `after` as
+//! concatenated frames (see [`wfldb_core::ChangeRecord::encode_frame`]), with
+//! the cursor for the next call in `x-wfldb-journal-next` and the journal's
+//! head in `x-wfldb-journal-head`, so a follower can tell how far behind it
+//! is. It answers 410 once
+//! the requested records have been archived away.
+//!
+//! The membership route lists the peers this node knows, with each one's
+//! state (`alive`, `suspect` or `dead`), role and journal position (see
+//! [`crate::membership`]).
+//!
+//! The cluster route is the operator's overview of this node and the
+//! cluster it knows: node roles and journal positions with each member's
+//! lag behind the newest leader position (and, for replicas, staleness and
+//! whether they serve reads), replication and anti-entropy
+//! progress, storage use, background task and request queue backlog, and
+//! the /v1 service level objectives (see [`crate::slo`]).
+//!
+//! The merkle routes serve the trees replicas compare their buckets with
+//! (see [`crate::anti_entropy`]); buckets are named as in journal records,
+//! and the object route returns a key's data with its version in
+//! `x-wfldb-version`.
+//!
+//! The uploads route lists the multipart uploads in progress in every
+//! namespace, oldest first, with their parts (see [`crate::multipart`]).
+//!
+//! The identity route returns the server identity public key that signs
+//! audit exports, to be kept by whoever verifies them. The audit export
+//! route exists only on servers started with `--audit-log`; it answers the
+//! signed JSON Lines extract of the events from `from_ms` up to `to_ms`
+//! (default: now), see [`crate::audit`].
+//!
+//! Posting to the refcounts route starts a chunk reference count pass on
+//! the background task scheduler; its report is read back with the GET once
+//! the `refcount_check` task finishes. With `repair=true` the pass writes the
+//! recomputed counts and removes orphaned chunks, which is only safe while
+//! no large objects are being written.
+//!
+//! The chaos routes exist only on servers started with `--enable-chaos`;
+//! PUT replaces every fault setting at once, fields left out switching
+//! that fault off, and DELETE switches them all off (see [`crate::chaos`]).
+//!
+//! The config routes read and change the settings that can change while
+//! the server runs (see [`crate::reconfig`]); PATCH applies the fields it
+//! is sent, all of them or, with 400, none, and answers the settings now in
+//! effect.
+//!
+//! The quarantine routes exist only on servers started with
+//! `--quarantine-hook`. The GET lists held objects in every namespace,
+//! oldest first, with failed scan attempts; release and reject give the
+//! verdict by hand, reject deleting the object, for objects the hook keeps
+//! failing on. Buckets are named as in journal records (see
+//! [`crate::quarantine`]).
+//!
+//! Submitting proposal signatures needs no key packet: the signature over
+//! the proposal message is itself the credential. Their bodies are capped
+//! at [`MAX_SIGNATURE_BODY_BYTES`], and every other admin body at
+//! [`MAX_ADMIN_BODY_BYTES`]; past that the route answers 413.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use wfldb_auth::{
+    encode_public_key, headers, now_ms, AdminAction, AuthContext, AuthError, Authenticator, BucketAcl, KeyId,
+    Permissions, Principal,
+};
+use wfldb_core::{BucketId, HybridClock, Key};
+use wfldb_engine::{list_multipart_uploads, list_quarantine, reject_quarantined, release_quarantined};
+use wfldb_engine::{KeyUsage, Storage, StorageEngine};
+use wfldb_net::query_param;
+use crate::anti_entropy::merkle_json;
+use crate::error::ApiError;
+use crate::{audit, auth, sse};
+use crate::chaos::ChaosSettings;
+use crate::schema::BucketSchemas;
+use crate::health::free_disk_bytes;
+use crate::membership::{self, NodeRole};
+use crate::multipart::upload_json;
+use crate::reconfig::RuntimeSettings;
+use crate::replica;
+use crate::router::json_response;
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+use crate::spool::{Spool, SpooledBody};
+use crate::tasks::{RefcountCheck, Throttle};
+
+/// Response header carrying the journal cursor to resume from
+pub const JOURNAL_NEXT_HEADER: &str = "x-wfldb-journal-next";
+
+/// Response header carrying the last sequence in the journal
+pub const JOURNAL_HEAD_HEADER: &str = "x-wfldb-journal-head";
+
+/// Most change records returned by one journal request
+const MAX_JOURNAL_RECORDS: usize = 1000;
+
+/// Largest proposal signature body, `{"signer": ..., "signature": ...}`
+pub const MAX_SIGNATURE_BODY_BYTES: usize = 16 * 1024;
+
+/// Largest body of an authenticated admin request, enough for a bucket schema
+pub const MAX_ADMIN_BODY_BYTES: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct ProposeRequest {
+    action: AdminAction,
+}
+
+#[derive(Deserialize)]
+struct SignRequest {
+    signer: KeyId,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct BucketAclRequest {
+    acl: BucketAcl,
+}
+
+#[derive(Deserialize)]
+struct CloneBucketRequest {
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct PrincipalRequest {
+    keys: Vec<String>,
+    #[serde(default)]
+    permissions: Permissions,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// Handle a request under /admin/
+pub async fn handle(req: Request<Body>, state: &ServerState, timings: &mut RequestTimings) -> Response<Body> {
+    let authenticator = match &state.auth {
+        Some(auth) => auth,
+        None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Not found"})),
+    };
+    let method = req.method().clone();
+    let segments: Vec<String> = req
+        .uri()
+        .path()
+        .trim_start_matches("/admin/")
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (&method, segments.as_slice()) {
+        (&Method::POST, ["proposals"]) => {
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let request: ProposeRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            match state.proposals.propose(request.action, now_ms()) {
+                Ok(proposal) => {
+                    info!("Admin proposal {} created by {}", proposal.id, ctx.display_name());
+                    let policy = authenticator.authority().root_policy();
+                    json_response(
+                        StatusCode::CREATED,
+                        json!({
+                            "proposal": proposal,
+                            "message": proposal.message(),
+                            "threshold": policy.threshold,
+                        }),
+                    )
+                }
+                Err(e) => json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["proposals", id]) => match state.proposals.get(id, now_ms()) {
+            Some(proposal) => json_response(
+                StatusCode::OK,
+                json!({
+                    "proposal": proposal,
+                    "message": proposal.message(),
+                    "threshold": authenticator.authority().root_policy().threshold,
+                }),
+            ),
+            None => json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown proposal"})),
+        },
+
+        (&Method::POST, ["proposals", id, "signatures"]) => {
+            let body = match state.spool.read(req.into_body(), MAX_SIGNATURE_BODY_BYTES).await {
+                Ok(body) => body,
+                Err(e) => return e.into_response(),
+            };
+            let request: SignRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let result = timings.time(Phase::Auth, || {
+                state.proposals.sign(
+                    id,
+                    &request.signer,
+                    &request.signature,
+                    authenticator.authority(),
+                    now_ms(),
+                )
+            });
+            match result {
+                Ok(status) => {
+                    if status.executed {
+                        info!("Admin proposal {} reached quorum and was applied", id);
+                    }
+                    json_response(StatusCode::OK, json!(status))
+                }
+                Err(e @ AuthError::UnknownProposal(_)) => {
+                    json_response(StatusCode::NOT_FOUND, json!({"error": e.to_string()}))
+                }
+                Err(e @ AuthError::Storage(_)) => {
+                    warn!("Failed to apply admin proposal {}: {}", id, e);
+                    json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}))
+                }
+                Err(e) => auth::error_response(&e),
+            }
+        }
+
+        (&Method::GET, ["principals"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            json_response(StatusCode::OK, json!({ "principals": authenticator.principals().list() }))
+        }
+
+        (&Method::GET, ["principals", name]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            match authenticator.principals().get(name) {
+                Some(principal) => json_response(StatusCode::OK, json!(principal)),
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown principal"})),
+            }
+        }
+
+        (&Method::PUT, ["principals", name]) => {
+            let name = name.to_string();
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let request: PrincipalRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let principal = Principal {
+                name,
+                keys: request.keys,
+                permissions: request.permissions,
+                description: request.description,
+            };
+            match authenticator.principals().upsert(principal.clone()) {
+                Ok(()) => {
+                    info!("Principal '{}' updated by {}", principal.name, ctx.display_name());
+                    json_response(StatusCode::OK, json!(principal))
+                }
+                Err(e @ AuthError::Storage(_)) => {
+                    json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()}))
+                }
+                Err(e) => json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::DELETE, ["principals", name]) => {
+            let name = name.to_string();
+            let ctx = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok((ctx, _)) => ctx,
+                Err(response) => return response,
+            };
+            match authenticator.principals().remove(&name) {
+                Ok(Some(_)) => {
+                    info!("Principal '{}' removed by {}", name, ctx.display_name());
+                    json_response(StatusCode::OK, json!({"name": name, "deleted": true}))
+                }
+                Ok(None) => json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown principal"})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["usage"]) => {
+            let stale_days: Option<u64> = req
+                .uri()
+                .query()
+                .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("stale_days=")))
+                .and_then(|v| v.parse().ok());
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let usage = match &state.usage {
+                Some(usage) => usage,
+                None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Usage tracking disabled"})),
+            };
+            let cutoff_ms = stale_days.map(|days| now_ms().saturating_sub(days * 24 * 60 * 60 * 1000));
+            match usage.list() {
+                Ok(entries) => {
+                    let keys: Vec<Value> = entries
+                        .into_iter()
+                        .filter(|(_, usage)| cutoff_ms.is_none_or(|cutoff| usage.last_used_ms < cutoff))
+                        .map(|(key_id, usage)| usage_json(authenticator, &key_id, &usage))
+                        .collect();
+                    json_response(StatusCode::OK, json!({ "keys": keys }))
+                }
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["usage", key_id]) => {
+            let key_id = key_id.to_string();
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let usage = match &state.usage {
+                Some(usage) => usage,
+                None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Usage tracking disabled"})),
+            };
+            match usage.get(&key_id) {
+                Ok(Some(usage)) => json_response(StatusCode::OK, usage_json(authenticator, &key_id, &usage)),
+                Ok(None) => json_response(StatusCode::NOT_FOUND, json!({"error": "No usage recorded for key"})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["buckets"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let buckets: Vec<Value> = authenticator
+                .bucket_acls()
+                .list()
+                .into_iter()
+                .map(|(bucket, acl)| json!({"bucket": bucket, "acl": acl}))
+                .collect();
+            json_response(StatusCode::OK, json!({ "buckets": buckets }))
+        }
+
+        (&Method::GET, ["buckets", bucket, "acl"]) => {
+            let bucket = match BucketId::new(bucket) {
+                Ok(bucket) => bucket,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let acl = authenticator.bucket_acls().get(&bucket);
+            json_response(StatusCode::OK, json!({"bucket": bucket.as_str(), "acl": acl}))
+        }
+
+        (&Method::PUT, ["buckets", bucket, "acl"]) => {
+            let bucket = match BucketId::new(bucket) {
+                Ok(bucket) => bucket,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let request: BucketAclRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            match authenticator.bucket_acls().set(&bucket, request.acl) {
+                Ok(()) => {
+                    info!("Bucket '{}' ACL set to {:?} by {}", bucket.as_str(), request.acl, ctx.display_name());
+                    json_response(StatusCode::OK, json!({"bucket": bucket.as_str(), "acl": request.acl}))
+                }
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET | &Method::PUT | &Method::DELETE, ["buckets", bucket, "schema"]) => {
+            let bucket = match BucketId::new(bucket) {
+                Ok(bucket) => bucket,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let schema = match method {
+                Method::GET => {
+                    return match state.storage.bucket_schema(&bucket) {
+                        Ok(Some(schema)) => json_response(StatusCode::OK, json!({"bucket": bucket.as_str(), "schema": schema})),
+                        Ok(None) => json_response(StatusCode::NOT_FOUND, json!({"error": "Bucket has no schema"})),
+                        Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                    };
+                }
+                Method::PUT => {
+                    let schema: Value = match serde_json::from_slice(&body) {
+                        Ok(schema) => schema,
+                        Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+                    };
+                    if let Err(e) = BucketSchemas::compile(&schema) {
+                        return json_response(StatusCode::BAD_REQUEST, json!({"error": e}));
+                    }
+                    Some(schema)
+                }
+                _ => None,
+            };
+            let result = state.storage.set_bucket_schema(&bucket, schema.as_ref());
+            state.schemas.forget(bucket.as_str());
+            match result {
+                Ok(()) => {
+                    let action = if schema.is_some() { "set" } else { "removed" };
+                    info!("Bucket '{}' schema {} by {}", bucket.as_str(), action, ctx.display_name());
+                    json_response(StatusCode::OK, json!({"bucket": bucket.as_str(), "schema": schema}))
+                }
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::POST, ["buckets", source, "clone"]) => {
+            let source = match BucketId::new(source) {
+                Ok(source) => source,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let target = match serde_json::from_slice::<CloneBucketRequest>(&body)
+                .map_err(|e| e.to_string())
+                .and_then(|request| BucketId::new(&request.target).map_err(|e| e.to_string()))
+            {
+                Ok(target) => target,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e})),
+            };
+            if target == source {
+                return json_response(StatusCode::BAD_REQUEST, json!({"error": "cannot clone a bucket onto itself"}));
+            }
+
+            let storage = state.objects.clone();
+            match storage.list_objects(&target, "", Some(1)) {
+                Ok(keys) if keys.is_empty() => {}
+                Ok(_) => return json_response(StatusCode::CONFLICT, json!({"error": "target bucket is not empty"})),
+                Err(e) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+            let (from, to) = (source.clone(), target.clone());
+            // Cloning touches every object in the bucket, so keep it off the async workers
+            let started = std::time::Instant::now();
+            let cloned = tokio::task::spawn_blocking(move || storage.clone_bucket(&from, &to)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match cloned {
+                Ok(Ok(objects)) => {
+                    info!(
+                        "Bucket '{}' cloned to '{}' ({} objects) by {}",
+                        source.as_str(), target.as_str(), objects, ctx.display_name()
+                    );
+                    json_response(
+                        StatusCode::CREATED,
+                        json!({"source": source.as_str(), "target": target.as_str(), "objects": objects}),
+                    )
+                }
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::PUT | &Method::DELETE, ["buckets", bucket, "freeze"]) => {
+            let bucket = match BucketId::new(bucket) {
+                Ok(bucket) => bucket,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let ctx = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok((ctx, _)) => ctx,
+                Err(response) => return response,
+            };
+            let frozen = method == Method::PUT;
+            let mut frozen_buckets = state.frozen_buckets.write().unwrap();
+            if frozen {
+                frozen_buckets.insert(bucket.clone());
+            } else {
+                frozen_buckets.remove(&bucket);
+            }
+            info!(
+                "Bucket '{}' {} by {}",
+                bucket.as_str(),
+                if frozen { "frozen" } else { "unfrozen" },
+                ctx.display_name()
+            );
+            json_response(StatusCode::OK, json!({"bucket": bucket.as_str(), "frozen": frozen}))
+        }
+
+        (&Method::GET, ["identity"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            match &state.identity {
+                Some(identity) => json_response(
+                    StatusCode::OK,
+                    json!({"key_id": identity.key_id(), "public_key": encode_public_key(&identity.public_key())}),
+                ),
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "Server has no identity key"})),
+            }
+        }
+
+        (&Method::GET, ["audit", "export"]) => {
+            let query = req.uri().query().unwrap_or_default().to_string();
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let (Some(log), Some(identity)) = (state.audit.clone(), state.identity.clone()) else {
+                return json_response(StatusCode::NOT_FOUND, json!({"error": "Audit log disabled"}));
+            };
+            let time = |name, default| match query_param(&query, name) {
+                Some(value) => value.parse().map_err(|_| format!("Invalid {}", name)),
+                None => Ok(default),
+            };
+            let (from_ms, to_ms) = match (time("from_ms", 0), time("to_ms", now_ms())) {
+                (Ok(from_ms), Ok(to_ms)) if from_ms <= to_ms => (from_ms, to_ms),
+                (Err(e), _) | (_, Err(e)) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e})),
+                _ => return json_response(StatusCode::BAD_REQUEST, json!({"error": "from_ms is after to_ms"})),
+            };
+            let started = std::time::Instant::now();
+            let exported = tokio::task::spawn_blocking(move || audit::export(&log, &identity, from_ms, to_ms)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match exported {
+                Ok(Ok(extract)) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from(extract))
+                    .unwrap(),
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["uploads"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let engine = state.storage.clone();
+            let started = std::time::Instant::now();
+            let listed = tokio::task::spawn_blocking(move || list_multipart_uploads(&engine)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match listed {
+                Ok(Ok(uploads)) => {
+                    let uploads: Vec<Value> = uploads
+                        .iter()
+                        .map(|(bucket, upload)| {
+                            let mut entry = upload_json(upload);
+                            entry["bucket"] = json!(bucket);
+                            entry
+                        })
+                        .collect();
+                    json_response(StatusCode::OK, json!({"uploads": uploads}))
+                }
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["tasks"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            json_response(StatusCode::OK, json!({"tasks": state.tasks.status()}))
+        }
+
+        (&Method::PUT | &Method::DELETE, ["tasks", name, "pause"]) => {
+            let name = name.to_string();
+            let ctx = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok((ctx, _)) => ctx,
+                Err(response) => return response,
+            };
+            let paused = method == Method::PUT;
+            match state.tasks.set_paused(&name, paused) {
+                Some(status) => {
+                    info!(
+                        "Background task '{}' {} by {}",
+                        name,
+                        if paused { "paused" } else { "resumed" },
+                        ctx.display_name()
+                    );
+                    json_response(StatusCode::OK, json!(status))
+                }
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown task"})),
+            }
+        }
+
+        (&Method::PUT, ["tasks", name, "throttle"]) => {
+            let name = name.to_string();
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let throttle: Throttle = match serde_json::from_slice(&body) {
+                Ok(throttle) => throttle,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            if let Err(e) = throttle.validate() {
+                return json_response(StatusCode::BAD_REQUEST, json!({"error": e}));
+            }
+            match state.tasks.set_throttle(&name, throttle) {
+                Some(status) => {
+                    info!("Background task '{}' throttle set to {:?} by {}", name, throttle, ctx.display_name());
+                    json_response(StatusCode::OK, json!(status))
+                }
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown task"})),
+            }
+        }
+
+        (&Method::POST, ["tasks", name, "run"]) => {
+            let name = name.to_string();
+            let ctx = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok((ctx, _)) => ctx,
+                Err(response) => return response,
+            };
+            if state.tasks.run_now(&name) {
+                info!("Background task '{}' triggered by {}", name, ctx.display_name());
+                json_response(StatusCode::ACCEPTED, json!({"task": name, "triggered": true}))
+            } else {
+                json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown task"}))
+            }
+        }
+
+        (&Method::GET | &Method::PUT | &Method::DELETE, ["chaos"]) => {
+            let Some(chaos) = &state.chaos else {
+                return json_response(StatusCode::NOT_FOUND, json!({"error": "Fault injection is disabled"}));
+            };
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            let settings = match method {
+                Method::GET => return json_response(StatusCode::OK, json!(chaos.settings())),
+                Method::PUT => match serde_json::from_slice::<ChaosSettings>(&body) {
+                    Ok(settings) => settings,
+                    Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+                },
+                _ => ChaosSettings::default(),
+            };
+            if let Err(e) = settings.validate() {
+                return json_response(StatusCode::BAD_REQUEST, json!({"error": e}));
+            }
+            chaos.set(settings);
+            warn!("Fault injection set to {:?} by {}", settings, ctx.display_name());
+            json_response(StatusCode::OK, json!(settings))
+        }
+
+        (&Method::GET | &Method::PATCH, ["config"]) => {
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            if method == Method::PATCH {
+                let settings: RuntimeSettings = match serde_json::from_slice(&body) {
+                    Ok(settings) => settings,
+                    Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+                };
+                if let Err(e) = settings.apply(state, &ctx.display_name()) {
+                    return json_response(StatusCode::BAD_REQUEST, json!({"error": e}));
+                }
+            }
+            json_response(StatusCode::OK, json!(RuntimeSettings::current(state)))
+        }
+
+        (&Method::GET, ["quarantine"]) => {
+            let Some(stats) = &state.quarantine else {
+                return json_response(StatusCode::NOT_FOUND, json!({"error": "Quarantine is disabled"}));
+            };
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let engine = state.storage.clone();
+            let started = std::time::Instant::now();
+            let listed = tokio::task::spawn_blocking(move || list_quarantine(&engine)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match listed {
+                Ok(Ok(entries)) => {
+                    let held: Vec<Value> = entries
+                        .iter()
+                        .map(|entry| json!({
+                            "bucket": entry.bucket,
+                            "key": entry.key,
+                            "held_at_ms": entry.held_at_ms,
+                            "version": entry.version.as_ref().map(|v| v.to_string()),
+                            "attempts": entry.attempts,
+                            "last_error": entry.last_error,
+                        }))
+                        .collect();
+                    json_response(StatusCode::OK, json!({
+                        "held": held,
+                        "approved": stats.approved(),
+                        "rejected": stats.rejected(),
+                        "scan_failures": stats.failures(),
+                    }))
+                }
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::POST, ["quarantine", verdict @ ("release" | "reject")]) => {
+            if state.quarantine.is_none() {
+                return json_response(StatusCode::NOT_FOUND, json!({"error": "Quarantine is disabled"}));
+            }
+            let (ctx, body) = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok(parts) => parts,
+                Err(response) => return response,
+            };
+            #[derive(Deserialize)]
+            struct HeldObject {
+                bucket: String,
+                key: String,
+            }
+            let held: HeldObject = match serde_json::from_slice(&body) {
+                Ok(held) => held,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+            };
+            let (engine, release) = (state.storage.clone(), *verdict == "release");
+            let started = std::time::Instant::now();
+            let (bucket, key) = (held.bucket.clone(), held.key.clone());
+            let decided = tokio::task::spawn_blocking(move || {
+                let (scoped, bucket_id) = engine.resolve_namespaced(&bucket)?;
+                let entry = Storage::new(scoped).quarantine_entry(&bucket_id, &Key::new(&key)?)?;
+                match entry {
+                    // Still being written; there is nothing to judge yet
+                    Some(entry) if entry.version.is_some() => match release {
+                        true => release_quarantined(&engine, &entry).map(Some),
+                        false => reject_quarantined(&engine, &entry).map(Some),
+                    },
+                    Some(_) => Ok(Some(false)),
+                    None => Ok(None),
+                }
+            })
+            .await;
+            timings.record(Phase::Storage, started.elapsed());
+            match decided {
+                Ok(Ok(Some(true))) => {
+                    let outcome = if release { "released" } else { "rejected" };
+                    warn!("Quarantined {}/{} {} by {}", held.bucket, held.key, outcome, ctx.display_name());
+                    json_response(StatusCode::OK, json!({"bucket": held.bucket, "key": held.key, "outcome": outcome}))
+                }
+                Ok(Ok(Some(false))) => {
+                    json_response(StatusCode::CONFLICT, json!({"error": "Object is being written; retry once it settles"}))
+                }
+                Ok(Ok(None)) => json_response(StatusCode::NOT_FOUND, json!({"error": "Object is not held"})),
+                Ok(Err(e)) => json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["refcounts"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            match state.refcounts.last_report() {
+                Some(report) => json_response(StatusCode::OK, json!(report)),
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "No refcount pass has finished yet"})),
+            }
+        }
+
+        (&Method::POST, ["refcounts"]) => {
+            let repair = req.uri().query().and_then(|q| query_param(q, "repair")).as_deref() == Some("true");
+            let ctx = match admin_request(req, authenticator, &state.spool, timings).await {
+                Ok((ctx, _)) => ctx,
+                Err(response) => return response,
+            };
+            if state.tasks.is_paused(RefcountCheck::NAME) == Some(true) {
+                return json_response(StatusCode::CONFLICT, json!({"error": "refcount_check task is paused"}));
+            }
+            if repair {
+                state.refcounts.request_repair();
+            }
+            state.tasks.run_now(RefcountCheck::NAME);
+            info!("Refcount {} started by {}", if repair { "rebuild" } else { "check" }, ctx.display_name());
+            json_response(StatusCode::ACCEPTED, json!({"task": RefcountCheck::NAME, "repair": repair}))
+        }
+
+        (&Method::GET, ["journal", "head"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let journal = match state.storage.journal() {
+                Some(journal) => journal,
+                None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Change journal disabled"})),
+            };
+            match journal.oldest_seq() {
+                Ok(oldest) => json_response(StatusCode::OK, json!({"head": journal.next_seq() - 1, "oldest": oldest})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["journal"]) => {
+            let query = req.uri().query().unwrap_or("").to_string();
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let journal = match state.storage.journal() {
+                Some(journal) => journal.clone(),
+                None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Change journal disabled"})),
+            };
+            let after = query_param(&query, "after").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            let limit = query_param(&query, "limit")
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(MAX_JOURNAL_RECORDS)
+                .min(MAX_JOURNAL_RECORDS);
+            let bucket = query_param(&query, "bucket");
+            match journal.oldest_seq() {
+                Ok(oldest) if after + 1 < oldest => {
+                    return json_response(
+                        StatusCode::GONE,
+                        json!({"error": "records after this cursor were archived", "oldest": oldest}),
+                    )
+                }
+                Ok(_) => {}
+                Err(e) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+
+            let head = journal.next_seq() - 1;
+            let started = std::time::Instant::now();
+            let read = tokio::task::spawn_blocking(move || journal.read_after(after, bucket.as_deref(), limit)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match read {
+                Ok(Ok((records, next))) => {
+                    let body: Vec<u8> = records.iter().flat_map(|record| record.encode_frame()).collect();
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header("content-type", "application/octet-stream")
+                        .header(JOURNAL_NEXT_HEADER, next.to_string())
+                        .header(JOURNAL_HEAD_HEADER, head.to_string())
+                        .body(Body::from(body))
+                        .unwrap()
+                }
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["membership"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            match &state.cluster {
+                Some(cluster) => json_response(
+                    StatusCode::OK,
+                    json!({"self": cluster.self_url(), "leaders": cluster.leaders(), "members": cluster.members()}),
+                ),
+                None => json_response(StatusCode::NOT_FOUND, json!({"error": "Cluster membership disabled"})),
+            }
+        }
+
+        (&Method::GET, ["v1", "cluster"]) => {
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let engine = state.storage.clone();
+            let started = std::time::Instant::now();
+            let storage = tokio::task::spawn_blocking(move || storage_json(&engine)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match storage {
+                Ok(Ok(storage)) => json_response(StatusCode::OK, cluster_json(state, storage)),
+                Ok(Err(e)) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["merkle"]) => {
+            let query = req.uri().query().unwrap_or("").to_string();
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let (cache, storage) = (state.merkle.clone(), state.storage.clone());
+            let started = std::time::Instant::now();
+            let answer = tokio::task::spawn_blocking(move || merkle_json(&cache, &storage, &query)).await;
+            timings.record(Phase::Storage, started.elapsed());
+            match answer {
+                Ok(Ok(body)) => json_response(StatusCode::OK, body),
+                Ok(Err((status, error))) => json_response(status, json!({"error": error})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        (&Method::GET, ["merkle", "object"]) => {
+            let query = req.uri().query().unwrap_or("").to_string();
+            if let Err(response) = admin_request(req, authenticator, &state.spool, timings).await {
+                return response;
+            }
+            let (Some(bucket), Some(key)) = (query_param(&query, "bucket"), query_param(&query, "key")) else {
+                return json_response(StatusCode::BAD_REQUEST, json!({"error": "bucket and key are required"}));
+            };
+            let storage = state.storage.clone();
+            let started = std::time::Instant::now();
+            let read = tokio::task::spawn_blocking(move || {
+                let (engine, bucket_id) = storage.resolve_namespaced(&bucket)?;
+                Storage::new(engine).get_versioned(&bucket_id, &Key::new(&key)?)
+            })
+            .await;
+            timings.record(Phase::Storage, started.elapsed());
+            match read {
+                Ok(Ok(Some((data, metadata)))) => {
+                    let mut response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header("content-type", "application/octet-stream")
+                        .header(headers::VERSION, metadata.version.to_string())
+                        .body(Body::from(data))
+                        .unwrap();
+                    // The stored bytes, encrypted or not, with what marks them encrypted
+                    if let Some(customer_key_hash) = &metadata.customer_key_hash {
+                        sse::add_headers(&mut response, customer_key_hash);
+                    }
+                    response
+                }
+                Ok(Ok(None)) => json_response(StatusCode::NOT_FOUND, json!({"error": "Object not found"})),
+                Ok(Err(e)) => json_response(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+                Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+            }
+        }
+
+        _ => json_response(StatusCode::NOT_FOUND, json!({"error": "Not found"})),
+    }
+}
+
+fn usage_json(authenticator: &Authenticator, key_id: &str, usage: &KeyUsage) -> Value {
+    let principal = KeyId::from_hex(key_id)
+        .ok()
+        .and_then(|id| authenticator.principals().name_for(&id));
+    json!({
+        "key_id": key_id,
+        "principal": principal,
+        "revoked": KeyId::from_hex(key_id).is_ok_and(|id| authenticator.authority().is_revoked(&id)),
+        "usage": usage,
+    })
+}
+
+/// Bucket sizes across every namespace, and the data directory's free space
+fn storage_json(engine: &StorageEngine) -> wfldb_core::Result<Value> {
+    let (mut buckets, mut entries, mut disk_bytes) = (0, 0, 0);
+    for namespace in engine.namespaces()? {
+        for summary in namespace.bucket_summaries()? {
+            buckets += 1;
+            entries += summary.approximate_entries;
+            disk_bytes += summary.disk_bytes;
+        }
+    }
+    Ok(json!({
+        "buckets": buckets,
+        "approximate_entries": entries,
+        "disk_bytes": disk_bytes,
+        "free_disk_bytes": free_disk_bytes(engine.path()).ok(),
+    }))
+}
+
+fn cluster_json(state: &ServerState, storage: Value) -> Value {
+    let now = now_ms();
+    let role = NodeRole::of(state);
+    let applied_seq = replica::applied_seq(state);
+    let members = state.cluster.as_ref().map(|cluster| cluster.members()).unwrap_or_default();
+
+    let leader_seq = membership::leader_seq(state, &members);
+    let lag = |seq: Option<u64>| leader_seq.zip(seq).map(|(leader, seq)| leader.saturating_sub(seq));
+    let members: Vec<Value> = members
+        .iter()
+        .map(|member| {
+            let mut entry = json!(member);
+            entry["lag_seq"] = json!(lag(member.applied_seq));
+            entry
+        })
+        .collect();
+
+    let replication = match &state.replica {
+        Some(replica) => json!({
+            "leader": replica.leader(),
+            "applied_seq": replica.applied_seq(),
+            "leader_seq": replica.leader_seq(),
+            // What the leader said at the last pull beats a relayed probe
+            "lag_seq": replica.lag_seq().or_else(|| lag(Some(replica.applied_seq()))),
+            "staleness_ms": replica.staleness_ms(),
+            "max_lag_ms": replica.max_lag().map(|max_lag| max_lag.as_millis() as u64),
+            "in_read_pool": replica.in_read_pool(),
+            "sync_failures": replica.failures(),
+        }),
+        None => Value::Null,
+    };
+    let anti_entropy = state.anti_entropy.as_ref().map(|stats| {
+        json!({
+            "rounds": stats.rounds(),
+            "keys_repaired": stats.keys_repaired(),
+            "failures": stats.failures(),
+        })
+    });
+
+    let tasks = state.tasks.status();
+    let due = tasks
+        .iter()
+        .filter(|task| !task.running && task.next_run_ms.is_some_and(|next| next <= now))
+        .count();
+    let pools = state.pools.snapshot();
+    let backlog = json!({
+        "tasks_running": tasks.iter().filter(|task| task.running).count(),
+        "tasks_due": due,
+        "tasks_paused": tasks.iter().filter(|task| task.paused).count(),
+        "tasks_failing": tasks.iter().filter(|task| task.last_error.is_some()).count(),
+        "requests_in_flight": pools.iter().map(|pool| pool.in_use).sum::<usize>(),
+        "requests_queued": pools.iter().map(|pool| pool.waiting).sum::<u64>(),
+    });
+
+    json!({
+        "node": {
+            "url": state.cluster.as_ref().map(|cluster| cluster.self_url()),
+            "node_id": HybridClock::global().node_id(),
+            "role": role,
+            "applied_seq": applied_seq,
+            "lag_seq": lag(applied_seq),
+        },
+        "members": members,
+        "replication": replication,
+        "anti_entropy": anti_entropy,
+        "storage": storage,
+        "backlog": backlog,
+        "tasks": tasks,
+        "slo": state.slo.report(now),
+        "generated_at_ms": now,
+    })
+}
+
+/// Authenticate an admin request and read its body, checking the signed content hash
+async fn admin_request(
+    req: Request<Body>,
+    authenticator: &Authenticator,
+    spool: &Spool,
+    timings: &mut RequestTimings,
+) -> Result<(AuthContext, SpooledBody), Response<Body>> {
+    let authenticated = timings.time(Phase::Auth, || auth::authenticate_request(authenticator, &req));
+    if let Ok(ctx) = &authenticated {
+        audit::attribute(ctx);
+    }
+    let ctx = match authenticated {
+        Ok(ctx) if ctx.permissions().can_admin() => ctx,
+        Ok(_) => {
+            return Err(auth::error_response(&AuthError::PermissionDenied(
+                "admin permission required".to_string(),
+            )))
+        }
+        Err(e) => return Err(auth::error_response(&e)),
+    };
+    let body = spool.read(req.into_body(), MAX_ADMIN_BODY_BYTES).await.map_err(ApiError::into_response)?;
+    auth::check_body(Some(&ctx), &body, timings).map_err(ApiError::into_response)?;
+    Ok((ctx, body))
+}
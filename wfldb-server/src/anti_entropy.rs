@@ -0,0 +1,403 @@
+//! Merkle-tree anti-entropy between a replica and its leader
+//!
+//! The journal keeps a replica in step, but one seeded from an old snapshot
+//! or that lost writes in a crash can differ from its leader in ways
+//! replaying the journal won't fix. Periodically the replica compares every
+//! bucket's [`MerkleTree`] root with the leader's through `/admin/merkle`,
+//! walks down only the subtrees whose hashes differ, and repairs the keys of
+//! the leaves that still differ: the leader's newer writes and deletes are
+//! applied at the leader's versions, and keys the leader doesn't have are
+//! dropped. A bucket that matches costs one hash comparison, and a repair
+//! exchanges hashes and keys in proportion to the divergence rather than
+//! the bucket's size.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+use wfldb_auth::{headers, now_ms, RequestSigner};
+use wfldb_core::{ContentHash, Key, Version};
+use wfldb_engine::{children, LeafRepair, MerkleEntry, MerkleTree, Storage, StorageEngine, DEPTH, LEAVES};
+use wfldb_net::{encode_query_component, query_param};
+use crate::health::TaskHeartbeats;
+
+/// Name of the repair loop in the liveness probe
+const HEARTBEAT_TASK: &str = "anti_entropy";
+
+/// How long a tree built to answer a replica is reused, so one repair walk
+/// scans each bucket once rather than once per level
+const TREE_TTL: Duration = Duration::from_secs(30);
+
+/// Most tree nodes or leaves asked for per request
+const NODES_PER_REQUEST: usize = 256;
+
+/// Trees recently built for replicas comparing against this node
+#[derive(Default)]
+pub struct MerkleCache {
+    trees: Mutex<HashMap<String, Arc<MerkleTree>>>,
+}
+
+impl MerkleCache {
+    /// Tree of a namespaced bucket, built at most [`TREE_TTL`] ago
+    pub fn tree(&self, storage: &StorageEngine, bucket: &str) -> wfldb_core::Result<Arc<MerkleTree>> {
+        let now = now_ms();
+        let fresh = |tree: &MerkleTree| tree.built_at_ms() + TREE_TTL.as_millis() as u64 > now;
+        if let Some(tree) = self.trees.lock().unwrap().get(bucket).filter(|tree| fresh(tree)) {
+            return Ok(tree.clone());
+        }
+        let tree = Arc::new(build_tree(storage, bucket)?);
+        let mut trees = self.trees.lock().unwrap();
+        trees.retain(|_, tree| fresh(tree));
+        trees.insert(bucket.to_string(), tree.clone());
+        Ok(tree)
+    }
+}
+
+/// Build the tree of a bucket named as in journal records
+fn build_tree(storage: &StorageEngine, bucket: &str) -> wfldb_core::Result<MerkleTree> {
+    let (engine, bucket_id) = storage.resolve_namespaced(bucket)?;
+    MerkleTree::build(&engine.bucket(&bucket_id)?)
+}
+
+/// Answer a `GET /admin/merkle` query:
+///
+/// - no `bucket`: the root of every bucket
+/// - `bucket&level=L&nodes=i,j`: hashes of those nodes at level `L`
+/// - `bucket&leaves=i,j`: the entries of those leaves
+pub fn merkle_json(cache: &MerkleCache, storage: &StorageEngine, query: &str) -> Result<Value, (StatusCode, String)> {
+    let internal = |e: wfldb_core::WflDBError| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let Some(bucket) = query_param(query, "bucket") else {
+        let mut roots = BTreeMap::new();
+        for namespace in storage.namespaces().map_err(internal)? {
+            for bucket_id in namespace.bucket_ids().map_err(internal)? {
+                let name = namespace.namespaced(&bucket_id);
+                let root = cache.tree(storage, &name).map_err(internal)?.root().to_hex();
+                roots.insert(name, root);
+            }
+        }
+        return Ok(json!({ "buckets": roots }));
+    };
+
+    let tree = cache.tree(storage, &bucket).map_err(internal)?;
+    if let Some(leaves) = query_param(query, "leaves") {
+        let leaves = parse_indices(&leaves, LEAVES)?;
+        let entries: Vec<&[MerkleEntry]> = leaves.iter().map(|&leaf| tree.leaf(leaf).unwrap_or_default()).collect();
+        return Ok(json!({ "built_at_ms": tree.built_at_ms(), "leaves": entries }));
+    }
+    let level = query_param(query, "level")
+        .map(|level| level.parse::<usize>().ok().filter(|&level| level <= DEPTH))
+        .unwrap_or(Some(0))
+        .ok_or((StatusCode::BAD_REQUEST, "level out of range".to_string()))?;
+    let nodes = parse_indices(&query_param(query, "nodes").unwrap_or_else(|| "0".to_string()), LEAVES)?;
+    let hashes = nodes
+        .iter()
+        .map(|&index| tree.node(level, index).map(|hash| hash.to_hex()))
+        .collect::<Option<Vec<String>>>()
+        .ok_or((StatusCode::BAD_REQUEST, "node out of range".to_string()))?;
+    Ok(json!({ "built_at_ms": tree.built_at_ms(), "hashes": hashes }))
+}
+
+/// Comma-separated indices below `limit`, at most [`NODES_PER_REQUEST`]
+fn parse_indices(list: &str, limit: usize) -> Result<Vec<usize>, (StatusCode, String)> {
+    let indices: Option<Vec<usize>> = list
+        .split(',')
+        .map(|index| index.trim().parse().ok().filter(|&index| index < limit))
+        .collect();
+    match indices {
+        Some(indices) if indices.len() <= NODES_PER_REQUEST => Ok(indices),
+        Some(_) => Err((StatusCode::BAD_REQUEST, format!("at most {} nodes per request", NODES_PER_REQUEST))),
+        None => Err((StatusCode::BAD_REQUEST, "invalid node list".to_string())),
+    }
+}
+
+/// Outcome of the repair loop
+#[derive(Debug, Default)]
+pub struct AntiEntropyStats {
+    rounds: AtomicU64,
+    keys_repaired: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl AntiEntropyStats {
+    /// Completed comparisons with the leader
+    pub fn rounds(&self) -> u64 {
+        self.rounds.load(Ordering::Relaxed)
+    }
+
+    /// Keys written, deleted or dropped to match the leader
+    pub fn keys_repaired(&self) -> u64 {
+        self.keys_repaired.load(Ordering::Relaxed)
+    }
+
+    /// Rounds that failed part way
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+/// Periodically brings a replica's buckets in line with its leader's
+pub struct AntiEntropy {
+    upstream: String,
+    interval: Duration,
+    timeout: Duration,
+    storage: StorageEngine,
+    signer: RequestSigner,
+    stats: Arc<AntiEntropyStats>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl AntiEntropy {
+    /// Compare against the leader at a base URL such as `http://leader:8080`
+    /// every `interval`, signing requests with an admin key
+    pub fn new(upstream: &str, interval: Duration, storage: StorageEngine, signer: RequestSigner) -> Self {
+        AntiEntropy {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            interval,
+            timeout: Duration::from_secs(30),
+            storage,
+            signer,
+            stats: Arc::new(AntiEntropyStats::default()),
+            heartbeats: None,
+        }
+    }
+
+    /// Report each round to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.interval + self.timeout);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<AntiEntropyStats> {
+        self.stats.clone()
+    }
+
+    /// Compare and repair every interval until the task is dropped
+    pub async fn run(self) {
+        info!("Comparing buckets with {} every {:?}", self.upstream, self.interval);
+        let client = Client::new();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick fires at once; give the journal a round to catch up
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            match self.repair_once(&client).await {
+                Ok(repaired) => {
+                    self.stats.rounds.fetch_add(1, Ordering::Relaxed);
+                    if repaired > 0 {
+                        info!("Anti-entropy repaired {} keys from {}", repaired, self.upstream);
+                    }
+                }
+                Err(e) => {
+                    self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Anti-entropy with {} failed: {}", self.upstream, e);
+                }
+            }
+        }
+    }
+
+    /// Compare every bucket's root with the leader's, repairing those that
+    /// differ; returns the keys repaired
+    async fn repair_once(&self, client: &Client<HttpConnector>) -> Result<u64, String> {
+        let roots = self.get_json(client, "/admin/merkle").await?;
+        let roots: BTreeMap<String, String> =
+            serde_json::from_value(roots["buckets"].clone()).map_err(|e| e.to_string())?;
+
+        let mut repaired = 0;
+        for (bucket, root) in roots {
+            let storage = self.storage.clone();
+            let name = bucket.clone();
+            let local = tokio::task::spawn_blocking(move || build_tree(&storage, &name))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+            if local.root().to_hex() != root {
+                repaired += self.repair_bucket(client, &bucket, &local).await?;
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Descend the levels where the trees differ and repair the leaves
+    async fn repair_bucket(&self, client: &Client<HttpConnector>, bucket: &str, local: &MerkleTree) -> Result<u64, String> {
+        let mut differing = vec![0];
+        for level in 1..=DEPTH {
+            let candidates: Vec<usize> = differing.iter().flat_map(|&index| children(index)).collect();
+            differing = Vec::new();
+            for batch in candidates.chunks(NODES_PER_REQUEST) {
+                let path = format!(
+                    "/admin/merkle?bucket={}&level={}&nodes={}",
+                    encode_query_component(bucket.as_bytes()),
+                    level,
+                    index_list(batch)
+                );
+                let hashes: Vec<String> = serde_json::from_value(self.get_json(client, &path).await?["hashes"].clone())
+                    .map_err(|e| e.to_string())?;
+                differing.extend(
+                    batch
+                        .iter()
+                        .zip(hashes)
+                        .filter(|(&index, hash)| local.node(level, index).map(|local| local.to_hex()).as_ref() != Some(hash))
+                        .map(|(&index, _)| index),
+                );
+            }
+        }
+
+        let mut repaired = 0;
+        for batch in differing.chunks(NODES_PER_REQUEST) {
+            let path = format!(
+                "/admin/merkle?bucket={}&leaves={}",
+                encode_query_component(bucket.as_bytes()),
+                index_list(batch)
+            );
+            let answer = self.get_json(client, &path).await?;
+            let built_at_ms = answer["built_at_ms"].as_u64().ok_or("missing tree timestamp")?;
+            let leaves: Vec<Vec<MerkleEntry>> =
+                serde_json::from_value(answer["leaves"].clone()).map_err(|e| e.to_string())?;
+            for (&index, leader) in batch.iter().zip(leaves) {
+                let repair = LeafRepair::diff(local.leaf(index).unwrap_or_default(), &leader, built_at_ms);
+                repaired += self.apply(client, bucket, repair).await?;
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Write the leader's side of one leaf
+    async fn apply(&self, client: &Client<HttpConnector>, bucket: &str, repair: LeafRepair) -> Result<u64, String> {
+        let mut applied = 0;
+        for entry in repair.updates {
+            let object = match entry.deleted {
+                true => None,
+                false => match self.fetch_object(client, bucket, &entry.key).await? {
+                    Some(object) => Some(object),
+                    // Deleted since the leader's scan; the journal brings the delete
+                    None => continue,
+                },
+            };
+            let storage = self.storage.clone();
+            let bucket = bucket.to_string();
+            let changed = tokio::task::spawn_blocking(move || {
+                let (engine, bucket_id) = storage.resolve_namespaced(&bucket)?;
+                let storage = Storage::new(engine);
+                let key = Key::new(&entry.key)?;
+                match object {
+                    Some((data, version, customer_key_hash)) => storage
+                        .put_object_version_with_checksums(
+                            &bucket_id,
+                            &key,
+                            &data,
+                            entry.owner.as_deref(),
+                            version,
+                            BTreeMap::new(),
+                            customer_key_hash,
+                        )
+                        .map(|written| written.is_some()),
+                    None => storage.delete_object_version(&bucket_id, &key, entry.version),
+                }
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+            applied += changed as u64;
+        }
+
+        for key in repair.removals {
+            let storage = self.storage.clone();
+            let bucket = bucket.to_string();
+            tokio::task::spawn_blocking(move || {
+                let (engine, bucket_id) = storage.resolve_namespaced(&bucket)?;
+                Storage::new(engine).discard_object(&bucket_id, &Key::new(&key)?)
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+            applied += 1;
+        }
+        self.stats.keys_repaired.fetch_add(applied, Ordering::Relaxed);
+        Ok(applied)
+    }
+
+    /// The leader's current data, version and customer key hash of a key,
+    /// `None` once deleted
+    async fn fetch_object(
+        &self,
+        client: &Client<HttpConnector>,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Option<(Vec<u8>, Version, Option<ContentHash>)>, String> {
+        let path = format!(
+            "/admin/merkle/object?bucket={}&key={}",
+            encode_query_component(bucket.as_bytes()),
+            encode_query_component(key.as_bytes())
+        );
+        let response = self.get(client, &path).await?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Ok(None),
+            status => return Err(format!("unexpected status {} fetching {}", status, key)),
+        }
+        let version: Version = response
+            .headers()
+            .get(headers::VERSION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or("missing object version")?;
+        let customer_key_hash = response
+            .headers()
+            .get(headers::SSE_CUSTOMER_KEY_HASH)
+            .map(|hash| hash.to_str().ok().and_then(|hash| ContentHash::from_hex(hash).ok()))
+            .map(|hash| hash.ok_or("invalid customer key hash"))
+            .transpose()?;
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+        Ok(Some((body.to_vec(), version, customer_key_hash)))
+    }
+
+    async fn get_json(&self, client: &Client<HttpConnector>, path: &str) -> Result<Value, String> {
+        let response = self.get(client, path).await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("unexpected status {} for {}", response.status(), path));
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+
+    /// Signed GET to the leader
+    async fn get(&self, client: &Client<HttpConnector>, path: &str) -> Result<hyper::Response<Body>, String> {
+        let mut request = Request::get(format!("{}{}", self.upstream, path));
+        for (name, value) in self.signer.sign("GET", path, b"", now_ms()) {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::empty()).map_err(|e| e.to_string())?;
+        tokio::time::timeout(self.timeout, client.request(request))
+            .await
+            .map_err(|_| "timed out".to_string())?
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn index_list(indices: &[usize]) -> String {
+    indices.iter().map(usize::to_string).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_indices() {
+        assert_eq!(parse_indices("0,15, 4095", LEAVES).unwrap(), [0, 15, 4095]);
+        assert!(parse_indices("4096", LEAVES).is_err());
+        assert!(parse_indices("1,,2", LEAVES).is_err());
+        let too_many = index_list(&(0..NODES_PER_REQUEST + 1).collect::<Vec<_>>());
+        assert!(parse_indices(&too_many, LEAVES).is_err());
+    }
+}
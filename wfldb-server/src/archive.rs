@@ -0,0 +1,417 @@
+//! Continuous archival of the change journal
+//!
+//! The archiver periodically seals the active journal segment and ships every
+//! sealed segment to a directory (typically a mounted backup volume) or an S3
+//! bucket, deleting the local copy once the upload succeeds. Disaster
+//! recovery restores the last snapshot of the data directory and replays the
+//! archived segments on top with `--restore-journal`.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Client, Request, Uri};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use wfldb_auth::now_ms;
+use wfldb_engine::{ChangeJournal, SealedSegment};
+use crate::health::TaskHeartbeats;
+
+/// Name of the archiver in the liveness probe
+const HEARTBEAT_TASK: &str = "journal_archive";
+
+/// Where archived segments are written
+#[derive(Debug, Clone)]
+pub enum ArchiveDestination {
+    Directory(PathBuf),
+    S3(S3Destination),
+}
+
+impl ArchiveDestination {
+    /// Parse `s3://bucket/prefix` or a directory path; S3 credentials come
+    /// from the standard `AWS_*` environment variables
+    pub fn parse(spec: &str, s3_endpoint: Option<&str>) -> Result<Self, String> {
+        let Some(rest) = spec.strip_prefix("s3://") else {
+            let dir = spec.strip_prefix("file://").unwrap_or(spec);
+            return Ok(ArchiveDestination::Directory(PathBuf::from(dir)));
+        };
+
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!("Invalid archive destination '{}': missing bucket", spec));
+        }
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let access_key = env("AWS_ACCESS_KEY_ID").ok_or("AWS_ACCESS_KEY_ID is required for S3 archival")?;
+        let secret_key = env("AWS_SECRET_ACCESS_KEY").ok_or("AWS_SECRET_ACCESS_KEY is required for S3 archival")?;
+        let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = s3_endpoint
+            .map(|e| e.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+
+        let mut prefix = prefix.trim_matches('/').to_string();
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        Ok(ArchiveDestination::S3(S3Destination {
+            endpoint,
+            bucket: bucket.to_string(),
+            prefix,
+            region,
+            access_key,
+            secret_key,
+            session_token: env("AWS_SESSION_TOKEN"),
+        }))
+    }
+
+    async fn upload(&self, client: &S3Client, name: &str, data: Vec<u8>) -> Result<(), String> {
+        match self {
+            ArchiveDestination::Directory(dir) => {
+                let dir = dir.clone();
+                let name = name.to_string();
+                tokio::task::spawn_blocking(move || write_atomically(&dir, &name, &data))
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .map_err(|e| e.to_string())
+            }
+            ArchiveDestination::S3(s3) => s3.put_object(client, name, data).await,
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveDestination::Directory(dir) => write!(f, "{}", dir.display()),
+            ArchiveDestination::S3(s3) => write!(f, "s3://{}/{}", s3.bucket, s3.prefix),
+        }
+    }
+}
+
+/// Write to a temporary file and rename, so a partial upload is never
+/// mistaken for a complete segment
+fn write_atomically(dir: &Path, name: &str, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    std::fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, dir.join(name))
+}
+
+type S3Client = Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+fn s3_client() -> S3Client {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// An S3 (or S3-compatible) bucket, addressed path-style
+#[derive(Clone)]
+pub struct S3Destination {
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl std::fmt::Debug for S3Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Destination")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Destination {
+    async fn put_object(&self, client: &S3Client, name: &str, data: Vec<u8>) -> Result<(), String> {
+        let path = format!("/{}/{}", self.bucket, uri_encode_path(&format!("{}{}", self.prefix, name)));
+        let uri: Uri = format!("{}{}", self.endpoint, path).parse().map_err(|e| format!("invalid S3 URL: {}", e))?;
+        let host = match (uri.host(), uri.port_u16()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err("S3 endpoint has no host".to_string()),
+        };
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&data));
+        let mut headers = vec![
+            ("host".to_string(), host),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let authorization = sigv4_authorization(
+            &self.access_key,
+            &self.secret_key,
+            &self.region,
+            &amz_date,
+            "PUT",
+            &path,
+            &headers,
+            &payload_hash,
+        );
+
+        let mut request = Request::put(uri);
+        for (name, value) in &headers {
+            if name != "host" {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        }
+        let request = request
+            .header("authorization", authorization)
+            .header("content-length", data.len())
+            .body(Body::from(data))
+            .map_err(|e| e.to_string())?;
+
+        let response = client.request(request).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+            return Err(format!("S3 PUT returned {}: {}", status, String::from_utf8_lossy(&body)));
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode an object key for the request path, keeping `/`
+fn uri_encode_path(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sigv4_signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    hmac_sha256(&key, "aws4_request")
+}
+
+/// AWS Signature Version 4 `Authorization` header for an S3 request with no
+/// query string; `headers` must be lowercase and are all signed
+#[allow(clippy::too_many_arguments)]
+fn sigv4_authorization(
+    access_key: &str,
+    secret_key: &str,
+    region: &str,
+    amz_date: &str,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let canonical_headers: String = headers.iter().map(|(n, v)| format!("{}:{}\n", n, v.trim())).collect();
+    let signed_headers = headers.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signature = hex::encode(hmac_sha256(&sigv4_signing_key(secret_key, date, region, "s3"), &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature
+    )
+}
+
+/// Counters exposed through /metrics
+#[derive(Debug, Default)]
+pub struct ArchiveStats {
+    segments: AtomicU64,
+    bytes: AtomicU64,
+    failures: AtomicU64,
+    last_success_ms: AtomicU64,
+}
+
+impl ArchiveStats {
+    /// Segments shipped to the destination
+    pub fn segments(&self) -> u64 {
+        self.segments.load(Ordering::Relaxed)
+    }
+
+    /// Bytes shipped to the destination
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Failed archive passes
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Milliseconds since the last pass that shipped everything, if any
+    pub fn staleness_ms(&self) -> Option<u64> {
+        match self.last_success_ms.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(now_ms().saturating_sub(last)),
+        }
+    }
+}
+
+/// Periodically seals the journal's active segment and ships sealed segments
+pub struct JournalArchiver {
+    journal: Arc<ChangeJournal>,
+    destination: ArchiveDestination,
+    interval: Duration,
+    stats: Arc<ArchiveStats>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl JournalArchiver {
+    pub fn new(journal: Arc<ChangeJournal>, destination: ArchiveDestination, interval: Duration) -> Self {
+        JournalArchiver {
+            journal,
+            destination,
+            interval,
+            stats: Arc::new(ArchiveStats::default()),
+            heartbeats: None,
+        }
+    }
+
+    /// Report each pass to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.interval);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<ArchiveStats> {
+        self.stats.clone()
+    }
+
+    /// Archive until the task is dropped
+    pub async fn run(self) {
+        info!("Archiving journal segments to {} every {:?}", self.destination, self.interval);
+        let client = s3_client();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            match self.archive_once(&client).await {
+                Ok(0) => self.stats.last_success_ms.store(now_ms(), Ordering::Relaxed),
+                Ok(shipped) => {
+                    self.stats.last_success_ms.store(now_ms(), Ordering::Relaxed);
+                    debug!("Archived {} journal segments to {}", shipped, self.destination);
+                }
+                Err(e) => {
+                    self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Journal archival to {} failed: {}", self.destination, e);
+                }
+            }
+        }
+    }
+
+    /// Seal the active segment and ship everything sealed, oldest first
+    async fn archive_once(&self, client: &S3Client) -> Result<usize, String> {
+        let journal = self.journal.clone();
+        let segments: Vec<SealedSegment> = tokio::task::spawn_blocking(move || {
+            journal.rotate()?;
+            journal.sealed_segments()
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+        for segment in &segments {
+            let data = tokio::fs::read(&segment.path).await.map_err(|e| e.to_string())?;
+            let len = data.len() as u64;
+            // Stop at the first failure so the archive never has a gap
+            self.destination.upload(client, &segment.name(), data).await?;
+            self.journal.remove_segment(segment).map_err(|e| e.to_string())?;
+            self.stats.segments.fetch_add(1, Ordering::Relaxed);
+            self.stats.bytes.fetch_add(len, Ordering::Relaxed);
+        }
+        Ok(segments.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_core::{BucketId, Key};
+    use wfldb_core::ChangeOp;
+
+    #[test]
+    fn test_parse_destination() {
+        match ArchiveDestination::parse("/mnt/backup/wal", None).unwrap() {
+            ArchiveDestination::Directory(dir) => assert_eq!(dir, PathBuf::from("/mnt/backup/wal")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(ArchiveDestination::parse("s3:///prefix", None).is_err());
+    }
+
+    #[test]
+    fn test_sigv4_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = sigv4_signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20150830", "us-east-1", "iam");
+        assert_eq!(hex::encode(key), "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9");
+    }
+
+    #[test]
+    fn test_uri_encode_path() {
+        assert_eq!(uri_encode_path("wal/00000000000000000001.wal"), "wal/00000000000000000001.wal");
+        assert_eq!(uri_encode_path("my prefix/a+b"), "my%20prefix/a%2Bb");
+    }
+
+    #[tokio::test]
+    async fn test_ships_sealed_segments_to_directory() {
+        let local = tempfile::tempdir().unwrap();
+        let archive = tempfile::tempdir().unwrap();
+        let journal = Arc::new(ChangeJournal::open(local.path()).unwrap());
+        let bucket = BucketId::new("photos").unwrap();
+        let key = Key::new("cat.jpg").unwrap();
+        journal.append(bucket.as_str(), &key, ChangeOp::Delete).unwrap();
+
+        let archiver = JournalArchiver::new(
+            journal.clone(),
+            ArchiveDestination::Directory(archive.path().to_path_buf()),
+            Duration::from_secs(60),
+        );
+        assert_eq!(archiver.archive_once(&s3_client()).await.unwrap(), 1);
+
+        assert!(archive.path().join("00000000000000000001.wal").exists());
+        assert!(journal.sealed_segments().unwrap().is_empty());
+        assert_eq!(archiver.stats().segments(), 1);
+    }
+}
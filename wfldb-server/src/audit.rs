@@ -0,0 +1,228 @@
+//! Audit log recording and signed exports
+//!
+//! With `--audit-log`, every request to `/v1` and `/admin` is recorded as an
+//! [`AuditEvent`], attributed to the key that signed it once the signature
+//! checks out, even when the key then turns out not to be allowed the
+//! request. Events are kept for the retention period (see
+//! [`wfldb_engine::AuditLog`]).
+//!
+//! An export is a JSON Lines extract of the events of a time range, from
+//! `from_ms` up to but not including `to_ms`:
+//!
+//! ```text
+//! {"format": "wfldb-audit-export", "format_version": 1, "node_id", "from_ms", "to_ms", "exported_at_ms", "events"}
+//! {"at_ms", "kind": "access" | "admin", "request_id", "key_id", "principal", "method", "path", "status"}
+//! ...
+//! {"blake3": "<hex>", "signer": "<key id>", "signature": "<base64url>"}
+//! ```
+//!
+//! The last line signs every byte before it: `blake3` is their BLAKE3 hash,
+//! and `signature` is the server identity key's Ed25519 signature over
+//! `wfldb-audit-export-v1:{blake3}` (see [`wfldb_auth::ServerIdentity`]).
+//! Checking it needs only the server's public key, obtained from the server
+//! beforehand rather than trusted from the export. An export holds at most
+//! [`MAX_EXPORT_EVENTS`]; a larger range ends early, with the header's
+//! `to_ms` saying where, and the rest is exported from there.
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use wfldb_auth::{decode_public_key, now_ms, verify_audit_export, AuthContext, KeyId, ServerIdentity};
+use wfldb_core::{ContentHash, Result};
+use wfldb_engine::{AuditEvent, AuditLog};
+use crate::tasks::{BackgroundTask, TaskContext};
+
+pub const EXPORT_FORMAT: &str = "wfldb-audit-export";
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Most events in one export
+pub const MAX_EXPORT_EVENTS: usize = 100_000;
+
+/// Interval between audit log flushes to the system partition
+const AUDIT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most expired events deleted per flush
+const PRUNE_BATCH: usize = 10_000;
+
+/// First line of an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub format: String,
+    pub format_version: u32,
+    pub node_id: u16,
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub exported_at_ms: u64,
+    pub events: usize,
+}
+
+/// Last line of an export
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportSignature {
+    blake3: String,
+    signer: KeyId,
+    signature: String,
+}
+
+tokio::task_local! {
+    static ACTOR: RefCell<Option<(String, Option<String>)>>;
+}
+
+/// Run `f`, returning the key ID and principal it was attributed to
+pub async fn attributed<F: Future>(f: F) -> (F::Output, Option<(String, Option<String>)>) {
+    ACTOR
+        .scope(RefCell::new(None), async {
+            let output = f.await;
+            (output, ACTOR.with(RefCell::take))
+        })
+        .await
+}
+
+/// Attribute the request being handled to the key of `ctx`
+pub fn attribute(ctx: &AuthContext) {
+    let _ = ACTOR.try_with(|actor| {
+        *actor.borrow_mut() = Some((ctx.key_id().to_string(), ctx.principal().map(str::to_string)));
+    });
+}
+
+/// Extract the events from `from_ms` up to `to_ms`, signed with `identity`
+pub fn export(log: &AuditLog, identity: &ServerIdentity, from_ms: u64, to_ms: u64) -> Result<Vec<u8>> {
+    log.flush()?;
+    let mut events = log.events(from_ms, to_ms, MAX_EXPORT_EVENTS + 1)?;
+    let mut to_ms = to_ms;
+    if events.len() > MAX_EXPORT_EVENTS {
+        // End at a millisecond boundary so the next export starts cleanly
+        to_ms = events[MAX_EXPORT_EVENTS].at_ms;
+        events.retain(|event| event.at_ms < to_ms);
+    }
+
+    let header = ExportHeader {
+        format: EXPORT_FORMAT.to_string(),
+        format_version: EXPORT_FORMAT_VERSION,
+        node_id: wfldb_core::HybridClock::global().node_id(),
+        from_ms,
+        to_ms,
+        exported_at_ms: now_ms(),
+        events: events.len(),
+    };
+    let mut extract = serde_json::to_vec(&header)?;
+    extract.push(b'\n');
+    for event in &events {
+        serde_json::to_writer(&mut extract, event)?;
+        extract.push(b'\n');
+    }
+
+    let digest = ContentHash::new(&extract);
+    let signature = ExportSignature {
+        blake3: digest.to_hex(),
+        signer: identity.key_id(),
+        signature: identity.sign_audit_export(&digest),
+    };
+    serde_json::to_writer(&mut extract, &signature)?;
+    extract.push(b'\n');
+    Ok(extract)
+}
+
+/// Check that the holder of `public_key` (base64url) signed an export and
+/// nothing changed since, returning its header
+pub fn verify(extract: &[u8], public_key: &str) -> std::result::Result<ExportHeader, String> {
+    let key = decode_public_key(public_key.trim()).map_err(|e| e.to_string())?;
+    let body = extract.strip_suffix(b"\n").unwrap_or(extract);
+    let split = body.iter().rposition(|&b| b == b'\n').ok_or("Export has no signature line")? + 1;
+    let (content, signature_line) = body.split_at(split);
+    let signature: ExportSignature =
+        serde_json::from_slice(signature_line).map_err(|e| format!("Invalid signature line: {}", e))?;
+    if signature.signer != KeyId::from_public_key(&key) {
+        return Err(format!("Export was signed by {}, not the given key", signature.signer));
+    }
+    let digest = ContentHash::new(content);
+    if digest.to_hex() != signature.blake3 {
+        return Err("Export content does not match its signed hash".to_string());
+    }
+    verify_audit_export(&key, &digest, &signature.signature).map_err(|_| "Export signature is invalid".to_string())?;
+
+    let lines: Vec<&[u8]> = content.split(|&b| b == b'\n').filter(|line| !line.is_empty()).collect();
+    let header: ExportHeader = serde_json::from_slice(lines.first().ok_or("Export has no header")?)
+        .map_err(|e| format!("Invalid header: {}", e))?;
+    if header.format != EXPORT_FORMAT || header.format_version > EXPORT_FORMAT_VERSION {
+        return Err(format!("Unsupported export format {} {}", header.format, header.format_version));
+    }
+    for line in &lines[1..] {
+        serde_json::from_slice::<AuditEvent>(line).map_err(|e| format!("Invalid event: {}", e))?;
+    }
+    if lines.len() - 1 != header.events {
+        return Err(format!("Export holds {} events, its header says {}", lines.len() - 1, header.events));
+    }
+    Ok(header)
+}
+
+/// Flushes recorded audit events and drops those past retention
+pub struct AuditFlush {
+    pub log: Arc<AuditLog>,
+    pub retention: Duration,
+}
+
+impl BackgroundTask for AuditFlush {
+    fn name(&self) -> &'static str {
+        "audit_flush"
+    }
+
+    fn interval(&self) -> Duration {
+        AUDIT_FLUSH_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        let flushed = self.log.flush()?;
+        let cutoff = now_ms().saturating_sub(self.retention.as_millis() as u64);
+        let pruned = self.log.prune(cutoff, PRUNE_BATCH)?;
+        Ok(format!("flushed {} audit events, pruned {}", flushed, pruned))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_auth::{encode_public_key, generate_signing_key, MemoryAuthorityStore};
+    use wfldb_engine::{AuditKind, StorageEngine};
+
+    fn event(at_ms: u64) -> AuditEvent {
+        AuditEvent {
+            at_ms,
+            kind: AuditKind::Admin,
+            request_id: Some("req".to_string()),
+            key_id: None,
+            principal: None,
+            method: "PUT".to_string(),
+            path: "/admin/buckets/photos/acl".to_string(),
+            status: 200,
+        }
+    }
+
+    #[test]
+    fn test_export_verifies_and_detects_edits() {
+        let temp = tempfile::tempdir().unwrap();
+        let engine = StorageEngine::new(temp.path()).unwrap();
+        let log = AuditLog::new(engine.system().unwrap());
+        let identity = ServerIdentity::load_or_generate(&MemoryAuthorityStore::new()).unwrap();
+        for at_ms in [1_000, 2_000, 3_000] {
+            log.record(event(at_ms));
+        }
+
+        let public_key = encode_public_key(&identity.public_key());
+        let extract = export(&log, &identity, 1_000, 3_000).unwrap();
+        let header = verify(&extract, &public_key).unwrap();
+        assert_eq!((header.from_ms, header.to_ms, header.events), (1_000, 3_000, 2));
+
+        let edited = String::from_utf8(extract.clone()).unwrap().replace("\"status\":200", "\"status\":403");
+        assert!(verify(edited.as_bytes(), &public_key).is_err());
+        let other = encode_public_key(&generate_signing_key().verifying_key());
+        assert!(verify(&extract, &other).is_err());
+        // Dropping events breaks the hash even if the header is fixed up
+        let text = String::from_utf8(extract).unwrap();
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.remove(1);
+        assert!(verify(lines.join("\n").as_bytes(), &public_key).is_err());
+    }
+}
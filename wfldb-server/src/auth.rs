@@ -0,0 +1,213 @@
+//! Request authentication middleware
+
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response, StatusCode};
+use wfldb_auth::{headers, now_ms, AuthContext, AuthError, Authenticator, ChunkVerifier, RequestParts};
+use wfldb_core::{ContentHash, Key};
+use crate::error::ApiError;
+use crate::request_id;
+use crate::router::{parse_object_path, Route};
+use crate::slow_log::{Phase, RequestTimings};
+use crate::spool::{self, Spool, SpooledBody};
+use crate::{chunks, lease, multipart, objects};
+
+/// Authenticate a request against its key packet and signature headers
+pub fn authenticate_request(auth: &Authenticator, req: &Request<Body>) -> Result<AuthContext, AuthError> {
+    let headers = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str(), value)))
+        .collect();
+    let parts = RequestParts {
+        method: req.method().as_str(),
+        path: req.uri().path(),
+        query: req.uri().query().unwrap_or(""),
+        headers,
+    };
+    auth.authenticate(&parts, now_ms())
+}
+
+/// Check a body read whole against the content hash its request signed
+///
+/// A chunk-signed request signed no hash, and only object PUTs verify its
+/// chunks, so anywhere else its body is refused.
+pub fn check_body(ctx: Option<&AuthContext>, body: &[u8], timings: &mut RequestTimings) -> Result<(), ApiError> {
+    let Some(ctx) = ctx else {
+        return Ok(());
+    };
+    if ctx.is_streaming() {
+        return Err(ApiError::bad_request("Chunk-signed bodies are only accepted by object PUTs"));
+    }
+    let actual = timings.time(Phase::Auth, || ContentHash::new(body).to_hex());
+    if actual != ctx.content_hash {
+        return Err(ApiError::bad_request("Content hash does not match request body"));
+    }
+    Ok(())
+}
+
+/// Read a chunk-signed body of at most `limit` bytes, verifying each chunk
+/// as it arrives
+///
+/// Fails with a ready-made error response on a read error, a chunk that
+/// does not verify, or a body past the limit.
+pub async fn read_signed_chunks(
+    mut body: Body,
+    mut verifier: ChunkVerifier,
+    spool: &Spool,
+    limit: usize,
+) -> Result<SpooledBody, Response<Body>> {
+    let (mut payload, mut read) = (spool.buffer(), 0);
+    while let Some(data) = body.data().await {
+        let data = data.map_err(|_| ApiError::bad_request("Failed to read request body").into_response())?;
+        let verified = verifier.push(&data).map_err(|e| error_response(&e))?;
+        read += verified.len();
+        if read > limit {
+            return Err(ApiError::too_large(format!("Body exceeds {} bytes", limit)).into_response());
+        }
+        payload.push(&verified).await.map_err(|e| spool::spill_error(e).into_response())?;
+    }
+    verifier.finish().map_err(|e| error_response(&e))?;
+    payload.finish().await.map_err(|e| spool::spill_error(e).into_response())
+}
+
+/// The one key a request acts on, for checking a key prefix caveat
+///
+/// `None` for requests that act on several keys or on none (chunk uploads,
+/// composes, transactions, search), which a key prefix caveat refuses.
+pub fn addressed_key(route: Route, path: &str) -> Option<Key> {
+    let addressed = match route {
+        Route::PutObject | Route::GetObject | Route::PatchDocument | Route::DeleteObject => parse_object_path(path),
+        Route::Touch => objects::parse_touch_path(path)?,
+        Route::Lease => lease::parse_lease_path(path)?,
+        Route::Multipart => multipart::parse_upload_path(path)?,
+        Route::Chunks => match chunks::parse_chunk_path(path)?.ok()? {
+            chunks::ChunkRoute::Manifest(bucket_id, key) | chunks::ChunkRoute::Range(bucket_id, key) => Ok((bucket_id, key)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    addressed.ok().map(|(_, key)| key)
+}
+
+/// Limit a body to the `max_body_bytes` caveat of its request
+///
+/// A declared length over the cap is refused up front; a body that turns
+/// out longer than it fails to read once it passes the cap.
+pub fn cap_body(req: Request<Body>, ctx: Option<&AuthContext>, content_length: u64) -> Result<Request<Body>, ApiError> {
+    let Some(max) = ctx.and_then(|ctx| ctx.caveats()).and_then(|caveats| caveats.max_body_bytes) else {
+        return Ok(req);
+    };
+    if content_length > max {
+        return Err(ApiError::too_large(format!("Body exceeds the request's {}-byte caveat", max)));
+    }
+    let mut read = 0u64;
+    Ok(req.map(|body| {
+        Body::wrap_stream(body.map(move |chunk| {
+            let data = chunk?;
+            read += data.len() as u64;
+            if read > max {
+                return Err(format!("Body exceeds the request's {}-byte caveat", max).into());
+            }
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(data)
+        }))
+    }))
+}
+
+/// Map an authentication failure to a 401 or 403 response
+///
+/// 401 responses carry the server clock so clients can correct their timestamps.
+pub fn error_response(err: &AuthError) -> Response<Body> {
+    if let AuthError::PermissionDenied(_) = err {
+        let body = serde_json::json!({
+            "error": err.to_string(),
+            "code": err.code(),
+            "request_id": request_id::current(),
+        })
+        .to_string();
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+    }
+
+    let server_time = now_ms();
+    let body = serde_json::json!({
+        "error": err.to_string(),
+        "code": err.code(),
+        "server_time_ms": server_time,
+        "request_id": request_id::current(),
+    })
+    .to_string();
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("content-type", "application/json")
+        .header(headers::SERVER_TIME, server_time.to_string())
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wfldb_auth::{generate_signing_key, KeyAuthority, Permissions, RequestSigner};
+
+    #[test]
+    fn test_error_status_mapping() {
+        assert_eq!(
+            error_response(&AuthError::InvalidSignature).status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            error_response(&AuthError::PermissionDenied("GET on bucket 'b'".to_string())).status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn test_unauthorized_carries_server_time() {
+        let response = error_response(&AuthError::TimestampOutOfWindow);
+        let server_time: u64 = response.headers()[headers::SERVER_TIME].to_str().unwrap().parse().unwrap();
+        assert!(server_time.abs_diff(now_ms()) < 60_000);
+
+        let forbidden = error_response(&AuthError::PermissionDenied("GET on bucket 'b'".to_string()));
+        assert!(forbidden.headers().get(headers::SERVER_TIME).is_none());
+    }
+
+    #[test]
+    fn test_addressed_key() {
+        let key = |route, path| addressed_key(route, path).map(|key| key.as_str().to_string());
+        assert_eq!(key(Route::PutObject, "/v1/photos/cats/tom.jpg").as_deref(), Some("cats/tom.jpg"));
+        assert_eq!(key(Route::Touch, "/v1/photos/cats/tom.jpg/_touch").as_deref(), Some("cats/tom.jpg"));
+        assert_eq!(key(Route::Chunks, "/v1/photos/_manifest/cats/big.bin").as_deref(), Some("cats/big.bin"));
+        assert_eq!(key(Route::Chunks, "/v1/photos/_compose/cats/big.bin"), None);
+        assert_eq!(key(Route::Transaction, "/v1/photos/_txn"), None);
+    }
+
+    #[test]
+    fn test_check_body() {
+        let (root, holder) = (generate_signing_key(), generate_signing_key());
+        let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, 3_600).unwrap();
+        let authenticator = Authenticator::new(Arc::new(KeyAuthority::new(root.verifying_key())));
+        let signer = RequestSigner::new(holder, packet);
+        let request = RequestParts::new("POST", "/v1/photos/_txn");
+        let authenticate = |auth_headers: &[(&'static str, String)]| {
+            let parts = RequestParts {
+                headers: auth_headers.iter().map(|(name, value)| (*name, value.as_str())).collect(),
+                ..request.clone()
+            };
+            authenticator.authenticate(&parts, 1_000).unwrap()
+        };
+        let timings = &mut RequestTimings::start();
+
+        let signed = authenticate(&signer.sign_request(&request, b"{}", 1_000));
+        assert!(check_body(Some(&signed), b"{}", timings).is_ok());
+        assert_eq!(check_body(Some(&signed), b"{ }", timings).unwrap_err().into_response().status(), StatusCode::BAD_REQUEST);
+        let (auth_headers, _) = signer.sign_streaming(&request, 1_000);
+        let streaming = authenticate(&auth_headers);
+        assert_eq!(check_body(Some(&streaming), b"{}", timings).unwrap_err().into_response().status(), StatusCode::BAD_REQUEST);
+        assert!(check_body(None, b"anything", timings).is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+//! Persistent key authority and first-run bootstrap
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+use wfldb_auth::{
+    decode_public_key, encode_signing_key, generate_signing_key, AuthError, AuthorityStore,
+    KeyAuthority, Result,
+};
+use wfldb_engine::SystemPartition;
+
+/// Authority store backed by the engine's system partition
+pub struct EngineAuthorityStore {
+    system: SystemPartition,
+}
+
+impl EngineAuthorityStore {
+    pub fn new(system: SystemPartition) -> Self {
+        EngineAuthorityStore { system }
+    }
+}
+
+impl AuthorityStore for EngineAuthorityStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.system.get(key).map_err(|e| AuthError::Storage(e.to_string()))
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.system.put(key, value).map_err(|e| AuthError::Storage(e.to_string()))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.system.delete(key).map_err(|e| AuthError::Storage(e.to_string()))
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        self.system.scan_prefix(prefix).map_err(|e| AuthError::Storage(e.to_string()))
+    }
+}
+
+/// How to obtain the root key when the store has not been bootstrapped
+#[derive(Debug, Clone, Default)]
+pub struct BootstrapOptions<'a> {
+    /// Root public key to import (base64url)
+    pub import_root: Option<&'a str>,
+    /// Generate a root key pair, writing the secret key to this path
+    pub generate_root: Option<&'a Path>,
+}
+
+/// Open the persisted authority, bootstrapping it on first run
+///
+/// Returns `None` when the store is empty and no bootstrap option was given,
+/// in which case authentication stays disabled.
+pub fn load_or_bootstrap(
+    store: Arc<dyn AuthorityStore>,
+    options: &BootstrapOptions<'_>,
+) -> std::result::Result<Option<KeyAuthority>, String> {
+    if let Some(authority) = KeyAuthority::open(store.clone()).map_err(|e| e.to_string())? {
+        if let Some(import) = options.import_root {
+            let root = decode_public_key(import).map_err(|e| format!("Invalid auth root key: {}", e))?;
+            if root != authority.root() {
+                return Err(format!(
+                    "Data directory is bootstrapped with root {}; refusing to switch roots",
+                    authority.root_id()
+                ));
+            }
+        }
+        if options.generate_root.is_some() {
+            return Err(format!(
+                "Data directory is already bootstrapped with root {}",
+                authority.root_id()
+            ));
+        }
+        info!("Loaded key authority (root {})", authority.root_id());
+        return Ok(Some(authority));
+    }
+
+    let root = if let Some(import) = options.import_root {
+        decode_public_key(import).map_err(|e| format!("Invalid auth root key: {}", e))?
+    } else if let Some(path) = options.generate_root {
+        let signing_key = generate_signing_key();
+        write_secret(path, &encode_signing_key(&signing_key))
+            .map_err(|e| format!("Failed to write root key to {}: {}", path.display(), e))?;
+        println!("Root secret key written to {}; keep it offline", path.display());
+        signing_key.verifying_key()
+    } else {
+        return Ok(None);
+    };
+
+    let authority = KeyAuthority::bootstrap(store, root).map_err(|e| e.to_string())?;
+    println!("Bootstrapped key authority with root key ID {}", authority.root_id());
+    Ok(Some(authority))
+}
+
+/// Write a secret to a new file readable only by the owner
+fn write_secret(path: &Path, secret: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", secret)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_auth::{decode_signing_key, encode_public_key, MemoryAuthorityStore};
+
+    #[test]
+    fn test_unbootstrapped_store_without_options() {
+        let store = Arc::new(MemoryAuthorityStore::new());
+        assert!(load_or_bootstrap(store, &BootstrapOptions::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_generate_root_then_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("root.key");
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+
+        let options = BootstrapOptions { generate_root: Some(&path), ..Default::default() };
+        let authority = load_or_bootstrap(store.clone(), &options).unwrap().unwrap();
+        let secret = decode_signing_key(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(secret.verifying_key(), authority.root());
+
+        // Later starts load the same root and refuse to generate another
+        let reloaded = load_or_bootstrap(store.clone(), &BootstrapOptions::default()).unwrap().unwrap();
+        assert_eq!(reloaded.root(), authority.root());
+        assert!(load_or_bootstrap(store, &options).is_err());
+    }
+
+    #[test]
+    fn test_import_root_must_match() {
+        let store: Arc<dyn AuthorityStore> = Arc::new(MemoryAuthorityStore::new());
+        let root = encode_public_key(&generate_signing_key().verifying_key());
+        let other = encode_public_key(&generate_signing_key().verifying_key());
+
+        let import = |key| BootstrapOptions { import_root: Some(key), ..Default::default() };
+        assert!(load_or_bootstrap(store.clone(), &import(&root)).unwrap().is_some());
+        assert!(load_or_bootstrap(store.clone(), &import(&root)).unwrap().is_some());
+        assert!(load_or_bootstrap(store, &import(&other)).is_err());
+    }
+}
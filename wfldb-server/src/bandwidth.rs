@@ -0,0 +1,290 @@
+//! Per-key and per-bucket bandwidth limits
+//!
+//! Limits are named like pools: `key/{key id}`, `principal/{name}`, or
+//! `key/*` for every key without a limit of its own, and `bucket/{name}`
+//! with tenant buckets named as in journal records. A request pays into the
+//! limit of its key (the key's own, else its principal's, else `key/*`) and
+//! into its bucket's, each a token bucket per direction that refills at the
+//! configured rate and holds up to a second of it.
+//!
+//! Request and response bodies pass through [`throttle`], which sleeps after
+//! each slice until every limit it pays into is back out of debt. Holding
+//! back a request body stops hyper reading the connection, so an upload over
+//! its limit is slowed by TCP or HTTP/2 flow control rather than buffered.
+//!
+//! Limits can be replaced while the server runs (see [`crate::reconfig`]).
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use hyper::Body;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Largest slice of a body paid for at once, so pacing stays smooth for
+/// bodies handed over in one piece
+const SLICE_BYTES: usize = 64 * 1024;
+
+/// Limits created on demand, like pools, before new names go unlimited
+const MAX_LIMITERS: usize = 4096;
+
+/// Which way a body flows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Ingress,
+    Egress,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Ingress => "ingress",
+            Direction::Egress => "egress",
+        }
+    }
+}
+
+/// Bytes per second each way; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    pub ingress: Option<u64>,
+    pub egress: Option<u64>,
+}
+
+impl BandwidthLimit {
+    fn rate(&self, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::Ingress => self.ingress,
+            Direction::Egress => self.egress,
+        }
+    }
+}
+
+/// Configured limits by name
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthConfig {
+    pub limits: HashMap<String, BandwidthLimit>,
+}
+
+impl BandwidthConfig {
+    /// Parse a `{name}={in}/{out}` limit in KiB per second, `-` for
+    /// unlimited, e.g. `key/*=1024/4096` or `bucket/videos=-/20480`
+    pub fn parse_limit(spec: &str) -> Result<(String, BandwidthLimit), String> {
+        let (name, rates) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=IN/OUT, got '{}'", spec))?;
+        let valid_name = ["key/", "principal/", "bucket/"]
+            .iter()
+            .any(|prefix| name.strip_prefix(prefix).is_some_and(|rest| !rest.is_empty()));
+        if !valid_name {
+            return Err(format!("limit '{}' must be key/ID, key/*, principal/NAME, or bucket/NAME", name));
+        }
+        let (ingress, egress) = rates
+            .split_once('/')
+            .ok_or_else(|| format!("expected IN/OUT KiB per second in '{}'", spec))?;
+        let rate = |value: &str| -> Result<Option<u64>, String> {
+            match value {
+                "-" => Ok(None),
+                _ => match value.parse::<u64>() {
+                    Ok(kib) if kib > 0 => Ok(Some(kib * 1024)),
+                    _ => Err(format!("invalid rate '{}' in '{}'", value, spec)),
+                },
+            }
+        };
+        Ok((name.to_string(), BandwidthLimit { ingress: rate(ingress)?, egress: rate(egress)? }))
+    }
+
+    /// The limits as `parse_limit` specs, by name
+    pub fn specs(&self) -> Vec<String> {
+        let rate = |rate: Option<u64>| rate.map_or("-".to_string(), |bytes| (bytes / 1024).to_string());
+        let mut specs: Vec<String> = self
+            .limits
+            .iter()
+            .map(|(name, limit)| format!("{}={}/{}", name, rate(limit.ingress), rate(limit.egress)))
+            .collect();
+        specs.sort();
+        specs
+    }
+}
+
+/// A token bucket allowed into debt: a slice bigger than the balance is
+/// let through and later slices wait for the debt to be paid off
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+        TokenBucket { rate, state: Mutex::new((rate, Instant::now())) }
+    }
+
+    /// Pay for `bytes` at `now`, returning how long to wait before sending more
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * self.rate).min(self.rate);
+        *last = now.max(*last);
+        *tokens -= bytes as f64;
+        if *tokens < 0.0 {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Limiters shared by every request paying into the same limit
+pub struct Bandwidth {
+    config: RwLock<BandwidthConfig>,
+    limiters: Mutex<HashMap<(String, Direction), Arc<TokenBucket>>>,
+    delayed_ns: [AtomicU64; 2],
+}
+
+impl Bandwidth {
+    pub fn new(config: BandwidthConfig) -> Self {
+        Bandwidth {
+            config: RwLock::new(config),
+            limiters: Mutex::new(HashMap::new()),
+            delayed_ns: Default::default(),
+        }
+    }
+
+    /// Limits a request with this key, principal and namespaced bucket pays
+    /// into one way
+    pub fn limiters(
+        &self,
+        key_id: Option<&str>,
+        principal: Option<&str>,
+        bucket: Option<&str>,
+        direction: Direction,
+    ) -> Vec<Arc<TokenBucket>> {
+        let config = self.config.read().unwrap();
+        let key_limit = key_id.and_then(|key_id| {
+            [format!("key/{}", key_id)]
+                .into_iter()
+                .chain(principal.map(|principal| format!("principal/{}", principal)))
+                .chain(["key/*".to_string()])
+                .find(|name| config.limits.contains_key(name))
+        });
+        let bucket_limit = bucket.map(|bucket| format!("bucket/{}", bucket));
+        key_limit
+            .into_iter()
+            .chain(bucket_limit)
+            .filter_map(|name| self.limiter(&config, name, direction))
+            .collect()
+    }
+
+    fn limiter(&self, config: &BandwidthConfig, name: String, direction: Direction) -> Option<Arc<TokenBucket>> {
+        let rate = config.limits.get(&name)?.rate(direction)?;
+        let mut limiters = self.limiters.lock().unwrap();
+        if limiters.len() >= MAX_LIMITERS && !limiters.contains_key(&(name.clone(), direction)) {
+            return None;
+        }
+        Some(limiters.entry((name, direction)).or_insert_with(|| Arc::new(TokenBucket::new(rate))).clone())
+    }
+
+    /// Whether any limit is configured
+    pub fn is_limited(&self) -> bool {
+        !self.config.read().unwrap().limits.is_empty()
+    }
+
+    pub fn config(&self) -> BandwidthConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace every limit; bodies already being paced keep their old rates
+    pub fn set_config(&self, config: BandwidthConfig) {
+        let mut current = self.config.write().unwrap();
+        self.limiters.lock().unwrap().clear();
+        *current = config;
+    }
+
+    /// Total time bodies were held back one way
+    pub fn delayed(&self, direction: Direction) -> Duration {
+        Duration::from_nanos(self.delayed_ns[direction as usize].load(Ordering::Relaxed))
+    }
+}
+
+/// Pace `body` to the slowest of `limiters`
+pub fn throttle(body: Body, limiters: Vec<Arc<TokenBucket>>, bandwidth: Arc<Bandwidth>, direction: Direction) -> Body {
+    if limiters.is_empty() {
+        return body;
+    }
+    let sliced = body.flat_map(|chunk| {
+        let slices: Vec<Result<Bytes, hyper::Error>> = match chunk {
+            Ok(data) => (0..data.len())
+                .step_by(SLICE_BYTES)
+                .map(|start| Ok(data.slice(start..(start + SLICE_BYTES).min(data.len()))))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        stream::iter(slices)
+    });
+    Body::wrap_stream(sliced.then(move |slice| {
+        let (limiters, bandwidth) = (limiters.clone(), bandwidth.clone());
+        async move {
+            if let Ok(data) = &slice {
+                let now = Instant::now();
+                let wait = limiters.iter().map(|limiter| limiter.reserve(data.len(), now)).max().unwrap_or_default();
+                if !wait.is_zero() {
+                    bandwidth.delayed_ns[direction as usize].fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            slice
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(
+            BandwidthConfig::parse_limit("key/*=1024/-").unwrap(),
+            ("key/*".to_string(), BandwidthLimit { ingress: Some(1 << 20), egress: None })
+        );
+        assert!(BandwidthConfig::parse_limit("bucket/videos=-/20480").is_ok());
+        assert!(BandwidthConfig::parse_limit("tenant/acme=1/1").is_err());
+        assert!(BandwidthConfig::parse_limit("bucket/=1/1").is_err());
+        assert!(BandwidthConfig::parse_limit("key/*=0/1").is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_debt() {
+        let bucket = TokenBucket::new(1000);
+        let start = Instant::now();
+        // A second of burst, then debt paid off at the rate
+        assert_eq!(bucket.reserve(1000, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(500, start + Duration::from_millis(500)), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_limiters_chosen() {
+        let mut config = BandwidthConfig::default();
+        for spec in ["key/*=1/1", "principal/alice=2/2", "bucket/videos=-/4"] {
+            let (name, limit) = BandwidthConfig::parse_limit(spec).unwrap();
+            config.limits.insert(name, limit);
+        }
+        let bandwidth = Bandwidth::new(config);
+        let rates = |key, principal, bucket, direction| -> Vec<f64> {
+            bandwidth.limiters(key, principal, bucket, direction).iter().map(|l| l.rate).collect()
+        };
+        assert_eq!(rates(Some("ab"), Some("alice"), Some("videos"), Direction::Egress), [2048.0, 4096.0]);
+        assert_eq!(rates(Some("ab"), None, Some("videos"), Direction::Ingress), [1024.0]);
+        assert!(rates(None, None, Some("photos"), Direction::Egress).is_empty());
+
+        let mut config = bandwidth.config();
+        config.limits.remove("key/*");
+        bandwidth.set_config(config);
+        assert!(rates(Some("ab"), None, None, Direction::Egress).is_empty());
+        assert_eq!(bandwidth.config().specs(), ["bucket/videos=-/4", "principal/alice=2/2"]);
+    }
+}
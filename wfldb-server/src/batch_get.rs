@@ -0,0 +1,213 @@
+//! Batch GET of many keys in one request
+//!
+//! ```text
+//! POST /v1/{bucket}/_mget  {"keys": ["a", "b", ...]}
+//! ```
+//!
+//! The answer is a multiplexed body (see [`wfldb_net::multiplex`]) with one
+//! stream per requested key, numbered in request order, so reading a
+//! thousand small objects costs one round trip. Each key is read and
+//! authorized as a GET of it would be; a key that is missing, expired,
+//! held in quarantine, encrypted with a customer key, or outside the
+//! caller's caveats or ownership fails on its own stream while the rest are
+//! still sent. Small objects are read up front; chunked objects are
+//! streamed chunk by chunk as the connection takes them, like a GET of
+//! them, and a chunk that can't be read aborts only that object's stream.
+//!
+//! The request is a read sent as POST: it needs read permission on the
+//! bucket and is served by replicas and frozen buckets.
+
+use bytes::Bytes;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+use wfldb_auth::{now_ms, AuthContext, AuthError, BucketAcl};
+use wfldb_core::{BucketId, Key, WflDBError};
+use wfldb_engine::Storage;
+use wfldb_net::{EntryHeader, MuxFrame, MAX_MGET_KEYS, MUX_CONTENT_TYPE};
+use crate::{auth, sse, transport};
+use crate::error::ApiError;
+use crate::response_cache::{self, CachedRead};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Object key segment that addresses a bucket's batch GET endpoint
+pub const MGET_SEGMENT: &str = "_mget";
+
+/// Largest batch GET body: [`MAX_MGET_KEYS`] keys of up to 1 KiB, with room
+/// for JSON escapes
+const MAX_MGET_BODY_BYTES: usize = MAX_MGET_KEYS * 2 * 1024;
+
+#[derive(Deserialize)]
+struct BatchGetRequest {
+    keys: Vec<String>,
+}
+
+/// Whether `path` is a bucket's batch GET endpoint, "/v1/{bucket}/_mget"
+pub fn is_mget_path(path: &str) -> bool {
+    path.strip_prefix("/v1/")
+        .and_then(|rest| rest.split_once('/'))
+        .is_some_and(|(bucket, key)| !bucket.is_empty() && key == MGET_SEGMENT)
+}
+
+/// Handle `POST /v1/{bucket}/_mget`; read permission on the bucket was
+/// already checked, caveats and ownership are checked here key by key
+pub async fn handle(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let body_bytes = match state.spool.read(req.into_body(), MAX_MGET_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(auth_ctx, &body_bytes, timings) {
+        return e.into_response();
+    }
+    let request: BatchGetRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(request) => request,
+        Err(e) => return ApiError::bad_request(format!("Invalid batch GET: {}", e)).into_response(),
+    };
+    if request.keys.len() > MAX_MGET_KEYS {
+        return ApiError::bad_request(format!("A batch GET asks for at most {} keys", MAX_MGET_KEYS)).into_response();
+    }
+    let keys = match request.keys.iter().map(|key| Key::new(key).map_err(|_| key)).collect::<Result<Vec<_>, _>>() {
+        Ok(keys) => keys,
+        Err(key) => return ApiError::bad_request(format!("Invalid key '{}'", key)).into_response(),
+    };
+
+    let entries: Vec<BoxStream<'static, Result<Bytes, WflDBError>>> = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        keys.into_iter()
+            .enumerate()
+            .map(|(id, key)| {
+                let id = id as u32;
+                match read_entry(state, storage, bucket_id, &key, auth_ctx) {
+                    Ok(CachedRead::Found(data, metadata)) => {
+                        let header = EntryHeader::found(key.as_str(), metadata.version.to_string(), metadata.generation, metadata.size);
+                        let frames = std::iter::once(MuxFrame::header(id, &header))
+                            .chain(MuxFrame::data_frames(id, &data))
+                            .chain([MuxFrame::end(id)]);
+                        send(frames)
+                    }
+                    Ok(CachedRead::Chunked(manifest, metadata)) => {
+                        let header = EntryHeader::found(key.as_str(), metadata.version.to_string(), metadata.generation, metadata.size);
+                        let chunks = transport::chunk_body(storage.engine().clone(), bucket_id.clone(), key.clone(), manifest);
+                        send([MuxFrame::header(id, &header)]).chain(chunk_frames(id, key, chunks)).boxed()
+                    }
+                    Ok(CachedRead::NotModified(_) | CachedRead::Missing) => {
+                        send([MuxFrame::header(id, &EntryHeader::not_found(key.as_str())), MuxFrame::end(id)])
+                    }
+                    Err(header) => send([MuxFrame::header(id, &header), MuxFrame::end(id)]),
+                }
+            })
+            .collect()
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", MUX_CONTENT_TYPE)
+        .body(Body::wrap_stream(stream::iter(entries).flatten()))
+        .unwrap()
+}
+
+/// Read one key of the batch as a GET of it would, short of the body;
+/// a failure is the header of the key's stream, boxed since it is large
+fn read_entry(
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+) -> Result<CachedRead, Box<EntryHeader>> {
+    let failed = |e: ApiError| Box::new(e.entry_header(key.as_str()));
+    let storage_error = |e: WflDBError| failed(ApiError::from_storage(&e, now_ms()));
+    if let (Some(authenticator), Some(ctx)) = (&state.auth, auth_ctx) {
+        let denied = |e: AuthError| Box::new(EntryHeader::error(key.as_str(), 403, e.code(), e.to_string(), false));
+        ctx.authorize_key(Some(key.as_str())).map_err(denied)?;
+        let acl = authenticator.bucket_acls().get(bucket_id);
+        if acl == BucketAcl::OwnerOnly && ctx.tenant().is_none() {
+            let owner = storage.get_metadata(bucket_id, key).map_err(storage_error)?.and_then(|metadata| metadata.owner);
+            ctx.authorize_owner(acl, owner.as_deref()).map_err(denied)?;
+        }
+    }
+    let admin = auth_ctx.is_some_and(|ctx| ctx.permissions().can_admin());
+    if state.quarantine.is_some() && !admin && storage.quarantine_entry(bucket_id, key).map_err(storage_error)?.is_some() {
+        return Err(failed(ApiError::quarantined()));
+    }
+
+    let read = response_cache::read_object(storage, state.response_cache.as_ref(), bucket_id, key, None, true)
+        .map_err(storage_error)?;
+    // Expired objects read as missing until they're purged
+    if let CachedRead::Found(_, metadata) | CachedRead::Chunked(_, metadata) = &read {
+        let expires_at_ms = storage.object_expiry(bucket_id, key, &metadata.version).map_err(storage_error)?;
+        if expires_at_ms.is_some_and(|expires_at_ms| now_ms() >= expires_at_ms) {
+            return Ok(CachedRead::Missing);
+        }
+    }
+    // Without the customer key an encrypted object can only be refused
+    let read = sse::open(read, None, storage, bucket_id, key).map_err(failed)?;
+    if let (Some(hot_keys), CachedRead::Found(..) | CachedRead::Chunked(..)) = (&state.hot_keys, &read) {
+        hot_keys.record(&storage.engine().namespaced(bucket_id), key.as_str());
+    }
+    Ok(read)
+}
+
+/// A stream's frames that are ready now, as one piece of the body
+fn send(frames: impl IntoIterator<Item = MuxFrame>) -> BoxStream<'static, Result<Bytes, WflDBError>> {
+    let bytes: Vec<u8> = frames.into_iter().flat_map(|frame| frame.to_bytes()).collect();
+    stream::once(future::ready(Ok(Bytes::from(bytes)))).boxed()
+}
+
+/// Data frames for a chunked object's chunks as they are read, then `End`,
+/// or `Abort` once a chunk can't be read
+fn chunk_frames(
+    id: u32,
+    key: Key,
+    chunks: impl futures::Stream<Item = Result<Bytes, WflDBError>> + Send + 'static,
+) -> impl futures::Stream<Item = Result<Bytes, WflDBError>> + Send + 'static {
+    chunks
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .scan(false, move |aborted, chunk| {
+            let frames: Vec<u8> = match chunk {
+                _ if *aborted => return future::ready(None),
+                Some(Ok(data)) => MuxFrame::data_frames(id, &data).flat_map(|frame| frame.to_bytes()).collect(),
+                Some(Err(e)) => {
+                    *aborted = true;
+                    let header = EntryHeader::error(key.as_str(), e.http_status(), e.code(), e.to_string(), e.is_retryable());
+                    MuxFrame::abort(id, &header).to_bytes()
+                }
+                None => MuxFrame::end(id).to_bytes(),
+            };
+            future::ready(Some(Ok(Bytes::from(frames))))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_net::{MuxDecoder, MuxFrameKind};
+
+    #[test]
+    fn test_is_mget_path() {
+        assert!(is_mget_path("/v1/photos/_mget"));
+        assert!(!is_mget_path("/v1/photos/dir/_mget"));
+        assert!(!is_mget_path("/v1//_mget"));
+    }
+
+    #[tokio::test]
+    async fn test_chunk_frames_abort_on_failure() {
+        let key = Key::new("big.bin").unwrap();
+        let chunks = stream::iter(vec![Ok(Bytes::from_static(b"one")), Err(WflDBError::MissingChunks(vec!["ab".to_string()]))]);
+        let body: Vec<Bytes> = chunk_frames(3, key, chunks).map(Result::unwrap).collect().await;
+        let mut decoder = MuxDecoder::new();
+        body.iter().for_each(|bytes| decoder.push(bytes));
+        let kinds: Vec<MuxFrameKind> = std::iter::from_fn(|| decoder.next_frame().unwrap()).map(|frame| frame.kind).collect();
+        assert_eq!(kinds, [MuxFrameKind::Data, MuxFrameKind::Abort]);
+    }
+}
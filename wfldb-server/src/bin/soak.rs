@@ -0,0 +1,383 @@
+//! Soak test: run the server under a mixed workload for hours and fail on
+//! slow leaks
+//!
+//! Starts the server binary with authentication on, then keeps several
+//! workers issuing signed PUTs (small and chunked), GETs, listings and
+//! deletes over a bounded set of keys, so a healthy server settles into a
+//! steady state. Every sample interval it records the server's resident
+//! memory and open file descriptors from `/proc`, and the keyspace's
+//! partition count and the nonce cache size from `/metrics`. After the
+//! warm-up, a series whose floor rises in every window of the run is
+//! reported as a leak and the run fails.
+
+use clap::{Arg, Command};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wfldb_auth::*;
+
+/// Windows a run is split into when looking for growth
+const GROWTH_WINDOWS: usize = 4;
+/// Growth of a series' floor, first window to last, below which it counts as noise
+const GROWTH_TOLERANCE: f64 = 0.10;
+/// Failed requests, as a fraction of all requests, above which the run fails
+const MAX_ERROR_RATE: f64 = 0.001;
+
+const BUCKETS: &[&str] = &["soak-a", "soak-b", "soak-c", "soak-d"];
+const KEYS_PER_BUCKET: u64 = 500;
+const SMALL_OBJECT: usize = 2 * 1024;
+/// Past the engine's chunking threshold, so chunk refcounts are exercised
+const LARGE_OBJECT: usize = 192 * 1024;
+
+/// One resource tracked over the run
+struct Series {
+    name: &'static str,
+    samples: Vec<f64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("wfldb-soak")
+        .about("Long-running mixed workload against wfldb-server, failing on resource growth")
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .value_name("PATH")
+                .help("Server binary to run; defaults to wfldb-server beside this binary")
+        )
+        .arg(
+            Arg::new("duration-secs")
+                .long("duration-secs")
+                .value_name("SECS")
+                .help("How long to run the workload")
+                .default_value("14400")
+        )
+        .arg(
+            Arg::new("warmup-secs")
+                .long("warmup-secs")
+                .value_name("SECS")
+                .help("Samples taken before this are not checked for growth; should exceed the replay window")
+                .default_value("600")
+        )
+        .arg(
+            Arg::new("sample-secs")
+                .long("sample-secs")
+                .value_name("SECS")
+                .help("Interval between resource samples")
+                .default_value("60")
+        )
+        .arg(
+            Arg::new("workers")
+                .long("workers")
+                .value_name("N")
+                .help("Concurrent request loops")
+                .default_value("8")
+        )
+        .get_matches();
+    let secs = |name: &str| Duration::from_secs(matches.get_one::<String>(name).unwrap().parse().expect("invalid seconds"));
+    let duration = secs("duration-secs");
+    let warmup = secs("warmup-secs");
+    let sample_interval = secs("sample-secs");
+    let workers: u64 = matches.get_one::<String>("workers").unwrap().parse().expect("invalid worker count");
+    let server_bin = match matches.get_one::<String>("server") {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_exe()
+            .expect("locate this binary")
+            .with_file_name("wfldb-server"),
+    };
+
+    let root = generate_signing_key();
+    let holder = generate_signing_key();
+    let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, u32::MAX as u64)
+        .expect("issue key packet");
+    let signer = Arc::new(RequestSigner::new(holder, packet));
+
+    let mut server = SoakServer::start(&server_bin, &encode_public_key(&root.verifying_key()));
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    if let Err(reason) = server.wait_until_live(&client).await {
+        drop(server);
+        fail(&reason);
+    }
+    println!("soaking {} for {:?} with {} workers", server.addr, duration, workers);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let requests = Arc::new(AtomicU64::new(0));
+    let errors = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
+    for worker in 0..workers {
+        let load = Workload {
+            client: client.clone(),
+            signer: signer.clone(),
+            addr: server.addr,
+            requests: requests.clone(),
+            errors: errors.clone(),
+        };
+        let stop = stop.clone();
+        handles.push(tokio::spawn(async move { load.run(worker, &stop).await }));
+    }
+
+    let mut series = ["rss_bytes", "open_fds", "partitions", "nonce_cache_entries"]
+        .map(|name| Series { name, samples: Vec::new() });
+    let mut failures = Vec::new();
+    let start = Instant::now();
+    let mut warm_samples = None;
+    while start.elapsed() < duration {
+        tokio::time::sleep(sample_interval).await;
+        if let Ok(Some(status)) = server.child.try_wait() {
+            failures.push(format!("server exited mid-run: {}", status));
+            break;
+        }
+        let metrics = fetch_metrics(&client, server.addr).await;
+        let values = [
+            proc_rss_bytes(server.child.id()),
+            proc_open_fds(server.child.id()),
+            metrics.as_deref().and_then(|m| metric_value(m, "wfldb_engine_partitions")),
+            metrics.as_deref().and_then(|m| metric_value(m, "wfldb_auth_nonce_cache_entries")),
+        ];
+        for (series, value) in series.iter_mut().zip(values) {
+            series.samples.push(value.unwrap_or(f64::NAN));
+        }
+        if warm_samples.is_none() && start.elapsed() >= warmup {
+            warm_samples = Some(series[0].samples.len());
+        }
+        println!(
+            "{:>7}s  rss {:>8.1} MiB  fds {:>5}  partitions {:>4}  nonces {:>7}  requests {:>10}  errors {}",
+            start.elapsed().as_secs(),
+            values[0].unwrap_or(f64::NAN) / (1024.0 * 1024.0),
+            values[1].unwrap_or(f64::NAN),
+            values[2].unwrap_or(f64::NAN),
+            values[3].unwrap_or(f64::NAN),
+            requests.load(Ordering::Relaxed),
+            errors.load(Ordering::Relaxed),
+        );
+    }
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        let _ = handle.await;
+    }
+
+    let checked_from = warm_samples.unwrap_or(usize::MAX);
+    for series in &series {
+        let samples = series.samples.get(checked_from..).unwrap_or_default();
+        if samples.len() < GROWTH_WINDOWS * 2 {
+            println!("{}: too few samples after warm-up to check for growth", series.name);
+        } else if is_growing(samples, GROWTH_TOLERANCE) {
+            failures.push(format!("{} grew steadily after warm-up ({:?})", series.name, window_floors(samples)));
+        }
+    }
+    let total = requests.load(Ordering::Relaxed);
+    let failed = errors.load(Ordering::Relaxed);
+    if total == 0 || failed as f64 > total as f64 * MAX_ERROR_RATE {
+        failures.push(format!("{} of {} requests failed", failed, total));
+    }
+    drop(server);
+    if !failures.is_empty() {
+        fail(&failures.join("; "));
+    }
+    println!("soak passed: {} requests, {} failed", total, failed);
+}
+
+fn fail(reason: &str) -> ! {
+    eprintln!("soak failed: {}", reason);
+    std::process::exit(1);
+}
+
+/// Whether a series' floor rises across the run: its minimum in each of
+/// [`GROWTH_WINDOWS`] equal windows exceeds the previous window's, and the
+/// last window's is more than `tolerance` above the first's
+///
+/// Floors rather than means, so caches filling and draining don't look like
+/// a leak but memory that is never given back does.
+fn is_growing(samples: &[f64], tolerance: f64) -> bool {
+    let floors = window_floors(samples);
+    if floors.len() < 2 || floors.iter().any(|floor| floor.is_nan()) {
+        return false;
+    }
+    let rising = floors.windows(2).all(|pair| pair[1] > pair[0]);
+    let (first, last) = (floors[0], floors[floors.len() - 1]);
+    rising && last > first * (1.0 + tolerance)
+}
+
+fn window_floors(samples: &[f64]) -> Vec<f64> {
+    let size = samples.len() / GROWTH_WINDOWS;
+    if size == 0 {
+        return Vec::new();
+    }
+    samples
+        .chunks(size)
+        .take(GROWTH_WINDOWS)
+        .map(|window| window.iter().copied().fold(f64::INFINITY, f64::min))
+        .collect()
+}
+
+/// Server process under test, killed when dropped
+struct SoakServer {
+    child: Child,
+    addr: SocketAddr,
+    data_dir: PathBuf,
+}
+
+impl SoakServer {
+    fn start(server_bin: &Path, root_key: &str) -> SoakServer {
+        let data_dir = std::env::temp_dir().join(format!("wfldb-soak-{}", ulid::Ulid::new()));
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let child = std::process::Command::new(server_bin)
+            .arg("--data-dir").arg(&data_dir)
+            .arg("--bind").arg(addr.to_string())
+            .arg("--auth-root-key").arg(root_key)
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| fail(&format!("could not start {}: {}", server_bin.display(), e)));
+        SoakServer { child, addr, data_dir }
+    }
+
+    async fn wait_until_live(&mut self, client: &Client<HttpConnector>) -> std::result::Result<(), String> {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let uri = format!("http://{}/livez", self.addr);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return Err(format!("server exited during startup: {}", status));
+            }
+            if let Ok(response) = client.get(uri.parse().unwrap()).await {
+                if response.status() == StatusCode::OK {
+                    return Ok(());
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        Err(format!("server at {} did not come up", self.addr))
+    }
+}
+
+impl Drop for SoakServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// One worker's share of the mixed workload
+struct Workload {
+    client: Client<HttpConnector>,
+    signer: Arc<RequestSigner>,
+    addr: SocketAddr,
+    requests: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+}
+
+impl Workload {
+    async fn run(&self, worker: u64, stop: &AtomicBool) {
+        // Small xorshift generator; the workload only needs a spread of keys
+        let mut state = 0x9e37_79b9_7f4a_7c15 ^ (worker + 1).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let small = vec![0x61u8; SMALL_OBJECT];
+        // Differs per worker, so chunked writes aren't all deduplicated away
+        let large: Vec<u8> = (0..LARGE_OBJECT).map(|i| (i as u64 ^ worker) as u8).collect();
+        while !stop.load(Ordering::Relaxed) {
+            let bucket = BUCKETS[(next() % BUCKETS.len() as u64) as usize];
+            let key = format!("key-{}", next() % KEYS_PER_BUCKET);
+            let object = format!("/v1/{}/{}", bucket, key);
+            let (method, path, body, ok): (Method, String, &[u8], &[StatusCode]) = match next() % 10 {
+                0..=3 => (Method::GET, object, b"", &[StatusCode::OK, StatusCode::NOT_FOUND]),
+                4 | 5 => (Method::PUT, object, &small, &[StatusCode::CREATED]),
+                6 => (Method::PUT, object, &large, &[StatusCode::CREATED]),
+                7 => (Method::GET, format!("/v1/{}?prefix=key-1&limit=100", bucket), b"", &[StatusCode::OK]),
+                _ => (Method::DELETE, object, b"", &[StatusCode::OK, StatusCode::NOT_FOUND]),
+            };
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            match self.send(method.clone(), &path, body).await {
+                Ok(status) if ok.contains(&status) => {}
+                outcome => {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("{} {}: {:?}", method, path, outcome);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str, body: &[u8]) -> std::result::Result<StatusCode, hyper::Error> {
+        let (path_only, query) = path.split_once('?').unwrap_or((path, ""));
+        let mut request = RequestParts::new(method.as_str(), path_only);
+        request.query = query;
+        let mut builder = Request::builder()
+            .method(method.clone())
+            .uri(format!("http://{}{}", self.addr, path));
+        for (name, value) in self.signer.sign_request(&request, body, now_ms()) {
+            builder = builder.header(name, value);
+        }
+        let response = self.client.request(builder.body(Body::from(body.to_vec())).unwrap()).await?;
+        let status = response.status();
+        hyper::body::to_bytes(response.into_body()).await?;
+        Ok(status)
+    }
+}
+
+async fn fetch_metrics(client: &Client<HttpConnector>, addr: SocketAddr) -> Option<String> {
+    let response = client.get(format!("http://{}/metrics", addr).parse().unwrap()).await.ok()?;
+    let body = hyper::body::to_bytes(response.into_body()).await.ok()?;
+    String::from_utf8(body.to_vec()).ok()
+}
+
+/// Value of an unlabeled sample in Prometheus text
+fn metric_value(metrics: &str, name: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .filter_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .find_map(|value| value.trim().parse().ok())
+}
+
+fn proc_rss_bytes(pid: u32) -> Option<f64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kb: f64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024.0)
+}
+
+fn proc_open_fds(pid: u32) -> Option<f64> {
+    Some(std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?.count() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_growing() {
+        // A leak raises the floor in every window
+        let leak: Vec<f64> = (0..40).map(|i| 100.0 + i as f64 * 2.0).collect();
+        assert!(is_growing(&leak, GROWTH_TOLERANCE));
+        // A cache that fills and drains keeps returning to the same floor
+        let sawtooth: Vec<f64> = (0..40).map(|i| 100.0 + (i % 5) as f64 * 20.0).collect();
+        assert!(!is_growing(&sawtooth, GROWTH_TOLERANCE));
+        // Rising, but by less than the tolerance
+        let creep: Vec<f64> = (0..40).map(|i| 1000.0 + i as f64).collect();
+        assert!(!is_growing(&creep, GROWTH_TOLERANCE));
+        // Missing samples never count as growth
+        assert!(!is_growing(&[f64::NAN; 40], GROWTH_TOLERANCE));
+    }
+
+    #[test]
+    fn test_metric_value() {
+        let metrics = "# TYPE wfldb_engine_partitions gauge\nwfldb_engine_partitions 7\nwfldb_engine_partitions_total 9\n";
+        assert_eq!(metric_value(metrics, "wfldb_engine_partitions"), Some(7.0));
+        assert_eq!(metric_value(metrics, "wfldb_engine_segments"), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! Fault injection for failure drills
+//!
+//! A server started with `--enable-chaos` lets admins switch on faults at
+//! run time through `/admin/chaos`, so a game day against a staging node
+//! can check that clients retry and alerts fire:
+//!
+//! - `storage_latency_ms` delays every `/v1` request by that long before its
+//!   storage work, counted as storage time in `Server-Timing`
+//! - `write_failure_percent` answers that share of `/v1` writes with a
+//!   retryable 503 `injected_failure` instead of applying them
+//! - `drop_connection_percent` aborts that share of `/v1` responses after
+//!   the status line, which closes an HTTP/1 connection and resets an
+//!   HTTP/2 stream
+//!
+//! Only `/v1` is affected, so health checks, metrics and the admin API
+//! stay usable to watch the drill and turn the faults off. Every fault
+//! starts off and is off again after a restart.
+
+use hyper::{Body, Response, StatusCode};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Longest latency that may be injected
+const MAX_LATENCY_MS: u64 = 60_000;
+
+/// Faults currently injected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChaosSettings {
+    #[serde(default)]
+    pub storage_latency_ms: u64,
+    #[serde(default)]
+    pub write_failure_percent: u8,
+    #[serde(default)]
+    pub drop_connection_percent: u8,
+}
+
+impl ChaosSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.storage_latency_ms > MAX_LATENCY_MS {
+            return Err(format!("storage_latency_ms must be at most {}", MAX_LATENCY_MS));
+        }
+        if self.write_failure_percent > 100 || self.drop_connection_percent > 100 {
+            return Err("percentages must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self != ChaosSettings::default()
+    }
+}
+
+/// Switchable faults and counts of those injected
+#[derive(Debug, Default)]
+pub struct Chaos {
+    settings: RwLock<ChaosSettings>,
+    delayed: AtomicU64,
+    failed_writes: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Chaos {
+    pub fn settings(&self) -> ChaosSettings {
+        *self.settings.read().unwrap()
+    }
+
+    pub fn set(&self, settings: ChaosSettings) {
+        *self.settings.write().unwrap() = settings;
+    }
+
+    /// Delay to add before a request's storage work
+    pub fn storage_delay(&self) -> Option<Duration> {
+        let ms = self.settings().storage_latency_ms;
+        if ms == 0 {
+            return None;
+        }
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+        Some(Duration::from_millis(ms))
+    }
+
+    /// Whether to fail this write
+    pub fn fail_write(&self) -> bool {
+        let fail = roll(self.settings().write_failure_percent);
+        if fail {
+            self.failed_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        fail
+    }
+
+    /// A response to abort in place of this one, if its connection is to drop
+    pub fn drop_connection(&self) -> Option<Response<Body>> {
+        if !roll(self.settings().drop_connection_percent) {
+            return None;
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        let (sender, body) = Body::channel();
+        // A body that fails before its first byte makes hyper tear down the
+        // connection (HTTP/1) or stream (HTTP/2) it was sent on
+        sender.abort();
+        Some(
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("content-length", "1")
+                .body(body)
+                .unwrap(),
+        )
+    }
+
+    /// Requests delayed so far
+    pub fn delayed(&self) -> u64 {
+        self.delayed.load(Ordering::Relaxed)
+    }
+
+    /// Writes failed so far
+    pub fn failed_writes(&self) -> u64 {
+        self.failed_writes.load(Ordering::Relaxed)
+    }
+
+    /// Responses aborted so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn roll(percent: u8) -> bool {
+    percent > 0 && rand::thread_rng().gen_range(0..100) < percent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_validate() {
+        assert!(ChaosSettings::default().validate().is_ok());
+        assert!(!ChaosSettings::default().is_active());
+        let settings = ChaosSettings { write_failure_percent: 101, ..Default::default() };
+        assert!(settings.validate().is_err());
+        let settings = ChaosSettings { storage_latency_ms: MAX_LATENCY_MS + 1, ..Default::default() };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_faults_follow_settings() {
+        let chaos = Chaos::default();
+        assert_eq!(chaos.storage_delay(), None);
+        assert!(!chaos.fail_write());
+        assert!(chaos.drop_connection().is_none());
+
+        chaos.set(ChaosSettings { storage_latency_ms: 25, write_failure_percent: 100, drop_connection_percent: 100 });
+        assert_eq!(chaos.storage_delay(), Some(Duration::from_millis(25)));
+        assert!(chaos.fail_write());
+        assert!(chaos.drop_connection().is_some());
+        assert_eq!((chaos.delayed(), chaos.failed_writes(), chaos.dropped()), (1, 1, 1));
+    }
+}
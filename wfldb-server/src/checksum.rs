@@ -0,0 +1,139 @@
+//! Object checksums beside BLAKE3
+//!
+//! Clients migrating from S3 or GCS validate objects with CRC32C or
+//! SHA-256. A PUT asks for them with `x-wfldb-checksum-algorithm:
+//! crc32c,sha256`, or by sending the value it expects as
+//! `x-wfldb-checksum-{algorithm}`, which is checked against the body and
+//! answers 400 `checksum_mismatch` if they differ. Buckets can also be set
+//! to keep some algorithms for every PUT. Values are base64 of the
+//! big-endian digest, like S3's `x-amz-checksum-*` headers; they are stored
+//! with the object and a plain GET returns them in the same headers.
+//!
+//! Checksums cover the body of one PUT. Objects committed from chunks,
+//! parts, composes or range writes carry only their BLAKE3 hashes.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hyper::header::HeaderMap;
+use hyper::http::response::Builder;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use wfldb_core::ChecksumAlgorithm;
+use crate::error::ApiError;
+
+/// Comma-separated algorithms a PUT asks to have computed
+pub const ALGORITHM_HEADER: &str = "x-wfldb-checksum-algorithm";
+
+/// Header carrying an object's `algorithm` checksum, both ways
+pub fn header_name(algorithm: ChecksumAlgorithm) -> String {
+    format!("x-wfldb-checksum-{}", algorithm.as_str())
+}
+
+/// Checksum of `data` as stored and sent in headers
+pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+    match algorithm {
+        ChecksumAlgorithm::Crc32c => STANDARD.encode(crc32c::crc32c(data).to_be_bytes()),
+        ChecksumAlgorithm::Sha256 => STANDARD.encode(Sha256::digest(data)),
+    }
+}
+
+/// Parse a comma-separated list of algorithm names
+fn parse_algorithms(list: &str) -> Result<BTreeSet<ChecksumAlgorithm>, String> {
+    list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| ChecksumAlgorithm::parse(name).ok_or_else(|| format!("unknown checksum algorithm '{}'", name.trim())))
+        .collect()
+}
+
+/// Which algorithms each bucket computes on every PUT
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumConfig {
+    /// Algorithms by bucket name
+    pub buckets: HashMap<String, BTreeSet<ChecksumAlgorithm>>,
+}
+
+impl ChecksumConfig {
+    /// Parse a `{bucket}={algorithm},...` setting, e.g. `imports=crc32c,sha256`
+    pub fn parse_bucket(spec: &str) -> Result<(String, BTreeSet<ChecksumAlgorithm>), String> {
+        let (bucket, algorithms) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected BUCKET=ALGORITHM[,ALGORITHM], got '{}'", spec))?;
+        Ok((bucket.to_string(), parse_algorithms(algorithms)?))
+    }
+
+    /// Checksums of a PUT body to `bucket`: those the bucket keeps and those
+    /// the request asked for or sent, the sent ones checked against `data`
+    pub fn for_put(
+        &self,
+        bucket: &str,
+        headers: &HeaderMap,
+        data: &[u8],
+    ) -> Result<BTreeMap<ChecksumAlgorithm, String>, ApiError> {
+        let mut algorithms = self.buckets.get(bucket).cloned().unwrap_or_default();
+        if let Some(requested) = headers.get(ALGORITHM_HEADER) {
+            let requested = requested
+                .to_str()
+                .map_err(|_| ApiError::bad_request("Invalid checksum algorithm header"))?;
+            algorithms.extend(parse_algorithms(requested).map_err(ApiError::bad_request)?);
+        }
+        let mut checksums = BTreeMap::new();
+        for algorithm in ChecksumAlgorithm::ALL {
+            let expected = headers.get(header_name(algorithm)).map(|value| value.to_str().unwrap_or_default());
+            if expected.is_none() && !algorithms.contains(&algorithm) {
+                continue;
+            }
+            let actual = compute(algorithm, data);
+            if expected.is_some_and(|expected| expected.trim() != actual) {
+                return Err(ApiError::checksum_mismatch(algorithm.as_str()));
+            }
+            checksums.insert(algorithm, actual);
+        }
+        Ok(checksums)
+    }
+}
+
+/// Add an object's checksums to its response
+pub fn with_headers(mut response: Builder, checksums: &BTreeMap<ChecksumAlgorithm, String>) -> Builder {
+    for (algorithm, value) in checksums {
+        response = response.header(header_name(*algorithm), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        // RFC 3720 test vector and the SHA-256 of the empty string
+        assert_eq!(crc32c::crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(compute(ChecksumAlgorithm::Crc32c, b"123456789"), "4waSgw==");
+        assert_eq!(
+            compute(ChecksumAlgorithm::Sha256, b""),
+            "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+    }
+
+    #[test]
+    fn test_for_put() {
+        let mut config = ChecksumConfig::default();
+        let (bucket, algorithms) = ChecksumConfig::parse_bucket("imports=CRC32C").unwrap();
+        config.buckets.insert(bucket, algorithms);
+        assert!(ChecksumConfig::parse_bucket("imports=md5").is_err());
+        assert!(ChecksumConfig::parse_bucket("imports").is_err());
+
+        let empty = HeaderMap::new();
+        assert!(config.for_put("other", &empty, b"123456789").unwrap().is_empty());
+        let kept = config.for_put("imports", &empty, b"123456789").unwrap();
+        assert_eq!(kept.keys().copied().collect::<Vec<_>>(), [ChecksumAlgorithm::Crc32c]);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ALGORITHM_HEADER, "sha256".parse().unwrap());
+        headers.insert("x-wfldb-checksum-crc32c", "4waSgw==".parse().unwrap());
+        assert_eq!(config.for_put("other", &headers, b"123456789").unwrap().len(), 2);
+        assert!(config.for_put("other", &headers, b"12345678").is_err());
+
+        headers.insert(ALGORITHM_HEADER, "md5".parse().unwrap());
+        assert!(config.for_put("other", &headers, b"123456789").is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! Content-addressed chunk uploads
+//!
+//! ```text
+//! PUT  /v1/{bucket}/_chunks/{hash}    <chunk bytes>                  store a chunk
+//! HEAD /v1/{bucket}/_chunks/{hash}                                   200 if stored, 404 if not
+//! POST /v1/{bucket}/_chunks/exists   {"chunks": ["<hash>", ...]}    which of the chunks are stored
+//! PUT  /v1/{bucket}/_manifest/{key}   {"chunks": ["<hash>", ...]}    commit an object from stored chunks
+//! POST /v1/{bucket}/_compose/{key}    {"sources": ["<key>", ...]}    concatenate objects of the bucket
+//! PUT  /v1/{bucket}/_range/{key}?offset=N   <bytes>                 overwrite bytes from offset N
+//! ```
+//!
+//! Sync clients split large files themselves, upload only the chunks the
+//! bucket doesn't hold yet, and then commit the object as its manifest: the
+//! chunk hashes in order. `exists` checks many chunks at once, answering
+//! `{"stored": [...], "missing": [...]}`. Hashes are BLAKE3 in hex, a
+//! chunk's body must hash to the one in its path, and chunks are at most
+//! [`MAX_CHUNK_SIZE`](wfldb_engine::MAX_CHUNK_SIZE). A commit naming chunks
+//! the bucket doesn't store answers 400 `missing_chunks` listing them.
+//! Uploaded chunks no manifest ever uses are orphans to the refcount pass,
+//! which removes them on repair.
+//!
+//! A compose creates `{key}` from up to
+//! [`MAX_COMPOSE_SOURCES`](wfldb_engine::MAX_COMPOSE_SOURCES) objects of
+//! the same bucket in order, referencing their chunks rather than copying
+//! them, for assembling large outputs from part files; a missing source
+//! answers 404, and one encrypted with a customer key 400. Existence checks
+//! need read permission on the bucket, uploads and commits write
+//! permission, and composes both. A commit or compose to an owner-only
+//! bucket must come from the object's owner, who must also own every
+//! source.
+//!
+//! A range write replaces the bytes of a chunked object from `offset` on
+//! with the body, growing the object if it runs past the end, and stores
+//! only the chunks the range touches; the rest of the manifest is kept.
+//! The body is at most [`MAX_CHUNK_SIZE`](wfldb_engine::MAX_CHUNK_SIZE),
+//! `offset` at most the object's size, and an object stored inline answers
+//! 400, since rewriting it whole costs no more. So does an object encrypted
+//! with a customer key: its chunks hold ciphertext that can't be spliced.
+
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use wfldb_auth::{now_ms, AuthContext, BucketAcl};
+use wfldb_core::{BucketId, ContentHash, Key};
+use wfldb_engine::{Storage, MAX_CHUNK_SIZE, MAX_MANIFEST_CHUNKS};
+use wfldb_net::query_param;
+use crate::auth;
+use crate::error::ApiError;
+use crate::router::{json_response, method_not_allowed};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+use crate::spool::{Spool, SpooledBody};
+
+/// Path segment between the bucket and the hash of a chunk route
+const CHUNKS_SEGMENT: &str = "_chunks";
+/// Path segment between the bucket and the key of a manifest commit
+const MANIFEST_SEGMENT: &str = "_manifest";
+/// Path segment between the bucket and the key of a compose
+const COMPOSE_SEGMENT: &str = "_compose";
+/// Path segment between the bucket and the key of a range write
+const RANGE_SEGMENT: &str = "_range";
+/// Last segment of the batch existence check
+const EXISTS_SEGMENT: &str = "exists";
+
+/// Largest chunk list body: hex hashes plus JSON punctuation
+const MAX_CHUNK_LIST_BYTES: usize = MAX_MANIFEST_CHUNKS * 80;
+
+/// A chunk upload route
+pub enum ChunkRoute {
+    /// `/v1/{bucket}/_chunks/{hash}`
+    Chunk(BucketId, ContentHash),
+    /// `/v1/{bucket}/_chunks/exists`
+    Exists(BucketId),
+    /// `/v1/{bucket}/_manifest/{key}`
+    Manifest(BucketId, Key),
+    /// `/v1/{bucket}/_compose/{key}`
+    Compose(BucketId, Key),
+    /// `/v1/{bucket}/_range/{key}`
+    Range(BucketId, Key),
+}
+
+/// Body of a compose
+#[derive(Deserialize)]
+struct ComposeRequest {
+    sources: Vec<String>,
+}
+
+/// Body of a manifest commit or existence check
+#[derive(Deserialize)]
+struct ChunkList {
+    chunks: Vec<String>,
+}
+
+impl ChunkList {
+    /// Parse a body of at most `MAX_MANIFEST_CHUNKS` hashes
+    fn parse(body: &[u8]) -> std::result::Result<Vec<ContentHash>, ApiError> {
+        let list: ChunkList = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid chunk list: {}", e)))?;
+        if list.chunks.len() > MAX_MANIFEST_CHUNKS {
+            return Err(ApiError::bad_request(format!("At most {} chunks per request", MAX_MANIFEST_CHUNKS)));
+        }
+        list.chunks
+            .iter()
+            .map(|hex| ContentHash::from_hex(hex))
+            .collect::<wfldb_core::Result<_>>()
+            .map_err(|e| ApiError::from_storage(&e, now_ms()))
+    }
+}
+
+impl ChunkRoute {
+    /// Method the route's permission is checked as; an existence check only
+    /// reads, and a compose writes (its reads are checked by the handler)
+    pub fn auth_action<'a>(&self, method: &'a Method) -> &'a str {
+        match self {
+            ChunkRoute::Exists(_) => "GET",
+            ChunkRoute::Compose(_, _) => "PUT",
+            _ => method.as_str(),
+        }
+    }
+}
+
+/// Parse a chunk or manifest path; `None` if the path isn't one
+pub fn parse_chunk_path(path: &str) -> Option<std::result::Result<ChunkRoute, String>> {
+    let (bucket, rest) = path.strip_prefix("/v1/")?.split_once('/')?;
+    let (segment, rest) = rest.split_once('/')?;
+    if ![CHUNKS_SEGMENT, MANIFEST_SEGMENT, COMPOSE_SEGMENT, RANGE_SEGMENT].contains(&segment) {
+        return None;
+    }
+    let bucket_id = match BucketId::new(bucket) {
+        Ok(bucket_id) => bucket_id,
+        Err(_) => return Some(Err("Invalid bucket name".to_string())),
+    };
+    Some(if segment == CHUNKS_SEGMENT && rest == EXISTS_SEGMENT {
+        Ok(ChunkRoute::Exists(bucket_id))
+    } else if segment == CHUNKS_SEGMENT {
+        ContentHash::from_hex(rest)
+            .map(|hash| ChunkRoute::Chunk(bucket_id, hash))
+            .map_err(|e| e.to_string())
+    } else {
+        Key::from_url(rest)
+            .map(|key| match segment {
+                MANIFEST_SEGMENT => ChunkRoute::Manifest(bucket_id, key),
+                RANGE_SEGMENT => ChunkRoute::Range(bucket_id, key),
+                _ => ChunkRoute::Compose(bucket_id, key),
+            })
+            .map_err(|_| "Invalid key".to_string())
+    })
+}
+
+/// Handle a chunk route; bucket permission was already checked
+pub async fn handle(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    route: ChunkRoute,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    match (req.method().clone(), route) {
+        (Method::HEAD, ChunkRoute::Chunk(bucket_id, hash)) => {
+            let stored = timings.time(Phase::Storage, || {
+                let _busy = state.diagnostics.enter_storage();
+                storage.engine().bucket(&bucket_id)?.has_local_chunk(&hash)
+            });
+            match stored {
+                Ok(true) => Response::builder().status(StatusCode::OK).body(Body::empty()).unwrap(),
+                Ok(false) => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap(),
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        (Method::PUT, ChunkRoute::Chunk(bucket_id, hash)) => {
+            let body = match read_body(req, &state.spool, auth_ctx, timings, MAX_CHUNK_SIZE).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let actual = timings.time(Phase::Auth, || ContentHash::new(&body));
+            if actual != hash {
+                return ApiError::bad_request("Chunk body does not match its hash").into_response();
+            }
+            let stored = timings.time(Phase::ChunkIo, || {
+                let _busy = state.diagnostics.enter_storage();
+                storage.put_chunk(&bucket_id, &body)
+            });
+            match stored {
+                Ok((hash, new)) => json_response(
+                    if new { StatusCode::CREATED } else { StatusCode::OK },
+                    json!({"hash": hash.to_hex(), "size": body.len()}),
+                ),
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        (Method::POST, ChunkRoute::Exists(bucket_id)) => {
+            let body = match read_body(req, &state.spool, auth_ctx, timings, MAX_CHUNK_LIST_BYTES).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let chunks = match ChunkList::parse(&body) {
+                Ok(chunks) => chunks,
+                Err(e) => return e.into_response(),
+            };
+            let checked = timings.time(Phase::Storage, || {
+                let _busy = state.diagnostics.enter_storage();
+                let bucket = storage.engine().bucket(&bucket_id)?;
+                let mut stored = Vec::new();
+                let mut missing = Vec::new();
+                for hash in &chunks {
+                    if bucket.has_local_chunk(hash)? {
+                        stored.push(hash.to_hex());
+                    } else {
+                        missing.push(hash.to_hex());
+                    }
+                }
+                Ok((stored, missing))
+            });
+            match checked {
+                Ok((stored, missing)) => json_response(StatusCode::OK, json!({"stored": stored, "missing": missing})),
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        (Method::PUT, ChunkRoute::Manifest(bucket_id, key)) => {
+            let body = match read_body(req, &state.spool, auth_ctx, timings, MAX_CHUNK_LIST_BYTES).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let chunks = match ChunkList::parse(&body) {
+                Ok(chunks) => chunks,
+                Err(e) => return e.into_response(),
+            };
+            if let Some(response) = owner_rejection(state, storage, &bucket_id, &key, auth_ctx) {
+                return response;
+            }
+
+            let committed = timings.time(Phase::Storage, || {
+                let _busy = state.diagnostics.enter_storage();
+                let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+                storage.put_manifest_as(&bucket_id, &key, chunks, owner.as_deref())
+            });
+            match committed {
+                Ok(metadata) => {
+                    if let Some(cache) = &state.response_cache {
+                        cache.invalidate(&storage.engine().namespaced(&bucket_id), key.as_str());
+                    }
+                    json_response(StatusCode::CREATED, json!({
+                        "success": true,
+                        "bucket": bucket_id.as_str(),
+                        "key": key.as_str(),
+                        "size": metadata.size,
+                        "version": metadata.version.to_string(),
+                        "generation": metadata.generation,
+                        "chunked": true,
+                    }))
+                }
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        (Method::POST, ChunkRoute::Compose(bucket_id, key)) => {
+            let body = match read_body(req, &state.spool, auth_ctx, timings, MAX_CHUNK_LIST_BYTES).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            let request: ComposeRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return ApiError::bad_request(format!("Invalid compose request: {}", e)).into_response(),
+            };
+            let sources = match request.sources.iter().map(|source| Key::new(source)).collect::<wfldb_core::Result<Vec<_>>>() {
+                Ok(sources) => sources,
+                Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+            };
+            // Composing reads every source
+            if let Some(ctx) = auth_ctx {
+                if let Err(e) = ctx.authorize("GET", &bucket_id) {
+                    return auth::error_response(&e);
+                }
+            }
+            for object in sources.iter().chain([&key]) {
+                if let Some(response) = owner_rejection(state, storage, &bucket_id, object, auth_ctx) {
+                    return response;
+                }
+            }
+
+            let composed = timings.time(Phase::Storage, || {
+                let _busy = state.diagnostics.enter_storage();
+                let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+                storage.compose_as(&bucket_id, &key, &sources, owner.as_deref())
+            });
+            match composed {
+                Ok(metadata) => {
+                    if let Some(cache) = &state.response_cache {
+                        cache.invalidate(&storage.engine().namespaced(&bucket_id), key.as_str());
+                    }
+                    json_response(StatusCode::CREATED, json!({
+                        "success": true,
+                        "bucket": bucket_id.as_str(),
+                        "key": key.as_str(),
+                        "size": metadata.size,
+                        "version": metadata.version.to_string(),
+                        "generation": metadata.generation,
+                        "chunked": metadata.is_chunked(),
+                    }))
+                }
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        (Method::PUT, ChunkRoute::Range(bucket_id, key)) => {
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let Some(offset) = query_param(&query, "offset").and_then(|offset| offset.parse::<u64>().ok()) else {
+                return ApiError::bad_request("Expected a numeric offset parameter").into_response();
+            };
+            let body = match read_body(req, &state.spool, auth_ctx, timings, MAX_CHUNK_SIZE).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            if let Some(response) = owner_rejection(state, storage, &bucket_id, &key, auth_ctx) {
+                return response;
+            }
+
+            let written = timings.time(Phase::ChunkIo, || {
+                let _busy = state.diagnostics.enter_storage();
+                let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+                storage.write_range_as(&bucket_id, &key, offset, &body, owner.as_deref())
+            });
+            match written {
+                Ok(metadata) => {
+                    if let Some(cache) = &state.response_cache {
+                        cache.invalidate(&storage.engine().namespaced(&bucket_id), key.as_str());
+                    }
+                    json_response(StatusCode::OK, json!({
+                        "success": true,
+                        "bucket": bucket_id.as_str(),
+                        "key": key.as_str(),
+                        "size": metadata.size,
+                        "version": metadata.version.to_string(),
+                        "generation": metadata.generation,
+                        "chunked": true,
+                    }))
+                }
+                Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+            }
+        }
+        _ => method_not_allowed(),
+    }
+}
+
+/// Read a body of at most `limit` bytes, checking it with [`auth::check_body`]
+pub async fn read_body(
+    req: Request<Body>,
+    spool: &Spool,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+    limit: usize,
+) -> std::result::Result<SpooledBody, Response<Body>> {
+    // Checked against the header too: a body wrapped for its read timeout
+    // no longer knows its length
+    let declared = req.headers().get(hyper::header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()?.parse().ok());
+    if declared.unwrap_or(0).max(req.body().size_hint().lower()) > limit as u64 {
+        return Err(ApiError::too_large(format!("Body exceeds {} bytes", limit)).into_response());
+    }
+    let body = spool.read(req.into_body(), limit).await.map_err(ApiError::into_response)?;
+    auth::check_body(auth_ctx, &body, timings).map_err(ApiError::into_response)?;
+    Ok(body)
+}
+
+/// Refusal if the bucket is owner-only and the caller doesn't own the object
+pub fn owner_rejection(
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+) -> Option<Response<Body>> {
+    let (Some(authenticator), Some(ctx)) = (&state.auth, auth_ctx) else {
+        return None;
+    };
+    let acl = authenticator.bucket_acls().get(bucket_id);
+    if acl != BucketAcl::OwnerOnly || ctx.tenant().is_some() {
+        return None;
+    }
+    let owner = match storage.get_metadata(bucket_id, key) {
+        Ok(metadata) => metadata.and_then(|metadata| metadata.owner),
+        Err(e) => return Some(ApiError::from_storage(&e, now_ms()).into_response()),
+    };
+    ctx.authorize_owner(acl, owner.as_deref()).err().map(|e| auth::error_response(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wfldb_auth::{generate_signing_key, Authenticator, KeyAuthority, Permissions, RequestParts, RequestSigner};
+
+    #[test]
+    fn test_parse_chunk_path() {
+        let hash = ContentHash::new(b"chunk");
+        match parse_chunk_path(&format!("/v1/photos/_chunks/{}", hash.to_hex())) {
+            Some(Ok(ChunkRoute::Chunk(bucket, parsed))) => {
+                assert_eq!(bucket.as_str(), "photos");
+                assert_eq!(parsed, hash);
+            }
+            _ => panic!("expected a chunk route"),
+        }
+        match parse_chunk_path("/v1/photos/_manifest/albums/cat.jpg") {
+            Some(Ok(ChunkRoute::Manifest(_, key))) => assert_eq!(key.as_str(), "albums/cat.jpg"),
+            _ => panic!("expected a manifest route"),
+        }
+        assert!(matches!(parse_chunk_path("/v1/photos/_chunks/exists"), Some(Ok(ChunkRoute::Exists(_)))));
+        assert!(matches!(parse_chunk_path("/v1/logs/_compose/day.log"), Some(Ok(ChunkRoute::Compose(_, _)))));
+        assert!(matches!(parse_chunk_path("/v1/vms/_range/disk.img"), Some(Ok(ChunkRoute::Range(_, _)))));
+        assert!(matches!(parse_chunk_path("/v1/photos/_chunks/not-hex"), Some(Err(_))));
+        assert!(parse_chunk_path("/v1/photos/cat.jpg").is_none());
+        assert!(parse_chunk_path("/v1/photos/_chunks").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refuses_chunk_signed_bodies() {
+        let (root, holder) = (generate_signing_key(), generate_signing_key());
+        let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, 3_600).unwrap();
+        let authenticator = Authenticator::new(Arc::new(KeyAuthority::new(root.verifying_key())));
+        let signer = RequestSigner::new(holder, packet);
+        let path = "/v1/photos/_manifest/big.bin";
+        let (auth_headers, mut chunks) = signer.sign_streaming(&RequestParts::new("PUT", path), 1_000);
+        let parts = RequestParts {
+            method: "PUT",
+            path,
+            query: "",
+            headers: auth_headers.iter().map(|(name, value)| (*name, value.as_str())).collect(),
+        };
+        let ctx = authenticator.authenticate(&parts, 1_000).unwrap();
+
+        // Framing that would never verify, naming a chunk the client didn't sign
+        let mut body = chunks.sign_chunk(br#"{"chunks": []}"#);
+        body.extend(format!(r#"{{"chunks": ["{}"]}}"#, ContentHash::new(b"forged").to_hex()).into_bytes());
+        let req = Request::builder().method(Method::PUT).uri(path).body(Body::from(body)).unwrap();
+        let refused = read_body(req, &Spool::default(), Some(&ctx), &mut RequestTimings::start(), MAX_CHUNK_LIST_BYTES)
+            .await
+            .unwrap_err();
+        assert_eq!(refused.status(), StatusCode::BAD_REQUEST);
+    }
+}
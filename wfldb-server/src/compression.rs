@@ -0,0 +1,202 @@
+//! Response compression for GET
+//!
+//! A GET whose `Accept-Encoding` allows zstd or gzip gets a compressed body
+//! when the object is worth compressing: small enough to be stored inline,
+//! at least `min_bytes` long, and text-like. Objects don't record a content
+//! type, so bodies starting with the magic number of a compressed or media
+//! format are skipped, as is anything that isn't valid UTF-8 up front.
+//! Compression can be switched on or off per bucket.
+//!
+//! Compressed responses carry `Vary: Accept-Encoding` and a weak ETag, which
+//! still matches `If-None-Match` for the object version.
+
+use flate2::write::GzEncoder;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Bytes of the body checked for text
+const SNIFF_LEN: usize = 512;
+
+/// Leading bytes of formats that are already compressed
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x1f\x8b",             // gzip
+    b"\x28\xb5\x2f\xfd",     // zstd
+    b"PK\x03\x04",           // zip, docx, jar
+    b"BZh",                  // bzip2
+    b"\xfd7zXZ\x00",         // xz
+    b"7z\xbc\xaf\x27\x1c",   // 7-Zip
+    b"\x89PNG",              // png
+    b"\xff\xd8\xff",         // jpeg
+    b"GIF8",                 // gif
+    b"RIFF",                 // webp, wav, avi
+    b"%PDF",                 // pdf
+];
+
+/// Content coding of a response body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::encode_all(data, 3),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Pick the coding for an `Accept-Encoding` value, preferring zstd when the
+/// client ranks both the same
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    let mut wildcard = None;
+    let mut named = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "zstd" => named.push((Encoding::Zstd, q)),
+            "gzip" | "x-gzip" => named.push((Encoding::Gzip, q)),
+            "*" => wildcard = Some(q),
+            _ => {}
+        }
+    }
+    for encoding in [Encoding::Zstd, Encoding::Gzip] {
+        let q = named
+            .iter()
+            .find(|(e, _)| *e == encoding)
+            .map(|(_, q)| *q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Whether a body looks like text that will compress well
+pub fn is_compressible(data: &[u8]) -> bool {
+    if COMPRESSED_MAGIC.iter().any(|magic| data.starts_with(magic)) {
+        return false;
+    }
+    let head = &data[..data.len().min(SNIFF_LEN)];
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // The sample may end inside a multi-byte character
+        Err(e) => e.error_len().is_none() && head.len() - e.valid_up_to() < 4,
+    }
+}
+
+/// Which GET responses are compressed
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Whether buckets without an override compress
+    pub enabled: bool,
+    /// Smallest body worth compressing
+    pub min_bytes: usize,
+    /// Per-bucket on/off, by bucket name
+    pub overrides: HashMap<String, bool>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: false,
+            min_bytes: 1024,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Parse a `{bucket}={on|off}` override, e.g. `logs=on`
+    pub fn parse_override(spec: &str) -> Result<(String, bool), String> {
+        let (bucket, setting) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected BUCKET=on|off, got '{}'", spec))?;
+        let enabled = match setting {
+            "on" => true,
+            "off" => false,
+            _ => return Err(format!("expected on or off in '{}'", spec)),
+        };
+        Ok((bucket.to_string(), enabled))
+    }
+
+    /// Coding to use for a body from `bucket`, if any
+    pub fn choose(&self, bucket: &str, accept_encoding: Option<&str>, data: &[u8]) -> Option<Encoding> {
+        let enabled = self.overrides.get(bucket).copied().unwrap_or(self.enabled);
+        if !enabled || data.len() < self.min_bytes || !is_compressible(data) {
+            return None;
+        }
+        negotiate(accept_encoding?)
+    }
+
+    /// Compress `data` for `bucket` if the client accepts it and it's worth
+    /// it, returning the body and the coding used
+    pub fn compress(&self, bucket: &str, accept_encoding: Option<&str>, data: &[u8]) -> Option<(Vec<u8>, Encoding)> {
+        let encoding = self.choose(bucket, accept_encoding, data)?;
+        let compressed = encoding.encode(data).ok()?;
+        // Incompressible after all; the plain body is cheaper to serve
+        (compressed.len() < data.len()).then_some((compressed, encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br, zstd"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("gzip;q=1.0, zstd;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("zstd;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("br, *;q=0"), None);
+    }
+
+    #[test]
+    fn test_compress_only_text_in_enabled_buckets() {
+        let mut config = CompressionConfig { enabled: true, ..Default::default() };
+        config.overrides.insert("media".to_string(), false);
+        let json = serde_json::json!({"items": vec!["repeated value"; 200]}).to_string();
+
+        let (body, encoding) = config.compress("docs", Some("gzip"), json.as_bytes()).unwrap();
+        assert_eq!(encoding, Encoding::Gzip);
+        let mut inflated = String::new();
+        flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut inflated).unwrap();
+        assert_eq!(inflated, json);
+
+        let (body, _) = config.compress("docs", Some("zstd"), json.as_bytes()).unwrap();
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), json.as_bytes());
+
+        assert!(config.compress("media", Some("gzip"), json.as_bytes()).is_none());
+        assert!(config.compress("docs", None, json.as_bytes()).is_none());
+        assert!(config.compress("docs", Some("gzip"), b"{}").is_none());
+        let png = [b"\x89PNG".as_slice(), &[0u8; 2048]].concat();
+        assert!(config.compress("docs", Some("gzip"), &png).is_none());
+
+        assert_eq!(CompressionConfig::parse_override("logs=on").unwrap(), ("logs".to_string(), true));
+        assert!(CompressionConfig::parse_override("logs=yes").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Per-principal request concurrency caps
+//!
+//! A key packet may claim `max_concurrent_requests`. Requests made with it
+//! take a slot from a semaphore shared by every key of the same registered
+//! principal (or by the key alone when it has none) and hold it until the
+//! response is ready; a request finding every slot taken is refused with
+//! 429 `too_many_requests` instead of queueing, so one client fleet can't
+//! tie up the server's workers and pools.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use wfldb_auth::AuthContext;
+
+/// Idle semaphores are dropped once this many identities are tracked
+const PRUNE_ABOVE: usize = 4096;
+
+struct Slots {
+    max: u32,
+    semaphore: Arc<Semaphore>,
+}
+
+/// Semaphores by principal or key
+#[derive(Default)]
+pub struct ConcurrencyLimits {
+    slots: Mutex<HashMap<String, Slots>>,
+    rejected: AtomicU64,
+}
+
+impl ConcurrencyLimits {
+    /// Take a slot for a request made with `ctx`; `Ok(None)` when its packet
+    /// claims no cap, `Err` with the cap when every slot is in use
+    pub fn acquire(&self, ctx: &AuthContext) -> Result<Option<OwnedSemaphorePermit>, u32> {
+        let Some(max) = ctx.permissions().max_concurrent_requests else {
+            return Ok(None);
+        };
+        let identity = match ctx.principal() {
+            Some(principal) => format!("principal/{}", principal),
+            None => format!("key/{}", ctx.key_id()),
+        };
+        self.try_acquire(identity, max).map(Some)
+    }
+
+    fn try_acquire(&self, identity: String, max: u32) -> Result<OwnedSemaphorePermit, u32> {
+        let semaphore = {
+            let mut slots = self.slots.lock().unwrap();
+            if slots.len() > PRUNE_ABOVE {
+                slots.retain(|_, slots| slots.semaphore.available_permits() < slots.max as usize);
+            }
+            let entry = slots.entry(identity).or_insert_with(|| Slots::new(max));
+            // A reissued packet with another cap starts a fresh count;
+            // requests under the old one finish on the old semaphore
+            if entry.max != max {
+                *entry = Slots::new(max);
+            }
+            entry.semaphore.clone()
+        };
+        semaphore.try_acquire_owned().map_err(|_| {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            max
+        })
+    }
+
+    /// Requests refused for being over their cap
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl Slots {
+    fn new(max: u32) -> Self {
+        Slots { max, semaphore: Arc::new(Semaphore::new(max as usize)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_per_identity() {
+        let limits = ConcurrencyLimits::default();
+        let first = limits.try_acquire("principal/alice".to_string(), 2).unwrap();
+        let _second = limits.try_acquire("principal/alice".to_string(), 2).unwrap();
+        assert_eq!(limits.try_acquire("principal/alice".to_string(), 2).err(), Some(2));
+        assert!(limits.try_acquire("principal/bob".to_string(), 2).is_ok());
+        assert_eq!(limits.rejected(), 1);
+
+        drop(first);
+        assert!(limits.try_acquire("principal/alice".to_string(), 2).is_ok());
+    }
+}
@@ -0,0 +1,470 @@
+//! Layered configuration
+//!
+//! Every setting is a command-line flag, and each can also come from a
+//! `WFLDB_`-prefixed environment variable (`--slow-request-ms` is
+//! `WFLDB_SLOW_REQUEST_MS`) or from a TOML file named by `--config`, keyed by
+//! flag name, so a Helm chart can render one config map instead of an
+//! argument list. The command line wins over the environment, which wins
+//! over the file:
+//!
+//! ```toml
+//! bind = "0.0.0.0:8080"
+//! slow-request-ms = 250
+//! audit-log = true
+//! peer = ["http://wfldb-1.wfldb:8080", "http://wfldb-2.wfldb:8080"]
+//! ```
+//!
+//! In the file, switches take `true` or `false`, and repeatable flags and
+//! flags with several values take arrays. In the environment, switches take
+//! `true` or `false` (or `1` or `0`), and several values are separated by
+//! whitespace.
+//!
+//! clap only sees strings, so [`validate`] checks the values it can't:
+//! numbers, ranges, addresses, and the specs the server parses itself.
+
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use wfldb_auth::DEFAULT_REPLAY_WINDOW;
+use wfldb_core::{KeyCharset, KeyNormalization, MAX_BUCKET_NAME_LEN};
+use crate::archive::ArchiveDestination;
+use crate::bandwidth::BandwidthConfig;
+use crate::checksum::ChecksumConfig;
+use crate::compression::CompressionConfig;
+use crate::connection::ConnectionLimits;
+use crate::kubernetes;
+use crate::logging::{self, LogFormat, LogRotation};
+use crate::pools::PoolConfig;
+use crate::quarantine::ScanHook;
+use crate::transform::TransformRegistry;
+use crate::transport::Http2Config;
+
+/// Prefix of the environment variables settings can come from
+pub const ENV_PREFIX: &str = "WFLDB_";
+
+/// Flags about the configuration itself, which it can't set
+const META_FLAGS: [&str; 2] = ["config", "validate-config"];
+
+/// Where a setting's value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    CommandLine,
+}
+
+/// How a flag takes its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    /// On when present
+    Switch,
+    Single,
+    /// Repeatable, or several values at once
+    List,
+}
+
+impl Shape {
+    fn of(arg: &Arg) -> Self {
+        let arity = arg.get_num_args().map_or(1, |range| range.max_values());
+        match arg.get_action() {
+            ArgAction::SetTrue => Shape::Switch,
+            ArgAction::Append => Shape::List,
+            _ if arity > 1 => Shape::List,
+            _ => Shape::Single,
+        }
+    }
+}
+
+/// Settings merged from every source
+pub struct Config {
+    pub matches: ArgMatches,
+    sources: BTreeMap<String, (Source, Shape)>,
+}
+
+impl Config {
+    /// Merge the process's arguments, environment and config file, exiting
+    /// with clap's usage message if they don't parse
+    pub fn load(command: Command) -> Self {
+        Self::try_load(command).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Merge the process's arguments, environment and config file
+    pub fn try_load(command: Command) -> Result<Self, clap::Error> {
+        let env = std::env::vars().collect();
+        Self::load_from(command, std::env::args_os().collect(), &env)
+    }
+
+    fn load_from(command: Command, mut args: Vec<OsString>, env: &HashMap<String, String>) -> Result<Self, clap::Error> {
+        // A `requires` may be met by another source, so only the final parse checks constraints
+        let given = command.clone().ignore_errors(true).try_get_matches_from(&args)?;
+        let invalid = |message: String| command.clone().error(ErrorKind::InvalidValue, message);
+        let file = match given.get_one::<String>("config") {
+            Some(path) => read_file(path).map_err(invalid)?,
+            None => toml::Table::new(),
+        };
+        for key in file.keys() {
+            let known = command.get_arguments().any(|arg| arg.get_id() == key.as_str());
+            if !known || META_FLAGS.contains(&key.as_str()) {
+                return Err(invalid(format!("Unknown setting '{}' in the config file", key)));
+            }
+        }
+
+        let mut sources = BTreeMap::new();
+        for arg in command.get_arguments() {
+            let (id, Some(long)) = (arg.get_id().as_str(), arg.get_long()) else {
+                continue;
+            };
+            if META_FLAGS.contains(&id) {
+                continue;
+            }
+            let shape = Shape::of(arg);
+            if given.value_source(id) == Some(ValueSource::CommandLine) {
+                sources.insert(id.to_string(), (Source::CommandLine, shape));
+                continue;
+            }
+            let (source, values) = match (env.get(&env_var(id)), file.get(id)) {
+                (Some(value), _) => (Source::Env, env_values(shape, value)),
+                (None, Some(value)) => (Source::File, file_values(shape, value)),
+                (None, None) => continue,
+            };
+            let origin = match source {
+                Source::Env => env_var(id),
+                _ => format!("{} in the config file", id),
+            };
+            let Some(values) = values.map_err(|e| invalid(format!("{}: {}", origin, e)))? else {
+                continue;
+            };
+            match shape {
+                Shape::Switch => args.push(format!("--{}", long).into()),
+                Shape::Single => args.push(format!("--{}={}", long, values[0]).into()),
+                // Each occurrence of a repeatable flag takes one value
+                Shape::List if matches!(arg.get_action(), ArgAction::Append) => {
+                    args.extend(values.iter().map(|value| format!("--{}={}", long, value).into()));
+                }
+                Shape::List => {
+                    args.push(format!("--{}", long).into());
+                    args.extend(values.into_iter().map(OsString::from));
+                }
+            }
+            sources.insert(id.to_string(), (source, shape));
+        }
+
+        let matches = command.clone().try_get_matches_from(args)?;
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            if matches.value_source(id) == Some(ValueSource::DefaultValue) && !META_FLAGS.contains(&id) {
+                sources.entry(id.to_string()).or_insert((Source::Default, Shape::of(arg)));
+            }
+        }
+        Ok(Config { matches, sources })
+    }
+
+    /// Each setting that has a value, with where the value came from
+    pub fn effective(&self) -> Value {
+        let mut config = Map::new();
+        for (id, (source, shape)) in &self.sources {
+            let value = match shape {
+                Shape::Switch => json!(self.matches.get_flag(id)),
+                Shape::Single => json!(self.matches.get_one::<String>(id)),
+                Shape::List => json!(self.matches.get_many::<String>(id).into_iter().flatten().collect::<Vec<_>>()),
+            };
+            config.insert(id.clone(), json!({"value": value, "source": source}));
+        }
+        Value::Object(config)
+    }
+}
+
+/// Environment variable for the flag `id`
+fn env_var(id: &str) -> String {
+    format!("{}{}", ENV_PREFIX, id.to_ascii_uppercase().replace('-', "_"))
+}
+
+fn read_file(path: &str) -> Result<toml::Table, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+    toml::from_str(&text).map_err(|e| format!("Invalid config file {}: {}", path, e))
+}
+
+/// Values of an environment variable; `None` for a switch that is off
+fn env_values(shape: Shape, value: &str) -> Result<Option<Vec<String>>, String> {
+    match shape {
+        Shape::Switch => match value {
+            "true" | "1" => Ok(Some(Vec::new())),
+            "false" | "0" | "" => Ok(None),
+            _ => Err(format!("expected true or false, got '{}'", value)),
+        },
+        Shape::Single => Ok(Some(vec![value.to_string()])),
+        Shape::List => Ok(Some(value.split_whitespace().map(str::to_string).collect())),
+    }
+}
+
+/// Values of a config file entry; `None` for a switch that is off
+fn file_values(shape: Shape, value: &toml::Value) -> Result<Option<Vec<String>>, String> {
+    let scalar = |value: &toml::Value| match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        other => Err(format!("expected a string or number, got {}", other.type_str())),
+    };
+    match (shape, value) {
+        (Shape::Switch, toml::Value::Boolean(on)) => Ok(on.then(Vec::new)),
+        (Shape::Switch, other) => Err(format!("expected true or false, got {}", other.type_str())),
+        (Shape::List, toml::Value::Array(items)) => items.iter().map(scalar).collect::<Result<_, _>>().map(Some),
+        (_, toml::Value::Array(_)) => Err("takes a single value".to_string()),
+        (_, value) => scalar(value).map(|value| Some(vec![value])),
+    }
+}
+
+type Check = fn(&str) -> Result<(), String>;
+
+fn parsed<T: FromStr>(value: &str) -> Result<(), String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse::<T>().map(drop).map_err(|e| format!("'{}': {}", value, e))
+}
+
+fn percent(value: &str) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(()),
+        _ => Err(format!("'{}' is not a percentage between 0 and 100", value)),
+    }
+}
+
+/// Checks on each value of a flag
+const CHECKS: &[(&str, Check)] = &[
+    ("bind", parsed::<SocketAddr>),
+    ("node-id", parsed::<u16>),
+    ("drain-delay-secs", parsed::<u64>),
+    ("leader-election-lease", |lease| kubernetes::lease_name(lease).map(drop)),
+    ("leader-election-duration-secs", parsed::<u64>),
+    ("max-key-bytes", parsed::<usize>),
+    ("key-charset", |v| KeyCharset::parse(v).map(drop).ok_or_else(|| format!("unknown key charset '{}'", v))),
+    ("key-normalization", |v| {
+        KeyNormalization::parse(v).map(drop).ok_or_else(|| format!("unsupported key normalization '{}'", v))
+    }),
+    ("max-bucket-name-len", |v| match v.parse::<usize>() {
+        Ok(len) if (1..=MAX_BUCKET_NAME_LEN).contains(&len) => Ok(()),
+        _ => Err(format!("must be between 1 and {}", MAX_BUCKET_NAME_LEN)),
+    }),
+    ("slow-request-ms", parsed::<u64>),
+    ("slow-request-sample", parsed::<u64>),
+    ("h2-stream-window-kb", parsed::<u32>),
+    ("h2-connection-window-kb", parsed::<u32>),
+    ("h2-max-streams", parsed::<u32>),
+    ("h2-max-frame-kb", parsed::<u32>),
+    ("h2-send-buffer-kb", parsed::<u32>),
+    ("h2-keepalive-secs", parsed::<u64>),
+    ("h2-keepalive-timeout-secs", parsed::<u64>),
+    ("header-read-timeout-secs", parsed::<u64>),
+    ("idle-timeout-secs", parsed::<u64>),
+    ("body-read-timeout-secs", parsed::<u64>),
+    ("body-memory-kb", parsed::<usize>),
+    ("max-object-mb", parsed::<usize>),
+    ("spool-dir", |dir| match std::fs::metadata(dir) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(format!("{} is not a directory", dir)),
+        Err(e) => Err(format!("{}: {}", dir, e)),
+    }),
+    ("slo-availability", percent),
+    ("slo-latency-ms", parsed::<u64>),
+    ("slo-latency-target", percent),
+    ("memory-limit-mb", parsed::<usize>),
+    ("min-free-disk-mb", parsed::<u64>),
+    ("auth-cache-size", parsed::<usize>),
+    ("auth-clock-skew-secs", parsed::<u64>),
+    ("auth-nonceless-read-window-secs", |v| match v.parse::<u64>() {
+        Ok(secs) if secs > 0 && secs <= DEFAULT_REPLAY_WINDOW.as_secs() => Ok(()),
+        _ => Err(format!("must be between 1 and {}", DEFAULT_REPLAY_WINDOW.as_secs())),
+    }),
+    ("revocation-poll-secs", parsed::<u64>),
+    ("replica-key", |path| match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(()),
+        Ok(_) => Err(format!("{} is not a file", path)),
+        Err(e) => Err(format!("{}: {}", path, e)),
+    }),
+    ("replica-poll-ms", parsed::<u64>),
+    ("replica-max-lag-ms", parsed::<u64>),
+    ("anti-entropy-secs", parsed::<u64>),
+    ("gossip-ms", parsed::<u64>),
+    ("tombstone-retention-hours", parsed::<u64>),
+    ("multipart-expiry-hours", parsed::<u64>),
+    ("log-level", |v| logging::parse_level(v).map(drop)),
+    ("log-format", |v| LogFormat::parse(v).map(drop).ok_or_else(|| "must be text or json".to_string())),
+    ("log-rotation", |v| {
+        LogRotation::parse(v)
+            .map(drop)
+            .ok_or_else(|| "must be hourly, daily, never, or a size such as 100mb".to_string())
+    }),
+    ("log-max-files", parsed::<usize>),
+    ("quarantine-hook", |spec| ScanHook::parse(spec).map(drop)),
+    ("quarantine-interval-secs", parsed::<u64>),
+    ("quarantine-timeout-secs", parsed::<u64>),
+    ("archive-interval-secs", parsed::<u64>),
+    ("journal-segment-mb", parsed::<u64>),
+    ("audit-retention-days", parsed::<u64>),
+    ("pool-permits", parsed::<usize>),
+    ("pool", |spec| PoolConfig::parse_override(spec).map(drop)),
+    ("bandwidth", |spec| BandwidthConfig::parse_limit(spec).map(drop)),
+    ("warmup-keys", parsed::<usize>),
+    ("response-cache-mb", parsed::<usize>),
+    ("response-cache-entries", parsed::<usize>),
+    ("compress-bucket", |spec| CompressionConfig::parse_override(spec).map(drop)),
+    ("compress-min-bytes", parsed::<usize>),
+    ("bucket-checksums", |spec| ChecksumConfig::parse_bucket(spec).map(drop)),
+    ("cors-methods", |methods| {
+        methods.split(',').try_for_each(|m| parsed::<hyper::Method>(&m.trim().to_ascii_uppercase()))
+    }),
+    ("cors-max-age-secs", parsed::<u64>),
+    ("disable-transform", |name| match TransformRegistry::default().remove(name) {
+        true => Ok(()),
+        false => Err(format!("unknown transform '{}'", name)),
+    }),
+    ("max-background-tasks", parsed::<usize>),
+];
+
+/// Problems with the settings in `matches`, each naming its flag
+pub fn validate(matches: &ArgMatches) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (id, check) in CHECKS {
+        for value in matches.get_many::<String>(id).into_iter().flatten() {
+            if let Err(e) = check(value) {
+                errors.push(format!("--{}: {}", id, e));
+            }
+        }
+    }
+
+    // Settings that depend on one another
+    if let Some(template) = matches.get_one::<String>("advertise-url") {
+        let pod_name = matches.get_one::<String>("pod-name").map(String::as_str);
+        if let Err(e) = kubernetes::advertise_url(template, pod_name) {
+            errors.push(e);
+        }
+    }
+    if let Some(spec) = matches.get_one::<String>("archive-dest") {
+        let endpoint = matches.get_one::<String>("archive-s3-endpoint").map(String::as_str);
+        if let Err(e) = ArchiveDestination::parse(spec, endpoint) {
+            errors.push(format!("--archive-dest: {}", e));
+        }
+    }
+    if let Err(e) = http2(matches).and_then(|http2| http2.validate()) {
+        errors.push(e);
+    }
+    if let Err(e) = connection_limits(matches) {
+        errors.push(e);
+    }
+    errors
+}
+
+/// HTTP/2 settings from the `h2-*` flags
+pub fn http2(matches: &ArgMatches) -> Result<Http2Config, String> {
+    let number = |id: &str| -> Result<Option<u32>, String> {
+        matches.get_one::<String>(id).map(|v| v.parse().map_err(|_| format!("Invalid --{}", id))).transpose()
+    };
+    let kib = |id: &str| Ok::<_, String>(number(id)?.map(|kb| kb.saturating_mul(1024)));
+    Ok(Http2Config {
+        stream_window: kib("h2-stream-window-kb")?,
+        connection_window: kib("h2-connection-window-kb")?,
+        adaptive_window: matches.get_flag("h2-adaptive-window"),
+        max_concurrent_streams: number("h2-max-streams")?,
+        max_frame_size: kib("h2-max-frame-kb")?,
+        max_send_buffer: kib("h2-send-buffer-kb")?.map(|bytes| bytes as usize),
+    })
+}
+
+/// Connection timeouts from their `*-secs` flags, 0 turning one off
+pub fn connection_limits(matches: &ArgMatches) -> Result<ConnectionLimits, String> {
+    let secs = |id: &str| -> Result<Option<Duration>, String> {
+        let secs: u64 = matches.get_one::<String>(id).unwrap().parse().map_err(|_| format!("Invalid --{}", id))?;
+        Ok((secs > 0).then(|| Duration::from_secs(secs)))
+    };
+    let keep_alive_interval = secs("h2-keepalive-secs")?;
+    let keep_alive_timeout = secs("h2-keepalive-timeout-secs")?;
+    if keep_alive_interval.is_some() && keep_alive_timeout.is_none() {
+        return Err("--h2-keepalive-timeout-secs must be positive while HTTP/2 pings are on".to_string());
+    }
+    Ok(ConnectionLimits {
+        header_read_timeout: secs("header-read-timeout-secs")?,
+        keep_alive_interval,
+        keep_alive_timeout: keep_alive_timeout.unwrap_or_default(),
+        idle_timeout: secs("idle-timeout-secs")?,
+        body_read_timeout: secs("body-read-timeout-secs")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(args: &[&str], env: &[(&str, &str)], file: &str) -> Result<Config, clap::Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wfldb.toml");
+        std::fs::write(&path, file).unwrap();
+        let mut argv: Vec<OsString> = vec!["wfldb-server".into(), "--config".into(), path.into()];
+        argv.extend(args.iter().map(OsString::from));
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::load_from(crate::cli(), argv, &env)
+    }
+
+    #[test]
+    fn test_sources_layer_in_order() {
+        let file = r#"
+            bind = "0.0.0.0:9000"
+            slow-request-ms = 250
+            audit-log = true
+            leader-election-lease = "wfldb/leader"
+            advertise-url = "http://{pod_name}.wfldb:8080"
+            peer = ["http://a:8080", "http://b:8080"]
+            export-bucket = ["photos", "/backup"]
+        "#;
+        let env = [("WFLDB_SLOW_REQUEST_MS", "500"), ("WFLDB_POD_NAME", "wfldb-1")];
+        let config = load(&["--bind", "0.0.0.0:7000"], &env, file).unwrap();
+        let effective = config.effective();
+
+        assert_eq!(effective["bind"], json!({"value": "0.0.0.0:7000", "source": "command_line"}));
+        assert_eq!(effective["slow-request-ms"], json!({"value": "500", "source": "env"}));
+        assert_eq!(effective["audit-log"], json!({"value": true, "source": "file"}));
+        assert_eq!(effective["journal"], json!({"value": false, "source": "default"}));
+        assert_eq!(effective["peer"]["value"], json!(["http://a:8080", "http://b:8080"]));
+        assert_eq!(effective["export-bucket"]["value"], json!(["photos", "/backup"]));
+        // Its `requires` is met from the environment
+        assert_eq!(effective["pod-name"]["source"], "env");
+        assert!(effective.get("config").is_none());
+        assert!(effective.get("replica-of").is_none());
+
+        assert!(load(&[], &[], "bnid = \"0.0.0.0:9000\"").is_err());
+        assert!(load(&[], &[], "audit-log = \"yes\"").is_err());
+        assert!(load(&[], &[("WFLDB_AUDIT_LOG", "yes")], "").is_err());
+        assert!(load(&[], &[], "leader-election-lease = \"wfldb/leader\"").is_err());
+    }
+
+    #[test]
+    fn test_validation_names_each_bad_flag() {
+        let matches = crate::cli().get_matches_from(["wfldb-server", "--node-id", "7"]);
+        assert!(validate(&matches).is_empty());
+
+        let matches = crate::cli().get_matches_from([
+            "wfldb-server",
+            "--bind", "localhost",
+            "--node-id", "70000",
+            "--slo-availability", "101",
+            "--advertise-url", "http://{pod_name}.wfldb:8080",
+            "--h2-max-frame-kb", "1",
+            "--max-bucket-name-len", "500",
+        ]);
+        let errors = validate(&matches);
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        assert!(errors[0].starts_with("--bind:"));
+        assert!(errors.iter().any(|e| e.contains("{pod_name}")));
+        assert!(errors.iter().any(|e| e.contains("max frame size")));
+        assert!(errors.iter().any(|e| e.starts_with("--max-bucket-name-len:")));
+    }
+}
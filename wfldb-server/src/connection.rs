@@ -0,0 +1,335 @@
+//! Connection timeouts and slowloris defense
+//!
+//! A client that opens connections and goes quiet, or sends its request a
+//! byte at a time, would otherwise hold a socket and a task for as long as
+//! it likes. [`ConnectionLimits`] bounds each stage of a connection:
+//!
+//! - request headers must arrive within the header read timeout (HTTP/1)
+//! - HTTP/2 connections are pinged every keep-alive interval and closed when
+//!   a ping goes unanswered for the keep-alive timeout
+//! - a connection with no request in flight for the idle timeout is closed,
+//!   whatever bytes (such as pings) still cross it
+//! - a request body that goes the body read timeout without delivering its
+//!   next chunk, the first included, fails to read
+//!
+//! A request counts as in flight until its response body has been sent, so
+//! long streamed responses don't idle out.
+
+use bytes::Bytes;
+use futures::stream;
+use hyper::body::{HttpBody, SizeHint};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::server::Builder;
+use hyper::{Body, HeaderMap, Request};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+/// Timeouts of every connection; `None` turns one off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub header_read_timeout: Option<Duration>,
+    pub keep_alive_interval: Option<Duration>,
+    pub keep_alive_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub body_read_timeout: Option<Duration>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            header_read_timeout: Some(Duration::from_secs(30)),
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Duration::from_secs(20),
+            idle_timeout: Some(Duration::from_secs(120)),
+            body_read_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /// Apply the hyper-enforced timeouts to a server being built
+    pub fn apply<I>(&self, builder: Builder<I>) -> Builder<I> {
+        let mut builder = builder
+            .http2_keep_alive_interval(self.keep_alive_interval)
+            .http2_keep_alive_timeout(self.keep_alive_timeout);
+        if let Some(timeout) = self.header_read_timeout {
+            builder = builder.http1_header_read_timeout(timeout);
+        }
+        builder
+    }
+}
+
+/// Counts of connections and of those timed out
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    open: AtomicUsize,
+    accepted: AtomicU64,
+    idle_closed: AtomicU64,
+    body_timeouts: AtomicU64,
+}
+
+impl ConnectionStats {
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Connections closed for going idle
+    pub fn idle_closed(&self) -> u64 {
+        self.idle_closed.load(Ordering::Relaxed)
+    }
+
+    /// Request bodies failed for arriving too slowly
+    pub fn body_timeouts(&self) -> u64 {
+        self.body_timeouts.load(Ordering::Relaxed)
+    }
+}
+
+/// Requests in flight on one connection
+#[derive(Debug)]
+pub struct Activity {
+    in_flight: AtomicUsize,
+    idle_since: Mutex<Instant>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity { in_flight: AtomicUsize::new(0), idle_since: Mutex::new(Instant::now()) }
+    }
+
+    /// Mark a request in flight until the guard drops
+    pub fn begin(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.clone())
+    }
+
+    /// When the connection last had nothing in flight; `None` while busy
+    fn idle_since(&self) -> Option<Instant> {
+        let since = *self.idle_since.lock().unwrap();
+        (self.in_flight.load(Ordering::SeqCst) == 0).then_some(since)
+    }
+}
+
+/// A request in flight on its connection
+#[derive(Debug)]
+pub struct InFlight(Arc<Activity>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut since = self.0.idle_since.lock().unwrap();
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *since = Instant::now();
+        }
+    }
+}
+
+/// Accepted connections, each watched for going idle
+pub struct Incoming {
+    listener: AddrIncoming,
+    idle_timeout: Option<Duration>,
+    stats: Arc<ConnectionStats>,
+}
+
+impl Incoming {
+    pub fn new(listener: AddrIncoming, idle_timeout: Option<Duration>, stats: Arc<ConnectionStats>) -> Self {
+        Incoming { listener, idle_timeout, stats }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Connection>>> {
+        let this = self.get_mut();
+        let accepted = Pin::new(&mut this.listener).poll_accept(cx);
+        accepted.map(|accepted| {
+            accepted.map(|stream| stream.map(|stream| Connection::new(stream, this.idle_timeout, this.stats.clone())))
+        })
+    }
+}
+
+/// A client connection, failing its reads once idle too long
+pub struct Connection {
+    stream: AddrStream,
+    activity: Arc<Activity>,
+    idle: Option<(Duration, Pin<Box<Sleep>>)>,
+    stats: Arc<ConnectionStats>,
+}
+
+impl Connection {
+    fn new(stream: AddrStream, idle_timeout: Option<Duration>, stats: Arc<ConnectionStats>) -> Self {
+        stats.open.fetch_add(1, Ordering::Relaxed);
+        stats.accepted.fetch_add(1, Ordering::Relaxed);
+        let idle = idle_timeout.map(|timeout| (timeout, Box::pin(tokio::time::sleep(timeout))));
+        Connection { stream, activity: Arc::new(Activity::new()), idle, stats }
+    }
+
+    pub fn activity(&self) -> Arc<Activity> {
+        self.activity.clone()
+    }
+
+    /// Whether the connection has gone the idle timeout with nothing in
+    /// flight; otherwise the timer is set for when it next could have
+    fn idled_out(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some((timeout, sleep)) = &mut self.idle else {
+            return false;
+        };
+        while sleep.as_mut().poll(cx).is_ready() {
+            let now = Instant::now();
+            let deadline = self.activity.idle_since().unwrap_or(now) + *timeout;
+            if deadline <= now {
+                self.stats.idle_closed.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            sleep.as_mut().reset(deadline);
+        }
+        false
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stats.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.idled_out(cx) {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "connection idle")));
+        }
+        Pin::new(&mut this.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// A response body keeping its request in flight until it has been sent
+pub struct TrackedBody {
+    body: Body,
+    _in_flight: InFlight,
+}
+
+impl TrackedBody {
+    pub fn new(body: Body, in_flight: InFlight) -> Self {
+        TrackedBody { body, _in_flight: in_flight }
+    }
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, hyper::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_data(cx)
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, hyper::Error>> {
+        Pin::new(&mut self.get_mut().body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Fail a request body that goes `timeout` without delivering its next
+/// chunk, the first included
+pub fn time_body(req: Request<Body>, timeout: Duration, stats: Arc<ConnectionStats>) -> Request<Body> {
+    if req.body().is_end_stream() {
+        return req;
+    }
+    req.map(|body| {
+        Body::wrap_stream(stream::unfold(Some(body), move |body| {
+            let stats = stats.clone();
+            async move {
+                let mut body = body?;
+                let error: Box<dyn std::error::Error + Send + Sync> =
+                    match tokio::time::timeout(timeout, body.data()).await {
+                        Ok(Some(Ok(data))) => return Some((Ok(data), Some(body))),
+                        Ok(Some(Err(e))) => Box::new(e),
+                        Ok(None) => return None,
+                        Err(_) => {
+                            stats.body_timeouts.fetch_add(1, Ordering::Relaxed);
+                            format!("Request body sent nothing for {:?}", timeout).into()
+                        }
+                    };
+                Some((Err(error), None))
+            }
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stalled_body_fails() {
+        let stats = Arc::new(ConnectionStats::default());
+        let (mut sender, body) = Body::channel();
+        let req = time_body(Request::new(body), Duration::from_millis(50), stats.clone());
+        sender.send_data(Bytes::from_static(b"first")).await.unwrap();
+        let mut body = req.into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "first");
+        // The sender goes quiet while still holding the body open
+        assert!(body.data().await.unwrap().is_err());
+        assert!(body.data().await.is_none());
+        assert_eq!(stats.body_timeouts(), 1);
+        drop(sender);
+    }
+
+    #[test]
+    fn test_idle_only_with_nothing_in_flight() {
+        let activity = Arc::new(Activity::new());
+        assert!(activity.idle_since().is_some());
+        let first = activity.begin();
+        let second = activity.begin();
+        drop(first);
+        assert!(activity.idle_since().is_none());
+        let before = Instant::now();
+        drop(second);
+        assert!(activity.idle_since().unwrap() >= before);
+    }
+}
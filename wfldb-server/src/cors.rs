@@ -0,0 +1,173 @@
+//! CORS for browser clients
+//!
+//! With CORS configured, `/v1/*` answers preflight `OPTIONS` requests itself
+//! (before authentication, since browsers send preflights without
+//! credentials) and marks every response to an allowed origin, including
+//! auth failures, so browser apps can read the error. The allowed origin is
+//! echoed back with `Vary: Origin` rather than sent as `*`, and the wflDB
+//! response headers clients act on are exposed.
+
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::time::Duration;
+use wfldb_auth::headers;
+use crate::checksum;
+
+/// Response headers scripts may read besides the CORS-safelisted ones
+const EXPOSED_HEADERS: &[&str] = &[
+    "etag",
+    "content-encoding",
+    headers::VERSION,
+    headers::GENERATION,
+    headers::SERVER_TIME,
+    "retry-after",
+    headers::REQUEST_ID,
+    "x-wfldb-checksum-crc32c",
+    "x-wfldb-checksum-sha256",
+];
+
+/// Which cross-origin requests browsers may make
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// Origins such as `https://app.example.com`, or `*` for any
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    /// Request headers scripts may set, lowercase
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer
+    pub max_age: Duration,
+}
+
+impl CorsConfig {
+    /// Allow `origins` to use every API method and the request-signing headers
+    pub fn new(origins: Vec<String>) -> Self {
+        CorsConfig {
+            allowed_origins: origins,
+            allowed_methods: vec![Method::GET, Method::PUT, Method::POST, Method::PATCH, Method::DELETE],
+            allowed_headers: [
+                "authorization",
+                "content-type",
+                headers::SIGNATURE,
+                headers::TIMESTAMP,
+                headers::NONCE,
+                headers::CONTENT_HASH,
+                headers::SIGNED_HEADERS,
+                headers::PRIORITY,
+                headers::LEASE,
+                "if-none-match",
+                checksum::ALGORITHM_HEADER,
+                "x-wfldb-checksum-crc32c",
+                "x-wfldb-checksum-sha256",
+            ]
+            .iter()
+            .map(|h| h.to_string())
+            .collect(),
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Answer a preflight for `/v1/*`; `None` if `req` isn't one
+    pub fn preflight(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        if req.method() != Method::OPTIONS || !req.uri().path().starts_with("/v1/") {
+            return None;
+        }
+        let request_headers = req.headers();
+        let origin = request_headers.get(header::ORIGIN).and_then(|v| v.to_str().ok())?;
+        let requested_method = request_headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| v.to_str().ok())?;
+        let requested_headers = request_headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        let method_allowed = self.allowed_methods.iter().any(|m| m.as_str() == requested_method);
+        let headers_allowed = requested_headers
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .all(|h| self.allowed_headers.contains(&h));
+        if !self.origin_allowed(origin) || !method_allowed || !headers_allowed {
+            return Some(Response::builder().status(StatusCode::FORBIDDEN).body(Body::empty()).unwrap());
+        }
+
+        let methods: Vec<&str> = self.allowed_methods.iter().map(Method::as_str).collect();
+        Some(
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods.join(", "))
+                .header(header::ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers.join(", "))
+                .header(header::ACCESS_CONTROL_MAX_AGE, self.max_age.as_secs().to_string())
+                .header(header::VARY, "origin")
+                .body(Body::empty())
+                .unwrap(),
+        )
+    }
+
+    /// Let `origin` read `response` if it's allowed
+    pub fn decorate(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        let Some(origin) = origin.filter(|o| o.to_str().is_ok_and(|o| self.origin_allowed(o))) else {
+            return;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_str(&EXPOSED_HEADERS.join(", ")).unwrap(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/photos/cat.jpg")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = CorsConfig::new(vec!["https://app.example.com".to_string()]);
+
+        let ok = cors.preflight(&preflight("https://app.example.com", "PUT", "Authorization, X-Wfldb-Nonce")).unwrap();
+        assert_eq!(ok.status(), StatusCode::NO_CONTENT);
+        assert_eq!(ok.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+        assert_eq!(ok.headers()[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let other_origin = cors.preflight(&preflight("https://evil.example", "PUT", "")).unwrap();
+        assert_eq!(other_origin.status(), StatusCode::FORBIDDEN);
+        let odd_header = cors.preflight(&preflight("https://app.example.com", "GET", "x-custom")).unwrap();
+        assert_eq!(odd_header.status(), StatusCode::FORBIDDEN);
+
+        let plain_options = Request::builder().method(Method::OPTIONS).uri("/v1/a/b").body(Body::empty()).unwrap();
+        assert!(cors.preflight(&plain_options).is_none());
+    }
+
+    #[test]
+    fn test_decorate_only_allowed_origins() {
+        let cors = CorsConfig::new(vec!["*".to_string()]);
+        let mut response = Response::new(Body::empty());
+        cors.decorate(Some(&HeaderValue::from_static("https://a.example")), &mut response);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://a.example");
+        assert!(response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS].to_str().unwrap().contains("etag"));
+
+        let cors = CorsConfig::new(vec!["https://a.example".to_string()]);
+        let mut response = Response::new(Body::empty());
+        cors.decorate(Some(&HeaderValue::from_static("https://b.example")), &mut response);
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
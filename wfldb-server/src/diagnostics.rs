@@ -0,0 +1,165 @@
+//! Runtime diagnostics for the /debug/runtime endpoint
+//!
+//! Storage calls currently run inline on the tokio worker threads, so a burst
+//! of slow fjall operations can stall the reactor. These counters make that
+//! visible: compare `storage.in_flight` against `runtime.workers`.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Concurrency counters for a single handler (or the storage layer)
+#[derive(Debug, Default)]
+pub struct ConcurrencyStats {
+    in_flight: AtomicU64,
+    peak: AtomicU64,
+    total: AtomicU64,
+}
+
+impl ConcurrencyStats {
+    fn enter(self: &Arc<Self>) -> InFlightGuard {
+        let now = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(now, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { stats: self.clone() }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+            "peak": self.peak.load(Ordering::Relaxed),
+            "total": self.total.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Decrements the in-flight counter when dropped
+pub struct InFlightGuard {
+    stats: Arc<ConcurrencyStats>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Per-handler and storage concurrency tracking
+#[derive(Default)]
+pub struct RuntimeDiagnostics {
+    handlers: Mutex<BTreeMap<&'static str, Arc<ConcurrencyStats>>>,
+    storage: Arc<ConcurrencyStats>,
+}
+
+impl RuntimeDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark a request as being served by the named handler
+    pub fn enter_handler(&self, handler: &'static str) -> InFlightGuard {
+        let stats = self
+            .handlers
+            .lock()
+            .unwrap()
+            .entry(handler)
+            .or_default()
+            .clone();
+        stats.enter()
+    }
+
+    /// Mark a blocking storage call as running on the current worker
+    pub fn enter_storage(&self) -> InFlightGuard {
+        self.storage.enter()
+    }
+
+    /// Build the JSON snapshot served by /debug/runtime
+    pub fn snapshot(&self) -> Value {
+        let handlers: serde_json::Map<String, Value> = self
+            .handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (name.to_string(), stats.to_json()))
+            .collect();
+
+        json!({
+            "runtime": runtime_snapshot(),
+            "storage": self.storage.to_json(),
+            "handlers": handlers,
+            "tokio_console": cfg!(feature = "tokio-console"),
+        })
+    }
+}
+
+fn runtime_snapshot() -> Value {
+    let handle = match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => return Value::Null,
+    };
+    let metrics = handle.metrics();
+
+    let worker_busy_ms: Vec<u128> = (0..metrics.num_workers())
+        .map(|worker| metrics.worker_total_busy_duration(worker).as_millis())
+        .collect();
+
+    #[allow(unused_mut)]
+    let mut snapshot = json!({
+        "workers": metrics.num_workers(),
+        "alive_tasks": metrics.num_alive_tasks(),
+        "global_queue_depth": metrics.global_queue_depth(),
+        "worker_busy_ms": worker_busy_ms,
+    });
+
+    // Blocking pool metrics are only exposed by tokio under --cfg tokio_unstable
+    #[cfg(tokio_unstable)]
+    {
+        snapshot["blocking_threads"] = json!(metrics.num_blocking_threads());
+        snapshot["idle_blocking_threads"] = json!(metrics.num_idle_blocking_threads());
+        snapshot["blocking_queue_depth"] = json!(metrics.blocking_queue_depth());
+    }
+
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handler_concurrency_tracking() {
+        let diagnostics = RuntimeDiagnostics::new();
+
+        let first = diagnostics.enter_handler("get_object");
+        let second = diagnostics.enter_handler("get_object");
+        let snapshot = diagnostics.snapshot();
+        assert_eq!(snapshot["handlers"]["get_object"]["in_flight"], 2);
+
+        drop(first);
+        drop(second);
+        let snapshot = diagnostics.snapshot();
+        assert_eq!(snapshot["handlers"]["get_object"]["in_flight"], 0);
+        assert_eq!(snapshot["handlers"]["get_object"]["peak"], 2);
+        assert_eq!(snapshot["handlers"]["get_object"]["total"], 2);
+    }
+
+    #[test]
+    fn test_storage_calls_tracked() {
+        let diagnostics = RuntimeDiagnostics::new();
+
+        {
+            let _guard = diagnostics.enter_storage();
+            assert_eq!(diagnostics.snapshot()["storage"]["in_flight"], 1);
+        }
+
+        assert_eq!(diagnostics.snapshot()["storage"]["in_flight"], 0);
+        assert_eq!(diagnostics.snapshot()["storage"]["total"], 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_runtime_snapshot_reports_workers() {
+        let snapshot = RuntimeDiagnostics::new().snapshot();
+        assert_eq!(snapshot["runtime"]["workers"], 2);
+    }
+}
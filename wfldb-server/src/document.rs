@@ -0,0 +1,101 @@
+//! Partial updates and projections of JSON documents
+//!
+//! ```text
+//! PATCH /v1/{bucket}/{key}                  <merge patch>   RFC 7396 JSON Merge Patch
+//! PATCH /v1/{bucket}/{key}?pointer=/a/b     <JSON value>    set the value at a JSON Pointer
+//! GET   /v1/{bucket}/{key}?fields=a,/b/c                    only the listed fields
+//! ```
+//!
+//! The update is applied server-side against the current version, so
+//! concurrent patches don't overwrite each other. A merge patch creates a
+//! missing document; a pointer update needs the document and the pointer's
+//! parent to exist. Fields are top-level names or JSON Pointers. In a
+//! bucket with a JSON Schema the patched document must match it (see
+//! [`crate::schema`]).
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use wfldb_auth::{now_ms, AuthContext};
+use wfldb_core::{BucketId, Key, WflDBError};
+use wfldb_engine::{parse_document, project, DocumentPatch, Storage};
+use wfldb_net::query_param;
+use crate::auth;
+use crate::error::ApiError;
+use crate::schema;
+use crate::router::json_response;
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Largest patch body; documents are patched in place, not uploaded whole
+const MAX_PATCH_BYTES: usize = 1024 * 1024;
+
+/// Handle `PATCH /v1/{bucket}/{key}`; permissions were already checked
+pub async fn handle_patch(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let pointer = req.uri().query().and_then(|q| query_param(q, "pointer"));
+    let body_bytes = match state.spool.read(req.into_body(), MAX_PATCH_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(auth_ctx, &body_bytes, timings) {
+        return e.into_response();
+    }
+
+    let value: Value = match serde_json::from_slice(&body_bytes) {
+        Ok(value) => value,
+        Err(e) => return ApiError::bad_request(format!("Invalid JSON: {}", e)).into_response(),
+    };
+    let patch = match pointer {
+        Some(pointer) => DocumentPatch::Pointer { pointer, value },
+        None => DocumentPatch::Merge(value),
+    };
+
+    let validator = match state.schemas.validator(storage, bucket_id) {
+        Ok(validator) => validator,
+        Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+    };
+    // Violations of the last attempt, for the error response
+    let rejected = RefCell::new(None);
+    let result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+        let check = |document: &Value| match validator.as_ref().and_then(|v| schema::violations(v, document)) {
+            Some(found) => {
+                *rejected.borrow_mut() = Some(found);
+                Err(WflDBError::InvalidDocument("document does not match the bucket's schema".to_string()))
+            }
+            None => Ok(()),
+        };
+        storage.patch_document_checked(bucket_id, key, &patch, owner.as_deref(), check)
+    });
+    if let (Err(_), Some(found)) = (&result, rejected.take()) {
+        return ApiError::schema_violation(found).into_response();
+    }
+    match result {
+        Ok(metadata) => json_response(StatusCode::OK, json!({
+            "success": true,
+            "bucket": bucket_id.as_str(),
+            "key": key.as_str(),
+            "size": metadata.size,
+            "version": metadata.version.to_string(),
+            "generation": metadata.generation,
+        })),
+        Err(WflDBError::ObjectNotFound { .. }) => ApiError::not_found("Object not found").into_response(),
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// Body of a GET with `?fields=`: the selected fields of the document
+pub fn projection(data: &[u8], fields: &str) -> wfldb_core::Result<Vec<u8>> {
+    let document = parse_document(data)?;
+    let fields: Vec<&str> = fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect();
+    Ok(project(&document, &fields).to_string().into_bytes())
+}
@@ -0,0 +1,258 @@
+//! API error responses
+//!
+//! Errors on `/v1` share one body shape:
+//!
+//! ```text
+//! {"error": "<message>", "code": "<code>", "retryable": <bool>, "request_id": "<id>", ...details}
+//! ```
+//!
+//! `request_id` is the id of the failed request, also sent in `X-Request-Id`.
+//! `code` is stable and machine-readable. `retryable` tells clients whether
+//! sending the same request again can succeed: overload, a full disk, a
+//! frozen bucket, a lagging replica, a held lease, a node that isn't the
+//! elected leader, a key over its concurrency cap, transaction conflicts, and failures injected for a drill are retryable, while bad input, missing objects, and failed preconditions
+//! are not. Retryable errors with a known wait also set `Retry-After`
+//! (seconds).
+
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+use wfldb_core::WflDBError;
+use crate::request_id;
+
+/// Wait suggested after shedding load
+const OVERLOAD_RETRY: Duration = Duration::from_secs(1);
+/// Wait suggested after a write failed for lack of disk space
+const STORAGE_FULL_RETRY: Duration = Duration::from_secs(30);
+/// Wait suggested while a bucket is frozen for migration
+const FROZEN_RETRY: Duration = Duration::from_secs(5);
+/// Wait suggested when a replica is behind the requested read floor
+const REPLICA_RETRY: Duration = Duration::from_secs(1);
+/// Wait suggested when a key is at its concurrency cap
+const CONCURRENCY_RETRY: Duration = Duration::from_secs(1);
+/// Wait suggested while an object waits for its content scan
+const QUARANTINE_RETRY: Duration = Duration::from_secs(5);
+
+/// An error response with its classification
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    retryable: bool,
+    retry_after: Option<Duration>,
+    details: Map<String, Value>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>, retryable: bool) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            retryable,
+            retry_after: None,
+            details: Map::new(),
+        }
+    }
+
+    /// Malformed or invalid request; terminal
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message, false)
+    }
+
+    /// No such object or route; terminal
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message, false)
+    }
+
+    /// History the request asks for has been archived away; terminal
+    pub fn gone(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::GONE, "gone", message, false)
+    }
+
+    /// Request body over a size limit; terminal
+    pub fn too_large(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::PAYLOAD_TOO_LARGE, "too_large", message, false)
+    }
+
+    /// The server is shedding load; retry shortly
+    pub fn overloaded(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "overloaded", message, true).with_retry_after(OVERLOAD_RETRY)
+    }
+
+    /// Writes to the bucket are paused while it moves to another node
+    pub fn bucket_frozen() -> Self {
+        Self::new(StatusCode::LOCKED, "bucket_frozen", "Bucket is frozen for migration", true)
+            .with_retry_after(FROZEN_RETRY)
+    }
+
+    /// A replica hasn't caught up far enough to serve the read; retry or
+    /// read from the leader
+    pub fn replica_behind(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "replica_behind", message, true).with_retry_after(REPLICA_RETRY)
+    }
+
+    /// The key already has as many requests in progress as its packet
+    /// allows; retry once one finishes
+    pub fn too_many_requests(max: u32) -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_requests",
+            format!("Key packet allows {} concurrent requests", max),
+            true,
+        )
+        .with_retry_after(CONCURRENCY_RETRY)
+        .with_detail("max_concurrent_requests", max)
+    }
+
+    /// Writes go to the leader; terminal on this node
+    pub fn read_only_replica(leader: &str) -> Self {
+        Self::new(StatusCode::MISDIRECTED_REQUEST, "read_only_replica", "This node is a read replica", false)
+            .with_detail("leader", leader)
+    }
+
+    /// Under leader election, this node doesn't hold the lease; retry
+    /// against the holder
+    pub fn not_leader(holder: Option<String>) -> Self {
+        let error = Self::new(StatusCode::SERVICE_UNAVAILABLE, "not_leader", "This node is not the elected leader", true);
+        match holder {
+            Some(holder) => error.with_detail("holder", holder),
+            None => error,
+        }
+    }
+
+    /// Body doesn't match a checksum the client sent with it; terminal
+    pub fn checksum_mismatch(algorithm: &str) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "checksum_mismatch",
+            format!("{} checksum does not match request body", algorithm),
+            false,
+        )
+        .with_detail("algorithm", algorithm)
+    }
+
+    /// Failure injected by an admin for a failure drill; retry shortly
+    pub fn injected_failure() -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "injected_failure", "Write failed by fault injection", true)
+            .with_retry_after(OVERLOAD_RETRY)
+    }
+
+    /// Document the bucket's JSON Schema doesn't accept; terminal
+    pub fn schema_violation(violations: Vec<Value>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, "schema_violation", "Document does not match the bucket's schema", false)
+            .with_detail("violations", violations)
+    }
+
+    /// Object still waiting for its content scan; retry later
+    pub fn quarantined() -> Self {
+        Self::new(StatusCode::CONFLICT, "quarantined", "Object is held until its content has been scanned", true)
+            .with_retry_after(QUARANTINE_RETRY)
+    }
+
+    /// Object is encrypted with a customer key the request didn't send; terminal
+    pub fn customer_key_required() -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "customer_key_required", "Object is encrypted with a customer key", false)
+    }
+
+    /// Customer key doesn't match the one the object is encrypted with; terminal
+    pub fn customer_key_mismatch() -> Self {
+        Self::new(StatusCode::FORBIDDEN, "customer_key_mismatch", "Customer key does not match the object's", false)
+    }
+
+    /// Failure inside the server; terminal
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", message, false)
+    }
+
+    /// Suggest how long to wait before retrying
+    pub fn with_retry_after(mut self, wait: Duration) -> Self {
+        self.retry_after = Some(wait);
+        self
+    }
+
+    /// Add a field to the response body
+    pub fn with_detail(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The error as one entry of a batch GET's multiplexed body
+    pub fn entry_header(&self, key: &str) -> wfldb_net::EntryHeader {
+        wfldb_net::EntryHeader::error(key, self.status.as_u16(), self.code, self.message.clone(), self.retryable)
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let mut body = self.details;
+        body.insert("error".to_string(), json!(self.message));
+        body.insert("code".to_string(), json!(self.code));
+        body.insert("retryable".to_string(), json!(self.retryable));
+        if let Some(id) = request_id::current() {
+            body.insert("request_id".to_string(), json!(id));
+        }
+        let mut response = Response::builder()
+            .status(self.status)
+            .header("content-type", "application/json");
+        if let Some(wait) = self.retry_after {
+            // Round up so clients never come back early
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response = response.header("retry-after", secs.max(1).to_string());
+        }
+        response.body(Body::from(Value::Object(body).to_string())).unwrap()
+    }
+}
+
+impl ApiError {
+    /// Classify an engine error by its code, status and retryability,
+    /// taking `now_ms` to time lease expiry
+    pub fn from_storage(err: &WflDBError, now_ms: u64) -> Self {
+        let status = StatusCode::from_u16(err.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let error = Self::new(status, err.code(), err.to_string(), err.is_retryable());
+        match err {
+            _ if err.is_storage_full() => error.with_retry_after(STORAGE_FULL_RETRY),
+            WflDBError::MissingChunks(missing) => error.with_detail("missing", missing.clone()),
+            WflDBError::PreconditionFailed { key } | WflDBError::TxnConflict { key } => {
+                error.with_detail("key", key.as_str())
+            }
+            WflDBError::LeaseHeld { expires_at_ms, .. } => error
+                .with_retry_after(Duration::from_millis(expires_at_ms.saturating_sub(now_ms)))
+                .with_detail("expires_at_ms", *expires_at_ms),
+            _ => error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(response: Response<Body>) -> Value {
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_classification() {
+        let held = ApiError::from_storage(&WflDBError::LeaseHeld { key: "k".to_string(), expires_at_ms: 12_500 }, 10_000);
+        let response = held.into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()["retry-after"], "3");
+        let held = body(response).await;
+        assert_eq!((held["code"].as_str(), held["retryable"].as_bool()), (Some("lease_held"), Some(true)));
+        assert_eq!(held["expires_at_ms"], 12_500);
+
+        let full = WflDBError::Io(std::io::Error::from(std::io::ErrorKind::StorageFull));
+        let response = ApiError::from_storage(&full, 0).into_response();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(response.headers()["retry-after"], "30");
+
+        let missing = ApiError::from_storage(&WflDBError::ObjectNotFound { key: "k".to_string() }, 0).into_response();
+        assert!(!missing.headers().contains_key("retry-after"));
+        let missing = body(missing).await;
+        assert_eq!(missing["retryable"], false);
+        assert!(missing.get("request_id").is_none());
+
+        let response = request_id::scope("trace-42".to_string(), async { ApiError::not_found("gone").into_response() }).await;
+        assert_eq!(body(response).await["request_id"], "trace-42");
+    }
+}
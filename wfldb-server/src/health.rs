@@ -0,0 +1,237 @@
+//! Liveness and readiness probes
+//!
+//! `/livez` only fails when a background task has stopped making progress,
+//! which a restart would fix. `/readyz` additionally checks that the keyspace
+//! accepts a canary write within a deadline, the data directory has free
+//! space, and the heap is below the shedding limit. It also fails while the
+//! node drains for shutdown and, on a replica, until the first sync has
+//! applied everything the leader had. Local recovery (data directory
+//! migration, journal replay, the startup integrity check, warm-up) is done
+//! before the listener binds, so no probe answers until it finishes.
+
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::simple_server_fixed::ServerState;
+
+/// A task is wedged once it misses this many consecutive heartbeats
+const WEDGED_AFTER_INTERVALS: u32 = 3;
+
+/// Default deadline for the canary write and read-back
+pub const DEFAULT_CANARY_DEADLINE: Duration = Duration::from_secs(2);
+
+/// Readiness thresholds
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub min_free_disk_bytes: u64,
+    pub canary_deadline: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            min_free_disk_bytes: 512 * 1024 * 1024,
+            canary_deadline: DEFAULT_CANARY_DEADLINE,
+        }
+    }
+}
+
+struct Heartbeat {
+    last: Instant,
+    interval: Duration,
+}
+
+/// Last-progress timestamps of periodic background tasks
+#[derive(Default)]
+pub struct TaskHeartbeats {
+    tasks: Mutex<BTreeMap<&'static str, Heartbeat>>,
+}
+
+impl TaskHeartbeats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a task expected to beat every `interval`
+    pub fn register(&self, name: &'static str, interval: Duration) {
+        self.tasks.lock().unwrap().insert(name, Heartbeat { last: Instant::now(), interval });
+    }
+
+    /// Record progress for a task
+    pub fn beat(&self, name: &'static str) {
+        if let Some(heartbeat) = self.tasks.lock().unwrap().get_mut(name) {
+            heartbeat.last = Instant::now();
+        }
+    }
+
+    /// Per-task detail, and whether every task is keeping up
+    pub fn check(&self) -> (bool, Value) {
+        let tasks = self.tasks.lock().unwrap();
+        let mut healthy = true;
+        let detail: serde_json::Map<String, Value> = tasks
+            .iter()
+            .map(|(name, heartbeat)| {
+                let age = heartbeat.last.elapsed();
+                let ok = age <= heartbeat.interval * WEDGED_AFTER_INTERVALS;
+                healthy &= ok;
+                let entry = json!({
+                    "ok": ok,
+                    "age_ms": age.as_millis() as u64,
+                    "interval_ms": heartbeat.interval.as_millis() as u64,
+                });
+                (name.to_string(), entry)
+            })
+            .collect();
+        (healthy, Value::Object(detail))
+    }
+}
+
+/// Free bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn free_disk_bytes(path: &Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid NUL-terminated string and `stat` is a writable statvfs
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_disk_bytes(_path: &Path) -> std::io::Result<u64> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "disk space check not supported"))
+}
+
+/// GET /livez
+pub fn livez(state: &ServerState) -> Response<Body> {
+    let (tasks_ok, tasks) = state.heartbeats.check();
+    probe_response(tasks_ok, json!({ "tasks": { "ok": tasks_ok, "tasks": tasks } }))
+}
+
+/// GET /readyz
+pub async fn readyz(state: &ServerState) -> Response<Body> {
+    let mut checks = serde_json::Map::new();
+    let mut ready = true;
+
+    let engine = state.storage.clone();
+    let started = Instant::now();
+    let canary = tokio::time::timeout(
+        state.health.canary_deadline,
+        tokio::task::spawn_blocking(move || engine.canary_check()),
+    )
+    .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let canary = match canary {
+        Ok(Ok(Ok(()))) => json!({ "ok": true, "latency_ms": latency_ms }),
+        Ok(Ok(Err(e))) => json!({ "ok": false, "latency_ms": latency_ms, "error": e.to_string() }),
+        Ok(Err(e)) => json!({ "ok": false, "error": format!("canary task failed: {}", e) }),
+        Err(_) => json!({
+            "ok": false,
+            "error": format!("canary exceeded {}ms deadline", state.health.canary_deadline.as_millis()),
+        }),
+    };
+    ready &= canary["ok"] == true;
+    checks.insert("canary".to_string(), canary);
+
+    let disk = match free_disk_bytes(state.storage.path()) {
+        Ok(free) => json!({
+            "ok": free >= state.health.min_free_disk_bytes,
+            "free_bytes": free,
+            "min_free_bytes": state.health.min_free_disk_bytes,
+        }),
+        Err(e) => json!({ "ok": false, "error": e.to_string() }),
+    };
+    ready &= disk["ok"] == true;
+    checks.insert("disk".to_string(), disk);
+
+    let shedding = state.memory_guard.over_limit();
+    ready &= !shedding;
+    checks.insert("memory".to_string(), json!({ "ok": !shedding, "limit_bytes": state.memory_guard.limit_bytes() }));
+
+    let (tasks_ok, tasks) = state.heartbeats.check();
+    ready &= tasks_ok;
+    checks.insert("tasks".to_string(), json!({ "ok": tasks_ok, "tasks": tasks }));
+
+    let draining = state.draining.load(Ordering::Relaxed);
+    ready &= !draining;
+    checks.insert("drain".to_string(), json!({ "ok": !draining }));
+
+    if let Some(replica) = &state.replica {
+        let caught_up = replica.staleness_ms().is_some();
+        ready &= caught_up;
+        checks.insert("recovery".to_string(), json!({ "ok": caught_up, "applied_seq": replica.applied_seq() }));
+        if let Some(max_lag) = replica.max_lag() {
+            let in_pool = replica.in_read_pool();
+            ready &= in_pool;
+            checks.insert(
+                "replication_lag".to_string(),
+                json!({
+                    "ok": in_pool,
+                    "staleness_ms": replica.staleness_ms(),
+                    "max_lag_ms": max_lag.as_millis() as u64,
+                }),
+            );
+        }
+    }
+
+    // Standing in the election doesn't affect readiness: every node serves reads
+    if let Some(election) = &state.election {
+        checks.insert(
+            "leader_election".to_string(),
+            json!({ "ok": true, "leader": election.is_leader(), "holder": election.holder() }),
+        );
+    }
+
+    probe_response(ready, Value::Object(checks))
+}
+
+fn probe_response(ok: bool, checks: Value) -> Response<Body> {
+    let (status, label) = if ok {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+    };
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("cache-control", "no-store")
+        .body(Body::from(json!({ "status": label, "checks": checks }).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wedged_task_detected() {
+        let heartbeats = TaskHeartbeats::new();
+        heartbeats.register("fast", Duration::from_secs(60));
+        heartbeats.register("wedged", Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(50));
+        heartbeats.beat("fast");
+
+        let (ok, detail) = heartbeats.check();
+        assert!(!ok);
+        assert_eq!(detail["fast"]["ok"], true);
+        assert_eq!(detail["wedged"]["ok"], false);
+
+        heartbeats.beat("wedged");
+        assert!(heartbeats.check().0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_disk_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(free_disk_bytes(dir.path()).unwrap() > 0);
+    }
+}
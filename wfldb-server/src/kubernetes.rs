@@ -0,0 +1,499 @@
+//! Running under Kubernetes
+//!
+//! Pod naming: a StatefulSet pod passes its name from the downward API as
+//! `--pod-name $(POD_NAME)`. Unless `--node-id` is given, the node id is the
+//! pod's ordinal (`wfldb-2` is node 2), and `{pod_name}` in `--advertise-url`
+//! is replaced by the name, so one pod template gives every pod its own
+//! identity and a URL peers reach it at through the headless Service.
+//!
+//! Draining: on SIGTERM (or Ctrl-C) the node fails `/readyz` with `drain`
+//! but keeps serving for `--drain-delay-secs`, long enough for its endpoint
+//! to be taken out of rotation, then stops accepting connections and
+//! finishes the requests in flight. This does what a `preStop` sleep would;
+//! `terminationGracePeriodSeconds` must cover the delay plus the slowest
+//! request.
+//!
+//! Leader election: with `--leader-election-lease NAMESPACE/NAME`, nodes
+//! compete for a `coordination.k8s.io/v1` Lease through the API server,
+//! using the pod's service account. Only the holder takes writes; the others
+//! answer them 503 `not_leader` naming the holder, and tell cluster peers
+//! they are replicas. A holder that can't renew within two thirds of the
+//! lease duration stops taking writes before anyone can take the lease
+//! over. Whether a lease has lapsed is judged by how long its record has
+//! gone unchanged on this node's clock, never by the timestamps in it, so
+//! clock skew between pods doesn't matter.
+
+use chrono::{SecondsFormat, Utc};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use crate::health::TaskHeartbeats;
+
+/// Name of the elector in the liveness probe
+const HEARTBEAT_TASK: &str = "leader_election";
+
+/// Where Kubernetes mounts the pod's service account token and CA
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// StatefulSet ordinal of a pod name, e.g. 2 for `wfldb-2`
+pub fn statefulset_ordinal(pod_name: &str) -> Option<u16> {
+    pod_name.rsplit_once('-')?.1.parse().ok()
+}
+
+/// `--advertise-url` with `{pod_name}` filled in
+pub fn advertise_url(template: &str, pod_name: Option<&str>) -> Result<String, String> {
+    match pod_name {
+        Some(pod_name) => Ok(template.replace("{pod_name}", pod_name)),
+        None if template.contains("{pod_name}") => Err("--advertise-url uses {pod_name} without --pod-name".to_string()),
+        None => Ok(template.to_string()),
+    }
+}
+
+/// Namespace and name of the Lease `NAMESPACE/NAME`
+pub fn lease_name(lease: &str) -> Result<(&str, &str), String> {
+    lease
+        .split_once('/')
+        .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+        .ok_or_else(|| format!("Leader election lease '{}' must be NAMESPACE/NAME", lease))
+}
+
+/// Wait for SIGTERM or Ctrl-C, then fail readiness for `delay` while
+/// still serving; the server shuts down once this returns
+pub async fn drain_on_shutdown(draining: Arc<AtomicBool>, delay: Duration) {
+    shutdown_signal().await;
+    draining.store(true, Ordering::Relaxed);
+    info!("Draining: failing readiness for {:?} before closing", delay);
+    tokio::time::sleep(delay).await;
+    info!("Drained; finishing requests in flight");
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler installs");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Where this node stands in the election, shared with request handling
+#[derive(Debug)]
+pub struct LeaderElection {
+    identity: String,
+    leader: AtomicBool,
+    holder: RwLock<Option<String>>,
+    failures: AtomicU64,
+}
+
+impl LeaderElection {
+    fn new(identity: String) -> Self {
+        LeaderElection {
+            identity,
+            leader: AtomicBool::new(false),
+            holder: RwLock::new(None),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Name this node holds the lease under
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Whether this node holds the lease and may take writes
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    /// Holder of the lease when last read, this node included
+    pub fn holder(&self) -> Option<String> {
+        self.holder.read().unwrap().clone()
+    }
+
+    /// Failed attempts to read, take, or renew the lease
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, leader: bool, holder: Option<String>) {
+        if leader != self.leader.swap(leader, Ordering::Relaxed) {
+            match leader {
+                true => info!("Became leader as {}", self.identity),
+                false => warn!("No longer leader; lease held by {}", holder.as_deref().unwrap_or("nobody")),
+            }
+        }
+        *self.holder.write().unwrap() = holder;
+    }
+}
+
+/// The parts of a Lease the election looks at
+#[derive(Debug, Clone)]
+struct LeaseRecord {
+    holder: Option<String>,
+    duration: Duration,
+    renew_time: Option<String>,
+    transitions: u64,
+}
+
+impl LeaseRecord {
+    fn parse(lease: &Value) -> Self {
+        let spec = &lease["spec"];
+        LeaseRecord {
+            // Releasing a lease leaves an empty holder
+            holder: spec["holderIdentity"].as_str().filter(|holder| !holder.is_empty()).map(str::to_string),
+            duration: Duration::from_secs(spec["leaseDurationSeconds"].as_u64().unwrap_or(0)),
+            renew_time: spec["renewTime"].as_str().map(str::to_string),
+            transitions: spec["leaseTransitions"].as_u64().unwrap_or(0),
+        }
+    }
+}
+
+/// What the elector does with the lease it read
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Create,
+    Renew,
+    TakeOver,
+    Wait,
+}
+
+/// Tracks when the lease record last changed, to tell when it lapsed
+#[derive(Debug)]
+struct Observer {
+    identity: String,
+    observed: Option<(LeaseRecord, Instant)>,
+}
+
+impl Observer {
+    fn step(&mut self, current: Option<&LeaseRecord>, now: Instant) -> Step {
+        let Some(record) = current else {
+            return Step::Create;
+        };
+        // Every renewal moves renewTime, so an unchanged record is an unrenewed one
+        let changed = self.observed.as_ref().is_none_or(|(observed, _)| {
+            observed.holder != record.holder || observed.renew_time != record.renew_time
+        });
+        if changed {
+            self.observed = Some((record.clone(), now));
+        }
+        if record.holder.as_deref() == Some(self.identity.as_str()) {
+            return Step::Renew;
+        }
+        let unchanged_for = self.observed.as_ref().map_or(Duration::ZERO, |(_, since)| now - *since);
+        if record.holder.is_none() || unchanged_for >= record.duration {
+            Step::TakeOver
+        } else {
+            Step::Wait
+        }
+    }
+}
+
+/// Competes for a Lease through the API server
+pub struct LeaderElector {
+    api: ApiServer,
+    namespace: String,
+    name: String,
+    lease_duration: Duration,
+    election: Arc<LeaderElection>,
+    observer: Observer,
+    renewed_at: Option<Instant>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl LeaderElector {
+    /// Elector for the Lease `NAMESPACE/NAME`, held as `identity`
+    pub fn new(lease: &str, identity: &str, lease_duration: Duration) -> Result<Self, String> {
+        let (namespace, name) = lease_name(lease)?;
+        Ok(LeaderElector {
+            api: ApiServer::in_cluster()?,
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            lease_duration,
+            election: Arc::new(LeaderElection::new(identity.to_string())),
+            observer: Observer { identity: identity.to_string(), observed: None },
+            renewed_at: None,
+            heartbeats: None,
+        })
+    }
+
+    /// Report each attempt to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.retry_period() * 2);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn election(&self) -> Arc<LeaderElection> {
+        self.election.clone()
+    }
+
+    fn retry_period(&self) -> Duration {
+        self.lease_duration / 5
+    }
+
+    /// Longest a holder goes without renewing before it steps down
+    fn renew_deadline(&self) -> Duration {
+        self.lease_duration * 2 / 3
+    }
+
+    /// Take part in the election until the task is dropped
+    pub async fn run(mut self) {
+        info!(
+            "Competing for lease {}/{} as {} (duration {:?})",
+            self.namespace, self.name, self.election.identity(), self.lease_duration
+        );
+        let mut ticker = tokio::time::interval(self.retry_period());
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            match tokio::time::timeout(self.retry_period(), self.attempt()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    self.election.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Leader election for {}/{} failed: {}", self.namespace, self.name, e);
+                }
+                Err(_) => {
+                    self.election.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Leader election for {}/{} timed out", self.namespace, self.name);
+                }
+            }
+            // Step down while the lease is still ours, before anyone can take it over
+            let lapsing = self.renewed_at.is_none_or(|at| at.elapsed() > self.renew_deadline());
+            if self.election.is_leader() && lapsing {
+                self.election.set(false, self.election.holder());
+            }
+        }
+    }
+
+    async fn attempt(&mut self) -> Result<(), String> {
+        let lease = self.api.get(&self.lease_path()).await?;
+        let record = lease.as_ref().map(LeaseRecord::parse);
+        let started = Instant::now();
+        let written = match self.observer.step(record.as_ref(), started) {
+            Step::Wait => {
+                self.election.set(false, record.and_then(|record| record.holder));
+                return Ok(());
+            }
+            Step::Create => {
+                let lease = json!({
+                    "apiVersion": "coordination.k8s.io/v1",
+                    "kind": "Lease",
+                    "metadata": {"name": self.name, "namespace": self.namespace},
+                    "spec": self.spec(0),
+                });
+                self.api.create(&self.leases_path(), &lease).await?
+            }
+            Step::Renew => {
+                let mut lease = lease.expect("renewing a lease that was read");
+                lease["spec"]["holderIdentity"] = json!(self.election.identity());
+                lease["spec"]["leaseDurationSeconds"] = json!(self.lease_duration.as_secs());
+                lease["spec"]["renewTime"] = json!(micro_time());
+                self.api.replace(&self.lease_path(), &lease).await?
+            }
+            Step::TakeOver => {
+                let mut lease = lease.expect("taking over a lease that was read");
+                let transitions = record.map_or(0, |record| record.transitions + 1);
+                lease["spec"] = self.spec(transitions);
+                self.api.replace(&self.lease_path(), &lease).await?
+            }
+        };
+        // Writing over a newer record conflicts; another node won the round
+        if written {
+            self.renewed_at = Some(started);
+            self.election.set(true, Some(self.election.identity().to_string()));
+        }
+        Ok(())
+    }
+
+    fn spec(&self, transitions: u64) -> Value {
+        let now = micro_time();
+        json!({
+            "holderIdentity": self.election.identity(),
+            "leaseDurationSeconds": self.lease_duration.as_secs(),
+            "acquireTime": now,
+            "renewTime": now,
+            "leaseTransitions": transitions,
+        })
+    }
+
+    fn leases_path(&self) -> String {
+        format!("/apis/coordination.k8s.io/v1/namespaces/{}/leases", self.namespace)
+    }
+
+    fn lease_path(&self) -> String {
+        format!("{}/{}", self.leases_path(), self.name)
+    }
+}
+
+/// A Kubernetes `MicroTime`
+fn micro_time() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// The API server, reached with the pod's service account
+struct ApiServer {
+    base_url: String,
+    token_path: PathBuf,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl ApiServer {
+    fn in_cluster() -> Result<Self, String> {
+        let host = std::env::var("KUBERNETES_SERVICE_HOST")
+            .map_err(|_| "Leader election needs to run in a Kubernetes pod (KUBERNETES_SERVICE_HOST is unset)")?;
+        let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+        let host = if host.contains(':') { format!("[{}]", host) } else { host };
+
+        let dir = Path::new(SERVICE_ACCOUNT_DIR);
+        let ca_path = dir.join("ca.crt");
+        let ca = std::fs::read(&ca_path).map_err(|e| format!("Failed to read {}: {}", ca_path.display(), e))?;
+        let mut roots = rustls::RootCertStore::empty();
+        let certs = rustls_pemfile::certs(&mut &ca[..]).map_err(|e| format!("Invalid {}: {}", ca_path.display(), e))?;
+        for cert in certs {
+            roots
+                .add(&rustls::Certificate(cert))
+                .map_err(|e| format!("Invalid {}: {}", ca_path.display(), e))?;
+        }
+        let tls = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_only()
+            .enable_http1()
+            .build();
+        Ok(ApiServer {
+            base_url: format!("https://{}:{}", host, port),
+            token_path: dir.join("token"),
+            client: Client::builder().build(connector),
+        })
+    }
+
+    /// The object at `path`, `None` if there is none
+    async fn get(&self, path: &str) -> Result<Option<Value>, String> {
+        match self.send(Method::GET, path, None).await? {
+            (StatusCode::OK, lease) => Ok(Some(lease)),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, body) => Err(api_error(status, &body)),
+        }
+    }
+
+    /// Create an object; `false` if one was created first
+    async fn create(&self, path: &str, object: &Value) -> Result<bool, String> {
+        match self.send(Method::POST, path, Some(object)).await? {
+            (status, _) if status.is_success() => Ok(true),
+            (StatusCode::CONFLICT, _) => Ok(false),
+            (status, body) => Err(api_error(status, &body)),
+        }
+    }
+
+    /// Replace an object at the `resourceVersion` it was read at; `false`
+    /// if it changed since
+    async fn replace(&self, path: &str, object: &Value) -> Result<bool, String> {
+        match self.send(Method::PUT, path, Some(object)).await? {
+            (StatusCode::OK, _) => Ok(true),
+            (StatusCode::CONFLICT, _) => Ok(false),
+            (status, body) => Err(api_error(status, &body)),
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<&Value>) -> Result<(StatusCode, Value), String> {
+        // Projected tokens are rotated, so read the current one every time
+        let token = std::fs::read_to_string(&self.token_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.token_path.display(), e))?;
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_url, path))
+            .header("authorization", format!("Bearer {}", token.trim()))
+            .header("accept", "application/json")
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .map_err(|e| e.to_string())?;
+        let response = self.client.request(request).await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+        Ok((status, serde_json::from_slice(&body).unwrap_or(Value::Null)))
+    }
+}
+
+fn api_error(status: StatusCode, body: &Value) -> String {
+    match body["message"].as_str() {
+        Some(message) => format!("API server answered {}: {}", status, message),
+        None => format!("API server answered {}", status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(holder: &str, renew_time: &str) -> LeaseRecord {
+        LeaseRecord {
+            holder: Some(holder.to_string()).filter(|holder| !holder.is_empty()),
+            duration: Duration::from_secs(15),
+            renew_time: Some(renew_time.to_string()),
+            transitions: 3,
+        }
+    }
+
+    #[test]
+    fn test_pod_naming() {
+        assert_eq!(statefulset_ordinal("wfldb-2"), Some(2));
+        assert_eq!(statefulset_ordinal("wfldb-store-12"), Some(12));
+        assert_eq!(statefulset_ordinal("wfldb"), None);
+        assert_eq!(statefulset_ordinal("wfldb-7d9f8b-x2kqp"), None);
+        assert_eq!(
+            advertise_url("http://{pod_name}.wfldb:8080", Some("wfldb-2")).unwrap(),
+            "http://wfldb-2.wfldb:8080"
+        );
+        assert!(advertise_url("http://{pod_name}.wfldb:8080", None).is_err());
+        assert_eq!(advertise_url("http://node:8080", None).unwrap(), "http://node:8080");
+    }
+
+    #[test]
+    fn test_lease_is_taken_over_once_unrenewed_for_its_duration() {
+        let mut observer = Observer { identity: "wfldb-1".to_string(), observed: None };
+        let start = Instant::now();
+        assert_eq!(observer.step(None, start), Step::Create);
+
+        // Another holder that keeps renewing is never taken over
+        let held = record("wfldb-0", "t1");
+        assert_eq!(observer.step(Some(&held), start), Step::Wait);
+        assert_eq!(observer.step(Some(&held), start + Duration::from_secs(10)), Step::Wait);
+        let renewed = record("wfldb-0", "t2");
+        assert_eq!(observer.step(Some(&renewed), start + Duration::from_secs(20)), Step::Wait);
+        // Measured from when this node saw the record change, not its timestamps
+        assert_eq!(observer.step(Some(&renewed), start + Duration::from_secs(34)), Step::Wait);
+        assert_eq!(observer.step(Some(&renewed), start + Duration::from_secs(35)), Step::TakeOver);
+
+        assert_eq!(observer.step(Some(&record("", "t3")), start + Duration::from_secs(36)), Step::TakeOver);
+        assert_eq!(observer.step(Some(&record("wfldb-1", "t4")), start + Duration::from_secs(37)), Step::Renew);
+    }
+
+    #[test]
+    fn test_lease_record_parse() {
+        let lease = json!({"spec": {"holderIdentity": "", "leaseDurationSeconds": 15, "leaseTransitions": 2}});
+        let record = LeaseRecord::parse(&lease);
+        assert_eq!(record.holder, None);
+        assert_eq!(record.duration, Duration::from_secs(15));
+        assert_eq!(record.transitions, 2);
+    }
+}
@@ -0,0 +1,157 @@
+//! Lease API
+//!
+//! ```text
+//! GET    /v1/{bucket}/_lease/{key}                                   current holder and expiry
+//! POST   /v1/{bucket}/_lease/{key}  {"ttl_secs": N}                  acquire
+//! PUT    /v1/{bucket}/_lease/{key}  {"ttl_secs": N}  x-wfldb-lease   renew
+//! DELETE /v1/{bucket}/_lease/{key}                   x-wfldb-lease   release
+//! ```
+//!
+//! Acquiring answers 201 with the lease id, which the holder sends back in
+//! `x-wfldb-lease` to renew or release; only the holder ever sees it. A key
+//! that is already leased answers 409 with the current expiry, and renewing
+//! or releasing a lease that has lapsed or been taken over answers 410.
+//! Every call but the GET needs write permission on the bucket.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use wfldb_auth::{headers, now_ms, AuthContext, BucketAcl};
+use wfldb_core::{BucketId, Key};
+use wfldb_engine::{Storage, MAX_LEASE_TTL};
+use crate::auth;
+use crate::error::ApiError;
+use crate::router::{json_response, method_not_allowed};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Path segment between the bucket and the key of a lease route
+const LEASE_SEGMENT: &str = "_lease";
+
+/// Largest lease body, `{"ttl_secs": N}`
+const MAX_LEASE_BODY_BYTES: usize = 4 * 1024;
+
+#[derive(Deserialize)]
+struct TtlRequest {
+    ttl_secs: u64,
+}
+
+/// Parse "/v1/{bucket}/_lease/{key}"; `None` if the path isn't a lease route
+pub fn parse_lease_path(path: &str) -> Option<std::result::Result<(BucketId, Key), String>> {
+    let (bucket, rest) = path.strip_prefix("/v1/")?.split_once('/')?;
+    let key = rest.strip_prefix(LEASE_SEGMENT)?.strip_prefix('/')?;
+    Some(
+        BucketId::new(bucket)
+            .map_err(|_| "Invalid bucket name".to_string())
+            .and_then(|bucket| Ok((bucket, Key::from_url(key).map_err(|_| "Invalid key".to_string())?))),
+    )
+}
+
+/// Handle a lease route; bucket permission was already checked
+pub async fn handle(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let method = req.method().clone();
+    let lease_id = req.headers()
+        .get(headers::LEASE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body_bytes = match state.spool.read(req.into_body(), MAX_LEASE_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(auth_ctx, &body_bytes, timings) {
+        return e.into_response();
+    }
+
+    // On owner-only buckets only the object's owner may coordinate on it
+    if let (Some(authenticator), Some(ctx)) = (&state.auth, auth_ctx) {
+        let acl = authenticator.bucket_acls().get(bucket_id);
+        if method != Method::GET && acl == BucketAcl::OwnerOnly && ctx.tenant().is_none() {
+            let owner = match storage.get_metadata(bucket_id, key) {
+                Ok(metadata) => metadata.and_then(|metadata| metadata.owner),
+                Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+            };
+            if let Err(e) = ctx.authorize_owner(acl, owner.as_deref()) {
+                return auth::error_response(&e);
+            }
+        }
+    }
+
+    let ttl = match method {
+        Method::POST | Method::PUT => match serde_json::from_slice::<TtlRequest>(&body_bytes) {
+            Ok(request) if (1..=MAX_LEASE_TTL.as_secs()).contains(&request.ttl_secs) => {
+                Duration::from_secs(request.ttl_secs)
+            }
+            _ => {
+                let error = format!("Expected {{\"ttl_secs\": 1-{}}}", MAX_LEASE_TTL.as_secs());
+                return ApiError::bad_request(error).into_response();
+            }
+        },
+        _ => Duration::ZERO,
+    };
+    if matches!(method, Method::PUT | Method::DELETE) && lease_id.is_none() {
+        let error = format!("Missing {} header", headers::LEASE);
+        return ApiError::bad_request(error).into_response();
+    }
+
+    let now = now_ms();
+    let _busy = state.diagnostics.enter_storage();
+    let result = timings.time(Phase::Storage, || match method {
+        Method::GET => storage.lease(bucket_id, key, now).map(|lease| match lease {
+            Some(lease) => json_response(StatusCode::OK, json!({
+                "key": key.as_str(),
+                "holder": lease.holder,
+                "acquired_at_ms": lease.acquired_at_ms,
+                "expires_at_ms": lease.expires_at_ms,
+            })),
+            None => ApiError::not_found("No lease held").into_response(),
+        }),
+        Method::POST => {
+            let holder = auth_ctx.map(|ctx| ctx.key_id().to_string());
+            storage.acquire_lease(bucket_id, key, ttl, holder.as_deref(), now).map(|lease| {
+                json_response(StatusCode::CREATED, json!({
+                    "key": key.as_str(),
+                    "lease_id": lease.id,
+                    "holder": lease.holder,
+                    "acquired_at_ms": lease.acquired_at_ms,
+                    "expires_at_ms": lease.expires_at_ms,
+                }))
+            })
+        }
+        Method::PUT => storage
+            .renew_lease(bucket_id, key, lease_id.as_deref().unwrap_or_default(), ttl, now)
+            .map(|lease| {
+                json_response(StatusCode::OK, json!({"key": key.as_str(), "expires_at_ms": lease.expires_at_ms}))
+            }),
+        Method::DELETE => storage
+            .release_lease(bucket_id, key, lease_id.as_deref().unwrap_or_default(), now)
+            .map(|()| json_response(StatusCode::OK, json!({"key": key.as_str(), "released": true}))),
+        _ => Ok(method_not_allowed()),
+    });
+    match result {
+        Ok(response) => response,
+        Err(e) => ApiError::from_storage(&e, now).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lease_path() {
+        let (bucket, key) = parse_lease_path("/v1/jobs/_lease/nightly/export").unwrap().unwrap();
+        assert_eq!((bucket.as_str(), key.as_str()), ("jobs", "nightly/export"));
+        assert!(parse_lease_path("/v1/jobs/_lease/").unwrap().is_err());
+        assert!(parse_lease_path("/v1/jobs/_leases/a").is_none());
+        assert!(parse_lease_path("/v1/jobs/a").is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Log output: stdout, rotated files, or both
+//!
+//! Logs go to stdout as text or JSON lines, and with a log directory also
+//! to files there. Files rotate hourly, daily, or once they reach a size,
+//! and only the newest `max_files` are kept. File writes happen on a
+//! background thread, so a slow disk doesn't stall requests; lines that
+//! would overflow its buffer are dropped rather than queued.
+//!
+//! The level filter is `--log-level`, else `RUST_LOG`, else `info`, and can
+//! be replaced while the server runs with [`set_level`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+/// Name of the active log file; rotated files take a suffix after it
+const LOG_FILE_PREFIX: &str = "wfldb-server";
+
+/// The installed filter and the directives it was built from
+static FILTER: OnceLock<(reload::Handle<EnvFilter, Registry>, Mutex<String>)> = OnceLock::new();
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl LogFormat {
+    /// Parse `text` or `json`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// When the log file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    /// Once the file reaches this many bytes
+    Size(u64),
+    Never,
+}
+
+impl LogRotation {
+    /// Parse `hourly`, `daily`, `never`, or a size such as `100mb` or `1gb`
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim().to_ascii_lowercase();
+        match spec.as_str() {
+            "hourly" => return Some(LogRotation::Hourly),
+            "daily" => return Some(LogRotation::Daily),
+            "never" => return Some(LogRotation::Never),
+            _ => {}
+        }
+        let (number, unit) = spec.split_at(spec.find(|c: char| !c.is_ascii_digit())?);
+        let multiplier = match unit {
+            "kb" => 1024,
+            "mb" => 1024 * 1024,
+            "gb" => 1024 * 1024 * 1024,
+            _ => return None,
+        };
+        let bytes = number.parse::<u64>().ok()?.checked_mul(multiplier)?;
+        (bytes > 0).then_some(LogRotation::Size(bytes))
+    }
+}
+
+/// Where logs go
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Filter directives as in `RUST_LOG`, e.g. `info,wfldb_engine=debug`
+    pub level: Option<String>,
+    pub format: LogFormat,
+    pub stdout: bool,
+    /// Directory for log files; `None` logs to stdout only
+    pub dir: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Log files kept, the active one included
+    pub max_files: usize,
+}
+
+/// Install the global subscriber for `config`
+///
+/// Keep the returned guard alive for as long as the server runs; dropping
+/// it flushes the lines still queued for the log file.
+pub fn init(config: &LogConfig) -> io::Result<Option<WorkerGuard>> {
+    let filter = match &config.level {
+        Some(level) => parse_level(level).map_err(io::Error::other)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let directives = filter.to_string();
+    let (filter, handle) = reload::Layer::new(filter);
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+    if config.stdout {
+        layers.push(format_layer(config.format, io::stdout, true));
+    }
+    let guard = match &config.dir {
+        Some(dir) => {
+            let (writer, guard) = tracing_appender::non_blocking(open_log_file(config, dir)?);
+            layers.push(format_layer(config.format, writer, false));
+            Some(guard)
+        }
+        None => None,
+    };
+    let subscriber = tracing_subscriber::registry().with(layers.with_filter(filter));
+    // The console sees runtime spans whatever the log filter is
+    #[cfg(feature = "tokio-console")]
+    let subscriber = subscriber.with(console_subscriber::spawn());
+    subscriber.try_init().map_err(io::Error::other)?;
+    let _ = FILTER.set((handle, Mutex::new(directives)));
+    Ok(guard)
+}
+
+/// Filter directives in effect, once logging is initialized
+pub fn level() -> Option<String> {
+    FILTER.get().map(|(_, directives)| directives.lock().unwrap().clone())
+}
+
+/// Check filter directives without applying them
+pub fn parse_level(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|e| format!("invalid log level '{}': {}", level, e))
+}
+
+/// Replace the filter of the running logger
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_level(level)?;
+    let (handle, directives) = FILTER.get().ok_or("logging is not initialized")?;
+    let mut directives = directives.lock().unwrap();
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    *directives = level.to_string();
+    Ok(())
+}
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'a> tracing_subscriber::fmt::MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+fn open_log_file(config: &LogConfig, dir: &Path) -> io::Result<Box<dyn Write + Send>> {
+    let rotation = match config.rotation {
+        LogRotation::Size(max_bytes) => {
+            return Ok(Box::new(SizeRotatingFile::open(dir, LOG_FILE_PREFIX, max_bytes, config.max_files)?));
+        }
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(config.max_files.max(1))
+        .build(dir)
+        .map_err(io::Error::other)?;
+    Ok(Box::new(appender))
+}
+
+/// Log file rotated by size: `{name}.log` is written until the next line
+/// would take it past `max_bytes`, then renamed to `{name}.log.1`, with
+/// older files shifting up a number and the oldest beyond `max_files`
+/// removed
+pub struct SizeRotatingFile {
+    dir: PathBuf,
+    name: String,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotatingFile {
+    pub fn open(dir: &Path, name: &str, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.log", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(SizeRotatingFile {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            max_bytes,
+            max_files: max_files.max(1),
+            file,
+            written,
+        })
+    }
+
+    fn path(&self, generation: usize) -> PathBuf {
+        match generation {
+            0 => self.dir.join(format!("{}.log", self.name)),
+            n => self.dir.join(format!("{}.log.{}", self.name, n)),
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let oldest = self.path(self.max_files - 1);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for generation in (0..self.max_files - 1).rev() {
+            let from = self.path(generation);
+            if from.exists() {
+                fs::rename(from, self.path(generation + 1))?;
+            }
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(self.path(0))?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A line longer than the limit still gets a file of its own
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!(LogRotation::parse("daily"), Some(LogRotation::Daily));
+        assert_eq!(LogRotation::parse("Hourly"), Some(LogRotation::Hourly));
+        assert_eq!(LogRotation::parse("100MB"), Some(LogRotation::Size(100 * 1024 * 1024)));
+        assert_eq!(LogRotation::parse("64kb"), Some(LogRotation::Size(64 * 1024)));
+        assert_eq!(LogRotation::parse("0mb"), None);
+        assert_eq!(LogRotation::parse("mb"), None);
+        assert_eq!(LogRotation::parse("10"), None);
+        assert_eq!(LogRotation::parse("weekly"), None);
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_max_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("logs");
+        let mut file = SizeRotatingFile::open(&dir, "server", 10, 3).unwrap();
+        for line in ["aaaaaaa\n", "bbbbbbb\n", "ccccccc\n", "ddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("server.log"), "ddddddd\n");
+        assert_eq!(read("server.log.1"), "ccccccc\n");
+        assert_eq!(read("server.log.2"), "bbbbbbb\n");
+        assert!(!dir.join("server.log.3").exists());
+
+        // Reopening appends to the active file and counts what it holds
+        let mut reopened = SizeRotatingFile::open(&dir, "server", 10, 3).unwrap();
+        reopened.write_all(b"e\n").unwrap();
+        assert_eq!(read("server.log"), "ddddddd\ne\n");
+        reopened.write_all(b"f\n").unwrap();
+        assert_eq!(read("server.log"), "f\n");
+    }
+}
@@ -1,23 +1,122 @@
 //! wflDB server implementation - Phase 0 spike version
 
+use clap::parser::ValueSource;
 use clap::{Arg, Command};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, warn};
-use wfldb_engine::StorageEngine;
+use authority_store::{BootstrapOptions, EngineAuthorityStore};
+use wfldb_auth::{
+    decode_signing_key, encode_public_key, AuthorityStore, Authenticator, BucketAclRegistry, NonceCache,
+    PrincipalRegistry, RequestSigner, ServerIdentity, VerifiedPacketCache, DEFAULT_CACHE_TTL, DEFAULT_REPLAY_WINDOW,
+};
+use wfldb_core::{BucketId, HybridClock, KeyCharset, KeyNormalization, NamePolicy};
+use wfldb_engine::{
+    check_consistency, data_format_version, export_bucket, import_bucket, replay_segments, AuditLog, ChangeJournal,
+    CheckOptions, MigrationOptions, Storage, StorageEngine, DATA_FORMAT_VERSION,
+};
 
+mod admin;
+mod anti_entropy;
+mod archive;
+mod audit;
+mod bandwidth;
+mod batch_get;
+mod auth;
+mod authority_store;
+mod chaos;
+mod checksum;
+mod chunks;
+mod compression;
+mod concurrency;
+mod config;
+mod connection;
+mod cors;
+mod diagnostics;
+mod document;
+mod error;
+mod health;
+mod kubernetes;
+mod lease;
+mod logging;
+mod membership;
+mod memory;
+mod multipart;
+mod objects;
+mod metrics;
+mod pools;
+mod quarantine;
+mod reconfig;
+mod replica;
+mod request_id;
+mod response_cache;
+mod revocation_sync;
+mod router;
+mod schema;
+mod search;
 mod simple_server_fixed;
+mod slo;
+mod slow_log;
+mod spool;
+mod sse;
+mod tasks;
+#[cfg(feature = "test-endpoints")]
+mod test_endpoints;
+mod transform;
+mod transport;
+mod txn;
+mod watch;
 
+use archive::ArchiveDestination;
+use bandwidth::BandwidthConfig;
+use checksum::ChecksumConfig;
+use compression::CompressionConfig;
+use cors::CorsConfig;
+use logging::{LogConfig, LogFormat, LogRotation};
+use pools::PoolConfig;
+use quarantine::{QuarantineConfig, ScanHook};
 use simple_server_fixed::SimpleServer;
+use slo::SloConfig;
+use slow_log::SlowRequestLog;
+use transform::TransformRegistry;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("features `jemalloc` and `mimalloc` are mutually exclusive");
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: memory::TrackingAllocator<tikv_jemallocator::Jemalloc> =
+    memory::TrackingAllocator::new(tikv_jemallocator::Jemalloc);
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: memory::TrackingAllocator<mimalloc::MiMalloc> =
+    memory::TrackingAllocator::new(mimalloc::MiMalloc);
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+#[global_allocator]
+static GLOBAL: memory::TrackingAllocator<std::alloc::System> =
+    memory::TrackingAllocator::new(std::alloc::System);
 
-    let matches = Command::new("wfldb-server")
+/// The server's flags; see [`config`] for their other sources
+fn cli() -> Command {
+    Command::new("wfldb-server")
         .version("0.1.0")
         .about("High-performance permissioned key-object store")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML file of settings keyed by flag name; flags and WFLDB_* environment variables override it")
+        )
+        .arg(
+            Arg::new("validate-config")
+                .long("validate-config")
+                .help("Check the configuration, print the effective settings as JSON, and exit")
+                .action(clap::ArgAction::SetTrue)
+        )
         .arg(
             Arg::new("data-dir")
                 .long("data-dir")
@@ -25,6 +124,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Data directory path")
                 .default_value("./data")
         )
+        .arg(
+            Arg::new("no-migration-backup")
+                .long("no-migration-backup")
+                .help("Migrate an older data directory in place, without copying it aside first")
+                .action(clap::ArgAction::SetTrue)
+        )
         .arg(
             Arg::new("bind")
                 .long("bind")
@@ -32,7 +137,629 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Bind address")
                 .default_value("127.0.0.1:8080")
         )
-        .get_matches();
+        .arg(
+            Arg::new("node-id")
+                .long("node-id")
+                .value_name("N")
+                .help("Node id (0-65535) stamped into object versions; give each node of a deployment its own")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("pod-name")
+                .long("pod-name")
+                .value_name("NAME")
+                .help("Kubernetes pod name, e.g. $(POD_NAME); a StatefulSet ordinal becomes the default node id and fills {pod_name} in --advertise-url")
+        )
+        .arg(
+            Arg::new("drain-delay-secs")
+                .long("drain-delay-secs")
+                .value_name("SECONDS")
+                .help("After SIGTERM, keep serving with /readyz failing this long before closing")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("leader-election-lease")
+                .long("leader-election-lease")
+                .value_name("NAMESPACE/NAME")
+                .help("Compete for this Kubernetes Lease and take writes only while holding it")
+                .requires("pod-name")
+        )
+        .arg(
+            Arg::new("leader-election-duration-secs")
+                .long("leader-election-duration-secs")
+                .value_name("SECONDS")
+                .help("How long an unrenewed leader election lease lasts before another node may take it")
+                .default_value("15")
+        )
+        .arg(
+            Arg::new("max-key-bytes")
+                .long("max-key-bytes")
+                .value_name("BYTES")
+                .help("Longest object key accepted, in UTF-8 bytes")
+                .default_value("1024")
+        )
+        .arg(
+            Arg::new("key-charset")
+                .long("key-charset")
+                .value_name("CHARSET")
+                .help("Characters keys may use: unicode (anything but controls), ascii, url-safe, or binary (unicode text or any bytes)")
+                .default_value("unicode")
+        )
+        .arg(
+            Arg::new("key-normalization")
+                .long("key-normalization")
+                .value_name("FORM")
+                .help("Normalize keys before storing them: none, or nfc in builds with the unicode-normalization feature")
+                .default_value("none")
+        )
+        .arg(
+            Arg::new("max-bucket-name-len")
+                .long("max-bucket-name-len")
+                .value_name("N")
+                .help("Longest bucket name accepted, at most 185 so partition names fit the storage engine")
+                .default_value("63")
+        )
+        .arg(
+            Arg::new("slow-request-ms")
+                .long("slow-request-ms")
+                .value_name("MS")
+                .help("Log requests slower than this many milliseconds (0 disables)")
+                .default_value("100")
+        )
+        .arg(
+            Arg::new("slow-request-sample")
+                .long("slow-request-sample")
+                .value_name("N")
+                .help("Log one in every N slow requests")
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("h2-stream-window-kb")
+                .long("h2-stream-window-kb")
+                .value_name("KB")
+                .help("HTTP/2 initial flow-control window of each stream")
+        )
+        .arg(
+            Arg::new("h2-connection-window-kb")
+                .long("h2-connection-window-kb")
+                .value_name("KB")
+                .help("HTTP/2 initial flow-control window of each connection")
+        )
+        .arg(
+            Arg::new("h2-adaptive-window")
+                .long("h2-adaptive-window")
+                .help("Size HTTP/2 windows from the measured bandwidth-delay product instead")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("h2-max-streams")
+                .long("h2-max-streams")
+                .value_name("N")
+                .help("Concurrent HTTP/2 streams a connection may open")
+        )
+        .arg(
+            Arg::new("h2-max-frame-kb")
+                .long("h2-max-frame-kb")
+                .value_name("KB")
+                .help("Largest HTTP/2 frame payload accepted (16-16383)")
+        )
+        .arg(
+            Arg::new("h2-send-buffer-kb")
+                .long("h2-send-buffer-kb")
+                .value_name("KB")
+                .help("Response bytes queued per HTTP/2 stream before the body is held back")
+        )
+        .arg(
+            Arg::new("h2-keepalive-secs")
+                .long("h2-keepalive-secs")
+                .value_name("SECS")
+                .help("Ping HTTP/2 connections this often (0 to never ping)")
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("h2-keepalive-timeout-secs")
+                .long("h2-keepalive-timeout-secs")
+                .value_name("SECS")
+                .help("Close an HTTP/2 connection whose ping goes unanswered this long")
+                .default_value("20")
+        )
+        .arg(
+            Arg::new("header-read-timeout-secs")
+                .long("header-read-timeout-secs")
+                .value_name("SECS")
+                .help("Close an HTTP/1 connection whose request headers take longer (0 for no limit)")
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("idle-timeout-secs")
+                .long("idle-timeout-secs")
+                .value_name("SECS")
+                .help("Close a connection with no request in flight this long (0 for no limit)")
+                .default_value("120")
+        )
+        .arg(
+            Arg::new("body-read-timeout-secs")
+                .long("body-read-timeout-secs")
+                .value_name("SECS")
+                .help("Fail a request whose body sends nothing this long (0 for no limit)")
+                .default_value("60")
+        )
+        .arg(
+            Arg::new("body-memory-kb")
+                .long("body-memory-kb")
+                .value_name("KB")
+                .help("Bytes of a buffered request body held in memory before it spills to disk")
+                .default_value("1024")
+        )
+        .arg(
+            Arg::new("max-object-mb")
+                .long("max-object-mb")
+                .value_name("MB")
+                .help("Largest object body a PUT may send")
+                .default_value("5120")
+        )
+        .arg(
+            Arg::new("spool-dir")
+                .long("spool-dir")
+                .value_name("DIR")
+                .help("Directory spilled request bodies are written to (default: the system temp directory)")
+        )
+        .arg(
+            Arg::new("slo-availability")
+                .long("slo-availability")
+                .value_name("PERCENT")
+                .help("Share of /v1 requests that must not fail with a server error")
+                .default_value("99.9")
+        )
+        .arg(
+            Arg::new("slo-latency-ms")
+                .long("slo-latency-ms")
+                .value_name("MS")
+                .help("/v1 requests slower than this count against the latency objective")
+                .default_value("500")
+        )
+        .arg(
+            Arg::new("slo-latency-target")
+                .long("slo-latency-target")
+                .value_name("PERCENT")
+                .help("Share of /v1 requests that must finish within --slo-latency-ms")
+                .default_value("99")
+        )
+        .arg(
+            Arg::new("memory-limit-mb")
+                .long("memory-limit-mb")
+                .value_name("MB")
+                .help("Shed object requests while the heap exceeds this size")
+        )
+        .arg(
+            Arg::new("min-free-disk-mb")
+                .long("min-free-disk-mb")
+                .value_name("MB")
+                .help("Report not ready on /readyz below this much free disk space")
+                .default_value("512")
+        )
+        .arg(
+            Arg::new("auth-root-key")
+                .long("auth-root-key")
+                .value_name("KEY")
+                .help("Root Ed25519 public key (base64url) to bootstrap request authentication with")
+        )
+        .arg(
+            Arg::new("generate-root-key")
+                .long("generate-root-key")
+                .value_name("PATH")
+                .help("On first run, generate a root key pair and write the secret key to PATH")
+                .conflicts_with("auth-root-key")
+        )
+        .arg(
+            Arg::new("auth-cache-size")
+                .long("auth-cache-size")
+                .value_name("N")
+                .help("Number of verified key packets to cache")
+                .default_value("10000")
+        )
+        .arg(
+            Arg::new("auth-clock-skew-secs")
+                .long("auth-clock-skew-secs")
+                .value_name("SECS")
+                .help("Extra tolerance for client clock skew on top of the replay window")
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("auth-nonceless-read-window-secs")
+                .long("auth-nonceless-read-window-secs")
+                .value_name("SECS")
+                .help("Accept GET and HEAD requests within SECS of the server clock without nonce tracking; only use behind TLS")
+        )
+        .arg(
+            Arg::new("revocation-upstream")
+                .long("revocation-upstream")
+                .value_name("URL")
+                .help("Pull the revocation list from this node (e.g. http://leader:8080)")
+        )
+        .arg(
+            Arg::new("revocation-poll-secs")
+                .long("revocation-poll-secs")
+                .value_name("SECS")
+                .help("Revocation list poll interval; bounds revocation propagation delay")
+                .default_value("5")
+        )
+        .arg(
+            Arg::new("replica-of")
+                .long("replica-of")
+                .value_name("URL")
+                .requires("replica-key")
+                .help("Serve reads as a replica of this leader, following its change journal; writes are refused")
+        )
+        .arg(
+            Arg::new("replica-key")
+                .long("replica-key")
+                .value_name("PATH")
+                .help("File with the signing key and admin key packet (one per line) used to read the leader's journal")
+        )
+        .arg(
+            Arg::new("replica-poll-ms")
+                .long("replica-poll-ms")
+                .value_name("MS")
+                .help("How often a replica pulls the leader's journal; reads with a floor pull sooner")
+                .default_value("500")
+        )
+        .arg(
+            Arg::new("replica-max-lag-ms")
+                .long("replica-max-lag-ms")
+                .value_name("MS")
+                .help("Take a replica out of the read pool (failing reads and /readyz) while it last matched the leader longer ago than this; 0 disables")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("anti-entropy-secs")
+                .long("anti-entropy-secs")
+                .value_name("SECS")
+                .help("How often a replica compares its buckets with the leader's and repairs differences; 0 disables")
+                .default_value("3600")
+        )
+        .arg(
+            Arg::new("advertise-url")
+                .long("advertise-url")
+                .value_name("URL")
+                .help("URL cluster peers reach this node at; turns on cluster membership")
+        )
+        .arg(
+            Arg::new("peer")
+                .long("peer")
+                .value_name("URL")
+                .help("Cluster peer to probe; the rest of the cluster is learned from it (repeatable)")
+                .action(clap::ArgAction::Append)
+                .requires("advertise-url")
+        )
+        .arg(
+            Arg::new("gossip-ms")
+                .long("gossip-ms")
+                .value_name("MS")
+                .help("How often every known cluster member is probed")
+                .default_value("1000")
+        )
+        .arg(
+            Arg::new("tombstone-retention-hours")
+                .long("tombstone-retention-hours")
+                .value_name("HOURS")
+                .help("How long journaled deletes keep a tombstone; replicas further behind must be re-seeded")
+                .default_value("168")
+        )
+        .arg(
+            Arg::new("multipart-expiry-hours")
+                .long("multipart-expiry-hours")
+                .value_name("HOURS")
+                .help("How long an unfinished multipart upload is kept before it is aborted")
+                .default_value("24")
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .value_name("DIRECTIVES")
+                .help("Log filter as in RUST_LOG, e.g. info,wfldb_engine=debug; overrides RUST_LOG")
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .help("Log line format: text or json")
+                .default_value("text")
+        )
+        .arg(
+            Arg::new("log-dir")
+                .long("log-dir")
+                .value_name("DIR")
+                .help("Also write logs to rotated files in DIR")
+        )
+        .arg(
+            Arg::new("log-rotation")
+                .long("log-rotation")
+                .value_name("WHEN")
+                .help("When to start a new log file: hourly, daily, never, or a size such as 100mb")
+                .default_value("daily")
+        )
+        .arg(
+            Arg::new("log-max-files")
+                .long("log-max-files")
+                .value_name("N")
+                .help("Log files to keep in --log-dir, the current one included")
+                .default_value("7")
+        )
+        .arg(
+            Arg::new("no-log-stdout")
+                .long("no-log-stdout")
+                .help("Write logs only to --log-dir, not stdout")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("debug-endpoints")
+                .long("debug-endpoints")
+                .help("Expose /debug/runtime diagnostics")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("enable-chaos")
+                .long("enable-chaos")
+                .help("Let admins inject latency, write failures and dropped connections through /admin/chaos")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("quarantine-hook")
+                .long("quarantine-hook")
+                .help("Hold new objects from non-admin readers until this URL or shell command approves them")
+        )
+        .arg(
+            Arg::new("quarantine-interval-secs")
+                .long("quarantine-interval-secs")
+                .help("Scan held objects this often")
+                .default_value("5")
+        )
+        .arg(
+            Arg::new("quarantine-timeout-secs")
+                .long("quarantine-timeout-secs")
+                .help("Give up on a scan after this long and retry it on a later pass")
+                .default_value("60")
+        )
+        .arg(
+            Arg::new("archive-dest")
+                .long("archive-dest")
+                .help("Journal object changes and archive them to a directory or s3://bucket/prefix")
+        )
+        .arg(
+            Arg::new("archive-s3-endpoint")
+                .long("archive-s3-endpoint")
+                .help("S3-compatible endpoint URL (default: AWS for $AWS_REGION)")
+        )
+        .arg(
+            Arg::new("archive-interval-secs")
+                .long("archive-interval-secs")
+                .help("Seal and ship the active journal segment at least this often")
+                .default_value("60")
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .help("Journal object changes without archiving them, so buckets can be migrated off this node")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("journal-segment-mb")
+                .long("journal-segment-mb")
+                .help("Seal journal segments once they reach this size")
+                .default_value("64")
+        )
+        .arg(
+            Arg::new("restore-journal")
+                .long("restore-journal")
+                .help("Replay archived journal segments from this directory onto the data directory, then exit")
+        )
+        .arg(
+            Arg::new("export-bucket")
+                .long("export-bucket")
+                .num_args(2)
+                .value_names(["BUCKET", "DIR"])
+                .help("Write a bucket to a portable archive in DIR (empty or new), then exit")
+        )
+        .arg(
+            Arg::new("import-bucket")
+                .long("import-bucket")
+                .num_args(2)
+                .value_names(["BUCKET", "DIR"])
+                .help("Load the archive in DIR into a bucket, keeping newer objects already there, then exit")
+                .conflicts_with("export-bucket")
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .help("Record every /v1 and /admin request in an audit log that can be exported signed")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("audit-retention-days")
+                .long("audit-retention-days")
+                .help("Drop audit events older than this")
+                .default_value("365")
+        )
+        .arg(
+            Arg::new("export-audit")
+                .long("export-audit")
+                .num_args(3)
+                .value_names(["FROM_MS", "TO_MS", "FILE"])
+                .help("Write the audit events of a time range to FILE, signed by this server's identity key, then exit")
+        )
+        .arg(
+            Arg::new("verify-audit-export")
+                .long("verify-audit-export")
+                .num_args(2)
+                .value_names(["FILE", "PUBLIC_KEY"])
+                .help("Check that an audit export was signed by this identity public key and is unaltered, then exit")
+        )
+        .arg(
+            Arg::new("pool-permits")
+                .long("pool-permits")
+                .value_name("N")
+                .help("Concurrent storage requests admitted per bucket, or per tenant for tenant keys")
+                .default_value("32")
+        )
+        .arg(
+            Arg::new("pool")
+                .long("pool")
+                .value_name("POOL=N")
+                .help("Override one pool's size, e.g. bucket/videos=4 or tenant/acme=64 (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("bandwidth")
+                .long("bandwidth")
+                .value_name("NAME=IN/OUT")
+                .help("Cap a key, principal or bucket at IN/OUT KiB/s ('-' for unlimited), e.g. key/*=1024/4096 or bucket/videos=-/20480 (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("warmup-keys")
+                .long("warmup-keys")
+                .value_name("N")
+                .help("Remember the N most recently read objects and read them back before serving after a restart")
+        )
+        .arg(
+            Arg::new("response-cache-mb")
+                .long("response-cache-mb")
+                .value_name("MB")
+                .help("Cache up to MB of object bodies in memory for GETs")
+        )
+        .arg(
+            Arg::new("response-cache-entries")
+                .long("response-cache-entries")
+                .value_name("N")
+                .help("Most objects the response cache holds")
+                .default_value("10000")
+        )
+        .arg(
+            Arg::new("compress-responses")
+                .long("compress-responses")
+                .help("Compress text-like GET bodies for clients that accept gzip or zstd")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("compress-bucket")
+                .long("compress-bucket")
+                .value_name("BUCKET=on|off")
+                .help("Turn response compression on or off for one bucket (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("compress-min-bytes")
+                .long("compress-min-bytes")
+                .value_name("BYTES")
+                .help("Smallest GET body worth compressing")
+                .default_value("1024")
+        )
+        .arg(
+            Arg::new("bucket-checksums")
+                .long("bucket-checksums")
+                .value_name("BUCKET=ALGORITHMS")
+                .help("Keep crc32c and/or sha256 checksums of every PUT to a bucket, e.g. imports=crc32c,sha256 (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("search-index")
+                .long("search-index")
+                .value_name("BUCKET")
+                .help("Keep a full-text index of a bucket for /v1/{bucket}/_search; tenant buckets as TENANT#BUCKET (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("cors-origin")
+                .long("cors-origin")
+                .value_name("ORIGIN")
+                .help("Let browser apps from ORIGIN call /v1, e.g. https://app.example.com or * (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("cors-methods")
+                .long("cors-methods")
+                .value_name("METHODS")
+                .help("Comma-separated methods cross-origin requests may use")
+                .default_value("GET,PUT,POST,PATCH,DELETE")
+        )
+        .arg(
+            Arg::new("cors-headers")
+                .long("cors-headers")
+                .value_name("HEADERS")
+                .help("Comma-separated extra request headers cross-origin requests may set")
+        )
+        .arg(
+            Arg::new("cors-max-age-secs")
+                .long("cors-max-age-secs")
+                .value_name("SECONDS")
+                .help("How long browsers may cache a preflight answer")
+                .default_value("600")
+        )
+        .arg(
+            Arg::new("disable-transform")
+                .long("disable-transform")
+                .value_name("NAME")
+                .help("Turn off a read transform, e.g. gunzip (repeatable)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("max-background-tasks")
+                .long("max-background-tasks")
+                .value_name("N")
+                .help("Background maintenance tasks allowed to run at once")
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("deep-check")
+                .long("deep-check")
+                .help("Verify every object and chunk on startup instead of a bounded pass")
+                .action(clap::ArgAction::SetTrue)
+        )
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::Config::load(cli());
+    let matches = &config.matches;
+    let errors = config::validate(matches);
+    // Before logging starts, so stdout holds only the report
+    if matches.get_flag("validate-config") {
+        let report = serde_json::json!({"valid": errors.is_empty(), "errors": errors, "config": config.effective()});
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        std::process::exit(if errors.is_empty() { 0 } else { 1 });
+    }
+    if !errors.is_empty() {
+        return Err(format!("Invalid configuration: {}", errors.join("; ")).into());
+    }
+
+    let log_config = LogConfig {
+        level: matches.get_one::<String>("log-level").cloned(),
+        format: LogFormat::parse(matches.get_one::<String>("log-format").unwrap())
+            .expect("--log-format must be text or json"),
+        stdout: !matches.get_flag("no-log-stdout") || !matches.contains_id("log-dir"),
+        dir: matches.get_one::<String>("log-dir").map(PathBuf::from),
+        rotation: LogRotation::parse(matches.get_one::<String>("log-rotation").unwrap())
+            .expect("--log-rotation must be hourly, daily, never, or a size such as 100mb"),
+        max_files: matches.get_one::<String>("log-max-files")
+            .unwrap()
+            .parse()
+            .expect("Invalid --log-max-files"),
+    };
+    // Initialize tracing; the guard flushes queued file output on exit
+    let _log_guard = logging::init(&log_config)?;
+
+    // Needs no data directory, so compliance can run it anywhere
+    if let Some(mut values) = matches.get_many::<String>("verify-audit-export") {
+        let (file, public_key) = (values.next().unwrap(), values.next().unwrap());
+        let extract = std::fs::read(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+        let header = audit::verify(&extract, public_key).map_err(|e| format!("Verification failed: {}", e))?;
+        println!(
+            "Verified: {} events from node {} between {} and {} ms, exported at {} ms",
+            header.events, header.node_id, header.from_ms, header.to_ms, header.exported_at_ms
+        );
+        return Ok(());
+    }
 
     let data_dir: PathBuf = matches.get_one::<String>("data-dir")
         .unwrap()
@@ -44,9 +771,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .parse()
         .expect("Invalid bind address");
 
+    let slow_request_ms: u64 = matches.get_one::<String>("slow-request-ms")
+        .unwrap()
+        .parse()
+        .expect("Invalid slow request threshold");
+
+    let slow_request_sample: u64 = matches.get_one::<String>("slow-request-sample")
+        .unwrap()
+        .parse()
+        .expect("Invalid slow request sampling interval");
+
+    // A StatefulSet pod is numbered by its ordinal unless told otherwise
+    let pod_name = matches.get_one::<String>("pod-name");
+    let ordinal = pod_name.and_then(|pod_name| kubernetes::statefulset_ordinal(pod_name));
+    let node_id: u16 = match ordinal {
+        Some(ordinal) if matches.value_source("node-id") != Some(ValueSource::CommandLine) => ordinal,
+        _ => matches.get_one::<String>("node-id")
+            .unwrap()
+            .parse()
+            .expect("Invalid node id"),
+    };
+    HybridClock::global().set_node_id(node_id);
+
+    // Names are checked against the policy wherever they're built, so set it first
+    let key_charset = matches.get_one::<String>("key-charset").unwrap();
+    let key_normalization = matches.get_one::<String>("key-normalization").unwrap();
+    NamePolicy::set_global(NamePolicy {
+        max_key_bytes: matches.get_one::<String>("max-key-bytes")
+            .unwrap()
+            .parse()
+            .expect("Invalid key length limit"),
+        key_charset: KeyCharset::parse(key_charset)
+            .ok_or_else(|| format!("unknown key charset '{}'", key_charset))?,
+        key_normalization: KeyNormalization::parse(key_normalization)
+            .ok_or_else(|| format!("unsupported key normalization '{}'", key_normalization))?,
+        max_bucket_len: matches.get_one::<String>("max-bucket-name-len")
+            .unwrap()
+            .parse()
+            .expect("Invalid bucket name length limit"),
+    });
+
     info!("Starting wflDB server (Phase 0 Spike)");
     info!("Data directory: {}", data_dir.display());
     info!("Bind address: {}", bind_addr);
+    info!("Node id: {}", node_id);
 
     // Create data directory if it doesn't exist
     if !data_dir.exists() {
@@ -54,14 +822,451 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("Created data directory: {}", data_dir.display());
     }
 
-    // Initialize storage engine
-    let storage_engine = StorageEngine::new(&data_dir)
+    // Initialize storage engine, migrating an older data directory first
+    match data_format_version(&data_dir)? {
+        Some(found) if found < DATA_FORMAT_VERSION => {
+            info!("Migrating data directory from format {} to {}", found, DATA_FORMAT_VERSION)
+        }
+        _ => {}
+    }
+    let migration_options = MigrationOptions { backup: !matches.get_flag("no-migration-backup") };
+    let mut storage_engine = StorageEngine::open(&data_dir, migration_options)
         .map_err(|e| format!("Failed to initialize storage engine: {}", e))?;
 
     info!("Storage engine initialized");
 
+    if let Some(archive_dir) = matches.get_one::<String>("restore-journal") {
+        let summary = replay_segments(archive_dir, &Storage::new(storage_engine.clone()))
+            .map_err(|e| format!("Journal restore failed: {}", e))?;
+        info!(
+            "Restored {} changes from {} archived segments (now at journal sequence {})",
+            summary.applied, summary.segments, summary.last_seq
+        );
+        return Ok(());
+    }
+
+    let archive_dest = matches.get_one::<String>("archive-dest")
+        .map(|spec| ArchiveDestination::parse(spec, matches.get_one::<String>("archive-s3-endpoint").map(String::as_str)))
+        .transpose()?;
+    if archive_dest.is_some() || matches.get_flag("journal") {
+        let segment_mb: u64 = matches.get_one::<String>("journal-segment-mb")
+            .unwrap()
+            .parse()
+            .expect("Invalid journal segment size");
+        let journal = ChangeJournal::open_with_checkpoint(data_dir.join("journal"), storage_engine.system()?)
+            .map_err(|e| format!("Failed to open change journal: {}", e))?
+            .with_segment_bytes(segment_mb.max(1) * 1024 * 1024);
+        info!("Change journal enabled at sequence {}", journal.next_seq());
+        storage_engine = storage_engine.with_journal(Arc::new(journal));
+    }
+
+    // After the journal opens, so replicas follow an import like any other writes
+    let archive_args = |name: &str| -> Result<Option<(BucketId, String)>, Box<dyn std::error::Error>> {
+        let Some(mut values) = matches.get_many::<String>(name) else {
+            return Ok(None);
+        };
+        let bucket = values.next().unwrap();
+        let bucket = BucketId::new(bucket).map_err(|e| format!("Invalid bucket '{}': {}", bucket, e))?;
+        Ok(Some((bucket, values.next().unwrap().clone())))
+    };
+    if let Some((bucket, dir)) = archive_args("export-bucket")? {
+        let manifest = export_bucket(&Storage::new(storage_engine.clone()), &bucket, &dir)
+            .map_err(|e| format!("Export failed: {}", e))?;
+        info!(
+            "Exported {} objects ({} bytes, {} chunk files) from {} to {}",
+            manifest.objects, manifest.bytes, manifest.chunks, bucket.as_str(), dir
+        );
+        return Ok(());
+    }
+    if let Some((bucket, dir)) = archive_args("import-bucket")? {
+        let summary = import_bucket(&Storage::new(storage_engine.clone()), &bucket, &dir)
+            .map_err(|e| format!("Import failed: {}", e))?;
+        info!(
+            "Imported {} objects into {} from {} ({} already current, {} expired)",
+            summary.imported, bucket.as_str(), dir, summary.skipped, summary.expired
+        );
+        return Ok(());
+    }
+
+    let authority_store: Arc<dyn AuthorityStore> = Arc::new(EngineAuthorityStore::new(storage_engine.system()?));
+    let identity = ServerIdentity::load_or_generate(authority_store.as_ref())
+        .map_err(|e| format!("Failed to load server identity key: {}", e))?;
+    info!("Server identity key {} ({})", identity.key_id(), encode_public_key(&identity.public_key()));
+    if let Some(mut values) = matches.get_many::<String>("export-audit") {
+        let mut time = || -> Result<u64, String> {
+            let value = values.next().unwrap();
+            value.parse().map_err(|_| format!("Invalid time '{}'; expected milliseconds since the epoch", value))
+        };
+        let (from_ms, to_ms) = (time()?, time()?);
+        let file = values.next().unwrap();
+        let log = AuditLog::new(storage_engine.system()?);
+        let extract = audit::export(&log, &identity, from_ms, to_ms).map_err(|e| format!("Audit export failed: {}", e))?;
+        std::fs::write(file, &extract).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+        info!("Exported the audit log from {} to {} ms to {}", from_ms, to_ms, file);
+        return Ok(());
+    }
+
+    let check_options = if matches.get_flag("deep-check") {
+        CheckOptions::deep()
+    } else {
+        CheckOptions::quick()
+    };
+    let report = check_consistency(&storage_engine, check_options)
+        .map_err(|e| format!("Startup consistency check failed: {}", e))?;
+    if report.is_clean() {
+        info!("Recovery report: {}", report);
+    } else {
+        warn!("Recovery report: {}", report);
+        for name in report.corrupt_system_entries.iter().take(10) {
+            warn!("Corrupt system entry: {}", name);
+        }
+        for name in report.corrupt_metadata.iter().take(10) {
+            warn!("Corrupt object metadata: {}", name);
+        }
+        for name in report.incomplete_objects.iter().take(10) {
+            warn!("Object with missing data (partial write?): {}", name);
+        }
+        for name in report.hash_mismatches.iter().take(10) {
+            warn!("Object data does not match its hash: {}", name);
+        }
+    }
+
     // Create and start server
-    let server = SimpleServer::new(storage_engine);
+    let slow_log = if slow_request_ms == 0 {
+        SlowRequestLog::disabled()
+    } else {
+        info!("Slow request threshold: {}ms (sampling 1/{})", slow_request_ms, slow_request_sample);
+        SlowRequestLog::new(Duration::from_millis(slow_request_ms), slow_request_sample)
+    };
+
+    let memory_limit: Option<usize> = matches.get_one::<String>("memory-limit-mb")
+        .map(|mb| mb.parse::<usize>().expect("Invalid memory limit"))
+        .map(|mb| mb * 1024 * 1024);
+    info!("Allocator: {}", memory::allocator_name());
+    if let Some(limit) = memory_limit {
+        info!("Heap limit: {} bytes", limit);
+    }
+
+    let debug_endpoints = matches.get_flag("debug-endpoints");
+    if debug_endpoints {
+        info!("Debug endpoints enabled at /debug/*");
+    }
+
+    let enable_chaos = matches.get_flag("enable-chaos");
+    if enable_chaos {
+        warn!("Fault injection enabled at /admin/chaos; do not use in production");
+    }
+
+    let min_free_disk_mb: u64 = matches.get_one::<String>("min-free-disk-mb")
+        .unwrap()
+        .parse()
+        .expect("Invalid minimum free disk space");
+
+    let mut server = SimpleServer::new(storage_engine.clone())
+        .with_slow_log(slow_log)
+        .with_debug_endpoints(debug_endpoints)
+        .with_chaos(enable_chaos)
+        .with_memory_limit(memory_limit)
+        .with_min_free_disk(min_free_disk_mb * 1024 * 1024);
+
+    let percent = |name: &str| -> f64 {
+        let value: f64 = matches.get_one::<String>(name).unwrap().parse().expect("Invalid SLO target");
+        assert!((0.0..=100.0).contains(&value), "--{} must be between 0 and 100", name);
+        value / 100.0
+    };
+    server = server.with_slo(SloConfig {
+        availability: percent("slo-availability"),
+        latency_threshold: Duration::from_millis(
+            matches.get_one::<String>("slo-latency-ms").unwrap().parse().expect("Invalid SLO latency threshold"),
+        ),
+        latency_target: percent("slo-latency-target"),
+    });
+
+    let http2 = config::http2(matches)?;
+    http2.validate()?;
+    server = server.with_http2(http2);
+    server = server.with_connection_limits(config::connection_limits(matches)?);
+    let body_memory_kb: usize = matches.get_one::<String>("body-memory-kb")
+        .unwrap()
+        .parse()
+        .expect("Invalid body memory size");
+    let spool_dir = matches.get_one::<String>("spool-dir").map_or_else(std::env::temp_dir, PathBuf::from);
+    server = server.with_body_spool(body_memory_kb.saturating_mul(1024), spool_dir);
+    let max_object_mb: usize = matches.get_one::<String>("max-object-mb")
+        .unwrap()
+        .parse()
+        .expect("Invalid max object size");
+    server = server.with_max_object_size(max_object_mb.saturating_mul(1024 * 1024));
+
+    let mut pools = PoolConfig {
+        default_permits: matches.get_one::<String>("pool-permits")
+            .unwrap()
+            .parse()
+            .expect("Invalid pool permit count"),
+        ..Default::default()
+    };
+    for spec in matches.get_many::<String>("pool").into_iter().flatten() {
+        let (name, permits) = PoolConfig::parse_override(spec)?;
+        pools.overrides.insert(name, permits);
+    }
+    server = server.with_pools(pools);
+
+    let mut bandwidth = BandwidthConfig::default();
+    for spec in matches.get_many::<String>("bandwidth").into_iter().flatten() {
+        let (name, limit) = BandwidthConfig::parse_limit(spec)?;
+        bandwidth.limits.insert(name, limit);
+    }
+    if !bandwidth.limits.is_empty() {
+        info!("Bandwidth limits: {}", bandwidth.limits.len());
+        server = server.with_bandwidth(bandwidth);
+    }
+
+    if let Some(mb) = matches.get_one::<String>("response-cache-mb") {
+        let mb: usize = mb.parse().expect("Invalid response cache size");
+        let entries: usize = matches.get_one::<String>("response-cache-entries")
+            .unwrap()
+            .parse()
+            .expect("Invalid response cache entry count");
+        info!("Response cache enabled: {} MB, {} entries", mb, entries);
+        server = server.with_response_cache(mb * 1024 * 1024, entries);
+    }
+
+    let mut compression = CompressionConfig {
+        enabled: matches.get_flag("compress-responses"),
+        min_bytes: matches.get_one::<String>("compress-min-bytes")
+            .unwrap()
+            .parse()
+            .expect("Invalid compression threshold"),
+        ..Default::default()
+    };
+    for spec in matches.get_many::<String>("compress-bucket").into_iter().flatten() {
+        let (bucket, enabled) = CompressionConfig::parse_override(spec)?;
+        compression.overrides.insert(bucket, enabled);
+    }
+    server = server.with_compression(compression);
+
+    let mut checksums = ChecksumConfig::default();
+    for spec in matches.get_many::<String>("bucket-checksums").into_iter().flatten() {
+        let (bucket, algorithms) = ChecksumConfig::parse_bucket(spec)?;
+        checksums.buckets.insert(bucket, algorithms);
+    }
+    server = server.with_checksums(checksums);
+
+    let search_buckets: Vec<String> = matches.get_many::<String>("search-index").into_iter().flatten().cloned().collect();
+    for bucket in &search_buckets {
+        storage_engine.resolve_namespaced(bucket)?;
+    }
+    server = server.with_search_indexes(search_buckets);
+
+    server = server.with_identity(identity);
+    if matches.get_flag("audit-log") {
+        let days: u64 = matches.get_one::<String>("audit-retention-days")
+            .unwrap()
+            .parse()
+            .expect("Invalid audit retention");
+        server = server.with_audit_log(Duration::from_secs(days.max(1) * 24 * 60 * 60));
+        info!("Audit log enabled, keeping {} days", days.max(1));
+    }
+
+    let cors_origins: Vec<String> = matches.get_many::<String>("cors-origin").into_iter().flatten().cloned().collect();
+    if !cors_origins.is_empty() {
+        let mut cors = CorsConfig::new(cors_origins);
+        cors.allowed_methods = matches.get_one::<String>("cors-methods")
+            .unwrap()
+            .split(',')
+            .map(|m| m.trim().to_ascii_uppercase().parse())
+            .collect::<Result<_, _>>()
+            .expect("Invalid CORS method");
+        if let Some(extra) = matches.get_one::<String>("cors-headers") {
+            cors.allowed_headers.extend(extra.split(',').map(|h| h.trim().to_ascii_lowercase()));
+        }
+        cors.max_age = Duration::from_secs(matches.get_one::<String>("cors-max-age-secs")
+            .unwrap()
+            .parse()
+            .expect("Invalid CORS max age"));
+        info!("CORS enabled for {}", cors.allowed_origins.join(", "));
+        server = server.with_cors(cors);
+    }
+
+    let mut transforms = TransformRegistry::default();
+    for name in matches.get_many::<String>("disable-transform").into_iter().flatten() {
+        if !transforms.remove(name) {
+            return Err(format!("Unknown transform '{}'", name).into());
+        }
+    }
+    server = server.with_transforms(transforms);
+
+    let max_background_tasks: usize = matches.get_one::<String>("max-background-tasks")
+        .unwrap()
+        .parse()
+        .expect("Invalid background task limit");
+    server = server.with_max_background_tasks(max_background_tasks);
+
+    // Flags and environment are as at startup, so a reload picks up edits to the file
+    server = server.with_reload(|| {
+        let config = config::Config::try_load(cli()).map_err(|e| e.to_string())?;
+        let errors = config::validate(&config.matches);
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        Ok(reconfig::RuntimeSettings::from_matches(&config.matches))
+    });
+
+    if let Some(keys) = matches.get_one::<String>("warmup-keys") {
+        let keys: usize = keys.parse().expect("Invalid warm-up key count");
+        info!("Warm-up enabled for the {} most recently read objects", keys);
+        server = server.with_warmup(keys);
+    }
+
+    if let Some(leader) = matches.get_one::<String>("replica-of") {
+        let key_path = matches.get_one::<String>("replica-key").unwrap();
+        let credentials = std::fs::read_to_string(key_path)
+            .map_err(|e| format!("Failed to read replica key {}: {}", key_path, e))?;
+        let mut lines = credentials.lines().map(str::trim).filter(|line| !line.is_empty());
+        let (Some(key), Some(packet)) = (lines.next(), lines.next()) else {
+            return Err(format!("{} must hold a signing key and a key packet", key_path).into());
+        };
+        let key = decode_signing_key(key).map_err(|e| format!("Invalid replica signing key: {}", e))?;
+        let poll_ms: u64 = matches.get_one::<String>("replica-poll-ms")
+            .unwrap()
+            .parse()
+            .expect("Invalid replica poll interval");
+        let anti_entropy_secs: u64 = matches.get_one::<String>("anti-entropy-secs")
+            .unwrap()
+            .parse()
+            .expect("Invalid anti-entropy interval");
+        if anti_entropy_secs > 0 {
+            server = server.with_anti_entropy(Duration::from_secs(anti_entropy_secs));
+        }
+        let max_lag_ms: u64 = matches.get_one::<String>("replica-max-lag-ms")
+            .unwrap()
+            .parse()
+            .expect("Invalid replica lag limit");
+        if max_lag_ms > 0 {
+            info!("Replica leaves the read pool beyond {}ms of lag", max_lag_ms);
+            server = server.with_replica_max_lag(Duration::from_millis(max_lag_ms));
+        }
+        info!("Read replica of {}", leader);
+        server = server.with_replica_of(
+            leader.clone(),
+            Duration::from_millis(poll_ms.max(10)),
+            RequestSigner::new(key, packet.to_string()),
+        );
+    }
+
+    if let Some(advertise_url) = matches.get_one::<String>("advertise-url") {
+        let peers: Vec<String> = matches.get_many::<String>("peer").into_iter().flatten().cloned().collect();
+        let gossip_ms: u64 = matches.get_one::<String>("gossip-ms")
+            .unwrap()
+            .parse()
+            .expect("Invalid gossip interval");
+        let advertise_url = kubernetes::advertise_url(advertise_url, pod_name.map(String::as_str))?;
+        server = server.with_membership(advertise_url, peers, Duration::from_millis(gossip_ms.max(100)));
+    }
+
+    let drain_delay_secs: u64 = matches.get_one::<String>("drain-delay-secs")
+        .unwrap()
+        .parse()
+        .expect("Invalid drain delay");
+    server = server.with_drain_delay(Duration::from_secs(drain_delay_secs));
+
+    if let Some(lease) = matches.get_one::<String>("leader-election-lease") {
+        let duration_secs: u64 = matches.get_one::<String>("leader-election-duration-secs")
+            .unwrap()
+            .parse()
+            .expect("Invalid leader election lease duration");
+        let identity = pod_name.expect("--leader-election-lease requires --pod-name");
+        server = server.with_leader_election(lease.clone(), identity.clone(), Duration::from_secs(duration_secs.max(1)));
+    }
+
+    let retention_hours: u64 = matches.get_one::<String>("tombstone-retention-hours")
+        .unwrap()
+        .parse()
+        .expect("Invalid tombstone retention");
+    server = server.with_tombstone_retention(Duration::from_secs(retention_hours * 60 * 60));
+
+    let expiry_hours: u64 = matches.get_one::<String>("multipart-expiry-hours")
+        .unwrap()
+        .parse()
+        .expect("Invalid multipart upload expiry");
+    server = server.with_upload_expiry(Duration::from_secs(expiry_hours.max(1) * 60 * 60));
+
+    if let Some(destination) = archive_dest {
+        let interval_secs: u64 = matches.get_one::<String>("archive-interval-secs")
+            .unwrap()
+            .parse()
+            .expect("Invalid archive interval");
+        server = server.with_journal_archive(destination, Duration::from_secs(interval_secs.max(1)));
+    }
+
+    if let Some(spec) = matches.get_one::<String>("quarantine-hook") {
+        let secs = |name: &str| -> Duration {
+            let secs: u64 = matches.get_one::<String>(name).unwrap().parse().expect("Invalid quarantine interval");
+            Duration::from_secs(secs.max(1))
+        };
+        let hook = ScanHook::parse(spec)?;
+        server = server.with_quarantine(QuarantineConfig {
+            hook,
+            interval: secs("quarantine-interval-secs"),
+            timeout: secs("quarantine-timeout-secs"),
+        });
+    }
+
+    let generate_root = matches.get_one::<String>("generate-root-key").map(PathBuf::from);
+    let bootstrap = BootstrapOptions {
+        import_root: matches.get_one::<String>("auth-root-key").map(String::as_str),
+        generate_root: generate_root.as_deref(),
+    };
+    let authority = authority_store::load_or_bootstrap(authority_store.clone(), &bootstrap)?;
+
+    if let Some(authority) = authority {
+        let cache_size: usize = matches.get_one::<String>("auth-cache-size")
+            .unwrap()
+            .parse()
+            .expect("Invalid auth cache size");
+        let clock_skew_secs: u64 = matches.get_one::<String>("auth-clock-skew-secs")
+            .unwrap()
+            .parse()
+            .expect("Invalid clock skew tolerance");
+        let mut nonces = NonceCache::new(DEFAULT_REPLAY_WINDOW).with_clock_skew(Duration::from_secs(clock_skew_secs));
+        if let Some(window) = matches.get_one::<String>("auth-nonceless-read-window-secs") {
+            let window: u64 = window.parse().expect("Invalid nonce-less read window");
+            // The read window replaces the nonce cache, so it must be the tighter check
+            if window == 0 || window > DEFAULT_REPLAY_WINDOW.as_secs() {
+                return Err(format!(
+                    "--auth-nonceless-read-window-secs must be between 1 and {}",
+                    DEFAULT_REPLAY_WINDOW.as_secs()
+                ).into());
+            }
+            nonces = nonces.with_nonceless_reads(Duration::from_secs(window));
+            warn!("Reads are authenticated without nonces within {}s; captured reads can be replayed that long", window);
+        }
+        let principals = PrincipalRegistry::open(authority_store.clone())
+            .map_err(|e| format!("Failed to load principals: {}", e))?;
+        info!("Loaded {} principals", principals.list().len());
+        let bucket_acls = BucketAclRegistry::open(authority_store)
+            .map_err(|e| format!("Failed to load bucket ACLs: {}", e))?;
+        let authenticator = Authenticator::new(Arc::new(authority))
+            .with_principals(Arc::new(principals))
+            .with_bucket_acls(Arc::new(bucket_acls))
+            .with_cache(VerifiedPacketCache::new(cache_size, DEFAULT_CACHE_TTL))
+            .with_nonce_cache(nonces);
+        info!(
+            "Request authentication enabled (packet cache: {} entries, clock skew tolerance: {}s)",
+            cache_size, clock_skew_secs
+        );
+        server = server.with_auth(authenticator);
+
+        if let Some(upstream) = matches.get_one::<String>("revocation-upstream") {
+            let poll_secs: u64 = matches.get_one::<String>("revocation-poll-secs")
+                .unwrap()
+                .parse()
+                .expect("Invalid revocation poll interval");
+            server = server.with_revocation_upstream(upstream.clone(), Duration::from_secs(poll_secs.max(1)));
+        }
+    } else {
+        warn!("Request authentication disabled; bootstrap with --auth-root-key or --generate-root-key");
+    }
     
     match server.serve(bind_addr).await {
         Ok(_) => info!("Server shutdown gracefully"),
@@ -0,0 +1,352 @@
+//! Cluster membership
+//!
+//! A node started with `--advertise-url` (the URL its peers reach it at)
+//! and any number of `--peer` seeds probes every member it knows with
+//! `GET /cluster/ping` each interval. The answer names the peer's node id,
+//! role and journal position along with every member the peer knows, so a
+//! node learns the whole cluster from a single seed, and a node joining
+//! later is learned by everyone after a round or two. Replicas also report
+//! how stale they are and whether they are in the read pool, so any node
+//! can show each follower's lag.
+//!
+//! A member that misses a probe is suspect; one that misses
+//! [`DEAD_AFTER_MISSES`] in a row is dead. Dead members stay in the map and
+//! come back to life on their next answer.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+use wfldb_auth::now_ms;
+use wfldb_core::HybridClock;
+use crate::health::TaskHeartbeats;
+use crate::replica;
+use crate::simple_server_fixed::ServerState;
+
+/// Name of the prober in the liveness probe
+const HEARTBEAT_TASK: &str = "membership";
+
+/// Consecutive missed probes after which a member is dead
+pub const DEAD_AFTER_MISSES: u32 = 3;
+
+/// Longest a probe waits for an answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether a node takes writes or follows a leader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeRole {
+    Leader,
+    Replica,
+}
+
+impl NodeRole {
+    /// Role of the node serving `state`; under leader election, nodes not
+    /// holding the lease count as replicas since they refuse writes
+    pub fn of(state: &ServerState) -> Self {
+        match (&state.replica, &state.election) {
+            (Some(_), _) => NodeRole::Replica,
+            (None, Some(election)) if !election.is_leader() => NodeRole::Replica,
+            (None, _) => NodeRole::Leader,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberState {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+impl MemberState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemberState::Alive => "alive",
+            MemberState::Suspect => "suspect",
+            MemberState::Dead => "dead",
+        }
+    }
+}
+
+/// What this node knows of a peer
+#[derive(Debug, Clone, Serialize)]
+pub struct Member {
+    pub url: String,
+    pub state: MemberState,
+    /// From the peer's last answer; `None` until it answers
+    pub node_id: Option<u16>,
+    pub role: Option<NodeRole>,
+    pub applied_seq: Option<u64>,
+    /// Time since a replica member last matched its leader
+    pub staleness_ms: Option<u64>,
+    /// Whether a replica member serves reads; `None` for leaders
+    pub in_read_pool: Option<bool>,
+    pub last_seen_ms: Option<u64>,
+    /// Probes missed since the last answer
+    pub missed: u32,
+}
+
+impl Member {
+    fn new(url: String) -> Self {
+        Member {
+            url,
+            state: MemberState::Suspect,
+            node_id: None,
+            role: None,
+            applied_seq: None,
+            staleness_ms: None,
+            in_read_pool: None,
+            last_seen_ms: None,
+            missed: 0,
+        }
+    }
+}
+
+/// A node's answer to `GET /cluster/ping`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingAnswer {
+    pub url: String,
+    pub node_id: u16,
+    pub role: NodeRole,
+    pub applied_seq: Option<u64>,
+    /// On a replica, the time since it last matched its leader
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staleness_ms: Option<u64>,
+    /// On a replica, whether it serves reads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_read_pool: Option<bool>,
+    /// Members the node knows, dead ones included
+    pub members: Vec<String>,
+}
+
+/// This node's view of the cluster
+#[derive(Debug)]
+pub struct ClusterMap {
+    self_url: String,
+    members: RwLock<BTreeMap<String, Member>>,
+}
+
+impl ClusterMap {
+    /// Map of a node reachable at `self_url`, seeded with `peers`
+    pub fn new(self_url: &str, peers: impl IntoIterator<Item = String>) -> Self {
+        let map = ClusterMap {
+            self_url: normalize(self_url),
+            members: RwLock::new(BTreeMap::new()),
+        };
+        for peer in peers {
+            map.learn(&peer);
+        }
+        map
+    }
+
+    /// URL peers reach this node at
+    pub fn self_url(&self) -> &str {
+        &self.self_url
+    }
+
+    /// Every known peer, ordered by URL
+    pub fn members(&self) -> Vec<Member> {
+        self.members.read().unwrap().values().cloned().collect()
+    }
+
+    /// Alive leaders, for routing writes
+    pub fn leaders(&self) -> Vec<String> {
+        self.members
+            .read()
+            .unwrap()
+            .values()
+            .filter(|member| member.state == MemberState::Alive && member.role == Some(NodeRole::Leader))
+            .map(|member| member.url.clone())
+            .collect()
+    }
+
+    /// Add a peer not yet known
+    fn learn(&self, url: &str) {
+        let url = normalize(url);
+        if url != self.self_url && !url.is_empty() {
+            self.members.write().unwrap().entry(url.clone()).or_insert_with(|| Member::new(url));
+        }
+    }
+
+    fn record_answer(&self, url: &str, answer: PingAnswer, now_ms: u64) {
+        if let Some(member) = self.members.write().unwrap().get_mut(url) {
+            if member.state != MemberState::Alive {
+                info!("Cluster member {} (node {}) is alive", url, answer.node_id);
+            }
+            member.state = MemberState::Alive;
+            member.node_id = Some(answer.node_id);
+            member.role = Some(answer.role);
+            member.applied_seq = answer.applied_seq;
+            member.staleness_ms = answer.staleness_ms;
+            member.in_read_pool = answer.in_read_pool;
+            member.last_seen_ms = Some(now_ms);
+            member.missed = 0;
+        }
+        for peer in &answer.members {
+            self.learn(peer);
+        }
+    }
+
+    fn record_miss(&self, url: &str) {
+        if let Some(member) = self.members.write().unwrap().get_mut(url) {
+            member.missed += 1;
+            let state = match member.missed {
+                missed if missed >= DEAD_AFTER_MISSES => MemberState::Dead,
+                _ => MemberState::Suspect,
+            };
+            if state == MemberState::Dead && member.state != MemberState::Dead {
+                warn!("Cluster member {} is dead after {} missed probes", url, member.missed);
+            }
+            member.state = state;
+        }
+    }
+
+    /// Members in each state
+    pub fn counts(&self) -> [(MemberState, usize); 3] {
+        let members = self.members.read().unwrap();
+        [MemberState::Alive, MemberState::Suspect, MemberState::Dead]
+            .map(|state| (state, members.values().filter(|member| member.state == state).count()))
+    }
+}
+
+fn normalize(url: &str) -> String {
+    url.trim().trim_end_matches('/').to_string()
+}
+
+/// This node's answer to a probe
+pub fn ping_answer(state: &ServerState, map: &ClusterMap) -> PingAnswer {
+    PingAnswer {
+        url: map.self_url().to_string(),
+        node_id: HybridClock::global().node_id(),
+        role: NodeRole::of(state),
+        applied_seq: replica::applied_seq(state),
+        staleness_ms: state.replica.as_ref().and_then(|replica| replica.staleness_ms()),
+        in_read_pool: state.replica.as_ref().map(|replica| replica.in_read_pool()),
+        members: map.members().into_iter().map(|member| member.url).collect(),
+    }
+}
+
+/// The newest journal position any alive leader reported, this node's own
+/// included, which members' lag is measured against
+pub fn leader_seq(state: &ServerState, members: &[Member]) -> Option<u64> {
+    members
+        .iter()
+        .filter(|member| member.state == MemberState::Alive && member.role == Some(NodeRole::Leader))
+        .filter_map(|member| member.applied_seq)
+        .chain(replica::applied_seq(state).filter(|_| NodeRole::of(state) == NodeRole::Leader))
+        .max()
+}
+
+/// Probes every known member each interval
+pub struct MembershipProber {
+    map: Arc<ClusterMap>,
+    interval: Duration,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl MembershipProber {
+    pub fn new(map: Arc<ClusterMap>, interval: Duration) -> Self {
+        MembershipProber { map, interval, heartbeats: None }
+    }
+
+    /// Report each round to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.interval + PROBE_TIMEOUT);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Probe until the task is dropped
+    pub async fn run(self) {
+        info!(
+            "Cluster membership as {} with {} seed peers",
+            self.map.self_url(),
+            self.map.members().len()
+        );
+        let client = Client::new();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            let probes = self.map.members().into_iter().map(|member| {
+                let client = client.clone();
+                async move {
+                    let answer = probe(&client, &member.url).await;
+                    (member.url, answer)
+                }
+            });
+            for (url, answer) in futures::future::join_all(probes).await {
+                match answer {
+                    Ok(answer) => self.map.record_answer(&url, answer, now_ms()),
+                    Err(_) => self.map.record_miss(&url),
+                }
+            }
+        }
+    }
+}
+
+async fn probe(client: &Client<HttpConnector>, url: &str) -> Result<PingAnswer, String> {
+    let request = Request::get(format!("{}/cluster/ping", url))
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    let response = tokio::time::timeout(PROBE_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    if response.status() != StatusCode::OK {
+        return Err(format!("unexpected status {}", response.status()));
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(url: &str, role: NodeRole, members: &[&str]) -> PingAnswer {
+        PingAnswer {
+            url: url.to_string(),
+            node_id: 2,
+            role,
+            applied_seq: Some(10),
+            staleness_ms: None,
+            in_read_pool: None,
+            members: members.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_members_learned_and_failed() {
+        let map = ClusterMap::new("http://a:8080/", ["http://b:8080".to_string()]);
+        assert_eq!(map.members()[0].state, MemberState::Suspect);
+
+        // b knows c and us; we learn c but never list ourselves
+        map.record_answer("http://b:8080", answer("http://b:8080", NodeRole::Leader, &["http://a:8080", "http://c:8080/"]), 1);
+        let urls: Vec<String> = map.members().into_iter().map(|m| m.url).collect();
+        assert_eq!(urls, ["http://b:8080", "http://c:8080"]);
+        assert_eq!(map.leaders(), ["http://b:8080"]);
+
+        for _ in 0..DEAD_AFTER_MISSES - 1 {
+            map.record_miss("http://b:8080");
+        }
+        assert_eq!(map.members()[0].state, MemberState::Suspect);
+        assert!(map.leaders().is_empty());
+        map.record_miss("http://b:8080");
+        assert_eq!(map.members()[0].state, MemberState::Dead);
+
+        // A dead member that answers again is alive
+        map.record_answer("http://b:8080", answer("http://b:8080", NodeRole::Leader, &[]), 2);
+        assert_eq!((map.members()[0].state, map.members()[0].missed), (MemberState::Alive, 0));
+        assert_eq!(map.counts()[0], (MemberState::Alive, 1));
+    }
+}
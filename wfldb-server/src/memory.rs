@@ -0,0 +1,191 @@
+//! Allocator selection, heap accounting, and memory-based load shedding
+//!
+//! The global allocator (system, jemalloc, or mimalloc depending on cargo
+//! features) is wrapped in a [`TrackingAllocator`] so the current and peak
+//! heap size are known regardless of which allocator is compiled in.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Global allocator wrapper that keeps a running count of live heap bytes
+pub struct TrackingAllocator<A> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        TrackingAllocator { inner }
+    }
+}
+
+fn record_alloc(size: usize) {
+    let now = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(now, Ordering::Relaxed);
+}
+
+fn record_dealloc(size: usize) {
+    ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Bytes currently allocated through the global allocator
+pub fn allocated_bytes() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+/// Highest value `allocated_bytes` has reached since startup
+pub fn peak_bytes() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// Name of the allocator compiled into this binary
+pub fn allocator_name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else {
+        "system"
+    }
+}
+
+/// Allocator-specific statistics as (metric suffix, help, bytes)
+#[cfg(feature = "jemalloc")]
+pub fn allocator_stats() -> Vec<(&'static str, &'static str, u64)> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc caches its statistics until the epoch is advanced
+    if epoch::advance().is_err() {
+        return Vec::new();
+    }
+
+    let mut out = Vec::new();
+    if let Ok(v) = stats::allocated::read() {
+        out.push(("allocated", "Bytes allocated by the application", v as u64));
+    }
+    if let Ok(v) = stats::active::read() {
+        out.push(("active", "Bytes in active pages", v as u64));
+    }
+    if let Ok(v) = stats::resident::read() {
+        out.push(("resident", "Bytes in physically resident pages", v as u64));
+    }
+    if let Ok(v) = stats::retained::read() {
+        out.push(("retained", "Bytes retained for future reuse", v as u64));
+    }
+    out
+}
+
+/// Allocator-specific statistics as (metric suffix, help, bytes)
+#[cfg(not(feature = "jemalloc"))]
+pub fn allocator_stats() -> Vec<(&'static str, &'static str, u64)> {
+    Vec::new()
+}
+
+/// Sheds requests once the heap crosses a configured high-water mark
+pub struct MemoryGuard {
+    limit_bytes: Option<usize>,
+    shed_count: AtomicU64,
+}
+
+impl MemoryGuard {
+    /// Create a guard rejecting requests above `limit_bytes` of live heap
+    pub fn new(limit_bytes: Option<usize>) -> Self {
+        MemoryGuard {
+            limit_bytes,
+            shed_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Configured heap limit, if any
+    pub fn limit_bytes(&self) -> Option<usize> {
+        self.limit_bytes
+    }
+
+    /// Number of requests rejected so far
+    pub fn shed_count(&self) -> u64 {
+        self.shed_count.load(Ordering::Relaxed)
+    }
+
+    /// Check the current heap size, counting the request as shed if over the limit
+    pub fn should_shed(&self) -> bool {
+        self.check(allocated_bytes())
+    }
+
+    /// Whether the heap is currently over the limit, without counting a shed request
+    pub fn over_limit(&self) -> bool {
+        matches!(self.limit_bytes, Some(limit) if allocated_bytes() >= limit)
+    }
+
+    fn check(&self, allocated: usize) -> bool {
+        match self.limit_bytes {
+            Some(limit) if allocated >= limit => {
+                self.shed_count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracking_allocator_counts_live_bytes() {
+        let buffer = vec![0u8; 4 * 1024 * 1024];
+        assert!(allocated_bytes() >= buffer.len());
+        assert!(peak_bytes() >= buffer.len());
+        drop(buffer);
+    }
+
+    #[test]
+    fn test_memory_guard_sheds_above_limit() {
+        let guard = MemoryGuard::new(Some(1024));
+        assert!(!guard.check(512));
+        assert!(guard.check(1024));
+        assert!(guard.check(4096));
+        assert_eq!(guard.shed_count(), 2);
+    }
+
+    #[test]
+    fn test_memory_guard_without_limit_never_sheds() {
+        let guard = MemoryGuard::new(None);
+        assert!(!guard.check(usize::MAX));
+        assert_eq!(guard.shed_count(), 0);
+    }
+}
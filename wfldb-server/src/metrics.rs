@@ -0,0 +1,559 @@
+//! Prometheus text exposition for the /metrics endpoint
+
+use std::fmt::{Display, Write};
+use crate::bandwidth::Direction;
+use crate::membership::{self, MemberState, NodeRole};
+use crate::memory;
+use crate::pools::{PoolStats, PriorityStats};
+use crate::simple_server_fixed::ServerState;
+use crate::tasks::TaskStatus;
+use wfldb_engine::HistogramSnapshot;
+
+/// Minimal writer for the Prometheus text format
+#[derive(Default)]
+pub struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a single unlabeled gauge
+    pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.header(name, help, "gauge");
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    /// Write a single unlabeled counter
+    pub fn counter(&mut self, name: &str, help: &str, value: impl Display) {
+        self.header(name, help, "counter");
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    /// Write a gauge family with one sample per label set
+    pub fn labeled_gauge<V: Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        samples: &[(&[(&str, &str)], V)],
+    ) {
+        self.labeled(name, help, "gauge", samples);
+    }
+
+    /// Write a counter family with one sample per label set
+    pub fn labeled_counter<V: Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        samples: &[(&[(&str, &str)], V)],
+    ) {
+        self.labeled(name, help, "counter", samples);
+    }
+
+    /// Write a histogram from cumulative bucket counts; the `+Inf` bucket is
+    /// the total count
+    pub fn histogram(&mut self, name: &str, help: &str, snapshot: &HistogramSnapshot) {
+        self.header(name, help, "histogram");
+        for (bound, count) in &snapshot.buckets {
+            let _ = writeln!(self.out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(self.out, "{}_bucket{{le=\"+Inf\"}} {}", name, snapshot.count);
+        let _ = writeln!(self.out, "{}_sum {}", name, snapshot.sum_seconds);
+        let _ = writeln!(self.out, "{}_count {}", name, snapshot.count);
+    }
+
+    /// Finish and return the encoded text
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    fn labeled<V: Display>(&mut self, name: &str, help: &str, kind: &str, samples: &[(&[(&str, &str)], V)]) {
+        self.header(name, help, kind);
+        for (labels, value) in samples {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render all server metrics
+pub fn render(state: &ServerState) -> String {
+    let mut w = MetricsWriter::new();
+
+    w.labeled_gauge(
+        "wfldb_allocator_info",
+        "Allocator compiled into the server",
+        &[(&[("allocator", memory::allocator_name())], 1)],
+    );
+    w.gauge(
+        "wfldb_heap_allocated_bytes",
+        "Bytes currently allocated through the global allocator",
+        memory::allocated_bytes(),
+    );
+    w.gauge(
+        "wfldb_heap_peak_bytes",
+        "High-water mark of allocated heap bytes",
+        memory::peak_bytes(),
+    );
+    if let Some(limit) = state.memory_guard.limit_bytes() {
+        w.gauge(
+            "wfldb_heap_limit_bytes",
+            "Heap size above which requests are shed",
+            limit,
+        );
+    }
+    w.counter(
+        "wfldb_memory_shed_requests_total",
+        "Requests rejected because the heap limit was exceeded",
+        state.memory_guard.shed_count(),
+    );
+    w.gauge("wfldb_connections_open", "Client connections open", state.connections.open());
+    w.counter("wfldb_connections_accepted_total", "Client connections accepted", state.connections.accepted());
+    w.counter(
+        "wfldb_connections_idle_closed_total",
+        "Connections closed after the idle timeout with no request in flight",
+        state.connections.idle_closed(),
+    );
+    w.counter(
+        "wfldb_request_body_timeouts_total",
+        "Request bodies failed for sending nothing within the body read timeout",
+        state.connections.body_timeouts(),
+    );
+    w.counter(
+        "wfldb_request_body_spills_total",
+        "Buffered request bodies that outgrew memory and spilled to disk",
+        state.spool.spills(),
+    );
+    w.counter(
+        "wfldb_request_body_spilled_bytes_total",
+        "Bytes of request bodies spilled to disk",
+        state.spool.spilled_bytes(),
+    );
+
+    if let Some(auth) = &state.auth {
+        let cache = auth.cache();
+        w.counter(
+            "wfldb_auth_packet_cache_hits_total",
+            "Key packet lookups served from the verified-packet cache",
+            cache.hits(),
+        );
+        w.counter(
+            "wfldb_auth_packet_cache_misses_total",
+            "Key packet lookups that required full verification",
+            cache.misses(),
+        );
+        w.gauge(
+            "wfldb_auth_packet_cache_entries",
+            "Verified key packets currently cached",
+            cache.len(),
+        );
+        w.gauge(
+            "wfldb_auth_nonce_cache_entries",
+            "Request nonces remembered for replay protection",
+            auth.nonces().len(),
+        );
+        w.counter(
+            "wfldb_concurrency_rejected_total",
+            "Requests refused because their key was at its concurrency cap",
+            state.concurrency.rejected(),
+        );
+    }
+
+    if let Some(auth) = &state.auth {
+        w.gauge(
+            "wfldb_auth_revocation_version",
+            "Local revocation list version",
+            auth.authority().revocation_version(),
+        );
+    }
+
+    if let Some(sync) = &state.revocation_sync {
+        w.gauge(
+            "wfldb_auth_revocation_upstream_version",
+            "Last revocation list version pulled from the upstream",
+            sync.upstream_version(),
+        );
+        if let Some(staleness) = sync.staleness_ms() {
+            w.gauge(
+                "wfldb_auth_revocation_staleness_seconds",
+                "Time since the revocation list was last pulled successfully",
+                staleness as f64 / 1000.0,
+            );
+        }
+        w.counter(
+            "wfldb_auth_revocation_sync_failures_total",
+            "Failed revocation list pulls",
+            sync.failures(),
+        );
+        w.counter(
+            "wfldb_auth_revocations_applied_total",
+            "Revocations learned from the upstream",
+            sync.applied(),
+        );
+    }
+
+    if let Some(replica) = &state.replica {
+        w.gauge(
+            "wfldb_replica_applied_seq",
+            "Last leader journal sequence applied on this replica",
+            replica.applied_seq(),
+        );
+        if let Some(staleness) = replica.staleness_ms() {
+            w.gauge(
+                "wfldb_replica_staleness_seconds",
+                "Time since this replica last had every leader change applied",
+                staleness as f64 / 1000.0,
+            );
+        }
+        if let Some(lag) = replica.lag_seq() {
+            w.gauge(
+                "wfldb_replica_lag_seq",
+                "Leader journal sequences not yet applied on this replica, as of the last pull",
+                lag,
+            );
+        }
+        w.gauge(
+            "wfldb_replica_in_read_pool",
+            "Whether this replica is within its lag limit and serving reads",
+            replica.in_read_pool() as u64,
+        );
+        w.counter(
+            "wfldb_replica_sync_failures_total",
+            "Failed pulls of the leader's journal",
+            replica.failures(),
+        );
+        w.counter(
+            "wfldb_replica_changes_applied_total",
+            "Change records applied from the leader",
+            replica.applied(),
+        );
+    }
+
+    if let Some(election) = &state.election {
+        w.gauge(
+            "wfldb_leader_election_leader",
+            "Whether this node holds the leader election lease",
+            election.is_leader() as u64,
+        );
+        w.counter(
+            "wfldb_leader_election_failures_total",
+            "Failed attempts to read, take, or renew the leader election lease",
+            election.failures(),
+        );
+    }
+
+    if let Some(cluster) = &state.cluster {
+        let counts = cluster.counts();
+        let labels: Vec<[(&str, &str); 1]> = counts.iter().map(|(state, _)| [("state", state.as_str())]).collect();
+        let samples: Vec<(&[(&str, &str)], usize)> =
+            labels.iter().zip(counts).map(|(labels, (_, count))| (&labels[..], count)).collect();
+        w.labeled_gauge("wfldb_cluster_members", "Known cluster peers by probe state", &samples);
+
+        // Each follower's lag as this node last heard it
+        let members = cluster.members();
+        let leader_seq = membership::leader_seq(state, &members);
+        let followers: Vec<_> = members
+            .iter()
+            .filter(|member| member.state == MemberState::Alive && member.role == Some(NodeRole::Replica))
+            .collect();
+        let labels: Vec<[(&str, &str); 1]> = followers.iter().map(|member| [("member", member.url.as_str())]).collect();
+        let lag: Vec<(&[(&str, &str)], u64)> = followers
+            .iter()
+            .zip(&labels)
+            .filter_map(|(member, labels)| {
+                let lag = leader_seq.zip(member.applied_seq).map(|(leader, seq)| leader.saturating_sub(seq))?;
+                Some((&labels[..], lag))
+            })
+            .collect();
+        w.labeled_gauge(
+            "wfldb_cluster_member_lag_seq",
+            "Journal sequences each follower is behind the newest leader position",
+            &lag,
+        );
+        let staleness: Vec<(&[(&str, &str)], f64)> = followers
+            .iter()
+            .zip(&labels)
+            .filter_map(|(member, labels)| Some((&labels[..], member.staleness_ms? as f64 / 1000.0)))
+            .collect();
+        w.labeled_gauge(
+            "wfldb_cluster_member_staleness_seconds",
+            "Time since each follower last matched its leader",
+            &staleness,
+        );
+        let in_pool: Vec<(&[(&str, &str)], u64)> = followers
+            .iter()
+            .zip(&labels)
+            .filter_map(|(member, labels)| Some((&labels[..], member.in_read_pool? as u64)))
+            .collect();
+        w.labeled_gauge(
+            "wfldb_cluster_member_in_read_pool",
+            "Whether each follower is serving reads",
+            &in_pool,
+        );
+    }
+
+    if let Some(anti_entropy) = &state.anti_entropy {
+        w.counter(
+            "wfldb_anti_entropy_rounds_total",
+            "Completed bucket comparisons with the leader",
+            anti_entropy.rounds(),
+        );
+        w.counter(
+            "wfldb_anti_entropy_keys_repaired_total",
+            "Keys written, deleted or dropped to match the leader",
+            anti_entropy.keys_repaired(),
+        );
+        w.counter(
+            "wfldb_anti_entropy_failures_total",
+            "Bucket comparisons with the leader that failed",
+            anti_entropy.failures(),
+        );
+    }
+
+    if let Some(archive) = &state.archive {
+        w.counter(
+            "wfldb_journal_archived_segments_total",
+            "Journal segments shipped to the archive",
+            archive.segments(),
+        );
+        w.counter(
+            "wfldb_journal_archived_bytes_total",
+            "Journal bytes shipped to the archive",
+            archive.bytes(),
+        );
+        w.counter(
+            "wfldb_journal_archive_failures_total",
+            "Failed journal archive passes",
+            archive.failures(),
+        );
+        if let Some(staleness) = archive.staleness_ms() {
+            w.gauge(
+                "wfldb_journal_archive_staleness_seconds",
+                "Time since the journal was last fully archived",
+                staleness as f64 / 1000.0,
+            );
+        }
+    }
+
+    let engine = state.storage.stats();
+    w.histogram(
+        "wfldb_engine_persist_duration_seconds",
+        "Time taken to sync the keyspace to disk",
+        &engine.persist_latency(),
+    );
+    w.counter(
+        "wfldb_engine_chunks_written_total",
+        "Chunks stored because no copy was present",
+        engine.chunks_written(),
+    );
+    w.counter(
+        "wfldb_engine_chunks_deduplicated_total",
+        "Chunk writes skipped because the bucket already held the chunk",
+        engine.chunks_deduplicated(),
+    );
+    w.counter(
+        "wfldb_engine_refcount_increments_total",
+        "Chunk references taken by committed objects",
+        engine.refcount_increments(),
+    );
+    w.counter(
+        "wfldb_engine_refcount_decrements_total",
+        "Chunk references dropped by deleted objects",
+        engine.refcount_decrements(),
+    );
+    w.counter(
+        "wfldb_engine_chunks_freed_total",
+        "Chunks removed when their last reference was dropped",
+        engine.chunks_freed(),
+    );
+    w.counter(
+        "wfldb_engine_partition_cache_hits_total",
+        "Bucket opens served by an already open partition",
+        engine.partition_cache_hits(),
+    );
+    w.counter(
+        "wfldb_engine_partition_cache_misses_total",
+        "Bucket opens that had to open the partition",
+        engine.partition_cache_misses(),
+    );
+    // A keyspace that can't be sampled leaves these out rather than failing the scrape
+    if let Ok(keyspace) = state.storage.keyspace_stats() {
+        w.gauge("wfldb_engine_disk_bytes", "Bytes on disk across every partition", keyspace.disk_bytes);
+        w.gauge(
+            "wfldb_engine_write_buffer_bytes",
+            "Bytes in memtables not yet flushed to disk segments",
+            keyspace.write_buffer_bytes,
+        );
+        w.gauge("wfldb_engine_journals", "Journal files not yet released by a memtable flush", keyspace.journals);
+        w.gauge("wfldb_engine_partitions", "Partitions open in the keyspace", keyspace.partitions);
+        w.gauge("wfldb_engine_segments", "Disk segments across bucket partitions", keyspace.segments);
+        w.gauge("wfldb_engine_blob_files", "Value-log blob files across bucket partitions", keyspace.blob_files);
+    }
+
+    if let Some(cache) = &state.response_cache {
+        w.counter(
+            "wfldb_response_cache_hits_total",
+            "GET bodies served from the response cache",
+            cache.hits(),
+        );
+        w.counter(
+            "wfldb_response_cache_misses_total",
+            "GET bodies read from storage with the response cache enabled",
+            cache.misses(),
+        );
+        w.gauge(
+            "wfldb_response_cache_entries",
+            "Object bodies currently cached",
+            cache.len(),
+        );
+        w.gauge(
+            "wfldb_response_cache_bytes",
+            "Bytes of object bodies currently cached",
+            cache.size_bytes(),
+        );
+    }
+
+    if state.bandwidth.is_limited() {
+        let bandwidth = &state.bandwidth;
+        let directions = [Direction::Ingress, Direction::Egress];
+        let labels: Vec<[(&str, &str); 1]> = directions.iter().map(|d| [("direction", d.as_str())]).collect();
+        let samples: Vec<(&[(&str, &str)], f64)> = labels
+            .iter()
+            .zip(directions)
+            .map(|(labels, direction)| (&labels[..], bandwidth.delayed(direction).as_secs_f64()))
+            .collect();
+        w.labeled_counter(
+            "wfldb_bandwidth_delay_seconds_total",
+            "Time request and response bodies were held back by bandwidth limits",
+            &samples,
+        );
+    }
+
+    if let Some(chaos) = &state.chaos {
+        w.gauge(
+            "wfldb_chaos_active",
+            "Whether any fault is being injected for a drill",
+            chaos.settings().is_active() as u64,
+        );
+        w.counter("wfldb_chaos_delayed_requests_total", "Requests delayed by injected storage latency", chaos.delayed());
+        w.counter("wfldb_chaos_failed_writes_total", "Writes failed by fault injection", chaos.failed_writes());
+        w.counter("wfldb_chaos_dropped_connections_total", "Responses aborted by fault injection", chaos.dropped());
+    }
+
+    if let Some(search) = &state.search {
+        w.counter("wfldb_search_indexed_total", "Objects indexed or removed from search indexes", search.indexed());
+        w.gauge("wfldb_search_backlog", "Written keys waiting to be indexed", search.backlog() as u64);
+    }
+
+    if let Some(quarantine) = &state.quarantine {
+        w.gauge("wfldb_quarantine_held", "Objects held for a content scan at the last pass", quarantine.held());
+        w.counter("wfldb_quarantine_approved_total", "Held objects the scanning hook approved", quarantine.approved());
+        w.counter("wfldb_quarantine_rejected_total", "Held objects the scanning hook rejected and deleted", quarantine.rejected());
+        w.counter("wfldb_quarantine_scan_failures_total", "Scans that reached no verdict", quarantine.failures());
+    }
+
+    let pools = state.pools.snapshot();
+    if !pools.is_empty() {
+        let labels: Vec<[(&str, &str); 1]> = pools.iter().map(|p| [("pool", p.name.as_str())]).collect();
+        let samples = |value: fn(&PoolStats) -> f64| -> Vec<(&[(&str, &str)], f64)> {
+            pools.iter().zip(&labels).map(|(pool, labels)| (&labels[..], value(pool))).collect()
+        };
+        w.labeled_gauge("wfldb_pool_permits", "Concurrent requests a pool admits", &samples(|p| p.permits as f64));
+        w.labeled_gauge("wfldb_pool_in_use", "Permits currently held", &samples(|p| p.in_use as f64));
+        w.labeled_gauge("wfldb_pool_waiting", "Requests queued for a permit", &samples(|p| p.waiting as f64));
+        w.labeled_counter("wfldb_pool_acquired_total", "Permits granted", &samples(|p| p.acquired as f64));
+        w.labeled_counter(
+            "wfldb_pool_wait_seconds_total",
+            "Time requests spent queued for a permit",
+            &samples(|p| p.wait_seconds),
+        );
+
+        let classes = state.pools.priority_snapshot();
+        let labels: Vec<[(&str, &str); 1]> = classes.iter().map(|c| [("priority", c.priority.as_str())]).collect();
+        let samples = |value: fn(&PriorityStats) -> f64| -> Vec<(&[(&str, &str)], f64)> {
+            classes.iter().zip(&labels).map(|(class, labels)| (&labels[..], value(class))).collect()
+        };
+        w.labeled_counter(
+            "wfldb_priority_acquired_total",
+            "Pool permits granted per priority class",
+            &samples(|c| c.acquired as f64),
+        );
+        w.labeled_counter(
+            "wfldb_priority_wait_seconds_total",
+            "Time requests of each priority class spent queued for a permit",
+            &samples(|c| c.wait_seconds),
+        );
+    }
+
+    let tasks = state.tasks.status();
+    if !tasks.is_empty() {
+        let labels: Vec<[(&str, &str); 1]> = tasks.iter().map(|t| [("task", t.name)]).collect();
+        let samples = |value: fn(&TaskStatus) -> f64| -> Vec<(&[(&str, &str)], f64)> {
+            tasks.iter().zip(&labels).map(|(task, labels)| (&labels[..], value(task))).collect()
+        };
+        w.labeled_counter("wfldb_task_runs_total", "Completed background task runs", &samples(|t| t.runs as f64));
+        w.labeled_counter("wfldb_task_failures_total", "Background task runs that failed", &samples(|t| t.failures as f64));
+        w.labeled_gauge("wfldb_task_paused", "Whether a background task is paused", &samples(|t| t.paused as u8 as f64));
+        w.labeled_gauge("wfldb_task_running", "Whether a background task is running", &samples(|t| t.running as u8 as f64));
+        w.labeled_gauge(
+            "wfldb_task_last_duration_seconds",
+            "Duration of a background task's last run",
+            &samples(|t| t.last_duration_ms.unwrap_or(0) as f64 / 1000.0),
+        );
+    }
+
+    for (suffix, help, value) in memory::allocator_stats() {
+        w.gauge(&format!("wfldb_allocator_{}_bytes", suffix), help, value);
+    }
+
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_writer_format() {
+        let mut w = MetricsWriter::new();
+        w.gauge("wfldb_test_gauge", "A gauge", 42);
+        w.counter("wfldb_test_total", "A counter", 7);
+        w.labeled_gauge("wfldb_test_info", "Info", &[(&[("name", "a\"b")], 1)]);
+        let text = w.finish();
+
+        assert!(text.contains("# TYPE wfldb_test_gauge gauge\nwfldb_test_gauge 42\n"));
+        assert!(text.contains("# TYPE wfldb_test_total counter\nwfldb_test_total 7\n"));
+        assert!(text.contains("wfldb_test_info{name=\"a\\\"b\"} 1\n"));
+    }
+
+    #[test]
+    fn test_histogram_format() {
+        let mut w = MetricsWriter::new();
+        let snapshot = HistogramSnapshot { buckets: vec![(0.001, 2), (0.01, 5)], sum_seconds: 0.25, count: 6 };
+        w.histogram("wfldb_test_seconds", "A histogram", &snapshot);
+        let text = w.finish();
+
+        assert!(text.contains("# TYPE wfldb_test_seconds histogram\n"));
+        assert!(text.contains("wfldb_test_seconds_bucket{le=\"0.001\"} 2\nwfldb_test_seconds_bucket{le=\"0.01\"} 5\n"));
+        assert!(text.contains("wfldb_test_seconds_bucket{le=\"+Inf\"} 6\n"));
+        assert!(text.contains("wfldb_test_seconds_sum 0.25\nwfldb_test_seconds_count 6\n"));
+    }
+}
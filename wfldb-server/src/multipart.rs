@@ -0,0 +1,213 @@
+//! Multipart upload API
+//!
+//! ```text
+//! POST   /v1/{bucket}/_uploads/{key}                                 start an upload
+//! PUT    /v1/{bucket}/_uploads/{key}?upload_id=ID&part=N  <bytes>    store part N
+//! GET    /v1/{bucket}/_uploads/{key}?upload_id=ID                    parts stored so far
+//! POST   /v1/{bucket}/_uploads/{key}?upload_id=ID                    commit the parts as the object
+//! DELETE /v1/{bucket}/_uploads/{key}?upload_id=ID                    abort
+//! ```
+//!
+//! Starting an upload answers 201 with its id. Parts are numbered from 1,
+//! may arrive in any order and in parallel, and are at most
+//! [`MAX_PART_SIZE`]; sending a part again replaces it, so a failed part
+//! can be retried blindly. The GET lists each stored part's number, size and
+//! BLAKE3 hash, for a client resuming after a crash to compare with its own.
+//! Completing commits the parts in part order and needs every number from 1
+//! to the highest without gaps. Uploads are kept in the
+//! engine, so they survive a restart, until completed, aborted, or purged by
+//! the `multipart_purge` task once older than the configured expiry. An
+//! upload that doesn't exist (or belongs to another key) answers 404
+//! `upload_not_found`. Every call but the GET needs write permission on the
+//! bucket, and on owner-only buckets only the object's owner may upload it.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::time::SystemTime;
+use wfldb_auth::{now_ms, AuthContext};
+use wfldb_core::{BucketId, Key, MultipartUploadState, WflDBError};
+use wfldb_engine::Storage;
+use wfldb_net::query_param;
+use crate::chunks;
+use crate::error::ApiError;
+use crate::router::{json_response, method_not_allowed};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Path segment between the bucket and the key of an upload route
+const UPLOADS_SEGMENT: &str = "_uploads";
+
+/// Largest part body
+pub const MAX_PART_SIZE: usize = 64 * 1024 * 1024;
+
+/// Parse "/v1/{bucket}/_uploads/{key}"; `None` if the path isn't an upload route
+pub fn parse_upload_path(path: &str) -> Option<std::result::Result<(BucketId, Key), String>> {
+    let (bucket, rest) = path.strip_prefix("/v1/")?.split_once('/')?;
+    let key = rest.strip_prefix(UPLOADS_SEGMENT)?.strip_prefix('/')?;
+    Some(
+        BucketId::new(bucket)
+            .map_err(|_| "Invalid bucket name".to_string())
+            .and_then(|bucket| Ok((bucket, Key::from_url(key).map_err(|_| "Invalid key".to_string())?))),
+    )
+}
+
+/// Handle an upload route; bucket permission was already checked
+pub async fn handle(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let method = req.method().clone();
+    let query = req.uri().query().unwrap_or_default().to_string();
+    let upload_id = query_param(&query, "upload_id");
+    let part = query_param(&query, "part");
+
+    if method != Method::GET {
+        if let Some(response) = chunks::owner_rejection(state, storage, bucket_id, key, auth_ctx) {
+            return response;
+        }
+    }
+    let limit = if method == Method::PUT { MAX_PART_SIZE } else { 0 };
+    let body = match chunks::read_body(req, &state.spool, auth_ctx, timings, limit).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let Some(upload_id) = upload_id else {
+        if method != Method::POST {
+            return ApiError::bad_request("Missing upload_id parameter").into_response();
+        }
+        let started = timings.time(Phase::Storage, || {
+            let _busy = state.diagnostics.enter_storage();
+            storage.create_multipart_upload(bucket_id, key)
+        });
+        return match started {
+            Ok(upload) => json_response(StatusCode::CREATED, upload_json(&upload)),
+            Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+        };
+    };
+
+    // An upload id only works with the key it was started for
+    let upload = match storage.multipart_upload(bucket_id, &upload_id) {
+        Ok(Some(upload)) if upload.key == *key => upload,
+        Ok(_) => return ApiError::from_storage(&WflDBError::UploadNotFound { upload_id }, now_ms()).into_response(),
+        Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+    };
+
+    let _busy = state.diagnostics.enter_storage();
+    let result = match method {
+        Method::GET => Ok(json_response(StatusCode::OK, upload_json(&upload))),
+        Method::PUT => {
+            let Some(part_number) = part.and_then(|part| part.parse::<u32>().ok()) else {
+                return ApiError::bad_request("Expected a numeric part parameter").into_response();
+            };
+            timings
+                .time(Phase::ChunkIo, || storage.upload_part(bucket_id, &upload_id, part_number, &body))
+                .map(|part| {
+                    json_response(StatusCode::OK, json!({
+                        "upload_id": upload_id,
+                        "part": part.part_number,
+                        "size": part.size,
+                        "hash": part.content_hash.to_hex(),
+                    }))
+                })
+        }
+        Method::POST => {
+            let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+            timings
+                .time(Phase::Storage, || storage.complete_multipart_upload(bucket_id, &upload_id, owner.as_deref()))
+                .map(|metadata| {
+                    if let Some(cache) = &state.response_cache {
+                        cache.invalidate(&storage.engine().namespaced(bucket_id), key.as_str());
+                    }
+                    json_response(StatusCode::CREATED, json!({
+                        "success": true,
+                        "bucket": bucket_id.as_str(),
+                        "key": key.as_str(),
+                        "size": metadata.size,
+                        "version": metadata.version.to_string(),
+                        "generation": metadata.generation,
+                        "chunked": true,
+                    }))
+                })
+        }
+        Method::DELETE => timings
+            .time(Phase::Storage, || storage.abort_multipart_upload(bucket_id, &upload_id))
+            .map(|()| json_response(StatusCode::OK, json!({"upload_id": upload_id, "aborted": true}))),
+        _ => Ok(method_not_allowed()),
+    };
+    match result {
+        Ok(response) => response,
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// An upload as the API and admin listing describe it
+pub fn upload_json(upload: &MultipartUploadState) -> Value {
+    let created_at_ms = upload
+        .created_at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    json!({
+        "upload_id": upload.upload_id,
+        "key": upload.key.as_str(),
+        "created_at_ms": created_at_ms,
+        "size": upload.total_size(),
+        "parts": upload.parts.iter().map(|part| json!({
+            "part": part.part_number,
+            "size": part.size,
+            "hash": part.content_hash.to_hex(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wfldb_auth::{generate_signing_key, Authenticator, KeyAuthority, Permissions, RequestParts, RequestSigner};
+    use crate::spool::Spool;
+
+    #[test]
+    fn test_parse_upload_path() {
+        let (bucket, key) = parse_upload_path("/v1/videos/_uploads/2024/clip.mp4").unwrap().unwrap();
+        assert_eq!((bucket.as_str(), key.as_str()), ("videos", "2024/clip.mp4"));
+        assert!(parse_upload_path("/v1/videos/_uploads/").unwrap().is_err());
+        assert!(parse_upload_path("/v1/videos/_upload/a").is_none());
+        assert!(parse_upload_path("/v1/videos/a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chunk_signed_part_is_refused() {
+        let (root, holder) = (generate_signing_key(), generate_signing_key());
+        let packet = KeyAuthority::issue(&root, &holder.verifying_key(), Permissions::read_write(), 0, 3_600).unwrap();
+        let authenticator = Authenticator::new(Arc::new(KeyAuthority::new(root.verifying_key())));
+        let signer = RequestSigner::new(holder, packet);
+        let (path, query) = ("/v1/videos/_uploads/clip.mp4", "upload_id=u1&part=1");
+        let request = RequestParts::new("PUT", path).with_query(query);
+        let (auth_headers, mut framer) = signer.sign_streaming(&request, 1_000);
+        let parts = RequestParts {
+            headers: auth_headers.iter().map(|(name, value)| (*name, value.as_str())).collect(),
+            ..request
+        };
+        let ctx = authenticator.authenticate(&parts, 1_000).unwrap();
+
+        // Even well-framed chunks would be stored with their framing as the part
+        let mut body = framer.sign_chunk(b"frame one");
+        body.extend(framer.finish());
+        let req = Request::builder()
+            .method(Method::PUT)
+            .uri(format!("{}?{}", path, query))
+            .body(Body::from(body))
+            .unwrap();
+        let refused = chunks::read_body(req, &Spool::default(), Some(&ctx), &mut RequestTimings::start(), MAX_PART_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(refused.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,647 @@
+//! Object and bucket endpoints under `/v1`
+//!
+//! `PUT`, `GET` and `DELETE /v1/{bucket}/{key}` write, read and remove an
+//! object; `GET /v1/{bucket}` lists a bucket's keys and `GET /v1` the
+//! buckets a key can see. Paths are parsed and permissions checked by the
+//! router before these run.
+//!
+//! A PUT with `x-wfldb-ttl-secs` makes the object expire that long after
+//! it's written, and `POST /v1/{bucket}/{key}/_touch` with
+//! `{"ttl_secs": N, "if_version": "<version>"}` moves the expiry of what the
+//! key holds now to N seconds from now without rewriting it (see
+//! [`wfldb_engine::expiry`]). Expired objects answer 404 until the purge
+//! task deletes them.
+//!
+//! Every write counts up the key's generation, which deletes don't reset;
+//! writes and GETs return it in `x-wfldb-generation`. A PUT or DELETE with
+//! `x-wfldb-if-generation: N` is fenced: it answers 412 unless the key
+//! exists at generation N, so a writer that lost its lead can't clobber or
+//! remove what its successor wrote.
+//!
+//! A GET of a chunked object honours a single `Range: bytes=...` with a
+//! 206 holding those bytes, read from only the chunks they overlap, and a
+//! range starting past the end with 416. With `If-Range` naming any ETag
+//! but the current one the whole object comes back with a 200 instead.
+//! Objects small enough to be stored inline always come back whole.
+//!
+//! PUTs and GETs may send a customer-supplied key to store the object
+//! encrypted with it (see [`crate::sse`]).
+
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Instant;
+use wfldb_auth::{now_ms, AuthContext, AuthError};
+use wfldb_core::{BucketId, ContentHash, Key, Precondition, Version, WflDBError};
+use wfldb_engine::Storage;
+use wfldb_net::query_param;
+use crate::{admin, auth, checksum, document, sse, transport};
+use crate::error::ApiError;
+use crate::response_cache::{self, CachedRead};
+use crate::router::{json_response, parse_object_path};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+use crate::spool::SpooledBody;
+use crate::transform::TransformError;
+
+/// Most keys returned by a single list request
+pub const MAX_LIST_LIMIT: usize = 1000;
+
+/// Largest object PUT body unless `--max-object-mb` says otherwise
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+/// Largest touch body, `{"ttl_secs": N, "if_version": "..."}`
+const MAX_TOUCH_BODY_BYTES: usize = 4 * 1024;
+
+/// Last path segment addressing an object's touch endpoint
+pub const TOUCH_SEGMENT: &str = "_touch";
+
+/// Parse a touch path, "/v1/{bucket}/{key}/_touch"; `None` if it isn't one
+pub fn parse_touch_path(path: &str) -> Option<Result<(BucketId, Key), String>> {
+    let object = path.strip_suffix(TOUCH_SEGMENT)?.strip_suffix('/')?;
+    Some(parse_object_path(object))
+}
+
+/// Expiry time for a TTL starting now
+pub fn expires_at(ttl_secs: u64) -> u64 {
+    now_ms().saturating_add(ttl_secs.saturating_mul(1000))
+}
+
+/// TTL a PUT asks for in `x-wfldb-ttl-secs`, if any
+fn requested_ttl(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(wfldb_auth::headers::TTL) else {
+        return Ok(None);
+    };
+    match value.to_str().ok().and_then(|v| v.trim().parse::<u64>().ok()) {
+        Some(ttl_secs) if ttl_secs > 0 => Ok(Some(ttl_secs)),
+        _ => Err(ApiError::bad_request(format!("{} must be a positive number of seconds", wfldb_auth::headers::TTL))),
+    }
+}
+
+/// Generation a PUT or DELETE is fenced on with `x-wfldb-if-generation`, if any
+fn requested_generation(headers: &HeaderMap) -> Result<Option<u64>, ApiError> {
+    let Some(value) = headers.get(wfldb_auth::headers::IF_GENERATION) else {
+        return Ok(None);
+    };
+    match value.to_str().ok().and_then(|v| v.trim().parse::<u64>().ok()) {
+        Some(generation) => Ok(Some(generation)),
+        None => Err(ApiError::bad_request(format!("{} must be a generation number", wfldb_auth::headers::IF_GENERATION))),
+    }
+}
+
+/// Handle `PUT /v1/{bucket}/{key}`
+pub async fn put(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+    let customer_key = match sse::CustomerKey::from_headers(&parts.headers) {
+        Ok(customer_key) => customer_key,
+        Err(e) => return e.into_response(),
+    };
+    // An encrypted body is sealed whole on the heap, so it must stay inline
+    let limit = match customer_key {
+        Some(_) => sse::max_plaintext(state.storage.value_threshold()),
+        None => state.max_object_size,
+    };
+    // Chunk-signed bodies are verified as they arrive instead of by hash
+    let body_result = match auth_ctx.and_then(|ctx| ctx.chunk_verifier()) {
+        Some(verifier) => auth::read_signed_chunks(body, verifier, &state.spool, limit).await,
+        None => state.spool.read(body, limit).await
+            .and_then(|body| auth::check_body(auth_ctx, &body, timings).map(|()| body))
+            .map_err(ApiError::into_response),
+    };
+    let body_bytes = match body_result {
+        Ok(body_bytes) => body_bytes,
+        Err(response) => return response,
+    };
+    if let Err(e) = timings.time(Phase::Storage, || state.schemas.check_put(storage, bucket_id, &body_bytes)) {
+        return e.into_response();
+    }
+    let ttl_secs = match requested_ttl(&parts.headers) {
+        Ok(ttl_secs) => ttl_secs,
+        Err(e) => return e.into_response(),
+    };
+    let fence = match requested_generation(&parts.headers) {
+        Ok(generation) => generation.map(Precondition::Generation),
+        Err(e) => return e.into_response(),
+    };
+    let checksums = match timings.time(Phase::Auth, || {
+        state.checksums.for_put(bucket_id.as_str(), &parts.headers, &body_bytes)
+    }) {
+        Ok(checksums) => checksums,
+        Err(e) => return e.into_response(),
+    };
+    let (body_bytes, customer_key_hash) = match customer_key {
+        Some(customer_key) => {
+            let sealed = timings.time(Phase::Storage, || customer_key.encrypt(&body_bytes));
+            (SpooledBody::from(bytes::Bytes::from(sealed)), Some(customer_key.hash()))
+        }
+        None => (body_bytes, None),
+    };
+
+    // Large bodies are chunked by the engine, so their write time is chunk I/O
+    let phase = if body_bytes.len() > state.storage.value_threshold() {
+        Phase::ChunkIo
+    } else {
+        Phase::Storage
+    };
+    // The TTL's expiry record commits together with the version it applies to
+    let expires_at_ms = ttl_secs.map(expires_at);
+    let put_result = timings.time(phase, || {
+        let _busy = state.diagnostics.enter_storage();
+        let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+        storage.put_object_if(bucket_id, key, &body_bytes, owner.as_deref(), checksums, fence, customer_key_hash, expires_at_ms)
+    });
+    match put_result {
+        Ok(metadata) => {
+            let mut body = json!({
+                "success": true,
+                "bucket": bucket_id.as_str(),
+                "key": key.as_str(),
+                "size": metadata.size,
+                "version": metadata.version.to_string(),
+                "generation": metadata.generation,
+                "chunked": metadata.is_chunked(),
+            });
+            if let Some(expires_at_ms) = expires_at_ms {
+                body["expires_at_ms"] = json!(expires_at_ms);
+            }
+            let mut response = checksum::with_headers(Response::builder(), &metadata.checksums)
+                .status(StatusCode::CREATED)
+                .header("content-type", "application/json")
+                .header(wfldb_auth::headers::GENERATION, metadata.generation)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            if let Some(customer_key_hash) = &metadata.customer_key_hash {
+                sse::add_headers(&mut response, customer_key_hash);
+            }
+            response
+        }
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// Handle `GET /v1/{bucket}/{key}`, applying any `?transform=` and
+/// `?fields=` the request asks for
+pub fn get(
+    req: &Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let query = req.uri().query().unwrap_or_default();
+    // Transformed and projected bodies aren't the object, so they carry no ETag
+    let plain = query_param(query, "transform").is_none() && query_param(query, "fields").is_none();
+    let if_none_match = req.headers()
+        .get(hyper::header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| plain);
+    // The key is checked before a precondition can answer 304, so a caller
+    // without it learns nothing of an encrypted object, not even its ETag
+    let customer_key = match sse::CustomerKey::from_headers(req.headers()) {
+        Ok(customer_key) => customer_key,
+        Err(e) => return e.into_response(),
+    };
+    let get_start = Instant::now();
+    let get_result = {
+        let _busy = state.diagnostics.enter_storage();
+        response_cache::read_object(storage, state.response_cache.as_ref(), bucket_id, key, if_none_match, plain)
+    };
+    let value_threshold = state.storage.value_threshold();
+    let phase = match &get_result {
+        Ok(CachedRead::Found(data, _)) if data.len() > value_threshold => Phase::ChunkIo,
+        Ok(CachedRead::Chunked(..)) => Phase::ChunkIo,
+        _ => Phase::Storage,
+    };
+    timings.record(phase, get_start.elapsed());
+
+    // Expired objects read as missing until they're purged
+    let version = match &get_result {
+        Ok(CachedRead::NotModified(metadata) | CachedRead::Found(_, metadata) | CachedRead::Chunked(_, metadata)) => {
+            Some(&metadata.version)
+        }
+        _ => None,
+    };
+    let expires_at_ms = match version.map(|version| storage.object_expiry(bucket_id, key, version)).transpose() {
+        Ok(expires_at_ms) => expires_at_ms.flatten(),
+        Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+    };
+    if expires_at_ms.is_some_and(|expires_at_ms| now_ms() >= expires_at_ms) {
+        return ApiError::not_found("Object not found").into_response();
+    }
+
+    let get_result = match get_result {
+        Ok(read) => {
+            let opened = timings.time(Phase::Storage, || sse::open(read, customer_key.as_ref(), storage, bucket_id, key));
+            match opened {
+                Ok(read) => Ok(read),
+                Err(e) => return e.into_response(),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let customer_key_hash = match &get_result {
+        Ok(CachedRead::Found(_, metadata)) => metadata.customer_key_hash.clone(),
+        _ => None,
+    };
+
+    let mut response = match get_result {
+        Ok(CachedRead::NotModified(metadata)) => Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("etag", response_cache::etag(&metadata.version))
+            .header(wfldb_auth::headers::VERSION, metadata.version.to_string())
+            .body(Body::empty())
+            .unwrap(),
+        Ok(CachedRead::Found(data, metadata)) => {
+            if let Some(hot_keys) = &state.hot_keys {
+                hot_keys.record(&storage.engine().namespaced(bucket_id), key.as_str());
+            }
+            let (data, transformed_type) = match query_param(query, "transform") {
+                Some(names) => {
+                    // With auth on, only keys granted a transform may request it
+                    let allowed = |name: &str| match (&state.auth, auth_ctx) {
+                        (None, _) => true,
+                        (Some(_), Some(ctx)) => ctx.permissions().can_transform(name),
+                        (Some(_), None) => false,
+                    };
+                    let result = timings.time(Phase::Transform, || {
+                        state.transforms.apply(&names, data.to_vec(), query, allowed)
+                    });
+                    match result {
+                        Ok((transformed, content_type)) => (transformed.into(), content_type),
+                        Err(e @ TransformError::NotPermitted(_)) => {
+                            return auth::error_response(&AuthError::PermissionDenied(e.to_string()));
+                        }
+                        Err(e) => return ApiError::bad_request(e.to_string()).into_response(),
+                    }
+                }
+                None => (data, None),
+            };
+            let (data, content_type) = match query_param(query, "fields") {
+                Some(fields) => match document::projection(&data, &fields) {
+                    Ok(projected) => (projected.into(), "application/json"),
+                    Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+                },
+                None => (data, transformed_type.unwrap_or("application/octet-stream")),
+            };
+            let accept_encoding = req.headers()
+                .get(hyper::header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok());
+            timings.time(Phase::ResponseWrite, || {
+                // Chunked objects are large binaries; compressing them isn't worth the CPU
+                let compressed = if metadata.is_chunked() {
+                    None
+                } else {
+                    state.compression.compress(bucket_id.as_str(), accept_encoding, &data)
+                };
+                // Checksums describe the stored bytes, not a transform or projection of them
+                let checksums = if plain { &metadata.checksums } else { &Default::default() };
+                let mut response = checksum::with_headers(Response::builder(), checksums)
+                    .status(StatusCode::OK)
+                    .header("content-type", content_type)
+                    .header(wfldb_auth::headers::VERSION, metadata.version.to_string())
+                    .header(wfldb_auth::headers::GENERATION, metadata.generation)
+                    .header("vary", "accept-encoding");
+                let etag = response_cache::etag(&metadata.version);
+                let data = match compressed {
+                    Some((body, encoding)) => {
+                        response = response.header("content-encoding", encoding.as_str());
+                        if plain {
+                            response = response.header("etag", format!("W/{}", etag));
+                        }
+                        bytes::Bytes::from(body)
+                    }
+                    None => {
+                        if plain {
+                            response = response.header("etag", etag);
+                        }
+                        data
+                    }
+                };
+                response
+                    .header("content-length", data.len().to_string())
+                    .body(Body::from(data))
+                    .unwrap()
+            })
+        }
+        Ok(CachedRead::Chunked(manifest, metadata)) => {
+            if let Some(hot_keys) = &state.hot_keys {
+                hot_keys.record(&storage.engine().namespaced(bucket_id), key.as_str());
+            }
+            let etag = response_cache::etag(&metadata.version);
+            // Ranges need every chunk's length to find where they start
+            let range = requested_range(req.headers(), &etag, metadata.size)
+                .filter(|_| plain && manifest.chunk_sizes.len() == manifest.chunks.len());
+            let response = Response::builder()
+                .header("content-type", "application/octet-stream")
+                .header("accept-ranges", "bytes")
+                .header(wfldb_auth::headers::VERSION, metadata.version.to_string())
+                .header(wfldb_auth::headers::GENERATION, metadata.generation)
+                .header("etag", etag);
+            let (engine, bucket_id, key) = (storage.engine().clone(), bucket_id.clone(), key.clone());
+            match range {
+                None => {
+                    let body = transport::chunk_body(engine, bucket_id, key, manifest);
+                    checksum::with_headers(response, &metadata.checksums)
+                        .status(StatusCode::OK)
+                        .header("content-length", metadata.size.to_string())
+                        .body(Body::wrap_stream(body))
+                        .unwrap()
+                }
+                Some(transport::RangeRequest::Bytes(first, last)) => {
+                    let body = transport::chunk_range_body(engine, bucket_id, key, manifest, (first, last));
+                    // The object's checksums still go out, for a client
+                    // assembling the ranges to check the whole against
+                    checksum::with_headers(response, &metadata.checksums)
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header("content-range", format!("bytes {}-{}/{}", first, last, metadata.size))
+                        .header("content-length", (last - first + 1).to_string())
+                        .body(Body::wrap_stream(body))
+                        .unwrap()
+                }
+                Some(transport::RangeRequest::Unsatisfiable) => response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header("content-range", format!("bytes */{}", metadata.size))
+                    .body(Body::empty())
+                    .unwrap(),
+            }
+        }
+        Ok(CachedRead::Missing) => ApiError::not_found("Object not found").into_response(),
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    };
+    if let Some(expires_at_ms) = expires_at_ms {
+        response.headers_mut().insert(wfldb_auth::headers::EXPIRES_AT, expires_at_ms.into());
+    }
+    if let Some(customer_key_hash) = &customer_key_hash {
+        sse::add_headers(&mut response, customer_key_hash);
+    }
+    response
+}
+
+/// The range a GET's `Range` header asks of an object with `etag`, unless
+/// its `If-Range` names another version and the whole object is wanted
+fn requested_range(headers: &HeaderMap, etag: &str, size: u64) -> Option<transport::RangeRequest> {
+    let range = headers.get(hyper::header::RANGE)?.to_str().ok()?;
+    if let Some(if_range) = headers.get(hyper::header::IF_RANGE) {
+        if if_range.to_str().ok()? != etag {
+            return None;
+        }
+    }
+    transport::parse_range(range, size)
+}
+
+#[derive(Deserialize)]
+struct TouchRequest {
+    ttl_secs: u64,
+    if_version: Option<Version>,
+}
+
+/// Handle `POST /v1/{bucket}/{key}/_touch`
+pub async fn touch(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let body_bytes = match state.spool.read(req.into_body(), MAX_TOUCH_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(auth_ctx, &body_bytes, timings) {
+        return e.into_response();
+    }
+    let request = match serde_json::from_slice::<TouchRequest>(&body_bytes) {
+        Ok(request) if request.ttl_secs > 0 => request,
+        Ok(_) => return ApiError::bad_request("ttl_secs must be positive").into_response(),
+        Err(e) => return ApiError::bad_request(format!("Invalid touch: {}", e)).into_response(),
+    };
+
+    let expires_at_ms = expires_at(request.ttl_secs);
+    let touch_result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        storage.touch(bucket_id, key, expires_at_ms, request.if_version.map(Precondition::Version))
+    });
+    match touch_result {
+        Ok(metadata) => json_response(StatusCode::OK, json!({
+            "success": true,
+            "bucket": bucket_id.as_str(),
+            "key": key.as_str(),
+            "version": metadata.version.to_string(),
+            "expires_at_ms": expires_at_ms,
+        })),
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// Handle `DELETE /v1/{bucket}/{key}`
+pub fn delete(
+    req: &Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let fence = match requested_generation(req.headers()) {
+        Ok(generation) => generation.map(Precondition::Generation),
+        Err(e) => return e.into_response(),
+    };
+    let delete_result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        match fence {
+            Some(fence) => storage.delete_object_if(bucket_id, key, fence),
+            None => storage.delete_object(bucket_id, key),
+        }
+    });
+    match delete_result {
+        Ok(()) => json_response(StatusCode::OK, json!({
+            "success": true,
+            "bucket": bucket_id.as_str(),
+            "key": key.as_str(),
+            "deleted": true,
+        })),
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// Handle `GET /v1/{bucket}?prefix=&limit=&start_after=&delimiter=&metadata=`
+///
+/// With a delimiter, keys holding it after the prefix are rolled up into
+/// their common prefix (through the delimiter), listed once in
+/// `common_prefixes` and counted once toward the limit. `metadata=true` adds
+/// each key's size, version and generation under `objects`.
+pub fn list(
+    req: &Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let query = req.uri().query().unwrap_or("");
+    let prefix = query_param(query, "prefix").unwrap_or_default();
+    let limit = query_param(query, "limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(MAX_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    let start_after = query_param(query, "start_after");
+    let delimiter = query_param(query, "delimiter").filter(|delimiter| !delimiter.is_empty());
+    let with_metadata = query_param(query, "metadata").as_deref() == Some("true");
+    let list_result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        let mut listing = list_entries(storage, bucket_id, &prefix, delimiter.as_deref(), start_after, limit)?;
+        // Keys waiting for a scan are only listed to admins
+        if state.quarantine.is_some() && !auth_ctx.is_some_and(|ctx| ctx.permissions().can_admin()) {
+            let held = storage.quarantined_keys(bucket_id, &prefix)?;
+            listing.keys.retain(|key| !held.iter().any(|held| held == key.as_str()));
+        }
+        let mut objects = Vec::new();
+        if with_metadata {
+            for key in &listing.keys {
+                // Deleted since it was listed
+                let Some(metadata) = storage.get_metadata(bucket_id, key)? else {
+                    continue;
+                };
+                let created_at_ms = metadata.created_at.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+                objects.push(json!({
+                    "key": key.as_str(),
+                    "size": metadata.size,
+                    "version": metadata.version.to_string(),
+                    "generation": metadata.generation,
+                    "content_hash": metadata.content_hash.as_ref().map(ContentHash::to_hex),
+                    "created_at_ms": created_at_ms.as_millis() as u64,
+                    "chunked": metadata.is_chunked(),
+                }));
+            }
+        }
+        Ok::<_, WflDBError>((listing, objects))
+    });
+    match list_result {
+        Ok((listing, objects)) => {
+            let mut body = json!({
+                "bucket": bucket_id.as_str(),
+                "prefix": prefix,
+                "keys": listing.keys.iter().map(|k| k.as_str()).collect::<Vec<_>>(),
+                "next_start_after": listing.next,
+            });
+            if delimiter.is_some() {
+                body["common_prefixes"] = json!(listing.common_prefixes);
+            }
+            if with_metadata {
+                body["objects"] = json!(objects);
+            }
+            let mut response = json_response(StatusCode::OK, body);
+            // Journal cursor a change feed bootstrapped from this page follows
+            if let Some(position) = listing.position {
+                response.headers_mut().insert(admin::JOURNAL_NEXT_HEADER, position.into());
+            }
+            response
+        }
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+/// One page of a listing
+#[derive(Debug, Default)]
+struct Listing {
+    keys: Vec<Key>,
+    common_prefixes: Vec<String>,
+    /// Cursor to pass back as `start_after`, when the page was full
+    next: Option<String>,
+    /// Journal position the first scan covered
+    position: Option<u64>,
+}
+
+/// Sorts after every key under a common prefix, so a cursor ending in it
+/// resumes past them
+const PAST_PREFIX: char = char::MAX;
+
+/// List up to `limit` keys and common prefixes after `start_after`
+fn list_entries(
+    storage: &Storage,
+    bucket_id: &BucketId,
+    prefix: &str,
+    delimiter: Option<&str>,
+    start_after: Option<String>,
+    limit: usize,
+) -> Result<Listing, WflDBError> {
+    let Some(delimiter) = delimiter else {
+        let (keys, position) = storage.list_objects_at(bucket_id, prefix, start_after.as_deref(), Some(limit))?;
+        // A full page may have more behind it; pass the last key back as start_after
+        let next = keys.last().filter(|_| keys.len() == limit).map(|k| k.as_str().to_string());
+        return Ok(Listing { keys, next, position, ..Default::default() });
+    };
+    let mut listing = Listing::default();
+    let mut after = start_after;
+    let mut first = true;
+    'scan: while listing.keys.len() + listing.common_prefixes.len() < limit {
+        let wanted = limit - listing.keys.len() - listing.common_prefixes.len();
+        let (keys, position) = storage.list_objects_at(bucket_id, prefix, after.as_deref(), Some(wanted))?;
+        if std::mem::take(&mut first) {
+            listing.position = position;
+        }
+        let exhausted = keys.len() < wanted;
+        for key in keys {
+            let text = key.as_str();
+            match text.strip_prefix(prefix).and_then(|rest| rest.find(delimiter)) {
+                Some(at) => {
+                    // Skip the rest of the common prefix with a fresh scan
+                    let common = text[..prefix.len() + at + delimiter.len()].to_string();
+                    after = Some(format!("{}{}", common, PAST_PREFIX));
+                    listing.common_prefixes.push(common);
+                    continue 'scan;
+                }
+                None => {
+                    after = Some(text.to_string());
+                    listing.keys.push(key);
+                }
+            }
+        }
+        if exhausted {
+            return Ok(listing);
+        }
+    }
+    listing.next = after;
+    Ok(listing)
+}
+
+/// Handle `GET /v1`: the buckets the caller holds some permission on
+pub fn list_buckets(
+    state: &ServerState,
+    storage: &Storage,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let summaries = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        storage.engine().bucket_summaries()
+    });
+    match summaries {
+        Ok(summaries) => {
+            let buckets: Vec<_> = summaries
+                .iter()
+                .filter(|s| auth_ctx.is_none_or(|ctx| ctx.permissions().can_access(&s.id)))
+                .map(|s| json!({
+                    "name": s.id.as_str(),
+                    "created_at_ms": s.created_at_ms,
+                    "approximate_entries": s.approximate_entries,
+                    "disk_bytes": s.disk_bytes,
+                }))
+                .collect();
+            json_response(StatusCode::OK, json!({"buckets": buckets}))
+        }
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
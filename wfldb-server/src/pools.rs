@@ -0,0 +1,379 @@
+//! Per-bucket and per-tenant request pools
+//!
+//! Storage calls run on the async workers, so a burst of large-object
+//! requests against one bucket can tie up every worker and stall small reads
+//! everywhere else. Each bucket, or each tenant for tenant-scoped keys, gets
+//! its own permits; a request holds a permit from its pool while it does
+//! storage work, which bounds how much of the runtime one pool can take.
+//!
+//! Requests waiting on a full pool queue by priority class. A freed permit
+//! goes to the classes by weighted round robin, so interactive reads jump
+//! ahead of bulk backfills without starving them.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use wfldb_core::{BucketId, Priority, TenantId};
+
+/// Permits per pool unless overridden
+pub const DEFAULT_POOL_PERMITS: usize = 32;
+
+/// Pools created on demand before new names share the overflow pool, so
+/// requests for arbitrary bucket names can't grow the table without bound
+const MAX_POOLS: usize = 1024;
+
+/// Pool shared by names beyond `MAX_POOLS`
+pub const OVERFLOW_POOL: &str = "overflow";
+
+/// Share of freed permits each class gets while all are queued, in
+/// `Priority::ALL` order: eight interactive and four normal grants per bulk one
+const CLASS_WEIGHTS: [i64; 3] = [8, 4, 1];
+
+fn class_index(priority: Priority) -> usize {
+    match priority {
+        Priority::Interactive => 0,
+        Priority::Normal => 1,
+        Priority::Bulk => 2,
+    }
+}
+
+/// Pool sizes
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub default_permits: usize,
+    /// Permits for specific pools, by pool name (`bucket/{name}` or `tenant/{name}`)
+    pub overrides: HashMap<String, usize>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            default_permits: DEFAULT_POOL_PERMITS,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Parse a `{pool}={permits}` override, e.g. `tenant/acme=8`
+    pub fn parse_override(spec: &str) -> Result<(String, usize), String> {
+        let (name, permits) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected POOL=PERMITS, got '{}'", spec))?;
+        if !(name.starts_with("bucket/") || name.starts_with("tenant/") || name == OVERFLOW_POOL) {
+            return Err(format!("pool '{}' must be bucket/NAME, tenant/NAME, or {}", name, OVERFLOW_POOL));
+        }
+        let permits: usize = permits
+            .parse()
+            .map_err(|_| format!("invalid permit count in '{}'", spec))?;
+        if permits == 0 {
+            return Err(format!("pool '{}' needs at least one permit", name));
+        }
+        Ok((name.to_string(), permits))
+    }
+
+    fn permits_for(&self, name: &str) -> usize {
+        self.overrides.get(name).copied().unwrap_or(self.default_permits).max(1)
+    }
+}
+
+struct Pool {
+    permits: usize,
+    queue: Mutex<PoolQueue>,
+    acquired: AtomicU64,
+    wait_ns: AtomicU64,
+}
+
+struct PoolQueue {
+    /// Free permits; only nonzero while no one is queued
+    available: usize,
+    waiters: [VecDeque<oneshot::Sender<PoolPermit>>; 3],
+    /// Smooth weighted round robin state per class
+    credit: [i64; 3],
+}
+
+impl PoolQueue {
+    /// Pick who gets the next freed permit, dropping waiters that gave up
+    fn next_waiter(&mut self) -> Option<oneshot::Sender<PoolPermit>> {
+        let mut total = 0;
+        for (class, waiters) in self.waiters.iter_mut().enumerate() {
+            waiters.retain(|tx| !tx.is_closed());
+            if waiters.is_empty() {
+                self.credit[class] = 0;
+            } else {
+                self.credit[class] += CLASS_WEIGHTS[class];
+                total += CLASS_WEIGHTS[class];
+            }
+        }
+        // Ties go to the higher class
+        let class = (0..3)
+            .filter(|&class| !self.waiters[class].is_empty())
+            .max_by_key(|&class| (self.credit[class], std::cmp::Reverse(class)))?;
+        self.credit[class] -= total;
+        self.waiters[class].pop_front()
+    }
+
+    fn waiting(&self) -> u64 {
+        self.waiters
+            .iter()
+            .flatten()
+            .filter(|tx| !tx.is_closed())
+            .count() as u64
+    }
+}
+
+impl Pool {
+    async fn acquire(self: &Arc<Self>, priority: Priority) -> PoolPermit {
+        let granted = {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.available > 0 {
+                queue.available -= 1;
+                return PoolPermit { pool: Some(self.clone()) };
+            }
+            let (tx, rx) = oneshot::channel();
+            queue.waiters[class_index(priority)].push_back(tx);
+            rx
+        };
+        granted.await.expect("pools hand a permit to every waiter they drop")
+    }
+
+    /// Hand a freed permit to the next waiter, or put it back
+    fn release(self: &Arc<Self>) {
+        let mut permit = PoolPermit { pool: Some(self.clone()) };
+        loop {
+            let waiter = {
+                let mut queue = self.queue.lock().unwrap();
+                match queue.next_waiter() {
+                    Some(waiter) => waiter,
+                    None => {
+                        queue.available += 1;
+                        permit.pool = None;
+                        return;
+                    }
+                }
+            };
+            // Sent outside the lock: a permit dropped with its channel releases again
+            match waiter.send(permit) {
+                Ok(()) => return,
+                Err(returned) => permit = returned,
+            }
+        }
+    }
+}
+
+/// A permit from a request pool, given back when dropped
+pub struct PoolPermit {
+    pool: Option<Arc<Pool>>,
+}
+
+impl Drop for PoolPermit {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.release();
+        }
+    }
+}
+
+/// Point-in-time view of a pool, for metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    pub name: String,
+    pub permits: usize,
+    pub in_use: usize,
+    pub waiting: u64,
+    pub acquired: u64,
+    pub wait_seconds: f64,
+}
+
+/// Permits granted to one priority class across all pools, for metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriorityStats {
+    pub priority: Priority,
+    pub acquired: u64,
+    pub wait_seconds: f64,
+}
+
+#[derive(Default)]
+struct ClassCounters {
+    acquired: AtomicU64,
+    wait_ns: AtomicU64,
+}
+
+/// Request pools keyed by bucket or tenant
+pub struct RequestPools {
+    config: PoolConfig,
+    pools: Mutex<BTreeMap<String, Arc<Pool>>>,
+    classes: [ClassCounters; 3],
+}
+
+impl RequestPools {
+    pub fn new(config: PoolConfig) -> Self {
+        RequestPools {
+            config,
+            pools: Mutex::new(BTreeMap::new()),
+            classes: Default::default(),
+        }
+    }
+
+    /// Pool a request belongs to: its tenant's if it has one, else its bucket's
+    pub fn pool_name(tenant: Option<&TenantId>, bucket: &BucketId) -> String {
+        match tenant {
+            Some(tenant) => format!("tenant/{}", tenant.as_str()),
+            None => format!("bucket/{}", bucket.as_str()),
+        }
+    }
+
+    /// Wait for a permit from the named pool, returning it and the time spent queued
+    pub async fn acquire(&self, name: &str, priority: Priority) -> (PoolPermit, Duration) {
+        let pool = self.pool(name);
+        let started = Instant::now();
+        let permit = pool.acquire(priority).await;
+        let waited = started.elapsed();
+        let wait_ns = waited.as_nanos() as u64;
+        pool.acquired.fetch_add(1, Ordering::Relaxed);
+        pool.wait_ns.fetch_add(wait_ns, Ordering::Relaxed);
+        let class = &self.classes[class_index(priority)];
+        class.acquired.fetch_add(1, Ordering::Relaxed);
+        class.wait_ns.fetch_add(wait_ns, Ordering::Relaxed);
+        (permit, waited)
+    }
+
+    /// Grants per priority class, highest class first
+    pub fn priority_snapshot(&self) -> Vec<PriorityStats> {
+        Priority::ALL
+            .iter()
+            .map(|&priority| {
+                let class = &self.classes[class_index(priority)];
+                PriorityStats {
+                    priority,
+                    acquired: class.acquired.load(Ordering::Relaxed),
+                    wait_seconds: class.wait_ns.load(Ordering::Relaxed) as f64 / 1e9,
+                }
+            })
+            .collect()
+    }
+
+    /// Every pool created so far, by name
+    pub fn snapshot(&self) -> Vec<PoolStats> {
+        self.pools
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, pool)| {
+                let queue = pool.queue.lock().unwrap();
+                PoolStats {
+                    name: name.clone(),
+                    permits: pool.permits,
+                    in_use: pool.permits - queue.available,
+                    waiting: queue.waiting(),
+                    acquired: pool.acquired.load(Ordering::Relaxed),
+                    wait_seconds: pool.wait_ns.load(Ordering::Relaxed) as f64 / 1e9,
+                }
+            })
+            .collect()
+    }
+
+    fn pool(&self, name: &str) -> Arc<Pool> {
+        let mut pools = self.pools.lock().unwrap();
+        if let Some(pool) = pools.get(name) {
+            return pool.clone();
+        }
+        // Configured pools always get their own permits
+        let name = if pools.len() >= MAX_POOLS && !self.config.overrides.contains_key(name) {
+            OVERFLOW_POOL
+        } else {
+            name
+        };
+        let permits = self.config.permits_for(name);
+        pools
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Pool {
+                    permits,
+                    queue: Mutex::new(PoolQueue {
+                        available: permits,
+                        waiters: Default::default(),
+                        credit: [0; 3],
+                    }),
+                    acquired: AtomicU64::new(0),
+                    wait_ns: AtomicU64::new(0),
+                })
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pools_are_independent() {
+        let mut config = PoolConfig::default();
+        config.overrides.insert("bucket/videos".to_string(), 1);
+        let pools = Arc::new(RequestPools::new(config));
+
+        // A saturated pool queues its own requests...
+        let (held, _) = pools.acquire("bucket/videos", Priority::Normal).await;
+        let queued = tokio::spawn({
+            let pools = pools.clone();
+            async move { pools.acquire("bucket/videos", Priority::Normal).await.1 }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!queued.is_finished());
+
+        // ...without holding up anyone else
+        let (_thumbs, waited) = pools.acquire("bucket/thumbs", Priority::Normal).await;
+        assert!(waited < Duration::from_millis(20));
+
+        drop(held);
+        assert!(queued.await.unwrap() >= Duration::from_millis(20));
+        let videos = pools.snapshot().into_iter().find(|p| p.name == "bucket/videos").unwrap();
+        assert_eq!((videos.permits, videos.acquired, videos.waiting), (1, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_interactive_requests_go_first_without_starving_bulk() {
+        let mut config = PoolConfig::default();
+        config.overrides.insert("bucket/backfill".to_string(), 1);
+        let pools = Arc::new(RequestPools::new(config));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let (held, _) = pools.acquire("bucket/backfill", Priority::Bulk).await;
+        let queue = |priority: Priority| {
+            let pools = pools.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _permit = pools.acquire("bucket/backfill", priority).await;
+                order.lock().unwrap().push(priority);
+            })
+        };
+        // Bulk work queued first, then a burst of interactive reads
+        let mut tasks = vec![queue(Priority::Bulk)];
+        tasks.extend((0..12).map(|_| queue(Priority::Interactive)));
+        while pools.snapshot()[0].waiting < 13 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let order = order.lock().unwrap();
+        let bulk_at = order.iter().position(|p| *p == Priority::Bulk).unwrap();
+        // One bulk grant in every nine while both classes are queued
+        assert_eq!(order[0], Priority::Interactive);
+        assert_eq!(bulk_at, 4);
+        assert_eq!(pools.priority_snapshot()[2].acquired, 2);
+    }
+
+    #[test]
+    fn test_parse_override() {
+        assert_eq!(PoolConfig::parse_override("tenant/acme=8").unwrap(), ("tenant/acme".to_string(), 8));
+        assert!(PoolConfig::parse_override("acme=8").is_err());
+        assert!(PoolConfig::parse_override("bucket/photos=0").is_err());
+        assert!(PoolConfig::parse_override("bucket/photos").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Upload quarantine and the content scanning hook
+//!
+//! With `--quarantine-hook`, every object written through `/v1` is held
+//! (see [`wfldb_engine::quarantine`]) until the hook approves it: until
+//! then only admin keys can read it, other readers get a retryable 409
+//! `quarantined`, and listings leave it out. The hook is either an HTTP(S)
+//! URL or a shell command:
+//!
+//! - a URL is sent `POST` with the object as the body and its bucket, key
+//!   and version in `x-wfldb-bucket`, `x-wfldb-key` and `x-wfldb-version`;
+//!   a 2xx answer approves it, a 4xx rejects it with the body as the reason
+//! - a command runs under `sh -c` with the object on stdin and
+//!   `WFLDB_BUCKET`, `WFLDB_KEY`, `WFLDB_VERSION` and `WFLDB_SIZE` set; exit
+//!   status 0 approves it, 1 rejects it with stdout as the reason
+//!
+//! Rejected objects are deleted. Anything else (a 5xx, another exit status,
+//! a timeout) leaves the object held for the next pass. Buckets are named
+//! as in journal records, with the tenant prefix.
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Uri};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+use wfldb_auth::now_ms;
+use wfldb_core::{BucketId, Key, ObjectMetadata, WflDBError};
+use wfldb_engine::{list_quarantine, refresh_hold, reject_quarantined, release_quarantined, record_scan_failure};
+use wfldb_engine::{QuarantineEntry, Storage, StorageEngine};
+use crate::health::TaskHeartbeats;
+use crate::router::{parse_object_path, Route};
+use crate::{chunks, multipart, transport};
+
+const HEARTBEAT_TASK: &str = "quarantine_scan";
+
+/// Holds older than this that no write settled are settled by the scanner
+const ABANDONED_HOLD_MS: u64 = 5 * 60 * 1000;
+
+/// Longest rejection reason kept from a hook's answer
+const MAX_REASON_BYTES: usize = 512;
+
+/// Where objects are sent for a verdict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanHook {
+    Http(Uri),
+    Command(String),
+}
+
+impl ScanHook {
+    /// A URL when it starts with `http://` or `https://`, else a command
+    pub fn parse(spec: &str) -> Result<ScanHook, String> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return spec.parse().map(ScanHook::Http).map_err(|e| format!("invalid quarantine hook URL: {}", e));
+        }
+        match spec.trim() {
+            "" => Err("quarantine hook command is empty".to_string()),
+            command => Ok(ScanHook::Command(command.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ScanHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanHook::Http(uri) => write!(f, "{}", uri),
+            ScanHook::Command(command) => write!(f, "`{}`", command),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuarantineConfig {
+    pub hook: ScanHook,
+    pub interval: Duration,
+    /// Longest a single scan may take before it counts as failed
+    pub timeout: Duration,
+}
+
+/// What a hook said about an object
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Verdict {
+    Approve,
+    Reject(String),
+}
+
+#[derive(Debug, Default)]
+pub struct QuarantineStats {
+    held: AtomicU64,
+    approved: AtomicU64,
+    rejected: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl QuarantineStats {
+    /// Keys held at the end of the last pass
+    pub fn held(&self) -> u64 {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    pub fn approved(&self) -> u64 {
+        self.approved.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Scans that reached no verdict
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+/// Holds taken for one request's writes
+pub struct Holds {
+    storage: Storage,
+    bucket_id: BucketId,
+    holds: Vec<(Key, String)>,
+}
+
+impl Holds {
+    /// Hold `keys` ahead of writing them
+    pub fn take<'k>(storage: &Storage, bucket_id: &BucketId, keys: impl IntoIterator<Item = &'k Key>) -> Result<Holds, WflDBError> {
+        let now = now_ms();
+        let holds = keys
+            .into_iter()
+            .map(|key| Ok((key.clone(), storage.hold_for_scan(bucket_id, key, now)?)))
+            .collect::<Result<_, WflDBError>>()?;
+        Ok(Holds { storage: storage.clone(), bucket_id: bucket_id.clone(), holds })
+    }
+
+    /// Queue whatever the writes left for scanning
+    pub fn settle(self) {
+        for (key, id) in &self.holds {
+            if let Err(e) = self.storage.settle_hold(&self.bucket_id, key, id) {
+                // The scanner settles holds left behind once they're old enough
+                warn!("Failed to settle quarantine hold on {}/{}: {}", self.bucket_id.as_str(), key.as_str(), e);
+            }
+        }
+    }
+}
+
+/// Object a request replaces the content of, which has to be held first
+pub fn written_object(route: Route, method: &Method, path: &str, query: Option<&str>) -> Option<(BucketId, Key)> {
+    match route {
+        Route::PutObject | Route::PatchDocument => parse_object_path(path).ok(),
+        Route::Chunks => match chunks::parse_chunk_path(path)?.ok()? {
+            chunks::ChunkRoute::Manifest(bucket_id, key)
+            | chunks::ChunkRoute::Compose(bucket_id, key)
+            | chunks::ChunkRoute::Range(bucket_id, key) => Some((bucket_id, key)),
+            _ => None,
+        },
+        // Only completing an upload writes the object
+        Route::Multipart if method == Method::POST && query.is_some_and(|q| q.contains("upload_id=")) => {
+            multipart::parse_upload_path(path)?.ok()
+        }
+        _ => None,
+    }
+}
+
+/// Re-checks held objects and sends them to the scanning hook
+pub struct QuarantineScanner {
+    engine: StorageEngine,
+    config: QuarantineConfig,
+    stats: Arc<QuarantineStats>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl QuarantineScanner {
+    pub fn new(engine: StorageEngine, config: QuarantineConfig) -> Self {
+        QuarantineScanner {
+            engine,
+            config,
+            stats: Arc::new(QuarantineStats::default()),
+            heartbeats: None,
+        }
+    }
+
+    /// Report each pass to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.config.interval);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<QuarantineStats> {
+        self.stats.clone()
+    }
+
+    /// Scan until the task is dropped
+    pub async fn run(self) {
+        info!("Scanning quarantined uploads with {} every {:?}", self.config.hook, self.config.interval);
+        let client = hook_client();
+        let mut ticker = tokio::time::interval(self.config.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            if let Err(e) = self.scan_once(&client).await {
+                warn!("Quarantine scan pass failed: {}", e);
+            }
+        }
+    }
+
+    /// Scan every settled hold, oldest first
+    async fn scan_once(&self, client: &HttpsClient) -> Result<(), String> {
+        let engine = self.engine.clone();
+        let entries = blocking(move || list_quarantine(&engine)).await?;
+        self.stats.held.store(entries.len() as u64, Ordering::Relaxed);
+        for entry in entries {
+            if entry.version.is_none() {
+                if now_ms().saturating_sub(entry.held_at_ms) > ABANDONED_HOLD_MS {
+                    let engine = self.engine.clone();
+                    blocking(move || refresh_hold(&engine, &entry)).await?;
+                }
+                continue;
+            }
+            self.scan_entry(client, entry).await?;
+        }
+        Ok(())
+    }
+
+    async fn scan_entry(&self, client: &HttpsClient, entry: QuarantineEntry) -> Result<(), String> {
+        let (engine, lookup) = (self.engine.clone(), entry.clone());
+        let metadata = blocking(move || {
+            let (scoped, bucket_id) = engine.resolve_namespaced(&lookup.bucket)?;
+            Storage::new(scoped).get_metadata(&bucket_id, &Key::new(&lookup.key)?)
+        })
+        .await?;
+        // Gone, or written without a new hold: scan what's there next pass
+        let Some(metadata) = metadata.filter(|metadata| Some(&metadata.version) == entry.version.as_ref()) else {
+            let engine = self.engine.clone();
+            return blocking(move || refresh_hold(&engine, &entry)).await;
+        };
+
+        let scan = self.scan(client, &entry, &metadata);
+        let verdict = match tokio::time::timeout(self.config.timeout, scan).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("scan timed out after {:?}", self.config.timeout)),
+        };
+        let engine = self.engine.clone();
+        match verdict {
+            Ok(Verdict::Approve) => {
+                if blocking(move || release_quarantined(&engine, &entry)).await? {
+                    self.stats.approved.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Ok(Verdict::Reject(reason)) => {
+                let name = format!("{}/{}", entry.bucket, entry.key);
+                if blocking(move || reject_quarantined(&engine, &entry)).await? {
+                    self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                    warn!("Quarantine hook rejected {}, deleted it: {}", name, reason);
+                }
+            }
+            Err(e) => {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                debug!("Scanning {}/{} failed: {}", entry.bucket, entry.key, e);
+                blocking(move || record_scan_failure(&engine, &entry, &e)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan(&self, client: &HttpsClient, entry: &QuarantineEntry, metadata: &ObjectMetadata) -> Result<Verdict, String> {
+        let data = self.object_stream(entry, metadata).await?;
+        let version = metadata.version.to_string();
+        match &self.config.hook {
+            ScanHook::Http(uri) => {
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(uri.clone())
+                    .header("content-type", "application/octet-stream")
+                    .header("content-length", metadata.size)
+                    .header("x-wfldb-bucket", &entry.bucket)
+                    .header("x-wfldb-key", &entry.key)
+                    .header("x-wfldb-version", &version)
+                    .body(Body::wrap_stream(data))
+                    .map_err(|e| e.to_string())?;
+                let response = client.request(request).await.map_err(|e| e.to_string())?;
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+                match status.as_u16() {
+                    200..=299 => Ok(Verdict::Approve),
+                    400..=499 => Ok(Verdict::Reject(reason(&body, status.as_str()))),
+                    _ => Err(format!("hook answered {}", status)),
+                }
+            }
+            ScanHook::Command(command) => {
+                let mut child = tokio::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("WFLDB_BUCKET", &entry.bucket)
+                    .env("WFLDB_KEY", &entry.key)
+                    .env("WFLDB_VERSION", &version)
+                    .env("WFLDB_SIZE", metadata.size.to_string())
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| format!("failed to start hook: {}", e))?;
+                let mut stdin = child.stdin.take().unwrap();
+                // Feed stdin alongside reading stdout, so neither pipe can fill up and stall
+                let feed = async move {
+                    let mut data = data;
+                    while let Some(chunk) = data.next().await {
+                        // A hook may decide before reading everything
+                        if stdin.write_all(&chunk.map_err(|e| e.to_string())?).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok::<_, String>(())
+                };
+                let (fed, output) = tokio::join!(feed, child.wait_with_output());
+                fed?;
+                let output = output.map_err(|e| e.to_string())?;
+                match output.status.code() {
+                    Some(0) => Ok(Verdict::Approve),
+                    Some(1) => Ok(Verdict::Reject(reason(&output.stdout, "exit status 1"))),
+                    _ => Err(format!("hook exited with {}", output.status)),
+                }
+            }
+        }
+    }
+
+    /// The object's content, chunk by chunk for chunked objects
+    async fn object_stream(
+        &self,
+        entry: &QuarantineEntry,
+        metadata: &ObjectMetadata,
+    ) -> Result<BoxStream<'static, Result<Bytes, WflDBError>>, String> {
+        let (scoped, bucket_id) = self.engine.resolve_namespaced(&entry.bucket).map_err(|e| e.to_string())?;
+        let key = Key::new(&entry.key).map_err(|e| e.to_string())?;
+        if let Some(manifest) = metadata.chunk_manifest.clone() {
+            return Ok(transport::chunk_body(scoped, bucket_id, key, manifest).boxed());
+        }
+        let data = blocking(move || Storage::new(scoped).get_object(&bucket_id, &key)).await?;
+        Ok(stream::iter(data.map(|data| Ok(Bytes::from(data)))).boxed())
+    }
+}
+
+type HttpsClient = Client<hyper_rustls::HttpsConnector<HttpConnector>>;
+
+fn hook_client() -> HttpsClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// Run storage work off the async threads
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, WflDBError> + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| e.to_string())?.map_err(|e| e.to_string())
+}
+
+/// A hook's explanation, or `fallback` when it gave none
+fn reason(output: &[u8], fallback: &str) -> String {
+    let text = String::from_utf8_lossy(&output[..output.len().min(MAX_REASON_BYTES)]).trim().to_string();
+    if text.is_empty() { fallback.to_string() } else { text }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hook() {
+        assert!(matches!(ScanHook::parse("https://scanner.internal/scan"), Ok(ScanHook::Http(_))));
+        assert_eq!(ScanHook::parse("clamdscan -"), Ok(ScanHook::Command("clamdscan -".to_string())));
+        assert!(ScanHook::parse("  ").is_err());
+    }
+
+    #[test]
+    fn test_written_object() {
+        let object = |route, method, path, query| written_object(route, &method, path, query).map(|(_, key)| key);
+        assert_eq!(object(Route::PutObject, Method::PUT, "/v1/docs/a.txt", None).unwrap().as_str(), "a.txt");
+        assert_eq!(object(Route::Chunks, Method::PUT, "/v1/docs/_manifest/b.bin", None).unwrap().as_str(), "b.bin");
+        assert!(object(Route::Chunks, Method::PUT, "/v1/docs/_chunks/exists", None).is_none());
+        // Starting a multipart upload writes nothing yet
+        assert!(object(Route::Multipart, Method::POST, "/v1/docs/_uploads/c.iso", None).is_none());
+        assert!(object(Route::Multipart, Method::POST, "/v1/docs/_uploads/c.iso", Some("upload_id=01J")).is_some());
+        assert!(object(Route::GetObject, Method::GET, "/v1/docs/a.txt", None).is_none());
+    }
+}
@@ -0,0 +1,206 @@
+//! Settings changed while the server runs
+//!
+//! A few settings can be changed without a restart: the log filter,
+//! bandwidth limits, how many background tasks run at once and each task's
+//! throttle, the response cache limits, and injected faults. `PATCH
+//! /admin/config` applies the fields it is sent, and SIGHUP reloads the
+//! `--config` file and environment (see [`crate::config`]), applying every
+//! reloadable setting that has a value there.
+//!
+//! A change is checked as a whole before any of it is applied, so a bad
+//! field leaves every setting as it was. Settings that need something set
+//! up at startup can't be introduced at runtime: the response cache only
+//! resizes when `--response-cache-mb` was given, and faults only inject with
+//! `--enable-chaos`.
+
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+use crate::bandwidth::BandwidthConfig;
+use crate::chaos::ChaosSettings;
+use crate::logging;
+use crate::simple_server_fixed::ServerState;
+use crate::tasks::Throttle;
+
+/// Builds the settings a SIGHUP applies, from the reloaded configuration
+pub type Reload = Box<dyn Fn() -> Result<RuntimeSettings, String> + Send + Sync>;
+
+/// Reloadable settings; `None` leaves a setting as it is
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeSettings {
+    /// Log filter directives, as for `--log-level`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// Every bandwidth limit, as `--bandwidth` specs; replaces them all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_background_tasks: Option<usize>,
+    /// Throttles of the named tasks; tasks left out keep theirs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_throttles: Option<BTreeMap<String, Throttle>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_cache_mb: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_cache_entries: Option<usize>,
+    /// Injected faults, replaced as a whole as with `PUT /admin/chaos`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chaos: Option<ChaosSettings>,
+}
+
+impl RuntimeSettings {
+    /// The reloadable settings of a parsed configuration; bandwidth limits
+    /// left out of it are lifted
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let number = |id: &str| matches.get_one::<String>(id).and_then(|v| v.parse().ok());
+        let cache_mb = number("response-cache-mb");
+        RuntimeSettings {
+            log_level: matches.get_one::<String>("log-level").cloned(),
+            bandwidth: Some(matches.get_many::<String>("bandwidth").into_iter().flatten().cloned().collect()),
+            max_background_tasks: number("max-background-tasks"),
+            task_throttles: None,
+            response_cache_mb: cache_mb,
+            // Has a default, so only sent along with a cache to size
+            response_cache_entries: cache_mb.and(number("response-cache-entries")),
+            chaos: None,
+        }
+    }
+
+    /// The settings in effect
+    pub fn current(state: &ServerState) -> Self {
+        let limits = state.response_cache.as_ref().map(|cache| cache.limits());
+        let throttles = state.tasks.status().into_iter().map(|status| (status.name.to_string(), status.throttle));
+        RuntimeSettings {
+            log_level: logging::level(),
+            bandwidth: Some(state.bandwidth.config().specs()),
+            max_background_tasks: Some(state.tasks.max_running()),
+            task_throttles: Some(throttles.collect()),
+            response_cache_mb: limits.map(|(bytes, _)| bytes / (1024 * 1024)),
+            response_cache_entries: limits.map(|(_, entries)| entries),
+            chaos: state.chaos.as_ref().map(|chaos| chaos.settings()),
+        }
+    }
+
+    /// Check every field against `state`, returning the bandwidth limits to set
+    fn validate(&self, state: &ServerState) -> Result<Option<BandwidthConfig>, String> {
+        if let Some(level) = &self.log_level {
+            logging::parse_level(level)?;
+        }
+        if self.max_background_tasks == Some(0) {
+            return Err("max_background_tasks must be positive".to_string());
+        }
+        for (name, throttle) in self.task_throttles.iter().flatten() {
+            if state.tasks.is_paused(name).is_none() {
+                return Err(format!("unknown task '{}'", name));
+            }
+            throttle.validate().map_err(|e| format!("task '{}': {}", name, e))?;
+        }
+        if self.response_cache_mb.is_some() || self.response_cache_entries.is_some() {
+            if state.response_cache.is_none() {
+                return Err("the response cache is off; start with --response-cache-mb to size it".to_string());
+            }
+            if self.response_cache_entries == Some(0) {
+                return Err("response_cache_entries must be positive".to_string());
+            }
+        }
+        match (&self.chaos, &state.chaos) {
+            (Some(_), None) => return Err("fault injection is disabled; start with --enable-chaos".to_string()),
+            (Some(settings), Some(_)) => settings.validate()?,
+            _ => {}
+        }
+        let Some(specs) = &self.bandwidth else {
+            return Ok(None);
+        };
+        let mut bandwidth = BandwidthConfig::default();
+        for spec in specs {
+            let (name, limit) = BandwidthConfig::parse_limit(spec)?;
+            bandwidth.limits.insert(name, limit);
+        }
+        Ok(Some(bandwidth))
+    }
+
+    /// Apply the fields that are set, all or none, logging each change
+    /// against `actor`
+    pub fn apply(&self, state: &ServerState, actor: &str) -> Result<(), String> {
+        let bandwidth = self.validate(state)?;
+        if let Some(level) = &self.log_level {
+            logging::set_level(level)?;
+            info!("Log level set to '{}' by {}", level, actor);
+        }
+        if let Some(bandwidth) = bandwidth {
+            info!("Bandwidth limits set to [{}] by {}", bandwidth.specs().join(", "), actor);
+            state.bandwidth.set_config(bandwidth);
+        }
+        if let Some(max) = self.max_background_tasks {
+            state.tasks.set_max_running(max);
+            info!("Background task limit set to {} by {}", max, actor);
+        }
+        for (name, throttle) in self.task_throttles.iter().flatten() {
+            state.tasks.set_throttle(name, *throttle);
+            info!("Background task '{}' throttle set to {:?} by {}", name, throttle, actor);
+        }
+        if let Some(cache) = &state.response_cache {
+            let (bytes, entries) = cache.limits();
+            if self.response_cache_mb.is_some() || self.response_cache_entries.is_some() {
+                let bytes = self.response_cache_mb.map_or(bytes, |mb| mb * 1024 * 1024);
+                let entries = self.response_cache_entries.unwrap_or(entries);
+                cache.resize(bytes, entries);
+                info!("Response cache resized to {} bytes, {} entries by {}", bytes, entries, actor);
+            }
+        }
+        if let (Some(settings), Some(chaos)) = (self.chaos, &state.chaos) {
+            chaos.set(settings);
+            warn!("Fault injection set to {:?} by {}", settings, actor);
+        }
+        Ok(())
+    }
+}
+
+/// Reload the configuration on every SIGHUP, keeping the running settings
+/// when it doesn't load or check out
+#[cfg(unix)]
+pub async fn reload_on_sighup(state: Arc<ServerState>, reload: Reload) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Configuration reload on SIGHUP unavailable: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match reload().and_then(|settings| settings.apply(&state, "SIGHUP")) {
+            Ok(()) => info!("Configuration reloaded"),
+            Err(e) => warn!("Configuration reload failed, keeping the running settings: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_state: Arc<ServerState>, _reload: Reload) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reloaded_settings() {
+        let matches = crate::cli().get_matches_from(["wfldb-server", "--bandwidth", "key/*=64/64"]);
+        let settings = RuntimeSettings::from_matches(&matches);
+        assert_eq!(settings.bandwidth, Some(vec!["key/*=64/64".to_string()]));
+        assert_eq!(settings.max_background_tasks, Some(1));
+        assert_eq!((settings.response_cache_mb, settings.response_cache_entries), (None, None));
+
+        let matches = crate::cli().get_matches_from(["wfldb-server", "--response-cache-mb", "64"]);
+        let settings = RuntimeSettings::from_matches(&matches);
+        assert_eq!(settings.bandwidth, Some(Vec::new()));
+        assert_eq!((settings.response_cache_mb, settings.response_cache_entries), (Some(64), Some(10_000)));
+
+        let patch: RuntimeSettings = serde_json::from_str(r#"{"max_background_tasks": 2}"#).unwrap();
+        assert_eq!(patch, RuntimeSettings { max_background_tasks: Some(2), ..Default::default() });
+        assert!(serde_json::from_str::<RuntimeSettings>(r#"{"slow_request_ms": 5}"#).is_err());
+    }
+}
@@ -0,0 +1,506 @@
+//! Read replicas and read consistency
+//!
+//! A replica follows its leader's change journal through
+//! `/admin/journal`, applies each record to its own storage, and refuses
+//! writes. Its position is the last leader sequence it applied, saved in the
+//! system partition so a restart resumes where it stopped. The leader's
+//! position is its own journal head.
+//!
+//! Every `/v1` response carries the answering node's position in
+//! `x-wfldb-applied-seq`. A client that remembers the highest position it
+//! has seen can send it back as `x-wfldb-min-seq` to read its own writes on
+//! any node: a replica holds the read until it has applied that far, and
+//! answers `replica_behind` if it can't within [`READ_FLOOR_WAIT`].
+//! `x-wfldb-max-staleness-ms` bounds how long ago the replica last matched
+//! the leader instead.
+//!
+//! With a lag limit (`--replica-max-lag-ms`) a replica leaves the read pool
+//! while it last matched the leader longer ago than that: it fails
+//! `/readyz` and turns reads away with `replica_behind` straight away, so
+//! clients read from the leader until it has caught up again.
+//!
+//! `x-wfldb-session` carries the same floor inside a [`SessionToken`] along
+//! with the newest clock reading the client has seen. Every node advances
+//! its clock past the token's, so a write made after reading something
+//! versions after it, and answers with the token merged with its own
+//! position and clock.
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, HeaderMap, Request, StatusCode, Uri};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+use wfldb_auth::{headers, now_ms, RequestSigner};
+use wfldb_core::{decode_change_frames, HlcTimestamp, HybridClock, SessionToken, WflDBError};
+use wfldb_engine::{apply_change, StorageEngine};
+use crate::admin::{JOURNAL_HEAD_HEADER, JOURNAL_NEXT_HEADER};
+use crate::error::ApiError;
+use crate::health::TaskHeartbeats;
+use crate::simple_server_fixed::ServerState;
+
+/// Name of the follower in the liveness probe
+const HEARTBEAT_TASK: &str = "replica_follow";
+
+/// System partition key holding the last leader sequence applied here
+const REPLICA_POSITION_KEY: &str = "replica/applied_seq";
+
+/// Records asked for per journal request
+const FOLLOW_BATCH: usize = 1000;
+
+/// Longest a read waits for the replica to reach its floor
+pub const READ_FLOOR_WAIT: Duration = Duration::from_secs(1);
+
+/// Pause between checks while a read waits
+const READ_FLOOR_POLL: Duration = Duration::from_millis(20);
+
+/// Where a replica stands relative to its leader
+#[derive(Debug)]
+pub struct ReplicaState {
+    leader: String,
+    applied_seq: AtomicU64,
+    /// The leader's journal head plus one, as of the last pull; 0 until a
+    /// pull reports it
+    leader_next_seq: AtomicU64,
+    caught_up_at_ms: AtomicU64,
+    /// Staleness past which reads are turned away; 0 for no limit
+    max_lag_ms: AtomicU64,
+    failures: AtomicU64,
+    applied: AtomicU64,
+    sync_requested: Notify,
+}
+
+impl ReplicaState {
+    /// Base URL of the leader this replica follows
+    pub fn leader(&self) -> &str {
+        &self.leader
+    }
+
+    /// Last leader journal sequence applied here
+    pub fn applied_seq(&self) -> u64 {
+        self.applied_seq.load(Ordering::Relaxed)
+    }
+
+    /// How far behind the leader this replica may be: the time since a
+    /// sync last found nothing left to apply; `None` until that happens
+    pub fn staleness_ms(&self) -> Option<u64> {
+        match self.caught_up_at_ms.load(Ordering::Relaxed) {
+            0 => None,
+            at => Some(now_ms().saturating_sub(at)),
+        }
+    }
+
+    /// The leader's journal head as of the last pull, if it reported one
+    pub fn leader_seq(&self) -> Option<u64> {
+        self.leader_next_seq.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    /// Journal sequences the leader had committed at the last pull that
+    /// aren't applied here yet
+    pub fn lag_seq(&self) -> Option<u64> {
+        self.leader_seq().map(|leader| leader.saturating_sub(self.applied_seq()))
+    }
+
+    /// Staleness past which this replica leaves the read pool
+    pub fn max_lag(&self) -> Option<Duration> {
+        match self.max_lag_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Whether the replica is close enough to the leader to serve reads:
+    /// always without a lag limit, otherwise once it has caught up within it
+    pub fn in_read_pool(&self) -> bool {
+        match self.max_lag() {
+            None => true,
+            Some(max_lag) => self.staleness_ms().is_some_and(|staleness| staleness <= max_lag.as_millis() as u64),
+        }
+    }
+
+    /// Failed sync attempts
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Change records applied from the leader
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Relaxed)
+    }
+
+    /// Sync now rather than at the next interval
+    pub fn request_sync(&self) {
+        self.sync_requested.notify_one();
+    }
+}
+
+/// Keeps a replica's storage in step with the leader's change journal
+pub struct JournalFollower {
+    upstream: String,
+    interval: Duration,
+    timeout: Duration,
+    storage: StorageEngine,
+    signer: RequestSigner,
+    state: Arc<ReplicaState>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl JournalFollower {
+    /// Follow the leader at a base URL such as `http://leader:8080`, signing
+    /// journal reads with an admin key
+    pub fn new(upstream: &str, interval: Duration, storage: StorageEngine, signer: RequestSigner) -> Result<Self, String> {
+        let upstream = upstream.trim_end_matches('/').to_string();
+        upstream
+            .parse::<Uri>()
+            .map_err(|e| format!("Invalid replica leader '{}': {}", upstream, e))?;
+        let applied_seq = storage
+            .system()
+            .and_then(|system| system.get(REPLICA_POSITION_KEY))
+            .map_err(|e| format!("Failed to read replica position: {}", e))?
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+            .unwrap_or(0);
+        Ok(JournalFollower {
+            state: Arc::new(ReplicaState {
+                leader: upstream.clone(),
+                applied_seq: AtomicU64::new(applied_seq),
+                leader_next_seq: AtomicU64::new(0),
+                caught_up_at_ms: AtomicU64::new(0),
+                max_lag_ms: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+                applied: AtomicU64::new(0),
+                sync_requested: Notify::new(),
+            }),
+            upstream,
+            interval,
+            timeout: interval.max(Duration::from_secs(5)),
+            storage,
+            signer,
+            heartbeats: None,
+        })
+    }
+
+    /// Report each sync to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.interval + self.timeout);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Leave the read pool while the leader was last matched longer than
+    /// `max_lag` ago
+    pub fn with_max_lag(self, max_lag: Duration) -> Self {
+        self.state.max_lag_ms.store(max_lag.as_millis() as u64, Ordering::Relaxed);
+        self
+    }
+
+    pub fn state(&self) -> Arc<ReplicaState> {
+        self.state.clone()
+    }
+
+    /// Sync every interval, or sooner when a read is waiting, until the
+    /// task is dropped
+    pub async fn run(self) {
+        info!(
+            "Following {} from journal sequence {} every {:?}",
+            self.upstream,
+            self.state.applied_seq(),
+            self.interval
+        );
+        let client = Client::new();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = self.state.sync_requested.notified() => {}
+            }
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            if let Err(e) = self.sync_once(&client).await {
+                self.state.failures.fetch_add(1, Ordering::Relaxed);
+                warn!("Replica sync from {} failed: {}", self.upstream, e);
+            }
+        }
+    }
+
+    /// Apply pages of the leader's journal until one comes back empty
+    async fn sync_once(&self, client: &Client<HttpConnector>) -> Result<(), String> {
+        loop {
+            let started_ms = now_ms();
+            let after = self.state.applied_seq();
+            let (records, next) = self.fetch(client, after).await?;
+            if records.is_empty() {
+                // Everything the leader had committed when the request left
+                // is applied
+                self.state.caught_up_at_ms.store(started_ms, Ordering::Relaxed);
+                return Ok(());
+            }
+
+            let count = records.len() as u64;
+            let storage = self.storage.clone();
+            tokio::task::spawn_blocking(move || {
+                let target = wfldb_engine::Storage::new(storage.clone());
+                for record in records {
+                    // Versions written here after the change sort after it,
+                    // even if this node's clock is behind the leader's
+                    let hlc = match &record.version {
+                        Some(version) => version.hlc(),
+                        None => HlcTimestamp::new(record.timestamp_ms, 0),
+                    };
+                    HybridClock::global().observe(hlc);
+                    apply_change(&target, record)?;
+                }
+                storage.system()?.put(REPLICA_POSITION_KEY, &next.to_le_bytes())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e: WflDBError| e.to_string())?;
+
+            self.state.applied_seq.store(next, Ordering::Relaxed);
+            self.state.applied.fetch_add(count, Ordering::Relaxed);
+            debug!("Applied {} changes from {} (now at {})", count, self.upstream, next);
+        }
+    }
+
+    async fn fetch(
+        &self,
+        client: &Client<HttpConnector>,
+        after: u64,
+    ) -> Result<(Vec<wfldb_core::ChangeRecord>, u64), String> {
+        let path = format!("/admin/journal?after={}&limit={}", after, FOLLOW_BATCH);
+        let mut request = Request::get(format!("{}{}", self.upstream, path));
+        for (name, value) in self.signer.sign("GET", &path, b"", now_ms()) {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::empty()).map_err(|e| e.to_string())?;
+
+        let response = tokio::time::timeout(self.timeout, client.request(request))
+            .await
+            .map_err(|_| "timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::GONE => {
+                return Err(format!(
+                    "the leader archived records after {}; seed this replica from a snapshot",
+                    after
+                ))
+            }
+            status => return Err(format!("unexpected status {}", status)),
+        }
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        let next = header(JOURNAL_NEXT_HEADER).ok_or("missing journal cursor")?;
+        // Leaders that predate the head header leave the lag unknown
+        if let Some(head) = header(JOURNAL_HEAD_HEADER) {
+            self.state.leader_next_seq.store(head + 1, Ordering::Relaxed);
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| e.to_string())?;
+        let (records, valid_len) = decode_change_frames(&body).map_err(|e| e.to_string())?;
+        if valid_len != body.len() {
+            return Err("truncated change record".to_string());
+        }
+        Ok((records, next))
+    }
+}
+
+/// This node's journal position: what a replica has applied, or the
+/// leader's journal head; `None` on a leader without a journal
+pub fn applied_seq(state: &ServerState) -> Option<u64> {
+    match (&state.replica, state.storage.journal()) {
+        (Some(replica), _) => Some(replica.applied_seq()),
+        (None, Some(journal)) => Some(journal.next_seq() - 1),
+        (None, None) => None,
+    }
+}
+
+/// Read floor and staleness bound sent with a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadRequirement {
+    pub min_seq: Option<u64>,
+    pub max_staleness: Option<Duration>,
+    pub session: Option<SessionToken>,
+}
+
+impl ReadRequirement {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| {
+                    v.to_str()
+                        .ok()
+                        .and_then(|v| v.trim().parse::<u64>().ok())
+                        .ok_or_else(|| ApiError::bad_request(format!("Invalid {} header", name)))
+                })
+                .transpose()
+        };
+        let session = headers
+            .get(headers::SESSION)
+            .map(|v| {
+                v.to_str()
+                    .ok()
+                    .and_then(|v| SessionToken::parse(v).ok())
+                    .ok_or_else(|| ApiError::bad_request("Invalid session token"))
+            })
+            .transpose()?;
+        let min_seq = match (number(headers::MIN_SEQ)?, session) {
+            (Some(min_seq), Some(session)) => Some(min_seq.max(session.seq)),
+            (min_seq, session) => min_seq.or(session.map(|s| s.seq)),
+        };
+        Ok(ReadRequirement {
+            min_seq,
+            max_staleness: number(headers::MAX_STALENESS)?.map(Duration::from_millis),
+            session,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_seq.is_none() && self.max_staleness.is_none()
+    }
+
+    /// Why a node at `applied_seq`, last level with the leader
+    /// `staleness_ms` ago, can't serve the read yet
+    fn unmet(&self, applied_seq: u64, staleness_ms: Option<u64>) -> Option<String> {
+        if let Some(min_seq) = self.min_seq.filter(|&min_seq| applied_seq < min_seq) {
+            return Some(format!("Applied journal sequence {} is below the read floor {}", applied_seq, min_seq));
+        }
+        match (self.max_staleness, staleness_ms) {
+            (Some(bound), Some(staleness)) if staleness > bound.as_millis() as u64 => {
+                Some(format!("Replica is {}ms behind the leader", staleness))
+            }
+            (Some(_), None) => Some("Replica has not caught up with the leader yet".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// The caller's session token brought up to what this node has applied and
+/// its clock; `None` on a leader without a journal
+pub fn session_token(state: &ServerState, session: Option<SessionToken>) -> Option<SessionToken> {
+    let clock = HybridClock::global();
+    let ours = SessionToken {
+        seq: applied_seq(state)?,
+        hlc: clock.last(),
+        node_id: clock.node_id(),
+    };
+    Some(session.map_or(ours, |session| session.merge(ours)))
+}
+
+/// Hold a read until this node meets the request's floor and staleness
+/// bound, for at most [`READ_FLOOR_WAIT`]; a replica out of the read pool
+/// refuses it outright
+pub async fn await_read(state: &ServerState, requirement: ReadRequirement) -> Result<(), ApiError> {
+    let Some(applied) = applied_seq(state) else {
+        // Without a journal there is nothing to replicate or compare against
+        return Ok(());
+    };
+    let Some(replica) = &state.replica else {
+        // The leader is never stale, and can't catch up to a floor above its
+        // own journal
+        return match requirement.unmet(applied, Some(0)) {
+            Some(reason) => Err(ApiError::replica_behind(reason).with_detail("applied_seq", applied)),
+            None => Ok(()),
+        };
+    };
+
+    if !replica.in_read_pool() {
+        // Turned away at once: waiting on a lagging replica only slows the
+        // fallback to the leader
+        replica.request_sync();
+        let staleness = replica.staleness_ms().map_or("unknown".to_string(), |ms| format!("{}ms", ms));
+        return Err(ApiError::replica_behind(format!("Replica is out of the read pool (staleness {})", staleness))
+            .with_detail("applied_seq", replica.applied_seq())
+            .with_detail("leader", replica.leader()));
+    }
+
+    let deadline = Instant::now() + READ_FLOOR_WAIT;
+    loop {
+        let applied = replica.applied_seq();
+        let reason = match requirement.unmet(applied, replica.staleness_ms()) {
+            None => return Ok(()),
+            Some(reason) => reason,
+        };
+        if Instant::now() >= deadline {
+            return Err(ApiError::replica_behind(reason)
+                .with_detail("applied_seq", applied)
+                .with_detail("leader", replica.leader()));
+        }
+        replica.request_sync();
+        tokio::time::sleep(READ_FLOOR_POLL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_requirement() {
+        let mut headers = HeaderMap::new();
+        assert!(ReadRequirement::from_headers(&headers).unwrap().is_empty());
+
+        headers.insert(headers::MIN_SEQ, "42".parse().unwrap());
+        headers.insert(headers::MAX_STALENESS, "500".parse().unwrap());
+        let requirement = ReadRequirement::from_headers(&headers).unwrap();
+        assert_eq!(requirement.min_seq, Some(42));
+        assert_eq!(requirement.max_staleness, Some(Duration::from_millis(500)));
+
+        assert!(requirement.unmet(42, Some(100)).is_none());
+        assert!(requirement.unmet(41, Some(100)).is_some());
+        assert!(requirement.unmet(50, Some(800)).is_some());
+        // A replica that never caught up has unknown staleness
+        assert!(requirement.unmet(50, None).is_some());
+        let floor_only = ReadRequirement { min_seq: Some(42), ..Default::default() };
+        assert!(floor_only.unmet(42, None).is_none());
+
+        // A session token raises the floor to the position it carries
+        let token = SessionToken { seq: 60, hlc: HlcTimestamp::new(1_000, 0), node_id: 1 };
+        headers.insert(headers::SESSION, token.to_string().parse().unwrap());
+        let requirement = ReadRequirement::from_headers(&headers).unwrap();
+        assert_eq!((requirement.min_seq, requirement.session), (Some(60), Some(token)));
+
+        headers.insert(headers::MIN_SEQ, "soon".parse().unwrap());
+        assert!(ReadRequirement::from_headers(&headers).is_err());
+        headers.insert(headers::MIN_SEQ, "1".parse().unwrap());
+        headers.insert(headers::SESSION, "stale".parse().unwrap());
+        assert!(ReadRequirement::from_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn test_read_pool() {
+        let replica = ReplicaState {
+            leader: "http://leader:8080".to_string(),
+            applied_seq: AtomicU64::new(40),
+            leader_next_seq: AtomicU64::new(0),
+            caught_up_at_ms: AtomicU64::new(0),
+            max_lag_ms: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            applied: AtomicU64::new(0),
+            sync_requested: Notify::new(),
+        };
+        assert_eq!(replica.lag_seq(), None);
+        replica.leader_next_seq.store(43, Ordering::Relaxed);
+        assert_eq!((replica.leader_seq(), replica.lag_seq()), (Some(42), Some(2)));
+
+        // Without a limit even a replica that never caught up serves reads
+        assert!(replica.in_read_pool());
+        replica.max_lag_ms.store(1_000, Ordering::Relaxed);
+        assert!(!replica.in_read_pool());
+        replica.caught_up_at_ms.store(now_ms(), Ordering::Relaxed);
+        assert!(replica.in_read_pool());
+        replica.caught_up_at_ms.store(now_ms() - 5_000, Ordering::Relaxed);
+        assert!(!replica.in_read_pool());
+    }
+}
@@ -0,0 +1,75 @@
+//! Request ids
+//!
+//! Every request gets an id: the caller's `X-Request-Id` when it sends a
+//! usable one, otherwise a fresh ULID. The request is handled inside a
+//! tracing span carrying the id, so every log line it causes names it; the
+//! response echoes it in `X-Request-Id`, and error bodies include it as
+//! `request_id`. Clients send the same id on each retry of an operation, so
+//! one id ties the attempts together across client and server logs.
+
+use hyper::{Body, Request};
+use std::future::Future;
+use wfldb_auth::headers;
+
+/// Longest caller-supplied id accepted; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+/// The caller's request id if it is usable, otherwise a new one
+pub fn for_request(req: &Request<Body>) -> String {
+    req.headers()
+        .get(headers::REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| ulid::Ulid::new().to_string())
+}
+
+/// Ids are 1 to 128 visible ASCII characters, so they're safe to log and echo
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Run `f` as the handling of request `id`
+pub async fn scope<F: Future>(id: String, f: F) -> F::Output {
+    CURRENT.scope(id, f).await
+}
+
+/// Id of the request being handled, if any
+pub fn current() -> Option<String> {
+    CURRENT.try_with(String::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri("/v1/photos/cat.jpg");
+        if let Some(id) = id {
+            builder = builder.header(headers::REQUEST_ID, id);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_for_request() {
+        assert_eq!(for_request(&request(Some("trace-42"))), "trace-42");
+        let generated = for_request(&request(None));
+        assert!(ulid::Ulid::from_string(&generated).is_ok());
+        assert_ne!(for_request(&request(None)), generated);
+        // Unusable ids are replaced rather than echoed
+        assert_ne!(for_request(&request(Some("a b"))), "a b");
+        assert_eq!(for_request(&request(Some(&"x".repeat(200)))).len(), 26);
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(current(), None);
+        let seen = scope("trace-42".to_string(), async { current() }).await;
+        assert_eq!(seen.as_deref(), Some("trace-42"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Cache of object bodies for GET
+//!
+//! Hot objects are kept in memory by (bucket, key, version). A GET reads
+//! only the object's metadata and serves the body from the cache when the
+//! cached version is still current, so a stale body is never returned even
+//! if a write bypassed invalidation; writes through this server drop their
+//! key's entry right away so dead bodies don't hold memory. The cache is
+//! bounded by total bytes and by entry count, evicting the least recently
+//! used entries, and skips objects too large to be worth holding.
+//!
+//! Every plain GET carries an `ETag` of the object version, and a GET whose
+//! `If-None-Match` names the current version answers 304 without reading
+//! the body, with or without the cache.
+
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use wfldb_core::{BucketId, ChunkManifest, Key, ObjectMetadata, Result, Version};
+use wfldb_engine::Storage;
+
+/// Share of the byte budget one object may take
+const MAX_ENTRY_SHARE: usize = 8;
+
+/// Quoted entity tag for an object version
+pub fn etag(version: &Version) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Whether an `If-None-Match` header value matches `etag`
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Outcome of a GET through the cache
+pub enum CachedRead {
+    Found(Bytes, ObjectMetadata),
+    /// The client's copy is current; the metadata is kept so a customer key
+    /// can still be checked before answering 304
+    NotModified(ObjectMetadata),
+    /// A chunked object to stream from its manifest rather than read whole
+    Chunked(ChunkManifest, ObjectMetadata),
+    Missing,
+}
+
+/// Read an object for a GET, consulting `cache` and `if_none_match`
+///
+/// With `stream_chunked` a chunked object is returned as
+/// [`CachedRead::Chunked`] for the caller to stream, and never cached.
+pub fn read_object(
+    storage: &Storage,
+    cache: Option<&ResponseCache>,
+    bucket_id: &BucketId,
+    key: &Key,
+    if_none_match: Option<&str>,
+    stream_chunked: bool,
+) -> Result<CachedRead> {
+    if cache.is_none() && if_none_match.is_none() && !stream_chunked {
+        return Ok(match storage.get_versioned(bucket_id, key)? {
+            Some((data, metadata)) => CachedRead::Found(Bytes::from(data), metadata),
+            None => CachedRead::Missing,
+        });
+    }
+
+    let Some(metadata) = storage.get_metadata(bucket_id, key)? else {
+        return Ok(CachedRead::Missing);
+    };
+    if if_none_match.is_some_and(|tags| etag_matches(tags, &etag(&metadata.version))) {
+        return Ok(CachedRead::NotModified(metadata));
+    }
+    if let (true, Some(manifest)) = (stream_chunked, &metadata.chunk_manifest) {
+        return Ok(CachedRead::Chunked(manifest.clone(), metadata));
+    }
+    let bucket = storage.engine().namespaced(bucket_id);
+    if let Some(data) = cache.and_then(|cache| cache.get(&bucket, key.as_str(), &metadata.version)) {
+        return Ok(CachedRead::Found(data, metadata));
+    }
+
+    // The object may have changed since the metadata read; trust this read
+    let Some((data, metadata)) = storage.get_versioned(bucket_id, key)? else {
+        return Ok(CachedRead::Missing);
+    };
+    let data = Bytes::from(data);
+    if let Some(cache) = cache {
+        cache.insert(&bucket, key.as_str(), &metadata.version, data.clone());
+    }
+    Ok(CachedRead::Found(data, metadata))
+}
+
+/// Object bodies by namespaced bucket, key, and version
+pub struct ResponseCache {
+    max_bytes: AtomicUsize,
+    max_entries: AtomicUsize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    tick: u64,
+    bytes: usize,
+    by_key: HashMap<(String, String), Entry>,
+    by_tick: BTreeMap<u64, (String, String)>,
+}
+
+struct Entry {
+    version: Version,
+    data: Bytes,
+    tick: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, id: &(String, String)) {
+        if let Some(entry) = self.by_key.remove(id) {
+            self.by_tick.remove(&entry.tick);
+            self.bytes -= entry.data.len();
+        }
+    }
+
+    /// Drop the least recently used entries until both limits hold
+    fn evict(&mut self, max_bytes: usize, max_entries: usize) {
+        while self.bytes > max_bytes || self.by_key.len() > max_entries {
+            let Some((_, oldest)) = self.by_tick.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+impl ResponseCache {
+    /// Hold at most `max_bytes` of bodies in at most `max_entries` entries
+    pub fn new(max_bytes: usize, max_entries: usize) -> Self {
+        ResponseCache {
+            max_bytes: AtomicUsize::new(max_bytes),
+            max_entries: AtomicUsize::new(max_entries.max(1)),
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Cached body of `key` if the cached version is `version`
+    pub fn get(&self, bucket: &str, key: &str, version: &Version) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+        let id = (bucket.to_string(), key.to_string());
+        let found = match entries.by_key.get_mut(&id) {
+            Some(entry) if entry.version == *version => {
+                let previous = std::mem::replace(&mut entry.tick, tick);
+                Some((previous, entry.data.clone()))
+            }
+            _ => None,
+        };
+        match found {
+            Some((previous, data)) => {
+                entries.by_tick.remove(&previous);
+                entries.by_tick.insert(tick, id);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(data)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Cache a body, replacing any other version of the key
+    pub fn insert(&self, bucket: &str, key: &str, version: &Version, data: Bytes) {
+        let (max_bytes, max_entries) = self.limits();
+        if data.len() > max_bytes / MAX_ENTRY_SHARE {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let id = (bucket.to_string(), key.to_string());
+        entries.remove(&id);
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.bytes += data.len();
+        entries.by_tick.insert(tick, id.clone());
+        entries.by_key.insert(id, Entry { version: version.clone(), data, tick });
+
+        entries.evict(max_bytes, max_entries);
+    }
+
+    /// Byte and entry limits
+    pub fn limits(&self) -> (usize, usize) {
+        (self.max_bytes.load(Ordering::Relaxed), self.max_entries.load(Ordering::Relaxed))
+    }
+
+    /// Change the limits, evicting what no longer fits
+    pub fn resize(&self, max_bytes: usize, max_entries: usize) {
+        let max_entries = max_entries.max(1);
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        self.max_entries.store(max_entries, Ordering::Relaxed);
+        self.entries.lock().unwrap().evict(max_bytes, max_entries);
+    }
+
+    /// Drop the cached body of a key after it was written or deleted
+    pub fn invalidate(&self, bucket: &str, key: &str) {
+        self.entries.lock().unwrap().remove(&(bucket.to_string(), key.to_string()));
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().by_key.len()
+    }
+
+    /// Bytes of bodies currently held
+    pub fn size_bytes(&self) -> usize {
+        self.entries.lock().unwrap().bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_bounds_and_versions() {
+        let cache = ResponseCache::new(800, 3);
+        let (v1, v2) = (Version::new(), Version::new());
+        cache.insert("b", "a", &v1, Bytes::from_static(b"old"));
+        assert_eq!(cache.get("b", "a", &v1).as_deref(), Some(&b"old"[..]));
+
+        // Another version replaces the entry
+        cache.insert("b", "a", &v2, Bytes::from_static(b"new"));
+        assert!(cache.get("b", "a", &v1).is_none());
+        assert_eq!(cache.get("b", "a", &v2).as_deref(), Some(&b"new"[..]));
+
+        // Entry bound evicts the least recently used
+        cache.insert("b", "b", &v1, Bytes::from_static(b"x"));
+        cache.insert("b", "c", &v1, Bytes::from_static(b"x"));
+        cache.get("b", "a", &v2);
+        cache.insert("b", "d", &v1, Bytes::from_static(b"x"));
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get("b", "b", &v1).is_none());
+        assert!(cache.get("b", "a", &v2).is_some());
+
+        // Objects over an eighth of the byte budget are not cached
+        cache.insert("b", "big", &v1, Bytes::from(vec![0; 101]));
+        assert!(cache.get("b", "big", &v1).is_none());
+
+        cache.invalidate("b", "a");
+        assert!(cache.get("b", "a", &v2).is_none());
+        assert_eq!(cache.size_bytes(), 2);
+
+        // Shrinking evicts down to the new limits
+        cache.resize(800, 1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get("b", "d", &v1).is_some());
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let tag = etag(&Version::new());
+        assert!(etag_matches(&tag, &tag));
+        assert!(etag_matches(&format!("\"other\", W/{}", tag), &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"other\"", &tag));
+    }
+}
@@ -0,0 +1,253 @@
+//! The revocation list endpoint, and its background pull from an upstream node
+//!
+//! `GET /auth/revocations` serves the list with its version as the ETag and
+//! `POST /auth/revocations` revokes a key. Followers and gateways poll the upstream's `/auth/revocations` endpoint,
+//! sending the last version they saw as an `If-None-Match` tag. A key revoked
+//! upstream is therefore blocked here within `interval + timeout`.
+
+use hyper::{Body, Client, Request, Response, StatusCode, Uri};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use wfldb_auth::{now_ms, AuthError, Authenticator, KeyAuthority, KeyId, RevocationList};
+use crate::auth;
+use crate::error::ApiError;
+use crate::health::TaskHeartbeats;
+use crate::router::json_response;
+use crate::slow_log::{Phase, RequestTimings};
+use crate::spool::Spool;
+
+/// Name of the puller in the liveness probe
+const HEARTBEAT_TASK: &str = "revocation_sync";
+
+/// Largest revocation body, `{"key_id": "<hex>"}`
+const MAX_REVOKE_BODY_BYTES: usize = 16 * 1024;
+
+/// Counters exposed through /metrics
+#[derive(Debug, Default)]
+pub struct RevocationSyncStats {
+    upstream_version: AtomicU64,
+    last_success_ms: AtomicU64,
+    failures: AtomicU64,
+    applied: AtomicU64,
+}
+
+impl RevocationSyncStats {
+    /// Last revocation list version seen from the upstream
+    pub fn upstream_version(&self) -> u64 {
+        self.upstream_version.load(Ordering::Relaxed)
+    }
+
+    /// Milliseconds since the last successful sync, if any
+    pub fn staleness_ms(&self) -> Option<u64> {
+        match self.last_success_ms.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(now_ms().saturating_sub(last)),
+        }
+    }
+
+    /// Failed sync attempts
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Revocations learned from the upstream
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Relaxed)
+    }
+}
+
+/// Periodically merges the upstream revocation list into the local authority
+pub struct RevocationPuller {
+    upstream: Uri,
+    interval: Duration,
+    timeout: Duration,
+    authority: Arc<KeyAuthority>,
+    stats: Arc<RevocationSyncStats>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl RevocationPuller {
+    /// Create a puller for an upstream base URL such as `http://leader:8080`
+    pub fn new(upstream: &str, interval: Duration, authority: Arc<KeyAuthority>) -> Result<Self, String> {
+        let upstream = format!("{}/auth/revocations", upstream.trim_end_matches('/'))
+            .parse::<Uri>()
+            .map_err(|e| format!("Invalid revocation upstream '{}': {}", upstream, e))?;
+        Ok(RevocationPuller {
+            upstream,
+            interval,
+            timeout: interval.max(Duration::from_secs(1)),
+            authority,
+            stats: Arc::new(RevocationSyncStats::default()),
+            heartbeats: None,
+        })
+    }
+
+    /// Report each poll to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, self.interval + self.timeout);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<RevocationSyncStats> {
+        self.stats.clone()
+    }
+
+    /// Poll the upstream until the task is dropped
+    pub async fn run(self) {
+        info!("Pulling revocations from {} every {:?}", self.upstream, self.interval);
+        let client = Client::new();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            match tokio::time::timeout(self.timeout, self.sync_once(&client)).await {
+                Ok(Ok(())) => {
+                    self.stats.last_success_ms.store(now_ms(), Ordering::Relaxed);
+                }
+                Ok(Err(e)) => {
+                    self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Revocation sync from {} failed: {}", self.upstream, e);
+                }
+                Err(_) => {
+                    self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Revocation sync from {} timed out", self.upstream);
+                }
+            }
+        }
+    }
+
+    async fn sync_once(&self, client: &Client<hyper::client::HttpConnector>) -> Result<(), String> {
+        let known = self.stats.upstream_version();
+        let request = Request::get(self.upstream.clone())
+            .header("if-none-match", format!("\"{}\"", known))
+            .body(Body::empty())
+            .map_err(|e| e.to_string())?;
+
+        let response = client.request(request).await.map_err(|e| e.to_string())?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(()),
+            StatusCode::OK => {}
+            status => return Err(format!("unexpected status {}", status)),
+        }
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| e.to_string())?;
+        let list: RevocationList = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+
+        let added = self.authority.apply_revocations(&list).map_err(|e| e.to_string())?;
+        for id in &added {
+            info!("Revoked key {} (from upstream)", id);
+        }
+        self.stats.applied.fetch_add(added.len() as u64, Ordering::Relaxed);
+        self.stats.upstream_version.store(list.version, Ordering::Relaxed);
+        debug!("Revocation list synced at upstream version {}", list.version);
+        Ok(())
+    }
+}
+
+/// Parse the version out of an `If-None-Match` tag
+pub fn parse_version_tag(tag: &str) -> Option<u64> {
+    tag.trim().trim_start_matches("W/").trim_matches('"').parse().ok()
+}
+
+/// Serve the revocation list, or 304 when `If-None-Match` names its version
+pub fn list_response(req: &Request<Body>, authenticator: &Authenticator) -> Response<Body> {
+    let list = authenticator.authority().revocation_list();
+    let etag = format!("\"{}\"", list.version);
+    let not_modified = req.headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_version_tag)
+        == Some(list.version);
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("etag", etag)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let mut response = json_response(StatusCode::OK, json!(list));
+    response.headers_mut().insert("etag", etag.parse().unwrap());
+    response
+}
+
+/// Revoke the key named by a `{"key_id": "<hex>"}` body
+///
+/// Only admin keys may, and only while the authority doesn't require a
+/// multi-signature proposal for it.
+pub async fn revoke(
+    req: Request<Body>,
+    authenticator: &Authenticator,
+    spool: &Spool,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let ctx = match timings.time(Phase::Auth, || auth::authenticate_request(authenticator, &req)) {
+        Ok(_) if authenticator.authority().requires_quorum() => {
+            return auth::error_response(&AuthError::PermissionDenied(
+                "revocation requires a multi-signature proposal at /admin/proposals".to_string(),
+            ));
+        }
+        Ok(ctx) if ctx.permissions().can_admin() => ctx,
+        Ok(ctx) => {
+            warn!("Key {} attempted revocation without admin permission", ctx.key_id());
+            return auth::error_response(&AuthError::PermissionDenied("revocation requires admin".to_string()));
+        }
+        Err(e) => return auth::error_response(&e),
+    };
+    let body_bytes = match spool.read(req.into_body(), MAX_REVOKE_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(Some(&ctx), &body_bytes, timings) {
+        return e.into_response();
+    }
+    let key_id = serde_json::from_slice::<serde_json::Value>(&body_bytes)
+        .ok()
+        .and_then(|v| v["key_id"].as_str().and_then(|id| KeyId::from_hex(id).ok()));
+    let Some(key_id) = key_id else {
+        return json_response(StatusCode::BAD_REQUEST, json!({"error": "Expected {\"key_id\": \"<hex>\"}"}));
+    };
+    let revoked = match authenticator.authority().revoke(key_id) {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            // The key is already blocked in memory; only persistence failed
+            error!("Failed to persist revocation of {}: {}", key_id, e);
+            return ApiError::internal(e.to_string()).into_response();
+        }
+    };
+    authenticator.cache().invalidate(&key_id);
+    info!("Key {} revoked by {}", key_id, ctx.display_name());
+    json_response(StatusCode::OK, json!({
+        "key_id": key_id,
+        "revoked": revoked,
+        "version": authenticator.authority().revocation_version(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_tag() {
+        assert_eq!(parse_version_tag("\"42\""), Some(42));
+        assert_eq!(parse_version_tag("W/\"7\""), Some(7));
+        assert_eq!(parse_version_tag("*"), None);
+    }
+
+    #[test]
+    fn test_invalid_upstream_rejected() {
+        let authority = Arc::new(KeyAuthority::new(wfldb_auth::generate_signing_key().verifying_key()));
+        assert!(RevocationPuller::new("http://leader:8080/", Duration::from_secs(5), authority.clone()).is_ok());
+        assert!(RevocationPuller::new("not a url", Duration::from_secs(5), authority).is_err());
+    }
+}
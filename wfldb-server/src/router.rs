@@ -0,0 +1,392 @@
+//! Request routing
+//!
+//! [`Route::resolve`] is the one table mapping a method and path to the
+//! endpoint serving it. The request handler names its diagnostics after
+//! the route and [`dispatch`] calls the route's handler, so a new endpoint
+//! is a variant, a row in the table and an arm in `dispatch`. Path parsing
+//! shared by the middleware and the response builders handlers have in
+//! common live here too.
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use wfldb_auth::AuthContext;
+use wfldb_core::{BucketId, Key};
+use wfldb_engine::Storage;
+use crate::{admin, batch_get, chunks, document, health, lease, membership, metrics, multipart, objects, revocation_sync, search, sse, txn, watch};
+use crate::error::ApiError;
+use crate::request_id;
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::RequestTimings;
+#[cfg(feature = "test-endpoints")]
+use crate::test_endpoints;
+
+/// Admin API sections, by path prefix, and their diagnostics names
+const ADMIN_SECTIONS: [(&str, &str); 12] = [
+    ("/admin/proposals", "admin_proposals"),
+    ("/admin/principals", "admin_principals"),
+    ("/admin/usage", "admin_usage"),
+    ("/admin/buckets", "admin_buckets"),
+    ("/admin/journal", "admin_journal"),
+    ("/admin/membership", "admin_membership"),
+    ("/admin/uploads", "admin_uploads"),
+    ("/admin/chaos", "admin_chaos"),
+    ("/admin/config", "admin_config"),
+    ("/admin/quarantine", "admin_quarantine"),
+    ("/admin/audit", "admin_audit"),
+    ("/admin/v1/cluster", "admin_cluster"),
+];
+
+/// Endpoint a request is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Health,
+    ClusterPing,
+    Livez,
+    Readyz,
+    Metrics,
+    DebugRuntime,
+    RevocationList,
+    RevokeKey,
+    /// Anything under `/admin/`, with the section's diagnostics name
+    Admin(&'static str),
+    Echo,
+    /// Transport test endpoints under `/_test/`
+    #[cfg(feature = "test-endpoints")]
+    Test,
+    Chunks,
+    Multipart,
+    Lease,
+    Transaction,
+    BatchGet,
+    Touch,
+    Watch,
+    Search,
+    PutObject,
+    ListBuckets,
+    ListObjects,
+    GetObject,
+    PatchDocument,
+    DeleteObject,
+    NotFound,
+}
+
+impl Route {
+    /// Route for a method and path; earlier rows win where paths overlap
+    pub fn resolve(method: &Method, path: &str) -> Route {
+        match (method, path) {
+            (&Method::GET, "/health") => Route::Health,
+            (&Method::GET, "/cluster/ping") => Route::ClusterPing,
+            (&Method::GET, "/livez") => Route::Livez,
+            (&Method::GET, "/readyz") => Route::Readyz,
+            (&Method::GET, "/metrics") => Route::Metrics,
+            (&Method::GET, "/debug/runtime") => Route::DebugRuntime,
+            (&Method::GET, "/auth/revocations") => Route::RevocationList,
+            (&Method::POST, "/auth/revocations") => Route::RevokeKey,
+            (_, path) if path.starts_with("/admin/") => {
+                let section = ADMIN_SECTIONS.iter().find(|(prefix, _)| path.starts_with(prefix));
+                Route::Admin(section.map_or("admin", |(_, name)| name))
+            }
+            (&Method::POST, "/echo") => Route::Echo,
+            #[cfg(feature = "test-endpoints")]
+            (_, path) if path.starts_with(test_endpoints::PREFIX) => Route::Test,
+            // Reserved segments under a bucket come before plain object keys
+            (_, path) if chunks::parse_chunk_path(path).is_some() => Route::Chunks,
+            (_, path) if multipart::parse_upload_path(path).is_some() => Route::Multipart,
+            (_, path) if lease::parse_lease_path(path).is_some() => Route::Lease,
+            (&Method::POST, path) if is_txn_path(path) => Route::Transaction,
+            (&Method::POST, path) if batch_get::is_mget_path(path) => Route::BatchGet,
+            (&Method::POST, path) if objects::parse_touch_path(path).is_some() => Route::Touch,
+            (&Method::GET, path) if watch::is_watch_path(path) => Route::Watch,
+            (&Method::GET, path) if search::is_search_path(path) => Route::Search,
+            (&Method::PUT, path) if path.starts_with("/v1/") => Route::PutObject,
+            (&Method::GET, "/v1" | "/v1/") => Route::ListBuckets,
+            (&Method::GET, path) if parse_bucket_path(path).is_some() => Route::ListObjects,
+            (&Method::GET, path) if path.starts_with("/v1/") => Route::GetObject,
+            (&Method::PATCH, path) if path.starts_with("/v1/") => Route::PatchDocument,
+            (&Method::DELETE, path) if path.starts_with("/v1/") => Route::DeleteObject,
+            _ => Route::NotFound,
+        }
+    }
+
+    /// Handler name used for per-handler diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Route::Health => "health",
+            Route::ClusterPing => "cluster_ping",
+            Route::Livez => "livez",
+            Route::Readyz => "readyz",
+            Route::Metrics => "metrics",
+            Route::DebugRuntime => "debug_runtime",
+            Route::RevocationList => "revocation_list",
+            Route::RevokeKey => "revoke_key",
+            Route::Admin(name) => name,
+            Route::Echo => "echo",
+            #[cfg(feature = "test-endpoints")]
+            Route::Test => "test",
+            Route::Chunks => "chunks",
+            Route::Multipart => "multipart",
+            Route::Lease => "lease",
+            Route::Transaction => "transaction",
+            Route::BatchGet => "batch_get",
+            Route::Touch => "touch",
+            Route::Watch => "watch",
+            Route::Search => "search",
+            Route::PutObject => "put_object",
+            Route::ListBuckets => "list_buckets",
+            Route::ListObjects => "list_objects",
+            Route::GetObject => "get_object",
+            Route::PatchDocument => "patch_document",
+            Route::DeleteObject => "delete_object",
+            Route::NotFound => "not_found",
+        }
+    }
+}
+
+/// Serve a request with its route's handler
+///
+/// Authentication, admission and the rest of the middleware have already
+/// run; `storage` is scoped to the caller's tenant. Endpoints switched off
+/// in this server's configuration answer 404 like unknown paths.
+pub async fn dispatch(
+    route: Route,
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let path = req.uri().path().to_string();
+    // Anywhere else a customer key would be ignored, leaving the data unencrypted
+    if !matches!(route, Route::PutObject | Route::GetObject) && sse::requested(req.headers()) {
+        return ApiError::bad_request("Customer keys are only accepted on object PUTs and GETs").into_response();
+    }
+    match route {
+        Route::Health => json_response(
+            StatusCode::OK,
+            json!({"status": "healthy", "version": "0.1.0", "service": "wfldb"}),
+        ),
+        Route::ClusterPing => match &state.cluster {
+            Some(cluster) => json_response(StatusCode::OK, json!(membership::ping_answer(state, cluster))),
+            None => ApiError::not_found("Cluster membership is disabled").into_response(),
+        },
+        Route::Livez => health::livez(state),
+        Route::Readyz => health::readyz(state).await,
+        Route::Metrics => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics::render(state)))
+            .unwrap(),
+        Route::DebugRuntime if state.debug_endpoints => json_response(StatusCode::OK, state.diagnostics.snapshot()),
+        Route::RevocationList => match &state.auth {
+            Some(authenticator) => revocation_sync::list_response(&req, authenticator),
+            None => not_found(),
+        },
+        Route::RevokeKey => match &state.auth {
+            Some(authenticator) => revocation_sync::revoke(req, authenticator, &state.spool, timings).await,
+            None => not_found(),
+        },
+        Route::Admin(_) if state.auth.is_some() => admin::handle(req, state, timings).await,
+        Route::Echo => echo(req).await,
+        #[cfg(feature = "test-endpoints")]
+        Route::Test => test_endpoints::handle(req).await,
+        Route::Chunks => match chunks::parse_chunk_path(&path) {
+            Some(Ok(route)) => chunks::handle(req, state, storage, route, auth_ctx, timings).await,
+            _ => ApiError::bad_request("Invalid chunk path. Expected /v1/{bucket}/_chunks/{hash}, /v1/{bucket}/_manifest/{key}, /v1/{bucket}/_compose/{key} or /v1/{bucket}/_range/{key}").into_response(),
+        },
+        Route::Multipart => match multipart::parse_upload_path(&path) {
+            Some(Ok((bucket_id, key))) => {
+                multipart::handle(req, state, storage, &bucket_id, &key, auth_ctx, timings).await
+            }
+            _ => ApiError::bad_request("Invalid upload path. Expected /v1/{bucket}/_uploads/{key}").into_response(),
+        },
+        Route::Lease => match lease::parse_lease_path(&path) {
+            Some(Ok((bucket_id, key))) => lease::handle(req, state, storage, &bucket_id, &key, auth_ctx, timings).await,
+            _ => ApiError::bad_request("Invalid lease path. Expected /v1/{bucket}/_lease/{key}").into_response(),
+        },
+        Route::Transaction => match parse_object_path(&path) {
+            Ok((bucket_id, _)) => txn::handle(req, state, storage, &bucket_id, auth_ctx, timings).await,
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::BatchGet => match parse_object_path(&path) {
+            Ok((bucket_id, _)) => batch_get::handle(req, state, storage, &bucket_id, auth_ctx, timings).await,
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::Touch => match objects::parse_touch_path(&path) {
+            Some(Ok((bucket_id, key))) => objects::touch(req, state, storage, &bucket_id, &key, auth_ctx, timings).await,
+            _ => ApiError::bad_request("Invalid touch path. Expected /v1/{bucket}/{key}/_touch").into_response(),
+        },
+        Route::PutObject => match parse_object_path(&path) {
+            Ok((bucket_id, key)) => objects::put(req, state, storage, &bucket_id, &key, auth_ctx, timings).await,
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::ListBuckets => objects::list_buckets(state, storage, auth_ctx, timings),
+        Route::Watch => match parse_object_path(&path) {
+            Ok((bucket_id, _)) => watch::handle(req, storage, &bucket_id, auth_ctx, timings).await,
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::Search => match parse_object_path(&path) {
+            Ok((bucket_id, _)) => search::handle(&req, state, storage, &bucket_id, auth_ctx, timings),
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::ListObjects => match parse_bucket_path(&path) {
+            Some(Ok(bucket_id)) => objects::list(&req, state, storage, &bucket_id, auth_ctx, timings),
+            _ => ApiError::bad_request("Invalid bucket name").into_response(),
+        },
+        Route::GetObject => match parse_object_path(&path) {
+            Ok((bucket_id, key)) => objects::get(&req, state, storage, &bucket_id, &key, auth_ctx, timings),
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::PatchDocument => match parse_object_path(&path) {
+            Ok((bucket_id, key)) => {
+                document::handle_patch(req, state, storage, &bucket_id, &key, auth_ctx, timings).await
+            }
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::DeleteObject => match parse_object_path(&path) {
+            Ok((bucket_id, key)) => objects::delete(&req, state, storage, &bucket_id, &key, timings),
+            Err(e) => ApiError::bad_request(e).into_response(),
+        },
+        Route::DebugRuntime | Route::Admin(_) | Route::NotFound => not_found(),
+    }
+}
+
+/// Echo endpoint for testing
+async fn echo(req: Request<Body>) -> Response<Body> {
+    match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body_bytes) => json_response(StatusCode::OK, json!({
+            "echo": String::from_utf8_lossy(&body_bytes),
+            "size": body_bytes.len(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        })),
+        Err(_) => ApiError::bad_request("Failed to read request body").into_response(),
+    }
+}
+
+/// JSON response with the given status
+pub fn json_response(status: StatusCode, mut body: Value) -> Response<Body> {
+    // Error bodies name the request so the failure can be found in the logs
+    if status.is_client_error() || status.is_server_error() {
+        if let (Value::Object(fields), Some(id)) = (&mut body, request_id::current()) {
+            fields.insert("request_id".to_string(), json!(id));
+        }
+    }
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Answer for a method an endpoint doesn't support
+pub fn method_not_allowed() -> Response<Body> {
+    json_response(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "Method not allowed"}))
+}
+
+fn not_found() -> Response<Body> {
+    ApiError::not_found("Not found").into_response()
+}
+
+/// Parse a bucket path like "/v1/bucket" or "/v1/bucket/"; `None` if it isn't one
+pub fn parse_bucket_path(path: &str) -> Option<Result<BucketId, String>> {
+    let bucket = path.strip_prefix("/v1/")?;
+    let bucket = bucket.strip_suffix('/').unwrap_or(bucket);
+    if bucket.is_empty() || bucket.contains('/') {
+        return None;
+    }
+    Some(BucketId::new(bucket).map_err(|_| "Invalid bucket name".to_string()))
+}
+
+/// Whether `path` is a bucket's transaction endpoint, "/v1/{bucket}/_txn"
+fn is_txn_path(path: &str) -> bool {
+    path.strip_prefix("/v1/")
+        .and_then(|rest| rest.split_once('/'))
+        .is_some_and(|(bucket, key)| !bucket.is_empty() && key == txn::TXN_SEGMENT)
+}
+
+/// Parse object path like "/v1/bucket/key" into bucket and key
+pub fn parse_object_path(path: &str) -> Result<(BucketId, Key), String> {
+    let parts: Vec<&str> = path.strip_prefix("/v1/")
+        .unwrap_or("")
+        .split('/')
+        .collect();
+
+    if parts.len() < 2 || parts[0].is_empty() || parts[1].is_empty() {
+        return Err("Invalid path format. Expected /v1/{bucket}/{key}".to_string());
+    }
+
+    let bucket_id = BucketId::new(parts[0])
+        .map_err(|_| "Invalid bucket name".to_string())?;
+
+    let key_part = parts[1..].join("/"); // Support nested keys
+    let key = Key::from_url(&key_part)
+        .map_err(|_| "Invalid key".to_string())?;
+
+    Ok((bucket_id, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_path() {
+        // Valid paths
+        let (bucket, key) = parse_object_path("/v1/photos/cat.jpg").unwrap();
+        assert_eq!(bucket.as_str(), "photos");
+        assert_eq!(key.as_str(), "cat.jpg");
+
+        let (bucket, key) = parse_object_path("/v1/documents/folder/file.txt").unwrap();
+        assert_eq!(bucket.as_str(), "documents");
+        assert_eq!(key.as_str(), "folder/file.txt");
+
+        // Keys are percent-decoded
+        let (_, key) = parse_object_path("/v1/photos/%C3%A9t%C3%A9%202024.jpg").unwrap();
+        assert_eq!(key.as_str(), "été 2024.jpg");
+
+        // Invalid paths
+        assert!(parse_object_path("/v1/").is_err());
+        assert!(parse_object_path("/v1/bucket/").is_err());
+        assert!(parse_object_path("/v1//key").is_err());
+        assert!(parse_object_path("/v1/bucket/bad%zz").is_err());
+    }
+
+    #[test]
+    fn test_route_name() {
+        let name = |method: &Method, path: &str| Route::resolve(method, path).name();
+        assert_eq!(name(&Method::GET, "/health"), "health");
+        assert_eq!(name(&Method::PUT, "/v1/photos/cat.jpg"), "put_object");
+        assert_eq!(name(&Method::GET, "/v1/photos/cat.jpg"), "get_object");
+        assert_eq!(name(&Method::GET, "/v1/photos/"), "list_objects");
+        assert_eq!(name(&Method::GET, "/v1"), "list_buckets");
+        assert_eq!(name(&Method::PATCH, "/v1/photos/cat.jpg"), "patch_document");
+        assert_eq!(name(&Method::OPTIONS, "/v1/photos/cat.jpg"), "not_found");
+        assert_eq!(name(&Method::POST, "/v1/photos/_txn"), "transaction");
+        assert_eq!(name(&Method::POST, "/v1/photos/_mget"), "batch_get");
+        assert_eq!(name(&Method::GET, "/v1/photos/_watch"), "watch");
+        assert_eq!(name(&Method::GET, "/v1/photos/_lease/cat.jpg"), "lease");
+        assert_eq!(name(&Method::PUT, "/v1/photos/_manifest/cat.jpg"), "chunks");
+        assert_eq!(name(&Method::PUT, "/v1/videos/_uploads/clip.mp4"), "multipart");
+        assert_eq!(name(&Method::POST, "/v1/photos/dir/_txn"), "not_found");
+        assert_eq!(name(&Method::POST, "/v1/sessions/user/42/_touch"), "touch");
+        assert_eq!(name(&Method::GET, "/v1/sessions/user/42/_touch"), "get_object");
+        assert_eq!(name(&Method::DELETE, "/admin/principals/ops"), "admin_principals");
+        assert_eq!(name(&Method::GET, "/admin/tasks"), "admin");
+    }
+
+    #[test]
+    fn test_reserved_segments_win_over_keys() {
+        assert_eq!(Route::resolve(&Method::GET, "/v1/photos/_chunks/ab"), Route::Chunks);
+        assert_eq!(Route::resolve(&Method::GET, "/v1/photos/_uploads/cat.jpg"), Route::Multipart);
+        assert_eq!(Route::resolve(&Method::DELETE, "/v1/photos/_lease/cat.jpg"), Route::Lease);
+        assert_eq!(Route::resolve(&Method::PUT, "/v1/photos/_txn"), Route::PutObject);
+    }
+
+    #[test]
+    fn test_parse_bucket_path() {
+        assert_eq!(parse_bucket_path("/v1/photos").unwrap().unwrap().as_str(), "photos");
+        assert_eq!(parse_bucket_path("/v1/photos/").unwrap().unwrap().as_str(), "photos");
+        assert!(parse_bucket_path("/v1/photos/cat.jpg").is_none());
+        assert!(parse_bucket_path("/v1/").is_none());
+        assert!(parse_bucket_path("/v1/Bad Bucket").unwrap().is_err());
+    }
+}
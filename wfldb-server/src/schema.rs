@@ -0,0 +1,121 @@
+//! JSON Schema validation of documents
+//!
+//! An admin can attach a JSON Schema to a bucket (see [`crate::admin`]).
+//! From then on, every PUT to the bucket must be a JSON document the schema
+//! accepts, whatever its content type says, and so must every value a
+//! transaction puts and every document a PATCH leaves behind. Chunk
+//! manifests and multipart uploads aren't checked. A document that doesn't
+//! match answers 422 `schema_violation`, with a `violations`
+//! list giving each failure's JSON Pointer into the document (`path`), into
+//! the schema (`schema_path`) and a `message`.
+//!
+//! Schemas are compiled once and kept until they're replaced; `$ref`s must
+//! resolve within the schema, nothing is fetched.
+
+use jsonschema::Validator;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use wfldb_core::{BucketId, WflDBError};
+use wfldb_engine::Storage;
+use crate::error::ApiError;
+
+/// Most violations listed in one error
+const MAX_REPORTED_VIOLATIONS: usize = 20;
+
+/// Compiled schemas by namespaced bucket name, `None` for buckets without one
+#[derive(Default)]
+pub struct BucketSchemas {
+    validators: RwLock<HashMap<String, Option<Arc<Validator>>>>,
+}
+
+impl BucketSchemas {
+    /// Check a schema before attaching it
+    pub fn compile(schema: &Value) -> Result<Validator, String> {
+        jsonschema::validator_for(schema).map_err(|e| format!("Invalid schema: {}", e))
+    }
+
+    /// The bucket's schema, compiled
+    pub fn validator(&self, storage: &Storage, bucket_id: &BucketId) -> Result<Option<Arc<Validator>>, WflDBError> {
+        let bucket = storage.engine().namespaced(bucket_id);
+        if let Some(validator) = self.validators.read().unwrap().get(&bucket) {
+            return Ok(validator.clone());
+        }
+        let validator = match storage.engine().bucket_schema(bucket_id)? {
+            // Checked when attached, so only a newer server's dialect fails here
+            Some(schema) => Some(Arc::new(Self::compile(&schema).map_err(WflDBError::InvalidDocument)?)),
+            None => None,
+        };
+        self.validators.write().unwrap().insert(bucket, validator.clone());
+        Ok(validator)
+    }
+
+    /// Drop a bucket's compiled schema after it changed
+    pub fn forget(&self, bucket: &str) {
+        self.validators.write().unwrap().remove(bucket);
+    }
+
+    /// Check the body of a PUT against the bucket's schema, if it has one
+    pub fn check_put(&self, storage: &Storage, bucket_id: &BucketId, data: &[u8]) -> Result<(), ApiError> {
+        let Some(validator) = self.validator(storage, bucket_id).map_err(|e| ApiError::from_storage(&e, wfldb_auth::now_ms()))? else {
+            return Ok(());
+        };
+        let document: Value = serde_json::from_slice(data).map_err(|e| ApiError::bad_request(format!("Invalid JSON: {}", e)))?;
+        match violations(&validator, &document) {
+            Some(found) => Err(ApiError::schema_violation(found)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How `document` fails the schema, or `None` if it matches
+pub fn violations(validator: &Validator, document: &Value) -> Option<Vec<Value>> {
+    let found: Vec<Value> = validator
+        .iter_errors(document)
+        .take(MAX_REPORTED_VIOLATIONS)
+        .map(|error| json!({
+            "path": error.instance_path().to_string(),
+            "schema_path": error.schema_path().to_string(),
+            "message": error.to_string(),
+        }))
+        .collect();
+    (!found.is_empty()).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_engine::StorageEngine;
+
+    #[test]
+    fn test_check_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = StorageEngine::new(dir.path()).unwrap();
+        let storage = Storage::new(engine.clone());
+        let people = BucketId::new("people").unwrap();
+        let schema = json!({"type": "object", "required": ["name"], "properties": {"age": {"minimum": 0}}});
+
+        let schemas = BucketSchemas::default();
+        assert!(schemas.check_put(&storage, &people, br#"{"age": -1}"#).is_ok());
+        engine.set_bucket_schema(&people, Some(&schema)).unwrap();
+        // Still the cached absence until the schema is forgotten
+        assert!(schemas.check_put(&storage, &people, br#"{"age": -1}"#).is_ok());
+        schemas.forget("people");
+
+        assert!(schemas.check_put(&storage, &people, br#"{"name": "Ada", "age": 36}"#).is_ok());
+        assert!(schemas.check_put(&storage, &people, b"not json").is_err());
+
+        let validator = schemas.validator(&storage, &people).unwrap().unwrap();
+        let found = violations(&validator, &json!({"age": -1})).unwrap();
+        let paths: Vec<&str> = found.iter().map(|v| v["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["", "/age"]);
+        assert_eq!(found[1]["schema_path"], "/properties/age/minimum");
+    }
+
+    #[test]
+    fn test_compile_rejects_invalid_schema() {
+        assert!(BucketSchemas::compile(&json!({"type": 5})).is_err());
+        assert!(BucketSchemas::compile(&json!({"$ref": "https://example.com/schema.json"})).is_err());
+        assert!(BucketSchemas::compile(&json!(true)).is_ok());
+    }
+}
@@ -0,0 +1,503 @@
+//! Full-text search over small text and JSON objects
+//!
+//! ```text
+//! GET /v1/{bucket}/_search?q=<query>[&limit=N][&offset=M]
+//! ```
+//!
+//! Buckets named with `--search-index` (namespaced like journal records)
+//! get an inverted index under `search/` in the data directory. Writes
+//! through `/v1` mark the keys they touch, and the indexer picks them up on
+//! its next pass, so a write shows up in results within about a second.
+//! An index that doesn't exist yet is built from the whole bucket first.
+//! Inline objects are indexed: the string values of JSON documents, or the
+//! whole object if it's other UTF-8 text. Chunked and binary objects aren't.
+//!
+//! Queries use tantivy's syntax (`error AND timeout`, `"exact phrase"`,
+//! `-excluded`, `pay*`); bare terms must all match. Hits are ranked by
+//! relevance and carry only the key and score. The caller needs read and
+//! list permission on the bucket, and sees only keys it could list, objects
+//! it may read under an owner-only ACL, and no quarantined objects; with
+//! auth on, anonymous searches are refused even on public-read buckets.
+
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{BooleanQuery, ConstScoreQuery, Occur, Query, QueryParser, RegexQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, TantivyDocument, Value as _, STORED, STRING, TEXT};
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, Term};
+use tracing::{info, warn};
+use wfldb_auth::{now_ms, AuthContext, AuthError, BucketAcl};
+use wfldb_core::{BucketId, Key, ObjectMetadata, WflDBError};
+use wfldb_engine::{Storage, StorageEngine};
+use wfldb_net::query_param;
+use crate::error::ApiError;
+use crate::health::TaskHeartbeats;
+use crate::quarantine;
+use crate::router::{json_response, parse_object_path, Route};
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Last path segment of a search
+pub const SEARCH_SEGMENT: &str = "_search";
+
+const HEARTBEAT_TASK: &str = "search_index";
+
+/// Interval between indexer passes
+const INDEX_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Memory each index writer buffers before flushing a segment
+const WRITER_HEAP_BYTES: usize = 32 * 1024 * 1024;
+
+/// Keys read per batch while building an index from scratch
+const BUILD_BATCH: usize = 1000;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+/// File marking an index as fully built from its bucket
+const BUILT_MARKER: &str = "built";
+
+/// Owner term of objects written without a key
+const NO_OWNER: &str = "-";
+
+#[derive(Clone, Copy)]
+struct Fields {
+    key: Field,
+    owner: Field,
+    body: Field,
+}
+
+fn schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+    let fields = Fields {
+        key: builder.add_text_field("key", STRING | STORED),
+        owner: builder.add_text_field("owner", STRING),
+        body: builder.add_text_field("body", TEXT),
+    };
+    (builder.build(), fields)
+}
+
+/// One bucket's index
+struct BucketIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+    /// Not built from the bucket yet
+    needs_build: bool,
+    path: PathBuf,
+}
+
+/// Search indexes of the buckets that have one, and the keys written since
+/// the indexer's last pass
+pub struct SearchIndexes {
+    indexes: HashMap<String, BucketIndex>,
+    pending: Mutex<HashMap<String, BTreeSet<String>>>,
+    indexed: AtomicU64,
+}
+
+impl SearchIndexes {
+    /// Open (or create) the indexes of `buckets` under `dir`
+    pub fn open(dir: &Path, buckets: &[String]) -> Result<Self, String> {
+        let mut indexes = HashMap::new();
+        for bucket in buckets {
+            let path = dir.join(index_dir_name(bucket));
+            let needs_build = !path.join(BUILT_MARKER).exists();
+            std::fs::create_dir_all(&path).map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+            let (schema, fields) = schema();
+            let open = || -> tantivy::Result<(Index, IndexReader, IndexWriter)> {
+                let index = Index::open_or_create(tantivy::directory::MmapDirectory::open(&path)?, schema)?;
+                let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into()?;
+                let writer = index.writer(WRITER_HEAP_BYTES)?;
+                Ok((index, reader, writer))
+            };
+            let (index, reader, writer) = open().map_err(|e| format!("failed to open search index of '{}': {}", bucket, e))?;
+            indexes.insert(bucket.clone(), BucketIndex { index, reader, writer: Mutex::new(writer), fields, needs_build, path });
+        }
+        Ok(SearchIndexes { indexes, pending: Mutex::new(HashMap::new()), indexed: AtomicU64::new(0) })
+    }
+
+    /// Note that a write changed `key` in the bucket with namespaced name `bucket`
+    pub fn changed(&self, bucket: &str, key: &Key) {
+        if self.indexes.contains_key(bucket) {
+            self.pending.lock().unwrap().entry(bucket.to_string()).or_default().insert(key.as_str().to_string());
+        }
+    }
+
+    pub fn is_indexed(&self, bucket: &str) -> bool {
+        self.indexes.contains_key(bucket)
+    }
+
+    /// Objects (re)indexed or removed since the server started
+    pub fn indexed(&self) -> u64 {
+        self.indexed.load(Ordering::Relaxed)
+    }
+
+    /// Keys marked but not yet indexed
+    pub fn backlog(&self) -> usize {
+        self.pending.lock().unwrap().values().map(BTreeSet::len).sum()
+    }
+
+    /// Bring one key's document up to date with the object
+    fn reindex(&self, engine: &StorageEngine, bucket: &str, key: &str, writer: &IndexWriter) -> Result<(), WflDBError> {
+        let index = &self.indexes[bucket];
+        let (scoped, bucket_id) = engine.resolve_namespaced(bucket)?;
+        let storage = Storage::new(scoped);
+        let key = Key::new(key)?;
+        writer.delete_term(Term::from_field_text(index.fields.key, key.as_str()));
+        self.indexed.fetch_add(1, Ordering::Relaxed);
+        // Encrypted objects can't be read without their customer key
+        let indexable = |metadata: &ObjectMetadata| !metadata.is_chunked() && metadata.customer_key_hash.is_none();
+        let Some(metadata) = storage.get_metadata(&bucket_id, &key)?.filter(indexable) else {
+            return Ok(());
+        };
+        let Some(text) = storage.get_object(&bucket_id, &key)?.and_then(|data| indexable_text(&data)) else {
+            return Ok(());
+        };
+        let mut document = TantivyDocument::default();
+        document.add_text(index.fields.key, key.as_str());
+        document.add_text(index.fields.owner, metadata.owner.as_deref().unwrap_or(NO_OWNER));
+        document.add_text(index.fields.body, &text);
+        writer.add_document(document).map_err(WflDBError::storage)?;
+        Ok(())
+    }
+
+    /// Index every object of a bucket whose index is new
+    fn build(&self, engine: &StorageEngine, bucket: &str) -> Result<(), WflDBError> {
+        let index = &self.indexes[bucket];
+        let (scoped, bucket_id) = engine.resolve_namespaced(bucket)?;
+        let storage = Storage::new(scoped);
+        let writer = index.writer.lock().unwrap();
+        let mut start_after: Option<String> = None;
+        loop {
+            let (keys, _) = storage.list_objects_at(&bucket_id, "", start_after.as_deref(), Some(BUILD_BATCH))?;
+            for key in &keys {
+                self.reindex(engine, bucket, key.as_str(), &writer)?;
+            }
+            match keys.last() {
+                Some(last) if keys.len() == BUILD_BATCH => start_after = Some(last.as_str().to_string()),
+                _ => break,
+            }
+        }
+        drop(writer);
+        commit(index)?;
+        std::fs::write(index.path.join(BUILT_MARKER), b"")?;
+        info!("Built search index of '{}'", bucket);
+        Ok(())
+    }
+
+    /// Index the keys written since the last pass and make them searchable
+    fn apply_pending(&self, engine: &StorageEngine) -> Result<(), WflDBError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for (bucket, keys) in pending {
+            let index = &self.indexes[&bucket];
+            let writer = index.writer.lock().unwrap();
+            for key in &keys {
+                self.reindex(engine, &bucket, key, &writer)?;
+            }
+            drop(writer);
+            commit(index)?;
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` keys matching `query` from `offset` on, with the total
+    /// number of matches
+    fn search(&self, bucket: &str, query: &str, filter: &Visibility, limit: usize, offset: usize) -> Result<(usize, Vec<(String, f32)>), ApiError> {
+        let index = &self.indexes[bucket];
+        let mut parser = QueryParser::for_index(&index.index, vec![index.fields.body]);
+        parser.set_conjunction_by_default();
+        let parsed = parser.parse_query(query).map_err(|e| ApiError::bad_request(format!("Invalid query: {}", e)))?;
+        let query = filter.apply(parsed, index.fields)?;
+
+        let searcher = index.reader.searcher();
+        let (total, top) = searcher
+            .search(&query, &(Count, TopDocs::with_limit(limit).and_offset(offset)))
+            .map_err(|e| ApiError::internal(e.to_string()))?;
+        let hits = top
+            .into_iter()
+            .filter_map(|(score, address)| {
+                let document: TantivyDocument = searcher.doc(address).ok()?;
+                Some((document.get_first(index.fields.key)?.as_str()?.to_string(), score))
+            })
+            .collect();
+        Ok((total, hits))
+    }
+}
+
+fn commit(index: &BucketIndex) -> Result<(), WflDBError> {
+    index.writer.lock().unwrap().commit().map_err(WflDBError::storage)?;
+    index.reader.reload().map_err(WflDBError::storage)
+}
+
+/// Directory holding the indexes, next to the keyspace
+pub fn index_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("search")
+}
+
+/// Directory of a bucket's index; `#` separates tenants and is kept out of paths
+fn index_dir_name(bucket: &str) -> String {
+    bucket.replace(wfldb_engine::TENANT_SEPARATOR, "@")
+}
+
+/// The text to index in an object, if it's text at all
+fn indexable_text(data: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(data).ok()?;
+    match serde_json::from_str::<Value>(text) {
+        Ok(document) => {
+            let mut strings = Vec::new();
+            collect_strings(&document, &mut strings);
+            Some(strings.join("\n"))
+        }
+        Err(_) => Some(text.to_string()),
+    }
+}
+
+fn collect_strings<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+    match value {
+        Value::String(s) => out.push(s),
+        Value::Array(items) => items.iter().for_each(|item| collect_strings(item, out)),
+        Value::Object(fields) => fields.values().for_each(|field| collect_strings(field, out)),
+        _ => {}
+    }
+}
+
+/// What a caller may see of a bucket's keys
+#[derive(Debug, Default)]
+struct Visibility {
+    /// Key prefixes the caller may list; `None` means any
+    prefixes: Option<Vec<String>>,
+    /// Key id of a caller confined to its own objects
+    owner: Option<String>,
+    /// Quarantined keys
+    hidden: Vec<String>,
+}
+
+impl Visibility {
+    fn apply(&self, query: Box<dyn Query>, fields: Fields) -> Result<Box<dyn Query>, ApiError> {
+        // Filters must match but leave the ranking to the query
+        let filter = |query: BooleanQuery| -> Box<dyn Query> { Box::new(ConstScoreQuery::new(Box::new(query), 0.0)) };
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        if let Some(prefixes) = &self.prefixes {
+            let mut any = Vec::new();
+            for prefix in prefixes {
+                let pattern = format!("{}.*", regex_escape(prefix));
+                let query = RegexQuery::from_pattern(&pattern, fields.key).map_err(|e| ApiError::internal(e.to_string()))?;
+                any.push((Occur::Should, Box::new(query) as Box<dyn Query>));
+            }
+            clauses.push((Occur::Must, filter(BooleanQuery::new(any))));
+        }
+        if let Some(owner) = &self.owner {
+            let term = |owner: &str| -> Box<dyn Query> {
+                Box::new(TermQuery::new(Term::from_field_text(fields.owner, owner), IndexRecordOption::Basic))
+            };
+            clauses.push((Occur::Must, filter(BooleanQuery::new(vec![(Occur::Should, term(owner)), (Occur::Should, term(NO_OWNER))]))));
+        }
+        for key in &self.hidden {
+            let term = Term::from_field_text(fields.key, key);
+            clauses.push((Occur::MustNot, Box::new(TermQuery::new(term, IndexRecordOption::Basic))));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+}
+
+/// Escape the regex syntax in a literal key prefix
+fn regex_escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if "\\.+*?()|[]{}^$#&-~\"<>@".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether a path is a bucket's search endpoint
+pub fn is_search_path(path: &str) -> bool {
+    path.strip_prefix("/v1/")
+        .and_then(|rest| rest.split_once('/'))
+        .is_some_and(|(bucket, segment)| !bucket.is_empty() && segment == SEARCH_SEGMENT)
+}
+
+/// Object a write request may have changed, to re-index
+pub fn changed_object(route: Route, method: &hyper::Method, path: &str, query: Option<&str>) -> Option<(BucketId, Key)> {
+    match route {
+        Route::DeleteObject => parse_object_path(path).ok(),
+        _ => quarantine::written_object(route, method, path, query),
+    }
+}
+
+/// Handle `GET /v1/{bucket}/_search`; read permission was already checked
+pub fn handle(
+    req: &Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let bucket = storage.engine().namespaced(bucket_id);
+    let Some(indexes) = state.search.as_ref().filter(|indexes| indexes.is_indexed(&bucket)) else {
+        return ApiError::not_found("Bucket has no search index").into_response();
+    };
+    let query_string = req.uri().query().unwrap_or_default();
+    let Some(query) = query_param(query_string, "q").filter(|q| !q.trim().is_empty()) else {
+        return ApiError::bad_request("Missing query parameter 'q'").into_response();
+    };
+    let limit = query_param(query_string, "limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+    let offset = query_param(query_string, "offset").and_then(|o| o.parse::<usize>().ok()).unwrap_or(0);
+
+    if state.auth.is_some() && auth_ctx.is_none() {
+        let denied = AuthError::PermissionDenied(format!("anonymous search in bucket '{}'", bucket_id.as_str()));
+        return crate::auth::error_response(&denied);
+    }
+    let mut filter = Visibility::default();
+    let admin = auth_ctx.is_some_and(|ctx| ctx.permissions().can_admin());
+    if let (Some(authenticator), Some(ctx), false) = (&state.auth, auth_ctx, admin) {
+        // Results name keys, so searching takes the same right as listing
+        let permissions = ctx.permissions();
+        let prefixes = permissions.list_prefixes.clone().unwrap_or_else(|| vec![String::new()]);
+        if !prefixes.iter().any(|prefix| permissions.can_list(bucket_id, prefix)) {
+            let denied = AuthError::PermissionDenied(format!("search in bucket '{}'", bucket_id.as_str()));
+            return crate::auth::error_response(&denied);
+        }
+        filter.prefixes = permissions.list_prefixes.clone();
+        if authenticator.bucket_acls().get(bucket_id) == BucketAcl::OwnerOnly && ctx.tenant().is_none() {
+            filter.owner = Some(ctx.key_id().to_string());
+        }
+    }
+    if state.quarantine.is_some() && !admin {
+        match storage.quarantined_keys(bucket_id, "") {
+            Ok(held) => filter.hidden = held,
+            Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+        }
+    }
+
+    let result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        indexes.search(&bucket, &query, &filter, limit, offset)
+    });
+    match result {
+        Ok((total, hits)) => {
+            let next_offset = (offset + hits.len() < total).then_some(offset + hits.len());
+            json_response(StatusCode::OK, json!({
+                "bucket": bucket_id.as_str(),
+                "query": query,
+                "total": total,
+                "hits": hits.iter().map(|(key, score)| json!({"key": key, "score": score})).collect::<Vec<_>>(),
+                "next_offset": next_offset,
+            }))
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Keeps the search indexes up to date with writes
+pub struct SearchIndexer {
+    engine: StorageEngine,
+    indexes: Arc<SearchIndexes>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl SearchIndexer {
+    pub fn new(engine: StorageEngine, indexes: Arc<SearchIndexes>) -> Self {
+        SearchIndexer { engine, indexes, heartbeats: None }
+    }
+
+    /// Report each pass to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        heartbeats.register(HEARTBEAT_TASK, INDEX_INTERVAL);
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Build new indexes, then index writes until the task is dropped
+    pub async fn run(self) {
+        let (engine, indexes) = (self.engine.clone(), self.indexes.clone());
+        let built = tokio::task::spawn_blocking(move || {
+            for (bucket, index) in &indexes.indexes {
+                if index.needs_build {
+                    indexes.build(&engine, bucket)?;
+                }
+            }
+            Ok::<_, WflDBError>(())
+        });
+        if let Ok(Err(e)) | Err(e) = built.await.map_err(|e| WflDBError::internal(e.to_string())) {
+            warn!("Building search indexes failed: {}", e);
+        }
+
+        let mut ticker = tokio::time::interval(INDEX_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Some(heartbeats) = &self.heartbeats {
+                heartbeats.beat(HEARTBEAT_TASK);
+            }
+            let (engine, indexes) = (self.engine.clone(), self.indexes.clone());
+            match tokio::task::spawn_blocking(move || indexes.apply_pending(&engine)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Search indexing failed: {}", e),
+                Err(e) => warn!("Search indexing failed: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexable_text() {
+        assert_eq!(indexable_text(br#"{"title": "Hello", "tags": ["a", "b"], "n": 1}"#).unwrap(), "a\nb\nHello");
+        assert_eq!(indexable_text(b"plain notes").unwrap(), "plain notes");
+        assert!(indexable_text(b"\xff\xfe").is_none());
+    }
+
+    #[test]
+    fn test_search_filters_what_caller_may_see() {
+        let data = tempfile::tempdir().unwrap();
+        let engine = StorageEngine::new(data.path().join("db")).unwrap();
+        let storage = Storage::new(engine.clone());
+        let notes = BucketId::new("notes").unwrap();
+        let put = |key: &str, body: &str, owner: &str| {
+            storage.put_object_with_checksums_as(&notes, &Key::new(key).unwrap(), body.as_bytes(), Some(owner), Default::default()).unwrap();
+        };
+        put("team/plan", r#"{"text": "launch timeout fix"}"#, "alice");
+        put("team/retro", "the timeout was the database", "bob");
+        put("private/diary", "timeout again", "alice");
+
+        let indexes = SearchIndexes::open(&data.path().join("search"), &["notes".to_string()]).unwrap();
+        indexes.build(&engine, "notes").unwrap();
+        let keys = |filter: &Visibility, query: &str| -> Vec<String> {
+            let (_, hits) = indexes.search("notes", query, filter, 10, 0).unwrap();
+            let mut keys: Vec<String> = hits.into_iter().map(|(key, _)| key).collect();
+            keys.sort();
+            keys
+        };
+        let everyone = Visibility::default();
+        assert_eq!(keys(&everyone, "timeout"), ["private/diary", "team/plan", "team/retro"]);
+        assert_eq!(keys(&everyone, "timeout database"), ["team/retro"]);
+
+        let scoped = Visibility { prefixes: Some(vec!["team/".to_string()]), owner: Some("alice".to_string()), ..Default::default() };
+        assert_eq!(keys(&scoped, "timeout"), ["team/plan"]);
+        let held = Visibility { hidden: vec!["team/plan".to_string()], ..Default::default() };
+        assert_eq!(keys(&held, "launch OR database"), ["team/retro"]);
+
+        // Writes show up once the indexer has applied them
+        storage.delete_object(&notes, &Key::new("team/retro").unwrap()).unwrap();
+        indexes.changed("notes", &Key::new("team/retro").unwrap());
+        indexes.apply_pending(&engine).unwrap();
+        assert_eq!(keys(&everyone, "database"), Vec::<String>::new());
+        assert!(indexes.search("notes", "title:(", &everyone, 10, 0).is_err());
+    }
+}
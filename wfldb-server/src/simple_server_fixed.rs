@@ -1,36 +1,605 @@
 //! Simplified HTTP server for Phase 0 spike - Fixed for hyper 0.14
 
-use hyper::{Body, Request, Response, Server, Method, StatusCode};
+use hyper::{Body, Request, Response, Server, Method};
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::convert::Infallible;
-use std::sync::Arc;
-use tracing::{error, info, debug};
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, debug, warn, Instrument};
 use wfldb_core::*;
-use wfldb_engine::{StorageEngine, Storage};
+use wfldb_engine::{purge_expired_leases, purge_expired_objects, purge_stale_uploads, purge_tombstones, DANGLING_UPLOAD_AGE, warm_up, AuditEvent, AuditKind, AuditLog, HotKeyTracker, StorageEngine, Storage, UsageTracker};
+use wfldb_auth::{now_ms, AuthError, Authenticator, BucketAcl, ProposalBook, RequestSigner, ServerIdentity};
+use wfldb_net::query_param;
+use crate::{audit, auth, bandwidth, chunks, kubernetes, lease, objects, reconfig, replica, request_id, router};
+use crate::audit::AuditFlush;
+use crate::health::{HealthConfig, TaskHeartbeats};
+use crate::kubernetes::{LeaderElection, LeaderElector};
+use crate::diagnostics::RuntimeDiagnostics;
+use crate::memory::MemoryGuard;
+use crate::membership::{ClusterMap, MembershipProber};
+use crate::pools::{PoolConfig, RequestPools};
+use crate::anti_entropy::{AntiEntropy, AntiEntropyStats, MerkleCache};
+use crate::bandwidth::{Bandwidth, BandwidthConfig, Direction};
+use crate::archive::{ArchiveDestination, ArchiveStats, JournalArchiver};
+use crate::replica::{JournalFollower, ReadRequirement, ReplicaState};
+use crate::revocation_sync::{RevocationPuller, RevocationSyncStats};
+use crate::slo::{SloConfig, SloTracker};
+use crate::slow_log::{Phase, RequestTimings, SlowRequestLog};
+use crate::spool::Spool;
+use crate::chaos::Chaos;
+use crate::schema::BucketSchemas;
+use crate::search::{self, SearchIndexer, SearchIndexes};
+use crate::quarantine::{self, Holds, QuarantineConfig, QuarantineScanner, QuarantineStats};
+use crate::checksum::ChecksumConfig;
+use crate::compression::CompressionConfig;
+use crate::concurrency::ConcurrencyLimits;
+use crate::connection::{time_body, Connection, ConnectionLimits, ConnectionStats, Incoming, TrackedBody};
+use crate::cors::CorsConfig;
+use crate::error::ApiError;
+use crate::reconfig::{Reload, RuntimeSettings};
+use crate::response_cache::ResponseCache;
+use crate::router::{parse_bucket_path, parse_object_path, Route};
+use crate::transform::TransformRegistry;
+use crate::transport::Http2Config;
+use crate::tasks::{BackgroundTask, RefcountCheck, TaskContext, TaskManager, DEFAULT_MAX_RUNNING};
+
+/// State shared by all connections
+pub struct ServerState {
+    pub storage: StorageEngine,
+    /// Object storage over the shared namespace, reused by every request
+    pub objects: Storage,
+    pub slow_log: SlowRequestLog,
+    pub diagnostics: RuntimeDiagnostics,
+    pub debug_endpoints: bool,
+    pub memory_guard: MemoryGuard,
+    pub auth: Option<Authenticator>,
+    pub revocation_sync: Option<Arc<RevocationSyncStats>>,
+    pub archive: Option<Arc<ArchiveStats>>,
+    /// Set when this node is a read replica following a leader
+    pub replica: Option<Arc<ReplicaState>>,
+    pub anti_entropy: Option<Arc<AntiEntropyStats>>,
+    /// Peers and their health, when cluster membership is on
+    pub cluster: Option<Arc<ClusterMap>>,
+    /// Trees built for replicas comparing their buckets with this node's
+    pub merkle: Arc<MerkleCache>,
+    pub proposals: ProposalBook,
+    pub usage: Option<Arc<UsageTracker>>,
+    pub heartbeats: Arc<TaskHeartbeats>,
+    pub health: HealthConfig,
+    pub slo: SloTracker,
+    /// Buckets refusing writes while a migration cuts over to another node
+    pub frozen_buckets: RwLock<HashSet<BucketId>>,
+    pub pools: RequestPools,
+    pub concurrency: ConcurrencyLimits,
+    /// Recently read objects, saved for the next start's warm-up
+    pub hot_keys: Option<Arc<HotKeyTracker>>,
+    pub tasks: TaskManager,
+    pub refcounts: Arc<RefcountCheck>,
+    pub transforms: TransformRegistry,
+    pub response_cache: Option<ResponseCache>,
+    /// Per-key and per-bucket bandwidth limits; none unless configured
+    pub bandwidth: Arc<Bandwidth>,
+    pub compression: CompressionConfig,
+    pub checksums: ChecksumConfig,
+    /// Compiled JSON Schemas of buckets that validate their documents
+    pub schemas: BucketSchemas,
+    pub cors: Option<CorsConfig>,
+    /// Faults admins can inject for drills, when the server allows them
+    pub chaos: Option<Arc<Chaos>>,
+    /// Scans of held uploads, when new objects are quarantined
+    pub quarantine: Option<Arc<QuarantineStats>>,
+    /// Full-text indexes of the buckets that have one
+    pub search: Option<Arc<SearchIndexes>>,
+    /// Set with `--audit-log`
+    pub audit: Option<Arc<AuditLog>>,
+    pub identity: Option<ServerIdentity>,
+    /// Set once shutdown starts, failing readiness while requests drain
+    pub draining: Arc<AtomicBool>,
+    /// Set with `--leader-election-lease`; only the lease holder takes writes
+    pub election: Option<Arc<LeaderElection>>,
+    /// Open connections and those closed for timing out
+    pub connections: Arc<ConnectionStats>,
+    /// Buffers whole request bodies, spilling large ones to disk
+    pub spool: Spool,
+    /// Largest body an object PUT takes
+    pub max_object_size: usize,
+}
 
 pub struct SimpleServer {
     storage: StorageEngine,
+    slow_log: SlowRequestLog,
+    debug_endpoints: bool,
+    memory_limit: Option<usize>,
+    auth: Option<Authenticator>,
+    revocation_upstream: Option<(String, Duration)>,
+    journal_archive: Option<(ArchiveDestination, Duration)>,
+    replica_of: Option<(String, Duration, RequestSigner)>,
+    replica_max_lag: Option<Duration>,
+    tombstone_retention: Duration,
+    upload_expiry: Duration,
+    anti_entropy_interval: Option<Duration>,
+    membership: Option<(String, Vec<String>, Duration)>,
+    health: HealthConfig,
+    slo: SloConfig,
+    http2: Http2Config,
+    connection_limits: ConnectionLimits,
+    spool: Spool,
+    max_object_size: usize,
+    pools: PoolConfig,
+    warmup_keys: Option<usize>,
+    max_background_tasks: usize,
+    transforms: TransformRegistry,
+    response_cache: Option<ResponseCache>,
+    bandwidth: Option<BandwidthConfig>,
+    compression: CompressionConfig,
+    checksums: ChecksumConfig,
+    cors: Option<CorsConfig>,
+    chaos: bool,
+    quarantine: Option<QuarantineConfig>,
+    search_buckets: Vec<String>,
+    audit_retention: Option<Duration>,
+    identity: Option<ServerIdentity>,
+    drain_delay: Duration,
+    leader_election: Option<(String, String, Duration)>,
+    reload: Option<Reload>,
 }
 
 impl SimpleServer {
     pub fn new(storage: StorageEngine) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            slow_log: SlowRequestLog::disabled(),
+            debug_endpoints: false,
+            memory_limit: None,
+            auth: None,
+            revocation_upstream: None,
+            journal_archive: None,
+            replica_of: None,
+            replica_max_lag: None,
+            tombstone_retention: DEFAULT_TOMBSTONE_RETENTION,
+            upload_expiry: DEFAULT_UPLOAD_EXPIRY,
+            anti_entropy_interval: None,
+            membership: None,
+            health: HealthConfig::default(),
+            slo: SloConfig::default(),
+            http2: Http2Config::default(),
+            connection_limits: ConnectionLimits::default(),
+            spool: Spool::default(),
+            max_object_size: objects::DEFAULT_MAX_OBJECT_SIZE,
+            pools: PoolConfig::default(),
+            warmup_keys: None,
+            max_background_tasks: DEFAULT_MAX_RUNNING,
+            transforms: TransformRegistry::default(),
+            response_cache: None,
+            bandwidth: None,
+            compression: CompressionConfig::default(),
+            checksums: ChecksumConfig::default(),
+            cors: None,
+            chaos: false,
+            quarantine: None,
+            search_buckets: Vec::new(),
+            audit_retention: None,
+            identity: None,
+            drain_delay: Duration::ZERO,
+            leader_election: None,
+            reload: None,
+        }
+    }
+
+    /// Log requests slower than the slow log's threshold
+    pub fn with_slow_log(mut self, slow_log: SlowRequestLog) -> Self {
+        self.slow_log = slow_log;
+        self
+    }
+
+    /// Expose the /debug/* endpoints
+    pub fn with_debug_endpoints(mut self, enabled: bool) -> Self {
+        self.debug_endpoints = enabled;
+        self
+    }
+
+    /// Shed requests while the heap is above `limit_bytes`
+    pub fn with_memory_limit(mut self, limit_bytes: Option<usize>) -> Self {
+        self.memory_limit = limit_bytes;
+        self
+    }
+
+    /// Require signed requests on the /v1/ object endpoints
+    pub fn with_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Pull the revocation list from an upstream node every `interval`
+    pub fn with_revocation_upstream(mut self, upstream: String, interval: Duration) -> Self {
+        self.revocation_upstream = Some((upstream, interval));
+        self
+    }
+
+    /// Ship sealed journal segments to `destination` every `interval`
+    pub fn with_journal_archive(mut self, destination: ArchiveDestination, interval: Duration) -> Self {
+        self.journal_archive = Some((destination, interval));
+        self
+    }
+
+    /// Serve reads as a replica of `leader`, applying its change journal
+    /// every `interval` with journal reads signed by `signer`
+    pub fn with_replica_of(mut self, leader: String, interval: Duration, signer: RequestSigner) -> Self {
+        self.replica_of = Some((leader, interval, signer));
+        self
+    }
+
+    /// On a replica, stop serving reads and fail readiness while the leader
+    /// was last matched longer than `max_lag` ago
+    pub fn with_replica_max_lag(mut self, max_lag: Duration) -> Self {
+        self.replica_max_lag = Some(max_lag);
+        self
+    }
+
+    /// On a replica, compare every bucket with the leader's every
+    /// `interval` and repair what differs
+    pub fn with_anti_entropy(mut self, interval: Duration) -> Self {
+        self.anti_entropy_interval = Some(interval);
+        self
+    }
+
+    /// Join the cluster as `advertise_url`, probing `peers` and every member
+    /// they know of each `interval`
+    pub fn with_membership(mut self, advertise_url: String, peers: Vec<String>, interval: Duration) -> Self {
+        self.membership = Some((advertise_url, peers, interval));
+        self
+    }
+
+    /// Keep delete tombstones for `retention`; replicas further behind than
+    /// that must be re-seeded
+    pub fn with_tombstone_retention(mut self, retention: Duration) -> Self {
+        self.tombstone_retention = retention;
+        self
+    }
+
+    /// Abort multipart uploads left unfinished for longer than `expiry`
+    pub fn with_upload_expiry(mut self, expiry: Duration) -> Self {
+        self.upload_expiry = expiry;
+        self
+    }
+
+    /// Hold the /v1 API to these objectives in the cluster status report
+    pub fn with_slo(mut self, slo: SloConfig) -> Self {
+        self.slo = slo;
+        self
+    }
+
+    /// Pace request and response bodies to per-key and per-bucket limits
+    pub fn with_bandwidth(mut self, config: BandwidthConfig) -> Self {
+        self.bandwidth = Some(config);
+        self
+    }
+
+    /// Tune HTTP/2 flow control and stream limits
+    pub fn with_http2(mut self, http2: Http2Config) -> Self {
+        self.http2 = http2;
+        self
+    }
+
+    /// Bound how long connections may sit idle or send slowly
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connection_limits = limits;
+        self
+    }
+
+    /// Hold up to `memory_threshold` bytes of a buffered body in memory,
+    /// spilling bodies past it to a file in `dir`
+    pub fn with_body_spool(mut self, memory_threshold: usize, dir: PathBuf) -> Self {
+        self.spool = Spool::new(memory_threshold, dir);
+        self
+    }
+
+    /// Refuse object PUTs with bodies over `max_object_size` bytes
+    pub fn with_max_object_size(mut self, max_object_size: usize) -> Self {
+        self.max_object_size = max_object_size;
+        self
+    }
+
+    /// Size the per-bucket and per-tenant request pools
+    pub fn with_pools(mut self, pools: PoolConfig) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    /// Track the `keys` most recently read objects and read them back on startup
+    pub fn with_warmup(mut self, keys: usize) -> Self {
+        self.warmup_keys = Some(keys);
+        self
+    }
+
+    /// Read transforms GET requests may ask for
+    pub fn with_transforms(mut self, transforms: TransformRegistry) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Cache up to `max_bytes` of GET bodies in at most `max_entries` entries
+    pub fn with_response_cache(mut self, max_bytes: usize, max_entries: usize) -> Self {
+        self.response_cache = Some(ResponseCache::new(max_bytes, max_entries));
+        self
+    }
+
+    /// Which GET responses are compressed for clients that accept it
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Which buckets keep CRC32C or SHA-256 checksums of every PUT
+    pub fn with_checksums(mut self, checksums: ChecksumConfig) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Let browser apps on other origins call the API
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Let at most `max` background tasks run at once
+    pub fn with_max_background_tasks(mut self, max: usize) -> Self {
+        self.max_background_tasks = max;
+        self
+    }
+
+    /// Let admins inject faults into /v1 requests through /admin/chaos
+    pub fn with_chaos(mut self, enabled: bool) -> Self {
+        self.chaos = enabled;
+        self
+    }
+
+    /// Hold new objects back from non-admin readers until a hook approves them
+    pub fn with_quarantine(mut self, config: QuarantineConfig) -> Self {
+        self.quarantine = Some(config);
+        self
+    }
+
+    /// Record /v1 and /admin requests in the audit log, keeping them for `retention`
+    pub fn with_audit_log(mut self, retention: Duration) -> Self {
+        self.audit_retention = Some(retention);
+        self
+    }
+
+    /// Key this server signs audit exports with
+    pub fn with_identity(mut self, identity: ServerIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Keep full-text indexes of these buckets, by namespaced name
+    pub fn with_search_indexes(mut self, buckets: Vec<String>) -> Self {
+        self.search_buckets = buckets;
+        self
+    }
+
+    /// Report not-ready on /readyz when the data directory has less free space
+    pub fn with_min_free_disk(mut self, bytes: u64) -> Self {
+        self.health.min_free_disk_bytes = bytes;
+        self
+    }
+
+    /// Keep serving this long after a shutdown signal, with readiness failing
+    pub fn with_drain_delay(mut self, delay: Duration) -> Self {
+        self.drain_delay = delay;
+        self
+    }
+
+    /// Take writes only while holding the Kubernetes Lease `NAMESPACE/NAME`
+    pub fn with_leader_election(mut self, lease: String, identity: String, lease_duration: Duration) -> Self {
+        self.leader_election = Some((lease, identity, lease_duration));
+        self
+    }
+
+    /// Apply the settings `reload` returns on every SIGHUP
+    pub fn with_reload(
+        mut self,
+        reload: impl Fn() -> std::result::Result<RuntimeSettings, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
     }
 
     pub async fn serve(self, addr: SocketAddr) -> std::result::Result<(), Box<dyn std::error::Error>> {
-        let storage = Arc::new(self.storage);
-        
-        let make_svc = make_service_fn(move |_conn| {
-            let storage = storage.clone();
+        let heartbeats = Arc::new(TaskHeartbeats::new());
+        let mut revocation_sync = None;
+        if let Some((upstream, interval)) = &self.revocation_upstream {
+            let auth = self.auth.as_ref()
+                .ok_or("revocation sync requires request authentication to be enabled")?;
+            let puller = RevocationPuller::new(upstream, *interval, auth.authority().clone())?
+                .with_heartbeats(heartbeats.clone());
+            revocation_sync = Some(puller.stats());
+            tokio::spawn(puller.run());
+        }
+
+        let mut archive = None;
+        if let Some((destination, interval)) = self.journal_archive.clone() {
+            let journal = self.storage.journal()
+                .ok_or("journal archival requires the storage engine to have a change journal")?
+                .clone();
+            let archiver = JournalArchiver::new(journal, destination, interval)
+                .with_heartbeats(heartbeats.clone());
+            archive = Some(archiver.stats());
+            tokio::spawn(archiver.run());
+        }
+
+        let mut replica = None;
+        let mut anti_entropy = None;
+        if let Some((leader, interval, signer)) = self.replica_of {
+            if let Some(repair_interval) = self.anti_entropy_interval {
+                let repairer = AntiEntropy::new(&leader, repair_interval, self.storage.clone(), signer.clone())
+                    .with_heartbeats(heartbeats.clone());
+                anti_entropy = Some(repairer.stats());
+                tokio::spawn(repairer.run());
+            }
+            let mut follower = JournalFollower::new(&leader, interval, self.storage.clone(), signer)?
+                .with_heartbeats(heartbeats.clone());
+            if let Some(max_lag) = self.replica_max_lag {
+                follower = follower.with_max_lag(max_lag);
+            }
+            replica = Some(follower.state());
+            tokio::spawn(follower.run());
+        }
+
+        let mut quarantine = None;
+        if let Some(config) = self.quarantine.clone() {
+            let scanner = QuarantineScanner::new(self.storage.clone(), config).with_heartbeats(heartbeats.clone());
+            quarantine = Some(scanner.stats());
+            tokio::spawn(scanner.run());
+        }
+
+        let mut search = None;
+        if !self.search_buckets.is_empty() {
+            let indexes = Arc::new(SearchIndexes::open(&search::index_dir(self.storage.path()), &self.search_buckets)?);
+            let indexer = SearchIndexer::new(self.storage.clone(), indexes.clone()).with_heartbeats(heartbeats.clone());
+            search = Some(indexes);
+            tokio::spawn(indexer.run());
+        }
+
+        let mut cluster = None;
+        if let Some((advertise_url, peers, interval)) = self.membership.clone() {
+            let map = Arc::new(ClusterMap::new(&advertise_url, peers));
+            let prober = MembershipProber::new(map.clone(), interval).with_heartbeats(heartbeats.clone());
+            cluster = Some(map);
+            tokio::spawn(prober.run());
+        }
+
+        let mut election = None;
+        if let Some((lease, identity, lease_duration)) = &self.leader_election {
+            let elector = LeaderElector::new(lease, identity, *lease_duration)?.with_heartbeats(heartbeats.clone());
+            election = Some(elector.election());
+            tokio::spawn(elector.run());
+        }
+
+        let mut tasks = TaskManager::new(self.max_background_tasks).with_heartbeats(heartbeats.clone());
+        let refcounts = Arc::new(RefcountCheck::new(self.storage.clone()));
+        tasks.register(refcounts.clone());
+        tasks.register(Arc::new(LeasePurge(self.storage.clone())));
+        tasks.register(Arc::new(ExpiryPurge(self.storage.clone())));
+        tasks.register(Arc::new(MultipartPurge(self.storage.clone(), self.upload_expiry)));
+        // Only deletes made with a journal leave tombstones
+        if self.storage.journal().is_some() {
+            tasks.register(Arc::new(TombstonePurge(self.storage.clone(), self.tombstone_retention)));
+        }
+
+        // Per-key usage is only meaningful when requests are authenticated
+        let usage = match &self.auth {
+            Some(_) => {
+                let tracker = Arc::new(UsageTracker::new(self.storage.system()?));
+                tasks.register(Arc::new(UsageFlush(tracker.clone())));
+                Some(tracker)
+            }
+            None => None,
+        };
+
+        let audit = match self.audit_retention {
+            Some(retention) => {
+                let log = Arc::new(AuditLog::new(self.storage.system()?));
+                tasks.register(Arc::new(AuditFlush { log: log.clone(), retention }));
+                Some(log)
+            }
+            None => None,
+        };
+
+        let hot_keys = match self.warmup_keys {
+            Some(keys) => {
+                let report = tokio::task::block_in_place(|| warm_up(&self.storage, keys, WARMUP_BUDGET))?;
+                info!("Warm-up {}", report);
+                let tracker = Arc::new(HotKeyTracker::new(self.storage.system()?, keys));
+                tasks.register(Arc::new(HotKeysFlush(tracker.clone())));
+                Some(tracker)
+            }
+            None => None,
+        };
+        tasks.start();
+
+        let draining = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(ConnectionStats::default());
+        let state = Arc::new(ServerState {
+            objects: Storage::new(self.storage.clone()),
+            storage: self.storage,
+            slow_log: self.slow_log,
+            diagnostics: RuntimeDiagnostics::new(),
+            debug_endpoints: self.debug_endpoints,
+            memory_guard: MemoryGuard::new(self.memory_limit),
+            auth: self.auth,
+            revocation_sync,
+            archive,
+            replica,
+            anti_entropy,
+            cluster,
+            merkle: Arc::new(MerkleCache::default()),
+            proposals: ProposalBook::default(),
+            usage,
+            heartbeats,
+            health: self.health,
+            slo: SloTracker::new(self.slo),
+            frozen_buckets: RwLock::new(HashSet::new()),
+            pools: RequestPools::new(self.pools),
+            concurrency: ConcurrencyLimits::default(),
+            hot_keys,
+            tasks,
+            refcounts,
+            transforms: self.transforms,
+            response_cache: self.response_cache,
+            bandwidth: Arc::new(Bandwidth::new(self.bandwidth.unwrap_or_default())),
+            compression: self.compression,
+            checksums: self.checksums,
+            schemas: BucketSchemas::default(),
+            cors: self.cors,
+            chaos: self.chaos.then(|| Arc::new(Chaos::default())),
+            quarantine,
+            search,
+            audit,
+            identity: self.identity,
+            draining: draining.clone(),
+            election,
+            connections: connections.clone(),
+            spool: self.spool,
+            max_object_size: self.max_object_size,
+        });
+        if let Some(reload) = self.reload {
+            tokio::spawn(reconfig::reload_on_sighup(state.clone(), reload));
+        }
+
+        let limits = self.connection_limits;
+        let make_svc = make_service_fn(move |conn: &Connection| {
+            let (state, activity) = (state.clone(), conn.activity());
             async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
-                    handle_request(req, storage.clone())
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let state = state.clone();
+                    let in_flight = activity.begin();
+                    let req = match limits.body_read_timeout {
+                        Some(timeout) => time_body(req, timeout, state.connections.clone()),
+                        None => req,
+                    };
+                    async move {
+                        let response = handle_with_request_id(req, state).await?;
+                        Ok::<_, Infallible>(response.map(|body| TrackedBody::new(body, in_flight)))
+                    }
                 }))
             }
         });
 
-        let server = Server::bind(&addr).serve(make_svc);
+        let mut listener = AddrIncoming::bind(&addr)?;
+        // A body frame written after the headers would otherwise wait on
+        // Nagle for the client's delayed ACK, about 40ms per streamed response
+        listener.set_nodelay(true);
+        let incoming = Incoming::new(listener, limits.idle_timeout, connections);
+        let server = limits.apply(self.http2.apply(Server::builder(incoming)))
+            .serve(make_svc)
+            .with_graceful_shutdown(kubernetes::drain_on_shutdown(draining, self.drain_delay));
 
         info!("wflDB server listening on {}", addr);
 
@@ -43,255 +612,502 @@ impl SimpleServer {
     }
 }
 
-/// Simple request handler for spike
+/// Handle a request under its request id, echoing the id on the response
+async fn handle_with_request_id(
+    req: Request<Body>,
+    state: Arc<ServerState>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let id = request_id::for_request(&req);
+    let span = tracing::info_span!("request", id = %id);
+    let mut response = request_id::scope(id.clone(), handle_with_audit(req, state)).instrument(span).await?;
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&id) {
+        response.headers_mut().insert(wfldb_auth::headers::REQUEST_ID, value);
+    }
+    Ok(response)
+}
+
+/// Record /v1 and /admin requests in the audit log once answered
+async fn handle_with_audit(
+    req: Request<Body>,
+    state: Arc<ServerState>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    let kind = match &path {
+        p if p == "/v1" || p.starts_with("/v1/") => AuditKind::Access,
+        p if p.starts_with("/admin/") => AuditKind::Admin,
+        _ => return handle_with_cors(req, state).await,
+    };
+    let Some(log) = state.audit.clone() else {
+        return handle_with_cors(req, state).await;
+    };
+    let method = req.method().to_string();
+    let (response, actor) = audit::attributed(handle_with_cors(req, state)).await;
+    let response = response?;
+    let (key_id, principal) = actor.unzip();
+    log.record(AuditEvent {
+        at_ms: now_ms(),
+        kind,
+        request_id: request_id::current(),
+        key_id,
+        principal: principal.flatten(),
+        method,
+        path,
+        status: response.status().as_u16(),
+    });
+    Ok(response)
+}
+
+/// Answer CORS preflights and let allowed origins read responses
+async fn handle_with_cors(
+    req: Request<Body>,
+    state: Arc<ServerState>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let Some(cors) = &state.cors else {
+        return handle_request(req, state).await;
+    };
+    if let Some(preflight) = cors.preflight(&req) {
+        return Ok(preflight);
+    }
+    let origin = req.headers().get(hyper::header::ORIGIN).cloned();
+    let api = req.uri().path().starts_with("/v1/");
+    let mut response = handle_request(req, state.clone()).await?;
+    if api {
+        cors.decorate(origin.as_ref(), &mut response);
+    }
+    Ok(response)
+}
+
 async fn handle_request(
     req: Request<Body>,
-    storage_engine: Arc<StorageEngine>,
+    state: Arc<ServerState>,
 ) -> std::result::Result<Response<Body>, Infallible> {
     let method = req.method().clone();
     let uri = req.uri().clone();
     let path = uri.path();
+    let mut timings = RequestTimings::start();
+    let bytes_in: u64 = req.headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let route = Route::resolve(&method, path);
+    let _in_flight = state.diagnostics.enter_handler(route.name());
+    let debug_timing = req.headers().contains_key(wfldb_auth::headers::DEBUG_TIMING);
     
     debug!("Handling {} {}", method, path);
 
-    let storage = Storage::new((*storage_engine).clone());
-
-    let result: std::result::Result<Response<Body>, Infallible> = match (&method, path) {
-        // Health check endpoint
-        (&Method::GET, "/health") => {
-            let response_body = r#"{"status":"healthy","version":"0.1.0","service":"wfldb"}"#;
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("content-type", "application/json")
-                .body(Body::from(response_body))
-                .unwrap())
-        }
-        
-        // Echo endpoint for testing
-        (&Method::POST, "/echo") => {
-            match hyper::body::to_bytes(req.into_body()).await {
-                Ok(body_bytes) => {
-                    let echo_response = format!(
-                        r#"{{"echo":"{}","size":{},"timestamp":"{}"}}"#,
-                        String::from_utf8_lossy(&body_bytes),
-                        body_bytes.len(),
-                        chrono::Utc::now().to_rfc3339()
-                    );
-                    Ok(Response::builder()
-                        .status(StatusCode::OK)
-                        .header("content-type", "application/json")
-                        .body(Body::from(echo_response))
-                        .unwrap())
-                }
-                Err(_) => {
-                    let error_response = r#"{"error":"Failed to read request body"}"#;
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(error_response))
-                        .unwrap())
+    // Operational endpoints stay reachable so the overload can be observed
+    if path.starts_with("/v1/") && state.memory_guard.should_shed() {
+        warn!("Shedding {} {}: heap above limit", method, path);
+        return Ok(ApiError::overloaded("Server is low on memory, retry later").into_response());
+    }
+
+    let storage = &state.objects;
+
+    let auth_ctx = match &state.auth {
+        Some(authenticator) if path.starts_with("/v1/") || path == "/v1" => {
+            // A touch is authorized against the object it touches
+            let object = match objects::parse_touch_path(path) {
+                Some(touched) => touched.ok(),
+                None => parse_object_path(path).ok(),
+            };
+            let acl = object
+                .as_ref()
+                .map(|(bucket_id, _)| authenticator.bucket_acls().get(bucket_id))
+                .unwrap_or_default();
+            // A batch GET reads, though it's sent as POST
+            let method_name = if route == Route::BatchGet { "GET" } else { method.as_str() };
+            // Watches and searches parse as object paths but name keys, so
+            // they're held to the list permission even on public buckets,
+            // which anonymous callers never have
+            let anonymous = acl.allows_anonymous(method_name)
+                && !matches!(route, Route::Watch | Route::Search)
+                && !req.headers().contains_key(hyper::header::AUTHORIZATION);
+            // Taking, renewing, and releasing a lease all count as writes
+            let action = match (lease::parse_lease_path(path), chunks::parse_chunk_path(path)) {
+                (Some(_), _) if method != Method::GET => "PUT",
+                (_, Some(Ok(route))) => route.auth_action(&method),
+                _ => method_name,
+            };
+            let authorized = timings.time(Phase::Auth, || {
+                if anonymous {
+                    return Ok(None);
                 }
-            }
-        }
-        
-        // Object storage endpoints
-        (&Method::PUT, path) if path.starts_with("/v1/") => {
-            match parse_object_path(path) {
-                Ok((bucket_id, key)) => {
-                    match hyper::body::to_bytes(req.into_body()).await {
-                        Ok(body_bytes) => {
-                            match storage.put_object(&bucket_id, &key, &body_bytes) {
-                                Ok(metadata) => {
-                                    let response = format!(
-                                        r#"{{"success":true,"bucket":"{}","key":"{}","size":{},"version":"{}","chunked":{}}}"#,
-                                        bucket_id.as_str(),
-                                        key.as_str(),
-                                        metadata.size,
-                                        metadata.version.to_string(),
-                                        metadata.is_chunked()
-                                    );
-                                    Ok(Response::builder()
-                                        .status(StatusCode::CREATED)
-                                        .header("content-type", "application/json")
-                                        .body(Body::from(response))
-                                        .unwrap())
-                                }
-                                Err(e) => {
-                                    let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                                    Ok(Response::builder()
-                                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                        .header("content-type", "application/json")
-                                        .body(Body::from(error_response))
-                                        .unwrap())
-                                }
-                            }
-                        }
-                        Err(_) => {
-                            let error_response = r#"{"error":"Failed to read request body"}"#;
-                            Ok(Response::builder()
-                                .status(StatusCode::BAD_REQUEST)
-                                .header("content-type", "application/json")
-                                .body(Body::from(error_response))
-                                .unwrap())
-                        }
+                let ctx = auth::authenticate_request(authenticator, &req)?;
+                audit::attribute(&ctx);
+                if let Some((bucket_id, key)) = &object {
+                    ctx.authorize(action, bucket_id)?;
+                    // Bucket ACLs are set by operators for the shared namespace
+                    if acl == BucketAcl::OwnerOnly && ctx.tenant().is_none() {
+                        let owner = storage
+                            .get_metadata(bucket_id, key)
+                            .map_err(|e| AuthError::Storage(e.to_string()))?
+                            .and_then(|metadata| metadata.owner);
+                        ctx.authorize_owner(acl, owner.as_deref())?;
                     }
+                } else if let Some(Ok(bucket_id)) = parse_bucket_path(path) {
+                    let prefix = uri.query().and_then(|q| query_param(q, "prefix")).unwrap_or_default();
+                    ctx.authorize_list(&bucket_id, &prefix)?;
                 }
-                Err(e) => {
-                    let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(error_response))
-                        .unwrap())
-                }
-            }
-        }
-        
-        (&Method::GET, path) if path.starts_with("/v1/") => {
-            match parse_object_path(path) {
-                Ok((bucket_id, key)) => {
-                    match storage.get_object(&bucket_id, &key) {
-                        Ok(Some(data)) => {
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("content-type", "application/octet-stream")
-                                .header("content-length", data.len().to_string())
-                                .body(Body::from(data))
-                                .unwrap())
-                        }
-                        Ok(None) => {
-                            let error_response = r#"{"error":"Object not found"}"#;
-                            Ok(Response::builder()
-                                .status(StatusCode::NOT_FOUND)
-                                .header("content-type", "application/json")
-                                .body(Body::from(error_response))
-                                .unwrap())
-                        }
-                        Err(e) => {
-                            let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                            Ok(Response::builder()
-                                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                .header("content-type", "application/json")
-                                .body(Body::from(error_response))
-                                .unwrap())
-                        }
-                    }
+                // Listings and watches are held to the caveat's prefix by
+                // authorize_list, and batch GETs to its keys key by key
+                if !matches!(route, Route::ListBuckets | Route::ListObjects | Route::BatchGet | Route::Watch) {
+                    ctx.authorize_key(auth::addressed_key(route, path).as_ref().map(Key::as_str))?;
                 }
+                Ok(Some(ctx))
+            });
+            match authorized {
+                Ok(ctx) => ctx,
                 Err(e) => {
-                    let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(error_response))
-                        .unwrap())
+                    warn!("Rejected {} {}: {}", method, path, e);
+                    return Ok(auth::error_response(&e));
                 }
             }
         }
-        
-        (&Method::DELETE, path) if path.starts_with("/v1/") => {
-            match parse_object_path(path) {
-                Ok((bucket_id, key)) => {
-                    match storage.delete_object(&bucket_id, &key) {
-                        Ok(()) => {
-                            let response = format!(
-                                r#"{{"success":true,"bucket":"{}","key":"{}","deleted":true}}"#,
-                                bucket_id.as_str(),
-                                key.as_str()
-                            );
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("content-type", "application/json")
-                                .body(Body::from(response))
-                                .unwrap())
-                        }
-                        Err(e) => {
-                            let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                            Ok(Response::builder()
-                                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                .header("content-type", "application/json")
-                                .body(Body::from(error_response))
-                                .unwrap())
-                        }
-                    }
-                }
-                Err(e) => {
-                    let error_response = format!(r#"{{"error":"{}"}}"#, e);
-                    Ok(Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(error_response))
-                        .unwrap())
-                }
+        _ => None,
+    };
+
+    // A request's own caveats may cap its body below the server's limits
+    let req = match auth::cap_body(req, auth_ctx.as_ref(), bytes_in) {
+        Ok(req) => req,
+        Err(e) => return Ok(e.into_response()),
+    };
+
+    // Tenant keys only ever see their tenant's buckets
+    let tenant = auth_ctx.as_ref().and_then(|ctx| ctx.tenant());
+    let tenant_storage;
+    let storage = match tenant {
+        Some(tenant) => {
+            tenant_storage = storage.for_tenant(tenant);
+            &tenant_storage
+        }
+        None => storage,
+    };
+
+    // A frozen bucket is being cut over to another node; reads keep working
+    let write = matches!(method, Method::PUT | Method::POST | Method::PATCH | Method::DELETE) && route != Route::BatchGet;
+    if write && tenant.is_none() {
+        if let Ok((bucket_id, _)) = parse_object_path(path) {
+            if state.frozen_buckets.read().unwrap().contains(&bucket_id) {
+                return Ok(ApiError::bucket_frozen().into_response());
             }
         }
-        
-        // Not found
-        _ => {
-            let error_response = r#"{"error":"Not found"}"#;
-            Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .header("content-type", "application/json")
-                .body(Body::from(error_response))
-                .unwrap())
+    }
+
+    // Replicas only change through the leader's journal, and under leader
+    // election only the lease holder takes writes; the existence check is
+    // a read sent as POST, like a batch GET
+    let exists_check = matches!(chunks::parse_chunk_path(path), Some(Ok(chunks::ChunkRoute::Exists(_))));
+    if path.starts_with("/v1/") && write && !exists_check {
+        if let Some(replica) = &state.replica {
+            return Ok(ApiError::read_only_replica(replica.leader()).into_response());
+        }
+        if let Some(election) = state.election.as_ref().filter(|election| !election.is_leader()) {
+            return Ok(ApiError::not_leader(election.holder()).into_response());
+        }
+    }
+
+    // Reads may name a journal position or staleness bound the answering
+    // node must meet; anything the session saw happened before this request
+    let requirement = match path.starts_with("/v1") {
+        true => match ReadRequirement::from_headers(req.headers()) {
+            Ok(requirement) => requirement,
+            Err(e) => return Ok(e.into_response()),
+        },
+        false => ReadRequirement::default(),
+    };
+    if let Some(session) = &requirement.session {
+        HybridClock::global().observe(session.hlc);
+    }
+    // A replica with a lag limit checks every read, bound or not
+    let gated = !requirement.is_empty() || state.replica.as_ref().is_some_and(|replica| replica.max_lag().is_some());
+    if (matches!(method, Method::GET | Method::HEAD) || route == Route::BatchGet) && gated {
+        if let Err(e) = replica::await_read(&state, requirement).await {
+            return Ok(e.into_response());
+        }
+    }
+
+    // Keys claiming a concurrency cap hold a slot until the response is ready
+    let _concurrency_slot = match auth_ctx.as_ref().map(|ctx| state.concurrency.acquire(ctx)) {
+        Some(Err(max)) => return Ok(ApiError::too_many_requests(max).into_response()),
+        Some(Ok(slot)) => slot,
+        None => None,
+    };
+
+    // Storage work holds a permit from the bucket's (or tenant's) pool
+    let pool_bucket = match parse_bucket_path(path) {
+        Some(bucket) => bucket.ok(),
+        None => parse_object_path(path).ok().map(|(bucket_id, _)| bucket_id),
+    };
+    // Queued by the requested class, capped by the key packet; anonymous
+    // requests can't claim interactive
+    let requested = req
+        .headers()
+        .get(wfldb_auth::headers::PRIORITY)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Priority::parse)
+        .unwrap_or_default();
+    let priority = match &auth_ctx {
+        Some(ctx) => ctx.permissions().cap_priority(requested),
+        None if state.auth.is_some() => requested.min(Priority::Normal),
+        None => requested,
+    };
+    // Bodies are paced to the bandwidth limits of the key and the bucket
+    let (req, egress_limits) = if path.starts_with("/v1") && state.bandwidth.is_limited() {
+        let bandwidth = &state.bandwidth;
+        let key_id = auth_ctx.as_ref().map(|ctx| ctx.key_id().to_string());
+        let principal = auth_ctx.as_ref().and_then(|ctx| ctx.principal());
+        let bucket = pool_bucket.as_ref().map(|bucket_id| storage.engine().namespaced(bucket_id));
+        let limits = |direction| bandwidth.limiters(key_id.as_deref(), principal, bucket.as_deref(), direction);
+        let ingress = limits(Direction::Ingress);
+        let req = req.map(|body| bandwidth::throttle(body, ingress, bandwidth.clone(), Direction::Ingress));
+        (req, limits(Direction::Egress))
+    } else {
+        (req, Vec::new())
+    };
+    let _pool_permit = match pool_bucket {
+        Some(bucket_id) => {
+            let pool = RequestPools::pool_name(tenant, &bucket_id);
+            let (permit, waited) = state.pools.acquire(&pool, priority).await;
+            timings.record(Phase::Queue, waited);
+            Some(permit)
         }
+        None => None,
     };
 
-    match result {
-        Ok(response) => {
-            info!("{} {} -> {}", method, path, response.status());
-            Ok(response)
+    // Faults switched on for a drill hit requests that got this far
+    if let (Some(chaos), true) = (&state.chaos, path.starts_with("/v1")) {
+        if let Some(response) = chaos.drop_connection() {
+            warn!("Chaos: dropping connection for {} {}", method, path);
+            return Ok(response);
+        }
+        if write && chaos.fail_write() {
+            warn!("Chaos: failing {} {}", method, path);
+            return Ok(ApiError::injected_failure().into_response());
+        }
+        if let Some(delay) = chaos.storage_delay() {
+            tokio::time::sleep(delay).await;
+            timings.record(Phase::Storage, delay);
+        }
+    }
+
+    // With quarantine on, writes hold their key before changing it and
+    // non-admins can't read what's held
+    let mut holds = None;
+    if state.quarantine.is_some() {
+        if let Some((bucket_id, key)) = quarantine::written_object(route, &method, path, uri.query()) {
+            match Holds::take(storage, &bucket_id, [&key]) {
+                Ok(taken) => holds = Some(taken),
+                Err(e) => return Ok(ApiError::from_storage(&e, now_ms()).into_response()),
+            }
+        }
+        let admin = auth_ctx.as_ref().is_some_and(|ctx| ctx.permissions().can_admin());
+        if let (Route::GetObject, Ok((bucket_id, key)), false) = (route, parse_object_path(path), admin) {
+            match storage.quarantine_entry(&bucket_id, &key) {
+                Ok(None) => {}
+                Ok(Some(_)) => return Ok(ApiError::quarantined().into_response()),
+                Err(e) => return Ok(ApiError::from_storage(&e, now_ms()).into_response()),
+            }
+        }
+    }
+
+    let mut response = router::dispatch(route, req, &state, storage, auth_ctx.as_ref(), &mut timings).await;
+    if let Some(holds) = holds {
+        holds.settle();
+    }
+
+    // The breakdown reveals server internals, so only admins get it
+    let timing_allowed = match (&state.auth, &auth_ctx) {
+        (None, _) => true,
+        (Some(_), Some(ctx)) => ctx.permissions().can_admin(),
+        (Some(_), None) => false,
+    };
+    if debug_timing && timing_allowed {
+        if let Ok(value) = hyper::header::HeaderValue::from_str(&timings.server_timing()) {
+            response.headers_mut().insert("server-timing", value);
         }
-        Err(e) => {
-            error!("Handler error for {} {}: {}", method, path, e);
-            let error_response = r#"{"error":"Internal server error"}"#;
-            Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header("content-type", "application/json")
-                .body(Body::from(error_response))
-                .unwrap())
+    }
+    // Clients track the highest position they have seen to read
+    // their own writes from replicas
+    if path.starts_with("/v1") {
+        if let Some(seq) = replica::applied_seq(&state) {
+            response.headers_mut().insert(wfldb_auth::headers::APPLIED_SEQ, seq.into());
+        }
+        let token = replica::session_token(&state, requirement.session)
+            .and_then(|token| hyper::header::HeaderValue::from_str(&token.to_string()).ok());
+        if let Some(token) = token {
+            response.headers_mut().insert(wfldb_auth::headers::SESSION, token);
+        }
+    }
+    // Written objects are re-indexed on the search indexer's next pass
+    if let (Some(indexes), true) = (&state.search, response.status().is_success()) {
+        if let Some((bucket_id, key)) = search::changed_object(route, &method, path, uri.query()) {
+            indexes.changed(&storage.engine().namespaced(&bucket_id), &key);
         }
     }
+    // Drop cached bodies of objects this request changed
+    if let Some(cache) = &state.response_cache {
+        let changed = matches!(method, Method::PUT | Method::PATCH | Method::DELETE) && response.status().is_success();
+        if let (true, Ok((bucket_id, key))) = (changed, parse_object_path(path)) {
+            cache.invalidate(&storage.engine().namespaced(&bucket_id), key.as_str());
+        }
+    }
+    if let (Some(usage), Some(ctx)) = (&state.usage, &auth_ctx) {
+        let bytes_out = response.body().size_hint().exact().unwrap_or(0);
+        let error = response.status().is_client_error() || response.status().is_server_error();
+        usage.record(&ctx.key_id().to_string(), bytes_in, bytes_out, error, now_ms());
+    }
+    match &auth_ctx {
+        Some(ctx) => info!("{} {} -> {} ({})", method, path, response.status(), ctx.display_name()),
+        None => info!("{} {} -> {}", method, path, response.status()),
+    }
+    // A watch waits for changes on purpose, so its latency says nothing
+    if route != Route::Watch {
+        state.slow_log.observe(method.as_str(), path, response.status().as_u16(), &timings);
+        if path.starts_with("/v1") {
+            state.slo.record(response.status().as_u16(), timings.total(), now_ms());
+        }
+    }
+    if !egress_limits.is_empty() {
+        let bandwidth = &state.bandwidth;
+        // A paced body has no known size, so keep the framing in the header
+        if let Some(len) = response.body().size_hint().exact() {
+            response.headers_mut().entry(hyper::header::CONTENT_LENGTH).or_insert(len.into());
+        }
+        response = response.map(|body| bandwidth::throttle(body, egress_limits, bandwidth.clone(), Direction::Egress));
+    }
+    Ok(response)
 }
 
-/// Parse object path like "/v1/bucket/key" into bucket and key
-fn parse_object_path(path: &str) -> std::result::Result<(BucketId, Key), String> {
-    let parts: Vec<&str> = path.strip_prefix("/v1/")
-        .unwrap_or("")
-        .split('/')
-        .collect();
-    
-    if parts.len() < 2 || parts[0].is_empty() || parts[1].is_empty() {
-        return Err("Invalid path format. Expected /v1/{bucket}/{key}".to_string());
+/// Interval between usage statistics flushes to the system partition
+const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Merges per-key usage into the system partition
+struct UsageFlush(Arc<UsageTracker>);
+
+impl BackgroundTask for UsageFlush {
+    fn name(&self) -> &'static str {
+        "usage_flush"
+    }
+
+    fn interval(&self) -> Duration {
+        USAGE_FLUSH_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        Ok(format!("flushed usage for {} keys", self.0.flush()?))
     }
-    
-    let bucket_id = BucketId::new(parts[0])
-        .map_err(|_| "Invalid bucket name".to_string())?;
-    
-    let key_part = parts[1..].join("/"); // Support nested keys
-    let key = Key::new(&key_part)
-        .map_err(|_| "Invalid key".to_string())?;
-    
-    Ok((bucket_id, key))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Interval between saves of the recently read key list
+const HOT_KEYS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
 
-    #[test]
-    fn test_parse_object_path() {
-        // Valid paths
-        let (bucket, key) = parse_object_path("/v1/photos/cat.jpg").unwrap();
-        assert_eq!(bucket.as_str(), "photos");
-        assert_eq!(key.as_str(), "cat.jpg");
+/// Longest the startup warm-up may delay serving
+const WARMUP_BUDGET: Duration = Duration::from_secs(60);
 
-        let (bucket, key) = parse_object_path("/v1/documents/folder/file.txt").unwrap();
-        assert_eq!(bucket.as_str(), "documents");
-        assert_eq!(key.as_str(), "folder/file.txt");
+/// Saves the recently read key list for the next start's warm-up
+struct HotKeysFlush(Arc<HotKeyTracker>);
 
-        // Invalid paths
-        assert!(parse_object_path("/v1/").is_err());
-        assert!(parse_object_path("/v1/bucket/").is_err());
-        assert!(parse_object_path("/v1//key").is_err());
+impl BackgroundTask for HotKeysFlush {
+    fn name(&self) -> &'static str {
+        "hot_keys_flush"
     }
-}
\ No newline at end of file
+
+    fn interval(&self) -> Duration {
+        HOT_KEYS_FLUSH_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        Ok(format!("saved {} hot keys", self.0.flush()?))
+    }
+}
+
+/// Interval between sweeps of expired leases
+const LEASE_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Removes leases that lapsed without being released
+struct LeasePurge(StorageEngine);
+
+impl BackgroundTask for LeasePurge {
+    fn name(&self) -> &'static str {
+        "lease_purge"
+    }
+
+    fn interval(&self) -> Duration {
+        LEASE_PURGE_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        Ok(format!("purged {} expired leases", purge_expired_leases(&self.0, now_ms())?))
+    }
+}
+
+/// Interval between sweeps of expired objects
+const EXPIRY_PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Most expired objects deleted by one sweep
+const EXPIRY_PURGE_BATCH: usize = 10_000;
+
+/// Deletes objects whose TTL has run out
+struct ExpiryPurge(StorageEngine);
+
+impl BackgroundTask for ExpiryPurge {
+    fn name(&self) -> &'static str {
+        "expiry_purge"
+    }
+
+    fn interval(&self) -> Duration {
+        EXPIRY_PURGE_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        Ok(format!("deleted {} expired objects", purge_expired_objects(&self.0, now_ms(), EXPIRY_PURGE_BATCH)?))
+    }
+}
+
+/// Default age after which an unfinished multipart upload is aborted
+pub const DEFAULT_UPLOAD_EXPIRY: Duration = DANGLING_UPLOAD_AGE;
+
+/// Interval between sweeps of expired multipart uploads
+const UPLOAD_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Aborts multipart uploads left unfinished for longer than the expiry
+struct MultipartPurge(StorageEngine, Duration);
+
+impl BackgroundTask for MultipartPurge {
+    fn name(&self) -> &'static str {
+        "multipart_purge"
+    }
+
+    fn interval(&self) -> Duration {
+        UPLOAD_PURGE_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        Ok(format!("aborted {} expired uploads", purge_stale_uploads(&self.0, self.1, SystemTime::now())?))
+    }
+}
+
+/// Default time delete tombstones are kept for replicas to catch up
+pub const DEFAULT_TOMBSTONE_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Interval between sweeps of expired tombstones
+const TOMBSTONE_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Removes delete tombstones older than the retention period
+struct TombstonePurge(StorageEngine, Duration);
+
+impl BackgroundTask for TombstonePurge {
+    fn name(&self) -> &'static str {
+        "tombstone_purge"
+    }
+
+    fn interval(&self) -> Duration {
+        TOMBSTONE_PURGE_INTERVAL
+    }
+
+    fn run(&self, _ctx: &mut TaskContext) -> Result<String> {
+        let cutoff_ms = now_ms().saturating_sub(self.1.as_millis() as u64);
+        Ok(format!("purged {} expired tombstones", purge_tombstones(&self.0, cutoff_ms)?))
+    }
+}
@@ -0,0 +1,156 @@
+//! Service level objectives for the /v1 API
+//!
+//! Every /v1 request is counted in one-minute buckets over a rolling
+//! [`WINDOW_MINUTES`] window: as an error when it answers 5xx, and as slow
+//! when it takes longer than the latency threshold. The report compares the
+//! share of good requests with each objective's target and says how much
+//! of the window's error budget is left.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Minutes of traffic the objectives are measured over
+pub const WINDOW_MINUTES: u64 = 60;
+
+/// Objectives the API is held to
+#[derive(Debug, Clone, Copy)]
+pub struct SloConfig {
+    /// Share of requests that must not fail with a server error
+    pub availability: f64,
+    /// Requests slower than this count against the latency objective
+    pub latency_threshold: Duration,
+    /// Share of requests that must finish within the threshold
+    pub latency_target: f64,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig {
+            availability: 0.999,
+            latency_threshold: Duration::from_millis(500),
+            latency_target: 0.99,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct MinuteCounts {
+    minute: u64,
+    requests: u64,
+    errors: u64,
+    slow: u64,
+}
+
+/// How one objective is doing over the window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ObjectiveStatus {
+    pub target: f64,
+    /// Share of good requests; `None` without traffic
+    pub actual: Option<f64>,
+    pub met: bool,
+    /// Share of the allowed bad requests not yet used; negative once overspent
+    pub error_budget_remaining: f64,
+}
+
+impl ObjectiveStatus {
+    fn new(target: f64, requests: u64, bad: u64) -> Self {
+        let allowed = requests as f64 * (1.0 - target);
+        let actual = (requests > 0).then(|| 1.0 - bad as f64 / requests as f64);
+        ObjectiveStatus {
+            target,
+            actual,
+            met: actual.is_none_or(|actual| actual >= target),
+            error_budget_remaining: if allowed > 0.0 { 1.0 - bad as f64 / allowed } else { 1.0 },
+        }
+    }
+}
+
+/// Both objectives over the window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SloReport {
+    pub window_secs: u64,
+    pub requests: u64,
+    pub errors: u64,
+    pub slow: u64,
+    pub latency_threshold_ms: u64,
+    pub availability: ObjectiveStatus,
+    pub latency: ObjectiveStatus,
+}
+
+/// Counts /v1 outcomes against the objectives
+#[derive(Debug)]
+pub struct SloTracker {
+    config: SloConfig,
+    minutes: Mutex<VecDeque<MinuteCounts>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        SloTracker { config, minutes: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Count a request that answered `status` after `elapsed`
+    pub fn record(&self, status: u16, elapsed: Duration, now_ms: u64) {
+        let minute = now_ms / 60_000;
+        let mut minutes = self.minutes.lock().unwrap();
+        if minutes.back().is_none_or(|last| last.minute != minute) {
+            minutes.push_back(MinuteCounts { minute, ..Default::default() });
+        }
+        while minutes.front().is_some_and(|first| first.minute + WINDOW_MINUTES <= minute) {
+            minutes.pop_front();
+        }
+        let counts = minutes.back_mut().unwrap();
+        counts.requests += 1;
+        counts.errors += (status >= 500) as u64;
+        counts.slow += (elapsed > self.config.latency_threshold) as u64;
+    }
+
+    pub fn report(&self, now_ms: u64) -> SloReport {
+        let minute = now_ms / 60_000;
+        let (requests, errors, slow) = self
+            .minutes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|counts| counts.minute + WINDOW_MINUTES > minute)
+            .fold((0, 0, 0), |(r, e, s), counts| (r + counts.requests, e + counts.errors, s + counts.slow));
+        SloReport {
+            window_secs: WINDOW_MINUTES * 60,
+            requests,
+            errors,
+            slow,
+            latency_threshold_ms: self.config.latency_threshold.as_millis() as u64,
+            availability: ObjectiveStatus::new(self.config.availability, requests, errors),
+            latency: ObjectiveStatus::new(self.config.latency_target, requests, slow),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objectives_over_window() {
+        let tracker = SloTracker::new(SloConfig { availability: 0.9, ..Default::default() });
+        assert!(tracker.report(0).availability.met);
+
+        for _ in 0..18 {
+            tracker.record(200, Duration::from_millis(5), 1_000);
+        }
+        tracker.record(503, Duration::from_millis(5), 1_000);
+        tracker.record(200, Duration::from_secs(1), 61_000);
+        let report = tracker.report(61_000);
+        assert_eq!((report.requests, report.errors, report.slow), (20, 1, 1));
+        assert!(report.availability.met);
+        assert!((report.availability.error_budget_remaining - 0.5).abs() < 1e-9);
+        // 1 in 20 slow misses a 99% target
+        assert!(!report.latency.met);
+
+        // The first minute has left the window
+        let report = tracker.report(WINDOW_MINUTES * 60_000 + 1_000);
+        assert_eq!((report.requests, report.slow), (1, 1));
+    }
+}
@@ -0,0 +1,204 @@
+//! Slow request logging with per-phase timing breakdown
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Request processing phases tracked for the slow request log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for a permit from the request's pool
+    Queue,
+    Auth,
+    Storage,
+    ChunkIo,
+    /// Read-side content transforms
+    Transform,
+    ResponseWrite,
+}
+
+/// Per-request timing breakdown
+#[derive(Debug, Clone)]
+pub struct RequestTimings {
+    started_at: Instant,
+    pub queue: Duration,
+    pub auth: Duration,
+    pub storage: Duration,
+    pub chunk_io: Duration,
+    pub transform: Duration,
+    pub response_write: Duration,
+}
+
+impl RequestTimings {
+    /// Start timing a new request
+    pub fn start() -> Self {
+        RequestTimings {
+            started_at: Instant::now(),
+            queue: Duration::ZERO,
+            auth: Duration::ZERO,
+            storage: Duration::ZERO,
+            chunk_io: Duration::ZERO,
+            transform: Duration::ZERO,
+            response_write: Duration::ZERO,
+        }
+    }
+
+    /// Add elapsed time to a phase
+    pub fn record(&mut self, phase: Phase, elapsed: Duration) {
+        match phase {
+            Phase::Queue => self.queue += elapsed,
+            Phase::Auth => self.auth += elapsed,
+            Phase::Storage => self.storage += elapsed,
+            Phase::ChunkIo => self.chunk_io += elapsed,
+            Phase::Transform => self.transform += elapsed,
+            Phase::ResponseWrite => self.response_write += elapsed,
+        }
+    }
+
+    /// Run a closure and attribute its duration to a phase
+    pub fn time<F, R>(&mut self, phase: Phase, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Total wall-clock time since the request started
+    pub fn total(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// `Server-Timing` header value: the phases the request spent time in,
+    /// then the total so far
+    pub fn server_timing(&self) -> String {
+        let phases = [
+            ("queue", self.queue),
+            ("auth", self.auth),
+            ("storage", self.storage),
+            ("chunk-io", self.chunk_io),
+            ("transform", self.transform),
+            ("response-write", self.response_write),
+        ];
+        let mut metrics: Vec<String> = phases
+            .iter()
+            .filter(|(_, elapsed)| !elapsed.is_zero())
+            .map(|(name, elapsed)| format!("{};dur={:.3}", name, as_ms(*elapsed)))
+            .collect();
+        metrics.push(format!("total;dur={:.3}", as_ms(self.total())));
+        metrics.join(", ")
+    }
+}
+
+/// Logger for requests exceeding a latency threshold
+///
+/// Every slow request is counted, but only one in `sample_every` is written
+/// to the log so a latency incident cannot flood the output.
+pub struct SlowRequestLog {
+    threshold: Duration,
+    sample_every: u64,
+    slow_count: AtomicU64,
+}
+
+impl SlowRequestLog {
+    /// Create a slow request log with the given threshold and sampling interval
+    pub fn new(threshold: Duration, sample_every: u64) -> Self {
+        SlowRequestLog {
+            threshold,
+            sample_every: sample_every.max(1),
+            slow_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a slow request log that never records anything
+    pub fn disabled() -> Self {
+        Self::new(Duration::MAX, 1)
+    }
+
+    /// Record a completed request, returning true if it was logged
+    pub fn observe(&self, method: &str, path: &str, status: u16, timings: &RequestTimings) -> bool {
+        let total = timings.total();
+        if total < self.threshold {
+            return false;
+        }
+
+        let seen = self.slow_count.fetch_add(1, Ordering::Relaxed);
+        if !seen.is_multiple_of(self.sample_every) {
+            return false;
+        }
+
+        warn!(
+            target: "wfldb::slow_request",
+            method,
+            path,
+            status,
+            total_ms = as_ms(total),
+            queue_ms = as_ms(timings.queue),
+            auth_ms = as_ms(timings.auth),
+            storage_ms = as_ms(timings.storage),
+            chunk_io_ms = as_ms(timings.chunk_io),
+            transform_ms = as_ms(timings.transform),
+            response_write_ms = as_ms(timings.response_write),
+            threshold_ms = as_ms(self.threshold),
+            "Slow request"
+        );
+        true
+    }
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_accumulate_per_phase() {
+        let mut timings = RequestTimings::start();
+        timings.record(Phase::Storage, Duration::from_millis(3));
+        timings.record(Phase::Storage, Duration::from_millis(2));
+        timings.record(Phase::ChunkIo, Duration::from_millis(7));
+
+        assert_eq!(timings.storage, Duration::from_millis(5));
+        assert_eq!(timings.chunk_io, Duration::from_millis(7));
+        assert_eq!(timings.auth, Duration::ZERO);
+        assert_eq!(timings.response_write, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_server_timing_lists_spent_phases() {
+        let mut timings = RequestTimings::start();
+        timings.record(Phase::Auth, Duration::from_micros(250));
+        timings.record(Phase::ChunkIo, Duration::from_millis(4));
+
+        let header = timings.server_timing();
+        assert!(header.starts_with("auth;dur=0.250, chunk-io;dur=4.000, total;dur="));
+        assert!(!header.contains("storage"));
+    }
+
+    #[test]
+    fn test_fast_requests_are_not_logged() {
+        let log = SlowRequestLog::new(Duration::from_secs(60), 1);
+        let timings = RequestTimings::start();
+
+        assert!(!log.observe("GET", "/v1/b/k", 200, &timings));
+        assert_eq!(log.slow_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_slow_requests_are_sampled() {
+        let log = SlowRequestLog::new(Duration::ZERO, 3);
+        let timings = RequestTimings::start();
+
+        let logged: Vec<bool> = (0..6)
+            .map(|_| log.observe("PUT", "/v1/b/k", 201, &timings))
+            .collect();
+
+        assert_eq!(logged, vec![true, false, false, true, false, false]);
+        assert_eq!(log.slow_count.load(Ordering::Relaxed), 6);
+    }
+}
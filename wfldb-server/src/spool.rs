@@ -0,0 +1,178 @@
+//! Request bodies buffered up to a memory threshold, then spilled to disk
+//!
+//! Object PUTs and chunk uploads need the whole body before they can check
+//! it (content hash, schema, checksums) and hand it to the engine. A
+//! [`Spool`] keeps the first `--body-memory-kb` of a body on the heap and
+//! writes the whole body to an unlinked temporary file once it grows past
+//! that. The file is then memory-mapped, so the body is read from the page
+//! cache, which the kernel can write back and evict, and a request's heap
+//! use stays at the threshold however large its body is. JSON bodies
+//! (transactions, batch GETs, patches, leases, touches) are read through it
+//! too, each with a limit sized to its endpoint.
+
+use bytes::{Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::Body;
+use memmap2::Mmap;
+use std::io;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+use crate::error::ApiError;
+
+/// Body bytes held in memory before a body spills to disk
+pub const DEFAULT_MEMORY_THRESHOLD: usize = 1024 * 1024;
+
+/// Where bodies spill, and counts of those that did
+#[derive(Debug)]
+pub struct Spool {
+    memory_threshold: usize,
+    dir: PathBuf,
+    spills: AtomicU64,
+    spilled_bytes: AtomicU64,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Spool::new(DEFAULT_MEMORY_THRESHOLD, std::env::temp_dir())
+    }
+}
+
+impl Spool {
+    pub fn new(memory_threshold: usize, dir: PathBuf) -> Self {
+        Spool { memory_threshold, dir, spills: AtomicU64::new(0), spilled_bytes: AtomicU64::new(0) }
+    }
+
+    /// Bodies that outgrew the memory threshold
+    pub fn spills(&self) -> u64 {
+        self.spills.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of bodies written to disk
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spilled_bytes.load(Ordering::Relaxed)
+    }
+
+    /// An empty buffer for a body fed in piece by piece
+    pub fn buffer(&self) -> SpoolBuffer<'_> {
+        SpoolBuffer { spool: self, memory: BytesMut::new(), file: None, len: 0 }
+    }
+
+    /// Read a whole body, failing with 413 once it passes `limit` bytes
+    pub async fn read(&self, mut body: Body, limit: usize) -> Result<SpooledBody, ApiError> {
+        let mut buffer = self.buffer();
+        while let Some(data) = body.data().await {
+            let data = data.map_err(|_| ApiError::bad_request("Failed to read request body"))?;
+            if buffer.len + data.len() > limit {
+                return Err(ApiError::too_large(format!("Body exceeds {} bytes", limit)));
+            }
+            buffer.push(&data).await.map_err(spill_error)?;
+        }
+        buffer.finish().await.map_err(spill_error)
+    }
+}
+
+/// A server-side failure to spill, reported as an internal error
+pub fn spill_error(e: io::Error) -> ApiError {
+    ApiError::internal(format!("Failed to buffer request body: {}", e))
+}
+
+/// A body being buffered
+pub struct SpoolBuffer<'a> {
+    spool: &'a Spool,
+    memory: BytesMut,
+    file: Option<tokio::fs::File>,
+    len: usize,
+}
+
+impl SpoolBuffer<'_> {
+    pub async fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.file.is_none() && self.memory.len() + data.len() <= self.spool.memory_threshold {
+            self.memory.extend_from_slice(data);
+            self.len += data.len();
+            return Ok(());
+        }
+        if self.file.is_none() {
+            let dir = self.spool.dir.clone();
+            let file = tokio::task::spawn_blocking(move || tempfile::tempfile_in(dir)).await.map_err(io::Error::other)??;
+            let mut file = tokio::fs::File::from_std(file);
+            file.write_all(&self.memory).await?;
+            self.memory = BytesMut::new();
+            self.spool.spills.fetch_add(1, Ordering::Relaxed);
+            self.file = Some(file);
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(data).await?;
+        }
+        self.len += data.len();
+        Ok(())
+    }
+
+    pub async fn finish(self) -> io::Result<SpooledBody> {
+        let Some(mut file) = self.file else {
+            return Ok(SpooledBody::Memory(self.memory.freeze()));
+        };
+        file.flush().await?;
+        let file = file.into_std().await;
+        self.spool.spilled_bytes.fetch_add(self.len as u64, Ordering::Relaxed);
+        // SAFETY: the file is unlinked and no longer written, so nothing else
+        // can change the mapped bytes
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(SpooledBody::Spilled(map))
+    }
+}
+
+/// A whole request body, in memory or mapped from its spill file
+#[derive(Debug)]
+pub enum SpooledBody {
+    Memory(Bytes),
+    Spilled(Mmap),
+}
+
+impl From<Bytes> for SpooledBody {
+    fn from(bytes: Bytes) -> Self {
+        SpooledBody::Memory(bytes)
+    }
+}
+
+impl Deref for SpooledBody {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            SpooledBody::Memory(bytes) => bytes,
+            SpooledBody::Spilled(map) => map,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spills_past_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::new(8, dir.path().to_path_buf());
+
+        let small = spool.read(Body::from("tiny"), 64).await.unwrap();
+        assert!(matches!(small, SpooledBody::Memory(_)));
+        assert_eq!(&*small, b"tiny");
+
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for part in ["0123456", "789abc", "def"] {
+                sender.send_data(Bytes::from(part)).await.unwrap();
+            }
+        });
+        let large = spool.read(body, 64).await.unwrap();
+        assert!(matches!(large, SpooledBody::Spilled(_)));
+        assert_eq!(&*large, b"0123456789abcdef");
+        assert_eq!((spool.spills(), spool.spilled_bytes()), (1, 16));
+        // The spill file is unlinked as soon as it's made
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        assert!(spool.read(Body::from("0123456789"), 4).await.is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Server-side encryption with customer-supplied keys (SSE-C)
+//!
+//! A PUT sending `x-wfldb-sse-customer-algorithm: AES256` and
+//! `x-wfldb-sse-customer-key` (base64 of a 32-byte key) stores the object
+//! encrypted with that key under AES-256-GCM, as
+//! `nonce: [u8; 12] | ciphertext | tag: [u8; 16]`. The key is used for the
+//! one request and never stored: the object's metadata keeps only its
+//! BLAKE3 hash, which a GET's key is checked against before decrypting.
+//! Responses carrying an encrypted object name the algorithm and the key's
+//! hash (hex) in `x-wfldb-sse-customer-key-hash`.
+//!
+//! A GET of an encrypted object answers 400 `customer_key_required`
+//! without a key and 403 `customer_key_mismatch` with another one; sending
+//! a key for an object that isn't encrypted answers 400. The key is checked
+//! before `If-None-Match`, so a 304 is never sent without it. Only plain
+//! PUTs and GETs take a key. Size and content hash describe the stored
+//! ciphertext, checksums the plaintext the client sent.
+//!
+//! An object is sealed as one buffer, so its ciphertext must fit inline
+//! (see [`StorageEngine::value_threshold`](wfldb_engine::StorageEngine::value_threshold)):
+//! a PUT body over [`max_plaintext`] answers 413.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use hyper::header::HeaderMap;
+use hyper::{Body, Response};
+use wfldb_auth::{headers, now_ms};
+use wfldb_core::{BucketId, ContentHash, Key};
+use wfldb_engine::Storage;
+use crate::error::ApiError;
+use crate::response_cache::CachedRead;
+
+/// The one algorithm customer keys are used with
+pub const ALGORITHM: &str = "AES256";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// A key a client sent with its request; deliberately not `Debug`
+pub struct CustomerKey {
+    key: [u8; KEY_LEN],
+}
+
+impl CustomerKey {
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        CustomerKey { key }
+    }
+
+    /// The key a request sends, if any
+    pub fn from_headers(request: &HeaderMap) -> Result<Option<Self>, ApiError> {
+        let algorithm = request.get(headers::SSE_CUSTOMER_ALGORITHM);
+        let key = request.get(headers::SSE_CUSTOMER_KEY);
+        let (algorithm, key) = match (algorithm, key) {
+            (None, None) => return Ok(None),
+            (Some(algorithm), Some(key)) => (algorithm, key),
+            _ => {
+                return Err(ApiError::bad_request(format!(
+                    "{} and {} must be sent together",
+                    headers::SSE_CUSTOMER_ALGORITHM,
+                    headers::SSE_CUSTOMER_KEY
+                )))
+            }
+        };
+        if algorithm.as_bytes() != ALGORITHM.as_bytes() {
+            return Err(ApiError::bad_request(format!("{} must be {}", headers::SSE_CUSTOMER_ALGORITHM, ALGORITHM)));
+        }
+        let key = STANDARD
+            .decode(key.as_bytes())
+            .ok()
+            .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+            .ok_or_else(|| {
+                ApiError::bad_request(format!("{} must be base64 of a {}-byte key", headers::SSE_CUSTOMER_KEY, KEY_LEN))
+            })?;
+        Ok(Some(CustomerKey::new(key)))
+    }
+
+    /// What the object's metadata keeps to recognize the key
+    pub fn hash(&self) -> ContentHash {
+        ContentHash::new(&self.key)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = self.cipher()
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("AES-GCM encrypts any length we store");
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt what [`encrypt`](Self::encrypt) produced; `None` if it was
+    /// altered or sealed with another key
+    pub fn decrypt(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher().decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.key).expect("key is 32 bytes")
+    }
+}
+
+/// Largest body a customer key encrypts, leaving its ciphertext inline
+pub fn max_plaintext(value_threshold: usize) -> usize {
+    value_threshold.saturating_sub(NONCE_LEN + TAG_LEN)
+}
+
+/// Whether a request sends any customer key header
+pub fn requested(request: &HeaderMap) -> bool {
+    request.contains_key(headers::SSE_CUSTOMER_ALGORITHM) || request.contains_key(headers::SSE_CUSTOMER_KEY)
+}
+
+/// Turn a GET's read of an encrypted object into its plaintext, checking
+/// `customer_key` first; reads of plain objects pass through
+///
+/// A chunked encrypted object is read whole, since the whole of it is one
+/// ciphertext, and only once the key checks out.
+pub fn open(
+    read: CachedRead,
+    customer_key: Option<&CustomerKey>,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    key: &Key,
+) -> Result<CachedRead, ApiError> {
+    let (sealed, metadata) = match read {
+        CachedRead::Found(data, metadata) => (data, metadata),
+        CachedRead::NotModified(metadata) => {
+            check_key(metadata.customer_key_hash.as_ref(), customer_key)?;
+            return Ok(CachedRead::NotModified(metadata));
+        }
+        CachedRead::Chunked(_, metadata) if metadata.customer_key_hash.is_some() => {
+            check_key(metadata.customer_key_hash.as_ref(), customer_key)?;
+            match storage.get_versioned(bucket_id, key).map_err(|e| ApiError::from_storage(&e, now_ms()))? {
+                Some((data, metadata)) => (Bytes::from(data), metadata),
+                None => return Ok(CachedRead::Missing),
+            }
+        }
+        CachedRead::Chunked(..) if customer_key.is_some() => return Err(not_encrypted()),
+        other => return Ok(other),
+    };
+    check_key(metadata.customer_key_hash.as_ref(), customer_key)?;
+    let Some(customer_key) = customer_key else {
+        return Ok(CachedRead::Found(sealed, metadata));
+    };
+    match customer_key.decrypt(&sealed) {
+        Some(plaintext) => Ok(CachedRead::Found(Bytes::from(plaintext), metadata)),
+        None => Err(ApiError::internal(format!("Encrypted object {} does not decrypt with its key", key))),
+    }
+}
+
+/// Check the request's key against the hash of the one the object is
+/// encrypted with, if it is
+fn check_key(stored_hash: Option<&ContentHash>, customer_key: Option<&CustomerKey>) -> Result<(), ApiError> {
+    match (stored_hash, customer_key) {
+        (None, None) => Ok(()),
+        (None, Some(_)) => Err(not_encrypted()),
+        (Some(_), None) => Err(ApiError::customer_key_required()),
+        (Some(stored_hash), Some(customer_key)) if customer_key.hash() != *stored_hash => {
+            Err(ApiError::customer_key_mismatch())
+        }
+        (Some(_), Some(_)) => Ok(()),
+    }
+}
+
+fn not_encrypted() -> ApiError {
+    ApiError::bad_request("Object is not encrypted with a customer key")
+}
+
+/// Name the algorithm and key hash of an encrypted object in a response
+pub fn add_headers(response: &mut Response<Body>, customer_key_hash: &ContentHash) {
+    let response_headers = response.headers_mut();
+    response_headers.insert(headers::SSE_CUSTOMER_ALGORITHM, ALGORITHM.parse().unwrap());
+    response_headers.insert(headers::SSE_CUSTOMER_KEY_HASH, customer_key_hash.to_hex().parse().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(algorithm: &str, key: &str) -> HeaderMap {
+        let mut request = HeaderMap::new();
+        request.insert(headers::SSE_CUSTOMER_ALGORITHM, algorithm.parse().unwrap());
+        request.insert(headers::SSE_CUSTOMER_KEY, key.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn test_keys_from_headers_seal_and_open() {
+        assert!(CustomerKey::from_headers(&HeaderMap::new()).unwrap().is_none());
+        let encoded = STANDARD.encode([7u8; 32]);
+        let key = CustomerKey::from_headers(&request(ALGORITHM, &encoded)).unwrap().unwrap();
+        assert_eq!(key.hash(), ContentHash::new(&[7u8; 32]));
+        assert!(CustomerKey::from_headers(&request("AES128", &encoded)).is_err());
+        assert!(CustomerKey::from_headers(&request(ALGORITHM, &STANDARD.encode([7u8; 16]))).is_err());
+        let mut key_only = request(ALGORITHM, &encoded);
+        key_only.remove(headers::SSE_CUSTOMER_ALGORITHM);
+        assert!(CustomerKey::from_headers(&key_only).is_err());
+
+        let sealed = key.encrypt(b"meow");
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + 4], b"meow");
+        assert_ne!(key.encrypt(b"meow"), sealed);
+        assert_eq!(key.encrypt(&vec![0u8; max_plaintext(64 * 1024)]).len(), 64 * 1024);
+        assert_eq!(key.decrypt(&sealed).unwrap(), b"meow");
+        assert!(CustomerKey::new([8u8; 32]).decrypt(&sealed).is_none());
+        let mut altered = sealed.clone();
+        altered[NONCE_LEN] ^= 1;
+        assert!(key.decrypt(&altered).is_none());
+    }
+
+    #[test]
+    fn test_open_checks_the_key() {
+        let temp = tempfile::tempdir().unwrap();
+        let storage = Storage::new(wfldb_engine::StorageEngine::new(temp.path()).unwrap());
+        let bucket_id = BucketId::new("vault").unwrap();
+        let (secret, plain) = (Key::new("secret").unwrap(), Key::new("plain").unwrap());
+        let customer_key = CustomerKey::new([7u8; 32]);
+        let sealed = customer_key.encrypt(b"meow");
+        let hash = Some(customer_key.hash());
+        storage.put_object_if(&bucket_id, &secret, &sealed, None, Default::default(), None, hash, None).unwrap();
+        storage.put_object(&bucket_id, &plain, b"purr").unwrap();
+        let read = |key: &Key, customer_key: Option<&CustomerKey>| {
+            let read = crate::response_cache::read_object(&storage, None, &bucket_id, key, None, true).unwrap();
+            open(read, customer_key, &storage, &bucket_id, key).map_err(|e| e.into_response().status())
+        };
+
+        match read(&secret, Some(&customer_key)) {
+            Ok(CachedRead::Found(data, metadata)) => {
+                assert_eq!(&data[..], b"meow");
+                assert_eq!(metadata.customer_key_hash, Some(customer_key.hash()));
+            }
+            _ => panic!("expected the plaintext"),
+        }
+        assert_eq!(read(&secret, None).err(), Some(hyper::StatusCode::BAD_REQUEST));
+        let other = CustomerKey::new([8u8; 32]);
+        assert_eq!(read(&secret, Some(&other)).err(), Some(hyper::StatusCode::FORBIDDEN));
+        assert!(matches!(read(&plain, None), Ok(CachedRead::Found(data, _)) if &data[..] == b"purr"));
+        assert_eq!(read(&plain, Some(&customer_key)).err(), Some(hyper::StatusCode::BAD_REQUEST));
+
+        // A matching If-None-Match still needs the key before it answers 304
+        let current = crate::response_cache::etag(&storage.get_metadata(&bucket_id, &secret).unwrap().unwrap().version);
+        let revalidate = |customer_key: Option<&CustomerKey>| {
+            let read = crate::response_cache::read_object(&storage, None, &bucket_id, &secret, Some(&current), true).unwrap();
+            open(read, customer_key, &storage, &bucket_id, &secret).map_err(|e| e.into_response().status())
+        };
+        assert_eq!(revalidate(None).err(), Some(hyper::StatusCode::BAD_REQUEST));
+        assert_eq!(revalidate(Some(&other)).err(), Some(hyper::StatusCode::FORBIDDEN));
+        assert!(matches!(revalidate(Some(&customer_key)), Ok(CachedRead::NotModified(_))));
+    }
+}
@@ -0,0 +1,480 @@
+//! Background task scheduling
+//!
+//! Storage maintenance (flushes, and later GC, scrubbing, and compaction)
+//! runs through one `TaskManager` instead of free-running loops, so the work
+//! can be seen and controlled in one place: each task has its own interval,
+//! a shared cap on how many run at once, optional IO and CPU throttles, and
+//! can be paused, resumed, or run immediately through the admin API.
+//!
+//! Tasks run on the blocking pool and cooperate with their throttle through
+//! `TaskContext`: they report bytes moved with `io` and call `checkpoint`
+//! between units of work, which sleeps as needed and tells them to stop
+//! early when the task has been paused.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, Semaphore};
+use tracing::{debug, error, warn};
+use wfldb_auth::now_ms;
+use wfldb_engine::{rebuild_refcounts, RefcountReport, StorageEngine};
+use crate::health::TaskHeartbeats;
+
+/// Work done between CPU throttle pauses
+const CPU_SLICE: Duration = Duration::from_millis(50);
+
+/// Background tasks allowed to run at once unless configured otherwise
+pub const DEFAULT_MAX_RUNNING: usize = 1;
+
+/// A periodic unit of background work
+pub trait BackgroundTask: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// Time between the end of one run and the start of the next
+    fn interval(&self) -> Duration;
+
+    /// Run one pass on a blocking thread, returning a short summary
+    fn run(&self, ctx: &mut TaskContext) -> wfldb_core::Result<String>;
+}
+
+/// Limits a task's resource use; `None` means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Throttle {
+    #[serde(default)]
+    pub io_bytes_per_sec: Option<u64>,
+    /// Share of wall time the task may spend working, 1-100
+    #[serde(default)]
+    pub cpu_percent: Option<u8>,
+}
+
+impl Throttle {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.cpu_percent {
+            Some(0) | Some(101..) => Err("cpu_percent must be between 1 and 100".to_string()),
+            _ if self.io_bytes_per_sec == Some(0) => Err("io_bytes_per_sec must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Scheduling state of one task, as reported by the admin API
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub interval_ms: u64,
+    pub paused: bool,
+    pub running: bool,
+    pub throttle: Throttle,
+    pub runs: u64,
+    pub failures: u64,
+    /// Start of the last run, in milliseconds since the Unix epoch
+    pub last_run_ms: Option<u64>,
+    pub last_duration_ms: Option<u64>,
+    pub last_result: Option<String>,
+    pub last_error: Option<String>,
+    /// When the next run is due; `None` while paused or running
+    pub next_run_ms: Option<u64>,
+}
+
+struct ManagedTask {
+    task: Arc<dyn BackgroundTask>,
+    status: Mutex<TaskStatus>,
+    trigger: Notify,
+}
+
+impl ManagedTask {
+    fn paused(&self) -> bool {
+        self.status.lock().unwrap().paused
+    }
+
+    fn snapshot(&self) -> TaskStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        if status.paused {
+            status.next_run_ms = None;
+        }
+        status
+    }
+}
+
+/// Handed to a running task to apply its throttle
+pub struct TaskContext {
+    managed: Arc<ManagedTask>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+    throttle: Throttle,
+    started: Instant,
+    io_bytes: u64,
+    slice_started: Instant,
+}
+
+impl TaskContext {
+    fn new(managed: Arc<ManagedTask>, heartbeats: Option<Arc<TaskHeartbeats>>) -> Self {
+        let throttle = managed.status.lock().unwrap().throttle;
+        let now = Instant::now();
+        TaskContext {
+            managed,
+            heartbeats,
+            throttle,
+            started: now,
+            io_bytes: 0,
+            slice_started: now,
+        }
+    }
+
+    /// Account for `bytes` read or written, sleeping to stay under the IO limit
+    pub fn io(&mut self, bytes: u64) {
+        self.io_bytes += bytes;
+        if let Some(limit) = self.throttle.io_bytes_per_sec {
+            let allowed_at = Duration::from_secs_f64(self.io_bytes as f64 / limit as f64);
+            if let Some(ahead) = allowed_at.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(ahead);
+            }
+        }
+    }
+
+    /// Between units of work: rest to honour the CPU limit, and report
+    /// whether to keep going (`false` once the task is paused)
+    pub fn checkpoint(&mut self) -> bool {
+        if let Some(heartbeats) = &self.heartbeats {
+            heartbeats.beat(self.managed.task.name());
+        }
+        if let Some(percent) = self.throttle.cpu_percent.filter(|p| *p < 100) {
+            let busy = self.slice_started.elapsed();
+            if busy >= CPU_SLICE {
+                std::thread::sleep(busy * (100 - percent as u32) / percent as u32);
+                self.slice_started = Instant::now();
+            }
+        }
+        !self.managed.paused()
+    }
+}
+
+/// Slots tasks run in; shrinking retires permits as running tasks return them
+struct Slots {
+    semaphore: Semaphore,
+    max: AtomicUsize,
+    /// Permits still to retire once their tasks finish
+    surplus: AtomicUsize,
+}
+
+impl Slots {
+    fn new(max: usize) -> Self {
+        Slots { semaphore: Semaphore::new(max), max: AtomicUsize::new(max), surplus: AtomicUsize::new(0) }
+    }
+
+    fn resize(&self, max: usize) {
+        let previous = self.max.swap(max, Ordering::SeqCst);
+        if max > previous {
+            // Permits not yet retired come back first
+            let grow = max - previous;
+            let kept = self.surplus.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| Some(s.saturating_sub(grow)));
+            self.semaphore.add_permits(grow - kept.unwrap_or(0).min(grow));
+        } else {
+            let shrink = previous - max;
+            let retired = self.semaphore.forget_permits(shrink);
+            self.surplus.fetch_add(shrink - retired, Ordering::SeqCst);
+        }
+    }
+
+    /// Whether a returned permit should be retired instead
+    fn retire(&self) -> bool {
+        self.surplus.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| s.checked_sub(1)).is_ok()
+    }
+}
+
+/// Runs registered background tasks on their intervals
+pub struct TaskManager {
+    tasks: BTreeMap<&'static str, Arc<ManagedTask>>,
+    slots: Arc<Slots>,
+    heartbeats: Option<Arc<TaskHeartbeats>>,
+}
+
+impl TaskManager {
+    /// Manager letting at most `max_running` tasks run at once
+    pub fn new(max_running: usize) -> Self {
+        TaskManager {
+            tasks: BTreeMap::new(),
+            slots: Arc::new(Slots::new(max_running.max(1))),
+            heartbeats: None,
+        }
+    }
+
+    /// Report each task's progress to the liveness probe
+    pub fn with_heartbeats(mut self, heartbeats: Arc<TaskHeartbeats>) -> Self {
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Add a task; it starts with the other tasks in `start`
+    pub fn register(&mut self, task: Arc<dyn BackgroundTask>) {
+        let status = TaskStatus {
+            name: task.name(),
+            interval_ms: task.interval().as_millis() as u64,
+            ..Default::default()
+        };
+        if let Some(heartbeats) = &self.heartbeats {
+            heartbeats.register(task.name(), task.interval());
+        }
+        self.tasks.insert(
+            task.name(),
+            Arc::new(ManagedTask { task, status: Mutex::new(status), trigger: Notify::new() }),
+        );
+    }
+
+    /// Spawn the scheduling loop of every registered task
+    pub fn start(&self) {
+        for managed in self.tasks.values() {
+            tokio::spawn(schedule(managed.clone(), self.slots.clone(), self.heartbeats.clone()));
+        }
+    }
+
+    /// Status of every task, by name
+    pub fn status(&self) -> Vec<TaskStatus> {
+        self.tasks.values().map(|m| m.snapshot()).collect()
+    }
+
+    /// Pause or resume a task; a running pass stops at its next checkpoint.
+    /// Returns `None` for an unknown task.
+    pub fn set_paused(&self, name: &str, paused: bool) -> Option<TaskStatus> {
+        let managed = self.tasks.get(name)?;
+        managed.status.lock().unwrap().paused = paused;
+        Some(managed.snapshot())
+    }
+
+    /// Replace a task's throttle, taking effect from its next run
+    pub fn set_throttle(&self, name: &str, throttle: Throttle) -> Option<TaskStatus> {
+        let managed = self.tasks.get(name)?;
+        managed.status.lock().unwrap().throttle = throttle;
+        Some(managed.snapshot())
+    }
+
+    /// Tasks allowed to run at once
+    pub fn max_running(&self) -> usize {
+        self.slots.max.load(Ordering::SeqCst)
+    }
+
+    /// Change how many tasks may run at once; when lowered, running tasks
+    /// finish and the limit holds as they do
+    pub fn set_max_running(&self, max_running: usize) {
+        self.slots.resize(max_running.max(1));
+    }
+
+    /// Whether a task exists and is paused
+    pub fn is_paused(&self, name: &str) -> Option<bool> {
+        self.tasks.get(name).map(|managed| managed.paused())
+    }
+
+    /// Start a task's next run now instead of waiting out its interval
+    pub fn run_now(&self, name: &str) -> bool {
+        match self.tasks.get(name) {
+            Some(managed) => {
+                managed.trigger.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+async fn schedule(managed: Arc<ManagedTask>, slots: Arc<Slots>, heartbeats: Option<Arc<TaskHeartbeats>>) {
+    let name = managed.task.name();
+    let interval = managed.task.interval();
+    loop {
+        managed.status.lock().unwrap().next_run_ms = Some(now_ms() + interval.as_millis() as u64);
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = managed.trigger.notified() => {}
+        }
+        if let Some(heartbeats) = &heartbeats {
+            heartbeats.beat(name);
+        }
+        if managed.paused() {
+            continue;
+        }
+
+        let slot = slots.semaphore.acquire().await.expect("task slots are never closed");
+        let started_ms = now_ms();
+        let started = Instant::now();
+        {
+            let mut status = managed.status.lock().unwrap();
+            status.running = true;
+            status.next_run_ms = None;
+        }
+        let mut ctx = TaskContext::new(managed.clone(), heartbeats.clone());
+        let task = managed.task.clone();
+        let result = tokio::task::spawn_blocking(move || task.run(&mut ctx)).await;
+
+        let mut status = managed.status.lock().unwrap();
+        status.running = false;
+        status.runs += 1;
+        status.last_run_ms = Some(started_ms);
+        status.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        match result {
+            Ok(Ok(summary)) => {
+                debug!("Background task {}: {}", name, summary);
+                status.last_result = Some(summary);
+                status.last_error = None;
+            }
+            Ok(Err(e)) => {
+                error!("Background task {} failed: {}", name, e);
+                status.failures += 1;
+                status.last_error = Some(e.to_string());
+            }
+            Err(e) => {
+                error!("Background task {} panicked: {}", name, e);
+                status.failures += 1;
+                status.last_error = Some(e.to_string());
+            }
+        }
+        if slots.retire() {
+            slot.forget();
+        }
+    }
+}
+
+/// Interval between scheduled chunk reference count checks
+const REFCOUNT_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Verifies chunk reference counts against every manifest, repairing them
+/// on the run after `request_repair`
+pub struct RefcountCheck {
+    engine: StorageEngine,
+    repair_next: AtomicBool,
+    last_report: Mutex<Option<RefcountReport>>,
+}
+
+impl RefcountCheck {
+    pub const NAME: &'static str = "refcount_check";
+
+    pub fn new(engine: StorageEngine) -> Self {
+        RefcountCheck {
+            engine,
+            repair_next: AtomicBool::new(false),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Make the next run write corrected counts and remove orphans
+    pub fn request_repair(&self) {
+        self.repair_next.store(true, Ordering::SeqCst);
+    }
+
+    /// Report of the last finished run
+    pub fn last_report(&self) -> Option<RefcountReport> {
+        self.last_report.lock().unwrap().clone()
+    }
+}
+
+impl BackgroundTask for RefcountCheck {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn interval(&self) -> Duration {
+        REFCOUNT_CHECK_INTERVAL
+    }
+
+    fn run(&self, ctx: &mut TaskContext) -> wfldb_core::Result<String> {
+        let repair = self.repair_next.swap(false, Ordering::SeqCst);
+        let report = rebuild_refcounts(&self.engine, repair, &mut |bytes| {
+            ctx.io(bytes);
+            ctx.checkpoint()
+        })?;
+        if !report.is_clean() {
+            warn!("Chunk {}", report);
+        }
+        let summary = report.to_string();
+        *self.last_report.lock().unwrap() = Some(report);
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct Counter {
+        runs: AtomicU64,
+    }
+
+    impl BackgroundTask for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_secs(3600)
+        }
+
+        fn run(&self, _ctx: &mut TaskContext) -> wfldb_core::Result<String> {
+            let runs = self.runs.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(format!("run {}", runs))
+        }
+    }
+
+    async fn runs_after_trigger(manager: &TaskManager) -> u64 {
+        assert!(manager.run_now("counter"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        manager.status()[0].runs
+    }
+
+    #[test]
+    fn test_slots_resize_under_running_tasks() {
+        let slots = Slots::new(2);
+        let (first, second) = (slots.semaphore.try_acquire().unwrap(), slots.semaphore.try_acquire().unwrap());
+        // Both slots are taken, so the one going away is retired when returned
+        slots.resize(1);
+        assert!(slots.retire());
+        first.forget();
+        assert!(!slots.retire());
+        drop(second);
+        assert_eq!(slots.semaphore.available_permits(), 1);
+
+        // Growing back before a retirement cancels it instead
+        let held = slots.semaphore.try_acquire().unwrap();
+        slots.resize(0);
+        slots.resize(3);
+        assert!(!slots.retire());
+        drop(held);
+        assert_eq!(slots.semaphore.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_now_and_pause() {
+        let counter = Arc::new(Counter { runs: AtomicU64::new(0) });
+        let mut manager = TaskManager::new(1);
+        manager.register(counter.clone());
+        manager.start();
+        tokio::task::yield_now().await;
+
+        assert_eq!(runs_after_trigger(&manager).await, 1);
+        assert_eq!(manager.status()[0].last_result.as_deref(), Some("run 1"));
+
+        manager.set_paused("counter", true).unwrap();
+        assert_eq!(runs_after_trigger(&manager).await, 1);
+
+        manager.set_paused("counter", false).unwrap();
+        assert_eq!(runs_after_trigger(&manager).await, 2);
+        assert!(!manager.run_now("missing"));
+    }
+
+    #[test]
+    fn test_io_throttle_paces_work() {
+        let manager = {
+            let mut manager = TaskManager::new(1);
+            manager.register(Arc::new(Counter { runs: AtomicU64::new(0) }));
+            manager
+        };
+        manager.set_throttle("counter", Throttle { io_bytes_per_sec: Some(10_000), cpu_percent: None });
+        let mut ctx = TaskContext::new(manager.tasks["counter"].clone(), None);
+
+        let started = Instant::now();
+        ctx.io(1_000);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(ctx.checkpoint());
+
+        assert!(Throttle { io_bytes_per_sec: None, cpu_percent: Some(0) }.validate().is_err());
+    }
+}
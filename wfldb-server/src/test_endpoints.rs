@@ -0,0 +1,155 @@
+//! Transport test endpoints, compiled in with the `test-endpoints` feature
+//!
+//! `GET /_test/stream?bytes=N&chunk=C&delay_ms=D` streams N bytes in chunks
+//! of C, sleeping before each chunk; `D` may be a comma-separated list the
+//! chunks cycle through. Each chunk is sent only once the connection has
+//! room for it, so a slow reader holds the stream back rather than making
+//! the server buffer it. `POST /_test/upload?delay_ms=D` reads the body a
+//! frame at a time with the same sleeps, so a fast writer is held back by
+//! a slow server, and answers with the bytes and frames it read.
+//! `GET /_test/stats` reports bytes streamed and requests in flight here,
+//! so tests can compare what the server sent with what they read.
+//!
+//! These run through the full request path of the real server, which is
+//! what the transport characterization tests measure.
+
+use futures::StreamExt;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use wfldb_net::query_param;
+use crate::error::ApiError;
+use crate::router::json_response;
+
+/// Path prefix of every test endpoint
+pub const PREFIX: &str = "/_test/";
+
+/// Largest chunk a stream may ask for
+const MAX_CHUNK: u64 = 16 * 1024 * 1024;
+
+static STREAMED_BYTES: AtomicU64 = AtomicU64::new(0);
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+static MAX_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a request as in flight until dropped
+struct InFlight;
+
+impl InFlight {
+    fn enter() -> Self {
+        let now = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_IN_FLIGHT.fetch_max(now, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handle a request under [`PREFIX`]
+pub async fn handle(req: Request<Body>) -> Response<Body> {
+    let query = req.uri().query().unwrap_or_default().to_string();
+    let delays = match parse_delays(query_param(&query, "delay_ms").as_deref()) {
+        Ok(delays) => delays,
+        Err(e) => return ApiError::bad_request(e).into_response(),
+    };
+    match (req.method(), &req.uri().path()[PREFIX.len()..]) {
+        (&Method::GET, "stream") => {
+            let number = |name: &str, default: u64| {
+                query_param(&query, name).map_or(Ok(default), |v| v.parse::<u64>().map_err(|_| v))
+            };
+            match (number("bytes", 0), number("chunk", 64 * 1024)) {
+                (Ok(bytes), Ok(chunk)) if (1..=MAX_CHUNK).contains(&chunk) => stream(bytes, chunk, delays),
+                _ => ApiError::bad_request("bytes and chunk must be numbers, chunk from 1 to 16MB").into_response(),
+            }
+        }
+        (&Method::POST, "upload") => upload(req.into_body(), delays).await,
+        (&Method::GET, "stats") => json_response(StatusCode::OK, json!({
+            "streamed_bytes": STREAMED_BYTES.load(Ordering::SeqCst),
+            "in_flight": IN_FLIGHT.load(Ordering::SeqCst),
+            "max_in_flight": MAX_IN_FLIGHT.load(Ordering::SeqCst),
+        })),
+        _ => ApiError::not_found("Unknown test endpoint").into_response(),
+    }
+}
+
+fn parse_delays(spec: Option<&str>) -> Result<Vec<Duration>, String> {
+    let Some(spec) = spec else {
+        return Ok(Vec::new());
+    };
+    spec.split(',')
+        .map(|ms| ms.trim().parse().map(Duration::from_millis).map_err(|_| format!("invalid delay_ms '{}'", ms)))
+        .collect()
+}
+
+fn stream(bytes: u64, chunk: u64, delays: Vec<Duration>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let _in_flight = InFlight::enter();
+        let mut remaining = bytes;
+        let mut delays = delays.iter().cycle();
+        while remaining > 0 {
+            if let Some(delay) = delays.next().filter(|d| !d.is_zero()) {
+                tokio::time::sleep(*delay).await;
+            }
+            let len = remaining.min(chunk);
+            // Waits for the client to take the previous chunk
+            if sender.send_data(vec![0x2a; len as usize].into()).await.is_err() {
+                break;
+            }
+            STREAMED_BYTES.fetch_add(len, Ordering::SeqCst);
+            remaining -= len;
+        }
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/octet-stream")
+        .header("content-length", bytes.to_string())
+        .body(body)
+        .unwrap()
+}
+
+async fn upload(mut body: Body, delays: Vec<Duration>) -> Response<Body> {
+    let _in_flight = InFlight::enter();
+    let mut delays = delays.iter().cycle();
+    let (mut bytes, mut frames) = (0u64, 0u64);
+    while let Some(frame) = body.next().await {
+        match frame {
+            Ok(data) => {
+                bytes += data.len() as u64;
+                frames += 1;
+            }
+            Err(_) => return ApiError::bad_request("Failed to read request body").into_response(),
+        }
+        if let Some(delay) = delays.next().filter(|d| !d.is_zero()) {
+            tokio::time::sleep(*delay).await;
+        }
+    }
+    json_response(StatusCode::OK, json!({"bytes": bytes, "frames": frames}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delays() {
+        assert_eq!(parse_delays(None), Ok(vec![]));
+        assert_eq!(
+            parse_delays(Some("50,20, 10")),
+            Ok(vec![Duration::from_millis(50), Duration::from_millis(20), Duration::from_millis(10)])
+        );
+        assert!(parse_delays(Some("50,soon")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_sends_requested_bytes() {
+        let response = handle(Request::get("/_test/stream?bytes=100000&chunk=30000").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body.len(), 100_000);
+    }
+}
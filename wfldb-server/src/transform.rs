@@ -0,0 +1,211 @@
+//! Read-side content transforms
+//!
+//! A transform rewrites an object's bytes on the way out of a GET, chosen
+//! with `?transform=name` (or several, comma-separated, applied in order).
+//! Transforms are compiled in: implement [`Transform`] and register it in
+//! [`TransformRegistry::default`]. Operators can turn any of them off with
+//! `--disable-transform`. Stored objects are never modified.
+//!
+//! A key packet may limit which transforms its holder can request (see
+//! [`Permissions::can_transform`](wfldb_auth::Permissions::can_transform));
+//! with auth enabled, anonymous requests can't request any.
+//!
+//! Built in:
+//! - `gunzip`: inflate a gzip-compressed object
+//! - `redact`: drop the fields listed in `?redact=` (top-level names or
+//!   JSON Pointers) from a JSON document
+
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use wfldb_engine::parse_document;
+use wfldb_net::query_param;
+
+/// Most transforms one request may chain
+const MAX_CHAIN: usize = 4;
+
+/// Largest output a transform may produce, so a small compressed object
+/// can't expand without bound
+pub const MAX_TRANSFORM_OUTPUT: u64 = 64 * 1024 * 1024;
+
+/// Rewrites object bytes on read
+pub trait Transform: Send + Sync {
+    /// Name requested with `?transform=`
+    fn name(&self) -> &'static str;
+
+    /// Content type of the output; `None` keeps the response's
+    fn content_type(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Transform `data`; `query` is the request's query string, for
+    /// transform-specific parameters
+    fn apply(&self, data: Vec<u8>, query: &str) -> Result<Vec<u8>, String>;
+}
+
+/// Failure to apply a requested transform chain
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransformError {
+    Unknown(String),
+    NotPermitted(String),
+    Failed { name: String, reason: String },
+}
+
+impl std::fmt::Display for TransformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransformError::Unknown(name) => write!(f, "unknown transform '{}'", name),
+            TransformError::NotPermitted(name) => write!(f, "transform '{}' is not permitted", name),
+            TransformError::Failed { name, reason } => write!(f, "transform '{}' failed: {}", name, reason),
+        }
+    }
+}
+
+/// Transforms available to GET requests, by name
+#[derive(Clone)]
+pub struct TransformRegistry {
+    transforms: BTreeMap<&'static str, Arc<dyn Transform>>,
+}
+
+impl Default for TransformRegistry {
+    fn default() -> Self {
+        let mut registry = TransformRegistry { transforms: BTreeMap::new() };
+        registry.register(Arc::new(Gunzip));
+        registry.register(Arc::new(Redact));
+        registry
+    }
+}
+
+impl TransformRegistry {
+    /// Add a transform, replacing any with the same name
+    pub fn register(&mut self, transform: Arc<dyn Transform>) {
+        self.transforms.insert(transform.name(), transform);
+    }
+
+    /// Remove a transform, returning whether it was registered
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.transforms.remove(name).is_some()
+    }
+
+    /// Apply the comma-separated chain `names` to `data`, checking each name
+    /// with `allowed`. Returns the output and its content type, if changed.
+    pub fn apply(
+        &self,
+        names: &str,
+        data: Vec<u8>,
+        query: &str,
+        allowed: impl Fn(&str) -> bool,
+    ) -> Result<(Vec<u8>, Option<&'static str>), TransformError> {
+        let mut chain = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()).take(MAX_CHAIN) {
+            let transform = self.transforms.get(name).ok_or_else(|| TransformError::Unknown(name.to_string()))?;
+            if !allowed(name) {
+                return Err(TransformError::NotPermitted(name.to_string()));
+            }
+            chain.push(transform);
+        }
+        let mut data = data;
+        let mut content_type = None;
+        for transform in chain {
+            data = transform.apply(data, query).map_err(|reason| TransformError::Failed {
+                name: transform.name().to_string(),
+                reason,
+            })?;
+            content_type = transform.content_type().or(content_type);
+        }
+        Ok((data, content_type))
+    }
+}
+
+/// Inflates gzip-compressed objects
+struct Gunzip;
+
+impl Transform for Gunzip {
+    fn name(&self) -> &'static str {
+        "gunzip"
+    }
+
+    fn apply(&self, data: Vec<u8>, _query: &str) -> Result<Vec<u8>, String> {
+        let mut inflated = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .take(MAX_TRANSFORM_OUTPUT + 1)
+            .read_to_end(&mut inflated)
+            .map_err(|e| e.to_string())?;
+        if inflated.len() as u64 > MAX_TRANSFORM_OUTPUT {
+            return Err(format!("output exceeds {} bytes", MAX_TRANSFORM_OUTPUT));
+        }
+        Ok(inflated)
+    }
+}
+
+/// Removes fields from JSON documents
+struct Redact;
+
+impl Transform for Redact {
+    fn name(&self) -> &'static str {
+        "redact"
+    }
+
+    fn content_type(&self) -> Option<&'static str> {
+        Some("application/json")
+    }
+
+    fn apply(&self, data: Vec<u8>, query: &str) -> Result<Vec<u8>, String> {
+        let mut document = parse_document(&data).map_err(|e| e.to_string())?;
+        let fields = query_param(query, "redact").unwrap_or_default();
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let pointer = if field.starts_with('/') {
+                field.to_string()
+            } else {
+                format!("/{}", field.replace('~', "~0").replace('/', "~1"))
+            };
+            let Some((parent, last)) = pointer.rsplit_once('/') else {
+                continue;
+            };
+            let last = last.replace("~1", "/").replace("~0", "~");
+            match document.pointer_mut(parent) {
+                Some(Value::Object(object)) => {
+                    object.remove(&last);
+                }
+                Some(Value::Array(array)) => {
+                    if let Ok(index) = last.parse::<usize>() {
+                        if index < array.len() {
+                            array.remove(index);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(document.to_string().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_chain_and_permissions() {
+        let registry = TransformRegistry::default();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(br#"{"name":"Ada","ssn":"123","card":{"number":"4111","brand":"visa"}}"#).unwrap();
+        let stored = encoder.finish().unwrap();
+
+        let (out, content_type) = registry
+            .apply("gunzip,redact", stored.clone(), "redact=ssn,/card/number", |_| true)
+            .unwrap();
+        assert_eq!(content_type, Some("application/json"));
+        let out: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(out, serde_json::json!({"name": "Ada", "card": {"brand": "visa"}}));
+
+        let denied = registry.apply("gunzip", stored.clone(), "", |name| name == "redact");
+        assert_eq!(denied, Err(TransformError::NotPermitted("gunzip".to_string())));
+        assert!(matches!(registry.apply("thumbnail", stored, "", |_| true), Err(TransformError::Unknown(_))));
+    }
+}
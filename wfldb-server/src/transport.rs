@@ -0,0 +1,240 @@
+//! HTTP/2 flow control and streamed object bodies
+//!
+//! [`Http2Config`] sets the windows, stream limit, frame size and send
+//! buffer of every HTTP/2 connection; unset fields keep hyper's defaults.
+//!
+//! Chunked objects are sent with [`chunk_body`], which reads each chunk only
+//! once the previous one has been handed to the connection. hyper polls a
+//! body for more data only when the stream has send capacity (the client's
+//! window and the per-stream send buffer on HTTP/2, the write buffer on
+//! HTTP/1), so a client that reads slowly holds up its own stream's chunk
+//! reads instead of having the whole object buffered for it. A byte range
+//! of one (see [`parse_range`]) is sent with [`chunk_range_body`], which
+//! reads only the chunks the range overlaps.
+
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use hyper::server::Builder;
+use tracing::warn;
+use wfldb_core::{BucketId, ChunkManifest, ContentHash, Key, WflDBError};
+use wfldb_engine::StorageEngine;
+
+/// Largest flow-control window HTTP/2 allows
+const MAX_WINDOW: u32 = (1 << 31) - 1;
+
+/// Frame size limits from RFC 9113
+const FRAME_SIZE_RANGE: std::ops::RangeInclusive<u32> = (1 << 14)..=(1 << 24) - 1;
+
+/// HTTP/2 connection settings; `None` keeps hyper's default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Http2Config {
+    /// Bytes a client may send on one stream before it is acknowledged
+    pub stream_window: Option<u32>,
+    /// Bytes a client may send across a connection's streams
+    pub connection_window: Option<u32>,
+    /// Grow windows to the measured bandwidth-delay product instead
+    pub adaptive_window: bool,
+    pub max_concurrent_streams: Option<u32>,
+    pub max_frame_size: Option<u32>,
+    /// Response bytes queued per stream before the body stops being polled
+    pub max_send_buffer: Option<usize>,
+}
+
+impl Http2Config {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, window) in [("stream window", self.stream_window), ("connection window", self.connection_window)] {
+            if window.is_some_and(|window| window == 0 || window > MAX_WINDOW) {
+                return Err(format!("HTTP/2 {} must be between 1 and {} bytes", name, MAX_WINDOW));
+            }
+        }
+        if self.max_frame_size.is_some_and(|size| !FRAME_SIZE_RANGE.contains(&size)) {
+            return Err(format!(
+                "HTTP/2 max frame size must be between {} and {} bytes",
+                FRAME_SIZE_RANGE.start(),
+                FRAME_SIZE_RANGE.end()
+            ));
+        }
+        if self.max_concurrent_streams == Some(0) || self.max_send_buffer == Some(0) {
+            return Err("HTTP/2 stream limit and send buffer must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Apply the settings to a server being built
+    pub fn apply<I>(&self, mut builder: Builder<I>) -> Builder<I> {
+        if self.adaptive_window {
+            builder = builder.http2_adaptive_window(true);
+        } else {
+            builder = builder
+                .http2_initial_stream_window_size(self.stream_window)
+                .http2_initial_connection_window_size(self.connection_window);
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            builder = builder.http2_max_concurrent_streams(max);
+        }
+        if let Some(size) = self.max_frame_size {
+            builder = builder.http2_max_frame_size(size);
+        }
+        if let Some(size) = self.max_send_buffer {
+            builder = builder.http2_max_send_buf_size(size);
+        }
+        builder
+    }
+}
+
+/// Body of a chunked object, reading each chunk as the connection asks for it
+///
+/// The key isn't locked while the body is sent: if the object is replaced
+/// and its chunks released mid-stream, the body ends with an error and the
+/// client sees a truncated response rather than a mix of two versions.
+pub fn chunk_body(
+    engine: StorageEngine,
+    bucket_id: BucketId,
+    key: Key,
+    manifest: ChunkManifest,
+) -> impl Stream<Item = Result<Bytes, WflDBError>> + Send {
+    chunk_stream(engine, bucket_id, key, manifest.chunks)
+}
+
+/// Bytes `first` through `last` of a chunked object, reading only the
+/// chunks they overlap, like [`chunk_body`]
+///
+/// The manifest must record its chunk lengths, and the range must lie
+/// within the object.
+pub fn chunk_range_body(
+    engine: StorageEngine,
+    bucket_id: BucketId,
+    key: Key,
+    manifest: ChunkManifest,
+    (first, last): (u64, u64),
+) -> impl Stream<Item = Result<Bytes, WflDBError>> + Send {
+    let (mut chunks, mut skip, mut start) = (Vec::new(), 0, 0);
+    for (hash, size) in manifest.chunks.into_iter().zip(manifest.chunk_sizes) {
+        let end = start + size as u64;
+        if end > first && start <= last {
+            if chunks.is_empty() {
+                skip = (first - start) as usize;
+            }
+            chunks.push(hash);
+        }
+        start = end;
+    }
+    let mut remaining = (last - first + 1) as usize;
+    chunk_stream(engine, bucket_id, key, chunks).map_ok(move |chunk| {
+        let chunk = chunk.slice(skip.min(chunk.len())..);
+        skip = 0;
+        let take = remaining.min(chunk.len());
+        remaining -= take;
+        chunk.slice(..take)
+    })
+}
+
+/// What a `Range` header asks of an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// First and last byte, inclusive, within the object
+    Bytes(u64, u64),
+    /// Starts past the end, answered with 416
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for an object of `size` bytes
+///
+/// `None` for what is answered with the whole object instead: several
+/// ranges, units other than bytes and malformed values.
+pub fn parse_range(value: &str, size: u64) -> Option<RangeRequest> {
+    let (first, last) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    if last.contains(',') {
+        return None;
+    }
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || size == 0 {
+                return Some(RangeRequest::Unsatisfiable);
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (first, "") => (first.parse().ok()?, u64::MAX),
+        (first, last) => (first.parse().ok()?, last.parse().ok()?),
+    };
+    if last < first {
+        return None;
+    }
+    if first >= size {
+        return Some(RangeRequest::Unsatisfiable);
+    }
+    Some(RangeRequest::Bytes(first, last.min(size - 1)))
+}
+
+/// Chunks of an object read one at a time as the body is polled
+fn chunk_stream(
+    engine: StorageEngine,
+    bucket_id: BucketId,
+    key: Key,
+    chunks: Vec<ContentHash>,
+) -> impl Stream<Item = Result<Bytes, WflDBError>> + Send {
+    stream::iter(chunks)
+        .then(move |hash| {
+            let (engine, bucket_id) = (engine.clone(), bucket_id.clone());
+            async move {
+                tokio::task::spawn_blocking(move || engine.bucket(&bucket_id)?.get_chunk(&hash).map(|chunk| (hash, chunk)))
+                    .await
+                    .map_err(WflDBError::internal_from)?
+            }
+        })
+        .map(move |read| match read {
+            Ok((_, Some(chunk))) => Ok(Bytes::from(chunk)),
+            Ok((hash, None)) => {
+                warn!("Chunk {} of {} went missing while it was being sent", hash.to_hex(), key.as_str());
+                Err(WflDBError::MissingChunks(vec![hash.to_hex()]))
+            }
+            Err(e) => {
+                warn!("Reading a chunk of {} failed while it was being sent: {}", key.as_str(), e);
+                Err(e)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(RangeRequest::Bytes(0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(RangeRequest::Bytes(900, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some(RangeRequest::Bytes(900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(RangeRequest::Bytes(900, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(RangeRequest::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-0", 0), Some(RangeRequest::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-9,20-29", 1000), None);
+        assert_eq!(parse_range("bytes=9-0", 1000), None);
+        assert_eq!(parse_range("items=0-9", 1000), None);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_range_body() {
+        let temp = tempfile::tempdir().unwrap();
+        let engine = StorageEngine::new(temp.path()).unwrap();
+        let storage = wfldb_engine::Storage::new(engine.clone());
+        let (bucket_id, key) = (BucketId::new("images").unwrap(), Key::new("disk.img").unwrap());
+        let data: Vec<u8> = (0..3 * wfldb_engine::MAX_CHUNK_SIZE + 10).map(|i| (i % 251) as u8).collect();
+        let manifest = storage.put_object(&bucket_id, &key, &data).unwrap().chunk_manifest.unwrap();
+        for (first, last) in [(0, 9), (5, wfldb_engine::MAX_CHUNK_SIZE as u64 + 5), (data.len() as u64 - 3, data.len() as u64 - 1)] {
+            let body = chunk_range_body(engine.clone(), bucket_id.clone(), key.clone(), manifest.clone(), (first, last));
+            let read: Vec<Bytes> = body.try_collect().await.unwrap();
+            assert_eq!(read.concat(), &data[first as usize..=last as usize]);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Http2Config::default().validate().is_ok());
+        let config = Http2Config { stream_window: Some(4 << 20), max_frame_size: Some(64 << 10), ..Default::default() };
+        assert!(config.validate().is_ok());
+        assert!(Http2Config { max_frame_size: Some(1024), ..Default::default() }.validate().is_err());
+        assert!(Http2Config { connection_window: Some(u32::MAX), ..Default::default() }.validate().is_err());
+        assert!(Http2Config { max_concurrent_streams: Some(0), ..Default::default() }.validate().is_err());
+    }
+}
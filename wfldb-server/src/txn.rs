@@ -0,0 +1,297 @@
+//! Multi-key transactions within a bucket
+//!
+//! ```text
+//! POST /v1/{bucket}/_txn  {"operations": [
+//!     {"op": "put", "key": "a", "data": "<base64>", "if_version": "<version>"},
+//!     {"op": "delete", "key": "b", "if_absent": false},
+//!     {"op": "put", "key": "e", "data": "<base64>", "if_generation": 7},
+//!     {"op": "touch", "key": "d", "ttl_secs": 300, "if_version": "<version>"}
+//! ], "reads": [{"key": "c", "version": "<version>" | null}]}
+//! ```
+//!
+//! Either every operation applies or none does. `if_version` requires the
+//! key to be at exactly that version, `if_generation` at exactly that
+//! generation (a fencing token, see [`objects`](crate::objects)), and
+//! `if_absent` requires it not to exist; a failed precondition answers 412
+//! naming the key. Values are
+//! standard base64 and must fit inline (see
+//! [`StorageEngine::value_threshold`](wfldb_engine::StorageEngine::value_threshold)).
+//! A touch makes an existing key expire `ttl_secs` from now without
+//! rewriting it, like `POST /v1/{bucket}/{key}/_touch`. In a bucket with a
+//! schema every value put must match it, as a PUT's body must (see
+//! [`schema`](crate::schema)); the 422 names the first key that doesn't.
+//!
+//! `reads` makes the request an optimistic transaction: it lists the
+//! versions the client saw (GET returns them in `x-wfldb-version`, `null`
+//! for a 404), and if any of those keys has changed since, the commit
+//! answers 409 naming the key and the client retries from its reads.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wfldb_auth::{now_ms, AuthContext, BucketAcl};
+use wfldb_core::{BucketId, Key, Precondition, TxnOp, TxnToken, Version};
+use wfldb_engine::{Storage, MAX_TXN_OPS};
+use crate::{auth, objects};
+use crate::error::ApiError;
+use crate::quarantine::Holds;
+use crate::router::json_response;
+use crate::simple_server_fixed::ServerState;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Object key segment that addresses a bucket's transaction endpoint
+pub const TXN_SEGMENT: &str = "_txn";
+
+/// Room in a transaction body for each operation besides its value: its
+/// key, precondition, and a read of the key
+const OP_OVERHEAD_BYTES: usize = 4 * 1024;
+
+/// Largest transaction body: [`MAX_TXN_OPS`] operations, each with a
+/// base64 value that fits inline
+fn body_limit(value_threshold: usize) -> usize {
+    MAX_TXN_OPS * (value_threshold.div_ceil(3) * 4 + OP_OVERHEAD_BYTES)
+}
+
+#[derive(Deserialize)]
+struct TxnRequest {
+    operations: Vec<OpRequest>,
+    #[serde(default)]
+    reads: Vec<ReadRequest>,
+}
+
+#[derive(Deserialize)]
+struct ReadRequest {
+    key: String,
+    version: Option<Version>,
+}
+
+impl TxnRequest {
+    fn parse(self) -> Result<(TxnToken, Vec<TxnOp>), String> {
+        let mut token = TxnToken::new();
+        for read in self.reads {
+            let key = Key::new(&read.key).map_err(|_| format!("Invalid key '{}'", read.key))?;
+            token.observe(&key, read.version.as_ref());
+        }
+        let ops = self.operations.into_iter().map(OpRequest::into_op).collect::<Result<_, _>>()?;
+        Ok((token, ops))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum OpRequest {
+    Put {
+        key: String,
+        data: String,
+        if_version: Option<Version>,
+        if_generation: Option<u64>,
+        #[serde(default)]
+        if_absent: bool,
+    },
+    Delete {
+        key: String,
+        if_version: Option<Version>,
+        if_generation: Option<u64>,
+        #[serde(default)]
+        if_absent: bool,
+    },
+    Touch {
+        key: String,
+        ttl_secs: u64,
+        if_version: Option<Version>,
+        if_generation: Option<u64>,
+    },
+}
+
+impl OpRequest {
+    fn into_op(self) -> Result<TxnOp, String> {
+        let precondition = |if_version: Option<Version>, if_generation: Option<u64>, if_absent: bool| {
+            match (if_version, if_generation, if_absent) {
+                (None, None, false) => Ok(None),
+                (Some(version), None, false) => Ok(Some(Precondition::Version(version))),
+                (None, Some(generation), false) => Ok(Some(Precondition::Generation(generation))),
+                (None, None, true) => Ok(Some(Precondition::Absent)),
+                _ => Err("if_version, if_generation and if_absent are mutually exclusive".to_string()),
+            }
+        };
+        let parse_key = |key: &str| Key::new(key).map_err(|_| format!("Invalid key '{}'", key));
+        match self {
+            OpRequest::Put { key, data, if_version, if_generation, if_absent } => Ok(TxnOp::Put {
+                key: parse_key(&key)?,
+                data: STANDARD
+                    .decode(data)
+                    .map_err(|_| format!("Value for '{}' is not valid base64", key))?,
+                precondition: precondition(if_version, if_generation, if_absent)?,
+            }),
+            OpRequest::Delete { key, if_version, if_generation, if_absent } => Ok(TxnOp::Delete {
+                key: parse_key(&key)?,
+                precondition: precondition(if_version, if_generation, if_absent)?,
+            }),
+            OpRequest::Touch { key, ttl_secs: 0, .. } => Err(format!("ttl_secs for '{}' must be positive", key)),
+            OpRequest::Touch { key, ttl_secs, if_version, if_generation } => Ok(TxnOp::Touch {
+                key: parse_key(&key)?,
+                expires_at_ms: objects::expires_at(ttl_secs),
+                precondition: precondition(if_version, if_generation, false)?,
+            }),
+        }
+    }
+}
+
+/// Handle `POST /v1/{bucket}/_txn`; write permission on the bucket was
+/// already checked, deletes and ownership are checked here
+pub async fn handle(
+    req: Request<Body>,
+    state: &ServerState,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let body_bytes = match state.spool.read(req.into_body(), body_limit(state.storage.value_threshold())).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = auth::check_body(auth_ctx, &body_bytes, timings) {
+        return e.into_response();
+    }
+
+    let request: TxnRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(request) => request,
+        Err(e) => return ApiError::bad_request(format!("Invalid transaction: {}", e)).into_response(),
+    };
+    let (token, ops) = match request.parse() {
+        Ok(parsed) => parsed,
+        Err(e) => return ApiError::bad_request(e).into_response(),
+    };
+
+    if let (Some(authenticator), Some(ctx)) = (&state.auth, auth_ctx) {
+        if ops.iter().any(|op| matches!(op, TxnOp::Delete { .. })) {
+            if let Err(e) = ctx.authorize("DELETE", bucket_id) {
+                return auth::error_response(&e);
+            }
+        }
+        let acl = authenticator.bucket_acls().get(bucket_id);
+        if acl == BucketAcl::OwnerOnly && ctx.tenant().is_none() {
+            let keys = ops.iter().map(TxnOp::key).chain(token.reads.iter().map(|read| &read.key));
+            for key in keys {
+                let owner = match storage.get_metadata(bucket_id, key) {
+                    Ok(metadata) => metadata.and_then(|metadata| metadata.owner),
+                    Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+                };
+                if let Err(e) = ctx.authorize_owner(acl, owner.as_deref()) {
+                    return auth::error_response(&e);
+                }
+            }
+        }
+    }
+
+    for op in &ops {
+        if let TxnOp::Put { key, data, .. } = op {
+            if let Err(e) = timings.time(Phase::Storage, || state.schemas.check_put(storage, bucket_id, data)) {
+                return e.with_detail("key", key.as_str()).into_response();
+            }
+        }
+    }
+
+    let keys: Vec<Key> = ops.iter().map(|op| op.key().clone()).collect();
+    let expiries: Vec<Option<u64>> = ops
+        .iter()
+        .map(|op| match op {
+            TxnOp::Touch { expires_at_ms, .. } => Some(*expires_at_ms),
+            _ => None,
+        })
+        .collect();
+    let result = timings.time(Phase::Storage, || {
+        let _busy = state.diagnostics.enter_storage();
+        // Quarantine holds the keys a transaction puts, like any other write
+        let puts = ops.iter().filter(|op| matches!(op, TxnOp::Put { .. })).map(TxnOp::key);
+        let holds = match state.quarantine {
+            Some(_) => Some(Holds::take(storage, bucket_id, puts)?),
+            None => None,
+        };
+        let owner = auth_ctx.map(|ctx| ctx.key_id().to_string());
+        let result = storage.commit_as(bucket_id, token, ops, owner.as_deref());
+        if let Some(holds) = holds {
+            holds.settle();
+        }
+        result
+    });
+    match result {
+        Ok(results) => {
+            let bucket = storage.engine().namespaced(bucket_id);
+            // Touches leave the content alone
+            let changed = keys.iter().zip(&expiries).filter(|(_, expiry)| expiry.is_none()).map(|(key, _)| key);
+            for key in changed {
+                if let Some(cache) = &state.response_cache {
+                    cache.invalidate(&bucket, key.as_str());
+                }
+                if let Some(indexes) = &state.search {
+                    indexes.changed(&bucket, key);
+                }
+            }
+            let applied: Vec<Value> = keys
+                .iter()
+                .zip(results)
+                .zip(expiries)
+                .map(|((key, metadata), expires_at_ms)| match (metadata, expires_at_ms) {
+                    (Some(metadata), Some(expires_at_ms)) => json!({
+                        "key": key.as_str(),
+                        "version": metadata.version.to_string(),
+                        "expires_at_ms": expires_at_ms,
+                    }),
+                    (Some(metadata), None) => json!({
+                        "key": key.as_str(),
+                        "size": metadata.size,
+                        "version": metadata.version.to_string(),
+                        "generation": metadata.generation,
+                    }),
+                    (None, _) => json!({"key": key.as_str(), "deleted": true}),
+                })
+                .collect();
+            json_response(
+                StatusCode::OK,
+                json!({"success": true, "bucket": bucket_id.as_str(), "operations": applied}),
+            )
+        }
+        Err(e) => ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_operations() {
+        let request: TxnRequest = serde_json::from_str(
+            r#"{"operations": [
+                {"op": "put", "key": "a", "data": "aGk=", "if_absent": true},
+                {"op": "delete", "key": "b", "if_generation": 3},
+                {"op": "touch", "key": "d", "ttl_secs": 60}
+            ], "reads": [{"key": "c", "version": null}]}"#,
+        )
+        .unwrap();
+        let (token, ops) = request.parse().unwrap();
+        assert_eq!(token.reads.len(), 1);
+        assert_eq!(token.reads[0].version, None);
+        assert!(matches!(&ops[0], TxnOp::Put { data, precondition: Some(Precondition::Absent), .. } if data == b"hi"));
+        assert!(matches!(&ops[1], TxnOp::Delete { precondition: Some(Precondition::Generation(3)), .. }));
+        assert!(matches!(&ops[2], TxnOp::Touch { expires_at_ms, precondition: None, .. } if *expires_at_ms > now_ms()));
+
+        let both = OpRequest::Delete { key: "b".to_string(), if_version: Some(Version::new()), if_generation: None, if_absent: true };
+        assert!(both.into_op().is_err());
+        let both = OpRequest::Put { key: "a".to_string(), data: String::new(), if_version: Some(Version::new()), if_generation: Some(1), if_absent: false };
+        assert!(both.into_op().is_err());
+    }
+
+    #[test]
+    fn test_body_limit_fits_full_transactions() {
+        let threshold = 64 * 1024;
+        let op = json!({"op": "put", "key": "k".repeat(1024), "data": STANDARD.encode(vec![0u8; threshold]), "if_version": Version::new()});
+        let read = json!({"key": "k".repeat(1024), "version": Version::new()});
+        let body = json!({"operations": vec![op; MAX_TXN_OPS], "reads": vec![read; MAX_TXN_OPS]}).to_string();
+        assert!(body.len() <= body_limit(threshold));
+        assert!(body_limit(threshold) < 10 * 1024 * 1024);
+    }
+}
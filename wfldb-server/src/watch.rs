@@ -0,0 +1,184 @@
+//! Change feed of a bucket for clients
+//!
+//! ```text
+//! GET /v1/{bucket}/_watch[?after=N][&prefix=P][&wait_ms=W][&limit=L]
+//! ```
+//!
+//! Answers with the bucket's changes after journal sequence `after` under
+//! `prefix`, as `{"events": [{"seq", "key", "op", "version", "timestamp_ms"}],
+//! "next": N}`, where `op` is `put`, `delete`, `copy` or `write_range` and
+//! `next` (also in `x-wfldb-journal-next`) is the cursor to pass as `after`
+//! next time.
+//! Without `after` the feed starts at the journal's head, so only changes
+//! from then on are sent. When nothing has changed the request waits up to
+//! `wait_ms` (at most 30 s) for a change before answering with no events,
+//! so a client can follow a bucket with one request outstanding instead of
+//! polling. Events carry no object data. An object committed from chunks
+//! is a `put`; the chunks journaled ahead of it aren't objects and send no
+//! events.
+//!
+//! Watching needs the journal and, since events name keys, the same list
+//! permission on the prefix a listing of it would, so a public-read bucket
+//! still refuses anonymous watches. A cursor older than the
+//! records kept locally answers 410 with the oldest sequence still held;
+//! the caller missed changes and must re-read what it cached.
+
+use std::time::{Duration, Instant};
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use wfldb_auth::{now_ms, AuthContext};
+use wfldb_core::{BucketId, ChangeOp, ChangeRecord, WflDBError};
+use wfldb_engine::Storage;
+use wfldb_net::query_param;
+use crate::admin::JOURNAL_NEXT_HEADER;
+use crate::auth;
+use crate::error::ApiError;
+use crate::router::json_response;
+use crate::slow_log::{Phase, RequestTimings};
+
+/// Last path segment of a watch
+pub const WATCH_SEGMENT: &str = "_watch";
+
+/// Longest a watch waits for a change
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// How often a waiting watch checks the journal for new records
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Most events in one answer
+const MAX_EVENTS: usize = 1000;
+
+/// Whether `path` is a bucket's watch endpoint, "/v1/{bucket}/_watch"
+pub fn is_watch_path(path: &str) -> bool {
+    path.strip_prefix("/v1/")
+        .and_then(|rest| rest.split_once('/'))
+        .is_some_and(|(bucket, segment)| !bucket.is_empty() && segment == WATCH_SEGMENT)
+}
+
+/// Handle `GET /v1/{bucket}/_watch`; read permission on the bucket was
+/// already checked, listing the prefix is checked here
+pub async fn handle(
+    req: Request<Body>,
+    storage: &Storage,
+    bucket_id: &BucketId,
+    auth_ctx: Option<&AuthContext>,
+    timings: &mut RequestTimings,
+) -> Response<Body> {
+    let Some(journal) = storage.engine().journal().cloned() else {
+        return ApiError::not_found("Change journal disabled").into_response();
+    };
+    let query = req.uri().query().unwrap_or_default().to_string();
+    let query = query.as_str();
+    let prefix = query_param(query, "prefix").unwrap_or_default();
+    if let Some(ctx) = auth_ctx {
+        if let Err(e) = timings.time(Phase::Auth, || ctx.authorize_list(bucket_id, &prefix)) {
+            return auth::error_response(&e);
+        }
+    }
+    let mut after = match query_param(query, "after").map(|after| after.parse::<u64>()) {
+        Some(Ok(after)) => after,
+        Some(Err(_)) => return ApiError::bad_request("Invalid 'after' cursor").into_response(),
+        None => journal.next_seq().saturating_sub(1),
+    };
+    let wait = query_param(query, "wait_ms")
+        .and_then(|w| w.parse::<u64>().ok())
+        .map_or(Duration::ZERO, Duration::from_millis)
+        .min(MAX_WAIT);
+    let limit = query_param(query, "limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(MAX_EVENTS)
+        .clamp(1, MAX_EVENTS);
+
+    match journal.oldest_seq() {
+        Ok(oldest) if after + 1 < oldest => {
+            return ApiError::gone("Changes after this cursor were archived").with_detail("oldest", oldest).into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+    }
+
+    let bucket = storage.engine().namespaced(bucket_id);
+    let deadline = Instant::now() + wait;
+    loop {
+        // Only read segments once something was appended past the cursor
+        if journal.next_seq() > after + 1 {
+            let started = Instant::now();
+            let (journal, bucket) = (journal.clone(), bucket.clone());
+            let read = tokio::task::spawn_blocking(move || journal.read_after(after, Some(&bucket), limit))
+                .await
+                .map_err(WflDBError::internal_from)
+                .and_then(|read| read);
+            timings.record(Phase::Storage, started.elapsed());
+            let (records, next) = match read {
+                Ok(read) => read,
+                Err(e) => return ApiError::from_storage(&e, now_ms()).into_response(),
+            };
+            after = next;
+            let events: Vec<Value> = records.iter().filter(|record| record.key.starts_with(&prefix)).filter_map(event).collect();
+            if !events.is_empty() {
+                return answer(bucket_id, events, after);
+            }
+        }
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return answer(bucket_id, Vec::new(), after);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn answer(bucket_id: &BucketId, events: Vec<Value>, next: u64) -> Response<Body> {
+    let mut response = json_response(StatusCode::OK, json!({"bucket": bucket_id.as_str(), "events": events, "next": next}));
+    response.headers_mut().insert(JOURNAL_NEXT_HEADER, next.into());
+    response
+}
+
+/// Event for a change to an object; `None` for the chunks uploaded ahead
+/// of a manifest, which aren't objects
+fn event(record: &ChangeRecord) -> Option<Value> {
+    let op = match record.op {
+        ChangeOp::Put { .. } | ChangeOp::Manifest { .. } => "put",
+        ChangeOp::Delete => "delete",
+        ChangeOp::Copy { .. } => "copy",
+        ChangeOp::WriteRange { .. } => "write_range",
+        ChangeOp::Chunk { .. } => return None,
+    };
+    Some(json!({
+        "seq": record.seq,
+        "key": record.key,
+        "op": op,
+        "version": record.version.as_ref().map(ToString::to_string),
+        "timestamp_ms": record.timestamp_ms,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wfldb_core::Version;
+
+    #[test]
+    fn test_is_watch_path() {
+        assert!(is_watch_path("/v1/photos/_watch"));
+        assert!(!is_watch_path("/v1/photos/cats/_watch"));
+        assert!(!is_watch_path("/v1//_watch"));
+    }
+
+    #[test]
+    fn test_event() {
+        let version = Version::new();
+        let record = ChangeRecord {
+            seq: 7,
+            timestamp_ms: 1_700_000_000_000,
+            bucket: "photos".to_string(),
+            key: "cats/tom.jpg".to_string(),
+            op: ChangeOp::Delete,
+            version: Some(version.clone()),
+        };
+        let event = event(&record).unwrap();
+        assert_eq!((event["seq"].as_u64(), event["op"].as_str()), (Some(7), Some("delete")));
+        assert_eq!(event["version"].as_str(), Some(version.to_string().as_str()));
+
+        let chunk = ChangeRecord { op: ChangeOp::Chunk { data: b"meow".to_vec() }, version: None, ..record };
+        assert!(super::event(&chunk).is_none());
+    }
+}
@@ -0,0 +1,296 @@
+//! HTTP API tests against the real server binary
+//!
+//! Each test starts `wfldb-server` on a free port with a fresh data
+//! directory and checks the status codes its routes answer with.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hyper::{Body, Client, Method, Request, Response, StatusCode};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use wfldb_auth::{encode_public_key, generate_signing_key, now_ms, KeyAuthority, Permissions, RequestSigner};
+use wfldb_core::ContentHash;
+
+/// Server process, killed when dropped so a failed test doesn't leave it behind
+struct TestServer {
+    child: Child,
+    addr: SocketAddr,
+    _data_dir: tempfile::TempDir,
+}
+
+impl TestServer {
+    /// Start the server with `args` on top of its data directory and address
+    async fn start(args: &[&str]) -> TestServer {
+        let data_dir = tempfile::tempdir().expect("create data directory");
+        // Take a free port from the OS; the server binds it right after
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_wfldb-server"))
+            .arg("--data-dir").arg(data_dir.path().join("data"))
+            .arg("--bind").arg(addr.to_string())
+            .args(args.iter().map(|arg| arg.replace("{dir}", &data_dir.path().display().to_string())))
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start wfldb-server");
+        let mut server = TestServer { child, addr, _data_dir: data_dir };
+        server.wait_until_live().await;
+        server
+    }
+
+    async fn wait_until_live(&mut self) {
+        let client = Client::new();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("server exited during startup: {}", status);
+            }
+            if let Ok(response) = client.get(self.uri("/livez")).await {
+                if response.status() == StatusCode::OK {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server at {} did not come up", self.addr);
+    }
+
+    fn uri(&self, path: &str) -> hyper::Uri {
+        format!("http://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    async fn send(&self, method: Method, path: &str, headers: &[(&str, &str)], body: impl Into<Body>) -> Response<Body> {
+        let mut request = Request::builder().method(method).uri(self.uri(path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        Client::new().request(request.body(body.into()).unwrap()).await.unwrap()
+    }
+
+    async fn send_signed(&self, signer: &RequestSigner, method: Method, path: &str, body: &[u8]) -> Response<Body> {
+        let headers = signer.sign(method.as_str(), path, body, now_ms());
+        let headers: Vec<(&str, &str)> = headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        self.send(method, path, &headers, body.to_vec()).await
+    }
+}
+
+/// Start the server trusting a fresh root key, with signers for an admin
+/// key and a read-write key it issued
+async fn start_with_auth(args: &[&str]) -> (TestServer, RequestSigner, RequestSigner) {
+    let root = generate_signing_key();
+    let signer = |permissions| {
+        let holder = generate_signing_key();
+        let packet = KeyAuthority::issue(&root, &holder.verifying_key(), permissions, 0, u32::MAX as u64).unwrap();
+        RequestSigner::new(holder, packet)
+    };
+    let (admin, writer) = (signer(Permissions::admin()), signer(Permissions::read_write()));
+    let public_key = encode_public_key(&root.verifying_key());
+    let server = TestServer::start(&[&["--auth-root-key", public_key.as_str()], args].concat()).await;
+    (server, admin, writer)
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn test_oversized_proposal_signature_is_refused() {
+    let server = TestServer::start(&["--generate-root-key", "{dir}/root.key"]).await;
+    let body = vec![b' '; 64 * 1024];
+    let response = server.send(Method::POST, "/admin/proposals/unknown/signatures", &[], body).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = server.send(Method::POST, "/admin/proposals/unknown/signatures", &[], "{}").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Generation a write answered with in `x-wfldb-generation`
+fn generation(response: &Response<Body>) -> String {
+    response.headers()["x-wfldb-generation"].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_fenced_put_and_delete() {
+    let server = TestServer::start(&[]).await;
+    let path = "/v1/locks/leader";
+    let first = server.send(Method::PUT, path, &[], "one").await;
+    assert_eq!(first.status(), StatusCode::CREATED);
+    let first = generation(&first);
+    let second = server.send(Method::PUT, path, &[("x-wfldb-if-generation", &first)], "two").await;
+    assert_eq!(second.status(), StatusCode::CREATED);
+    let second = generation(&second);
+
+    // The writer still holding the first generation is fenced off
+    let stale = [("x-wfldb-if-generation", first.as_str())];
+    assert_eq!(server.send(Method::PUT, path, &stale, "zombie").await.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(server.send(Method::DELETE, path, &stale, "").await.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(server.send(Method::GET, path, &[], "").await.status(), StatusCode::OK);
+
+    let bad = [("x-wfldb-if-generation", "soon")];
+    assert_eq!(server.send(Method::DELETE, path, &bad, "").await.status(), StatusCode::BAD_REQUEST);
+    let current = [("x-wfldb-if-generation", second.as_str())];
+    assert_eq!(server.send(Method::DELETE, path, &current, "").await.status(), StatusCode::OK);
+    assert_eq!(server.send(Method::GET, path, &[], "").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_ranged_get_of_chunked_object() {
+    let server = TestServer::start(&[]).await;
+    let path = "/v1/images/disk.img";
+    // Past the 4 MiB chunk size, so the ranges below cross a chunk boundary
+    let data: Vec<u8> = (0..4 * 1024 * 1024 + 1000).map(|i| (i % 251) as u8).collect();
+    let put = server.send(Method::PUT, path, &[], data.clone()).await;
+    assert_eq!(put.status(), StatusCode::CREATED);
+
+    let whole = server.send(Method::GET, path, &[], "").await;
+    assert_eq!(whole.status(), StatusCode::OK);
+    assert_eq!(whole.headers()["accept-ranges"], "bytes");
+    let etag = whole.headers()["etag"].to_str().unwrap().to_string();
+
+    let first = 4 * 1024 * 1024 - 10;
+    let range = format!("bytes={}-{}", first, first + 19);
+    let response = server.send(Method::GET, path, &[("range", &range), ("if-range", &etag)], "").await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], format!("bytes {}-{}/{}", first, first + 19, data.len()));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], &data[first..first + 20]);
+
+    let response = server.send(Method::GET, path, &[("range", "bytes=-5")], "").await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], &data[data.len() - 5..]);
+
+    // A stale If-Range gets the whole object back
+    let stale = [("range", "bytes=0-9"), ("if-range", "\"0\"")];
+    let response = server.send(Method::GET, path, &stale, "").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body.len(), data.len());
+
+    let past_end = format!("bytes={}-", data.len());
+    let response = server.send(Method::GET, path, &[("range", &past_end)], "").await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()["content-range"], format!("bytes */{}", data.len()));
+}
+
+#[tokio::test]
+async fn test_public_read_bucket_hides_keys_from_anonymous_callers() {
+    let (server, admin, writer) = start_with_auth(&["--journal", "--search-index", "photos"]).await;
+    let acl = server.send_signed(&admin, Method::PUT, "/admin/buckets/photos/acl", br#"{"acl": "public_read"}"#).await;
+    assert_eq!(acl.status(), StatusCode::OK);
+    let put = server.send_signed(&writer, Method::PUT, "/v1/photos/cats/tom.jpg", b"meow").await;
+    assert_eq!(put.status(), StatusCode::CREATED);
+
+    // Objects are public, the key names in the bucket aren't
+    assert_eq!(server.send(Method::GET, "/v1/photos/cats/tom.jpg", &[], "").await.status(), StatusCode::OK);
+    assert_eq!(server.send(Method::GET, "/v1/photos", &[], "").await.status(), StatusCode::UNAUTHORIZED);
+    let watch = "/v1/photos/_watch?after=0&wait_ms=0";
+    assert_eq!(server.send(Method::GET, watch, &[], "").await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.send_signed(&writer, Method::GET, watch, b"").await.status(), StatusCode::OK);
+    let search = "/v1/photos/_search?q=meow";
+    assert_eq!(server.send(Method::GET, search, &[], "").await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.send_signed(&writer, Method::GET, search, b"").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_schema_checks_every_put_and_transaction() {
+    let (server, admin, writer) = start_with_auth(&[]).await;
+    let schema = br#"{"type": "object", "required": ["name"]}"#;
+    let response = server.send_signed(&admin, Method::PUT, "/admin/buckets/people/schema", schema).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // No content type, or a non-JSON one, doesn't skip the check
+    let response = server.send_signed(&writer, Method::PUT, "/v1/people/ada", br#"{"age": 36}"#).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = server.send_signed(&writer, Method::PUT, "/v1/people/ada", b"Ada").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = server.send_signed(&writer, Method::PUT, "/v1/people/ada", br#"{"name": "Ada"}"#).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let valid = STANDARD.encode(br#"{"name": "Grace"}"#);
+    let invalid = STANDARD.encode(br#"{"age": 85}"#);
+    let txn = serde_json::json!({"operations": [
+        {"op": "put", "key": "grace", "data": valid},
+        {"op": "put", "key": "ada", "data": invalid},
+    ]})
+    .to_string();
+    let response = server.send_signed(&writer, Method::POST, "/v1/people/_txn", txn.as_bytes()).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let error = json(response).await;
+    assert_eq!(error["key"], "ada");
+    assert_eq!(error["code"], "schema_violation");
+    // Nothing in the refused transaction applied
+    let grace = server.send_signed(&writer, Method::GET, "/v1/people/grace", b"").await;
+    assert_eq!(grace.status(), StatusCode::NOT_FOUND);
+}
+
+/// Body of a response as JSON
+async fn json(response: Response<Body>) -> serde_json::Value {
+    serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_multipart_parts_listed_in_order_and_replaced() {
+    let server = TestServer::start(&[]).await;
+    let started = server.send(Method::POST, "/v1/videos/_uploads/clip.mp4", &[], "").await;
+    assert_eq!(started.status(), StatusCode::CREATED);
+    let upload_id = json(started).await["upload_id"].as_str().unwrap().to_string();
+    let path = format!("/v1/videos/_uploads/clip.mp4?upload_id={}", upload_id);
+
+    for (part, body) in [(3, "three"), (1, "one"), (2, "two")] {
+        let response = server.send(Method::PUT, &format!("{}&part={}", path, part), &[], body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let listed = json(server.send(Method::GET, &path, &[], "").await).await;
+    let parts: Vec<(u64, u64)> = listed["parts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|part| (part["part"].as_u64().unwrap(), part["size"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(parts, [(1, 3), (2, 3), (3, 5)]);
+
+    // Sending part 2 again replaces it rather than adding a fourth part
+    let response = server.send(Method::PUT, &format!("{}&part=2", path), &[], "TWO!").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed = json(server.send(Method::GET, &path, &[], "").await).await;
+    assert_eq!(listed["parts"].as_array().unwrap().len(), 3);
+    assert_eq!(listed["parts"][1]["size"], 4);
+    assert_eq!(listed["parts"][1]["hash"], ContentHash::new(b"TWO!").to_hex());
+    assert_eq!(listed["size"], 12);
+
+    assert_eq!(server.send(Method::POST, &path, &[], "").await.status(), StatusCode::CREATED);
+    let object = server.send(Method::GET, "/v1/videos/clip.mp4", &[], "").await;
+    assert_eq!(&hyper::body::to_bytes(object.into_body()).await.unwrap()[..], b"oneTWO!three");
+    assert_eq!(server.send(Method::GET, &path, &[], "").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_chunk_exists_reports_stored_and_missing() {
+    let server = TestServer::start(&[]).await;
+    let stored = ContentHash::new(b"stored chunk").to_hex();
+    let put = server.send(Method::PUT, &format!("/v1/sync/_chunks/{}", stored), &[], "stored chunk").await;
+    assert_eq!(put.status(), StatusCode::CREATED);
+    let missing = ContentHash::new(b"never sent").to_hex();
+
+    let body = serde_json::json!({"chunks": [missing, stored]}).to_string();
+    let response = server.send(Method::POST, "/v1/sync/_chunks/exists", &[], body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let answer = json(response).await;
+    assert_eq!(answer["stored"], serde_json::json!([stored]));
+    assert_eq!(answer["missing"], serde_json::json!([missing]));
+
+    let malformed = serde_json::json!({"chunks": [stored, "not-a-hash"]}).to_string();
+    let response = server.send(Method::POST, "/v1/sync/_chunks/exists", &[], malformed).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // One past the most a manifest may name
+    let too_many = serde_json::json!({"chunks": vec![missing; 10_001]}).to_string();
+    let response = server.send(Method::POST, "/v1/sync/_chunks/exists", &[], too_many).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let at_cap = serde_json::json!({"chunks": vec![stored; 10_000]}).to_string();
+    let response = server.send(Method::POST, "/v1/sync/_chunks/exists", &[], at_cap).await;
+    assert_eq!(json(response).await["stored"].as_array().unwrap().len(), 10_000);
+}
@@ -1,291 +1,242 @@
 //! Characterization tests for HTTP/2 transport via hyper
 //! These tests validate streaming, backpressure, concurrency, and memory efficiency
+//!
+//! Each test starts the real server binary and drives the `/_test/`
+//! endpoints, so the figures cover the server's own connection handling,
+//! middleware and routing. Memory is read from the server's `/metrics`.
+//! Needs the `test-endpoints` feature:
+//! `cargo test -p wfldb-server --features test-endpoints --test transport_characterization`
 
-use wfldb_core::test_utils::*;
-use hyper::{Body, Client, Method, Request, Response, Server, StatusCode};
-use hyper::service::{make_service_fn, service_fn};
-use std::convert::Infallible;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use futures::StreamExt;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
 use std::time::{Duration, Instant};
-use tokio::io::AsyncReadExt;
 use tokio::time::timeout;
 
+const MB: usize = 1024 * 1024;
+
+/// Server process, killed when dropped so a failed test doesn't leave it behind
+struct TestServer {
+    child: Child,
+    addr: SocketAddr,
+    _data_dir: tempfile::TempDir,
+}
+
+impl TestServer {
+    async fn start() -> TestServer {
+        let data_dir = tempfile::tempdir().expect("create data directory");
+        // Take a free port from the OS; the server binds it right after
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_wfldb-server"))
+            .arg("--data-dir").arg(data_dir.path())
+            .arg("--bind").arg(addr.to_string())
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start wfldb-server");
+        let mut server = TestServer { child, addr, _data_dir: data_dir };
+        server.wait_until_live().await;
+        server
+    }
+
+    async fn wait_until_live(&mut self) {
+        let client = Client::new();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("server exited during startup: {}", status);
+            }
+            if let Ok(response) = client.get(self.uri("/livez")).await {
+                if response.status() == StatusCode::OK {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("server at {} did not come up", self.addr);
+    }
+
+    fn uri(&self, path: &str) -> hyper::Uri {
+        format!("http://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    async fn get_json(&self, path: &str) -> serde_json::Value {
+        let response = Client::new().get(self.uri(path)).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Value of an unlabeled metric from `/metrics`
+    async fn metric(&self, name: &str) -> usize {
+        let response = Client::new().get(self.uri("/metrics")).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8_lossy(&body)
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.trim().parse().ok())
+            .unwrap_or_else(|| panic!("metric {} missing", name))
+    }
+
+    /// Bytes the server has sent from `/_test/stream`
+    async fn streamed_bytes(&self) -> usize {
+        self.get_json("/_test/stats").await["streamed_bytes"].as_u64().unwrap() as usize
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn http2_client() -> Client<HttpConnector> {
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    Client::builder().http2_only(true).build(connector)
+}
+
+/// Sample at percentile `p` (0 to 100) of sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[index.clamp(1, sorted.len()) - 1]
+}
+
 /// Test that server can stream 1GB without memory spikes
 #[tokio::test]
 async fn net_stream_server_can_stream_1gb_without_heap_spikes() {
-    let tracker = Arc::new(MemoryTracker::new());
-    let tracker_clone = tracker.clone();
-    
-    // Create a simple streaming server
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let tracker = tracker_clone.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |_req| {
-                let tracker = tracker.clone();
-                async move {
-                    // Stream 1GB in 1MB chunks
-                    const CHUNK_SIZE: usize = 1024 * 1024; // 1MB
-                    const TOTAL_SIZE: usize = 1024 * 1024 * 1024; // 1GB
-                    
-                    let (mut sender, body) = Body::channel();
-                    
-                    tokio::spawn(async move {
-                        let mut sent = 0;
-                        while sent < TOTAL_SIZE {
-                            let chunk = vec![42u8; CHUNK_SIZE];
-                            tracker.track_allocation(CHUNK_SIZE);
-                            
-                            if sender.send_data(chunk.into()).await.is_err() {
-                                break;
-                            }
-                            
-                            tracker.track_deallocation(CHUNK_SIZE);
-                            sent += CHUNK_SIZE;
-                            
-                            // Small delay to simulate real streaming
-                            tokio::time::sleep(Duration::from_micros(100)).await;
-                        }
-                    });
-                    
-                    Ok::<_, Infallible>(Response::new(body))
-                }
-            }))
-        }
-    });
-    
-    let server = Server::bind(&addr).serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
-    // Client to consume the stream
-    let client = Client::new();
-    let uri = format!("http://{}/stream", actual_addr).parse().unwrap();
-    let req = Request::builder()
-        .method(Method::GET)
-        .uri(uri)
-        .body(Body::empty())
-        .unwrap();
-    
+    let server = TestServer::start().await;
+    let baseline = server.metric("wfldb_heap_allocated_bytes").await;
+
     let start = Instant::now();
-    let resp = client.request(req).await.unwrap();
+    let resp = Client::new()
+        .get(server.uri(&format!("/_test/stream?bytes={}&chunk={}", 1024 * MB, MB)))
+        .await
+        .unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
-    
-    // Consume the body
-    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+    // Consume the body without holding on to it
+    let mut body = resp.into_body();
+    let mut total_read = 0;
+    while let Some(chunk) = body.next().await {
+        total_read += chunk.unwrap().len();
+    }
     let elapsed = start.elapsed();
-    
-    println!("Streamed {} MB in {:?}", body.len() / (1024 * 1024), elapsed);
-    
-    // Check memory usage stayed reasonable (should not buffer entire 1GB)
-    let peak_mb = tracker.peak_memory_bytes() / (1024 * 1024);
-    println!("Peak memory usage: {} MB", peak_mb);
-    
-    // Peak memory should be much less than 1GB (e.g., < 100MB for buffering)
+    assert_eq!(total_read, 1024 * MB);
+    println!("Streamed {} MB in {:?}", total_read / MB, elapsed);
+
+    // Should not buffer the entire 1GB on the server
+    let peak_mb = server.metric("wfldb_heap_peak_bytes").await.saturating_sub(baseline) / MB;
+    println!("Peak heap growth: {} MB", peak_mb);
     assert!(
         peak_mb < 100,
         "Memory usage too high: {} MB, expected < 100 MB",
         peak_mb
     );
-    
-    server_handle.abort();
 }
 
 /// Test backpressure handling with slow client
 #[tokio::test]
 async fn net_backpressure_client_slowness_handled() {
-    let bytes_sent = Arc::new(AtomicUsize::new(0));
-    let bytes_sent_clone = bytes_sent.clone();
-    
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let bytes_sent = bytes_sent_clone.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |_req| {
-                let bytes_sent = bytes_sent.clone();
-                async move {
-                    let (mut sender, body) = Body::channel();
-                    
-                    tokio::spawn(async move {
-                        // Try to send data faster than client can consume
-                        for _ in 0..100 {
-                            let chunk = vec![42u8; 1024 * 1024]; // 1MB chunks
-                            
-                            match timeout(
-                                Duration::from_millis(100),
-                                sender.send_data(chunk.into())
-                            ).await {
-                                Ok(Ok(_)) => {
-                                    bytes_sent.fetch_add(1024 * 1024, Ordering::SeqCst);
-                                }
-                                _ => {
-                                    // Backpressure detected - client is slow
-                                    println!("Backpressure applied after {} MB",
-                                        bytes_sent.load(Ordering::SeqCst) / (1024 * 1024));
-                                    break;
-                                }
-                            }
-                        }
-                    });
-                    
-                    Ok::<_, Infallible>(Response::new(body))
-                }
-            }))
-        }
-    });
-    
-    let server = Server::bind(&addr).serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
-    // Slow client that reads with delays
-    let client = Client::new();
-    let uri = format!("http://{}/data", actual_addr).parse().unwrap();
-    let req = Request::builder()
-        .method(Method::GET)
-        .uri(uri)
-        .body(Body::empty())
+    let server = TestServer::start().await;
+    let resp = Client::new()
+        .get(server.uri(&format!("/_test/stream?bytes={}&chunk={}", 100 * MB, MB)))
+        .await
         .unwrap();
-    
-    let resp = client.request(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
     let mut body = resp.into_body();
-    
-    // Read slowly
+
+    // Read slowly, then stop after 10MB
     let mut total_read = 0;
     while let Some(chunk) = body.next().await {
-        if let Ok(data) = chunk {
-            total_read += data.len();
-            // Simulate slow processing
-            tokio::time::sleep(Duration::from_millis(50)).await;
-            
-            if total_read > 10 * 1024 * 1024 { // Stop after 10MB
-                break;
-            }
+        total_read += chunk.unwrap().len();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if total_read > 10 * MB {
+            break;
         }
     }
-    
+
     // Verify backpressure was applied
-    let sent = bytes_sent.load(Ordering::SeqCst);
-    println!("Total sent: {} MB, Total read: {} MB",
-        sent / (1024 * 1024), total_read / (1024 * 1024));
-    
+    let sent = server.streamed_bytes().await;
+    println!("Total sent: {} MB, Total read: {} MB", sent / MB, total_read / MB);
+
     // Server should not have sent much more than client read
     assert!(
         sent < total_read * 2,
         "Server sent too much data without backpressure"
     );
-    
-    server_handle.abort();
+}
+
+/// Test that a slow server holds back a fast uploader
+#[tokio::test]
+async fn net_backpressure_slow_server_holds_back_upload() {
+    let server = TestServer::start().await;
+    let (mut sender, body) = Body::channel();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(server.uri("/_test/upload?delay_ms=20"))
+        .body(body)
+        .unwrap();
+    let response = tokio::spawn(Client::new().request(request));
+
+    // Write as fast as the connection takes it for one second
+    let mut written = 0;
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while let Ok(Ok(())) = timeout(deadline - Instant::now(), sender.send_data(vec![0x2a; 64 * 1024].into())).await {
+        written += 64 * 1024;
+    }
+    drop(sender);
+    println!("Wrote {} KB in one second against a 20ms-per-frame reader", written / 1024);
+
+    let response = response.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let read: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(read["bytes"].as_u64().unwrap() as usize, written);
+
+    // Loopback takes gigabytes a second; a held-back writer gets far less
+    assert!(written < 256 * MB, "Upload was not held back: {} MB in one second", written / MB);
 }
 
 /// Test handling 1000 concurrent connections
 #[tokio::test]
 async fn net_concurrent_handles_1000_concurrent_connections() {
-    let connection_count = Arc::new(AtomicUsize::new(0));
-    let connection_count_clone = connection_count.clone();
-    let max_concurrent = Arc::new(AtomicUsize::new(0));
-    let max_concurrent_clone = max_concurrent.clone();
-    
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let connection_count = connection_count_clone.clone();
-        let max_concurrent = max_concurrent_clone.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |_req| {
-                let connection_count = connection_count.clone();
-                let max_concurrent = max_concurrent.clone();
-                async move {
-                    // Track concurrent connections
-                    let current = connection_count.fetch_add(1, Ordering::SeqCst) + 1;
-                    
-                    // Update max if needed
-                    let mut max = max_concurrent.load(Ordering::SeqCst);
-                    while current > max {
-                        match max_concurrent.compare_exchange_weak(
-                            max,
-                            current,
-                            Ordering::SeqCst,
-                            Ordering::SeqCst,
-                        ) {
-                            Ok(_) => break,
-                            Err(m) => max = m,
-                        }
-                    }
-                    
-                    // Simulate some work
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                    
-                    connection_count.fetch_sub(1, Ordering::SeqCst);
-                    
-                    Ok::<_, Infallible>(Response::new(Body::from("OK")))
-                }
-            }))
-        }
-    });
-    
-    let server = Server::bind(&addr)
-        .http2_only(true)
-        .serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
-    // Spawn 1000 concurrent clients
-    let client = Client::builder()
-        .http2_only(true)
-        .build_http();
-    
+    let server = TestServer::start().await;
+    let client = http2_client();
+
     let mut handles = vec![];
     let start = Instant::now();
-    
+
     for i in 0..1000 {
         let client = client.clone();
-        let uri = format!("http://{}/test", actual_addr).parse().unwrap();
-        
+        let uri = server.uri("/_test/stream?bytes=2&delay_ms=10");
+
         let handle = tokio::spawn(async move {
-            let req = Request::builder()
-                .method(Method::GET)
-                .uri(uri)
-                .body(Body::empty())
-                .unwrap();
-            
-            match timeout(Duration::from_secs(5), client.request(req)).await {
-                Ok(Ok(resp)) => {
-                    assert_eq!(resp.status(), StatusCode::OK);
+            let request = async {
+                let resp = client.get(uri).await?;
+                let status = resp.status();
+                hyper::body::to_bytes(resp.into_body()).await?;
+                Ok::<_, hyper::Error>(status)
+            };
+            match timeout(Duration::from_secs(5), request).await {
+                Ok(Ok(status)) => {
+                    assert_eq!(status, StatusCode::OK);
                     Ok(())
                 }
                 Ok(Err(e)) => Err(format!("Request {} failed: {}", i, e)),
                 Err(_) => Err(format!("Request {} timed out", i)),
             }
         });
-        
+
         handles.push(handle);
-        
+
         // Small delay to avoid overwhelming the system
         if i % 100 == 0 {
             tokio::time::sleep(Duration::from_millis(1)).await;
         }
     }
-    
+
     // Wait for all requests to complete
     let mut success_count = 0;
     for handle in handles {
@@ -293,266 +244,153 @@ async fn net_concurrent_handles_1000_concurrent_connections() {
             success_count += 1;
         }
     }
-    
+
     let elapsed = start.elapsed();
-    
+    let stats = server.get_json("/_test/stats").await;
+
     println!("Handled {} concurrent connections in {:?}", success_count, elapsed);
-    println!("Max concurrent connections: {}", max_concurrent.load(Ordering::SeqCst));
-    
+    println!("Max concurrent streams on the server: {}", stats["max_in_flight"]);
+
     // Should handle most connections successfully
     assert!(
         success_count >= 950,
         "Only {} of 1000 connections succeeded",
         success_count
     );
-    
-    server_handle.abort();
 }
 
 /// Test HTTP/2 multiplexing
 #[tokio::test]
 async fn net_http2_multiplexing_works_correctly() {
-    let request_counter = Arc::new(AtomicUsize::new(0));
-    let request_counter_clone = request_counter.clone();
-    
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let request_counter = request_counter_clone.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                let request_counter = request_counter.clone();
-                async move {
-                    let id = request_counter.fetch_add(1, Ordering::SeqCst);
-                    
-                    // Variable response times to test multiplexing
-                    let delay = match id % 3 {
-                        0 => Duration::from_millis(50),
-                        1 => Duration::from_millis(20),
-                        _ => Duration::from_millis(10),
-                    };
-                    
-                    tokio::time::sleep(delay).await;
-                    
-                    let response = format!("Response {}", id);
-                    Ok::<_, Infallible>(Response::new(Body::from(response)))
-                }
-            }))
-        }
-    });
-    
-    let server = Server::bind(&addr)
-        .http2_only(true)
-        .serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
+    let server = TestServer::start().await;
     // Single HTTP/2 connection with multiple streams
-    let client = Client::builder()
-        .http2_only(true)
-        .build_http();
-    
+    let client = http2_client();
+
     let mut handles = vec![];
     let start = Instant::now();
-    
+
     // Send 100 requests over single connection
     for i in 0..100 {
         let client = client.clone();
-        let uri = format!("http://{}/multiplex", actual_addr).parse().unwrap();
-        
+        // Variable response times to test multiplexing
+        let delay_ms = [50, 20, 10][i % 3];
+        let uri = server.uri(&format!("/_test/stream?bytes=16&delay_ms={}", delay_ms));
+
         let handle = tokio::spawn(async move {
-            let req = Request::builder()
-                .method(Method::GET)
-                .uri(uri)
-                .body(Body::empty())
-                .unwrap();
-            
             let start = Instant::now();
-            let resp = client.request(req).await.unwrap();
-            let elapsed = start.elapsed();
-            
+            let resp = client.get(uri).await.unwrap();
             assert_eq!(resp.status(), StatusCode::OK);
-            
+
             let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
-            let body_str = String::from_utf8_lossy(&body);
-            
-            (i, elapsed, body_str.to_string())
+            let elapsed = start.elapsed();
+
+            (i, elapsed, body.len())
         });
-        
+
         handles.push(handle);
     }
-    
+
     // Collect results
     let mut results = vec![];
     for handle in handles {
         let result = handle.await.unwrap();
+        assert_eq!(result.2, 16);
         results.push(result);
     }
-    
+
     let total_elapsed = start.elapsed();
-    
+
     // Sort by completion time
     results.sort_by_key(|r| r.1);
-    
+
     println!("Completed {} multiplexed requests in {:?}", results.len(), total_elapsed);
-    
+
     // With multiplexing, total time should be much less than sum of individual times
     let sum_of_times: Duration = results.iter().map(|r| r.1).sum();
     println!("Sum of individual times: {:?}", sum_of_times);
-    
+
     assert!(
         total_elapsed < sum_of_times / 10,
         "Multiplexing not effective: total {:?} vs sum {:?}",
         total_elapsed,
         sum_of_times
     );
-    
-    server_handle.abort();
 }
 
 /// Test memory efficiency under load
 #[tokio::test]
 async fn net_memory_efficiency_under_load() {
-    let tracker = Arc::new(MemoryTracker::new());
-    let tracker_clone = tracker.clone();
-    
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(move |_conn| {
-        let tracker = tracker_clone.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |_req| {
-                let tracker = tracker.clone();
-                async move {
-                    // Allocate and deallocate memory to simulate processing
-                    let data = vec![42u8; 1024 * 1024]; // 1MB
-                    tracker.track_allocation(data.len());
-                    
-                    // Simulate processing
-                    tokio::time::sleep(Duration::from_millis(5)).await;
-                    
-                    let response = Body::from(data);
-                    tracker.track_deallocation(1024 * 1024);
-                    
-                    Ok::<_, Infallible>(Response::new(response))
-                }
-            }))
-        }
-    });
-    
-    let server = Server::bind(&addr).serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
+    let server = TestServer::start().await;
+    let baseline = server.metric("wfldb_heap_allocated_bytes").await;
+    let peak_before = server.metric("wfldb_heap_peak_bytes").await;
+
     // Generate load
     let client = Client::new();
     let mut handles = vec![];
-    
+
     for _ in 0..100 {
         let client = client.clone();
-        let uri = format!("http://{}/load", actual_addr).parse().unwrap();
-        
+        let uri = server.uri(&format!("/_test/stream?bytes={}&chunk={}&delay_ms=5", MB, MB));
+
         let handle = tokio::spawn(async move {
-            let req = Request::builder()
-                .method(Method::GET)
-                .uri(uri)
-                .body(Body::empty())
-                .unwrap();
-            
-            let resp = client.request(req).await.unwrap();
-            let _body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let resp = client.get(uri).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(body.len(), MB);
         });
-        
+
         handles.push(handle);
-        
+
         // Stagger requests slightly
         tokio::time::sleep(Duration::from_millis(10)).await;
     }
-    
+
     // Wait for completion
     for handle in handles {
         handle.await.unwrap();
     }
-    
+
     // Check memory was properly released
-    let peak_mb = tracker.peak_memory_bytes() / (1024 * 1024);
-    let current_mb = tracker.current_memory_bytes() / (1024 * 1024);
-    
-    println!("Peak memory: {} MB, Current memory: {} MB", peak_mb, current_mb);
-    
+    let peak_after = server.metric("wfldb_heap_peak_bytes").await;
+    // The high-water mark only says something about this load if it moved
+    let peak_mb = if peak_after > peak_before { (peak_after - baseline) / MB } else { 0 };
+    let current_mb = server.metric("wfldb_heap_allocated_bytes").await.saturating_sub(baseline) / MB;
+
+    println!("Peak heap growth: {} MB, Current heap growth: {} MB", peak_mb, current_mb);
+
     // Peak should be reasonable (not all 100MB at once)
     assert!(peak_mb < 20, "Peak memory too high: {} MB", peak_mb);
-    
-    // Current should be near zero (all deallocated)
+
+    // Current should be near the baseline (all deallocated)
     assert!(current_mb < 2, "Memory leak detected: {} MB still allocated", current_mb);
-    
-    server_handle.abort();
 }
 
 /// Test request/response latency
 #[tokio::test]
 async fn net_request_response_latency() {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
-    
-    let make_svc = make_service_fn(|_conn| async {
-        Ok::<_, Infallible>(service_fn(|_req| async {
-            Ok::<_, Infallible>(Response::new(Body::from("pong")))
-        }))
-    });
-    
-    let server = Server::bind(&addr).serve(make_svc);
-    let actual_addr = server.local_addr();
-    
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
-        }
-    });
-    
+    let server = TestServer::start().await;
+    let client = http2_client();
+    let uri = server.uri("/_test/stream?bytes=4");
+
     // Warm up
-    let client = Client::new();
     for _ in 0..10 {
-        let uri = format!("http://{}/ping", actual_addr).parse().unwrap();
-        let req = Request::builder()
-            .method(Method::GET)
-            .uri(uri)
-            .body(Body::empty())
-            .unwrap();
-        client.request(req).await.unwrap();
+        let resp = client.get(uri.clone()).await.unwrap();
+        hyper::body::to_bytes(resp.into_body()).await.unwrap();
     }
-    
+
     // Measure latencies
-    let mut perf = PerfAssert::new();
-    
+    let mut samples = Vec::with_capacity(100);
     for _ in 0..100 {
-        let uri = format!("http://{}/ping", actual_addr).parse().unwrap();
-        let req = Request::builder()
-            .method(Method::GET)
-            .uri(uri)
-            .body(Body::empty())
-            .unwrap();
-        
         let start = Instant::now();
-        let resp = client.request(req).await.unwrap();
+        let resp = client.get(uri.clone()).await.unwrap();
         let _body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
-        perf.record_sample(start.elapsed());
+        samples.push(start.elapsed());
     }
-    
+    samples.sort();
+    let p95 = percentile(&samples, 95.0);
+
     println!("HTTP/2 round-trip - p50: {:?}, p95: {:?}, p99: {:?}",
-        perf.p50(), perf.p95(), perf.p99());
-    
+        percentile(&samples, 50.0), p95, percentile(&samples, 99.0));
+
     // Local HTTP/2 round-trip should be very fast
-    assert_p95_under_ms!(perf, 5);
-    
-    server_handle.abort();
-}
\ No newline at end of file
+    assert!(p95 < Duration::from_millis(5), "p95 {:?} over 5ms", p95);
+}